@@ -0,0 +1,97 @@
+// `Connection` on tokio, with the same methods awaited, so async services can call the server
+// without blocking a runtime thread while it replies. Needs the async feature.
+// As with `Connection`, requests are sent one at a time, methods take `&mut self`, and errors the
+// server replies with hold the `ErrorReply`, see `connection::server_error`.
+
+use std::io;
+
+use rudibi_core::frame::{self, Frame};
+use tokio::io::BufReader;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::connection::{auth_payload, checked, rows, stream_rows, text};
+
+pub struct AsyncConnection {
+    input: BufReader<OwnedReadHalf>,
+    output: OwnedWriteHalf,
+    next_id: u32,
+}
+
+impl AsyncConnection {
+
+    pub async fn connect(address: impl ToSocketAddrs) -> io::Result<AsyncConnection> {
+        let (input, output) = TcpStream::connect(address).await?.into_split();
+        Ok(AsyncConnection { input: BufReader::new(input), output, next_id: 0 })
+    }
+
+    // Logs in as `user` with its password, or with a token if there's no user
+    pub async fn authenticate(&mut self, user: Option<&str>, secret: &str) -> io::Result<()> {
+        self.request(frame::AUTH, &auth_payload(user, secret)).await?;
+        self.reply().await.map(drop)
+    }
+
+    // The server's reply to a statement, as text
+    pub async fn execute(&mut self, statement: &str) -> io::Result<String> {
+        self.request(frame::EXECUTE, statement).await?;
+        text(self.reply().await?)
+    }
+
+    // The rows of a select, streamed, each a list of fields with the column names first
+    pub async fn select(&mut self, statement: &str) -> io::Result<Vec<Vec<String>>> {
+        self.request(frame::STREAM, statement).await?;
+        let mut csv = Vec::new();
+        while !stream_rows(&mut csv, self.reply().await?)? {}
+        rows(csv)
+    }
+
+    async fn request(&mut self, tag: u8, payload: &str) -> io::Result<()> {
+        self.next_id = self.next_id.wrapping_add(1);
+        Frame::new(tag, self.next_id, payload).write_to_async(&mut self.output).await
+    }
+
+    async fn reply(&mut self) -> io::Result<Frame> {
+        checked(Frame::read_from_async(&mut self.input).await?, self.next_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::connection::server_error;
+    use crate::testlib::{fruits_server, listening};
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap().block_on(future)
+    }
+
+    #[test]
+    fn statements_and_selects() {
+        let address = listening(Arc::new(fruits_server()));
+
+        let (inserted, rows) = block_on(async {
+            let mut conn = AsyncConnection::connect(address).await.unwrap();
+            let inserted = conn.execute("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi, gold')").await.unwrap();
+            (inserted, conn.select("SELECT id, name FROM Fruits WHERE id > 350").await.unwrap())
+        });
+
+        assert_eq!(inserted, "INSERTED 1");
+        assert_eq!(rows, [["id", "name"], ["400", "cherry"], ["500", "kiwi, gold"]]);
+    }
+
+    #[test]
+    fn server_errors() {
+        let address = listening(Arc::new(fruits_server()));
+
+        let (err, tables) = block_on(async {
+            let mut conn = AsyncConnection::connect(address).await.unwrap();
+            let err = conn.select("SELECT id FROM Vegetables").await.unwrap_err();
+            (err, conn.execute("SHOW TABLES").await.unwrap())
+        });
+
+        assert_eq!(server_error(&err).map(|reply| reply.code), Some(1001));
+        assert!(tables.contains("Fruits"));
+    }
+}
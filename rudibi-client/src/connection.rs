@@ -0,0 +1,286 @@
+// Client side of a connection speaking frames, see `rudibi_core::frame`
+// Requests are sent one at a time and their replies read before the next. Errors the server
+// replies with are `io::Error`s holding the `ErrorReply`, see `server_error`.
+
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::net::TcpStream;
+
+use rudibi_core::blob::{BlobId, BLOB_CHUNK_SIZE};
+use rudibi_core::command::{Command, Literal};
+use rudibi_core::errors::ErrorReply;
+use rudibi_core::frame::{self, Frame};
+use rudibi_core::serial;
+
+use crate::dtype::ColumnValue;
+use crate::select::Select;
+use crate::stream::{self, ChunkSink};
+use crate::table;
+use crate::transaction::Transaction;
+
+pub struct Connection {
+    input: BufReader<TcpStream>,
+    output: TcpStream,
+    next_id: u32,
+}
+
+impl Connection {
+
+    pub fn connect(address: &str) -> io::Result<Connection> {
+        let conn = TcpStream::connect(address)?;
+        Ok(Connection { input: BufReader::new(conn.try_clone()?), output: conn, next_id: 0 })
+    }
+
+    // Logs in as `user` with its password, or with a token if there's no user
+    pub fn authenticate(&mut self, user: Option<&str>, secret: &str) -> io::Result<()> {
+        self.request(frame::AUTH, auth_payload(user, secret))?;
+        self.reply().map(drop)
+    }
+
+    // The server's reply to a statement, as text
+    pub fn execute(&mut self, statement: &str) -> io::Result<String> {
+        self.request(frame::EXECUTE, statement)?;
+        text(self.reply()?)
+    }
+
+    // The rows of a select, streamed, each a list of fields with the column names first
+    pub fn select(&mut self, statement: &str) -> io::Result<Vec<Vec<String>>> {
+        self.stream(frame::STREAM, statement.as_bytes())
+    }
+
+    // A select of the table built in code rather than written out, see `Select`
+    pub fn select_from<'q>(&mut self, table: &'q str) -> Select<'_, 'q> {
+        Select::new(self, table)
+    }
+
+    // Streams the rows into the columns of the table, each chunk as large as the server's last
+    // window allows, see `frame::INSERT_ROWS`. They're stored once all were sent, how many is returned.
+    pub fn insert_rows<'v>(&mut self, table: &str, columns: &[&str], rows: impl IntoIterator<Item = Vec<ColumnValue<'v>>>) -> io::Result<usize> {
+        let mut insert = InsertRows { conn: self, table, columns };
+        let window = insert.send_chunk(Vec::new())?;
+        stream::stream_rows(rows, window, &mut insert)?;
+        self.request(frame::INSERT_DONE, "")?;
+        let reply = text(self.reply()?)?;
+        reply.strip_prefix("INSERTED ").and_then(|rows| rows.parse().ok()).ok_or_else(|| malformed(format!("Unexpected reply {reply}")))
+    }
+
+    // Stores what `source` holds as a blob, sent a chunk at a time, see `frame::BLOB_WRITE`
+    pub fn put_blob(&mut self, source: &mut dyn Read) -> io::Result<BlobId> {
+        let mut chunk = Vec::with_capacity(BLOB_CHUNK_SIZE);
+        loop {
+            chunk.clear();
+            if source.take(BLOB_CHUNK_SIZE as u64).read_to_end(&mut chunk)? == 0 {
+                break;
+            }
+            self.request(frame::BLOB_WRITE, &chunk)?;
+            self.reply()?;
+        }
+        self.request(frame::BLOB_DONE, "")?;
+        let reply = text(self.reply()?)?;
+        reply.parse().map(BlobId).map_err(|_| malformed(format!("Unexpected reply {reply}")))
+    }
+
+    // Writes the blob to `out` as its chunks come, returning its bytes
+    pub fn get_blob(&mut self, id: BlobId, out: &mut dyn Write) -> io::Result<u64> {
+        self.request(frame::BLOB_READ, id.0.to_string())?;
+        let mut read = 0;
+        loop {
+            let reply = self.reply()?;
+            match reply.tag {
+                frame::BLOB_CHUNK => {
+                    out.write_all(&reply.payload)?;
+                    read += reply.payload.len() as u64;
+                },
+                frame::DONE => return Ok(read),
+                tag => return Err(malformed(format!("Unexpected frame {tag} in a blob"))),
+            }
+        }
+    }
+
+    // Opens a transaction on the connection, see `Transaction`
+    pub fn begin(&mut self) -> io::Result<Transaction<'_>> {
+        self.execute("BEGIN")?;
+        Ok(Transaction::new(self))
+    }
+
+    // The rows of an encoded select, as `select`
+    pub(crate) fn query(&mut self, select: &[u8]) -> io::Result<Vec<Vec<String>>> {
+        self.stream(frame::QUERY, select)
+    }
+
+    fn stream(&mut self, tag: u8, payload: &[u8]) -> io::Result<Vec<Vec<String>>> {
+        self.request(tag, payload)?;
+        let mut csv = Vec::new();
+        while !stream_rows(&mut csv, self.reply()?)? {}
+        rows(csv)
+    }
+
+    fn request(&mut self, tag: u8, payload: impl AsRef<[u8]>) -> io::Result<()> {
+        self.next_id = self.next_id.wrapping_add(1);
+        Frame::new(tag, self.next_id, payload.as_ref()).write_to(&mut self.output)
+    }
+
+    // The next frame replying to the last request
+    fn reply(&mut self) -> io::Result<Frame> {
+        checked(Frame::read_from(&mut self.input)?, self.next_id)
+    }
+}
+
+// Rows of an insert sent in INSERT_ROWS frames, the window granted next read from the WINDOW reply
+struct InsertRows<'c, 'i> {
+    conn: &'c mut Connection,
+    table: &'i str,
+    columns: &'i [&'i str],
+}
+
+impl<'v> ChunkSink<Vec<ColumnValue<'v>>> for InsertRows<'_, '_> {
+    type Error = io::Error;
+
+    fn send_chunk(&mut self, chunk: Vec<Vec<ColumnValue<'v>>>) -> io::Result<usize> {
+        let texts: Vec<Vec<String>> = chunk.iter().map(|row| row.iter().map(literal_text).collect()).collect();
+        let rows = chunk.iter().zip(&texts)
+            .map(|(row, texts)| row.iter().zip(texts).map(|(value, text)| literal(value, text)).collect())
+            .collect();
+        let insert = Command::Insert { table: self.table, columns: self.columns.to_vec(), rows };
+        self.conn.request(frame::INSERT_ROWS, serial::encode(&insert))?;
+        let reply = self.conn.reply()?;
+        let window = (reply.tag == frame::WINDOW).then(|| text(reply)).transpose()?
+            .and_then(|update| update.split_once(' ')?.1.parse().ok());
+        window.ok_or_else(|| malformed("Expected a WINDOW reply"))
+    }
+}
+
+// The text of a value as the server parses it
+fn literal_text(value: &ColumnValue) -> String {
+    match value {
+        ColumnValue::UTF8(val) => val.to_string(),
+        ColumnValue::Bytes(val) => val.iter().map(|byte| format!("{byte:02x}")).collect(),
+        value => value.to_string(),
+    }
+}
+
+fn literal<'t>(value: &ColumnValue, text: &'t str) -> Literal<'t> {
+    match value {
+        ColumnValue::U32(_) | ColumnValue::F64(_) => Literal::Number(text),
+        ColumnValue::UTF8(_) | ColumnValue::Uuid(_) => Literal::Text(text),
+        ColumnValue::Bytes(_) => Literal::Hex(text),
+    }
+}
+
+// The parts shared with `async_connection`
+
+pub(crate) fn auth_payload(user: Option<&str>, secret: &str) -> String {
+    match user {
+        Some(user) => format!("{user}\0{secret}"),
+        None => secret.to_string(),
+    }
+}
+
+// The frame read as a reply to `request_id`, an error if it's not one or it's an ERROR
+pub(crate) fn checked(reply: Option<Frame>, request_id: u32) -> io::Result<Frame> {
+    match reply.ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "Server closed the connection"))? {
+        reply if reply.request_id != request_id => Err(malformed(format!("Reply to request {} rather than {request_id}", reply.request_id))),
+        reply if reply.tag == frame::ERROR => Err(ErrorReply::parse(&reply.payload).map_or_else(|| malformed("Unreadable error"), io::Error::other)),
+        reply => Ok(reply),
+    }
+}
+
+pub(crate) fn text(reply: Frame) -> io::Result<String> {
+    String::from_utf8(reply.payload).map_err(|_| malformed("Reply isn't UTF-8"))
+}
+
+// Adds the rows of a streamed reply to `csv`, true once the stream is DONE
+pub(crate) fn stream_rows(csv: &mut Vec<u8>, reply: Frame) -> io::Result<bool> {
+    match reply.tag {
+        frame::ROWS => csv.extend(reply.payload),
+        frame::DONE => return Ok(true),
+        tag => return Err(malformed(format!("Unexpected frame {tag} in a stream"))),
+    }
+    Ok(false)
+}
+
+pub(crate) fn rows(csv: Vec<u8>) -> io::Result<Vec<Vec<String>>> {
+    let csv = String::from_utf8(csv).map_err(|_| malformed("Rows aren't UTF-8"))?;
+    Ok(table::parse_csv(&csv))
+}
+
+// The error the server replied with, None if the connection failed
+pub fn server_error(err: &io::Error) -> Option<&ErrorReply> {
+    err.get_ref().and_then(|inner| inner.downcast_ref::<ErrorReply>())
+}
+
+fn malformed(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::testlib::{connected, connected_to, fruits_server};
+
+    #[test]
+    fn statements_and_selects() {
+        let mut conn = connected();
+
+        assert_eq!(conn.execute("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi, gold')").unwrap(), "INSERTED 1");
+        let rows = conn.select("SELECT id, name FROM Fruits WHERE id > 350").unwrap();
+
+        assert_eq!(rows, [["id", "name"], ["400", "cherry"], ["500", "kiwi, gold"]]);
+    }
+
+    #[test]
+    fn server_errors() {
+        let mut conn = connected();
+
+        let err = conn.execute("SELECT id FROM Vegetables").unwrap_err();
+
+        assert_eq!(server_error(&err).map(|reply| reply.code), Some(1001));
+        assert!(conn.execute("SHOW TABLES").unwrap().contains("Fruits"));
+    }
+
+    #[test]
+    fn inserts_within_windows() {
+        // Room for two rows as long as the longest the table holds, then six of those sent
+        let server = Arc::new(fruits_server().with_insert_budget(48));
+        let mut conn = connected_to(server.clone());
+
+        let inserted = conn.insert_rows("Fruits", &["id", "name"], (500..520).map(|id| vec![ColumnValue::U32(id), ColumnValue::UTF8("kiwi")])).unwrap();
+        let metrics = server.metrics();
+
+        // The window opened, then chunks of 2, 6, 6 and 6 rows
+        assert_eq!(inserted, 20);
+        assert!(metrics.contains("rudibi_queries_total{command=\"insert\"} 5\n"));
+        assert!(metrics.contains("rudibi_query_errors_total{command=\"insert\"} 0\n"));
+        assert_eq!(conn.select("SELECT id FROM Fruits WHERE id > 500").unwrap().len(), 20);
+    }
+
+    #[test]
+    fn inserts_of_every_type() {
+        let mut conn = connected();
+        conn.execute("CREATE TABLE Things (id U32, weight F64, label UTF8(10), data VARBINARY(4))").unwrap();
+
+        let rows = [vec![ColumnValue::U32(1), ColumnValue::F64(0.5), ColumnValue::UTF8("it's"), ColumnValue::Bytes(&[0xab, 0x01])]];
+        let inserted = conn.insert_rows("Things", &["id", "weight", "label", "data"], rows).unwrap();
+        let err = conn.insert_rows("Things", &["id"], [vec![ColumnValue::UTF8("one")]]).unwrap_err();
+
+        assert_eq!(inserted, 1);
+        assert_eq!(conn.select("SELECT * FROM Things").unwrap(), [["id", "weight", "label", "data"], ["1", "0.5", "it's", "0xab01"]]);
+        assert!(server_error(&err).is_some());
+    }
+
+    #[test]
+    fn blobs_in_chunks() {
+        let mut conn = connected();
+        let payload: Vec<u8> = (0..BLOB_CHUNK_SIZE * 3 + 1).map(|i| (i % 249) as u8).collect();
+
+        let id = conn.put_blob(&mut payload.as_slice()).unwrap();
+        let mut read_back = Vec::new();
+        let read = conn.get_blob(id, &mut read_back).unwrap();
+        let missing = conn.get_blob(BlobId(id.0 + 1), &mut Vec::new()).unwrap_err();
+
+        assert_eq!(read, payload.len() as u64);
+        assert!(read_back == payload);
+        assert_eq!(server_error(&missing).map(|reply| reply.code), Some(1009));
+    }
+}
@@ -0,0 +1,112 @@
+// How the client shows the rows of selects, picked with --format:
+//   table   aligned columns, see `table::format_table`
+//   csv     as the server streams them, see `rudibi_server::display::format_csv`
+//   json    a line of a JSON object per row, keyed by column, values as strings
+//   tsv     the header then the rows, fields split by tabs, with tabs, line breaks and
+//           backslashes escaped as \t, \n, \r and \\
+// Each ends with a line break but a table.
+
+use crate::table;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    #[default]
+    Table,
+    Csv,
+    Json,
+    Tsv,
+}
+
+impl Format {
+
+    pub fn parse(name: &str) -> Option<Format> {
+        match name.to_ascii_lowercase().as_str() {
+            "table" => Some(Format::Table),
+            "csv" => Some(Format::Csv),
+            "json" => Some(Format::Json),
+            "tsv" => Some(Format::Tsv),
+            _ => None,
+        }
+    }
+
+    pub fn render(self, header: &[String], rows: &[Vec<String>]) -> String {
+        match self {
+            Format::Table => table::format_table(header, rows),
+            Format::Csv => lines(header, rows, true, |fields| fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(",")),
+            Format::Json => lines(header, rows, false, |fields| {
+                let members: Vec<String> = header.iter().zip(fields).map(|(name, value)| format!("{}:{}", json_string(name), json_string(value))).collect();
+                format!("{{{}}}", members.join(","))
+            }),
+            Format::Tsv => lines(header, rows, true, |fields| fields.iter().map(|field| tsv_field(field)).collect::<Vec<_>>().join("\t")),
+        }
+    }
+}
+
+fn lines(header: &[String], rows: &[Vec<String>], with_header: bool, line: impl Fn(&[String]) -> String) -> String {
+    let mut out = String::new();
+    for fields in with_header.then_some(header).into_iter().chain(rows.iter().map(Vec::as_slice)) {
+        out.push_str(&line(fields));
+        out.push('\n');
+    }
+    out
+}
+
+fn csv_field(text: &str) -> String {
+    if text.is_empty() || text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn tsv_field(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows() -> (Vec<String>, Vec<Vec<String>>) {
+        let header = vec!["id".to_string(), "name".to_string()];
+        let rows = vec![
+            vec!["1".to_string(), "kiwi, \"gold\"".to_string()],
+            vec!["2".to_string(), "tab\there\nand\\".to_string()],
+        ];
+        (header, rows)
+    }
+
+    #[test]
+    fn formats() {
+        let (header, rows) = rows();
+
+        assert_eq!(Format::Csv.render(&header, &rows), "id,name\n1,\"kiwi, \"\"gold\"\"\"\n2,\"tab\there\nand\\\"\n");
+        assert_eq!(Format::Json.render(&header, &rows), "{\"id\":\"1\",\"name\":\"kiwi, \\\"gold\\\"\"}\n{\"id\":\"2\",\"name\":\"tab\\there\\nand\\\\\"}\n");
+        assert_eq!(Format::Tsv.render(&header, &rows), "id\tname\n1\tkiwi, \"gold\"\n2\ttab\\there\\nand\\\\\n");
+        assert!(Format::Table.render(&header, &rows).ends_with("(2 rows)"));
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Format::parse("JSON"), Some(Format::Json));
+        assert_eq!(Format::parse("xml"), None);
+    }
+}
@@ -0,0 +1,281 @@
+// Loading a CSV file into a table, for `rudibi-client import`
+// The file starts with a header. Its columns go to the table's columns of the same names, ignoring
+// case, and those with no such column are left out, unless the columns are given in the order of
+// the file's, `-` leaving one out. Every column of the table must be in the file, as inserts give
+// them all. Fields are written as the server writes them in CSV: numbers, text, UUIDs and enum
+// labels as they are, bytes in hex, after 0x or not.
+// Rows are inserted a batch at a time, see `stream::stream_rows`. A batch the server fails is
+// inserted again a row at a time, so only the rows it can't store are left out, each reported
+// with its line and why.
+
+use std::io::{self, BufRead};
+
+use crate::connection::{server_error, Connection};
+use crate::stream::{stream_rows, ChunkSink};
+use crate::table::parse_csv;
+
+pub const DEFAULT_BATCH_ROWS: usize = 1000;
+
+// A record of the file and the line it starts at, counted from 1
+#[derive(Debug, PartialEq)]
+pub struct Record {
+    pub line: usize,
+    pub fields: Vec<String>,
+}
+
+// Records of CSV read a line at a time, a quoted field can span lines
+pub struct CsvRecords<R> {
+    input: R,
+    line: usize,
+}
+
+impl<R: BufRead> CsvRecords<R> {
+    pub fn new(input: R) -> CsvRecords<R> {
+        CsvRecords { input, line: 0 }
+    }
+}
+
+impl<R: BufRead> Iterator for CsvRecords<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut text = String::new();
+        let start = self.line + 1;
+        loop {
+            match self.input.read_line(&mut text) {
+                Ok(0) if text.is_empty() => return None,
+                Ok(0) => break,
+                Ok(_) => self.line += 1,
+                Err(err) => return Some(Err(err)),
+            }
+            // Quotes come in pairs unless the last field goes on to the next line
+            if text.matches('"').count().is_multiple_of(2) {
+                break;
+            }
+        }
+        let text = text.strip_suffix("\r\n").or_else(|| text.strip_suffix('\n')).unwrap_or(&text);
+        let fields = parse_csv(text).pop().unwrap_or_default();
+        Some(Ok(Record { line: start, fields }))
+    }
+}
+
+// A column of the table, as DESCRIBE replies with it
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableColumn {
+    pub name: String,
+    pub dtype: String,
+}
+
+// The table column each of the file's columns goes to, None for those left out
+pub fn infer_mapping(header: &[String], columns: &[TableColumn]) -> Result<Vec<Option<TableColumn>>, String> {
+    let mapping = header.iter()
+        .map(|name| columns.iter().find(|column| column.name.eq_ignore_ascii_case(name.trim())).cloned())
+        .collect();
+    complete(mapping, columns)
+}
+
+// As `infer_mapping`, with the table columns given as `id,name,-`
+pub fn given_mapping(given: &str, columns: &[TableColumn]) -> Result<Vec<Option<TableColumn>>, String> {
+    let mapping = given.split(',').map(str::trim).map(|name| match name {
+        "-" => Ok(None),
+        name => columns.iter().find(|column| column.name.eq_ignore_ascii_case(name)).cloned().map(Some)
+            .ok_or_else(|| format!("Table has no column {name}")),
+    }).collect::<Result<_, _>>()?;
+    complete(mapping, columns)
+}
+
+// The mapping, if it has each of the table's columns once
+fn complete(mapping: Vec<Option<TableColumn>>, columns: &[TableColumn]) -> Result<Vec<Option<TableColumn>>, String> {
+    for column in columns {
+        match mapping.iter().flatten().filter(|mapped| mapped.name == column.name).count() {
+            0 => return Err(format!("Column {} of the table isn't in the file", column.name)),
+            1 => {},
+            _ => return Err(format!("Column {} of the table is in the file more than once", column.name)),
+        }
+    }
+    Ok(mapping)
+}
+
+// The table's columns, read from the rows of the text table DESCRIBE replies with, below its
+// header and divider. The last field of a row is the table's fingerprint.
+pub fn describe(conn: &mut Connection, table: &str) -> io::Result<Vec<TableColumn>> {
+    let reply = conn.execute(&format!("DESCRIBE {table}"))?;
+    Ok(reply.lines().skip(2).filter_map(|line| {
+        let (fields, _fingerprint) = line.strip_prefix('|')?.strip_suffix('|')?.rsplit_once('|')?;
+        let (name, dtype) = fields.split_once('|')?;
+        Some(TableColumn { name: name.trim().to_string(), dtype: dtype.trim().to_string() })
+    }).collect())
+}
+
+// The field as a literal of an insert into a column of type `dtype`
+pub fn literal(dtype: &str, field: &str) -> Result<String, String> {
+    let kind = dtype.split('(').next().unwrap_or(dtype);
+    match kind {
+        "U32" => field.parse::<u32>().map(|_| field.to_string()).map_err(|_| format!("{field:?} isn't a U32")),
+        "F64" => field.parse::<f64>().map(|_| field.to_string()).map_err(|_| format!("{field:?} isn't a F64")),
+        "VARBINARY" | "BUFFER" => {
+            let hex = field.strip_prefix("0x").unwrap_or(field);
+            match hex.len().is_multiple_of(2) && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                true => Ok(format!("x'{hex}'")),
+                false => Err(format!("{field:?} isn't hex")),
+            }
+        },
+        _ if field.contains('\'') => Err(format!("{field:?} holds a single quote, which strings can't")),
+        _ => Ok(format!("'{field}'")),
+    }
+}
+
+// Inserts batches of records into the table, reporting each to `progress`
+pub struct Importer<'c> {
+    conn: &'c mut Connection,
+    table: String,
+    mapping: Vec<Option<TableColumn>>,
+    batch_rows: usize,
+    progress: &'c mut dyn FnMut(Progress),
+    pub imported: usize,
+    pub failed: usize,
+}
+
+// What an import got to
+pub enum Progress {
+    // Rows imported so far, after each batch
+    Imported(usize),
+    // A row left out, with its line and why
+    Failed(usize, String),
+}
+
+impl<'c> Importer<'c> {
+
+    pub fn new(conn: &'c mut Connection, table: &str, mapping: Vec<Option<TableColumn>>, batch_rows: usize, progress: &'c mut dyn FnMut(Progress)) -> Importer<'c> {
+        Importer { conn, table: table.to_string(), mapping, batch_rows: batch_rows.max(1), progress, imported: 0, failed: 0 }
+    }
+
+    // Imports the records, failing only if the connection does
+    pub fn import(&mut self, records: impl IntoIterator<Item = Record>) -> io::Result<()> {
+        let batch_rows = self.batch_rows;
+        stream_rows(records, batch_rows, self).map(drop)
+    }
+
+    // The values of the record, in the order of the mapped columns
+    fn values(&self, record: &Record) -> Result<String, String> {
+        if record.fields.len() != self.mapping.len() {
+            return Err(format!("Has {} fields rather than {}", record.fields.len(), self.mapping.len()));
+        }
+        let values = self.mapping.iter().zip(&record.fields)
+            .filter_map(|(column, field)| column.as_ref().map(|column| literal(&column.dtype, field)))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(format!("({})", values.join(", ")))
+    }
+
+    fn insert(&mut self, values: &[&str]) -> io::Result<()> {
+        let columns: Vec<&str> = self.mapping.iter().flatten().map(|column| column.name.as_str()).collect();
+        self.conn.execute(&format!("INSERT INTO {} ({}) VALUES {}", self.table, columns.join(", "), values.join(", "))).map(drop)
+    }
+
+    fn fail(&mut self, line: usize, why: String) {
+        self.failed += 1;
+        (self.progress)(Progress::Failed(line, why));
+    }
+}
+
+impl ChunkSink<Record> for Importer<'_> {
+    type Error = io::Error;
+
+    fn send_chunk(&mut self, chunk: Vec<Record>) -> io::Result<usize> {
+        let mut rows = Vec::with_capacity(chunk.len());
+        for record in chunk {
+            match self.values(&record) {
+                Ok(values) => rows.push((record.line, values)),
+                Err(why) => self.fail(record.line, why),
+            }
+        }
+        let batch: Vec<&str> = rows.iter().map(|(_, values)| values.as_str()).collect();
+        match if batch.is_empty() { Ok(()) } else { self.insert(&batch) } {
+            Ok(()) => self.imported += rows.len(),
+            Err(err) if server_error(&err).is_some() => {
+                for (line, values) in &rows {
+                    match self.insert(&[values]) {
+                        Ok(()) => self.imported += 1,
+                        Err(err) => match server_error(&err) {
+                            Some(reply) => self.fail(*line, reply.to_string()),
+                            None => return Err(err),
+                        },
+                    }
+                }
+            },
+            Err(err) => return Err(err),
+        }
+        (self.progress)(Progress::Imported(self.imported));
+        Ok(self.batch_rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testlib::connected;
+
+    fn column(name: &str, dtype: &str) -> TableColumn {
+        TableColumn { name: name.to_string(), dtype: dtype.to_string() }
+    }
+
+    #[test]
+    fn records_spanning_lines() {
+        let input = "id,name\r\n1,\"two\nlines\"\n2,\"a \"\"quote\"\"\"\n";
+
+        let records: Vec<Record> = CsvRecords::new(input.as_bytes()).map(Result::unwrap).collect();
+
+        assert_eq!(records, [
+            Record { line: 1, fields: vec!["id".to_string(), "name".to_string()] },
+            Record { line: 2, fields: vec!["1".to_string(), "two\nlines".to_string()] },
+            Record { line: 4, fields: vec!["2".to_string(), "a \"quote\"".to_string()] },
+        ]);
+    }
+
+    #[test]
+    fn mappings() {
+        let columns = [column("id", "U32"), column("name", "UTF8(32)")];
+        let header = ["Name".to_string(), "colour".to_string(), "ID".to_string()];
+
+        assert_eq!(infer_mapping(&header, &columns), Ok(vec![Some(column("name", "UTF8(32)")), None, Some(column("id", "U32"))]));
+        assert_eq!(infer_mapping(&header[..2], &columns), Err("Column id of the table isn't in the file".to_string()));
+        assert_eq!(given_mapping("id, -, NAME", &columns), Ok(vec![Some(column("id", "U32")), None, Some(column("name", "UTF8(32)"))]));
+        assert_eq!(given_mapping("id,size", &columns), Err("Table has no column size".to_string()));
+        assert!(given_mapping("id,name,id", &columns).is_err());
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(literal("U32", "7"), Ok("7".to_string()));
+        assert!(literal("U32", "-7").is_err());
+        assert_eq!(literal("VARBINARY(8)", "00ff"), Ok("x'00ff'".to_string()));
+        assert_eq!(literal("BUFFER(1)", "0xff"), Ok("x'ff'".to_string()));
+        assert!(literal("BUFFER(2)", "fff").is_err());
+        assert_eq!(literal("ENUM('a', 'b')", "a"), Ok("'a'".to_string()));
+        assert!(literal("UTF8(8)", "it's").is_err());
+    }
+
+    #[test]
+    fn imports_batches_and_reports_failed_rows() {
+        let mut conn = connected();
+        let input = "name,id\nkiwi,500\nlime,x\nfig,600\n\"it's\",700\nmango,800\nthe longest fruit name yet,900\nplum,1000,1\n";
+        let mut records = CsvRecords::new(input.as_bytes()).map(Result::unwrap);
+        let header = records.next().unwrap().fields;
+        let mapping = infer_mapping(&header, &describe(&mut conn, "Fruits").unwrap()).unwrap();
+        let (mut batches, mut failures) = (Vec::new(), Vec::new());
+        let mut progress = |progress| match progress {
+            Progress::Imported(rows) => batches.push(rows),
+            Progress::Failed(line, _) => failures.push(line),
+        };
+
+        let mut importer = Importer::new(&mut conn, "Fruits", mapping, 2, &mut progress);
+        importer.import(records).unwrap();
+        let (imported, failed) = (importer.imported, importer.failed);
+
+        assert_eq!((imported, failed), (3, 4));
+        assert_eq!(batches, [1, 2, 3, 3]);
+        assert_eq!(failures, [3, 5, 7, 8]);
+        assert_eq!(conn.select("SELECT name FROM Fruits WHERE id >= 500").unwrap(), [["name"], ["kiwi"], ["fig"], ["mango"]]);
+    }
+}
@@ -0,0 +1,15 @@
+pub mod stream;
+pub mod connection;
+pub mod transaction;
+pub mod select;
+#[cfg(feature = "async")]
+pub mod async_connection;
+pub mod table;
+pub mod repl;
+pub mod format;
+pub mod import;
+#[cfg(test)]
+mod testlib;
+
+// Filters are built as the server takes them
+pub use rudibi_core::{dtype, query};
@@ -0,0 +1,243 @@
+// Interactive client of a server speaking frames
+// Usage: rudibi-client [--host HOST:PORT] [--user USER] [--format table|csv|json|tsv]
+// Connects to 127.0.0.1:1337 unless told otherwise. With RUDIBI_SECRET set, logs in with it, as
+// USER's password with --user, else as a token, see `rudibi_server::auth`.
+// Reads statements and commands with line editing, see `repl`, keeping their history in
+// ~/.rudibi_history. Rows of selects are shown as aligned tables, or in the --format given, see
+// `format`, other replies as the server sends them. With a format other than table, only rows go
+// to stdout and the rest to stderr, e.g. for
+//   echo 'SELECT id, name FROM Fruits WHERE id > 0;' | rudibi-client --format csv > fruits.csv
+// Ctrl-C drops the statement being typed, Ctrl-D or \q quits.
+// Usage: rudibi-client import --table TABLE [--columns COLUMN,...] [--batch ROWS] [--host HOST:PORT]
+//                             [--user USER] FILE.csv
+// Loads the rows of the file, or of stdin if it's -, into the table, see `import`. The table's
+// columns are matched with the file's header unless given as --columns, in the file's order.
+// Failed rows are reported with their lines on stderr, as is the progress on a terminal. Exits
+// with 1 if any row failed.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::path::PathBuf;
+use std::process::exit;
+
+use rudibi_client::connection::{server_error, Connection};
+use rudibi_client::format::Format;
+use rudibi_client::import::{describe, given_mapping, infer_mapping, CsvRecords, Importer, Progress, DEFAULT_BATCH_ROWS};
+use rudibi_client::repl::{is_select, Input, Statements, HELP};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+const PROMPT: &str = "rudibi> ";
+// While a statement spans lines
+const CONTINUED: &str = "     -> ";
+const USAGE: &str = "\
+Usage: rudibi-client [--host HOST:PORT] [--user USER] [--format table|csv|json|tsv]
+       rudibi-client import --table TABLE [--columns COLUMN,...] [--batch ROWS] [--host HOST:PORT] [--user USER] FILE.csv";
+
+struct Args {
+    address: String,
+    user: Option<String>,
+    format: Format,
+    // Set by the import subcommand
+    import: Option<Import>,
+}
+
+#[derive(Default)]
+struct Import {
+    table: String,
+    columns: Option<String>,
+    batch_rows: usize,
+    file: String,
+}
+
+// What a statement is replied to with, as it's shown
+enum Shown {
+    Rows(String),
+    Status(String),
+}
+
+fn main() {
+    let Args { address, user, format, import } = args().unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        exit(2)
+    });
+    let mut conn = Connection::connect(&address).unwrap_or_else(|err| {
+        eprintln!("Failed to connect to {address}: {err}");
+        exit(1)
+    });
+    if let Ok(secret) = std::env::var("RUDIBI_SECRET")
+        && let Err(err) = conn.authenticate(user.as_deref(), &secret) {
+        eprintln!("Failed to log in: {err}");
+        exit(1)
+    }
+    if let Some(import) = import {
+        exit(run_import(&mut conn, import))
+    }
+    let mut editor = DefaultEditor::new().unwrap_or_else(|err| {
+        eprintln!("Failed to read the terminal: {err}");
+        exit(1)
+    });
+    let history = history_file();
+    if let Some(history) = &history {
+        // None is kept yet the first time
+        let _ = editor.load_history(history);
+    }
+    let mut statements = Statements::default();
+    loop {
+        let line = match editor.readline(if statements.is_pending() { CONTINUED } else { PROMPT }) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                statements = Statements::default();
+                continue;
+            },
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("Failed to read a line: {err}");
+                break;
+            },
+        };
+        if !line.trim().is_empty() {
+            let _ = editor.add_history_entry(line.as_str());
+        }
+        for input in statements.push_line(&line) {
+            match input {
+                Input::Statement(statement) => match run(&mut conn, &statement, format) {
+                    Ok(Shown::Rows(rows)) if format != Format::Table => print!("{rows}"),
+                    Ok(Shown::Status(reply)) if format != Format::Table => eprintln!("{reply}"),
+                    Ok(Shown::Rows(reply) | Shown::Status(reply)) => println!("{reply}\n"),
+                    Err(err) => match server_error(&err) {
+                        Some(reply) if format != Format::Table => eprintln!("ERROR {reply}"),
+                        Some(reply) => println!("ERROR {reply}\n"),
+                        None => {
+                            eprintln!("Connection failed: {err}");
+                            save_history(&mut editor, &history);
+                            exit(1)
+                        },
+                    },
+                },
+                Input::Help => println!("{HELP}\n"),
+                Input::Unknown(command) => println!("Unknown command {command}, \\? lists them\n"),
+                Input::Quit => {
+                    save_history(&mut editor, &history);
+                    return;
+                },
+            }
+        }
+    }
+    save_history(&mut editor, &history);
+}
+
+fn run(conn: &mut Connection, statement: &str, format: Format) -> io::Result<Shown> {
+    if !is_select(statement) {
+        return conn.execute(statement).map(Shown::Status);
+    }
+    let mut rows = conn.select(statement)?;
+    let header = if rows.is_empty() { Vec::new() } else { rows.remove(0) };
+    Ok(Shown::Rows(format.render(&header, &rows)))
+}
+
+// The exit code of the import
+fn run_import(conn: &mut Connection, Import { table, columns, batch_rows, file }: Import) -> i32 {
+    let input: Box<dyn BufRead> = match file.as_str() {
+        "-" => Box::new(io::stdin().lock()),
+        path => match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(err) => {
+                eprintln!("Failed to open {path}: {err}");
+                return 1;
+            },
+        },
+    };
+    let mut records = CsvRecords::new(input);
+    let header = match records.next() {
+        Some(Ok(header)) => header.fields,
+        Some(Err(err)) => {
+            eprintln!("Failed to read {file}: {err}");
+            return 1;
+        },
+        None => {
+            eprintln!("{file} has no header");
+            return 1;
+        },
+    };
+    let mapping = describe(conn, &table).map_err(|err| format!("Failed to describe {table}: {err}"))
+        .and_then(|described| match &columns {
+            Some(given) => given_mapping(given, &described),
+            None => infer_mapping(&header, &described),
+        });
+    let mapping = match mapping {
+        Ok(mapping) if mapping.len() == header.len() => mapping,
+        Ok(mapping) => {
+            eprintln!("{} columns are given for the {} of {file}", mapping.len(), header.len());
+            return 1;
+        },
+        Err(err) => {
+            eprintln!("{err}");
+            return 1;
+        },
+    };
+
+    let terminal = io::stderr().is_terminal();
+    let mut progress = |progress| match progress {
+        Progress::Imported(rows) if terminal => eprint!("\r\x1b[KImported {rows} rows"),
+        Progress::Imported(_) => {},
+        Progress::Failed(line, why) if terminal => eprintln!("\r\x1b[K{file}:{line}: {why}"),
+        Progress::Failed(line, why) => eprintln!("{file}:{line}: {why}"),
+    };
+    let mut read_error = None;
+    let records = records.map_while(|record| record.map_err(|err| read_error = Some(err)).ok());
+    let mut importer = Importer::new(conn, &table, mapping, batch_rows, &mut progress);
+    let imported = importer.import(records);
+    let (rows, failed) = (importer.imported, importer.failed);
+    if terminal {
+        eprint!("\r\x1b[K");
+    }
+    eprintln!("Imported {rows} rows into {table}, {failed} failed");
+    match (imported, read_error) {
+        (Err(err), _) => eprintln!("Connection failed: {err}"),
+        (Ok(()), Some(err)) => eprintln!("Failed to read {file}: {err}"),
+        (Ok(()), None) if failed == 0 => return 0,
+        (Ok(()), None) => {},
+    }
+    1
+}
+
+fn args() -> Result<Args, String> {
+    let mut parsed = Args { address: "127.0.0.1:1337".to_string(), user: None, format: Format::default(), import: None };
+    let mut args = std::env::args().skip(1).peekable();
+    let mut import = args.next_if_eq("import").map(|_| Import { batch_rows: DEFAULT_BATCH_ROWS, ..Import::default() });
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+        match (arg.as_str(), &mut import) {
+            ("--host", _) => parsed.address = value()?,
+            ("--user", _) => parsed.user = Some(value()?),
+            ("--format", None) => {
+                let name = value()?;
+                parsed.format = Format::parse(&name).ok_or_else(|| format!("Unknown format {name}"))?;
+            },
+            ("--table", Some(import)) => import.table = value()?,
+            ("--columns", Some(import)) => import.columns = Some(value()?),
+            ("--batch", Some(import)) => {
+                let rows = value()?;
+                import.batch_rows = rows.parse().ok().filter(|&rows| rows > 0).ok_or_else(|| format!("Bad batch size {rows}"))?;
+            },
+            (file, Some(import)) if import.file.is_empty() && (file == "-" || !file.starts_with('-')) => import.file = file.to_string(),
+            _ => return Err(format!("Unknown argument {arg}")),
+        }
+    }
+    match import {
+        Some(import) if import.table.is_empty() || import.file.is_empty() => Err("Import needs --table and a file".to_string()),
+        import => Ok(Args { import, ..parsed }),
+    }
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".rudibi_history"))
+}
+
+fn save_history(editor: &mut DefaultEditor, history: &Option<PathBuf>) {
+    if let Some(history) = history
+        && let Err(err) = editor.save_history(history) {
+        eprintln!("Failed to save the history to {}: {err}", history.display());
+    }
+}
@@ -0,0 +1,124 @@
+// Input of the interactive client, a line at a time
+// Statements end with a semicolon outside quotes, so they can span lines, and a line can hold
+// several. A line starting with a backslash, while no statement is pending, is a command:
+//   \q          quits
+//   \dt         lists the tables, as SHOW TABLES
+//   \d TABLE    lists the table's columns, as DESCRIBE TABLE
+//   \?          shows the commands
+
+pub const HELP: &str = "\
+Statements end with a semicolon and can span lines. Commands:
+  \\q          quit
+  \\dt         list the tables
+  \\d TABLE    list the columns of TABLE
+  \\?          show this help";
+
+#[derive(Debug, PartialEq)]
+pub enum Input {
+    // Without its semicolon
+    Statement(String),
+    Quit,
+    Help,
+    // A command that isn't known, as it was typed
+    Unknown(String),
+}
+
+// Lines typed so far of a statement not yet ended
+#[derive(Default)]
+pub struct Statements {
+    pending: String,
+    quoted: bool,
+}
+
+impl Statements {
+
+    // What the line ends, the rest kept for the next lines
+    pub fn push_line(&mut self, line: &str) -> Vec<Input> {
+        if !self.is_pending() && line.trim_start().starts_with('\\') {
+            return vec![command(line.trim())];
+        }
+        if !self.pending.is_empty() {
+            self.pending.push('\n');
+        }
+        let mut ended = Vec::new();
+        for c in line.chars() {
+            match c {
+                '\'' => self.quoted = !self.quoted,
+                ';' if !self.quoted => {
+                    let statement = std::mem::take(&mut self.pending);
+                    if !statement.trim().is_empty() {
+                        ended.push(Input::Statement(statement.trim().to_string()));
+                    }
+                    continue;
+                },
+                _ => {},
+            }
+            self.pending.push(c);
+        }
+        if self.pending.trim().is_empty() {
+            self.pending.clear();
+        }
+        ended
+    }
+
+    // Whether a statement was started and isn't ended yet
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+
+fn command(line: &str) -> Input {
+    match line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["\\q"] => Input::Quit,
+        ["\\?"] => Input::Help,
+        ["\\dt"] => Input::Statement("SHOW TABLES".to_string()),
+        ["\\d", table] => Input::Statement(format!("DESCRIBE {table}")),
+        _ => Input::Unknown(line.to_string()),
+    }
+}
+
+// Whether the statement is a select, whose rows are streamed and shown by the client
+pub fn is_select(statement: &str) -> bool {
+    statement.split_whitespace().next().is_some_and(|keyword| keyword.eq_ignore_ascii_case("SELECT"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(text: &str) -> Input {
+        Input::Statement(text.to_string())
+    }
+
+    #[test]
+    fn statements_spanning_lines() {
+        let mut statements = Statements::default();
+
+        assert_eq!(statements.push_line("SELECT id"), []);
+        assert!(statements.is_pending());
+        assert_eq!(statements.push_line("FROM Fruits WHERE name = ';';  SHOW TABLES;"), [
+            statement("SELECT id\nFROM Fruits WHERE name = ';'"),
+            statement("SHOW TABLES"),
+        ]);
+        assert!(!statements.is_pending());
+    }
+
+    #[test]
+    fn commands() {
+        let mut statements = Statements::default();
+
+        assert_eq!(statements.push_line("  \\d Fruits"), [statement("DESCRIBE Fruits")]);
+        assert_eq!(statements.push_line("\\dt"), [statement("SHOW TABLES")]);
+        assert_eq!(statements.push_line("\\q"), [Input::Quit]);
+        assert_eq!(statements.push_line("\\x"), [Input::Unknown("\\x".to_string())]);
+        statements.push_line("SELECT");
+        assert_eq!(statements.push_line("\\q"), []);
+    }
+
+    #[test]
+    fn selects() {
+        assert!(is_select("  select id FROM Fruits"));
+        assert!(!is_select("SHOW TABLES"));
+        assert!(!is_select(""));
+    }
+}
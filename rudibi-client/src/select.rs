@@ -0,0 +1,150 @@
+// Selects built in code rather than written as text:
+//   conn.select_from("Fruits").columns(["id", "name"]).filter(col("id").gt(200u32)).fetch()
+// The filter is a `query::Bool`, as the server runs it, and the select is sent encoded in a QUERY
+// frame, see `rudibi_core::frame`. So values of any type, and strings holding quotes, are
+// compared as they are, with nothing to escape.
+
+use std::io;
+
+use rudibi_core::command::Command;
+use rudibi_core::serial;
+
+use crate::connection::Connection;
+use crate::dtype::ColumnValue;
+use crate::query::{Bool, Value};
+
+pub struct Select<'c, 'q> {
+    conn: &'c mut Connection,
+    table: &'q str,
+    columns: Vec<&'q str>,
+    filter: Bool<'q>,
+}
+
+impl<'c, 'q> Select<'c, 'q> {
+
+    pub(crate) fn new(conn: &'c mut Connection, table: &'q str) -> Select<'c, 'q> {
+        Select { conn, table, columns: Vec::new(), filter: Bool::True }
+    }
+
+    // The columns selected, in order, all of the table's if none are given
+    pub fn columns(mut self, columns: impl IntoIterator<Item = &'q str>) -> Self {
+        self.columns.extend(columns);
+        self
+    }
+
+    // Rows are selected if they match every filter given
+    pub fn filter(mut self, filter: Bool<'q>) -> Self {
+        self.filter = match self.filter {
+            Bool::True => filter,
+            previous => previous.and(filter),
+        };
+        self
+    }
+
+    // The selected rows, each a list of fields with the column names first, as `Connection::select`
+    pub fn fetch(self) -> io::Result<Vec<Vec<String>>> {
+        let select = Command::Select { table: self.table, columns: self.columns, filter: self.filter };
+        self.conn.query(&serial::encode(&select))
+    }
+}
+
+// A column to compare with values in a filter
+pub fn col(name: &str) -> Col<'_> {
+    Col(name)
+}
+
+// Rows not matching the filter
+pub fn not(filter: Bool<'_>) -> Bool<'_> {
+    Bool::Not(Box::new(filter))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Col<'q>(&'q str);
+
+impl<'q> Col<'q> {
+
+    pub fn eq(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::Eq, value)
+    }
+
+    pub fn neq(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::Neq, value)
+    }
+
+    pub fn gt(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::Gt, value)
+    }
+
+    pub fn gte(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::Gte, value)
+    }
+
+    pub fn lt(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::Lt, value)
+    }
+
+    pub fn lte(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::Lte, value)
+    }
+
+    // As `eq` and `neq`, but NaN matches NaN
+    pub fn is_not_distinct_from(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::IsNotDistinctFrom, value)
+    }
+
+    pub fn is_distinct_from(self, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        self.compare(Bool::IsDistinctFrom, value)
+    }
+
+    fn compare(self, op: fn(Value<'q>, Value<'q>) -> Bool<'q>, value: impl Into<ColumnValue<'q>>) -> Bool<'q> {
+        op(Value::ColumnRef(self.0), Value::Const(value.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::connection::server_error;
+    use crate::testlib::connected;
+
+    #[test]
+    fn filters_and_columns() {
+        let mut conn = connected();
+
+        let rows = conn.select_from("Fruits").columns(["id", "name"]).filter(col("id").gt(200u32)).fetch().unwrap();
+        let names = conn.select_from("Fruits").columns(["name"])
+            .filter(col("name").eq("banana").or(col("id").lte(100u32)))
+            .filter(not(col("id").eq(300u32)))
+            .fetch().unwrap();
+        let all = conn.select_from("Fruits").filter(col("name").neq("banana")).fetch().unwrap();
+
+        assert_eq!(rows, [["id", "name"], ["300", "banana"], ["400", "cherry"]]);
+        assert_eq!(names, [["name"], ["apple"], ["banana"]]);
+        assert_eq!(all, [["id", "name"], ["100", "apple"], ["400", "cherry"]]);
+    }
+
+    #[test]
+    fn quotes_and_transactions() {
+        let mut conn = connected();
+
+        let mut transaction = conn.begin().unwrap();
+        transaction.execute("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')").unwrap();
+        let kiwis = transaction.select_from("Fruits").columns(["id"]).filter(col("name").eq("kiwi")).fetch().unwrap();
+        transaction.rollback().unwrap();
+        let quoted = conn.select_from("Fruits").filter(col("name").eq("kiwi's")).fetch().unwrap();
+
+        assert_eq!(kiwis, [["id"], ["500"]]);
+        assert_eq!(quoted, [["id", "name"]]);
+    }
+
+    #[test]
+    fn server_errors() {
+        let mut conn = connected();
+
+        let err = conn.select_from("Fruits").columns(["weight"]).fetch().unwrap_err();
+
+        assert!(server_error(&err).is_some());
+        assert_eq!(conn.select_from("Fruits").filter(col("id").lt(200u32)).fetch().unwrap(), [["id", "name"], ["100", "apple"]]);
+    }
+}
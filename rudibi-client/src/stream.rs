@@ -0,0 +1,64 @@
+// Client side of flow-controlled insert streaming
+// Rows are pulled from the source iterator one window at a time, so datasets larger than
+// client memory can be loaded without manual batching
+
+// Transport for row chunks. Returns the window granted by the server for the next chunk.
+pub trait ChunkSink<R> {
+    type Error;
+    fn send_chunk(&mut self, chunk: Vec<R>) -> Result<usize, Self::Error>;
+}
+
+// Sends all `rows` through `sink`, never exceeding the current window. Returns rows sent.
+pub fn stream_rows<R, I, S>(rows: I, initial_window: usize, sink: &mut S) -> Result<usize, S::Error>
+where
+    I: IntoIterator<Item = R>,
+    S: ChunkSink<R>,
+{
+    let mut rows = rows.into_iter();
+    let mut window = initial_window.max(1);
+    let mut sent = 0;
+    loop {
+        let chunk: Vec<R> = rows.by_ref().take(window).collect();
+        if chunk.is_empty() {
+            return Ok(sent);
+        }
+        sent += chunk.len();
+        // A zero window would stall the stream forever, treat it as "one at a time"
+        window = sink.send_chunk(chunk)?.max(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ShrinkingSink {
+        chunks: Vec<Vec<u32>>,
+    }
+
+    impl ChunkSink<u32> for ShrinkingSink {
+        type Error = ();
+        fn send_chunk(&mut self, chunk: Vec<u32>) -> Result<usize, ()> {
+            let next_window = chunk.len().saturating_sub(1);
+            self.chunks.push(chunk);
+            Ok(next_window)
+        }
+    }
+
+    #[test]
+    fn chunks_follow_window_updates() {
+        let mut sink = ShrinkingSink { chunks: vec![] };
+
+        let sent = stream_rows(0..10u32, 4, &mut sink).unwrap();
+
+        assert_eq!(sent, 10);
+        assert_eq!(sink.chunks, vec![vec![0, 1, 2, 3], vec![4, 5, 6], vec![7, 8], vec![9]]);
+    }
+
+    #[test]
+    fn empty_source_sends_nothing() {
+        let mut sink = ShrinkingSink { chunks: vec![] };
+        assert_eq!(stream_rows(std::iter::empty(), 4, &mut sink), Ok(0));
+        assert!(sink.chunks.is_empty());
+    }
+}
@@ -0,0 +1,93 @@
+// Rows of streamed selects, read from the server's CSV and shown as aligned text tables in the
+// style of the server's own replies, see `rudibi_server::display::format_table`
+
+// Records of CSV as the server writes it, see `rudibi_server::display::format_csv`
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            ('"', _) => quoted = !quoted,
+            (',', false) => record.push(std::mem::take(&mut field)),
+            ('\n', false) => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            },
+            (c, _) => field.push(c),
+        }
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records
+}
+
+// Columns whose values are all numbers are aligned right, others left
+pub fn format_table(header: &[String], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|name| name.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let numeric: Vec<bool> = (0..header.len())
+        .map(|idx| rows.iter().all(|row| row.get(idx).is_none_or(|cell| cell.is_empty() || cell.parse::<f64>().is_ok())))
+        .collect();
+
+    let mut out = String::new();
+    let mut write_row = |row: &[String], align: bool| {
+        out.push('|');
+        for (idx, cell) in row.iter().enumerate().take(widths.len()) {
+            let pad = " ".repeat(widths[idx] - cell.chars().count());
+            if align && numeric[idx] {
+                out.push_str(&format!(" {pad}{cell} |"));
+            } else {
+                out.push_str(&format!(" {cell}{pad} |"));
+            }
+        }
+        out.push('\n');
+    };
+    write_row(header, false);
+    let divider: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    write_row(&divider, false);
+    for row in rows {
+        write_row(row, true);
+    }
+    out.push_str(&format!("({} rows)", rows.len()));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_fields() {
+        let records = parse_csv("id,name\n1,\"kiwi, \"\"gold\"\"\"\n2,\"\"\n3,\"two\nlines\"\n");
+
+        assert_eq!(records, [
+            vec!["id", "name"],
+            vec!["1", "kiwi, \"gold\""],
+            vec!["2", ""],
+            vec!["3", "two\nlines"],
+        ]);
+    }
+
+    #[test]
+    fn aligned_columns() {
+        let header = vec!["id".to_string(), "name".to_string()];
+        let rows = vec![vec!["5".to_string(), "kiwi".to_string()], vec!["100".to_string(), "fig".to_string()]];
+
+        let table = format_table(&header, &rows);
+
+        assert_eq!(table, "| id  | name |\n| --- | ---- |\n|   5 | kiwi |\n| 100 | fig  |\n(2 rows)");
+    }
+}
@@ -0,0 +1,35 @@
+// Servers of the Fruits table from `rudibi_server::testlib` for the tests of this crate to
+// connect to, each serving one connection on a thread of its own
+
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use rudibi_server::engine::StorageCfg;
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+
+use crate::connection::Connection;
+
+pub fn fruits_server() -> Server {
+    Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory)
+}
+
+// The address `server` takes a connection at
+pub fn listening(server: Arc<Server>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    thread::spawn(move || {
+        let conn = listener.accept().unwrap().0;
+        server.serve(&conn, &conn)
+    });
+    address
+}
+
+pub fn connected() -> Connection {
+    connected_to(Arc::new(fruits_server()))
+}
+
+pub fn connected_to(server: Arc<Server>) -> Connection {
+    Connection::connect(&listening(server)).unwrap()
+}
@@ -0,0 +1,110 @@
+// A transaction of a connection, see `Connection::begin` and the server's BEGIN, COMMIT and
+// ROLLBACK in `rudibi_server::protocol`. It borrows the connection, so only its statements are sent
+// on it until it's committed or rolled back. Dropped before either, it's rolled back, ignoring a
+// failed connection, which the server rolls back for once it closes.
+
+use std::io;
+
+use crate::connection::Connection;
+use crate::select::Select;
+
+pub struct Transaction<'c> {
+    conn: &'c mut Connection,
+    done: bool,
+}
+
+impl<'c> Transaction<'c> {
+
+    pub(crate) fn new(conn: &'c mut Connection) -> Transaction<'c> {
+        Transaction { conn, done: false }
+    }
+
+    // As `Connection::execute`, seeing the transaction's writes
+    pub fn execute(&mut self, statement: &str) -> io::Result<String> {
+        self.conn.execute(statement)
+    }
+
+    // As `Connection::select`, seeing the transaction's writes
+    pub fn select(&mut self, statement: &str) -> io::Result<Vec<Vec<String>>> {
+        self.conn.select(statement)
+    }
+
+    // As `Connection::select_from`, seeing the transaction's writes
+    pub fn select_from<'q>(&mut self, table: &'q str) -> Select<'_, 'q> {
+        self.conn.select_from(table)
+    }
+
+    pub fn commit(mut self) -> io::Result<()> {
+        self.end("COMMIT")
+    }
+
+    pub fn rollback(mut self) -> io::Result<()> {
+        self.end("ROLLBACK")
+    }
+
+    fn end(&mut self, statement: &str) -> io::Result<()> {
+        self.done = true;
+        self.conn.execute(statement).map(drop)
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.end("ROLLBACK");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::connection::server_error;
+    use crate::testlib::connected;
+
+    fn names(conn: &mut Connection) -> Vec<Vec<String>> {
+        conn.select("SELECT name FROM Fruits WHERE id > 300").unwrap()
+    }
+
+    #[test]
+    fn commit_keeps_writes() {
+        let mut conn = connected();
+
+        let mut transaction = conn.begin().unwrap();
+        transaction.execute("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')").unwrap();
+        let seen = transaction.select("SELECT name FROM Fruits WHERE id > 300").unwrap();
+        transaction.commit().unwrap();
+
+        assert_eq!(seen, [["name"], ["cherry"], ["kiwi"]]);
+        assert_eq!(names(&mut conn), [["name"], ["cherry"], ["kiwi"]]);
+    }
+
+    #[test]
+    fn rollback_and_drop_undo_writes() {
+        let mut conn = connected();
+
+        let mut transaction = conn.begin().unwrap();
+        transaction.execute("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')").unwrap();
+        transaction.rollback().unwrap();
+        {
+            let mut transaction = conn.begin().unwrap();
+            transaction.execute("INSERT INTO Fruits (id, name) VALUES (600, 'lime')").unwrap();
+        }
+
+        assert_eq!(names(&mut conn), [["name"], ["cherry"]]);
+    }
+
+    #[test]
+    fn failed_statements_keep_it_open() {
+        let mut conn = connected();
+
+        let mut transaction = conn.begin().unwrap();
+        transaction.execute("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')").unwrap();
+        let err = transaction.execute("CREATE TABLE Nuts (id U32)").unwrap_err();
+        transaction.commit().unwrap();
+
+        assert_eq!(server_error(&err).map(|reply| reply.code), Some(9001));
+        assert_eq!(names(&mut conn), [["name"], ["cherry"], ["kiwi"]]);
+    }
+}
@@ -0,0 +1,17 @@
+// Ids of blobs and the chunks they're sent in, see `rudibi_server::blob` for how they're stored
+
+// Most bytes of a blob held by one frame or stored chunk
+pub const BLOB_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlobId(pub u64);
+
+impl BlobId {
+    pub fn to_bytes(self) -> [u8; 8] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<BlobId> {
+        Some(BlobId(u64::from_le_bytes(bytes.try_into().ok()?)))
+    }
+}
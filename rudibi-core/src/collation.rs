@@ -0,0 +1,62 @@
+// Collations decide how UTF8 values of a column compare
+// Filters, ordering and indexes all go through the column's collation, so a case-insensitive
+// column never matches differently depending on how it's queried
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Collation {
+    // Byte-wise comparison of the UTF8 encoding
+    #[default]
+    Binary,
+    // Compares the Unicode lowercase mapping of both strings
+    CaseInsensitive,
+}
+
+impl Collation {
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Collation::Binary => "binary",
+            Collation::CaseInsensitive => "case_insensitive",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Collation> {
+        match name {
+            "binary" => Some(Collation::Binary),
+            "case_insensitive" => Some(Collation::CaseInsensitive),
+            _ => None,
+        }
+    }
+
+    pub fn compare(self, left: &str, right: &str) -> Ordering {
+        match self {
+            Collation::Binary => left.cmp(right),
+            Collation::CaseInsensitive => left.chars().flat_map(char::to_lowercase)
+                .cmp(right.chars().flat_map(char::to_lowercase)),
+        }
+    }
+
+    // Strings with equal keys compare as equal, for hashing and index lookups
+    pub fn sort_key(self, value: &str) -> Cow<'_, str> {
+        match self {
+            Collation::Binary => Cow::Borrowed(value),
+            Collation::CaseInsensitive => Cow::Owned(value.to_lowercase()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_ordering() {
+        assert_eq!(Collation::CaseInsensitive.compare("Apple", "aPPLE"), Ordering::Equal);
+        assert_eq!(Collation::CaseInsensitive.compare("apple", "Banana"), Ordering::Less);
+        assert_eq!(Collation::Binary.compare("apple", "Banana"), Ordering::Greater);
+        assert_eq!(Collation::CaseInsensitive.sort_key("ÄPFEL"), "äpfel");
+    }
+}
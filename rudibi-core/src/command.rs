@@ -0,0 +1,49 @@
+// Commands as the server runs them, parsed from text by `rudibi_server::protocol::parse` or built
+// by a client and sent encoded, see `serial`
+
+use crate::query::Bool;
+use crate::schema::Column;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Literal<'a> {
+    Number(&'a str),
+    Text(&'a str),
+    Hex(&'a str),
+}
+
+pub enum Command<'a> {
+    CreateTable { table: &'a str, columns: Vec<Column> },
+    Insert { table: &'a str, columns: Vec<&'a str>, rows: Vec<Vec<Literal<'a>>> },
+    // No columns selects all of them
+    Select { table: &'a str, columns: Vec<&'a str>, filter: Bool<'a> },
+    Delete { table: &'a str, filter: Bool<'a> },
+    // The rows of a select, kept for the connection to fetch, see `rudibi_server::cursor`
+    Declare { cursor: &'a str, table: &'a str, columns: Vec<&'a str>, filter: Bool<'a> },
+    Fetch { cursor: &'a str, count: usize },
+    Close { cursor: &'a str },
+    ShowTables,
+    Describe { table: &'a str },
+    Status,
+    Stats,
+    ListTables,
+}
+
+impl Command<'_> {
+    // What the command is counted as, see `rudibi_server::metrics`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::CreateTable { .. } => "create",
+            Command::Insert { .. } => "insert",
+            Command::Select { .. } => "select",
+            Command::Delete { .. } => "delete",
+            Command::Declare { .. } => "declare",
+            Command::Fetch { .. } => "fetch",
+            Command::Close { .. } => "close",
+            Command::ShowTables => "show",
+            Command::Describe { .. } => "describe",
+            Command::Status => "status",
+            Command::Stats => "stats",
+            Command::ListTables => "list",
+        }
+    }
+}
@@ -0,0 +1,246 @@
+// Compression of stored rows. `Compression::Lz` compresses each row on its own, with LZ77 in the
+// style of LZ4 without the dependency. The codecs of the lz4 and zstd features compress whole page
+// groups once they're full, which finds far more repetition than a row has.
+// An Lz block is a list of sequences: a token (literal count, match length), the literals, then a
+// back-reference into the output written so far. The last sequence only has literals.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    // Row content is LZ compressed when that makes it smaller, e.g. long or repetitive text
+    Lz,
+    // Full page groups are LZ4 compressed, with the lz4 feature
+    #[cfg(feature = "lz4")]
+    Lz4,
+    // Full page groups are zstd compressed, with the zstd feature
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Compression {
+
+    // Stored tag, e.g. in catalogs and compressed page groups. Tags never change.
+    pub fn tag(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz => 1,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => 2,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => 3,
+        }
+    }
+
+    // None for unknown tags, and those of codecs the build doesn't have
+    pub fn from_tag(tag: u8) -> Option<Compression> {
+        match tag {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz),
+            #[cfg(feature = "lz4")]
+            2 => Some(Compression::Lz4),
+            #[cfg(feature = "zstd")]
+            3 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    // Whether full page groups are compressed rather than rows
+    pub fn packs_pages(self) -> bool {
+        !matches!(self, Compression::None | Compression::Lz)
+    }
+
+    // Block of a page group's bytes, None for compressions that don't pack pages
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub fn pack(self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Compression::None | Compression::Lz => None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Some(lz4_flex::block::compress(data)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::compress(data, 0).ok(),
+        }
+    }
+
+    // None if the block is malformed or doesn't unpack to exactly `len` bytes
+    #[cfg_attr(not(any(feature = "lz4", feature = "zstd")), allow(unused_variables))]
+    pub fn unpack(self, block: &[u8], len: usize) -> Option<Vec<u8>> {
+        let data: Option<Vec<u8>> = match self {
+            Compression::None | Compression::Lz => None,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => lz4_flex::block::decompress(block, len).ok(),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => zstd::bulk::decompress(block, len).ok(),
+        };
+        data.filter(|data| data.len() == len)
+    }
+}
+
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = u16::MAX as usize;
+const HASH_BITS: u32 = 12;
+// Nibble value that continues the length in extra bytes
+const LENGTH_MORE: usize = 15;
+
+fn write_length(out: &mut Vec<u8>, mut len: usize) {
+    while len >= 255 {
+        out.push(255);
+        len -= 255;
+    }
+    out.push(len as u8);
+}
+
+fn read_length(input: &[u8], pos: &mut usize) -> Option<usize> {
+    let mut len = 0;
+    loop {
+        let byte = *input.get(*pos)?;
+        *pos += 1;
+        len += byte as usize;
+        if byte != 255 {
+            return Some(len);
+        }
+    }
+}
+
+// Match is (offset back from the current position, length)
+fn write_sequence(out: &mut Vec<u8>, literals: &[u8], back_ref: Option<(usize, usize)>) {
+    let match_len = back_ref.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push(((literals.len().min(LENGTH_MORE) as u8) << 4) | match_len.min(LENGTH_MORE) as u8);
+    if literals.len() >= LENGTH_MORE {
+        write_length(out, literals.len() - LENGTH_MORE);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = back_ref {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if match_len >= LENGTH_MORE {
+            write_length(out, match_len - LENGTH_MORE);
+        }
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(input.len() / 2 + 16);
+    // Last position + 1 of each hashed 4-byte sequence, 0 for none
+    let mut table = vec![0usize; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH <= input.len() {
+        let seq = u32::from_le_bytes(input[pos..pos + MIN_MATCH].try_into().unwrap());
+        let slot = (seq.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize;
+        let candidate = std::mem::replace(&mut table[slot], pos + 1);
+        let is_match = candidate > 0
+            && pos - (candidate - 1) <= MAX_OFFSET
+            && input[candidate - 1..candidate - 1 + MIN_MATCH] == input[pos..pos + MIN_MATCH];
+        if !is_match {
+            pos += 1;
+            continue;
+        }
+        let start = candidate - 1;
+        let mut len = MIN_MATCH;
+        while pos + len < input.len() && input[start + len] == input[pos + len] {
+            len += 1;
+        }
+        write_sequence(&mut out, &input[anchor..pos], Some((pos - start, len)));
+        pos += len;
+        anchor = pos;
+    }
+    write_sequence(&mut out, &input[anchor..], None);
+    out
+}
+
+// None if the block is malformed or doesn't decompress to exactly `len` bytes
+pub fn decompress(input: &[u8], len: usize) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut pos = 0;
+    loop {
+        let token = *input.get(pos)?;
+        pos += 1;
+        let mut literals = (token >> 4) as usize;
+        if literals == LENGTH_MORE {
+            literals += read_length(input, &mut pos)?;
+        }
+        if out.len() + literals > len {
+            return None;
+        }
+        out.extend_from_slice(input.get(pos..pos.checked_add(literals)?)?);
+        pos += literals;
+        if pos == input.len() {
+            break;
+        }
+
+        let offset = u16::from_le_bytes(input.get(pos..pos + 2)?.try_into().unwrap()) as usize;
+        pos += 2;
+        let mut match_len = (token & 0x0f) as usize;
+        if match_len == LENGTH_MORE {
+            match_len += read_length(input, &mut pos)?;
+        }
+        match_len += MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return None;
+        }
+        // Byte by byte, a match may overlap the bytes it produces
+        let start = out.len() - offset;
+        for idx in start..start + match_len {
+            out.push(out[idx]);
+        }
+    }
+    (out.len() == len).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input);
+        assert_eq!(decompress(&compressed, input.len()).as_deref(), Some(input));
+        compressed
+    }
+
+    #[test]
+    fn repetitive_input_shrinks() {
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(100);
+        assert!(round_trip(text.as_bytes()).len() < text.len() / 10);
+        assert!(round_trip(&[7u8; 10000]).len() < 100);
+    }
+
+    #[test]
+    fn any_input_round_trips() {
+        round_trip(b"");
+        round_trip(b"abc");
+        let mut state = 12345u64;
+        let noise: Vec<u8> = (0..5000).map(|_| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 56) as u8
+        }).collect();
+        round_trip(&noise);
+    }
+
+    #[test]
+    fn page_codecs_round_trip() {
+        let page = "the quick brown fox jumps over the lazy dog. ".repeat(200);
+        let codecs: Vec<Compression> = vec![
+            #[cfg(feature = "lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd,
+        ];
+        for codec in codecs {
+            let packed = codec.pack(page.as_bytes()).unwrap();
+            assert!(packed.len() < page.len() / 10);
+            assert_eq!(codec.unpack(&packed, page.len()).as_deref(), Some(page.as_bytes()));
+            assert_eq!(codec.unpack(&packed, page.len() + 1), None);
+            assert_eq!(codec.unpack(&packed[..packed.len() / 2], page.len()), None);
+            assert_eq!(Compression::from_tag(codec.tag()), Some(codec));
+        }
+        assert_eq!(Compression::Lz.pack(page.as_bytes()), None);
+    }
+
+    #[test]
+    fn malformed_blocks_are_rejected() {
+        let compressed = compress(&[1u8; 1000]);
+        assert_eq!(decompress(&compressed, 999), None);
+        assert_eq!(decompress(&compressed[..compressed.len() - 1], 1000), None);
+        assert_eq!(decompress(&[0x0f, 1, 0], 10), None);
+    }
+}
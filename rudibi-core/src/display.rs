@@ -0,0 +1,241 @@
+// Human-readable rendering of column values and result sets
+// Shared by the pretty-printer and CLI output so both truncate and encode values the same way
+
+use crate::dtype::*;
+use crate::schema::{Column, ResultSet, Row};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum BinaryFormat {
+    #[default]
+    Hex,
+    Base64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplayOptions {
+    // Longest rendering of a UTF8 or binary value, in characters. `None` shows full values.
+    pub max_width: Option<usize>,
+    pub binary: BinaryFormat,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions { max_width: Some(32), binary: BinaryFormat::Hex }
+    }
+}
+
+impl DisplayOptions {
+    pub fn full() -> Self {
+        DisplayOptions { max_width: None, ..Default::default() }
+    }
+}
+
+const ELLIPSIS: char = '…';
+
+fn truncate(text: String, max_width: Option<usize>) -> String {
+    match max_width {
+        Some(width) if text.chars().count() > width => {
+            let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+            truncated.push(ELLIPSIS);
+            truncated
+        },
+        _ => text,
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(2 + bytes.len() * 2);
+    out.push_str("0x");
+    for byte in bytes {
+        out.push_str(&format!("{byte:02x}"));
+    }
+    out
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let triple = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(triple >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+pub fn format_bytes(bytes: &[u8], options: &DisplayOptions) -> String {
+    let encoded = match options.binary {
+        BinaryFormat::Hex => hex(bytes),
+        BinaryFormat::Base64 => base64(bytes),
+    };
+    truncate(encoded, options.max_width)
+}
+
+pub fn format_value(value: &ColumnValue, options: &DisplayOptions) -> String {
+    match value {
+        ColumnValue::U32(val) => val.to_string(),
+        ColumnValue::F64(val) => val.to_string(),
+        ColumnValue::UTF8(val) => truncate(val.to_string(), options.max_width),
+        ColumnValue::Bytes(val) => format_bytes(val, options),
+        ColumnValue::Uuid(val) => val.to_string(),
+    }
+}
+
+// Stored bytes that can't be decoded as their data type are shown in the binary format
+pub fn format_raw(dtype: &DataType, data: &[u8], options: &DisplayOptions) -> String {
+    match canonical_column(dtype, data) {
+        Ok(value) => format_value(&value, options),
+        Err(_) => format_bytes(data, options),
+    }
+}
+
+fn right_aligned(dtype: &DataType) -> bool {
+    matches!(dtype, DataType::U32 | DataType::F64)
+}
+
+// Aligned text table with a header row, in the style of the benchmark printer
+pub fn format_table(results: &ResultSet, options: &DisplayOptions) -> String {
+    let header: Vec<String> = results.schema.iter().map(|col| col.name.clone()).collect();
+    let cells: Vec<Vec<String>> = results.data.iter()
+        .map(|row| results.schema.iter().enumerate()
+            .map(|(idx, col)| format_raw(&col.dtype, row.get_column(idx), options))
+            .collect())
+        .collect();
+
+    let mut widths: Vec<usize> = header.iter().map(|name| name.chars().count()).collect();
+    for row in &cells {
+        for (idx, cell) in row.iter().enumerate() {
+            widths[idx] = widths[idx].max(cell.chars().count());
+        }
+    }
+
+    let mut out = String::new();
+    let mut write_row = |row: &[String], align_by_type: bool| {
+        out.push('|');
+        for (idx, cell) in row.iter().enumerate() {
+            let pad = " ".repeat(widths[idx] - cell.chars().count());
+            if align_by_type && right_aligned(&results.schema[idx].dtype) {
+                out.push_str(&format!(" {pad}{cell} |"));
+            } else {
+                out.push_str(&format!(" {cell}{pad} |"));
+            }
+        }
+        out.push('\n');
+    };
+    write_row(&header, false);
+    let divider: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    write_row(&divider, false);
+    for row in &cells {
+        write_row(row, true);
+    }
+    out.push_str(&format!("({} rows)\n", results.len()));
+    out
+}
+
+// CSV with a header row, as in RFC 4180 but with newline line ends. Fields with a comma, quote or
+// line break are quoted, and so are empty ones, so no row is an empty line.
+pub fn format_csv(results: &ResultSet, options: &DisplayOptions) -> String {
+    let mut out = csv_header(&results.schema);
+    for row in &results.data {
+        out.push_str(&csv_row(&results.schema, row, options));
+    }
+    out
+}
+
+// Lines of `format_csv`, for rows that come one at a time
+pub fn csv_header(schema: &[Column]) -> String {
+    let header: Vec<String> = schema.iter().map(|col| csv_field(col.name.clone())).collect();
+    header.join(",") + "\n"
+}
+
+pub fn csv_row(schema: &[Column], row: &Row, options: &DisplayOptions) -> String {
+    let cells: Vec<String> = schema.iter().enumerate()
+        .map(|(idx, col)| csv_field(format_raw(&col.dtype, row.get_column(idx), options)))
+        .collect();
+    cells.join(",") + "\n"
+}
+
+fn csv_field(text: String) -> String {
+    if text.is_empty() || text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text
+    }
+}
+
+impl ResultSet {
+    pub fn format_table(&self) -> String {
+        format_table(self, &DisplayOptions::default())
+    }
+}
+
+impl std::fmt::Display for ResultSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.format_table())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Column, Row};
+
+    #[test]
+    fn base64_pads_partial_chunks() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn long_values_are_truncated() {
+        let options = DisplayOptions { max_width: Some(6), binary: BinaryFormat::Hex };
+        assert_eq!(format_value(&ColumnValue::UTF8("bananas"), &options), "banan…");
+        assert_eq!(format_value(&ColumnValue::UTF8("banana"), &options), "banana");
+        assert_eq!(format_value(&ColumnValue::Bytes(&[0xAA, 0xBB, 0xCC]), &options), "0xaab…");
+        assert_eq!(format_value(&ColumnValue::Bytes(&[0xAA, 0xBB, 0xCC]), &DisplayOptions::full()), "0xaabbcc");
+    }
+
+    #[test]
+    fn numbers_are_never_truncated() {
+        let options = DisplayOptions { max_width: Some(2), binary: BinaryFormat::Hex };
+        assert_eq!(format_value(&ColumnValue::U32(12345), &options), "12345");
+    }
+
+    #[test]
+    fn column_value_display() {
+        assert_eq!(ColumnValue::U32(7).to_string(), "7");
+        assert_eq!(ColumnValue::F64(1.5).to_string(), "1.5");
+        assert_eq!(ColumnValue::UTF8("it's").to_string(), "'it''s'");
+        assert_eq!(ColumnValue::Bytes(&[0x00, 0xFF]).to_string(), "0x00ff");
+    }
+
+    #[test]
+    fn undecodable_bytes_fall_back_to_binary() {
+        let options = DisplayOptions { max_width: None, binary: BinaryFormat::Base64 };
+        assert_eq!(format_raw(&DataType::UTF8 { max_bytes: 4 }, &[0xFF, 0xFE], &options), "//4=");
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        let results = ResultSet {
+            schema: vec![Column::new("id", DataType::U32), Column::new("name, full", DataType::UTF8 { max_bytes: 16 })],
+            data: vec![
+                Row::of_columns(&[&1u32.to_le_bytes(), b"say \"hi\""]),
+                Row::of_columns(&[&2u32.to_le_bytes(), b""]),
+                Row::of_columns(&[&3u32.to_le_bytes(), b"two\nlines"]),
+            ],
+            scan_stats: Default::default(),
+        };
+        assert_eq!(format_csv(&results, &DisplayOptions::full()), "id,\"name, full\"\n1,\"say \"\"hi\"\"\"\n2,\"\"\n3,\"two\nlines\"\n");
+    }
+}
@@ -0,0 +1,605 @@
+
+// Data types available in the database
+// The functionality of value comparisons and casts should go here
+
+use std::cmp::Ordering;
+use std::str;
+
+use crate::hash::StableHasher;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DataType {
+    U32,
+    F64,
+    UTF8 { max_bytes: usize },
+    VARBINARY { max_length: usize },
+    BUFFER { length: usize },
+    UUID,
+    // One of a fixed set of labels, stored as the label's index
+    ENUM { labels: Vec<String> },
+}
+
+impl DataType {
+
+    pub fn min_size(&self) -> usize {
+        match self {
+            DataType::U32 => size_of::<u32>(),
+            DataType::F64 => size_of::<f64>(),
+            DataType::UTF8 { max_bytes: _ } => 0,
+            DataType::VARBINARY { max_length: _ } => 0,
+            DataType::BUFFER { length } => *length,
+            DataType::UUID => size_of::<Uuid>(),
+            DataType::ENUM { labels } => enum_index_width(labels),
+        }
+    }
+
+    pub fn max_size(&self) -> usize {
+        match self {
+            DataType::U32 => size_of::<u32>(),
+            DataType::F64 => size_of::<f64>(),
+            DataType::UTF8 { max_bytes } => *max_bytes,
+            DataType::VARBINARY { max_length } => *max_length,
+            DataType::BUFFER { length } => *length,
+            DataType::UUID => size_of::<Uuid>(),
+            DataType::ENUM { labels } => enum_index_width(labels),
+        }
+    }
+
+    // Tags are part of schema fingerprints and must never change for existing types
+    pub fn hash_into(&self, hasher: &mut StableHasher) {
+        match self {
+            DataType::U32 => hasher.write(&[1]),
+            DataType::F64 => hasher.write(&[2]),
+            DataType::UTF8 { max_bytes } => { hasher.write(&[3]); hasher.write_u64(*max_bytes as u64) },
+            DataType::VARBINARY { max_length } => { hasher.write(&[4]); hasher.write_u64(*max_length as u64) },
+            DataType::BUFFER { length } => { hasher.write(&[5]); hasher.write_u64(*length as u64) },
+            DataType::UUID => hasher.write(&[6]),
+            DataType::ENUM { labels } => {
+                hasher.write(&[7]);
+                hasher.write_u64(labels.len() as u64);
+                for label in labels {
+                    hasher.write_field(label.as_bytes());
+                }
+            },
+        }
+    }
+}
+
+// Stored form of a data type, e.g. in table file headers. Uses the fingerprint tags.
+impl DataType {
+
+    pub fn encode_into(&self, out: &mut Vec<u8>) {
+        let param = |out: &mut Vec<u8>, tag: u8, val: usize| { out.push(tag); out.extend_from_slice(&(val as u64).to_le_bytes()) };
+        match self {
+            DataType::U32 => out.push(1),
+            DataType::F64 => out.push(2),
+            DataType::UTF8 { max_bytes } => param(out, 3, *max_bytes),
+            DataType::VARBINARY { max_length } => param(out, 4, *max_length),
+            DataType::BUFFER { length } => param(out, 5, *length),
+            DataType::UUID => out.push(6),
+            DataType::ENUM { labels } => {
+                param(out, 7, labels.len());
+                for label in labels {
+                    encode_field(out, label.as_bytes());
+                }
+            },
+        }
+    }
+
+    // Consumes the encoded type from the front of `bytes`
+    pub fn decode(bytes: &mut &[u8]) -> Option<DataType> {
+        let dtype = match take(bytes, 1)?[0] {
+            1 => DataType::U32,
+            2 => DataType::F64,
+            3 => DataType::UTF8 { max_bytes: take_u64(bytes)? as usize },
+            4 => DataType::VARBINARY { max_length: take_u64(bytes)? as usize },
+            5 => DataType::BUFFER { length: take_u64(bytes)? as usize },
+            6 => DataType::UUID,
+            7 => {
+                let count = take_u64(bytes)?;
+                let labels = (0..count)
+                    .map(|_| String::from_utf8(decode_field(bytes)?.to_vec()).ok())
+                    .collect::<Option<Vec<_>>>()?;
+                DataType::ENUM { labels }
+            },
+            _ => return None,
+        };
+        Some(dtype)
+    }
+}
+
+// Length-prefixed byte field, as in `StableHasher::write_field`
+pub fn encode_field(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+pub fn decode_field<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = take_u64(bytes)?;
+    take(bytes, usize::try_from(len).ok()?)
+}
+
+pub fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if bytes.len() < len {
+        return None;
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Some(head)
+}
+
+pub fn take_u64(bytes: &mut &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(take(bytes, size_of::<u64>())?.try_into().ok()?))
+}
+
+// Labels an enum may have, each stored as its index in at most two bytes
+pub const MAX_ENUM_LABELS: usize = u16::MAX as usize + 1;
+
+// Bytes per stored enum index: one for up to 256 labels, two beyond that
+pub fn enum_index_width(labels: &[String]) -> usize {
+    if labels.len() <= u8::MAX as usize + 1 { 1 } else { 2 }
+}
+
+pub fn encode_enum_label(labels: &[String], label: &[u8]) -> Option<Vec<u8>> {
+    let idx = u16::try_from(labels.iter().position(|l| l.as_bytes() == label)?).ok()?;
+    let bytes = idx.to_le_bytes();
+    Some(bytes[..enum_index_width(labels)].to_vec())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+    ConversionError,
+    InvalidArgType(String, DataType, DataType),
+    // Integer result doesn't fit its type, named by the operation
+    Overflow(String),
+    DivisionByZero,
+    UnknownColumn(String),
+}
+
+// 128-bit UUID, stored as its 16 bytes in RFC 9562 (big endian) order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+
+    // Accepts the hyphenated form ("67e55044-10b1-426f-9247-bb680e5fe0c8") or 32 bare hex digits,
+    // in either case
+    pub fn parse(text: &str) -> Result<Uuid, TypeError> {
+        let hex_digits: Vec<u8> = match text.len() {
+            36 => {
+                for pos in [8, 13, 18, 23] {
+                    if text.as_bytes()[pos] != b'-' {
+                        return Err(TypeError::ConversionError);
+                    }
+                }
+                text.bytes().filter(|b| *b != b'-').collect()
+            },
+            32 => text.bytes().collect(),
+            _ => return Err(TypeError::ConversionError),
+        };
+        if hex_digits.len() != 32 || !hex_digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(TypeError::ConversionError);
+        }
+        let mut bytes = [0u8; 16];
+        for (idx, pair) in hex_digits.chunks(2).enumerate() {
+            let pair = str::from_utf8(pair).map_err(|_| TypeError::ConversionError)?;
+            bytes[idx] = u8::from_str_radix(pair, 16).map_err(|_| TypeError::ConversionError)?;
+        }
+        Ok(Uuid(bytes))
+    }
+}
+
+// Literal-style rendering for logs and debugging: strings are quoted SQL-style, bytes are hex and
+// nothing is truncated
+impl std::fmt::Display for ColumnValue<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnValue::U32(val) => write!(f, "{val}"),
+            ColumnValue::F64(val) => write!(f, "{val}"),
+            ColumnValue::UTF8(val) => write!(f, "'{}'", val.replace('\'', "''")),
+            ColumnValue::Bytes(val) => {
+                write!(f, "0x")?;
+                val.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            },
+            ColumnValue::Uuid(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+impl std::fmt::Display for Uuid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, byte) in self.0.iter().enumerate() {
+            if matches!(idx, 4 | 6 | 8 | 10) {
+                write!(f, "-")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for Uuid {
+    type Err = TypeError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> { Uuid::parse(text) }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ColumnValue<'a> {
+    U32(u32),
+    F64(f64),
+    UTF8(&'a str),
+    Bytes(&'a [u8]),
+    Uuid(Uuid),
+}
+
+impl<'a> From<&ColumnValue<'a>> for DataType {
+    fn from(value: &ColumnValue<'a>) -> DataType {
+        match value {
+            ColumnValue::U32(_) => DataType::U32,
+            ColumnValue::F64(_) => DataType::F64,
+            ColumnValue::UTF8(val) => DataType::UTF8 { max_bytes: val.len() },
+            ColumnValue::Bytes(val) => DataType::BUFFER { length: val.len() },
+            ColumnValue::Uuid(_) => DataType::UUID,
+        }
+    }
+}
+
+// Conversions to and from native types, so callers don't have to match on variants.
+// Only the exact variant converts, e.g. a U32 is not silently widened into an f64.
+macro_rules! native_conversions {
+    ($($native:ty => $variant:ident, $into:expr, $from:expr);* $(;)?) => {$(
+        impl<'a> TryFrom<ColumnValue<'a>> for $native {
+            type Error = TypeError;
+            fn try_from(value: ColumnValue<'a>) -> Result<Self, TypeError> {
+                match value {
+                    ColumnValue::$variant(val) => Ok($into(val)),
+                    _ => Err(TypeError::ConversionError),
+                }
+            }
+        }
+
+        impl<'a> From<&'a $native> for ColumnValue<'a> {
+            fn from(value: &'a $native) -> Self {
+                ColumnValue::$variant($from(value))
+            }
+        }
+    )*};
+}
+
+native_conversions! {
+    u32 => U32, |val| val, |val: &u32| *val;
+    f64 => F64, |val| val, |val: &f64| *val;
+    String => UTF8, str::to_string, String::as_str;
+    Vec<u8> => Bytes, <[u8]>::to_vec, Vec::as_slice;
+    Uuid => Uuid, |val| val, |val: &Uuid| *val;
+}
+
+impl<'a> TryFrom<ColumnValue<'a>> for &'a str {
+    type Error = TypeError;
+    fn try_from(value: ColumnValue<'a>) -> Result<Self, TypeError> {
+        match value {
+            ColumnValue::UTF8(val) => Ok(val),
+            _ => Err(TypeError::ConversionError),
+        }
+    }
+}
+
+impl<'a> TryFrom<ColumnValue<'a>> for &'a [u8] {
+    type Error = TypeError;
+    fn try_from(value: ColumnValue<'a>) -> Result<Self, TypeError> {
+        match value {
+            ColumnValue::Bytes(val) => Ok(val),
+            _ => Err(TypeError::ConversionError),
+        }
+    }
+}
+
+impl From<u32> for ColumnValue<'_> {
+    fn from(value: u32) -> Self { ColumnValue::U32(value) }
+}
+
+impl From<f64> for ColumnValue<'_> {
+    fn from(value: f64) -> Self { ColumnValue::F64(value) }
+}
+
+impl From<Uuid> for ColumnValue<'_> {
+    fn from(value: Uuid) -> Self { ColumnValue::Uuid(value) }
+}
+
+impl<'a> From<&'a str> for ColumnValue<'a> {
+    fn from(value: &'a str) -> Self { ColumnValue::UTF8(value) }
+}
+
+impl<'a> From<&'a [u8]> for ColumnValue<'a> {
+    fn from(value: &'a [u8]) -> Self { ColumnValue::Bytes(value) }
+}
+
+// F64 semantics
+// - Comparisons (eq, neq, gt, ...) follow IEEE 754 like SQL engines do: -0.0 equals 0.0 and every
+//   comparison involving NaN is false, except `neq` which is true. So NaN != NaN.
+// - The total order (`total_cmp`) is used for sorting, grouping, indexes and IsDistinctFrom:
+//   -0.0 and 0.0 are the same value, all NaNs are one value that sorts after every number.
+#[inline(always)]
+pub fn f64_total_cmp(left: f64, right: f64) -> Ordering {
+    match (left.is_nan(), right.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        // Neither is NaN, so only the -0.0 == 0.0 case differs from f64::total_cmp
+        (false, false) => left.partial_cmp(&right).unwrap(),
+    }
+}
+
+// Bit pattern whose unsigned order matches `f64_total_cmp`, for keys of ordered indexes and hashes
+pub fn f64_ordered_bits(val: f64) -> u64 {
+    if val.is_nan() {
+        return u64::MAX;
+    }
+    // Folds -0.0 into 0.0
+    let bits = (val + 0.0).to_bits();
+    if bits >> 63 == 1 { !bits } else { bits | (1 << 63) }
+}
+
+impl<'cmp> ColumnValue<'cmp> {
+
+    // Order used by sorts, grouping and indexes. Unlike `gt`/`lt`, defined for every value of a type.
+    pub fn total_cmp(&self, other: &Self) -> Result<Ordering, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => l0.cmp(r0),
+            (Self::F64(l0), Self::F64(r0)) => f64_total_cmp(*l0, *r0),
+            (Self::UTF8(l0), Self::UTF8(r0)) => l0.cmp(r0),
+            (Self::Bytes(l0), Self::Bytes(r0)) => l0.cmp(r0),
+            (Self::Uuid(l0), Self::Uuid(r0)) => l0.cmp(r0),
+            (Self::Uuid(l0), Self::UTF8(r0)) => l0.cmp(&Uuid::parse(r0)?),
+            (Self::UTF8(l0), Self::Uuid(r0)) => Uuid::parse(l0)?.cmp(r0),
+            _ => return Err(TypeError::InvalidArgType("cmp".to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    // Like `neq`, but NaN is not distinct from NaN
+    pub fn is_distinct_from(&self, other: &Self) -> Result<bool, TypeError> {
+        self.total_cmp(other).map(|ord| ord != Ordering::Equal)
+    }
+
+    pub fn is_not_distinct_from(&self, other: &Self) -> Result<bool, TypeError> {
+        self.total_cmp(other).map(|ord| ord == Ordering::Equal)
+    }
+
+    #[inline(always)]
+    pub fn eq(&self, other: &Self) -> Result<bool, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => l0 == r0,
+            (Self::F64(l0), Self::F64(r0)) => l0 == r0,
+            (Self::UTF8(l0), Self::UTF8(r0)) => l0 == r0,
+            (Self::Bytes(r0), Self::Bytes(l0)) => r0 == l0,
+            // String constants are parsed, so filters can be written with the canonical text form
+            (Self::Uuid(l0), Self::Uuid(r0)) => l0 == r0,
+            (Self::Uuid(l0), Self::UTF8(r0)) => *l0 == Uuid::parse(r0)?,
+            (Self::UTF8(l0), Self::Uuid(r0)) => Uuid::parse(l0)? == *r0,
+            _ => return Err(TypeError::InvalidArgType("eq".to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    #[inline(always)]
+    pub fn neq(&self, other: &Self) -> Result<bool, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => l0 != r0,
+            (Self::F64(l0), Self::F64(r0)) => l0 != r0,
+            (Self::UTF8(l0), Self::UTF8(r0)) => l0 != r0,
+            (Self::Bytes(r0), Self::Bytes(l0)) => r0 != l0,
+            (Self::Uuid(l0), Self::Uuid(r0)) => l0 != r0,
+            (Self::Uuid(l0), Self::UTF8(r0)) => *l0 != Uuid::parse(r0)?,
+            (Self::UTF8(l0), Self::Uuid(r0)) => Uuid::parse(l0)? != *r0,
+            _ => return Err(TypeError::InvalidArgType("ne".to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    #[inline(always)]
+    pub fn gt(&self, other: &Self) -> Result<bool, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => l0 > r0,
+            (Self::F64(l0), Self::F64(r0)) => l0 > r0,
+            _ => return Err(TypeError::InvalidArgType("gt".to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    #[inline(always)]
+    pub fn gte(&self, other: &Self) -> Result<bool, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => l0 >= r0,
+            (Self::F64(l0), Self::F64(r0)) => l0 >= r0,
+            _ => return Err(TypeError::InvalidArgType("gte".to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    #[inline(always)]
+    pub fn lt(&self, other: &Self) -> Result<bool, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => l0 < r0,
+            (Self::F64(l0), Self::F64(r0)) => l0 < r0,
+            _ => return Err(TypeError::InvalidArgType("lt".to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    #[inline(always)]
+    pub fn lte(&self, other: &Self) -> Result<bool, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => l0 <= r0,
+            (Self::F64(l0), Self::F64(r0)) => l0 <= r0,
+            _ => return Err(TypeError::InvalidArgType("lte".to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    // Arithmetic on U32 is checked, mixing U32 and F64 promotes to F64, F64 follows IEEE 754
+    #[inline(always)]
+    fn arithmetic(
+        &self,
+        other: &Self,
+        name: &str,
+        int_op: fn(u32, u32) -> Option<u32>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Result<ColumnValue<'cmp>, TypeError> {
+        let res = match (self, other) {
+            (Self::U32(l0), Self::U32(r0)) => Self::U32(int_op(*l0, *r0).ok_or_else(|| TypeError::Overflow(name.to_string()))?),
+            (Self::F64(l0), Self::F64(r0)) => Self::F64(float_op(*l0, *r0)),
+            (Self::U32(l0), Self::F64(r0)) => Self::F64(float_op(*l0 as f64, *r0)),
+            (Self::F64(l0), Self::U32(r0)) => Self::F64(float_op(*l0, *r0 as f64)),
+            _ => return Err(TypeError::InvalidArgType(name.to_string(), self.into(), other.into())),
+        };
+        Ok(res)
+    }
+
+    pub fn checked_add(&self, other: &Self) -> Result<ColumnValue<'cmp>, TypeError> {
+        self.arithmetic(other, "add", u32::checked_add, |l, r| l + r)
+    }
+
+    pub fn checked_sub(&self, other: &Self) -> Result<ColumnValue<'cmp>, TypeError> {
+        self.arithmetic(other, "sub", u32::checked_sub, |l, r| l - r)
+    }
+
+    pub fn checked_mul(&self, other: &Self) -> Result<ColumnValue<'cmp>, TypeError> {
+        self.arithmetic(other, "mul", u32::checked_mul, |l, r| l * r)
+    }
+
+    // Integer division truncates, dividing an integer by zero is an error
+    pub fn checked_div(&self, other: &Self) -> Result<ColumnValue<'cmp>, TypeError> {
+        if let (Self::U32(_), Self::U32(0)) = (self, other) {
+            return Err(TypeError::DivisionByZero);
+        }
+        self.arithmetic(other, "div", u32::checked_div, |l, r| l / r)
+    }
+}
+
+// Panicking implementation of `eq`
+// Itended for use in tests
+// FIXME: Why doesn't #[cfg(test)] work correctly??
+// #[cfg(test)]
+impl<'a> PartialEq for ColumnValue<'a> {
+    fn eq(&self, other: &Self) -> bool { ColumnValue::eq(self, other).unwrap() }
+}
+
+// TODO: These byte conversions should be moved to `serial`
+#[inline(always)]
+pub fn canonical_column<'a>(dtype: &'a DataType, data: &'a [u8]) -> Result<ColumnValue<'a>, TypeError> {
+    match dtype {
+        DataType::U32 => { Ok(ColumnValue::U32(u32::from_le_bytes(data.try_into().map_err(|_| TypeError::ConversionError)?))) }
+        DataType::F64 => { Ok(ColumnValue::F64(f64::from_le_bytes(data.try_into().map_err(|_| TypeError::ConversionError)?))) }
+        DataType::UTF8 { .. } => Ok(ColumnValue::UTF8(str::from_utf8(data).map_err(|_| TypeError::ConversionError)?)),
+        DataType::VARBINARY { .. } => Ok(ColumnValue::Bytes(data)),
+        DataType::BUFFER { length } => {
+            if data.len() != *length {
+                return Err(TypeError::ConversionError);
+            }
+            Ok(ColumnValue::Bytes(data))
+        }
+        DataType::UUID => Ok(ColumnValue::Uuid(Uuid(data.try_into().map_err(|_| TypeError::ConversionError)?))),
+        DataType::ENUM { labels } => {
+            let idx = match data {
+                [idx] if enum_index_width(labels) == 1 => *idx as usize,
+                [lo, hi] if enum_index_width(labels) == 2 => u16::from_le_bytes([*lo, *hi]) as usize,
+                _ => return Err(TypeError::ConversionError),
+            };
+            labels.get(idx).map(|label| ColumnValue::UTF8(label)).ok_or(TypeError::ConversionError)
+        },
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::ColumnValue::*;
+    use super::Uuid;
+
+    #[test]
+    fn uuid_text_round_trip() {
+        let uuid = Uuid::parse("67E55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+        assert_eq!(uuid.0[0], 0x67);
+        assert_eq!(uuid.0[15], 0xc8);
+        assert_eq!(uuid.to_string(), "67e55044-10b1-426f-9247-bb680e5fe0c8");
+        assert_eq!(Uuid::parse("67e5504410b1426f9247bb680e5fe0c8"), Ok(uuid));
+    }
+
+    #[test]
+    fn malformed_uuids() {
+        assert_eq!(Uuid::parse(""), Err(TypeError::ConversionError));
+        assert_eq!(Uuid::parse("67e55044-10b1-426f-9247-bb680e5fe0c"), Err(TypeError::ConversionError));
+        assert_eq!(Uuid::parse("67e55044x10b1-426f-9247-bb680e5fe0c8"), Err(TypeError::ConversionError));
+        assert_eq!(Uuid::parse("g7e5504410b1426f9247bb680e5fe0c8"), Err(TypeError::ConversionError));
+        assert_eq!(Uuid::parse("+7e5504410b1426f9247bb680e5fe0c8"), Err(TypeError::ConversionError));
+    }
+
+    #[test]
+    fn nan_comparisons_follow_ieee() {
+        assert_eq!(F64(f64::NAN).eq(&F64(f64::NAN)), Ok(false));
+        assert_eq!(F64(f64::NAN).neq(&F64(f64::NAN)), Ok(true));
+        assert_eq!(F64(f64::NAN).gt(&F64(1.0)), Ok(false));
+        assert_eq!(F64(f64::NAN).lte(&F64(1.0)), Ok(false));
+        assert_eq!(F64(-0.0).eq(&F64(0.0)), Ok(true));
+    }
+
+    #[test]
+    fn native_conversions() {
+        let name = String::from("apple");
+        let bytes = vec![0xAA, 0xBB];
+        assert_eq!(u32::try_from(U32(7)), Ok(7));
+        assert_eq!(f64::try_from(F64(1.5)), Ok(1.5));
+        assert_eq!(String::try_from(ColumnValue::from(&name)), Ok(name.clone()));
+        assert_eq!(Vec::<u8>::try_from(ColumnValue::from(&bytes)), Ok(bytes.clone()));
+        assert_eq!(<&str>::try_from(UTF8("pear")), Ok("pear"));
+        assert_eq!(ColumnValue::from(7u32), U32(7));
+        assert_eq!(ColumnValue::from("pear"), UTF8("pear"));
+        assert_eq!(f64::try_from(U32(7)), Err(TypeError::ConversionError));
+        assert_eq!(String::try_from(Bytes(b"pear")), Err(TypeError::ConversionError));
+    }
+
+    #[test]
+    fn integer_arithmetic_is_checked() {
+        assert_eq!(U32(2).checked_add(&U32(3)), Ok(U32(5)));
+        assert_eq!(U32(7).checked_div(&U32(2)), Ok(U32(3)));
+        assert_eq!(U32(u32::MAX).checked_add(&U32(1)), Err(TypeError::Overflow("add".to_string())));
+        assert_eq!(U32(1).checked_sub(&U32(2)), Err(TypeError::Overflow("sub".to_string())));
+        assert_eq!(U32(1 << 16).checked_mul(&U32(1 << 16)), Err(TypeError::Overflow("mul".to_string())));
+        assert_eq!(U32(1).checked_div(&U32(0)), Err(TypeError::DivisionByZero));
+    }
+
+    #[test]
+    fn mixed_arithmetic_promotes_to_float() {
+        assert_eq!(U32(1).checked_add(&F64(0.5)), Ok(F64(1.5)));
+        assert_eq!(F64(3.0).checked_mul(&U32(2)), Ok(F64(6.0)));
+        assert_eq!(U32(1).checked_sub(&F64(2.0)), Ok(F64(-1.0)));
+        assert_eq!(F64(1.0).checked_div(&U32(0)), Ok(F64(f64::INFINITY)));
+        assert_eq!(UTF8("a").checked_add(&U32(1)), Err(TypeError::InvalidArgType("add".to_string(), DataType::UTF8 { max_bytes: 1 }, DataType::U32)));
+    }
+
+    #[test]
+    fn nan_sorts_last() {
+        let mut values = vec![f64::NAN, 1.0, f64::INFINITY, -0.0, f64::NEG_INFINITY, 0.0, -1.0];
+        values.sort_by(|l, r| f64_total_cmp(*l, *r));
+        assert_eq!(&format!("{values:?}"), "[-inf, -1.0, -0.0, 0.0, 1.0, inf, NaN]");
+        assert_eq!(F64(-0.0).total_cmp(&F64(0.0)), Ok(Ordering::Equal));
+    }
+
+    #[test]
+    fn nan_is_not_distinct_from_nan() {
+        assert_eq!(F64(f64::NAN).is_distinct_from(&F64(f64::NAN)), Ok(false));
+        assert_eq!(F64(-f64::NAN).is_not_distinct_from(&F64(f64::NAN)), Ok(true));
+        assert_eq!(F64(f64::NAN).is_distinct_from(&F64(1.0)), Ok(true));
+        assert_eq!(F64(-0.0).is_distinct_from(&F64(0.0)), Ok(false));
+    }
+
+    #[test]
+    fn ordered_bits_match_total_order() {
+        let values = [f64::NEG_INFINITY, -1.5, -0.0, 0.0, 1e-300, 2.0, f64::INFINITY, f64::NAN, -f64::NAN];
+        for left in values {
+            for right in values {
+                assert_eq!(f64_ordered_bits(left).cmp(&f64_ordered_bits(right)), f64_total_cmp(left, right), "{left} vs {right}");
+            }
+        }
+    }
+}
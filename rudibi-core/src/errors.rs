@@ -0,0 +1,55 @@
+// Errors as replied in ERROR frames, for clients of frames to tell failures apart by a stable
+// numeric code without parsing messages. The codes of the server's errors are given in
+// `rudibi_server::errors`. An ERROR frame holds the code, a message for people, then fields of
+// context as key=value, all separated by NULs:
+//   1001\0TableNotFound("Vegetables")\0table=Vegetables
+
+// The request's payload isn't UTF-8
+pub const BAD_ENCODING: u16 = 6001;
+pub const UNKNOWN_REQUEST: u16 = 6002;
+// None of the codecs of a COMPRESS request is supported
+pub const UNSUPPORTED_CODEC: u16 = 6003;
+// A compressed payload that can't be decompressed, or was sent before a codec was picked
+pub const BAD_COMPRESSION: u16 = 6004;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorReply {
+    pub code: u16,
+    pub message: String,
+    pub context: Vec<(String, String)>,
+}
+
+impl ErrorReply {
+
+    pub fn new(code: u16, message: impl Into<String>) -> ErrorReply {
+        ErrorReply { code, message: message.into(), context: Vec::new() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.context.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut fields = vec![self.code.to_string(), self.message.clone()];
+        fields.extend(self.context.iter().map(|(key, value)| format!("{key}={value}")));
+        fields.join("\0").into_bytes()
+    }
+
+    // None if it isn't the payload of an ERROR frame
+    pub fn parse(payload: &[u8]) -> Option<ErrorReply> {
+        let mut fields = std::str::from_utf8(payload).ok()?.split('\0');
+        let code = fields.next()?.parse().ok()?;
+        let message = fields.next()?.to_string();
+        let context = fields.map(|field| field.split_once('=').map(|(key, value)| (key.to_string(), value.to_string())))
+            .collect::<Option<_>>()?;
+        Some(ErrorReply { code, message, context })
+    }
+}
+
+impl std::fmt::Display for ErrorReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.message)
+    }
+}
+
+impl std::error::Error for ErrorReply {}
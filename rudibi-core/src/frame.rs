@@ -0,0 +1,281 @@
+// Length-prefixed messages of the wire protocol
+// A frame is its length as a little-endian u32, then a tag saying what it carries, the id of the
+// request, and the payload. The length counts the tag, the id and the payload. Replies carry the
+// id of their request, so a client can send several requests before reading the replies. They
+// come in the order of the requests, which run one after another as if each had been waited for.
+// After a COMPRESS request, payloads of `COMPRESS_MIN_BYTES` or more may be sent compressed with
+// the codec the server picked, both ways. Their tag has `COMPRESSED` set, so large result sets
+// and bulk inserts take less bandwidth on slow links for some CPU at both ends.
+// Modules named below that aren't in this crate are the server's, e.g. `rudibi_server::protocol`.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::compress::{compress, decompress};
+use crate::errors::ErrorReply;
+
+// Request: a command, see `protocol`, as UTF-8 text
+pub const EXECUTE: u8 = 1;
+// Reply: what the command gave, as UTF-8 text
+pub const REPLY: u8 = 2;
+// Reply: why the request failed, its code, message and context, see `errors`
+pub const ERROR: u8 = 3;
+// Request: `user\0secret`, or a token alone, see `auth`. Replied to with OK.
+pub const AUTH: u8 = 4;
+// Request: the connection's cancel key, replied to as `id secret`, see `cancel`
+pub const CANCEL_KEY: u8 = 5;
+// Request: a cancel key, cancelling the query of the connection with it. Replied to with OK.
+pub const CANCEL: u8 = 6;
+// Request: a select whose rows are streamed back, see `protocol`
+pub const STREAM: u8 = 7;
+// Reply: some of the streamed rows, as CSV lines
+pub const ROWS: u8 = 8;
+// Reply: the stream is over, with the number of rows sent
+pub const DONE: u8 = 9;
+// Request: anything, replied to with PONG even before authenticating, for health checks
+pub const PING: u8 = 10;
+// Request: the codecs the client takes, comma-separated by preference as `zstd,lz4`. Replied to
+// with the codec picked, the first the server has.
+pub const COMPRESS: u8 = 11;
+// Request: the tables of a primary, replied to with ROWS frames holding them then a DONE frame,
+// see `replication`
+pub const SNAPSHOT: u8 = 12;
+// Request: a sequence of the primary's change feed, replied to with the changes after it, see
+// `replication`
+pub const CHANGES: u8 = 13;
+// Request: a table, and optionally a sequence to resume after, replied to with EVENTS frames of its
+// changes as they come, see `changes`
+pub const SUBSCRIBE: u8 = 14;
+// Reply: some changes of a subscribed table as CSV
+pub const EVENTS: u8 = 15;
+// Request: a select, replied to with NOTIFY frames of the rows inserted later that it selects, see
+// `changes`
+pub const WATCH: u8 = 16;
+// Reply: some rows a watched select selects, as CSV
+pub const NOTIFY: u8 = 17;
+// Request: a select encoded as in `serial`, whose rows are streamed back as for STREAM, see
+// `protocol`
+pub const QUERY: u8 = 18;
+// Request: an insert encoded as in `serial`, replied to with a WINDOW frame. The first opens the
+// connection's stream of inserts into its table and columns, and is sent without rows to get the
+// first window. Each must then hold no more rows than the last window allows, see `ingest`.
+pub const INSERT_ROWS: u8 = 19;
+// Request: ends the connection's stream of inserts, replied to with INSERTED and the rows stored
+pub const INSERT_DONE: u8 = 20;
+// Reply: `accepted window`, the rows of the insert stored and the most the next may hold
+pub const WINDOW: u8 = 21;
+// Request: the next chunk of a blob, of `blob::BLOB_CHUNK_SIZE` bytes at most, replied to with OK.
+// The first opens the connection's blob, see `blob`.
+pub const BLOB_WRITE: u8 = 22;
+// Request: stores the connection's blob, replied to with its id
+pub const BLOB_DONE: u8 = 23;
+// Request: a blob's id, replied to with BLOB_CHUNK frames of its bytes then a DONE frame
+pub const BLOB_READ: u8 = 24;
+// Reply: the next chunk of a blob read
+pub const BLOB_CHUNK: u8 = 25;
+// Set in the tag of a frame whose payload is compressed with the connection's codec
+pub const COMPRESSED: u8 = 0x80;
+// Set in the tag of a request whose payload starts with a W3C traceparent and a NUL, see `telemetry`
+pub const TRACED: u8 = 0x40;
+
+// Shorter payloads are sent as they are, compressing saves too little
+pub const COMPRESS_MIN_BYTES: usize = 1 << 10;
+
+// Longer frames are taken for a broken stream rather than allocated
+pub const MAX_FRAME_BYTES: usize = 64 << 20;
+
+const HEADER_BYTES: usize = 1 + 4;
+
+// How compressed payloads are encoded, see `COMPRESS`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    // The payload's length as a little-endian u32, then an LZ4 block of it, see `compress`
+    Lz4,
+    // A zstd frame, with the zstd feature
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub tag: u8,
+    pub request_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+
+    pub fn new(tag: u8, request_id: u32, payload: impl Into<Vec<u8>>) -> Frame {
+        Frame { tag, request_id, payload: payload.into() }
+    }
+
+    pub fn error(request_id: u32, err: &ErrorReply) -> Frame {
+        Frame::new(ERROR, request_id, err.to_payload())
+    }
+
+    // None if the stream ends before the next frame starts
+    pub fn read_from(input: &mut impl Read) -> io::Result<Option<Frame>> {
+        let mut len = [0u8; 4];
+        loop {
+            match input.read(&mut len[..1]) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        input.read_exact(&mut len[1..])?;
+        let mut body = vec![0u8; body_len(len)?];
+        input.read_exact(&mut body)?;
+        Ok(Some(Frame::from_body(body)))
+    }
+
+    pub fn write_to(&self, output: &mut impl Write) -> io::Result<()> {
+        output.write_all(&self.to_bytes()?)?;
+        output.flush()
+    }
+
+    // As `read_from`, on tokio
+    #[cfg(feature = "async")]
+    pub async fn read_from_async(input: &mut (impl AsyncRead + Unpin)) -> io::Result<Option<Frame>> {
+        let mut len = [0u8; 4];
+        if input.read(&mut len[..1]).await? == 0 {
+            return Ok(None);
+        }
+        input.read_exact(&mut len[1..]).await?;
+        let mut body = vec![0u8; body_len(len)?];
+        input.read_exact(&mut body).await?;
+        Ok(Some(Frame::from_body(body)))
+    }
+
+    // As `write_to`, on tokio
+    #[cfg(feature = "async")]
+    pub async fn write_to_async(&self, output: &mut (impl AsyncWrite + Unpin)) -> io::Result<()> {
+        output.write_all(&self.to_bytes()?).await?;
+        output.flush().await
+    }
+
+    // Whether it's written out as soon as it's sent rather than along with later replies, as
+    // EVENTS and NOTIFY frames are, the next may be long to come
+    pub fn is_urgent(&self) -> bool {
+        matches!(self.tag & !COMPRESSED, EVENTS | NOTIFY)
+    }
+
+    // The frame as it's written, with its length
+    pub fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let len = HEADER_BYTES + self.payload.len();
+        if len > MAX_FRAME_BYTES {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("Frame of {len} bytes")));
+        }
+        let mut framed = Vec::with_capacity(4 + len);
+        framed.extend_from_slice(&(len as u32).to_le_bytes());
+        framed.push(self.tag);
+        framed.extend_from_slice(&self.request_id.to_le_bytes());
+        framed.extend_from_slice(&self.payload);
+        Ok(framed)
+    }
+
+    // The frame with its payload compressed, unless it's short or doesn't shrink
+    pub fn compressed(self, codec: Codec) -> io::Result<Frame> {
+        if self.tag & COMPRESSED != 0 || self.payload.len() < COMPRESS_MIN_BYTES {
+            return Ok(self);
+        }
+        let payload = codec.compress(&self.payload)?;
+        Ok(match payload.len() < self.payload.len() {
+            true => Frame { tag: self.tag | COMPRESSED, payload, ..self },
+            false => self,
+        })
+    }
+
+    // None if the payload can't be decompressed or would be longer than a frame
+    pub fn decompressed(&self, codec: Codec) -> Option<Frame> {
+        if self.tag & COMPRESSED == 0 {
+            return Some(self.clone());
+        }
+        let payload = codec.decompress(&self.payload)?;
+        Some(Frame { tag: self.tag & !COMPRESSED, request_id: self.request_id, payload })
+    }
+
+    // The traceparent of a TRACED request and the request without it, None if it has none
+    pub fn untraced(&self) -> Option<(String, Frame)> {
+        let at = self.payload.iter().position(|&byte| byte == 0)?;
+        let traceparent = String::from_utf8(self.payload[..at].to_vec()).ok()?;
+        Some((traceparent, Frame::new(self.tag & !TRACED, self.request_id, &self.payload[at + 1..])))
+    }
+
+    // The frame whose `body_len` bytes after the length are `body`
+    fn from_body(mut body: Vec<u8>) -> Frame {
+        let payload = body.split_off(HEADER_BYTES);
+        Frame { tag: body[0], request_id: u32::from_le_bytes(body[1..].try_into().unwrap()), payload }
+    }
+}
+
+// Bytes that follow a frame's length, checked to hold the header and fit the maximum
+fn body_len(len: [u8; 4]) -> io::Result<usize> {
+    let len = u32::from_le_bytes(len) as usize;
+    if !(HEADER_BYTES..=MAX_FRAME_BYTES).contains(&len) {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Frame of {len} bytes")));
+    }
+    Ok(len)
+}
+
+impl Codec {
+
+    pub fn parse(name: &str) -> Option<Codec> {
+        match name.to_ascii_lowercase().as_str() {
+            "lz4" => Some(Codec::Lz4),
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    // The first codec of a COMPRESS request that the server has
+    pub fn negotiate(offer: &str) -> Option<Codec> {
+        offer.split(',').find_map(|name| Codec::parse(name.trim()))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Lz4 => "lz4",
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Lz4 => {
+                let len = u32::try_from(data.len()).map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Payload too long to compress"))?;
+                let mut out = len.to_le_bytes().to_vec();
+                out.extend_from_slice(&compress(data));
+                Ok(out)
+            },
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => zstd::bulk::compress(data, 0),
+        }
+    }
+
+    // None if `data` is malformed or decompresses to more than `MAX_FRAME_BYTES`
+    pub fn decompress(&self, data: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Codec::Lz4 => {
+                let (len, block) = data.split_first_chunk::<4>()?;
+                let len = u32::from_le_bytes(*len) as usize;
+                if len > MAX_FRAME_BYTES {
+                    return None;
+                }
+                decompress(block, len)
+            },
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                let mut out = Vec::new();
+                let decoder = zstd::stream::read::Decoder::with_buffer(data).ok()?;
+                decoder.take(MAX_FRAME_BYTES as u64 + 1).read_to_end(&mut out).ok()?;
+                (out.len() <= MAX_FRAME_BYTES).then_some(out)
+            },
+        }
+    }
+}
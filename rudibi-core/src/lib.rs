@@ -0,0 +1,16 @@
+// What the server and its clients share: column types and values, filters, commands, frames and
+// their encodings sent between them. Re-exported by `rudibi_server` under the same module names.
+
+pub mod hash;
+pub mod dtype;
+pub mod query;
+pub mod collation;
+pub mod schema;
+pub mod display;
+pub mod mapping;
+pub mod command;
+pub mod serial;
+pub mod compress;
+pub mod errors;
+pub mod frame;
+pub mod blob;
@@ -0,0 +1,23 @@
+// Rows of results and inserts mapped to an application's structs, see `rudibi_server::mapping`
+// for deriving them
+
+use crate::dtype::TypeError;
+use crate::schema::{ResultSet, Row};
+
+pub trait FromRow: Sized {
+    // The row at index `row` of the results, failing if a column is missing or of another type
+    fn from_row(results: &ResultSet, row: usize) -> Result<Self, TypeError>;
+}
+
+pub trait ToRow {
+    // Names of the columns of the row, in order
+    const COLUMNS: &'static [&'static str];
+
+    fn to_row(&self) -> Row;
+}
+
+impl ResultSet {
+    pub fn rows_as<T: FromRow>(&self) -> Result<Vec<T>, TypeError> {
+        (0..self.len()).map(|row| T::from_row(self, row)).collect()
+    }
+}
@@ -44,6 +44,9 @@ pub enum Bool<'a> {
     Gte(Value<'a>, Value<'a>),
     Lt(Value<'a>, Value<'a>),
     Lte(Value<'a>, Value<'a>),
+    // Equality where NaN matches NaN, see `dtype::f64_total_cmp`
+    IsDistinctFrom(Value<'a>, Value<'a>),
+    IsNotDistinctFrom(Value<'a>, Value<'a>),
 
     And(Box<Bool<'a>>, Box<Bool<'a>>),
     Or(Box<Bool<'a>>, Box<Bool<'a>>),
@@ -59,6 +62,41 @@ impl<'a> Bool<'a> {
     pub fn and(self, other: Bool<'a>) -> Bool<'a> {
         Bool::And(Box::new(self), Box::new(other))
     }
+
+    pub fn xor(self, other: Bool<'a>) -> Bool<'a> {
+        Bool::Xor(Box::new(self), Box::new(other))
+    }
+}
+
+// SQL-style, for logs and the queries a server is running. Combined filters are in parentheses.
+impl std::fmt::Display for Value<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::ColumnRef(name) => f.write_str(name),
+            Value::Const(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+impl std::fmt::Display for Bool<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Bool::True => f.write_str("TRUE"),
+            Bool::False => f.write_str("FALSE"),
+            Bool::Eq(left, right) => write!(f, "{left} = {right}"),
+            Bool::Neq(left, right) => write!(f, "{left} != {right}"),
+            Bool::Gt(left, right) => write!(f, "{left} > {right}"),
+            Bool::Gte(left, right) => write!(f, "{left} >= {right}"),
+            Bool::Lt(left, right) => write!(f, "{left} < {right}"),
+            Bool::Lte(left, right) => write!(f, "{left} <= {right}"),
+            Bool::IsDistinctFrom(left, right) => write!(f, "{left} IS DISTINCT FROM {right}"),
+            Bool::IsNotDistinctFrom(left, right) => write!(f, "{left} IS NOT DISTINCT FROM {right}"),
+            Bool::And(left, right) => write!(f, "({left} AND {right})"),
+            Bool::Or(left, right) => write!(f, "({left} OR {right})"),
+            Bool::Xor(left, right) => write!(f, "({left} XOR {right})"),
+            Bool::Not(inner) => write!(f, "NOT ({inner})"),
+        }
+    }
 }
 
 fn collect_value_columns<'a>(value: &'a Value) -> Vec<&'a str> {
@@ -84,7 +122,9 @@ pub fn collect_filter_columns<'a>(bool_expr: &'a Bool) -> Vec<&'a str> {
         Bool::Gt(left, right) |
         Bool::Gte(left, right) |
         Bool::Lt(left, right) |
-        Bool::Lte(left, right) => {
+        Bool::Lte(left, right) |
+        Bool::IsDistinctFrom(left, right) |
+        Bool::IsNotDistinctFrom(left, right) => {
             let mut cols = collect_value_columns(left);
             cols.extend(collect_value_columns(right));
             cols
@@ -122,4 +162,13 @@ mod tests {
         assert_eq!(columns, vec!["age", "salary"]);
     }
 
+    #[test]
+    fn test_display() {
+        let query = Bool::Eq(Value::ColumnRef("name"), Value::Const(ColumnValue::UTF8("O'Neil")))
+            .or(Bool::Gte(Value::ColumnRef("id"), Value::Const(ColumnValue::U32(200))))
+            .xor(Bool::Not(Box::new(Bool::True)));
+
+        assert_eq!(query.to_string(), "((name = 'O''Neil' OR id >= 200) XOR NOT (TRUE))");
+    }
+
 }
\ No newline at end of file
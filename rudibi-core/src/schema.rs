@@ -0,0 +1,102 @@
+// What tables are made of and selects give back: columns, rows as their bytes, and result sets.
+// Kept here for clients to decode result sets as the server encodes them, see `serial`.
+
+use crate::collation::Collation;
+use crate::dtype::{canonical_column, ColumnValue, DataType, TypeError};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub dtype: DataType,
+    // Only meaningful for UTF8 columns
+    pub collation: Collation,
+}
+
+impl Column {
+    pub fn new(name: &str, dtype: DataType) -> Column {
+        Column { name: name.to_string(), dtype, collation: Collation::Binary }
+    }
+
+    pub fn with_collation(mut self, collation: Collation) -> Column {
+        self.collation = collation;
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub data: Vec<u8>,        // Contiguous buffer holding all column data
+    pub offsets: Vec<usize>,  // Start offsets for each column, plus end of last column
+}
+
+impl Row {
+    
+    pub fn of_columns(columns: &[&[u8]]) -> Row {
+        let mut data = Vec::new();
+        let mut offsets = Vec::new();
+        // Preallocating is slower??
+        // let mut data = Vec::with_capacity(columns.iter().map(|col| col.len()).sum());
+        // let mut offsets = Vec::with_capacity(columns.len() + 1);
+        offsets.push(0);
+        for col in columns {
+            data.extend_from_slice(col);
+            offsets.push(data.len());
+        }
+        Row { data, offsets }
+    }
+
+    pub fn get_column(&self, col_idx: usize) -> &[u8] {
+        let start = self.offsets[col_idx];
+        let end = self.offsets[col_idx + 1];
+        &self.data[start..end]
+    }
+}
+
+pub struct ResultSet {
+    pub schema: Vec<Column>,
+    pub data: Vec<Row>,
+    pub scan_stats: ScanStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ScanStats {
+    pub rows_scanned: usize,
+    pub rows_skipped: usize,
+    pub rows_raw: usize,
+}
+
+impl ScanStats {
+    pub fn add(&mut self, other: ScanStats) {
+        self.rows_scanned += other.rows_scanned;
+        self.rows_skipped += other.rows_skipped;
+        self.rows_raw += other.rows_raw;
+    }
+}
+
+impl ResultSet {
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Typed value of a column in the given row, e.g. `results.get::<u32>(0, "id")`
+    pub fn get<'a, T>(&'a self, row: usize, column: &str) -> Result<T, TypeError>
+    where T: TryFrom<ColumnValue<'a>, Error = TypeError> {
+        let idx = self.schema.iter().position(|col| col.name == column)
+            .ok_or_else(|| TypeError::UnknownColumn(column.to_string()))?;
+        T::try_from(canonical_column(&self.schema[idx].dtype, self.data[row].get_column(idx))?)
+    }
+}
+
+impl std::fmt::Debug for ResultSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResultSet")
+            .field("schema", &self.schema)
+            .field("data", &format!("{} rows", self.data.len()))
+            .field("scan_stats", &self.scan_stats)
+            .finish()
+    }
+}
@@ -0,0 +1,455 @@
+
+// Serialization impl for Client<->Server communication
+// The encodings of the server's own types, tables, WAL records and so on, are in
+// `rudibi_server::serial`, along with these.
+
+use crate::collation::Collation;
+use crate::command::{Command, Literal};
+use crate::dtype::{decode_field, encode_field, take, take_u64, ColumnValue, DataType, Uuid};
+use crate::query::{Bool, Value};
+use crate::schema::{Column, ResultSet, Row, ScanStats};
+
+pub trait Serializable<'a> : Sized {
+    fn serialized(&'a self) -> &'a [u8];
+}
+
+impl<'a> Serializable<'a> for u32 {
+    fn serialized(&'a self) -> &'a [u8] {
+        unsafe {
+            // Rust dark "unsafe" magic just to be able to view u32 as a byte ptr 
+            // (u32::to_le_bytes makes a copy)
+            // FIXME: Will this fail on big endian systems?
+            std::slice::from_raw_parts(self as *const u32 as *const u8, std::mem::size_of::<u32>())
+        }
+    }
+}
+
+impl<'a> Serializable<'a> for &'a str {
+    fn serialized(&'a self) -> &'a [u8] {
+        str::as_bytes(self)
+    }
+}
+
+impl<'a> Serializable<'a> for f64 {
+    fn serialized(&'a self) -> &'a [u8] {
+        unsafe {
+            // Rust dark "unsafe" magic just to be able to view u32 as a byte ptr 
+            // (f64::to_le_bytes makes a copy)
+            // FIXME: Will this fail on big endian systems?
+            std::slice::from_raw_parts(self as *const f64 as *const u8, std::mem::size_of::<f64>())
+        }
+    }
+}
+
+impl<'a> Serializable<'a> for String {
+    fn serialized(&'a self) -> &'a [u8] {
+        self.as_bytes()
+    }
+}
+
+impl<'a> Serializable<'a> for Vec<u8> {
+    fn serialized(&'a self) -> &'a [u8] {
+        self.as_slice()
+    }
+}
+
+impl<'a, const N: usize> Serializable<'a> for [u8; N] {
+    fn serialized(&'a self) -> &'a [u8] {
+        self.as_ref()
+    }
+}
+
+impl<'a> Serializable<'a> for Uuid {
+    fn serialized(&'a self) -> &'a [u8] {
+        &self.0
+    }
+}
+
+// Binary form of what client and server send each other. Strings and byte strings are
+// length-prefixed as in table files, counts are little-endian u64s, and each variant of an enum
+// starts with a tag byte. Decoded strings and bytes borrow from the buffer.
+pub trait Wire<'a> : Sized {
+    fn encode_into(&self, out: &mut Vec<u8>);
+    // Consumes the value from the front of `bytes`, None if it's malformed
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self>;
+}
+
+pub fn encode<'a, T: Wire<'a>>(value: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    value.encode_into(&mut out);
+    out
+}
+
+// The whole of `bytes` must be the value
+pub fn decode<'a, T: Wire<'a>>(mut bytes: &'a [u8]) -> Result<T, Malformed> {
+    match T::decode_from(&mut bytes) {
+        Some(value) if bytes.is_empty() => Ok(value),
+        _ => Err(Malformed(std::any::type_name::<T>())),
+    }
+}
+
+// Bytes that aren't an encoded value of the type named
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Malformed(pub &'static str);
+
+impl std::fmt::Display for Malformed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Malformed {}", self.0)
+    }
+}
+
+// Deeper filters are rejected rather than decoded on a stack they could overflow
+pub const MAX_FILTER_DEPTH: usize = 256;
+
+pub fn put_u64(out: &mut Vec<u8>, val: usize) {
+    out.extend_from_slice(&(val as u64).to_le_bytes());
+}
+
+pub fn take_tag(bytes: &mut &[u8]) -> Option<u8> {
+    Some(take(bytes, 1)?[0])
+}
+
+pub fn take_usize(bytes: &mut &[u8]) -> Option<usize> {
+    usize::try_from(take_u64(bytes)?).ok()
+}
+
+impl<'a> Wire<'a> for &'a str {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_field(out, self.as_bytes());
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        std::str::from_utf8(decode_field(bytes)?).ok()
+    }
+}
+
+impl<'a, T: Wire<'a>> Wire<'a> for Vec<T> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        put_u64(out, self.len());
+        for item in self {
+            item.encode_into(out);
+        }
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let count = take_u64(bytes)?;
+        (0..count).map(|_| T::decode_from(bytes)).collect()
+    }
+}
+
+impl<'a> Wire<'a> for ColumnValue<'a> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            ColumnValue::U32(val) => { out.push(1); out.extend_from_slice(&val.to_le_bytes()) },
+            ColumnValue::F64(val) => { out.push(2); out.extend_from_slice(&val.to_le_bytes()) },
+            ColumnValue::UTF8(val) => { out.push(3); val.encode_into(out) },
+            ColumnValue::Bytes(val) => { out.push(4); encode_field(out, val) },
+            ColumnValue::Uuid(val) => { out.push(5); out.extend_from_slice(&val.0) },
+        }
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let value = match take_tag(bytes)? {
+            1 => ColumnValue::U32(u32::from_le_bytes(take(bytes, 4)?.try_into().ok()?)),
+            2 => ColumnValue::F64(f64::from_le_bytes(take(bytes, 8)?.try_into().ok()?)),
+            3 => ColumnValue::UTF8(<&str>::decode_from(bytes)?),
+            4 => ColumnValue::Bytes(decode_field(bytes)?),
+            5 => ColumnValue::Uuid(Uuid(take(bytes, 16)?.try_into().ok()?)),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+impl<'a> Wire<'a> for Value<'a> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::ColumnRef(name) => { out.push(1); name.encode_into(out) },
+            Value::Const(value) => { out.push(2); value.encode_into(out) },
+        }
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        match take_tag(bytes)? {
+            1 => Some(Value::ColumnRef(<&str>::decode_from(bytes)?)),
+            2 => Some(Value::Const(ColumnValue::decode_from(bytes)?)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Wire<'a> for Bool<'a> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        let compare = |out: &mut Vec<u8>, tag: u8, left: &Value<'a>, right: &Value<'a>| {
+            out.push(tag);
+            left.encode_into(out);
+            right.encode_into(out);
+        };
+        let combine = |out: &mut Vec<u8>, tag: u8, left: &Bool<'a>, right: &Bool<'a>| {
+            out.push(tag);
+            left.encode_into(out);
+            right.encode_into(out);
+        };
+        match self {
+            Bool::True => out.push(1),
+            Bool::False => out.push(2),
+            Bool::Eq(left, right) => compare(out, 3, left, right),
+            Bool::Neq(left, right) => compare(out, 4, left, right),
+            Bool::Gt(left, right) => compare(out, 5, left, right),
+            Bool::Gte(left, right) => compare(out, 6, left, right),
+            Bool::Lt(left, right) => compare(out, 7, left, right),
+            Bool::Lte(left, right) => compare(out, 8, left, right),
+            Bool::IsDistinctFrom(left, right) => compare(out, 9, left, right),
+            Bool::IsNotDistinctFrom(left, right) => compare(out, 10, left, right),
+            Bool::And(left, right) => combine(out, 11, left, right),
+            Bool::Or(left, right) => combine(out, 12, left, right),
+            Bool::Xor(left, right) => combine(out, 13, left, right),
+            Bool::Not(inner) => { out.push(14); inner.encode_into(out) },
+        }
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        decode_filter(bytes, 0)
+    }
+}
+
+fn decode_filter<'a>(bytes: &mut &'a [u8], depth: usize) -> Option<Bool<'a>> {
+    if depth > MAX_FILTER_DEPTH {
+        return None;
+    }
+    let compare = |bytes: &mut &'a [u8], op: fn(Value<'a>, Value<'a>) -> Bool<'a>| {
+        Some(op(Value::decode_from(bytes)?, Value::decode_from(bytes)?))
+    };
+    let combine = |bytes: &mut &'a [u8], op: fn(Box<Bool<'a>>, Box<Bool<'a>>) -> Bool<'a>| {
+        Some(op(Box::new(decode_filter(bytes, depth + 1)?), Box::new(decode_filter(bytes, depth + 1)?)))
+    };
+    match take_tag(bytes)? {
+        1 => Some(Bool::True),
+        2 => Some(Bool::False),
+        3 => compare(bytes, Bool::Eq),
+        4 => compare(bytes, Bool::Neq),
+        5 => compare(bytes, Bool::Gt),
+        6 => compare(bytes, Bool::Gte),
+        7 => compare(bytes, Bool::Lt),
+        8 => compare(bytes, Bool::Lte),
+        9 => compare(bytes, Bool::IsDistinctFrom),
+        10 => compare(bytes, Bool::IsNotDistinctFrom),
+        11 => combine(bytes, Bool::And),
+        12 => combine(bytes, Bool::Or),
+        13 => combine(bytes, Bool::Xor),
+        14 => Some(Bool::Not(Box::new(decode_filter(bytes, depth + 1)?))),
+        _ => None,
+    }
+}
+
+impl<'a> Wire<'a> for Row {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_field(out, &self.data);
+        put_u64(out, self.offsets.len());
+        for offset in &self.offsets {
+            put_u64(out, *offset);
+        }
+    }
+
+    // Offsets must go from the start of the data to its end, never backwards
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let data = decode_field(bytes)?.to_vec();
+        let count = take_u64(bytes)?;
+        let offsets: Vec<usize> = (0..count).map(|_| take_usize(bytes)).collect::<Option<_>>()?;
+        let well_formed = offsets.first() == Some(&0)
+            && offsets.last() == Some(&data.len())
+            && offsets.windows(2).all(|pair| pair[0] <= pair[1]);
+        well_formed.then_some(Row { data, offsets })
+    }
+}
+
+impl<'a> Wire<'a> for Column {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.name.as_str().encode_into(out);
+        self.dtype.encode_into(out);
+        self.collation.name().encode_into(out);
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let name = <&str>::decode_from(bytes)?;
+        let dtype = DataType::decode(bytes)?;
+        let collation = Collation::from_name(<&str>::decode_from(bytes)?)?;
+        Some(Column::new(name, dtype).with_collation(collation))
+    }
+}
+
+impl<'a> Wire<'a> for ResultSet {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.schema.encode_into(out);
+        self.data.encode_into(out);
+        for count in [self.scan_stats.rows_scanned, self.scan_stats.rows_skipped, self.scan_stats.rows_raw] {
+            put_u64(out, count);
+        }
+    }
+
+    // Every row must have a value for each column
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let schema: Vec<Column> = Vec::decode_from(bytes)?;
+        let data: Vec<Row> = Vec::decode_from(bytes)?;
+        if data.iter().any(|row| row.offsets.len() != schema.len() + 1) {
+            return None;
+        }
+        let scan_stats = ScanStats { rows_scanned: take_usize(bytes)?, rows_skipped: take_usize(bytes)?, rows_raw: take_usize(bytes)? };
+        Some(ResultSet { schema, data, scan_stats })
+    }
+}
+
+impl<'a> Wire<'a> for Literal<'a> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        let (tag, text) = match self {
+            Literal::Number(text) => (1, text),
+            Literal::Text(text) => (2, text),
+            Literal::Hex(text) => (3, text),
+        };
+        out.push(tag);
+        text.encode_into(out);
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let literal: fn(&'a str) -> Literal<'a> = match take_tag(bytes)? {
+            1 => Literal::Number,
+            2 => Literal::Text,
+            3 => Literal::Hex,
+            _ => return None,
+        };
+        Some(literal(<&str>::decode_from(bytes)?))
+    }
+}
+
+impl<'a> Wire<'a> for Command<'a> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Command::CreateTable { table, columns } => {
+                out.push(1);
+                table.encode_into(out);
+                columns.encode_into(out);
+            },
+            Command::Insert { table, columns, rows } => {
+                out.push(2);
+                table.encode_into(out);
+                columns.encode_into(out);
+                rows.encode_into(out);
+            },
+            Command::Select { table, columns, filter } => {
+                out.push(3);
+                table.encode_into(out);
+                columns.encode_into(out);
+                filter.encode_into(out);
+            },
+            Command::Delete { table, filter } => {
+                out.push(4);
+                table.encode_into(out);
+                filter.encode_into(out);
+            },
+            Command::Status => out.push(5),
+            Command::Stats => out.push(6),
+            Command::ListTables => out.push(7),
+            Command::ShowTables => out.push(8),
+            Command::Describe { table } => {
+                out.push(9);
+                table.encode_into(out);
+            },
+            Command::Declare { cursor, table, columns, filter } => {
+                out.push(10);
+                cursor.encode_into(out);
+                table.encode_into(out);
+                columns.encode_into(out);
+                filter.encode_into(out);
+            },
+            Command::Fetch { cursor, count } => {
+                out.push(11);
+                cursor.encode_into(out);
+                put_u64(out, *count);
+            },
+            Command::Close { cursor } => {
+                out.push(12);
+                cursor.encode_into(out);
+            },
+        }
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let command = match take_tag(bytes)? {
+            1 => Command::CreateTable { table: Wire::decode_from(bytes)?, columns: Vec::decode_from(bytes)? },
+            2 => Command::Insert { table: Wire::decode_from(bytes)?, columns: Vec::decode_from(bytes)?, rows: Vec::decode_from(bytes)? },
+            3 => Command::Select { table: Wire::decode_from(bytes)?, columns: Vec::decode_from(bytes)?, filter: Bool::decode_from(bytes)? },
+            4 => Command::Delete { table: Wire::decode_from(bytes)?, filter: Bool::decode_from(bytes)? },
+            5 => Command::Status,
+            6 => Command::Stats,
+            7 => Command::ListTables,
+            8 => Command::ShowTables,
+            9 => Command::Describe { table: Wire::decode_from(bytes)? },
+            10 => Command::Declare { cursor: Wire::decode_from(bytes)?, table: Wire::decode_from(bytes)?, columns: Vec::decode_from(bytes)?, filter: Bool::decode_from(bytes)? },
+            11 => Command::Fetch { cursor: Wire::decode_from(bytes)?, count: take_usize(bytes)? },
+            12 => Command::Close { cursor: Wire::decode_from(bytes)? },
+            _ => return None,
+        };
+        Some(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn storable_f64_is_le_bytes() {
+        let val: f64 = 123.456;
+        assert_eq!(&val.to_le_bytes(), val.serialized());
+    }
+
+    #[test]
+    fn storable_u32_is_le_bytes() {
+        let val: u32 = 100;
+        assert_eq!(&val.to_le_bytes(), val.serialized());
+    }
+
+    #[test]
+    fn filters_round_trip() {
+        let uuid = Uuid([7; 16]);
+        let filter = Bool::Xor(
+            Box::new(Bool::IsDistinctFrom(Value::ColumnRef("price"), Value::Const(ColumnValue::F64(f64::NAN)))),
+            Box::new(Bool::IsNotDistinctFrom(Value::Const(ColumnValue::Uuid(uuid)), Value::Const(ColumnValue::Bytes(&[1, 2])))),
+        ).or(Bool::False).and(Bool::Gte(Value::ColumnRef("id"), Value::Const(ColumnValue::U32(9))));
+        let encoded = encode(&filter);
+        let decoded: Bool = decode(&encoded).unwrap();
+        assert_eq!(encode(&decoded), encoded);
+
+        let Ok(Bool::And(left, _)) = decode::<Bool>(&encoded) else { panic!("Not an AND") };
+        let Bool::Or(xor, _) = *left else { panic!("Not an OR") };
+        let Bool::Xor(_, right) = *xor else { panic!("Not an XOR") };
+        assert!(matches!(*right, Bool::IsNotDistinctFrom(Value::Const(ColumnValue::Uuid(got)), Value::Const(ColumnValue::Bytes(&[1, 2]))) if got == uuid));
+    }
+
+    #[test]
+    fn result_sets_round_trip() {
+        let results = ResultSet {
+            schema: vec![Column::new("id", DataType::U32), Column::new("name", DataType::UTF8 { max_bytes: 8 }).with_collation(Collation::CaseInsensitive)],
+            data: vec![Row::of_columns(&[&1u32.to_le_bytes(), b"apple"]), Row::of_columns(&[&2u32.to_le_bytes(), b""])],
+            scan_stats: ScanStats { rows_scanned: 5, rows_skipped: 1, rows_raw: 0 },
+        };
+
+        let decoded: ResultSet = decode(&encode(&results)).unwrap();
+
+        assert_eq!(decoded.schema, results.schema);
+        assert_eq!(decoded.data, results.data);
+        assert_eq!(decoded.scan_stats, results.scan_stats);
+    }
+
+    #[test]
+    fn malformed_filters_are_rejected() {
+        let mut deep = Bool::True;
+        for _ in 0..=MAX_FILTER_DEPTH {
+            deep = Bool::Not(Box::new(deep));
+        }
+
+        assert_eq!(decode::<Bool>(&encode(&deep)).err(), Some(Malformed(std::any::type_name::<Bool>())));
+        assert!(decode::<Bool>(&encode(&Bool::Not(Box::new(Bool::True)))).is_ok());
+        assert!(decode::<Bool>(&[1, 0]).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+// Derives of `FromRow` and `ToRow` for structs with named fields, re-exported by
+// `rudibi_server::mapping` with its derive feature, see there
+
+use proc_macro::TokenStream;
+use proc_macro2::Ident;
+use quote::quote;
+use syn::ext::IdentExt;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, LitStr};
+
+#[proc_macro_derive(FromRow, attributes(rudibi))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let columns = match columns(&input) {
+        Ok(columns) => columns,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let (fields, names): (Vec<Ident>, Vec<String>) = columns.into_iter().unzip();
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rudibi_server::mapping::FromRow for #name #type_generics #where_clause {
+            fn from_row(results: &::rudibi_server::engine::ResultSet, row: usize) -> ::std::result::Result<Self, ::rudibi_server::dtype::TypeError> {
+                ::std::result::Result::Ok(Self { #( #fields: results.get(row, #names)?, )* })
+            }
+        }
+    }.into()
+}
+
+#[proc_macro_derive(ToRow, attributes(rudibi))]
+pub fn derive_to_row(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let columns = match columns(&input) {
+        Ok(columns) => columns,
+        Err(err) => return err.to_compile_error().into(),
+    };
+    let (fields, names): (Vec<Ident>, Vec<String>) = columns.into_iter().unzip();
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    quote! {
+        impl #impl_generics ::rudibi_server::mapping::ToRow for #name #type_generics #where_clause {
+            const COLUMNS: &'static [&'static str] = &[#( #names ),*];
+
+            fn to_row(&self) -> ::rudibi_server::engine::Row {
+                ::rudibi_server::engine::Row::of_columns(&[#( ::rudibi_server::serial::Serializable::serialized(&self.#fields) ),*])
+            }
+        }
+    }.into()
+}
+
+// Each field with the name of its column, the field's own unless it's renamed by
+// `#[rudibi(rename = "...")]`
+fn columns(input: &DeriveInput) -> syn::Result<Vec<(Ident, String)>> {
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new_spanned(&input.ident, "Rows map only to structs"));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(syn::Error::new_spanned(&input.ident, "Rows map only to structs with named fields"));
+    };
+    fields.named.iter().map(|field| {
+        let ident = field.ident.clone().expect("Named fields have a name");
+        let mut column = ident.unraw().to_string();
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("rudibi")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename") {
+                    column = meta.value()?.parse::<LitStr>()?.value();
+                    Ok(())
+                } else {
+                    Err(meta.error("Expected rename = \"column\""))
+                }
+            })?;
+        }
+        Ok((ident, column))
+    }).collect()
+}
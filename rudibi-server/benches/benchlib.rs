@@ -108,14 +108,14 @@ pub fn run_bench<T: Copy + Debug, U, R> (
             let mut db = Database::new();
             let storage = match backend {
                 Backend::Memory => StorageCfg::InMemory,
-                Backend::Disk => StorageCfg::Disk { path: testlib::random_temp_file() },
+                Backend::Disk => StorageCfg::disk(&testlib::random_temp_file()),
             };
             db.new_table(&schema, storage.clone()).unwrap();
             let test_arg = setup(&mut db, arg);
             let start = std::time::Instant::now();
             black_box(test(black_box(&mut db), black_box(test_arg)));
             let time = start.elapsed();
-            if let StorageCfg::Disk { path } = storage { std::fs::remove_file(path).unwrap() }
+            if let StorageCfg::Disk { path, .. } = storage { std::fs::remove_file(path).unwrap() }
             measurements.push(time);
         }
         measurements.sort();
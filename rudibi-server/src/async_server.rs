@@ -0,0 +1,162 @@
+// TCP front end on tokio, so connections waiting for their next command don't each hold a
+// thread. Connections speak frames, or text mode, see `protocol`.
+// Commands still block, they run on tokio's blocking pool, at most `max_commands` at once, a
+// SUBSCRIBE for as long as it lasts. A connection reads its next request only once the last is
+// replied to, so a client sending faster than its commands run is slowed down rather than
+// buffered for. Connections beyond
+// `max_connections` wait to be accepted. Connections are closed once idle and probed by TCP
+// keepalive as `tcp` says. Frames of a streamed select are written as they come,
+// at most `STREAM_FRAMES` wait to be. As with `Server::serve`, replies to pipelined requests
+// are flushed together.
+
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Semaphore};
+
+use crate::config::LogLevel;
+use crate::frame::Frame;
+use crate::protocol::{statements, Server, TextFormat};
+use crate::tcp::{self, TcpSettings};
+
+const STREAM_FRAMES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AsyncServerConfig {
+    pub max_connections: usize,
+    pub max_commands: usize,
+    // Connections speak text mode rather than frames
+    pub text: bool,
+    pub log: LogLevel,
+    pub tcp: TcpSettings,
+}
+
+impl Default for AsyncServerConfig {
+    fn default() -> Self {
+        AsyncServerConfig { max_connections: 10_000, max_commands: 64, text: false, log: LogLevel::default(), tcp: TcpSettings::default() }
+    }
+}
+
+#[derive(Clone)]
+pub struct AsyncServer {
+    server: Arc<Server>,
+    config: AsyncServerConfig,
+    connections: Arc<Semaphore>,
+    commands: Arc<Semaphore>,
+}
+
+impl AsyncServer {
+
+    pub fn new(server: Server, config: AsyncServerConfig) -> AsyncServer {
+        AsyncServer {
+            server: Arc::new(server),
+            config,
+            connections: Arc::new(Semaphore::new(config.max_connections)),
+            commands: Arc::new(Semaphore::new(config.max_commands)),
+        }
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    // Serves each connection accepted on a task of its own
+    pub async fn listen(&self, listener: TcpListener) {
+        loop {
+            let permit = self.connections.clone().acquire_owned().await.expect("Connection limit is never closed");
+            let conn = match listener.accept().await {
+                Ok((conn, _)) => conn,
+                Err(err) => {
+                    if self.config.log >= LogLevel::Error {
+                        eprintln!("Failed to accept a connection: {err}");
+                    }
+                    continue;
+                },
+            };
+            let this = self.clone();
+            tokio::spawn(async move {
+                let served = match this.config.tcp.set_keepalive(&conn) {
+                    Ok(()) => {
+                        let (input, output) = conn.into_split();
+                        if this.config.text { this.serve_text(input, output).await } else { this.serve(input, output).await }
+                    },
+                    Err(err) => Err(err),
+                };
+                match served {
+                    Err(err) if tcp::timed_out(&err) && this.config.log >= LogLevel::Info => eprintln!("Closed an idle connection"),
+                    Err(err) if !tcp::timed_out(&err) && this.config.log >= LogLevel::Error => eprintln!("Connection ended: {err}"),
+                    _ => {},
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    // As `Server::serve`
+    pub async fn serve(&self, input: impl AsyncRead + Unpin, output: impl AsyncWrite + Unpin) -> io::Result<()> {
+        let mut input = BufReader::new(input);
+        let mut output = BufWriter::new(output);
+        let mut session = self.server.open_session();
+        while let Some(request) = self.until_idle(Frame::read_from_async(&mut input)).await? {
+            let (sender, mut replies) = mpsc::channel(STREAM_FRAMES);
+            let this = self.clone();
+            let running = tokio::spawn(async move {
+                this.run(move |server| {
+                    // Fails once the connection is gone
+                    let sent = server.reply_to(&request, &mut session, &mut |reply| sender.blocking_send(reply).map_err(io::Error::other));
+                    (sent, session)
+                }).await
+            });
+            while let Some(reply) = replies.recv().await {
+                output.write_all(&reply.to_bytes()?).await?;
+                if reply.is_urgent() {
+                    output.flush().await?;
+                }
+            }
+            let sent;
+            (sent, session) = running.await.map_err(io::Error::other)??;
+            sent?;
+            if input.buffer().is_empty() {
+                output.flush().await?;
+            }
+        }
+        output.flush().await
+    }
+
+    // As `Server::serve_text`
+    pub async fn serve_text(&self, input: impl AsyncRead + Unpin, mut output: impl AsyncWrite + Unpin) -> io::Result<()> {
+        let mut format = TextFormat::default();
+        let mut session = self.server.open_session();
+        let mut lines = BufReader::new(input).lines();
+        while let Some(line) = self.until_idle(lines.next_line()).await? {
+            for statement in statements(&line) {
+                let statement = statement.to_string();
+                let reply;
+                (reply, format, session) = self.run(move |server| {
+                    let reply = server.text_reply(&statement, &mut format, &mut session);
+                    (reply, format, session)
+                }).await?;
+                output.write_all(format!("{reply}\n\n").as_bytes()).await?;
+                output.flush().await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Fails as timed out if the connection stays idle for too long
+    async fn until_idle<T>(&self, read: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+        match self.config.tcp.idle_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, read).await.unwrap_or_else(|_| Err(tcp::idle_error())),
+            None => read.await,
+        }
+    }
+
+    async fn run<T: Send + 'static>(&self, call: impl FnOnce(&Server) -> T + Send + 'static) -> io::Result<T> {
+        let _permit = self.commands.acquire().await.expect("Command limit is never closed");
+        let server = self.server.clone();
+        tokio::task::spawn_blocking(move || call(&server)).await.map_err(io::Error::other)
+    }
+}
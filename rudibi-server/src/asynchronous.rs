@@ -0,0 +1,175 @@
+// Async reads of table files, for servers on tokio that can't block their runtime on disk scans
+// A scan reads without holding the table's lock, and hands what it read back to the storage
+// under the lock: bytes that raced a write are read again. Only reads are async, writes stay
+// blocking: `AsyncDatabase` runs them on tokio's blocking pool.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::engine::{Database, DbError, QueryOptions, ResultSet, Row};
+use crate::query::{Bool, Value};
+use crate::storage::{page_group_len, DiskStorage, ScanItem, PAGE_SIZE};
+
+pub type NextRead<'a> = Pin<Box<dyn Future<Output = Result<Option<StorageRead>, DbError>> + Send + 'a>>;
+
+// Bytes read by a scan, turned into rows by `AsyncStorage::rows_of`
+pub struct StorageRead {
+    position: u64,
+    bytes: Vec<u8>,
+    // State of the storage the read was made against
+    writes: u64,
+    file_len: u64,
+}
+
+// Async counterpart of the read path of `Storage`, see `Storage::as_async`
+pub trait AsyncStorage {
+    // The scan doesn't borrow the storage, the table can be unlocked while it reads
+    fn scan_async(&self) -> Box<dyn AsyncScan>;
+    // Live rows of what the scan read, or the read back if the storage was written to since it
+    // started, to be retried against the storage as it is now
+    fn rows_of(&self, read: StorageRead) -> Result<Result<Vec<ScanItem<'static>>, StorageRead>, DbError>;
+}
+
+// Live rows of a storage a read at a time, in scan order
+pub trait AsyncScan: Send {
+    // The next read, or None once everything was read
+    fn next_read(&mut self) -> NextRead<'_>;
+    // Reads again what `read` covered, on the next call
+    fn retry(&mut self, read: StorageRead);
+}
+
+// Reads one page group at a time, of the groups with live rows when the scan started
+struct DiskScan {
+    path: String,
+    file_len: u64,
+    file: Option<File>,
+    pages: VecDeque<u64>,
+    writes: u64,
+}
+
+impl DiskScan {
+    async fn read_page_group(&mut self, position: u64) -> Result<Vec<u8>, DbError> {
+        let path = &self.path;
+        let io_err = |err: std::io::Error| DbError::StorageError(format!("Failed to read {path}: {err}"));
+        if self.file.is_none() {
+            self.file = Some(File::open(path).await.map_err(io_err)?);
+        }
+        let file = self.file.as_mut().unwrap();
+        let remaining = self.file_len - position;
+        file.seek(SeekFrom::Start(position)).await.map_err(io_err)?;
+        let mut bytes = vec![0; remaining.min(PAGE_SIZE as u64) as usize];
+        file.read_exact(&mut bytes).await.map_err(io_err)?;
+        let group_len = page_group_len(&bytes) as u64;
+        if group_len > PAGE_SIZE as u64 && group_len <= remaining {
+            bytes.resize(group_len as usize, 0);
+            file.read_exact(&mut bytes[PAGE_SIZE..]).await.map_err(io_err)?;
+        }
+        Ok(bytes)
+    }
+}
+
+impl AsyncScan for DiskScan {
+    fn next_read(&mut self) -> NextRead<'_> {
+        Box::pin(async move {
+            let Some(position) = self.pages.pop_front() else { return Ok(None) };
+            let bytes = self.read_page_group(position).await?;
+            Ok(Some(StorageRead { position, bytes, writes: self.writes, file_len: self.file_len }))
+        })
+    }
+
+    fn retry(&mut self, read: StorageRead) {
+        self.writes = read.writes;
+        self.file_len = read.file_len;
+        self.pages.push_front(read.position);
+    }
+}
+
+impl AsyncStorage for DiskStorage {
+    fn scan_async(&self) -> Box<dyn AsyncScan> {
+        Box::new(DiskScan {
+            path: self.path().to_string(),
+            file_len: self.file_len(),
+            file: None,
+            pages: self.live_pages().into(),
+            writes: self.writes(),
+        })
+    }
+
+    fn rows_of(&self, mut read: StorageRead) -> Result<Result<Vec<ScanItem<'static>>, StorageRead>, DbError> {
+        if read.writes != self.writes() {
+            read.writes = self.writes();
+            read.file_len = self.file_len();
+            read.bytes.clear();
+            return Ok(Err(read));
+        }
+        self.live_rows_of(read.position, read.bytes).map(Ok)
+    }
+}
+
+// A database shared by tasks on a tokio runtime. Selects of tables with async reads await them,
+// everything else runs on the blocking pool so the runtime's threads stay free.
+// Arguments are owned, a blocking call can outlive the task that awaited it.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    db: Arc<Database>,
+}
+
+impl AsyncDatabase {
+
+    pub fn new(db: Database) -> Self {
+        AsyncDatabase { db: Arc::new(db) }
+    }
+
+    // For calls that aren't worth a trip to the blocking pool
+    pub fn database(&self) -> &Database {
+        &self.db
+    }
+
+    pub async fn select(&self, values: Vec<Value<'static>>, table: &str, filter: Bool<'static>) -> Result<ResultSet, DbError> {
+        self.select_with_options(values, table, filter, QueryOptions::default()).await
+    }
+
+    pub async fn select_with_options(&self, values: Vec<Value<'static>>, table: &str, filter: Bool<'static>, options: QueryOptions) -> Result<ResultSet, DbError> {
+        if self.db.reads_async(table)? {
+            return self.db.select_async(&values, table, &filter, &options).await;
+        }
+        let table = table.to_string();
+        self.run(move |db| db.select_with_options(&values, &table, &filter, &options)).await
+    }
+
+    pub async fn insert(&self, table: &str, columns: &[&str], what: Vec<Row>) -> Result<usize, DbError> {
+        let (table, columns) = (table.to_string(), owned(columns));
+        self.run(move |db| db.insert(&table, &borrowed(&columns), &what)).await
+    }
+
+    pub async fn delete(&self, table: &str, filter: Bool<'static>) -> Result<usize, DbError> {
+        let table = table.to_string();
+        self.run(move |db| db.delete(&table, &filter)).await
+    }
+
+    pub async fn update(&self, table: &str, columns: &[&str], values: Row, filter: Bool<'static>) -> Result<usize, DbError> {
+        let (table, columns) = (table.to_string(), owned(columns));
+        self.run(move |db| db.update(&table, &borrowed(&columns), &values, &filter)).await
+    }
+
+    // Runs `call` on the blocking pool, for whatever else the database offers
+    pub async fn run<T: Send + 'static>(&self, call: impl FnOnce(&Database) -> Result<T, DbError> + Send + 'static) -> Result<T, DbError> {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || call(&db)).await
+            .map_err(|err| DbError::DatabaseIntegrityError(format!("Blocking database call failed: {err}")))?
+    }
+}
+
+fn owned(columns: &[&str]) -> Vec<String> {
+    columns.iter().map(|col| col.to_string()).collect()
+}
+
+fn borrowed(columns: &[String]) -> Vec<&str> {
+    columns.iter().map(String::as_str).collect()
+}
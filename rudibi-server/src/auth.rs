@@ -0,0 +1,134 @@
+// Who may run commands on a server, see `Server::with_credentials`
+// Users log in with a password, kept as a PBKDF2-SHA256 hash:
+//   pbkdf2-sha256$ITERATIONS$SALT$HASH     salt and hash in hex
+// and services with a pre-shared token, kept as its SHA-256:
+//   sha256$HASH
+// `hash_password` and `hash_token` make them, `rudibi-server --hash-password` prints one for what
+// it reads from stdin. A connection authenticates as the name of a user or token before running
+// any command. Failures don't tell whether the name exists.
+
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{Arc, Mutex};
+
+use crate::blob::BlobWriter;
+use crate::cancel::{CancelKey, CancelSlot};
+use crate::cursor::Cursors;
+use crate::display::hex;
+use crate::engine::DbError;
+use crate::frame::Codec;
+use crate::ingest::InsertStream;
+use crate::hash::{constant_time_eq, pbkdf2_sha256, sha256};
+use crate::protocol::{decode_hex, OpenTransaction};
+use crate::throttle::Throttle;
+
+pub const PASSWORD_ITERATIONS: u32 = 100_000;
+const SALT_BYTES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Credentials {
+    users: HashMap<String, PasswordHash>,
+    // SHA-256 of each token, to its name
+    tokens: HashMap<[u8; 32], String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct PasswordHash {
+    iterations: u32,
+    salt: Vec<u8>,
+    hash: [u8; 32],
+}
+
+// A connection to a server, see `Server::open_session`
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    // Who the connection is authenticated as, None until it is
+    pub user: Option<String>,
+    // Cancels the connection's queries, when it's registered with the server
+    pub cancel: Option<Arc<CancelSlot>>,
+    // How the connection's frames are compressed, None until it asks, see `frame::COMPRESS`
+    pub codec: Option<Codec>,
+    // Limits how fast the connection runs queries, see `Server::with_rate_limits`
+    pub throttle: Option<Arc<Throttle>>,
+    // The transaction the connection began, until it ends, see `protocol`. Clones share it.
+    pub(crate) transaction: Option<Arc<OpenTransaction>>,
+    // The cursors the connection declared, see `cursor`. Clones share them.
+    pub(crate) cursors: Arc<Mutex<Cursors>>,
+    // The inserts the connection is streaming, until it's done, see `frame::INSERT_ROWS`
+    pub(crate) insert: Option<Arc<Mutex<InsertStream>>>,
+    // The blob the connection is writing, until it's done, see `frame::BLOB_WRITE`
+    pub(crate) blob: Option<Arc<Mutex<BlobWriter>>>,
+}
+
+impl Session {
+    pub fn cancel_key(&self) -> Option<CancelKey> {
+        self.cancel.as_ref().map(|slot| slot.key)
+    }
+}
+
+impl Credentials {
+
+    pub fn add_user(&mut self, name: &str, password_hash: &str) -> Result<(), DbError> {
+        let bad = || DbError::InputError(format!("Bad password hash for {name}, expected pbkdf2-sha256$ITERATIONS$SALT$HASH"));
+        let ["pbkdf2-sha256", iterations, salt, hash] = password_hash.split('$').collect::<Vec<_>>()[..] else { return Err(bad()) };
+        let hash = PasswordHash {
+            iterations: iterations.parse().ok().filter(|&n| n > 0).ok_or_else(bad)?,
+            salt: decode_hex(salt).ok_or_else(bad)?,
+            hash: decode_hex(hash).and_then(|hash| hash.try_into().ok()).ok_or_else(bad)?,
+        };
+        self.users.insert(name.to_string(), hash);
+        Ok(())
+    }
+
+    pub fn add_token(&mut self, name: &str, token_hash: &str) -> Result<(), DbError> {
+        let bad = || DbError::InputError(format!("Bad token hash for {name}, expected sha256$HASH"));
+        let hash = token_hash.strip_prefix("sha256$").and_then(decode_hex).and_then(|hash| hash.try_into().ok()).ok_or_else(bad)?;
+        self.tokens.insert(hash, name.to_string());
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.tokens.is_empty()
+    }
+
+    // The name `secret` authenticates as: the user's if it's their password or a token of that
+    // name, the token's if no user is given
+    pub fn check(&self, user: Option<&str>, secret: &str) -> Result<String, DbError> {
+        if let Some(stored) = user.and_then(|user| self.users.get(user)) {
+            let hash = pbkdf2_sha256(secret.as_bytes(), &stored.salt, stored.iterations);
+            if constant_time_eq(&hash, &stored.hash) {
+                return Ok(user.unwrap().to_string());
+            }
+        }
+        match self.tokens.get(&sha256(secret.as_bytes())) {
+            Some(name) if user.is_none_or(|user| user == name) => Ok(name.clone()),
+            _ => Err(DbError::AuthenticationFailed),
+        }
+    }
+}
+
+pub fn hash_password(password: &str) -> String {
+    hash_password_with(password, PASSWORD_ITERATIONS)
+}
+
+// More iterations take longer to check, for guessing passwords too
+pub fn hash_password_with(password: &str, iterations: u32) -> String {
+    let salt: [u8; SALT_BYTES] = random_bytes();
+    let hash = pbkdf2_sha256(password.as_bytes(), &salt, iterations);
+    format!("pbkdf2-sha256${iterations}${}${}", &hex(&salt)[2..], &hex(&hash)[2..])
+}
+
+pub fn hash_token(token: &str) -> String {
+    format!("sha256${}", &hex(&sha256(token.as_bytes()))[2..])
+}
+
+// Enough for salts and cancel keys, which only need to differ and not be guessed: std's hasher
+// keys are random per process and change with each `RandomState`
+pub(crate) fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for (idx, chunk) in bytes.chunks_mut(8).enumerate() {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos();
+        chunk.copy_from_slice(&RandomState::new().hash_one((nanos, idx)).to_le_bytes()[..chunk.len()]);
+    }
+    bytes
+}
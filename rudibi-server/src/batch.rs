@@ -0,0 +1,86 @@
+// Writes collected across calls and applied together, for ingestion loops that would otherwise
+// insert a row at a time. Each table is locked and committed once for all of its writes, and
+// consecutive inserts into a table with the same columns are validated and stored as one.
+// Writes to a table apply in the order they were added. A batch isn't a transaction: a failed
+// write stops it, and the writes applied before it stay.
+
+use crate::engine::{Database, DbError, Row, TableData};
+use crate::query::Bool;
+
+enum Write<'a> {
+    Insert { columns: Vec<String>, rows: Vec<Row> },
+    Delete(Bool<'a>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BatchReport {
+    pub rows_inserted: usize,
+    pub rows_deleted: usize,
+}
+
+pub struct WriteBatch<'a> {
+    db: &'a Database,
+    // Writes of each table, tables in the order they were first written to
+    tables: Vec<(String, Vec<Write<'a>>)>,
+}
+
+impl<'a> WriteBatch<'a> {
+
+    pub(crate) fn new(db: &'a Database) -> Self {
+        WriteBatch { db, tables: Vec::new() }
+    }
+
+    pub fn insert(&mut self, table_name: &str, columns: &[&str], what: &[Row]) -> &mut Self {
+        let writes = self.writes_of(table_name);
+        match writes.last_mut() {
+            Some(Write::Insert { columns: pending, rows }) if pending.iter().eq(columns) => rows.extend_from_slice(what),
+            _ => writes.push(Write::Insert { columns: columns.iter().map(|col| col.to_string()).collect(), rows: what.to_vec() }),
+        }
+        self
+    }
+
+    pub fn delete(&mut self, table_name: &str, filter: Bool<'a>) -> &mut Self {
+        self.writes_of(table_name).push(Write::Delete(filter));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn apply(self) -> Result<BatchReport, DbError> {
+        let mut report = BatchReport::default();
+        for (table_name, writes) in &self.tables {
+            let applied = self.db.write_table(table_name)
+                .and_then(|mut data| self.apply_to(table_name, &mut data, writes, &mut report));
+            // Writes applied before a failed one are kept, like those of earlier tables
+            let committed = self.db.commit_storage(table_name);
+            applied.and(committed)?;
+        }
+        Ok(report)
+    }
+
+    fn apply_to(&self, table_name: &str, data: &mut TableData, writes: &[Write], report: &mut BatchReport) -> Result<(), DbError> {
+        for write in writes {
+            match write {
+                Write::Insert { columns, rows } => {
+                    let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+                    report.rows_inserted += self.db.insert_into(table_name, data, &columns, rows)?.len();
+                },
+                Write::Delete(filter) => report.rows_deleted += self.db.delete_matching(table_name, data, filter)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn writes_of(&mut self, table_name: &str) -> &mut Vec<Write<'a>> {
+        let position = match self.tables.iter().position(|(table, _)| table == table_name) {
+            Some(position) => position,
+            None => {
+                self.tables.push((table_name.to_string(), Vec::new()));
+                self.tables.len() - 1
+            },
+        };
+        &mut self.tables[position].1
+    }
+}
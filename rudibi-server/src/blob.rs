@@ -0,0 +1,273 @@
+// Out-of-row storage for large binary values
+// Blobs are written and read in fixed-size chunks, so a multi-megabyte payload never has to
+// live in a single buffer. Rows reference a blob by storing its id, e.g. in a BUFFER { length: 8 }.
+// A database kept in a directory keeps each blob in a file of its `blobs` directory, named after
+// the id once it's complete and synced. The next id is kept there too, so ids of deleted blobs that
+// rows may still hold aren't given out again. Other databases keep the chunks in memory.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use crate::engine::DbError;
+
+pub use rudibi_core::blob::{BlobId, BLOB_CHUNK_SIZE};
+
+type Chunks = Arc<[Box<[u8]>]>;
+
+#[derive(Default)]
+pub struct BlobStore {
+    // None to keep blobs in memory
+    dir: Option<PathBuf>,
+    blobs: Mutex<HashMap<BlobId, Chunks>>,
+    next_id: Mutex<u64>,
+}
+
+fn storage_err(what: &str, path: &Path, err: io::Error) -> DbError {
+    DbError::StorageError(format!("Failed to {what} {}: {err}", path.display()))
+}
+
+impl BlobStore {
+
+    // Blobs kept in `dir`, created if needed. Blobs left half written by a crash are removed.
+    pub fn in_dir(dir: &Path) -> Result<BlobStore, DbError> {
+        fs::create_dir_all(dir).map_err(|err| storage_err("create", dir, err))?;
+        let next_path = dir.join("next");
+        let mut next_id = match fs::read_to_string(&next_path) {
+            Ok(next) => next.trim().parse().map_err(|_| DbError::StorageError(format!("Invalid next blob id in {}", next_path.display())))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(storage_err("read", &next_path, err)),
+        };
+        for entry in fs::read_dir(dir).map_err(|err| storage_err("list", dir, err))? {
+            let path = entry.map_err(|err| storage_err("list", dir, err))?.path();
+            match (path.file_stem().and_then(|stem| stem.to_str()?.parse::<u64>().ok()), path.extension().and_then(|ext| ext.to_str())) {
+                (Some(id), Some("blob")) => next_id = next_id.max(id + 1),
+                (Some(id), Some("part")) => {
+                    next_id = next_id.max(id + 1);
+                    fs::remove_file(&path).map_err(|err| storage_err("remove", &path, err))?;
+                },
+                _ => {},
+            }
+        }
+        Ok(BlobStore { dir: Some(dir.to_path_buf()), blobs: Mutex::default(), next_id: Mutex::new(next_id) })
+    }
+
+    // A blob to write a chunk at a time, stored by `finish`
+    pub fn create(&self) -> Result<BlobWriter, DbError> {
+        let id = self.take_id()?;
+        let target = match &self.dir {
+            Some(dir) => {
+                let path = dir.join(format!("{}.part", id.0));
+                let file = File::create(&path).map_err(|err| storage_err("create", &path, err))?;
+                BlobTarget::File { file: BufWriter::new(file), path }
+            },
+            None => BlobTarget::Memory(Vec::new()),
+        };
+        Ok(BlobWriter { id, target: Some(target) })
+    }
+
+    pub fn finish(&self, mut writer: BlobWriter) -> Result<BlobId, DbError> {
+        match writer.target.take() {
+            Some(BlobTarget::File { file, path }) => {
+                let file = file.into_inner().map_err(|err| storage_err("write", &path, err.into_error()))?;
+                file.sync_all().map_err(|err| storage_err("sync", &path, err))?;
+                let done = path.with_extension("blob");
+                fs::rename(&path, &done).map_err(|err| storage_err("rename", &path, err))?;
+            },
+            Some(BlobTarget::Memory(chunks)) => {
+                self.blobs().insert(writer.id, chunks.into_iter().map(Vec::into_boxed_slice).collect());
+            },
+            None => {},
+        }
+        Ok(writer.id)
+    }
+
+    pub fn put(&self, source: &mut dyn Read) -> Result<BlobId, DbError> {
+        let mut writer = self.create()?;
+        let mut chunk = Vec::with_capacity(BLOB_CHUNK_SIZE);
+        loop {
+            chunk.clear();
+            let read = source.take(BLOB_CHUNK_SIZE as u64).read_to_end(&mut chunk)
+                .map_err(|err| DbError::InputError(format!("Failed to read blob: {err}")))?;
+            if read == 0 {
+                break;
+            }
+            writer.write_all(&chunk).map_err(|err| DbError::StorageError(format!("Failed to write blob: {err}")))?;
+        }
+        self.finish(writer)
+    }
+
+    pub fn reader(&self, id: BlobId) -> Result<BlobReader, DbError> {
+        let source = match &self.dir {
+            Some(_) => BlobSource::File(BufReader::with_capacity(BLOB_CHUNK_SIZE, self.open(id)?)),
+            None => BlobSource::Memory { chunks: self.chunks(id)?, chunk: 0, offset: 0 },
+        };
+        Ok(BlobReader { source })
+    }
+
+    pub fn len(&self, id: BlobId) -> Result<usize, DbError> {
+        match &self.dir {
+            Some(_) => Ok(self.open(id)?.metadata().map_err(|err| DbError::StorageError(format!("Failed to read blob {}: {err}", id.0)))?.len() as usize),
+            None => Ok(self.chunks(id)?.iter().map(|chunk| chunk.len()).sum()),
+        }
+    }
+
+    // Bytes of all blobs kept in memory
+    pub fn memory_usage(&self) -> usize {
+        self.blobs().values().flat_map(|chunks| chunks.iter()).map(|chunk| chunk.len()).sum()
+    }
+
+    pub fn delete(&self, id: BlobId) -> Result<(), DbError> {
+        match &self.dir {
+            Some(dir) => fs::remove_file(dir.join(format!("{}.blob", id.0))).map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => DbError::BlobNotFound(id.0),
+                _ => DbError::StorageError(format!("Failed to delete blob {}: {err}", id.0)),
+            }),
+            None => self.blobs().remove(&id).map(|_| ()).ok_or(DbError::BlobNotFound(id.0)),
+        }
+    }
+
+    // Ids are taken in order, the next one saved before the blob is written
+    fn take_id(&self) -> Result<BlobId, DbError> {
+        let mut next_id = self.next_id.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = BlobId(*next_id);
+        if let Some(dir) = &self.dir {
+            let (path, saved) = (dir.join("next.tmp"), dir.join("next"));
+            let save = || -> io::Result<()> {
+                let mut file = File::create(&path)?;
+                file.write_all((id.0 + 1).to_string().as_bytes())?;
+                file.sync_all()?;
+                fs::rename(&path, &saved)
+            };
+            save().map_err(|err| storage_err("save", &saved, err))?;
+        }
+        *next_id += 1;
+        Ok(id)
+    }
+
+    fn open(&self, id: BlobId) -> Result<File, DbError> {
+        let dir = self.dir.as_ref().expect("Only blobs in a directory are opened");
+        File::open(dir.join(format!("{}.blob", id.0))).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => DbError::BlobNotFound(id.0),
+            _ => DbError::StorageError(format!("Failed to open blob {}: {err}", id.0)),
+        })
+    }
+
+    fn chunks(&self, id: BlobId) -> Result<Chunks, DbError> {
+        self.blobs().get(&id).cloned().ok_or(DbError::BlobNotFound(id.0))
+    }
+
+    fn blobs(&self) -> MutexGuard<'_, HashMap<BlobId, Chunks>> {
+        self.blobs.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+// A blob being written, see `BlobStore::create`. Dropped unfinished, it's discarded.
+#[derive(Debug)]
+pub struct BlobWriter {
+    id: BlobId,
+    // None once finished
+    target: Option<BlobTarget>,
+}
+
+#[derive(Debug)]
+enum BlobTarget {
+    Memory(Vec<Vec<u8>>),
+    File { file: BufWriter<File>, path: PathBuf },
+}
+
+impl Write for BlobWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.target {
+            Some(BlobTarget::Memory(_)) if buf.is_empty() => Ok(0),
+            Some(BlobTarget::Memory(chunks)) => {
+                if chunks.last().is_none_or(|chunk| chunk.len() == BLOB_CHUNK_SIZE) {
+                    chunks.push(Vec::with_capacity(BLOB_CHUNK_SIZE));
+                }
+                let chunk = chunks.last_mut().expect("Pushed if there was none");
+                let len = (BLOB_CHUNK_SIZE - chunk.len()).min(buf.len());
+                chunk.extend_from_slice(&buf[..len]);
+                Ok(len)
+            },
+            Some(BlobTarget::File { file, .. }) => file.write(buf),
+            None => Err(io::Error::other("Blob is already stored")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.target {
+            Some(BlobTarget::File { file, .. }) => file.flush(),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        if let Some(BlobTarget::File { path, .. }) = &self.target {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+pub struct BlobReader {
+    source: BlobSource,
+}
+
+enum BlobSource {
+    Memory { chunks: Chunks, chunk: usize, offset: usize },
+    File(BufReader<File>),
+}
+
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (chunks, chunk, offset) = match &mut self.source {
+            BlobSource::Memory { chunks, chunk, offset } => (chunks, chunk, offset),
+            BlobSource::File(file) => return file.read(buf),
+        };
+        while let Some(current) = chunks.get(*chunk) {
+            let remaining = &current[*offset..];
+            if remaining.is_empty() {
+                *chunk += 1;
+                *offset = 0;
+                continue;
+            }
+            let len = remaining.len().min(buf.len());
+            buf[..len].copy_from_slice(&remaining[..len]);
+            *offset += len;
+            return Ok(len);
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_across_chunks() {
+        let payload: Vec<u8> = (0..BLOB_CHUNK_SIZE * 2 + 17).map(|i| (i % 251) as u8).collect();
+        let store = BlobStore::default();
+
+        let id = store.put(&mut payload.as_slice()).unwrap();
+
+        assert_eq!(store.blobs()[&id].len(), 3);
+        assert_eq!(store.len(id), Ok(payload.len()));
+        let mut read_back = Vec::new();
+        store.reader(id).unwrap().read_to_end(&mut read_back).unwrap();
+        assert_eq!(read_back, payload);
+    }
+
+    #[test]
+    fn empty_blob() {
+        let store = BlobStore::default();
+        let id = store.put(&mut io::empty()).unwrap();
+
+        let mut read_back = Vec::new();
+        store.reader(id).unwrap().read_to_end(&mut read_back).unwrap();
+        assert!(read_back.is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+// Bloom filter over 64-bit key hashes
+// Answers "definitely not present" or "maybe present", so storage can skip data that can't
+// contain a key without reading it
+
+// ~1% false positives at the expected number of items
+const BITS_PER_ITEM: usize = 10;
+const HASHES: u64 = 7;
+
+#[derive(Debug, Clone)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+// Second hash for double hashing, derived from the first with the splitmix64 finalizer
+fn mix(mut hash: u64) -> u64 {
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
+
+impl BloomFilter {
+
+    pub fn new(expected_items: usize) -> BloomFilter {
+        let words = (expected_items * BITS_PER_ITEM).div_ceil(64).max(1);
+        BloomFilter { bits: vec![0; words] }
+    }
+
+    fn probes(&self, hash: u64) -> impl Iterator<Item = usize> {
+        let num_bits = self.bits.len() as u64 * 64;
+        let step = mix(hash) | 1;
+        (0..HASHES).map(move |i| (hash.wrapping_add(i.wrapping_mul(step)) % num_bits) as usize)
+    }
+
+    pub fn insert(&mut self, hash: u64) {
+        for bit in self.probes(hash).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    pub fn may_contain(&self, hash: u64) -> bool {
+        self.probes(hash).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    // Matches every key, for data whose keys can't be computed
+    pub fn saturate(&mut self) {
+        self.bits.fill(u64::MAX);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::stable_hash;
+
+    #[test]
+    fn inserted_keys_are_found() {
+        let mut filter = BloomFilter::new(1000);
+        for i in 0..1000u32 {
+            filter.insert(stable_hash(&i.to_le_bytes()));
+        }
+        assert!((0..1000u32).all(|i| filter.may_contain(stable_hash(&i.to_le_bytes()))));
+
+        let false_positives = (1000..11000u32).filter(|i| filter.may_contain(stable_hash(&i.to_le_bytes()))).count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn saturated_filter_matches_everything() {
+        let mut filter = BloomFilter::new(10);
+        assert!(!filter.may_contain(42));
+        filter.saturate();
+        assert!(filter.may_contain(42));
+    }
+}
@@ -0,0 +1,160 @@
+// Cancellation of queries from another thread, e.g. by a server whose client disconnected.
+// Scans check the token between batches of rows, so a cancelled query stops within a batch.
+// A server gives each connection a `CancelKey`, which another connection can send to cancel the
+// query the first is running, as in PostgreSQL. The registry of keys also tells how many
+// connections are open and what they're running, for the server's STATUS and STATS.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::Instant;
+
+use crate::engine::DbError;
+
+// Clones share the cancellation
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn check(token: Option<&CancellationToken>) -> Result<(), DbError> {
+        match token {
+            Some(token) if token.is_cancelled() => Err(DbError::QueryCancelled),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Queries compare equal if they'd be cancelled together
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+// Cancels what a connection is running, the secret keeps others from guessing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CancelKey {
+    pub id: u32,
+    pub secret: u32,
+}
+
+impl CancelKey {
+    // As displayed, `id secret`
+    pub fn parse(text: &str) -> Option<CancelKey> {
+        let (id, secret) = text.trim().split_once(' ')?;
+        Some(CancelKey { id: id.parse().ok()?, secret: secret.trim().parse().ok()? })
+    }
+}
+
+impl std::fmt::Display for CancelKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.id, self.secret)
+    }
+}
+
+// A query a connection is running, see `CancelRegistry::running`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunningQuery {
+    // Id of the connection's cancel key
+    pub connection: u32,
+    pub user: Option<String>,
+    pub text: String,
+    pub started: Instant,
+}
+
+// A connection's key, and the token of the query it's running or ran last
+#[derive(Debug)]
+pub struct CancelSlot {
+    pub key: CancelKey,
+    // The query too while it runs, if it was started with `run_query`
+    token: Mutex<(CancellationToken, Option<RunningQuery>)>,
+}
+
+impl CancelSlot {
+    // Token of the connection's next query, cancelling the last one's has no effect on it
+    pub fn start_query(&self) -> CancellationToken {
+        self.start(None)
+    }
+
+    // As `start_query`, listing the query as running until `finish_query`
+    pub fn run_query(&self, user: Option<&str>, text: &str) -> CancellationToken {
+        let started = Instant::now();
+        self.start(Some(RunningQuery { connection: self.key.id, user: user.map(str::to_string), text: text.to_string(), started }))
+    }
+
+    // Ends the query of `token`, unless the connection has started another since
+    pub fn finish_query(&self, token: &CancellationToken) {
+        let mut current = self.token.lock().unwrap_or_else(PoisonError::into_inner);
+        if current.0 == *token {
+            current.1 = None;
+        }
+    }
+
+    fn start(&self, query: Option<RunningQuery>) -> CancellationToken {
+        let token = CancellationToken::new();
+        *self.token.lock().unwrap_or_else(PoisonError::into_inner) = (token.clone(), query);
+        token
+    }
+}
+
+// Slots of the connections open, each dropped with its connection
+#[derive(Debug, Default)]
+pub struct CancelRegistry {
+    last_id: AtomicU32,
+    slots: Mutex<HashMap<u32, Weak<CancelSlot>>>,
+}
+
+impl CancelRegistry {
+
+    pub fn register(&self) -> Arc<CancelSlot> {
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        let secret = u32::from_le_bytes(crate::auth::random_bytes());
+        let slot = Arc::new(CancelSlot { key: CancelKey { id, secret }, token: Mutex::new((CancellationToken::new(), None)) });
+        let mut slots = self.slots.lock().unwrap_or_else(PoisonError::into_inner);
+        slots.retain(|_, slot| slot.strong_count() > 0);
+        slots.insert(id, Arc::downgrade(&slot));
+        slot
+    }
+
+    // False if no open connection has the key
+    pub fn cancel(&self, key: CancelKey) -> bool {
+        let slot = self.slots.lock().unwrap_or_else(PoisonError::into_inner).get(&key.id).and_then(Weak::upgrade);
+        match slot {
+            Some(slot) if slot.key == key => {
+                slot.token.lock().unwrap_or_else(PoisonError::into_inner).0.cancel();
+                true
+            },
+            _ => false,
+        }
+    }
+
+    // Connections open, those registered and not dropped yet
+    pub fn connections(&self) -> usize {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner).values().filter(|slot| slot.strong_count() > 0).count()
+    }
+
+    // Queries the connections are running, the longest running first
+    pub fn running(&self) -> Vec<RunningQuery> {
+        let slots: Vec<Arc<CancelSlot>> = self.slots.lock().unwrap_or_else(PoisonError::into_inner).values().filter_map(Weak::upgrade).collect();
+        let mut running: Vec<RunningQuery> = slots.iter()
+            .filter_map(|slot| slot.token.lock().unwrap_or_else(PoisonError::into_inner).1.clone())
+            .collect();
+        running.sort_by_key(|query| (query.started, query.connection));
+        running
+    }
+}
@@ -0,0 +1,159 @@
+// Database kept in a directory, see `Database::open_dir`
+// The directory holds one data file per managed table, named by number so any table name is safe,
+// and a catalog of the tables with the settings and indexes to restore them with. A lock on the
+// LOCK file keeps other processes out while the database is open.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::compress::Compression;
+use crate::dtype::{decode_field, encode_field, take, take_u64};
+use crate::engine::DbError;
+use crate::index::IndexKind;
+use crate::storage::Durability;
+
+const CATALOG_MAGIC: &[u8; 4] = b"RDBC";
+const CATALOG_VERSION: u32 = 1;
+const CATALOG_FILE: &str = "catalog";
+const LOCK_FILE: &str = "LOCK";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexDef {
+    pub column: String,
+    pub kind: IndexKind,
+    pub unique: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CatalogEntry {
+    pub table: String,
+    // Relative to the database directory
+    pub file: String,
+    pub durability: Durability,
+    pub compression: Compression,
+    pub indexes: Vec<IndexDef>,
+}
+
+pub struct Catalog {
+    dir: PathBuf,
+    // Held for the lifetime of the catalog, the OS releases the lock when it's closed
+    _lock: File,
+    next_file: u64,
+    pub entries: Vec<CatalogEntry>,
+}
+
+fn storage_err(dir: &Path, err: std::io::Error) -> DbError {
+    DbError::StorageError(format!("Failed to access database directory {}: {err}", dir.display()))
+}
+
+impl Catalog {
+
+    // Creates the directory if needed and locks it
+    pub fn open(dir: &str) -> Result<Catalog, DbError> {
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir).map_err(|err| storage_err(&dir, err))?;
+        let lock = OpenOptions::new().write(true).create(true).truncate(false).open(dir.join(LOCK_FILE))
+            .map_err(|err| storage_err(&dir, err))?;
+        match lock.try_lock() {
+            Ok(()) => {},
+            Err(TryLockError::WouldBlock) => return Err(DbError::DatabaseLocked(dir.display().to_string())),
+            Err(TryLockError::Error(err)) => return Err(storage_err(&dir, err)),
+        }
+
+        let mut catalog = Catalog { dir, _lock: lock, next_file: 0, entries: Vec::new() };
+        match std::fs::read(catalog.dir.join(CATALOG_FILE)) {
+            Ok(bytes) => catalog.decode(&bytes).ok_or_else(|| DbError::DatabaseIntegrityError(
+                format!("Catalog of {} is malformed", catalog.dir.display())
+            ))?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+            Err(err) => return Err(storage_err(&catalog.dir, err)),
+        }
+        Ok(catalog)
+    }
+
+    pub fn path_of(&self, entry: &CatalogEntry) -> String {
+        self.dir.join(&entry.file).display().to_string()
+    }
+
+    // Name for the data file of a new table, never handed out twice
+    pub fn new_file(&mut self) -> String {
+        self.next_file += 1;
+        format!("t{}.rdb", self.next_file)
+    }
+
+    pub fn entry_mut(&mut self, table: &str) -> Option<&mut CatalogEntry> {
+        self.entries.iter_mut().find(|entry| entry.table == table)
+    }
+
+    pub fn contains(&self, table: &str) -> bool {
+        self.entries.iter().any(|entry| entry.table == table)
+    }
+
+    // Written to a temporary file first, so a crash leaves either the old or the new catalog
+    pub fn save(&self) -> Result<(), DbError> {
+        let tmp = self.dir.join(format!("{CATALOG_FILE}.tmp"));
+        let mut file = File::create(&tmp).map_err(|err| storage_err(&self.dir, err))?;
+        file.write_all(&self.encode()).and_then(|_| file.sync_all()).map_err(|err| storage_err(&self.dir, err))?;
+        std::fs::rename(&tmp, self.dir.join(CATALOG_FILE)).map_err(|err| storage_err(&self.dir, err))
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(CATALOG_MAGIC);
+        out.extend_from_slice(&CATALOG_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.next_file.to_le_bytes());
+        out.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for entry in &self.entries {
+            encode_field(&mut out, entry.table.as_bytes());
+            encode_field(&mut out, entry.file.as_bytes());
+            out.push(durability_tag(entry.durability));
+            out.push(entry.compression.tag());
+            out.extend_from_slice(&(entry.indexes.len() as u64).to_le_bytes());
+            for index in &entry.indexes {
+                encode_field(&mut out, index.column.as_bytes());
+                out.push(match index.kind { IndexKind::Ordered => 0 });
+                out.push(index.unique as u8);
+            }
+        }
+        out
+    }
+
+    fn decode(&mut self, mut bytes: &[u8]) -> Option<()> {
+        if take(&mut bytes, 4)? != CATALOG_MAGIC || take(&mut bytes, 4)? != CATALOG_VERSION.to_le_bytes() {
+            return None;
+        }
+        self.next_file = take_u64(&mut bytes)?;
+        for _ in 0..take_u64(&mut bytes)? {
+            let table = take_string(&mut bytes)?;
+            let file = take_string(&mut bytes)?;
+            let durability = durability_of(take(&mut bytes, 1)?[0])?;
+            let compression = Compression::from_tag(take(&mut bytes, 1)?[0])?;
+            let indexes = (0..take_u64(&mut bytes)?).map(|_| {
+                let column = take_string(&mut bytes)?;
+                let kind = match take(&mut bytes, 1)?[0] { 0 => IndexKind::Ordered, _ => return None };
+                let unique = take(&mut bytes, 1)?[0] != 0;
+                Some(IndexDef { column, kind, unique })
+            }).collect::<Option<Vec<_>>>()?;
+            self.entries.push(CatalogEntry { table, file, durability, compression, indexes });
+        }
+        bytes.is_empty().then_some(())
+    }
+}
+
+fn durability_tag(durability: Durability) -> u8 {
+    match durability {
+        Durability::Flush => 0,
+        Durability::SyncPerBatch => 1,
+        Durability::SyncPerCommit => 2,
+        Durability::GroupCommit => 3,
+    }
+}
+
+fn durability_of(tag: u8) -> Option<Durability> {
+    [Durability::Flush, Durability::SyncPerBatch, Durability::SyncPerCommit, Durability::GroupCommit].get(tag as usize).copied()
+}
+
+fn take_string(bytes: &mut &[u8]) -> Option<String> {
+    String::from_utf8(decode_field(bytes)?.to_vec()).ok()
+}
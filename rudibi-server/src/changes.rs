@@ -0,0 +1,113 @@
+// Logical change streams of tables, for clients following what's written to them, e.g. to keep a
+// cache or a search index up to date, and watched selects, for simple pub/sub.
+// A SUBSCRIBE frame holding a table's name, and optionally the sequence of the server's change
+// feed to resume after, as `Stock 42`, is replied to with EVENTS frames for as long as the
+// connection stays open. They hold the table's changes as CSV, the first frame holding only the
+// header:
+//   sequence,change,row_id,id,count
+//   7,insert,4,1,10
+//   8,update,4,1,15
+//   9,delete,4,,
+// Rows are written as a select of every column writes them, a delete only gives the row's id. An
+// update that moves rows, as those of tables with columns of variable width, is a delete then an
+// insert. Without a sequence, the events start with the next change. A client resumes after the
+// last sequence it saw, which fails with `DbError::ChangesExpired` once the feed dropped it. After
+// `HEARTBEAT` without an event an empty EVENTS frame is sent, so a subscription ends soon after
+// its connection is gone.
+// A WATCH frame holding a select, as `SELECT name FROM Fruits WHERE id > 100`, is replied to with
+// NOTIFY frames of the rows inserted from then on that the select selects, as CSV with its header
+// first, and empty frames after `HEARTBEAT` as for a subscription. Rows that an update moves, see
+// `Database::update`, are stored anew and notified of again.
+// Both need a server with a change feed, see `Server::with_change_feed`, and with permissions the
+// read privilege on the table.
+
+use std::time::Duration;
+
+use crate::display::{csv_header, csv_row, DisplayOptions};
+use crate::engine::{Column, Database, DbError, Row};
+use crate::query::Bool;
+use crate::wal::{WalChange, WalRecord};
+
+// Longest wait for a change before an empty EVENTS frame is sent
+pub const HEARTBEAT: Duration = Duration::from_secs(10);
+
+// The table and sequence of a SUBSCRIBE request
+pub fn parse_subscribe(text: &str) -> Result<(&str, Option<u64>), DbError> {
+    let bad = || DbError::InputError(format!("Bad subscription {text}, expected a table and optionally a sequence"));
+    match text.split_whitespace().collect::<Vec<_>>()[..] {
+        [table] => Ok((table, None)),
+        [table, after] => Ok((table, Some(after.parse().map_err(|_| bad())?))),
+        _ => Err(bad()),
+    }
+}
+
+pub fn header(schema: &[Column]) -> String {
+    format!("sequence,change,row_id,{}", csv_header(schema))
+}
+
+// CSV lines of the changes to `table` among `records`
+pub fn events(schema: &[Column], table: &str, records: &[WalRecord]) -> String {
+    let options = DisplayOptions::full();
+    let mut out = String::new();
+    for record in records.iter().filter(|record| record.table == table) {
+        let sequence = record.sequence;
+        match &record.change {
+            WalChange::Store { row_ids, rows } | WalChange::Update { row_ids, rows } => {
+                let change = if matches!(record.change, WalChange::Store { .. }) { "insert" } else { "update" };
+                for (row_id, row) in row_ids.iter().zip(rows) {
+                    out.push_str(&format!("{sequence},{change},{row_id},{}", csv_row(schema, row, &options)));
+                }
+            },
+            WalChange::Delete { row_ids } => {
+                // No values rather than empty ones, which are quoted
+                let values = ",".repeat(schema.len() - 1);
+                for row_id in row_ids {
+                    out.push_str(&format!("{sequence},delete,{row_id},{values}\n"));
+                }
+            },
+            WalChange::Checkpoint => {},
+        }
+    }
+    out
+}
+
+// A select watched for the rows inserted that it selects
+pub struct Watch<'a> {
+    pub table: &'a str,
+    // Schema position of each column selected
+    positions: Vec<usize>,
+    pub columns: Vec<Column>,
+    filter: Bool<'a>,
+}
+
+impl<'a> Watch<'a> {
+
+    // No columns watches all of them
+    pub fn new(db: &Database, table: &'a str, columns: &[&str], filter: Bool<'a>) -> Result<Watch<'a>, DbError> {
+        let schema = db.schema_for(table)?;
+        let columns = match columns {
+            [] => schema.column_layout.iter().map(|col| col.name.as_str()).collect(),
+            columns => columns.to_vec(),
+        };
+        let (positions, columns) = schema.project_to_schema(&columns)?.into_iter().map(|(idx, col)| (idx, col.clone())).unzip();
+        // Fails on columns the table doesn't have
+        db.filter_rows(table, &filter, &[])?;
+        Ok(Watch { table, positions, columns, filter })
+    }
+
+    // CSV lines of the rows inserted among `records` that the select selects
+    pub fn notifications(&self, db: &Database, records: &[WalRecord]) -> Result<String, DbError> {
+        let options = DisplayOptions::full();
+        let mut out = String::new();
+        for record in records.iter().filter(|record| record.table == self.table) {
+            let WalChange::Store { rows, .. } = &record.change else { continue };
+            for (row, selected) in rows.iter().zip(db.filter_rows(self.table, &self.filter, rows)?) {
+                if selected {
+                    let row = Row::of_columns(&self.positions.iter().map(|idx| row.get_column(*idx)).collect::<Vec<_>>());
+                    out.push_str(&csv_row(&self.columns, &row, &options));
+                }
+            }
+        }
+        Ok(out)
+    }
+}
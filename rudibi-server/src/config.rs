@@ -0,0 +1,526 @@
+// Settings of rudibi-server, from `rudibi.toml` and the command line, e.g.
+//   [server]
+//   bind = "0.0.0.0"
+//   port = 5432
+//   protocol = "postgres"          # frames, text, postgres or resp
+//   workers = 64                   # connections served at once
+//   commands = 8                   # commands run at once on a pool, see `Server::with_pool`
+//   queue = 64                     # commands waiting for the pool before they fail as busy
+//   async = false                  # serve on tokio, see `async_server`
+//   log = "info"                   # off, error or info
+//   idle_timeout = 300             # seconds a connection may send nothing before it's closed
+//   keepalive = 60                 # seconds a connection is quiet before TCP keepalive probes
+//   metrics_port = 9100            # serves GET /metrics over HTTP on it, see `metrics`
+//
+//   [storage]
+//   data_dir = "/var/lib/rudibi"
+//   tables = "disk"                # memory or disk, where tables created by clients are kept
+//   durability = "group-commit"    # flush, sync-per-batch, sync-per-commit or group-commit
+//
+//   [limits]
+//   query_memory = 67108864
+//   disk_budget = 1_073_741_824
+//   soft_row_size = 0.8
+//   soft_result_rows = 100000
+//   soft_table_quota = 0.9         # fractions of the hard limits, see `limits`
+//   soft_disk_budget = 0.9
+//   soft_query_memory = 0.75
+//   cursors = 16                   # cursors a connection may have open, see `cursor`
+//   insert_budget = 16777216       # bytes of streamed inserts sent at once, see `ingest`
+//
+//   [limits.connection]            # per second for each connection, see `throttle`
+//   queries = 100
+//   rows_scanned = 1_000_000
+//   bytes_returned = 10_485_760
+//
+//   [limits.user]                  # per second for all of a user's connections together
+//   queries = 500
+//
+//   [replication]                  # see `replication`
+//   backlog = 100000               # changes kept for replicas to catch up with, none if not given
+//   primary = "10.0.0.1:1337"      # serves as a read-only replica of the primary there
+//   token = "replica-secret"       # authenticates with the primary
+//
+//   [tls]                          # with the tls feature, see `tls`
+//   cert = "/etc/rudibi/server.pem"
+//   key = "/etc/rudibi/server.key"
+//
+//   [users]                        # hashes from --hash-password, see `auth`
+//   alice = "pbkdf2-sha256$100000$..."
+//
+//   [tokens]                       # hashes from --hash-token
+//   backup = "sha256$..."
+//
+//   [roles.analytics]              # tables a role may read, write or create, * for all
+//   read = "*"
+//   admin = "*"                    # server status and running queries
+//   [roles.loader]
+//   write = "Orders, Stock"
+//   ddl = "Stock"
+//
+//   [user_roles]                   # see `permissions`, needs users or tokens
+//   alice = "analytics"
+//   backup = "analytics, loader"
+// Every setting is optional. Only the part of TOML needed for this is read: tables of keys whose
+// values are strings, integers, floats or booleans. Flags on the command line override the file.
+
+use std::path::Path;
+use std::time::Duration;
+
+use crate::auth::Credentials;
+use crate::compress::Compression;
+use crate::cursor::DEFAULT_MAX_OPEN_CURSORS;
+use crate::ingest::DEFAULT_INSERT_BUDGET_BYTES;
+use crate::engine::{DbError, StorageCfg};
+use crate::limits::SoftLimits;
+use crate::permissions::{Permissions, Privilege};
+use crate::storage::Durability;
+use crate::tcp::TcpSettings;
+use crate::throttle::RateLimits;
+
+// Read from the working directory when no --config is given, if it's there
+pub const DEFAULT_FILE: &str = "rudibi.toml";
+
+// What connections speak
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Protocol {
+    // See `frame`
+    #[default]
+    Frames,
+    // See `Server::serve_text`
+    Text,
+    // See `pgwire`
+    Postgres,
+    // See `resp`
+    Resp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogLevel {
+    Off,
+    // Failed connections
+    #[default]
+    Error,
+    // Also where the server listens, and each connection served
+    Info,
+}
+
+// Where tables created by clients are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableStorage {
+    Memory,
+    // Files in the data directory
+    Disk,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    pub protocol: Protocol,
+    pub workers: usize,
+    // None runs commands on the connections' threads
+    pub commands: Option<usize>,
+    pub queue: usize,
+    pub asynchronous: bool,
+    pub log: LogLevel,
+    pub tcp: TcpSettings,
+    // None serves no metrics
+    pub metrics_port: Option<u16>,
+    // None keeps every table in memory only
+    pub data_dir: Option<String>,
+    // None is on disk with a data directory, in memory without
+    pub tables: Option<TableStorage>,
+    pub durability: Durability,
+    pub query_memory: Option<usize>,
+    pub disk_budget: Option<u64>,
+    pub soft_limits: SoftLimits,
+    pub max_cursors: usize,
+    pub insert_budget: usize,
+    pub connection_rates: RateLimits,
+    // Only for authenticated connections
+    pub user_rates: RateLimits,
+    // Changes kept for replicas, None serves none
+    pub replication_backlog: Option<usize>,
+    // Where the primary of a replica listens for frames, None if the server isn't a replica
+    pub primary: Option<String>,
+    pub primary_token: Option<String>,
+    // Both or neither, connections are only served over TLS with them
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    // Connections must authenticate unless it's empty
+    pub credentials: Credentials,
+    // Authenticated users may do anything when it's empty
+    pub permissions: Permissions,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: "127.0.0.1".to_string(),
+            port: 1337,
+            protocol: Protocol::default(),
+            workers: 64,
+            commands: None,
+            queue: 64,
+            asynchronous: false,
+            log: LogLevel::default(),
+            tcp: TcpSettings::default(),
+            metrics_port: None,
+            data_dir: None,
+            tables: None,
+            durability: Durability::default(),
+            query_memory: None,
+            disk_budget: None,
+            soft_limits: SoftLimits::default(),
+            max_cursors: DEFAULT_MAX_OPEN_CURSORS,
+            insert_budget: DEFAULT_INSERT_BUDGET_BYTES,
+            connection_rates: RateLimits::default(),
+            user_rates: RateLimits::default(),
+            replication_backlog: None,
+            primary: None,
+            primary_token: None,
+            tls_cert: None,
+            tls_key: None,
+            credentials: Credentials::default(),
+            permissions: Permissions::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+
+    // The file given with --config, or `DEFAULT_FILE` if there's one, then the other flags
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<ServerConfig, DbError> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = match args.iter().position(|arg| arg == "--config") {
+            Some(at) => {
+                let path = args.get(at + 1).ok_or_else(|| DbError::InputError("--config needs a value".to_string()))?;
+                ServerConfig::load(path)?
+            },
+            None if Path::new(DEFAULT_FILE).exists() => ServerConfig::load(DEFAULT_FILE)?,
+            None => ServerConfig::default(),
+        };
+        config.apply_args(args)?;
+        Ok(config)
+    }
+
+    pub fn load(path: &str) -> Result<ServerConfig, DbError> {
+        let text = std::fs::read_to_string(path).map_err(|err| DbError::InputError(format!("Failed to read {path}: {err}")))?;
+        ServerConfig::from_toml(&text).map_err(|err| match err {
+            DbError::InputError(msg) => DbError::InputError(format!("{path}: {msg}")),
+            err => err,
+        })
+    }
+
+    pub fn from_toml(text: &str) -> Result<ServerConfig, DbError> {
+        let mut config = ServerConfig::default();
+        let mut section = String::new();
+        for (idx, line) in text.lines().enumerate() {
+            let at_line = |msg: String| DbError::InputError(format!("Line {}: {msg}", idx + 1));
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[') {
+                section = name.strip_suffix(']').map(str::trim).filter(|name| name.split('.').all(is_bare_key))
+                    .ok_or_else(|| at_line(format!("Bad table header {line}")))?.to_string();
+                continue;
+            }
+            let (key, value) = line.split_once('=').ok_or_else(|| at_line(format!("Expected key = value, got {line}")))?;
+            let key = key.trim();
+            if !is_bare_key(key) {
+                return Err(at_line(format!("Bad key {key}")));
+            }
+            let value = TomlValue::parse(value.trim()).map_err(at_line)?;
+            config.set(&format!("{section}.{key}"), value).map_err(at_line)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    // Overrides settings with the flags in `args`, see `main.rs`
+    pub fn apply_args(&mut self, args: impl IntoIterator<Item = String>) -> Result<(), DbError> {
+        let mut args = args.into_iter();
+        while let Some(flag) = args.next() {
+            let mut value = || args.next().ok_or_else(|| DbError::InputError(format!("{flag} needs a value")));
+            let setting = match flag.as_str() {
+                "--config" => {
+                    value()?;
+                    continue;
+                },
+                "--text" => ("server.protocol", TomlValue::Str("text".to_string())),
+                "--pg" => ("server.protocol", TomlValue::Str("postgres".to_string())),
+                "--resp" => ("server.protocol", TomlValue::Str("resp".to_string())),
+                "--async" => ("server.async", TomlValue::Bool(true)),
+                "--bind" => ("server.bind", TomlValue::Str(value()?)),
+                "--port" => ("server.port", TomlValue::from_arg(value()?)),
+                "--workers" => ("server.workers", TomlValue::from_arg(value()?)),
+                "--commands" => ("server.commands", TomlValue::from_arg(value()?)),
+                "--queue" => ("server.queue", TomlValue::from_arg(value()?)),
+                "--log" => ("server.log", TomlValue::Str(value()?)),
+                "--idle-timeout" => ("server.idle_timeout", TomlValue::from_arg(value()?)),
+                "--keepalive" => ("server.keepalive", TomlValue::from_arg(value()?)),
+                "--metrics-port" => ("server.metrics_port", TomlValue::from_arg(value()?)),
+                "--data-dir" => ("storage.data_dir", TomlValue::Str(value()?)),
+                "--tables" => ("storage.tables", TomlValue::Str(value()?)),
+                "--durability" => ("storage.durability", TomlValue::Str(value()?)),
+                "--backlog" => ("replication.backlog", TomlValue::from_arg(value()?)),
+                "--primary" => ("replication.primary", TomlValue::Str(value()?)),
+                "--tls-cert" => ("tls.cert", TomlValue::Str(value()?)),
+                "--tls-key" => ("tls.key", TomlValue::Str(value()?)),
+                _ => return Err(DbError::InputError(format!("Unknown argument {flag}"))),
+            };
+            self.set(setting.0, setting.1).map_err(|msg| DbError::InputError(format!("{flag}: {msg}")))?;
+        }
+        self.validate()
+    }
+
+    // Where tables created by clients are kept
+    pub fn storage(&self) -> StorageCfg {
+        match self.tables {
+            Some(TableStorage::Memory) => StorageCfg::InMemory,
+            None if self.data_dir.is_none() => StorageCfg::InMemory,
+            _ => StorageCfg::Managed { durability: self.durability, compression: Compression::default() },
+        }
+    }
+
+    fn validate(&self) -> Result<(), DbError> {
+        if self.tables == Some(TableStorage::Disk) && self.data_dir.is_none() {
+            return Err(DbError::InputError("Disk tables need a data_dir".to_string()));
+        }
+        if self.tls_cert.is_some() != self.tls_key.is_some() {
+            return Err(DbError::InputError("TLS needs both a cert and a key".to_string()));
+        }
+        if !self.permissions.is_empty() && self.credentials.is_empty() {
+            return Err(DbError::InputError("Roles need users or tokens to be given to".to_string()));
+        }
+        if !self.user_rates.is_empty() && self.credentials.is_empty() {
+            return Err(DbError::InputError("Rate limits per user need users or tokens".to_string()));
+        }
+        if self.primary_token.is_some() && self.primary.is_none() {
+            return Err(DbError::InputError("A replication token needs a primary".to_string()));
+        }
+        self.permissions.validate()
+    }
+
+    fn set(&mut self, key: &str, value: TomlValue) -> Result<(), String> {
+        match key {
+            "server.bind" => self.bind = value.string()?,
+            "server.port" => self.port = value.integer(1, u16::MAX as i64)? as u16,
+            "server.protocol" => self.protocol = match value.string()?.as_str() {
+                "frames" => Protocol::Frames,
+                "text" => Protocol::Text,
+                "postgres" => Protocol::Postgres,
+                "resp" => Protocol::Resp,
+                other => return Err(format!("Unknown protocol {other}, expected frames, text, postgres or resp")),
+            },
+            "server.workers" => self.workers = value.integer(1, i64::MAX)? as usize,
+            "server.commands" => self.commands = Some(value.integer(1, i64::MAX)? as usize),
+            "server.queue" => self.queue = value.integer(0, i64::MAX)? as usize,
+            "server.async" => self.asynchronous = value.boolean()?,
+            "server.log" => self.log = match value.string()?.as_str() {
+                "off" => LogLevel::Off,
+                "error" => LogLevel::Error,
+                "info" => LogLevel::Info,
+                other => return Err(format!("Unknown log level {other}, expected off, error or info")),
+            },
+            "server.idle_timeout" => self.tcp.idle_timeout = value.seconds()?,
+            "server.keepalive" => self.tcp.keepalive = value.seconds()?,
+            "server.metrics_port" => self.metrics_port = Some(value.integer(1, u16::MAX as i64)? as u16),
+            "storage.data_dir" => self.data_dir = Some(value.string()?),
+            "storage.tables" => self.tables = Some(match value.string()?.as_str() {
+                "memory" => TableStorage::Memory,
+                "disk" => TableStorage::Disk,
+                other => return Err(format!("Unknown table storage {other}, expected memory or disk")),
+            }),
+            "storage.durability" => self.durability = match value.string()?.as_str() {
+                "flush" => Durability::Flush,
+                "sync-per-batch" => Durability::SyncPerBatch,
+                "sync-per-commit" => Durability::SyncPerCommit,
+                "group-commit" => Durability::GroupCommit,
+                other => return Err(format!("Unknown durability {other}, expected flush, sync-per-batch, sync-per-commit or group-commit")),
+            },
+            "limits.query_memory" => self.query_memory = Some(value.integer(0, i64::MAX)? as usize),
+            "limits.disk_budget" => self.disk_budget = Some(value.integer(0, i64::MAX)? as u64),
+            "limits.soft_row_size" => self.soft_limits.row_size = Some(value.float()?),
+            "limits.soft_result_rows" => self.soft_limits.result_rows = Some(value.integer(0, i64::MAX)? as usize),
+            "limits.soft_table_quota" => self.soft_limits.table_quota = Some(value.float()?),
+            "limits.soft_disk_budget" => self.soft_limits.disk_budget = Some(value.float()?),
+            "limits.soft_query_memory" => self.soft_limits.query_memory = Some(value.float()?),
+            "limits.cursors" => self.max_cursors = value.integer(0, i64::MAX)? as usize,
+            "limits.insert_budget" => self.insert_budget = value.integer(0, i64::MAX)? as usize,
+            "limits.connection.queries" => self.connection_rates.queries = value.per_second()?,
+            "limits.connection.rows_scanned" => self.connection_rates.rows_scanned = value.per_second()?,
+            "limits.connection.bytes_returned" => self.connection_rates.bytes_returned = value.per_second()?,
+            "limits.user.queries" => self.user_rates.queries = value.per_second()?,
+            "limits.user.rows_scanned" => self.user_rates.rows_scanned = value.per_second()?,
+            "limits.user.bytes_returned" => self.user_rates.bytes_returned = value.per_second()?,
+            "replication.backlog" => self.replication_backlog = Some(value.integer(1, i64::MAX)? as usize),
+            "replication.primary" => self.primary = Some(value.string()?),
+            "replication.token" => self.primary_token = Some(value.string()?),
+            "tls.cert" => self.tls_cert = Some(value.string()?),
+            "tls.key" => self.tls_key = Some(value.string()?),
+            _ => {
+                let added = match key.split_once('.') {
+                    Some(("users", name)) => self.credentials.add_user(name, &value.string()?),
+                    Some(("tokens", name)) => self.credentials.add_token(name, &value.string()?),
+                    Some(("user_roles", user)) if !user.contains('.') => {
+                        for role in list(&value.string()?) {
+                            self.permissions.assign(user, role);
+                        }
+                        Ok(())
+                    },
+                    Some(("roles", role_privilege)) if role_privilege.matches('.').count() == 1 => {
+                        let (role, privilege) = role_privilege.split_once('.').unwrap();
+                        let privilege = Privilege::parse(privilege)
+                            .ok_or_else(|| format!("Unknown privilege {privilege}, expected read, write, ddl or admin"))?;
+                        for table in list(&value.string()?) {
+                            self.permissions.grant(role, privilege, table);
+                        }
+                        Ok(())
+                    },
+                    _ => return Err(format!("Unknown setting {key}")),
+                };
+                added.map_err(|err| match err {
+                    DbError::InputError(msg) => msg,
+                    err => format!("{err:?}"),
+                })?;
+            },
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TomlValue {
+    Str(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl TomlValue {
+
+    fn parse(text: &str) -> Result<TomlValue, String> {
+        if let Some(quoted) = text.strip_prefix('"') {
+            let body = quoted.strip_suffix('"').ok_or_else(|| format!("Unterminated string {text}"))?;
+            return unescape(body).map(TomlValue::Str);
+        }
+        if let Some(quoted) = text.strip_prefix('\'') {
+            let body = quoted.strip_suffix('\'').filter(|body| !body.contains('\'')).ok_or_else(|| format!("Unterminated string {text}"))?;
+            return Ok(TomlValue::Str(body.to_string()));
+        }
+        match text {
+            "true" => return Ok(TomlValue::Bool(true)),
+            "false" => return Ok(TomlValue::Bool(false)),
+            _ => {},
+        }
+        let digits = text.replace('_', "");
+        if let Ok(n) = digits.parse() {
+            return Ok(TomlValue::Integer(n));
+        }
+        match digits.parse() {
+            Ok(n) if text.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+') => Ok(TomlValue::Float(n)),
+            _ => Err(format!("Expected a string, number or boolean, got {text}")),
+        }
+    }
+
+    // Numbers given as flags, anything else is a string and fails where a number was expected
+    fn from_arg(arg: String) -> TomlValue {
+        arg.parse().map_or(TomlValue::Str(arg), TomlValue::Integer)
+    }
+
+    fn string(self) -> Result<String, String> {
+        match self {
+            TomlValue::Str(text) => Ok(text),
+            other => Err(format!("Expected a string, got {other:?}")),
+        }
+    }
+
+    fn integer(self, min: i64, max: i64) -> Result<i64, String> {
+        match self {
+            TomlValue::Integer(n) if (min..=max).contains(&n) => Ok(n),
+            TomlValue::Integer(n) => Err(format!("{n} isn't between {min} and {max}")),
+            other => Err(format!("Expected an integer, got {other:?}")),
+        }
+    }
+
+    // None for 0, which turns the setting off
+    fn seconds(self) -> Result<Option<Duration>, String> {
+        let seconds = self.integer(0, u32::MAX as i64)? as u64;
+        Ok((seconds > 0).then(|| Duration::from_secs(seconds)))
+    }
+
+    // None for 0, which turns the limit off
+    fn per_second(self) -> Result<Option<f64>, String> {
+        match self.float()? {
+            rate if rate < 0.0 || !rate.is_finite() => Err(format!("{rate} isn't a rate")),
+            rate => Ok((rate > 0.0).then_some(rate)),
+        }
+    }
+
+    fn float(self) -> Result<f64, String> {
+        match self {
+            TomlValue::Float(n) => Ok(n),
+            TomlValue::Integer(n) => Ok(n as f64),
+            other => Err(format!("Expected a number, got {other:?}")),
+        }
+    }
+
+    fn boolean(self) -> Result<bool, String> {
+        match self {
+            TomlValue::Bool(b) => Ok(b),
+            other => Err(format!("Expected true or false, got {other:?}")),
+        }
+    }
+}
+
+// The line up to a # that isn't in a string
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            },
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..idx],
+            _ => {},
+        }
+        escaped = false;
+    }
+    line
+}
+
+// Items of a comma-separated string
+fn list(text: &str) -> impl Iterator<Item = &str> {
+    text.split(',').map(str::trim).filter(|item| !item.is_empty())
+}
+
+fn is_bare_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn unescape(body: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push(match chars.next() {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                other => return Err(format!("Unsupported escape \\{}", other.map(String::from).unwrap_or_default())),
+            }),
+            '"' => return Err(format!("Unescaped quote in \"{body}\"")),
+            c => out.push(c),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,83 @@
+// Named cursors over query results, fetched in batches (DECLARE / FETCH / CLOSE, see `protocol`)
+// A connection owns one `Cursors` so clients can page through results they can't buffer at once.
+// Its cursors are closed when it's dropped, with the connection's session.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::engine::{Column, DbError, ResultSet, Row};
+
+pub const DEFAULT_MAX_OPEN_CURSORS: usize = 16;
+
+#[derive(Debug)]
+struct Cursor {
+    schema: Vec<Column>,
+    rows: std::vec::IntoIter<Row>,
+}
+
+#[derive(Debug)]
+pub struct Cursors {
+    max_open: usize,
+    open: HashMap<String, Cursor>,
+    // Cursors open in all the `Cursors` sharing it, those of a server's connections
+    total: Arc<AtomicUsize>,
+}
+
+impl Default for Cursors {
+    fn default() -> Self {
+        Cursors::new(DEFAULT_MAX_OPEN_CURSORS)
+    }
+}
+
+impl Cursors {
+
+    pub fn new(max_open: usize) -> Self {
+        Cursors::counted(max_open, Arc::default())
+    }
+
+    // Counting its open cursors in `total` too
+    pub fn counted(max_open: usize, total: Arc<AtomicUsize>) -> Self {
+        Cursors { max_open, open: HashMap::new(), total }
+    }
+
+    pub fn declare(&mut self, name: &str, results: ResultSet) -> Result<(), DbError> {
+        if self.open.contains_key(name) {
+            return Err(DbError::CursorAlreadyExists(name.to_string()));
+        }
+        if self.open.len() >= self.max_open {
+            return Err(DbError::TooManyCursors { max: self.max_open });
+        }
+        let cursor = Cursor { schema: results.schema, rows: results.data.into_iter() };
+        self.open.insert(name.to_string(), cursor);
+        self.total.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Next batch of at most `count` rows. An empty batch means the cursor is exhausted.
+    pub fn fetch(&mut self, name: &str, count: usize) -> Result<ResultSet, DbError> {
+        let cursor = self.open.get_mut(name).ok_or_else(|| DbError::CursorNotFound(name.to_string()))?;
+        let data: Vec<Row> = cursor.rows.by_ref().take(count).collect();
+        Ok(ResultSet { schema: cursor.schema.clone(), data, scan_stats: Default::default() })
+    }
+
+    pub fn close(&mut self, name: &str) -> Result<(), DbError> {
+        self.open.remove(name).ok_or_else(|| DbError::CursorNotFound(name.to_string()))?;
+        self.total.fetch_sub(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+}
+
+impl Drop for Cursors {
+    fn drop(&mut self) {
+        self.total.fetch_sub(self.open.len(), Ordering::Relaxed);
+    }
+}
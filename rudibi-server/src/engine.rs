@@ -1,16 +1,44 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LockResult, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError, TryLockResult};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use crate::blob::{BlobId, BlobReader, BlobStore, BlobWriter};
+use crate::catalog::{Catalog, CatalogEntry, IndexDef};
+use crate::tiered::{TieredStorage, DEFAULT_HOT_ROWS};
+use crate::wal::{read_wal, RecoveryTarget, WalChange, WalReplay, WalWriter};
+use crate::collation::Collation;
+use crate::compress::Compression;
 use crate::dtype::*;
+use crate::hash::StableHasher;
+use crate::index::{self, Index, IndexKey, IndexKind};
+use crate::maintenance::{MaintenanceConfig, MaintenanceHandle, MaintenanceReport, Worker};
+use crate::batch::WriteBatch;
+use crate::cancel::CancellationToken;
+use crate::group_commit::PendingSync;
+use crate::locking::{self, HeldLock, LockGraph, LockMode, TableLock};
+use crate::transaction::{IsolationLevel, Transaction};
+use crate::spill::{ResultStream, SpillingRows};
+use crate::permissions::Privilege;
+use crate::telemetry;
+use crate::throttle::Rate;
+use crate::limits::{Limit, LimitMonitor, LimitObserver, LimitWarning, QueryMemory, Quota, QuotaKind, QuotaPolicy, SoftLimits};
+use crate::plan::{collect_params, query_shape, CmpOp, Operand, Plan, PlanCache, Program, Truth};
 use crate::query::{Bool, Value};
-use crate::storage::{DiskStorage, InMemoryStorage, RowId, ScanItem, Storage};
+use crate::storage::{DiskStorage, Durability, InMemoryStorage, MemoryUsage, RecoveryReport, RowBatch, RowContent, RowId, ScanItem, Storage, StorageObserver};
 
-#[derive(Debug, PartialEq)]
+pub use rudibi_core::schema::{Column, ResultSet, Row, ScanStats};
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum DbError {
     TableNotFound(String),
     TableAlreadyExists(String),
     EmptyTableSchema,
     ColumnNotFound(String),
     InvalidColumnCount { expected: usize, got: usize },
+    InvalidEnumLabel { column: String, label: String },
     RowSizeExceeded { got: usize, max: usize },
     RowSizeTooSmall { got: usize, min: usize },
     ColumnSizeOutOfBounds { column: String, got: usize, min: usize, max: usize },
@@ -19,19 +47,45 @@ pub enum DbError {
     QueryError(TypeError),
 
     UnsupportedOperation(String),
-    DatabaseIntegrityError(String)
-}
+    DatabaseIntegrityError(String),
+    StorageError(String),
 
-#[derive(Debug, Clone)]
-pub struct Column {
-    pub name: String,
-    pub dtype: DataType,
-}
-
-impl Column {
-    pub fn new(name: &str, dtype: DataType) -> Column {
-        Column { name: name.to_string(), dtype }
-    }
+    CursorNotFound(String),
+    CursorAlreadyExists(String),
+    TooManyCursors { max: usize },
+    FlowControlViolation { window: usize, got: usize },
+    BlobNotFound(u64),
+    IndexAlreadyExists(String),
+    IndexNotFound(String),
+    DuplicateKey { index: String, key: IndexKey },
+    UnsupportedFormatVersion { found: u32, supported: u32 },
+    // Database directory is open in another process, or elsewhere in this one
+    DatabaseLocked(String),
+    // `got` is what the insert would have taken the table or database to
+    QuotaExceeded { table: String, quota: QuotaKind, max: u64, got: u64 },
+    SavepointNotFound(String),
+    QueryCancelled,
+    // `got` is as far as the query went before it was stopped
+    QueryMemoryExceeded { max: usize, got: usize },
+    // Waiting for the table's lock would never end, the thread holds a lock one of its holders waits for
+    Deadlock(String),
+    // A snapshot transaction wrote a table that was changed since its snapshot of it
+    WriteConflict(String),
+    // The server's queue of commands waiting for a worker is full, see `protocol::Server::with_pool`
+    ServerBusy,
+    // The name or secret a connection gave doesn't match the server's credentials, see `auth`
+    AuthenticationFailed,
+    // The connection sent a command before authenticating to a server that has credentials
+    NotAuthenticated,
+    // None of the user's roles has the privilege on the table, see `permissions`
+    PermissionDenied { user: String, privilege: Privilege, table: String },
+    // The connection or its user went over a rate limit, see `throttle`
+    RateLimited { rate: Rate, retry_after: Duration },
+    // A replica was sent a write, see `replication`
+    ReadOnly,
+    // The changes after `after` were dropped from the primary's feed, whose oldest is `oldest`.
+    // The replica asking for them needs a new snapshot, see `replication`.
+    ChangesExpired { after: u64, oldest: u64 },
 }
 
 #[derive(Debug, Clone)]
@@ -55,6 +109,23 @@ impl Table {
         }
     }
 
+    // Stable 64-bit digest of the table definition. Equal fingerprints mean peers agree on the
+    // table name and the names, order and data types of its columns.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        hasher.write_field(self.name.as_bytes());
+        hasher.write_u64(self.column_layout.len() as u64);
+        for col in &self.column_layout {
+            hasher.write_field(col.name.as_bytes());
+            col.dtype.hash_into(&mut hasher);
+            // Binary is left out so fingerprints of existing schemas don't change
+            if col.collation != Collation::Binary {
+                hasher.write_field(col.collation.name().as_bytes());
+            }
+        }
+        hasher.finish()
+    }
+
     // Projecting columns in select clauses, filters, etc.
     // Seen as projecting input columns to schema
     pub fn project_to_schema(&self, columns: &[&str]) -> Result<Vec<(usize, &Column)>, DbError> {
@@ -88,12 +159,34 @@ impl Table {
         Ok(indices)
     }
 
-    fn require_column<'schema>(&'schema self, name: &'_ str) -> Result<(usize, &'schema Column), DbError> {
+    pub(crate) fn require_column<'schema>(&'schema self, name: &'_ str) -> Result<(usize, &'schema Column), DbError> {
         self.columns.get(name)
             .map(|(i, col)| (*i, col))
             .ok_or_else(|| DbError::ColumnNotFound(name.to_string()))
     }
 
+    // Rows with enum labels replaced by their stored index, or None for tables without enums
+    fn encode_enums(&self, rows: &[Row], column_mapping: &[usize]) -> Result<Option<Vec<Row>>, DbError> {
+        if !self.column_layout.iter().any(|col| matches!(col.dtype, DataType::ENUM { .. })) {
+            return Ok(None);
+        }
+        let mut encoded_rows = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut encoded: Vec<Vec<u8>> = (0..row.offsets.len() - 1).map(|idx| row.get_column(idx).to_vec()).collect();
+            for (schema_idx, col) in self.column_layout.iter().enumerate() {
+                if let DataType::ENUM { labels } = &col.dtype {
+                    let input_idx = column_mapping[schema_idx];
+                    let label = encoded.get(input_idx).map(Vec::as_slice).unwrap_or_default();
+                    encoded[input_idx] = encode_enum_label(labels, label)
+                        .ok_or_else(|| DbError::InvalidEnumLabel { column: col.name.clone(), label: String::from_utf8_lossy(label).into_owned() })?;
+                }
+            }
+            let columns: Vec<&[u8]> = encoded.iter().map(Vec::as_slice).collect();
+            encoded_rows.push(Row::of_columns(&columns));
+        }
+        Ok(Some(encoded_rows))
+    }
+
     fn validate_input(&self, row: &Row, column_mapping: &Vec<usize>) -> Result<(), DbError> {
         // Validate the number of columns
         let input_offsets = row.offsets.len();
@@ -129,124 +222,402 @@ impl Table {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct Row {
-    pub data: Vec<u8>,        // Contiguous buffer holding all column data
-    pub offsets: Vec<usize>,  // Start offsets for each column, plus end of last column
+// What to do with a row whose stored bytes can't be decoded as its column's data type
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CorruptRowPolicy {
+    // Abort the query with `DbError::DatabaseIntegrityError`
+    #[default]
+    Fail,
+    // Leave the row out of the results
+    Skip,
+    // Return the row with its undecoded bytes, as if it matched the filter
+    Raw,
 }
 
-impl Row {
-    
-    pub fn of_columns(columns: &[&[u8]]) -> Row {
-        let mut data = Vec::new();
-        let mut offsets = Vec::new();
-        // Preallocating is slower??
-        // let mut data = Vec::with_capacity(columns.iter().map(|col| col.len()).sum());
-        // let mut offsets = Vec::with_capacity(columns.len() + 1);
-        offsets.push(0);
-        for col in columns {
-            data.extend_from_slice(col);
-            offsets.push(data.len());
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct QueryOptions {
+    pub corrupt_rows: CorruptRowPolicy,
+    // Threads filtering a scan of a large table, 0 for one per core
+    pub parallelism: usize,
+    // Fails the query with `QueryCancelled` once cancelled
+    pub cancel: Option<CancellationToken>,
+    // Bytes of rows the query may hold in memory, instead of the database's limit
+    pub max_memory: Option<usize>,
+}
+
+// Tables with fewer rows are always scanned on the calling thread
+pub const PARALLEL_SCAN_ROWS: usize = 65_536;
+// Rows a scan filters together
+pub const SCAN_BATCH_ROWS: usize = 1024;
+// Batches a scan hands to each thread at a time
+const BATCHES_PER_THREAD: usize = 16;
+
+// Differences found between an index and the table data by `Database::reindex`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexCheck {
+    pub column: String,
+    // Rows that weren't in the index
+    pub missing: usize,
+    // Index entries for deleted rows or outdated keys
+    pub stale: usize,
+}
+
+impl IndexCheck {
+    pub fn is_consistent(&self) -> bool {
+        self.missing == 0 && self.stale == 0
+    }
+}
+
+
+#[derive(Clone)]
+pub enum StorageCfg {
+    InMemory,
+    Disk { path: String, durability: Durability, compression: Compression },
+    // Disk table whose file is kept in the directory of a database from `Database::open_dir`
+    Managed { durability: Durability, compression: Compression },
+    // New rows are kept in memory and flushed to the file at `path` in batches, see `TieredStorage`
+    Tiered { path: String, durability: Durability, compression: Compression, max_hot_rows: usize },
+}
+
+impl StorageCfg {
+    pub fn disk(path: &str) -> StorageCfg {
+        StorageCfg::Disk { path: path.to_string(), durability: Durability::default(), compression: Compression::default() }
+    }
+
+    pub fn tiered(path: &str) -> StorageCfg {
+        StorageCfg::Tiered {
+            path: path.to_string(),
+            durability: Durability::default(),
+            compression: Compression::default(),
+            max_hot_rows: DEFAULT_HOT_ROWS,
         }
-        Row { data, offsets }
     }
 
-    pub fn get_column(&self, col_idx: usize) -> &[u8] {
-        let start = self.offsets[col_idx];
-        let end = self.offsets[col_idx + 1];
-        return &self.data[start..end];
+    pub fn managed() -> StorageCfg {
+        StorageCfg::Managed { durability: Durability::default(), compression: Compression::default() }
     }
 }
 
-pub struct ResultSet {
-    pub schema: Vec<Column>,
-    pub data: Vec<Row>,
+// Shared by the threads of a server: selects run concurrently, also on the same table, while
+// writes to a table run one at a time. Changes to the set of tables take `&mut self`.
+pub struct Database {
+    // Only changes through `&mut self`, so reading a schema takes no lock
+    schemas: HashMap<String, Table>,
+    tables: HashMap<String, TableSlot>,
+    plans: Mutex<PlanCache>,
+    observers: Vec<Arc<dyn StorageObserver>>,
+    limits: Mutex<LimitMonitor>,
+    blobs: BlobStore,
+    // Managed tables and their indexes, for databases kept in a directory
+    catalog: Option<Catalog>,
+    disk_budget: Option<u64>,
+    // Bytes of rows a query may hold in memory, unless its options say otherwise
+    query_memory: Option<usize>,
+    maintenance: Mutex<Option<Worker>>,
+    // As of the last maintenance run refreshing them
+    stats: Mutex<Vec<TableStats>>,
+    lock_graph: Mutex<LockGraph>,
+}
+
+// What changes with the rows of a table, behind the table's lock
+pub(crate) struct TableData {
+    pub(crate) storage: Box<dyn Storage>,
+    // At most one index per column
+    indexes: Vec<Index>,
+    quota: Quota,
+    // Changes to the rows so far, committed or not
+    pub(crate) changes: u64,
+}
+
+struct TableSlot {
+    data: RwLock<TableData>,
+    // File size as of the last write, so the disk budget is checked without locking every table
+    disk_bytes: AtomicU64,
+}
+
+// Write access to a table, publishing its file size when the write is done
+pub(crate) struct TableWrite<'a> {
+    data: RwLockWriteGuard<'a, TableData>,
+    disk_bytes: &'a AtomicU64,
 }
 
-impl ResultSet {
-    pub fn len(&self) -> usize {
-        return self.data.len();
+impl Deref for TableWrite<'_> {
+    type Target = TableData;
+
+    fn deref(&self) -> &TableData {
+        &self.data
     }
 }
 
-impl std::fmt::Debug for ResultSet {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("ResultSet")
-            .field("schema", &self.schema)
-            .field("data", &format!("{} rows", self.data.len()))
-            .finish()
+impl DerefMut for TableWrite<'_> {
+    fn deref_mut(&mut self) -> &mut TableData {
+        &mut self.data
     }
 }
 
+impl Drop for TableWrite<'_> {
+    fn drop(&mut self) {
+        self.disk_bytes.store(self.data.storage.disk_bytes(), Ordering::Relaxed);
+    }
+}
 
-#[derive(Clone)]
-pub enum StorageCfg {
-    InMemory,
-    Disk { path: String },
+// Read access to a table for a select. A select can let go of a lock it took itself once it has
+// a snapshot, not of one its caller holds.
+pub(crate) enum TableRead<'a> {
+    Locked(RwLockReadGuard<'a, TableData>),
+    Held(&'a TableData),
 }
 
-pub struct Database {
-    schemas: HashMap<String, Table>,
-    storage: HashMap<String, Box<dyn Storage>>
+impl Deref for TableRead<'_> {
+    type Target = TableData;
+
+    fn deref(&self) -> &TableData {
+        match self {
+            TableRead::Locked(data) => data,
+            TableRead::Held(data) => data,
+        }
+    }
 }
 
-pub struct FilterContext<'schema, 'row> {
+pub struct FilterContext<'schema, 'row, 'params> {
     schema: &'schema Table,
     item: &'row ScanItem<'row>,
+    params: &'params [ColumnValue<'params>],
 }
 
-impl<'schema, 'row, 'ctx> FilterContext<'schema, 'row> where 
-    'ctx: 'schema + 'row {
-    fn execute_binop(&self, left: &'ctx Value<'ctx>, right: &'ctx Value<'ctx>, op: fn(&ColumnValue<'row>, &ColumnValue<'row>) -> Result<bool, TypeError>) -> Result<bool, DbError> {
-        op(&self.resolve_value(&left)?, &self.resolve_value(&right)?).map_err(|err| DbError::QueryError(err))
-    }
+impl<'schema, 'row, 'params> FilterContext<'schema, 'row, 'params> where 'params: 'row {
 
-    fn resolve_value(&self, val: &'ctx Value<'ctx>) -> Result<ColumnValue<'row>, DbError> {
+    fn resolve_value<'v>(&'v self, val: &'v Operand) -> Result<ColumnValue<'v>, DbError> {
         match val {
-            Value::ColumnRef(column_name) => {
-                let (col_idx, col) = self.schema.require_column(&column_name)?;
-                let col_value = self.item.row_content.get_column(col_idx.clone());
+            Operand::Column { idx, col } => {
+                let col_value = self.item.row_content.get_column(*idx);
                 canonical_column(&col.dtype, col_value)
                     .map_err(|_| DbError::DatabaseIntegrityError(
-                        format!("Column {} at RowId={} in {} cannot be represented as data type {:?}", &column_name, &self.item.row_id, &self.schema.name, &col.dtype))
+                        format!("Column {} at RowId={} in {} cannot be represented as data type {:?}", &col.name, &self.item.row_id, &self.schema.name, &col.dtype))
                     )
             },
-            Value::Const(column_value) => Ok(*column_value),
+            Operand::Param(idx) => Ok(self.params[*idx]),
         }
     }
+
+    fn evaluate(&self, filter: &Program) -> Result<Truth, DbError> {
+        let res = match filter {
+            Program::True => Truth::True,
+            Program::False => Truth::False,
+            Program::Cmp(op, left, right) => {
+                let collation = left.collation().or(right.collation()).unwrap_or_default();
+                Truth::from(op.apply_collated(&self.resolve_value(left)?, &self.resolve_value(right)?, collation).map_err(DbError::QueryError)?)
+            },
+            Program::And(left, right) => self.evaluate(left)?.and(self.evaluate(right)?),
+            Program::Or(left, right) => self.evaluate(left)?.or(self.evaluate(right)?),
+            Program::Xor(left, right) => self.evaluate(left)?.xor(self.evaluate(right)?),
+            Program::Not(inner) => !self.evaluate(inner)?,
+        };
+        Ok(res)
+    }
+
+    // Rows pass only when the filter is definitively true
+    fn filter_row(&self, filter: &Program) -> Result<bool, DbError> {
+        Ok(self.evaluate(filter)?.is_true())
+    }
 }
 
-fn filter_row(schema: &Table, item: &ScanItem, filter: &Bool) -> Result<bool, DbError> {
-    let ctx = FilterContext { schema, item };
-    let res = match filter {
-        Bool::True => true,
-        Bool::False => false,
-        
-        Bool::Eq(left, right) => ctx.execute_binop(left, right, ColumnValue::eq)?,
-        Bool::Neq(left, right) => ctx.execute_binop(left, right, ColumnValue::neq)?,
-        Bool::Gt(left, right) => ctx.execute_binop(left, right, ColumnValue::gt)?,
-        Bool::Gte(left, right) => ctx.execute_binop(left, right, ColumnValue::gte)?,
-        Bool::Lt(left, right) => ctx.execute_binop(left, right, ColumnValue::lt)?,
-        Bool::Lte(left, right) => ctx.execute_binop(left, right, ColumnValue::lte)?,
-        Bool::And(left, right) => filter_row(schema, item, left)? & filter_row(schema, item, right)?,
-        Bool::Or(left, right) => filter_row(schema, item, left)? | filter_row(schema, item, right)?,
-        Bool::Xor(left, right) => filter_row(schema, item, left)? ^ filter_row(schema, item, right)?,
-        Bool::Not(inner) => !filter_row(schema, item, inner)?,
+fn filter_row(schema: &Table, item: &ScanItem, filter: &Program, params: &[ColumnValue]) -> Result<bool, DbError> {
+    FilterContext { schema, item, params }.filter_row(filter)
+}
+
+// Rows of the batch passing a comparison of a U32 column with a constant, compared a column at
+// a time. None for other filters, or if a value isn't a U32, so rows are filtered one by one.
+fn u32_kernel(batch: &RowBatch, filter: &Program, params: &[ColumnValue]) -> Option<Vec<bool>> {
+    let (op, col_idx, param) = match filter {
+        Program::Cmp(op, Operand::Column { idx, col }, Operand::Param(p)) if col.dtype == DataType::U32 => (*op, *idx, *p),
+        Program::Cmp(op, Operand::Param(p), Operand::Column { idx, col }) if col.dtype == DataType::U32 => (op.flipped(), *idx, *p),
+        _ => return None,
     };
-    Ok(res)
+    let ColumnValue::U32(key) = params[param] else { return None };
+    let values = batch.column(col_idx).into_iter()
+        .map(|bytes| bytes.try_into().ok().map(u32::from_le_bytes))
+        .collect::<Option<Vec<u32>>>()?;
+    // The operator is matched once per batch, leaving plain loops the compiler can vectorize
+    let keep = match op {
+        CmpOp::Eq | CmpOp::NotDistinctFrom => values.iter().map(|value| *value == key).collect(),
+        CmpOp::Neq | CmpOp::DistinctFrom => values.iter().map(|value| *value != key).collect(),
+        CmpOp::Gt => values.iter().map(|value| *value > key).collect(),
+        CmpOp::Gte => values.iter().map(|value| *value >= key).collect(),
+        CmpOp::Lt => values.iter().map(|value| *value < key).collect(),
+        CmpOp::Lte => values.iter().map(|value| *value <= key).collect(),
+    };
+    Some(keep)
+}
+
+// Projected rows of the batch passing the plan's filter, in scan order
+fn filter_and_project(schema: &Table, batch: &RowBatch, plan: &Plan, params: &[ColumnValue], corrupt_rows: CorruptRowPolicy) -> Result<(Vec<Row>, ScanStats), DbError> {
+    let mut rows = Vec::new();
+    let mut scan_stats = ScanStats::default();
+    let kept = u32_kernel(batch, &plan.filter, params);
+    for (pos, item) in batch.items().iter().enumerate() {
+        scan_stats.rows_scanned += 1;
+        let filtered = match &kept {
+            Some(kept) => Ok(kept[pos]),
+            None => filter_row(schema, item, &plan.filter, params),
+        };
+        let matches = match (filtered, corrupt_rows) {
+            (Err(DbError::DatabaseIntegrityError(_)), CorruptRowPolicy::Skip) => {
+                scan_stats.rows_skipped += 1;
+                false
+            },
+            (Err(DbError::DatabaseIntegrityError(_)), CorruptRowPolicy::Raw) => {
+                scan_stats.rows_raw += 1;
+                true
+            },
+            (res, _) => res?,
+        };
+        if matches {
+            let mut selected_row = Vec::new();
+            for proj_col in &plan.projection {
+                // FIXME: Cloning
+                selected_row.push(item.row_content.get_column(proj_col.0));
+            }
+            let projected = Row::of_columns(&selected_row);
+            rows.push(projected);
+        }
+    }
+    Ok((rows, scan_stats))
+}
+
+// Keys of the rows for each index of the table, checked against unique indexes
+fn index_keys(table_name: &str, data: &TableData, what: &[Row], column_mapping: &[usize]) -> Result<Vec<Vec<IndexKey>>, DbError> {
+    let mut index_keys = Vec::with_capacity(data.indexes.len());
+    for index in &data.indexes {
+        let keys = what.iter().map(|row| index.input_key(row, column_mapping)).collect::<Result<Vec<_>, _>>()?;
+        index.check_unique(table_name, &keys)?;
+        index_keys.push(keys);
+    }
+    Ok(index_keys)
+}
+
+// Rows changed by an update: their ids before and after, and their content before, in schema column order
+pub(crate) struct UpdatedRows {
+    pub(crate) old_ids: Vec<RowId>,
+    pub(crate) new_ids: Vec<RowId>,
+    pub(crate) old_rows: Vec<Row>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseMemory {
+    pub tables: HashMap<String, MemoryUsage>,
+    pub blobs: usize,
+}
+
+impl DatabaseMemory {
+    pub fn total(&self) -> usize {
+        self.tables.values().map(MemoryUsage::total).sum::<usize>() + self.blobs
+    }
+}
+
+// Live rows of a table and the size of its files, see `Database::table_stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    pub name: String,
+    pub rows: usize,
+    pub disk_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlanCacheStats {
+    pub plans: usize,
+    pub hits: usize,
+    pub misses: usize,
 }
 
 impl Database {
     pub fn new() -> Database {
         Database {
             schemas: HashMap::new(),
-            storage: HashMap::new(),
+            tables: HashMap::new(),
+            plans: Mutex::new(PlanCache::default()),
+            observers: Vec::new(),
+            limits: Mutex::new(LimitMonitor::default()),
+            blobs: BlobStore::default(),
+            catalog: None,
+            disk_budget: None,
+            query_memory: None,
+            maintenance: Mutex::default(),
+            stats: Mutex::default(),
+            lock_graph: Mutex::default(),
+        }
+    }
+
+    // Opens the database kept in `dir`, or starts one there. Managed tables of the directory are
+    // reopened with their indexes, the directory stays locked until the database is dropped.
+    pub fn open_dir(dir: &str) -> Result<Database, DbError> {
+        let catalog = Catalog::open(dir)?;
+        let mut db = Database::new();
+        db.blobs = BlobStore::in_dir(&std::path::Path::new(dir).join("blobs"))?;
+        for entry in &catalog.entries {
+            let path = catalog.path_of(entry);
+            let schema = Table::new(&entry.table, DiskStorage::stored_layout(&path)?);
+            let (storage, _) = DiskStorage::open(schema.clone(), &path)?;
+            db.attach_table(&schema, Box::new(storage.with_durability(entry.durability).with_compression(entry.compression)))?;
+            for index in &entry.indexes {
+                db.create_index_impl(&entry.table, &index.column, index.kind, index.unique)?;
+            }
         }
+        db.catalog = Some(catalog);
+        Ok(db)
     }
 
     pub fn new_table(&mut self, new_table: &Table, storage_cfg: StorageCfg) -> Result<(), DbError> {
+        self.validate_new_table(new_table)?;
+        let storage: Box<dyn Storage> = match storage_cfg {
+            StorageCfg::InMemory => Box::new(InMemoryStorage::new(new_table.clone())),
+            StorageCfg::Disk { path, durability, compression } => Box::new(
+                DiskStorage::new(new_table.clone(), &path)?.with_durability(durability).with_compression(compression)
+            ),
+            StorageCfg::Tiered { path, durability, compression, max_hot_rows } => Box::new(
+                TieredStorage::new(new_table.clone(), &path)?
+                    .with_durability(durability)
+                    .with_compression(compression)
+                    .with_max_hot_rows(max_hot_rows)
+            ),
+            StorageCfg::Managed { durability, compression } => return self.new_managed_table(new_table, durability, compression),
+        };
+        self.attach_table(new_table, storage)
+    }
+
+    fn new_managed_table(&mut self, new_table: &Table, durability: Durability, compression: Compression) -> Result<(), DbError> {
+        let catalog = self.catalog.as_mut()
+            .ok_or_else(|| DbError::InputError("Managed tables need a database from Database::open_dir".to_string()))?;
+        let entry = CatalogEntry { table: new_table.name.clone(), file: catalog.new_file(), durability, compression, indexes: Vec::new() };
+        let path = catalog.path_of(&entry);
+        // Left behind by a crash before the catalog listed it
+        if std::path::Path::new(&path).exists() {
+            std::fs::remove_file(&path).map_err(|err| DbError::StorageError(format!("Failed to remove stale {path}: {err}")))?;
+        }
+        let storage = DiskStorage::new(new_table.clone(), &path)?.with_durability(durability).with_compression(compression);
+        catalog.entries.push(entry);
+        catalog.save()?;
+        self.attach_table(new_table, Box::new(storage))
+    }
+
+    // Adds a table backed by a storage of the caller's, e.g. a backend from outside this crate.
+    // The storage must hold the table's columns in schema order and be empty or hold rows of this schema.
+    pub fn new_table_with_storage(&mut self, new_table: &Table, storage: Box<dyn Storage>) -> Result<(), DbError> {
+        self.validate_new_table(new_table)?;
+        self.attach_table(new_table, storage)
+    }
+
+    // Adds a table backed by an existing file, repairing rows torn by a crash
+    pub fn open_table(&mut self, table: &Table, path: &str, durability: Durability) -> Result<RecoveryReport, DbError> {
+        self.validate_new_table(table)?;
+        let (storage, report) = DiskStorage::open(table.clone(), path)?;
+        self.attach_table(table, Box::new(storage.with_durability(durability)))?;
+        Ok(report)
+    }
+
+    fn validate_new_table(&self, new_table: &Table) -> Result<(), DbError> {
         let table_name = &new_table.name;
-        if let Some(_) = self.schemas.get(table_name) {
+        if self.schemas.contains_key(table_name) {
             return Err(DbError::TableAlreadyExists(table_name.clone()));
         }
 
@@ -254,96 +625,938 @@ impl Database {
             return Err(DbError::EmptyTableSchema);
         }
 
-        self.schemas.insert(table_name.to_owned(), new_table.clone());
+        for col in &new_table.column_layout {
+            if col.collation != Collation::Binary && !matches!(col.dtype, DataType::UTF8 { .. }) {
+                return Err(DbError::InputError(format!("Collation {} requires a UTF8 column, {} is {:?}", col.collation.name(), col.name, col.dtype)));
+            }
+            if let DataType::ENUM { labels } = &col.dtype && labels.len() > MAX_ENUM_LABELS {
+                return Err(DbError::InputError(format!("ENUM {} has {} labels, more than the {MAX_ENUM_LABELS} allowed", col.name, labels.len())));
+            }
+        }
+        Ok(())
+    }
 
-        let storage: Box<dyn Storage> = match storage_cfg {
-            StorageCfg::InMemory => Box::new(InMemoryStorage::new(new_table.clone())),
-            StorageCfg::Disk { path } => Box::new(DiskStorage::new(new_table.clone(), &path)),
-        };
+    fn attach_table(&mut self, new_table: &Table, storage: Box<dyn Storage>) -> Result<(), DbError> {
+        let table_name = &new_table.name;
+        self.schemas.insert(table_name.to_owned(), new_table.clone());
 
-        let old_storage = self.storage.insert(table_name.to_owned(), storage);
+        let disk_bytes = AtomicU64::new(storage.disk_bytes());
+        let data = RwLock::new(TableData { storage, indexes: Vec::new(), quota: Quota::default(), changes: 0 });
+        let old_storage = self.tables.insert(table_name.to_owned(), TableSlot { data, disk_bytes });
         if old_storage.is_some() {
             // TODO: What to do in this case?
             return Err(DbError::TableAlreadyExists(table_name.clone()));
         }
-        return Ok(())
+        Ok(())
+    }
+
+    pub fn rename_table(&mut self, old_name: &str, new_name: &str) -> Result<(), DbError> {
+        self.rename_table_impl(old_name, new_name, None)
     }
 
-    pub fn insert(&mut self, table_name: &str, columns: &[&str], what: &[Row]) -> Result<usize, DbError> {
-        let schema = self.schema_for(&table_name)?;
+    // Same as `rename_table`, but also moves the backing file of a disk table to `new_path`
+    pub fn rename_table_with_path(&mut self, old_name: &str, new_name: &str, new_path: &str) -> Result<(), DbError> {
+        self.rename_table_impl(old_name, new_name, Some(new_path))
+    }
+
+    fn rename_table_impl(&mut self, old_name: &str, new_name: &str, new_path: Option<&str>) -> Result<(), DbError> {
+        self.schema_for(old_name)?;
+        if self.schemas.contains_key(new_name) {
+            return Err(DbError::TableAlreadyExists(new_name.to_string()));
+        }
+
+        let managed = self.catalog.as_ref().is_some_and(|catalog| catalog.contains(old_name));
+        if managed && new_path.is_some() {
+            return Err(DbError::UnsupportedOperation(format!("{old_name} is managed, its file stays in the database directory")));
+        }
+        if let Some(catalog) = self.catalog.as_mut() && managed {
+            catalog.entry_mut(old_name).unwrap().table = new_name.to_string();
+            if let Err(err) = catalog.save() {
+                catalog.entry_mut(new_name).unwrap().table = old_name.to_string();
+                return Err(err);
+            }
+        }
+
+        // A managed table's catalog entry is saved, or another's file moved, before the tables in
+        // memory change, so either failing leaves the database untouched
+        if let Some(path) = new_path {
+            self.write_table(old_name)?.storage.move_to(path)?;
+        }
+
+        self.plans().invalidate(old_name);
+        let mut schema = self.schemas.remove(old_name).unwrap();
+        let slot = self.tables.remove(old_name).unwrap();
+        schema.name = new_name.to_string();
+        self.schemas.insert(new_name.to_string(), schema);
+        self.tables.insert(new_name.to_string(), slot);
+        Ok(())
+    }
+
+    pub fn insert(&self, table_name: &str, columns: &[&str], what: &[Row]) -> Result<usize, DbError> {
+        let stored = self.insert_uncommitted(table_name, columns, what)?;
+        self.commit_storage(table_name)?;
+        Ok(stored.len())
+    }
+
+    // Changes made through the transaction are undone unless it's committed
+    pub fn begin(&self) -> Transaction<'_> {
+        self.begin_with(IsolationLevel::default())
+    }
+
+    // Transaction seeing, and letting others see, as much as `isolation` allows, see `IsolationLevel`
+    pub fn begin_with(&self, isolation: IsolationLevel) -> Transaction<'_> {
+        Transaction::new(self, isolation)
+    }
+
+    // Writes to apply together with `WriteBatch::apply`, see `WriteBatch`
+    pub fn batch(&self) -> WriteBatch<'_> {
+        WriteBatch::new(self)
+    }
+
+    // Keeps other threads from writing the table, or from reading it too, until the lock is
+    // dropped. The holder reads and writes the table through the lock, see `TableLock`.
+    pub fn lock_table(&self, table_name: &str, mode: LockMode) -> Result<TableLock<'_>, DbError> {
+        let held = match mode {
+            LockMode::Shared => HeldLock::Shared(self.read_table(table_name)?),
+            LockMode::Exclusive => HeldLock::Exclusive(self.write_table(table_name)?),
+        };
+        Ok(TableLock::new(self, table_name, held))
+    }
+
+    // Insert as one batch of a larger write, that commits once all batches are stored
+    pub(crate) fn insert_uncommitted(&self, table_name: &str, columns: &[&str], what: &[Row]) -> Result<Vec<RowId>, DbError> {
+        let mut data = self.write_table(table_name)?;
+        self.insert_into(table_name, &mut data, columns, what)
+    }
+
+    pub(crate) fn insert_into(&self, table_name: &str, data: &mut TableData, columns: &[&str], what: &[Row]) -> Result<Vec<RowId>, DbError> {
+        let schema = self.schema_for(table_name)?;
         let column_mapping = schema.project_from_schema(columns)?;
 
+        // Enum columns are given as labels, but stored as their index
+        let encoded = schema.encode_enums(what, &column_mapping)?;
+        let what = encoded.as_deref().unwrap_or(what);
+
         for row in what.iter().cloned() {
             schema.validate_input(&row, &column_mapping)?;
         }
 
-        let storage = self.mut_storage_for(&table_name)?;
-        storage.store(&what, &column_mapping);
-        
-        // Maybe return it from storage?
-        let stored = what.len();
-        Ok(stored)
+        let mut limits = self.limits();
+        if let Some(soft) = limits.soft.row_size_threshold(schema.max_row_size) {
+            let largest = what.iter().map(|row| row.data.len()).max().unwrap_or(0);
+            if largest > soft {
+                limits.report(LimitWarning { limit: Limit::RowSize, table: table_name.to_string(), value: largest, soft, hard: Some(schema.max_row_size) });
+            }
+        }
+        drop(limits);
+
+        self.store_validated(table_name, data, what, column_mapping)
     }
 
-    pub fn select(&self, values: &[Value], table: &str, filter: &Bool) -> Result<ResultSet, DbError> {
-        let schema = self.schema_for(&table)?;
-        let storage = self.storage_for(&table)?;
-
-        // Validate and project columns
-        let mut result_columns = Vec::with_capacity(values.len());
-        for val in values {
-            if let Value::ColumnRef(col_name) = val {
-                #[allow(suspicious_double_ref_op)]
-                result_columns.push(col_name.clone());
-            } else {
-                return Err(DbError::UnsupportedOperation(format!("Selecting values other than column references not supported {:?}", val)));
+    // Stores rows that passed validation, with enums encoded, and keeps indexes and observers up to date
+    fn store_validated(&self, table_name: &str, data: &mut TableData, what: &[Row], column_mapping: Vec<usize>) -> Result<Vec<RowId>, DbError> {
+        // Keys are computed up front, so a row the index can't take is rejected before storing
+        let index_keys = index_keys(table_name, data, what, &column_mapping)?;
+        self.enforce_quotas(table_name, data, what)?;
+        self.store_keyed(table_name, data, what, column_mapping, index_keys)
+    }
+
+    fn store_keyed(&self, table_name: &str, data: &mut TableData, what: &[Row], column_mapping: Vec<usize>, index_keys: Vec<Vec<IndexKey>>) -> Result<Vec<RowId>, DbError> {
+        let row_ids = data.storage.store(what, &column_mapping);
+        data.changes += 1;
+        for (index, keys) in data.indexes.iter_mut().zip(index_keys) {
+            for (key, row_id) in keys.into_iter().zip(&row_ids) {
+                index.insert(key, *row_id);
             }
         }
+        for observer in &self.observers {
+            observer.on_store(table_name, &row_ids, what, &column_mapping);
+        }
+        Ok(row_ids)
+    }
 
-        let result_mapping = schema.project_to_schema(&result_columns)?;
-        let filter_columns = crate::query::collect_filter_columns(&filter);
-        // TODO: Mapping of filters to column IDs is unused. Internally this will use string mapping.
-        // Validate filter columns
-        schema.project_to_schema(&filter_columns)?;
-    
-        // Filter and map rows
+    pub fn select(&self, values: &[Value], table: &str, filter: &Bool) -> Result<ResultSet, DbError> {
+        self.select_with_options(values, table, filter, &QueryOptions::default())
+    }
+
+    pub fn select_with_options(&self, values: &[Value], table: &str, filter: &Bool, options: &QueryOptions) -> Result<ResultSet, DbError> {
+        self.select_from(values, table, TableRead::Locked(self.read_table(table)?), filter, options)
+    }
+
+    pub(crate) fn select_from(&self, values: &[Value], table: &str, data: TableRead, filter: &Bool, options: &QueryOptions) -> Result<ResultSet, DbError> {
         let mut rows = Vec::new();
-        for item in storage.scan() {
-            if filter_row(&schema, &item, &filter)? {
-                let mut selected_row = Vec::new();
-                for proj_col in &result_mapping {
-                    // FIXME: Cloning
-                    selected_row.push(item.row_content.get_column(proj_col.0));
+        let mut memory = QueryMemory::new(options.max_memory.or(self.query_memory), table, &self.limits);
+        let (plan, scan_stats) = self.scan_select(values, table, data, filter, options, &mut |matched| {
+            memory.charge(&matched)?;
+            rows.extend(matched);
+            Ok(())
+        })?;
+        Ok(self.result_set(table, &plan, rows, scan_stats))
+    }
+
+    // Select whose rows are moved to a temporary file once they take up `max_bytes` in memory,
+    // and read back as the stream is consumed
+    pub fn select_stream(&self, values: &[Value], table: &str, filter: &Bool, options: &QueryOptions, max_bytes: usize) -> Result<ResultStream, DbError> {
+        let mut rows = SpillingRows::new(max_bytes);
+        let data = TableRead::Locked(self.read_table(table)?);
+        let (plan, scan_stats) = self.scan_select(values, table, data, filter, options, &mut |matched| {
+            matched.into_iter().try_for_each(|row| rows.push(row))
+        })?;
+        self.report_result_rows(table, rows.len());
+        rows.finish(plan.projection.iter().map(|col| col.1.clone()).collect(), scan_stats)
+    }
+
+    // Hands the projected rows passing `filter` to `out`, a batch at a time in scan order
+    fn scan_select(&self, values: &[Value], table: &str, data: TableRead, filter: &Bool, options: &QueryOptions, out: &mut dyn FnMut(Vec<Row>) -> Result<(), DbError>) -> Result<(Arc<Plan>, ScanStats), DbError> {
+        let schema = self.schema_for(table)?;
+
+        // Validate and project columns, reusing the plan of an earlier query of the same shape
+        let shape = query_shape("select", values, filter);
+        let plan = telemetry::in_span("plan", || self.plans().get_or_plan(table, shape, || Plan::for_select(schema, values, filter)))?;
+        let mut params = Vec::new();
+        collect_params(filter, &mut params);
+        let _scan = telemetry::span("scan");
+
+        // Only rows found through an index are loaded, if one applies to the filter
+        let candidates = index::candidates(&data.indexes, &plan.filter, &params);
+        // A full scan reads a snapshot if the storage takes them, writers don't wait for it
+        let snapshot = match (&candidates, &data) {
+            (None, TableRead::Locked(locked)) => locked.storage.snapshot(),
+            _ => None,
+        };
+        let held;
+        let storage: &dyn Storage = match &snapshot {
+            Some(snapshot) => {
+                drop(data);
+                snapshot.as_ref()
+            },
+            None => {
+                held = data;
+                held.storage.as_ref()
+            },
+        };
+        // Otherwise an equality lets the storage skip data that can't hold the key
+        let batches = match (&candidates, index::equality_key(&plan.filter, &params)) {
+            (Some(row_ids), _) => RowBatch::batches(storage.get_rows(row_ids), SCAN_BATCH_ROWS),
+            (None, Some((column_idx, key))) => RowBatch::batches(storage.scan_maybe_equal(column_idx, key.stable_hash()), SCAN_BATCH_ROWS),
+            (None, None) => storage.scan_batches(SCAN_BATCH_ROWS),
+        };
+
+        // Filter and map rows, large full scans in batches spread over threads
+        let threads = match options.parallelism {
+            0 => std::thread::available_parallelism().map_or(1, usize::from),
+            threads => threads,
+        };
+        let mut scan_stats = ScanStats::default();
+        let mut batches = batches.peekable();
+        if threads > 1 && candidates.is_none() && storage.row_count() >= PARALLEL_SCAN_ROWS {
+            let plan: &Plan = &plan;
+            let params = params.as_slice();
+            while batches.peek().is_some() {
+                CancellationToken::check(options.cancel.as_ref())?;
+                let round: Vec<RowBatch> = batches.by_ref().take(threads * BATCHES_PER_THREAD).collect();
+                let results: Vec<_> = std::thread::scope(|scope| {
+                    let workers: Vec<_> = round.chunks(BATCHES_PER_THREAD)
+                        .map(|share| scope.spawn(|| share.iter().map(|batch| filter_and_project(schema, batch, plan, params, options.corrupt_rows)).collect::<Vec<_>>()))
+                        .collect();
+                    workers.into_iter().flat_map(|worker| worker.join().expect("Scan thread panicked")).collect()
+                });
+                for result in results {
+                    let (matched, stats) = result?;
+                    scan_stats.add(stats);
+                    out(matched)?;
                 }
-                let projected = Row::of_columns(&selected_row);
-                rows.push(projected);
+            }
+        } else {
+            for batch in batches {
+                CancellationToken::check(options.cancel.as_ref())?;
+                let (matched, stats) = filter_and_project(schema, &batch, &plan, &params, options.corrupt_rows)?;
+                scan_stats.add(stats);
+                out(matched)?;
             }
         }
+        Ok((plan, scan_stats))
+    }
 
-        let result_schema: Vec<Column> = result_mapping.iter()
+    fn result_set(&self, table: &str, plan: &Plan, rows: Vec<Row>, scan_stats: ScanStats) -> ResultSet {
+        let result_schema: Vec<Column> = plan.projection.iter()
             .map(|col| col.1.clone())
             .collect();
-        Ok(ResultSet { data: rows, schema: result_schema})
+        self.report_result_rows(table, rows.len());
+        ResultSet { data: rows, schema: result_schema, scan_stats }
+    }
+
+    fn report_result_rows(&self, table: &str, rows: usize) {
+        let mut limits = self.limits();
+        if let Some(soft) = limits.soft.result_rows && rows > soft {
+            limits.report(LimitWarning { limit: Limit::ResultRows, table: table.to_string(), value: rows, soft, hard: None });
+        }
+    }
+
+    // Whether `select_async` can await the table's reads rather than block on them
+    #[cfg(feature = "async")]
+    pub(crate) fn reads_async(&self, table: &str) -> Result<bool, DbError> {
+        Ok(self.read_table(table)?.storage.as_async().is_some())
     }
 
-    pub fn delete(&mut self, table_name: &str, filter: &Bool) -> Result<usize, DbError> {
+    // Select that awaits the reads of a full scan instead of blocking on them.
+    // Lookups through an index and tables without async reads are served as by `select_with_options`.
+    #[cfg(feature = "async")]
+    pub async fn select_async(&self, values: &[Value<'_>], table: &str, filter: &Bool<'_>, options: &QueryOptions) -> Result<ResultSet, DbError> {
+        let schema = self.schema_for(table)?;
+        let shape = query_shape("select", values, filter);
+        let plan = self.plans().get_or_plan(table, shape, || Plan::for_select(schema, values, filter))?;
+        let mut params = Vec::new();
+        collect_params(filter, &mut params);
+
+        let scan = {
+            let data = self.read_table(table)?;
+            let async_storage = data.storage.as_async().filter(|_| index::candidates(&data.indexes, &plan.filter, &params).is_none());
+            async_storage.map(|storage| storage.scan_async())
+        };
+        let Some(mut scan) = scan else {
+            return self.select_with_options(values, table, filter, options);
+        };
+
+        // The table is only locked to decode what was read, writes go ahead while the scan awaits
+        let mut rows = Vec::new();
+        let mut scan_stats = ScanStats::default();
+        let mut memory = QueryMemory::new(options.max_memory.or(self.query_memory), table, &self.limits);
+        while let Some(read) = scan.next_read().await? {
+            CancellationToken::check(options.cancel.as_ref())?;
+            let data = self.read_table(table)?;
+            let async_storage = data.storage.as_async().ok_or_else(|| DbError::StorageError(format!("{table} no longer reads async")))?;
+            match async_storage.rows_of(read)? {
+                Ok(batch) => {
+                    let (matched, stats) = filter_and_project(schema, &RowBatch::new(batch), &plan, &params, options.corrupt_rows)?;
+                    memory.charge(&matched)?;
+                    rows.extend(matched);
+                    scan_stats.add(stats);
+                },
+                Err(stale) => scan.retry(stale),
+            }
+        }
+        Ok(self.result_set(table, &plan, rows, scan_stats))
+    }
+
+    pub fn delete(&self, table_name: &str, filter: &Bool) -> Result<usize, DbError> {
+        let deleted = self.delete_matching(table_name, &mut *self.write_table(table_name)?, filter)?;
+        self.commit_storage(table_name)?;
+        Ok(deleted)
+    }
+
+    pub(crate) fn delete_matching(&self, table_name: &str, data: &mut TableData, filter: &Bool) -> Result<usize, DbError> {
         let schema = self.schema_for(table_name)?;
 
         // Validate filter columns
-        let filter_columns = crate::query::collect_filter_columns(filter);
-        schema.project_to_schema(&filter_columns)?;
+        let shape = query_shape("delete", &[], filter);
+        let plan = self.plans().get_or_plan(table_name, shape, || Plan::for_filter(schema, filter))?;
+        let mut params = Vec::new();
+        collect_params(filter, &mut params);
 
-        // Filter rows to remove
-        let mut to_remove: Vec<RowId> = Vec::new();
-        for item in self.storage_for(table_name)?.scan() {
-            if filter_row(&schema, &item, &filter)? { to_remove.push(item.row_id); }
+        // The storage filters and removes rows in the same scan
+        let mut removed = data.storage.delete_where(&mut |item| filter_row(schema, item, &plan.filter, &params))?;
+        removed.sort_unstable();
+        self.forget_deleted(table_name, data, &removed);
+        Ok(removed.len())
+    }
+
+    // Ids of the rows passing `filter`, in scan order
+    fn matching_ids(&self, table_name: &str, data: &TableData, statement: &str, filter: &Bool) -> Result<Vec<RowId>, DbError> {
+        let schema = self.schema_for(table_name)?;
+
+        // Validate filter columns
+        let shape = query_shape(statement, &[], filter);
+        let plan = self.plans().get_or_plan(table_name, shape, || Plan::for_filter(schema, filter))?;
+        let mut params = Vec::new();
+        collect_params(filter, &mut params);
+
+        let mut matching = Vec::new();
+        for item in data.storage.scan() {
+            if filter_row(schema, &item, &plan.filter, &params)? { matching.push(item.row_id); }
+        }
+        Ok(matching)
+    }
+
+    // Whether each of `rows`, in schema column order, passes `filter` as the table's rows do
+    pub fn filter_rows(&self, table_name: &str, filter: &Bool, rows: &[Row]) -> Result<Vec<bool>, DbError> {
+        let schema = self.schema_for(table_name)?;
+        let shape = query_shape("filter", &[], filter);
+        let plan = self.plans().get_or_plan(table_name, shape, || Plan::for_filter(schema, filter))?;
+        let mut params = Vec::new();
+        collect_params(filter, &mut params);
+
+        rows.iter().zip(0..).map(|(row, row_id)| {
+            let row_content = RowContent { data: Cow::Borrowed(&row.data), offsets: Cow::Borrowed(&row.offsets) };
+            filter_row(schema, &ScanItem { row_id, row_content }, &plan.filter, &params)
+        }).collect()
+    }
+
+    // Sets `columns` to `values` in the rows passing `filter`. Tables with only fixed-width columns
+    // are updated in place when the storage supports it, otherwise rows are deleted and stored
+    // again under new ids. Updates don't add rows, so they aren't held to quotas.
+    pub fn update(&self, table_name: &str, columns: &[&str], values: &Row, filter: &Bool) -> Result<usize, DbError> {
+        let updated = self.update_uncommitted(table_name, columns, values, filter)?;
+        self.commit_storage(table_name)?;
+        Ok(updated.new_ids.len())
+    }
+
+    pub(crate) fn update_uncommitted(&self, table_name: &str, columns: &[&str], values: &Row, filter: &Bool) -> Result<UpdatedRows, DbError> {
+        let mut data = self.write_table(table_name)?;
+        self.update_in(table_name, &mut data, columns, values, filter)
+    }
+
+    pub(crate) fn update_in(&self, table_name: &str, data: &mut TableData, columns: &[&str], values: &Row, filter: &Bool) -> Result<UpdatedRows, DbError> {
+        let schema = self.schema_for(table_name)?;
+        if values.offsets.len() - 1 != columns.len() {
+            return Err(DbError::InvalidColumnCount { expected: columns.len(), got: values.offsets.len() - 1 });
+        }
+        // Schema position and stored bytes of each assigned column, enums are given as labels
+        let mut assigned: Vec<(usize, Vec<u8>)> = Vec::with_capacity(columns.len());
+        for (input_idx, name) in columns.iter().enumerate() {
+            let (schema_idx, col) = schema.require_column(name)?;
+            let value = values.get_column(input_idx);
+            let value = match &col.dtype {
+                DataType::ENUM { labels } => encode_enum_label(labels, value)
+                    .ok_or_else(|| DbError::InvalidEnumLabel { column: col.name.clone(), label: String::from_utf8_lossy(value).into_owned() })?,
+                _ => value.to_vec(),
+            };
+            assigned.push((schema_idx, value));
+        }
+
+        let mut row_ids = self.matching_ids(table_name, data, "update", filter)?;
+        row_ids.sort_unstable();
+        let identity: Vec<usize> = (0..schema.column_layout.len()).collect();
+        let mut new_rows = Vec::with_capacity(row_ids.len());
+        let mut old_rows = Vec::with_capacity(row_ids.len());
+        for item in data.storage.get_rows(&row_ids) {
+            let mut row: Vec<&[u8]> = (0..identity.len()).map(|idx| item.row_content.get_column(idx)).collect();
+            old_rows.push(Row::of_columns(&row));
+            for (schema_idx, value) in &assigned {
+                row[*schema_idx] = value;
+            }
+            let row = Row::of_columns(&row);
+            schema.validate_input(&row, &identity)?;
+            new_rows.push(row);
+        }
+
+        // Keys may move between the updated rows, but not onto a key of a row left as it is
+        let mut index_keys = Vec::with_capacity(data.indexes.len());
+        for index in &data.indexes {
+            let keys = new_rows.iter().map(|row| index.input_key(row, &identity)).collect::<Result<Vec<_>, _>>()?;
+            if index.unique {
+                let mut seen = std::collections::HashSet::new();
+                for key in &keys {
+                    let taken = index.get(key).iter().any(|id| row_ids.binary_search(id).is_err());
+                    if taken || !seen.insert(key) {
+                        return Err(DbError::DuplicateKey { index: format!("{table_name}.{}", index.column.name), key: key.clone() });
+                    }
+                }
+            }
+            index_keys.push(keys);
+        }
+
+        let fixed_width = schema.column_layout.iter().all(|col| col.dtype.min_size() == col.dtype.max_size());
+        let in_place = if fixed_width {
+            let replacements: Vec<(RowId, Row)> = row_ids.iter().copied().zip(new_rows.iter().cloned()).collect();
+            match data.storage.overwrite_rows(&replacements) {
+                Ok(()) => true,
+                Err(DbError::UnsupportedOperation(_)) => false,
+                Err(err) => return Err(err),
+            }
+        } else {
+            false
+        };
+
+        let old_ids = row_ids.clone();
+        let new_ids = if in_place {
+            for (index, keys) in data.indexes.iter_mut().zip(index_keys) {
+                index.remove(&row_ids);
+                for (key, row_id) in keys.into_iter().zip(&row_ids) {
+                    index.insert(key, *row_id);
+                }
+            }
+            data.changes += 1;
+            for observer in &self.observers {
+                observer.on_update(table_name, &row_ids, &new_rows);
+            }
+            row_ids
+        } else {
+            self.remove_ids(table_name, data, row_ids);
+            self.store_keyed(table_name, data, &new_rows, identity, index_keys)?
+        };
+        Ok(UpdatedRows { old_ids, new_ids, old_rows })
+    }
+
+    // Stores rows as they were before a change is undone, in schema column order.
+    // They passed validation and quotas when first stored, only unique keys are checked again.
+    pub(crate) fn restore_rows(&self, table_name: &str, data: &mut TableData, rows: &[Row]) -> Result<Vec<RowId>, DbError> {
+        let identity: Vec<usize> = (0..self.schema_for(table_name)?.column_layout.len()).collect();
+        let index_keys = index_keys(table_name, data, rows, &identity)?;
+        self.store_keyed(table_name, data, rows, identity, index_keys)
+    }
+
+    // Rejects rows that would take the table over its quota or the database over its disk budget,
+    // or evicts old rows to make room for them
+    fn enforce_quotas(&self, table_name: &str, data: &mut TableData, what: &[Row]) -> Result<(), DbError> {
+        let quota = data.quota;
+        let storage = &data.storage;
+        let exceeded = |quota: QuotaKind, max: u64, got: u64| DbError::QuotaExceeded { table: table_name.to_string(), quota, max, got };
+        let incoming = what.iter().map(|row| row.data.len() as u64).sum::<u64>();
+
+        if let Some(max) = quota.max_bytes {
+            let usage = storage.memory_usage();
+            let got = storage.disk_bytes() + (usage.data + usage.offsets) as u64 + incoming;
+            if got > max {
+                return Err(exceeded(QuotaKind::Bytes, max, got));
+            }
+            self.limits().check(Limit::TableQuota, table_name, got as usize, max as usize);
+        }
+        if let Some(budget) = self.disk_budget && storage.disk_bytes() > 0 {
+            // Other tables as of their last write, they aren't locked
+            let others = self.tables.iter()
+                .filter(|(name, _)| name.as_str() != table_name)
+                .map(|(_, slot)| slot.disk_bytes.load(Ordering::Relaxed))
+                .sum::<u64>();
+            let got = others + storage.disk_bytes() + incoming;
+            if got > budget {
+                return Err(exceeded(QuotaKind::DiskBudget, budget, got));
+            }
+            self.limits().check(Limit::DiskBudget, table_name, got as usize, budget as usize);
+        }
+        let Some(max) = quota.max_rows else { return Ok(()) };
+        let got = storage.row_count() + what.len();
+        if got <= max {
+            self.limits().check(Limit::TableQuota, table_name, got, max);
+            return Ok(());
+        }
+        if quota.policy == QuotaPolicy::Reject || what.len() > max {
+            return Err(exceeded(QuotaKind::Rows, max as u64, got as u64));
+        }
+        let oldest: Vec<RowId> = storage.scan().take(got - max).map(|item| item.row_id).collect();
+        self.remove_ids(table_name, data, oldest);
+        Ok(())
+    }
+
+    // `to_remove` must be sorted
+    pub(crate) fn remove_ids(&self, table_name: &str, data: &mut TableData, to_remove: Vec<RowId>) {
+        data.storage.delete_rows(to_remove.clone());
+        self.forget_deleted(table_name, data, &to_remove);
+    }
+
+    // Deletes the rows passing `filter`, returning their sorted ids and their content in schema column order
+    pub(crate) fn take_matching(&self, table_name: &str, data: &mut TableData, filter: &Bool) -> Result<(Vec<RowId>, Vec<Row>), DbError> {
+        let mut ids = self.matching_ids(table_name, data, "delete", filter)?;
+        ids.sort_unstable();
+        let rows = data.storage.get_rows(&ids)
+            .map(|item| Row { data: item.row_content.data.to_vec(), offsets: item.row_content.offsets.to_vec() })
+            .collect();
+        self.remove_ids(table_name, data, ids.clone());
+        Ok((ids, rows))
+    }
+
+    // Takes deleted rows out of indexes and tells observers, `removed` must be sorted
+    fn forget_deleted(&self, table_name: &str, data: &mut TableData, removed: &[RowId]) {
+        data.changes += 1;
+        for index in &mut data.indexes {
+            index.remove(removed);
+        }
+        for observer in &self.observers {
+            observer.on_delete(table_name, removed);
+        }
+    }
+
+    // A sync shared with other commits is waited for once the table is unlocked, so the writers
+    // it covers could commit meanwhile. The observers see the commit before it's durable in the
+    // table, a WAL among them is synced first.
+    pub(crate) fn commit_storage(&self, table_name: &str) -> Result<(), DbError> {
+        let pending = self.write_table(table_name)?.storage.commit_grouped()?;
+        self.notify_commit(table_name);
+        pending.map_or(Ok(()), PendingSync::wait)
+    }
+
+    // The table's rows as they are now, for reads that shouldn't see later writes. Indexes
+    // aren't copied, the copy is scanned. None if the storage can't take snapshots.
+    pub(crate) fn snapshot_table(&self, table_name: &str) -> Result<Option<TableData>, DbError> {
+        let data = self.read_table(table_name)?;
+        Ok(data.storage.snapshot().map(|storage| TableData { storage, indexes: Vec::new(), quota: data.quota, changes: data.changes }))
+    }
+
+    pub(crate) fn notify_commit(&self, table_name: &str) {
+        for observer in &self.observers {
+            observer.on_commit(table_name);
+        }
+    }
+
+    pub fn compact(&self, table_name: &str) -> Result<(), DbError> {
+        let mut data = self.write_table(table_name)?;
+        self.compact_in(table_name, &mut data);
+        Ok(())
+    }
+
+    fn compact_in(&self, table_name: &str, data: &mut TableData) {
+        data.storage.compact();
+        for observer in &self.observers {
+            observer.on_compact(table_name);
+        }
+    }
+
+    // Makes every table durable up to the log's last record, then drops the records up to it from
+    // the log. Gives the checkpoint's sequence, backups taken from then on roll forward from it.
+    // Fails for databases with tables that live only in memory, the log has their only copy.
+    pub fn checkpoint(&self, wal: &WalWriter) -> Result<u64, DbError> {
+        self.checkpoint_impl(wal, true).map(|sequence| sequence.expect("Waited for every table"))
+    }
+
+    // None if `wait` is off and a table is locked
+    fn checkpoint_impl(&self, wal: &WalWriter, wait: bool) -> Result<Option<u64>, DbError> {
+        // Records after it may have changes that aren't synced below
+        let sequence = wal.last_sequence();
+        for table in self.tables.keys() {
+            let mut data = if wait { self.write_table(table)? } else {
+                let Some(data) = self.try_write_table(table)? else { return Ok(None) };
+                data
+            };
+            data.storage.sync()?;
+        }
+        wal.checkpoint(sequence)?;
+        Ok(Some(sequence))
+    }
+
+    // Rolls the tables of a base backup, taken at WAL sequence `after`, forward to `target`
+    pub fn recover_from_wal(&mut self, wal_path: &str, after: u64, target: RecoveryTarget) -> Result<WalReplay, DbError> {
+        let mut replay = WalReplay { records_applied: 0, last_sequence: after };
+        // New ids come after every id of the backup, so unmapped ids are the backup's own
+        let mut new_ids: HashMap<String, HashMap<RowId, RowId>> = HashMap::new();
+        for record in read_wal(wal_path)? {
+            if let WalChange::Checkpoint = record.change {
+                // The records from the backup on up to the checkpoint aren't in the log anymore
+                if record.sequence > after {
+                    return Err(DbError::InputError(format!("Backup at WAL sequence {after} predates checkpoint {}", record.sequence)));
+                }
+                continue;
+            }
+            if record.sequence <= after {
+                continue;
+            }
+            if !target.includes(&record) {
+                break;
+            }
+            self.apply_logged(&record.table, record.change, new_ids.entry(record.table.clone()).or_default())?;
+            replay.records_applied += 1;
+            replay.last_sequence = record.sequence;
+        }
+        Ok(replay)
+    }
+
+    // Applies a change logged by this database earlier, or by another one, see `replication`.
+    // Stored rows get new ids, which `new_ids` maps the logged ones to, so later deletes in the log
+    // find them. Unmapped ids are taken as this table's own.
+    pub(crate) fn apply_logged(&self, table_name: &str, change: WalChange, new_ids: &mut HashMap<RowId, RowId>) -> Result<(), DbError> {
+        let (removed, stored) = match change {
+            WalChange::Store { row_ids, rows } => (Vec::new(), Some((row_ids, rows))),
+            WalChange::Delete { row_ids } => (row_ids, None),
+            // Stored anew, with new ids
+            WalChange::Update { row_ids, rows } => (row_ids.clone(), Some((row_ids, rows))),
+            WalChange::Checkpoint => return Ok(()),
+        };
+        let mut data = self.write_table(table_name)?;
+        if !removed.is_empty() {
+            let mut to_remove: Vec<RowId> = removed.into_iter().map(|id| new_ids.remove(&id).unwrap_or(id)).collect();
+            to_remove.sort();
+            self.remove_ids(table_name, &mut data, to_remove);
+        }
+        if let Some((row_ids, rows)) = stored {
+            let column_mapping = (0..self.schema_for(table_name)?.column_layout.len()).collect();
+            let stored = self.store_validated(table_name, &mut data, &rows, column_mapping)?;
+            new_ids.extend(row_ids.into_iter().zip(stored));
+        }
+        data.storage.commit()
+    }
+
+    // The table's live rows in schema column order, with their ids, and what `at` gives while no
+    // write can change them, e.g. the last sequence of a change feed
+    pub(crate) fn rows_with_ids<T>(&self, table_name: &str, at: impl FnOnce() -> T) -> Result<(T, Vec<RowId>, Vec<Row>), DbError> {
+        let data = self.read_table(table_name)?;
+        let (row_ids, rows) = data.storage.scan()
+            .map(|item| (item.row_id, Row { data: item.row_content.data.to_vec(), offsets: item.row_content.offsets.to_vec() }))
+            .unzip();
+        Ok((at(), row_ids, rows))
+    }
+
+    // Full row in schema column order, or None if no live row has the id
+    pub fn get_by_id(&self, table_name: &str, row_id: RowId) -> Result<Option<Row>, DbError> {
+        let data = self.read_table(table_name)?;
+        let item = data.storage.get_row(row_id);
+        Ok(item.map(|item| Row { data: item.row_content.data.to_vec(), offsets: item.row_content.offsets.to_vec() }))
+    }
+
+    // Builds the index from the rows already in the table, later changes keep it up to date
+    pub fn create_index(&mut self, table_name: &str, column: &str, kind: IndexKind) -> Result<(), DbError> {
+        self.create_index_impl(table_name, column, kind, false)
+    }
+
+    // Same as `create_index`, but inserts of a key that's already present fail with `DuplicateKey`
+    pub fn create_unique_index(&mut self, table_name: &str, column: &str, kind: IndexKind) -> Result<(), DbError> {
+        self.create_index_impl(table_name, column, kind, true)
+    }
+
+    fn create_index_impl(&mut self, table_name: &str, column: &str, kind: IndexKind, unique: bool) -> Result<(), DbError> {
+        let schema = self.schema_for(table_name)?;
+        let (column_idx, col) = schema.require_column(column)?;
+        let mut data = self.write_table(table_name)?;
+        if data.indexes.iter().any(|index| index.column.name == column) {
+            return Err(DbError::IndexAlreadyExists(format!("{table_name}.{column}")));
+        }
+
+        let mut index = if unique { Index::new_unique(kind, col.clone(), column_idx) } else { Index::new(kind, col.clone(), column_idx) };
+        for item in data.storage.scan() {
+            let key = index.key_of(item.row_content.get_column(column_idx))?;
+            index.check_unique(table_name, [&key])?;
+            index.insert(key, item.row_id);
+        }
+        data.indexes.push(index);
+        drop(data);
+        if let Some(catalog) = self.catalog.as_mut() && let Some(entry) = catalog.entry_mut(table_name) {
+            entry.indexes.push(IndexDef { column: column.to_string(), kind, unique });
+            catalog.save()?;
+        }
+        Ok(())
+    }
+
+    // Per-segment Bloom filter on the column, so equality filters skip segments without the key
+    pub fn create_bloom_filter(&self, table_name: &str, column: &str) -> Result<(), DbError> {
+        let (column_idx, _) = self.schema_for(table_name)?.require_column(column)?;
+        self.write_table(table_name)?.storage.add_bloom_filter(column_idx)
+    }
+
+    // Rebuilds every index of the table from its data, reporting what the old indexes got wrong.
+    // Nothing is replaced if any index can't be rebuilt, e.g. a unique index over duplicates.
+    pub fn reindex(&self, table_name: &str) -> Result<Vec<IndexCheck>, DbError> {
+        let mut data = self.write_table(table_name)?;
+
+        let mut rebuilt = Vec::with_capacity(data.indexes.len());
+        let mut checks = Vec::with_capacity(data.indexes.len());
+        for index in &data.indexes {
+            let mut fresh = index.empty_copy();
+            for item in data.storage.scan() {
+                let key = fresh.key_of(item.row_content.get_column(fresh.column_idx))?;
+                fresh.check_unique(table_name, [&key])?;
+                fresh.insert(key, item.row_id);
+            }
+            let (missing, stale) = index.diff(&fresh);
+            checks.push(IndexCheck { column: index.column.name.clone(), missing, stale });
+            rebuilt.push(fresh);
+        }
+        data.indexes = rebuilt;
+        Ok(checks)
+    }
+
+    pub fn drop_index(&mut self, table_name: &str, column: &str) -> Result<(), DbError> {
+        let not_found = || DbError::IndexNotFound(format!("{table_name}.{column}"));
+        let mut data = self.write_table(table_name).map_err(|_| not_found())?;
+        let position = data.indexes.iter().position(|index| index.column.name == column).ok_or_else(not_found)?;
+        data.indexes.remove(position);
+        drop(data);
+        if let Some(catalog) = self.catalog.as_mut() && let Some(entry) = catalog.entry_mut(table_name) {
+            entry.indexes.retain(|index| index.column != column);
+            catalog.save()?;
         }
+        Ok(())
+    }
+
+    // Takes `&mut self` to hand out the index without holding the table's lock
+    pub fn index_for(&mut self, table_name: &str, column: &str) -> Option<&Index> {
+        let data = self.tables.get_mut(table_name)?.data.get_mut().unwrap_or_else(PoisonError::into_inner);
+        data.indexes.iter().find(|index| index.column.name == column)
+    }
+
+    // Stores a large binary value chunk by chunk, without buffering it whole
+    pub fn put_blob(&self, source: &mut dyn std::io::Read) -> Result<BlobId, DbError> {
+        self.blobs.put(source)
+    }
+
+    // A blob written as its chunks come, e.g. in frames, stored once given to `finish_blob`
+    pub fn create_blob(&self) -> Result<BlobWriter, DbError> {
+        self.blobs.create()
+    }
+
+    pub fn finish_blob(&self, writer: BlobWriter) -> Result<BlobId, DbError> {
+        self.blobs.finish(writer)
+    }
+
+    pub fn get_blob_reader(&self, id: BlobId) -> Result<BlobReader, DbError> {
+        self.blobs.reader(id)
+    }
+
+    pub fn blob_len(&self, id: BlobId) -> Result<usize, DbError> {
+        self.blobs.len(id)
+    }
+
+    pub fn delete_blob(&self, id: BlobId) -> Result<(), DbError> {
+        self.blobs.delete(id)
+    }
+
+    // Replaces the table's quota, rows already over it stay
+    pub fn set_quota(&mut self, table_name: &str, quota: Quota) -> Result<(), DbError> {
+        self.write_table(table_name)?.quota = quota;
+        Ok(())
+    }
+
+    // Limit on the bytes of all table files together, None for no limit
+    pub fn set_disk_budget(&mut self, budget: Option<u64>) {
+        self.disk_budget = budget;
+    }
+
+    // Runs maintenance on a worker thread every `config.interval`, replacing the previous worker if any
+    pub fn start_maintenance(self: &Arc<Self>, config: MaintenanceConfig) -> Result<MaintenanceHandle, DbError> {
+        for ttl in &config.ttls {
+            let column = self.schema_for(&ttl.table)?.column_layout.iter().find(|col| col.name == ttl.column)
+                .ok_or_else(|| DbError::ColumnNotFound(ttl.column.clone()))?;
+            if column.dtype != DataType::U32 {
+                return Err(DbError::InputError(format!("TTL column {} must be U32 seconds, it's {:?}", ttl.column, column.dtype)));
+            }
+        }
+        let worker = Worker::start(config, Arc::downgrade(self));
+        let handle = worker.handle();
+        let previous = self.maintenance.lock().unwrap_or_else(PoisonError::into_inner).replace(worker);
+        // Waits for a run of the previous worker, outside the lock
+        drop(previous);
+        Ok(handle)
+    }
+
+    // Runs the maintenance now, whether or not a worker is scheduled. Tables locked at the time
+    // are left for a later run, their lock may be held by the caller.
+    pub fn run_maintenance(&self, config: &MaintenanceConfig) -> Result<MaintenanceReport, DbError> {
+        let mut report = MaintenanceReport::default();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        for ttl in &config.ttls {
+            let Some(mut data) = self.try_write_table(&ttl.table)? else { continue };
+            let cutoff = now.saturating_sub(ttl.max_age).as_secs().min(u32::MAX as u64) as u32;
+            report.rows_expired += self.delete_matching(&ttl.table, &mut data, &Bool::Lt(Value::ColumnRef(&ttl.column), Value::Const(ColumnValue::U32(cutoff))))?;
+            drop(data);
+            self.commit_storage(&ttl.table)?;
+        }
+        if config.compact {
+            for table in self.tables.keys() {
+                let Some(mut data) = self.try_write_table(table)? else { continue };
+                self.compact_in(table, &mut data);
+                report.tables_compacted += 1;
+            }
+        }
+        if config.refresh_stats {
+            let mut stats = self.last_table_stats();
+            for table in self.tables.keys() {
+                let Some(data) = self.try_read_table(table)? else { continue };
+                let refreshed = TableStats { name: table.clone(), rows: data.storage.row_count(), disk_bytes: data.storage.disk_bytes() };
+                match stats.iter_mut().find(|stats| stats.name == *table) {
+                    Some(stats) => *stats = refreshed,
+                    None => stats.push(refreshed),
+                }
+                report.stats_refreshed += 1;
+            }
+            stats.retain(|stats| self.tables.contains_key(&stats.name));
+            stats.sort_by(|a, b| a.name.cmp(&b.name));
+            *self.stats.lock().unwrap_or_else(PoisonError::into_inner) = stats;
+        }
+        if let Some(wal) = &config.checkpoint {
+            report.checkpoint = self.checkpoint_impl(wal, false)?;
+        }
+        Ok(report)
+    }
+
+    // Each table by name as of the last maintenance run refreshing stats, see `MaintenanceConfig`.
+    // Takes no table lock, unlike `table_stats`.
+    pub fn last_table_stats(&self) -> Vec<TableStats> {
+        self.stats.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
 
-        // Execute removal
-        let removed = to_remove.len();
-        // FIXME: Mutable borrow, again - borrow checker, storage.as_mut() doesn't work
-        self.mut_storage_for(table_name)?.delete_rows(to_remove);
-        Ok(removed)
+    // Queries holding more rows in memory fail with `QueryMemoryExceeded`. Streamed selects
+    // aren't held to it, they move rows to disk at their own threshold.
+    pub fn set_query_memory_limit(&mut self, max_bytes: Option<usize>) {
+        self.query_memory = max_bytes;
+    }
+
+    pub fn set_soft_limits(&mut self, soft: SoftLimits) {
+        self.limits().soft = soft;
+    }
+
+    pub fn add_limit_observer(&mut self, observer: Arc<dyn LimitObserver>) {
+        self.limits().add_observer(observer);
+    }
+
+    // Times a soft limit was crossed since the database was created
+    pub fn soft_limit_crossings(&self, limit: Limit) -> usize {
+        self.limits().crossed(limit)
+    }
+
+    // Observers are notified of changes to every table
+    pub fn add_observer(&mut self, observer: Arc<dyn StorageObserver>) {
+        self.observers.push(observer);
+    }
+
+    pub fn count(&self, table: &str, filter: &Bool) -> Result<usize, DbError> {
+        let data = self.read_table(table)?;
+        self.count_in(table, &data, filter)
+    }
+
+    pub(crate) fn count_in(&self, table: &str, data: &TableData, filter: &Bool) -> Result<usize, DbError> {
+        let schema = self.schema_for(table)?;
+        if let Bool::True = filter {
+            return Ok(data.storage.row_count());
+        }
+
+        let shape = query_shape("count", &[], filter);
+        let plan = self.plans().get_or_plan(table, shape, || Plan::for_filter(schema, filter))?;
+        let mut params = Vec::new();
+        collect_params(filter, &mut params);
+
+        let mut count = 0;
+        for batch in data.storage.scan_batches(SCAN_BATCH_ROWS) {
+            match u32_kernel(&batch, &plan.filter, &params) {
+                Some(kept) => count += kept.iter().filter(|keep| **keep).count(),
+                None => for item in batch.items() {
+                    if filter_row(schema, item, &plan.filter, &params)? { count += 1; }
+                },
+            }
+        }
+        Ok(count)
+    }
+
+    // Bytes held in memory per table, with their indexes, and by blobs
+    pub fn memory_usage(&self) -> DatabaseMemory {
+        let tables = self.tables.iter().map(|(table_name, slot)| {
+            let data = slot.data.read().unwrap_or_else(PoisonError::into_inner);
+            let indexes = data.indexes.iter().map(Index::memory_usage).sum();
+            (table_name.clone(), data.storage.memory_usage() + MemoryUsage { indexes, ..MemoryUsage::default() })
+        }).collect();
+        DatabaseMemory { tables, blobs: self.blobs.memory_usage() }
+    }
+
+    // Sorted, taking no lock
+    pub fn table_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.schemas.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+
+    // Each table by name, waiting for those being written
+    pub fn table_stats(&self) -> Result<Vec<TableStats>, DbError> {
+        let mut stats = self.tables.keys().map(|name| {
+            let data = self.read_table(name)?;
+            Ok(TableStats { name: name.clone(), rows: data.storage.row_count(), disk_bytes: data.storage.disk_bytes() })
+        }).collect::<Result<Vec<_>, DbError>>()?;
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(stats)
+    }
+
+    pub fn plan_cache_stats(&self) -> PlanCacheStats {
+        let plans = self.plans();
+        PlanCacheStats { plans: plans.len(), hits: plans.hits, misses: plans.misses }
+    }
+
+    pub fn schema_fingerprint(&self, table_name: &str) -> Result<u64, DbError> {
+        Ok(self.schema_for(table_name)?.fingerprint())
     }
 
     pub fn schema_for(&self, table_name: &str) -> Result<&Table, DbError> {
@@ -352,15 +1565,73 @@ impl Database {
             .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))
     }
 
-    fn storage_for(&self, table_name: &str) -> Result<&Box<dyn Storage>, DbError> {
-        self.storage
+    fn slot_for(&self, table_name: &str) -> Result<&TableSlot, DbError> {
+        self.tables
             .get(table_name)
             .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))
     }
 
-    fn mut_storage_for(&mut self, table_name: &str) -> Result<&mut Box<dyn Storage>, DbError> {
-        self.storage
-            .get_mut(table_name)
-            .ok_or_else(|| DbError::TableNotFound(table_name.to_string()))
+    // A write that panicked may have left the storage and its indexes apart
+    fn read_table(&self, table_name: &str) -> Result<RwLockReadGuard<'_, TableData>, DbError> {
+        let slot = self.slot_for(table_name)?;
+        self.acquire(table_name, || slot.data.try_read(), || slot.data.read())
+    }
+
+    pub(crate) fn write_table(&self, table_name: &str) -> Result<TableWrite<'_>, DbError> {
+        let slot = self.slot_for(table_name)?;
+        let data = self.acquire(table_name, || slot.data.try_write(), || slot.data.write())?;
+        Ok(TableWrite { data, disk_bytes: &slot.disk_bytes })
+    }
+
+    // A thread holding table locks only waits for another one if that can't deadlock. Threads
+    // holding none can't be part of a deadlock, they wait as usual.
+    fn acquire<G>(&self, table_name: &str, try_lock: impl FnOnce() -> TryLockResult<G>, lock: impl FnOnce() -> LockResult<G>) -> Result<G, DbError> {
+        if !locking::holds_table_locks() {
+            return lock().map_err(|_| poisoned(table_name));
+        }
+        match try_lock() {
+            Ok(guard) => return Ok(guard),
+            Err(TryLockError::Poisoned(_)) => return Err(poisoned(table_name)),
+            Err(TryLockError::WouldBlock) => (),
+        }
+        self.lock_graph().wait_for(table_name)?;
+        let guard = lock();
+        self.lock_graph().done_waiting();
+        guard.map_err(|_| poisoned(table_name))
+    }
+
+    pub(crate) fn lock_graph(&self) -> MutexGuard<'_, LockGraph> {
+        self.lock_graph.lock().unwrap_or_else(PoisonError::into_inner)
     }
+
+    // None if the table is locked
+    fn try_read_table(&self, table_name: &str) -> Result<Option<RwLockReadGuard<'_, TableData>>, DbError> {
+        match self.slot_for(table_name)?.data.try_read() {
+            Ok(data) => Ok(Some(data)),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Poisoned(_)) => Err(poisoned(table_name)),
+        }
+    }
+
+    fn try_write_table(&self, table_name: &str) -> Result<Option<TableWrite<'_>>, DbError> {
+        let slot = self.slot_for(table_name)?;
+        match slot.data.try_write() {
+            Ok(data) => Ok(Some(TableWrite { data, disk_bytes: &slot.disk_bytes })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Poisoned(_)) => Err(poisoned(table_name)),
+        }
+    }
+
+    // Caches stay usable after a panic, they hold nothing a query could see half written
+    fn plans(&self) -> MutexGuard<'_, PlanCache> {
+        self.plans.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn limits(&self) -> MutexGuard<'_, LimitMonitor> {
+        self.limits.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn poisoned(table_name: &str) -> DbError {
+    DbError::DatabaseIntegrityError(format!("A write to {table_name} panicked, the table may be inconsistent"))
 }
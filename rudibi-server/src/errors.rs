@@ -0,0 +1,137 @@
+// Stable numeric codes of errors, for clients of frames to tell failures apart without parsing
+// messages. A code keeps its meaning once given, new errors get new ones. By thousands:
+//   1xxx  the catalog: tables, columns, indexes, cursors, blobs
+//   2xxx  commands that can't be carried out as given: syntax, types, values
+//   3xxx  limits reached
+//   4xxx  authentication and permissions
+//   5xxx  queries stopped by something else: cancels, deadlocks, conflicts
+//   6xxx  requests the server doesn't understand
+//   9xxx  failures of the server or its storage
+// They're replied in ERROR frames as an `ErrorReply`, see `rudibi_core::errors`.
+
+use crate::dtype::TypeError;
+use crate::engine::DbError;
+use crate::limits::QuotaKind;
+use crate::permissions::Privilege;
+use crate::throttle::Rate;
+
+pub use rudibi_core::errors::{ErrorReply, BAD_COMPRESSION, BAD_ENCODING, UNKNOWN_REQUEST, UNSUPPORTED_CODEC};
+
+impl From<&DbError> for ErrorReply {
+    fn from(err: &DbError) -> ErrorReply {
+        let context = context(err).into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+        ErrorReply { code: code(err), message: format!("{err:?}"), context }
+    }
+}
+
+pub fn code(err: &DbError) -> u16 {
+    match err {
+        DbError::TableNotFound(_) => 1001,
+        DbError::TableAlreadyExists(_) => 1002,
+        DbError::EmptyTableSchema => 1003,
+        DbError::ColumnNotFound(_) => 1004,
+        DbError::IndexAlreadyExists(_) => 1005,
+        DbError::IndexNotFound(_) => 1006,
+        DbError::CursorNotFound(_) => 1007,
+        DbError::CursorAlreadyExists(_) => 1008,
+        DbError::BlobNotFound(_) => 1009,
+        DbError::SavepointNotFound(_) => 1010,
+        DbError::InputError(_) => 2001,
+        DbError::QueryError(_) => 2002,
+        DbError::InvalidColumnCount { .. } => 2003,
+        DbError::InvalidEnumLabel { .. } => 2004,
+        DbError::RowSizeExceeded { .. } => 2005,
+        DbError::RowSizeTooSmall { .. } => 2006,
+        DbError::ColumnSizeOutOfBounds { .. } => 2007,
+        DbError::DuplicateKey { .. } => 2008,
+        DbError::FlowControlViolation { .. } => 2009,
+        DbError::ReadOnly => 2010,
+        DbError::QuotaExceeded { .. } => 3001,
+        DbError::QueryMemoryExceeded { .. } => 3002,
+        DbError::TooManyCursors { .. } => 3003,
+        DbError::ServerBusy => 3004,
+        DbError::RateLimited { .. } => 3005,
+        DbError::ChangesExpired { .. } => 3006,
+        DbError::AuthenticationFailed => 4001,
+        DbError::NotAuthenticated => 4002,
+        DbError::PermissionDenied { .. } => 4003,
+        DbError::QueryCancelled => 5001,
+        DbError::Deadlock(_) => 5002,
+        DbError::WriteConflict(_) => 5003,
+        DbError::DatabaseLocked(_) => 5004,
+        DbError::UnsupportedOperation(_) => 9001,
+        DbError::DatabaseIntegrityError(_) => 9002,
+        DbError::StorageError(_) => 9003,
+        DbError::UnsupportedFormatVersion { .. } => 9004,
+    }
+}
+
+// What the error is about, for errors about something named or measured
+fn context(err: &DbError) -> Vec<(&'static str, String)> {
+    match err {
+        DbError::TableNotFound(table) | DbError::TableAlreadyExists(table) | DbError::Deadlock(table) | DbError::WriteConflict(table) =>
+            vec![("table", table.clone())],
+        DbError::ColumnNotFound(column) => vec![("column", column.clone())],
+        DbError::IndexAlreadyExists(index) | DbError::IndexNotFound(index) => vec![("index", index.clone())],
+        DbError::CursorNotFound(cursor) | DbError::CursorAlreadyExists(cursor) => vec![("cursor", cursor.clone())],
+        DbError::BlobNotFound(blob) => vec![("blob", blob.to_string())],
+        DbError::SavepointNotFound(savepoint) => vec![("savepoint", savepoint.clone())],
+        DbError::QueryError(err) => vec![("type_error", type_error(err).to_string())],
+        DbError::InvalidColumnCount { expected, got } => vec![("expected", expected.to_string()), ("got", got.to_string())],
+        DbError::InvalidEnumLabel { column, label } => vec![("column", column.clone()), ("label", label.clone())],
+        DbError::RowSizeExceeded { got, max } => vec![("got", got.to_string()), ("max", max.to_string())],
+        DbError::RowSizeTooSmall { got, min } => vec![("got", got.to_string()), ("min", min.to_string())],
+        DbError::ColumnSizeOutOfBounds { column, got, min, max } =>
+            vec![("column", column.clone()), ("got", got.to_string()), ("min", min.to_string()), ("max", max.to_string())],
+        DbError::DuplicateKey { index, key } => vec![("index", index.clone()), ("key", format!("{key:?}"))],
+        DbError::FlowControlViolation { window, got } => vec![("window", window.to_string()), ("got", got.to_string())],
+        DbError::QuotaExceeded { table, quota, max, got } =>
+            vec![("table", table.clone()), ("quota", quota_kind(*quota).to_string()), ("max", max.to_string()), ("got", got.to_string())],
+        DbError::QueryMemoryExceeded { max, got } => vec![("max", max.to_string()), ("got", got.to_string())],
+        DbError::TooManyCursors { max } => vec![("max", max.to_string())],
+        DbError::RateLimited { rate, retry_after } =>
+            vec![("rate", rate_name(*rate).to_string()), ("retry_after_ms", retry_after.as_millis().to_string())],
+        DbError::PermissionDenied { user, privilege, table } =>
+            vec![("user", user.clone()), ("privilege", privilege_name(*privilege).to_string()), ("table", table.clone())],
+        DbError::ChangesExpired { after, oldest } => vec![("after", after.to_string()), ("oldest", oldest.to_string())],
+        DbError::UnsupportedFormatVersion { found, supported } => vec![("found", found.to_string()), ("supported", supported.to_string())],
+        _ => Vec::new(),
+    }
+}
+
+fn type_error(err: &TypeError) -> &'static str {
+    match err {
+        TypeError::ConversionError => "conversion",
+        TypeError::InvalidArgType(..) => "invalid_arg_type",
+        TypeError::Overflow(_) => "overflow",
+        TypeError::DivisionByZero => "division_by_zero",
+        TypeError::UnknownColumn(_) => "unknown_column",
+    }
+}
+
+fn quota_kind(quota: QuotaKind) -> &'static str {
+    match quota {
+        QuotaKind::Rows => "rows",
+        QuotaKind::Bytes => "bytes",
+        QuotaKind::DiskBudget => "disk_budget",
+    }
+}
+
+// As in `config`
+fn rate_name(rate: Rate) -> &'static str {
+    match rate {
+        Rate::Queries => "queries",
+        Rate::RowsScanned => "rows_scanned",
+        Rate::BytesReturned => "bytes_returned",
+    }
+}
+
+// As `Privilege::parse` takes it
+fn privilege_name(privilege: Privilege) -> &'static str {
+    match privilege {
+        Privilege::Read => "read",
+        Privilege::Write => "write",
+        Privilege::Ddl => "ddl",
+        Privilege::Admin => "admin",
+    }
+}
@@ -0,0 +1,118 @@
+// Group commit: writers that commit while a sync is running wait for the next one, which then
+// makes all their writes durable at once. Under many small concurrent writes a file is synced
+// once per group of commits rather than once per commit.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+use crate::engine::DbError;
+
+#[derive(Default)]
+struct SyncState {
+    // Commits registered so far, and how many of them a finished sync covered
+    committed: u64,
+    synced: u64,
+    syncing: bool,
+    syncs: u64,
+}
+
+#[derive(Default)]
+pub struct GroupCommit {
+    state: Mutex<SyncState>,
+    synced: Condvar,
+}
+
+// A commit whose writes aren't durable yet, see `Storage::commit_grouped`
+pub struct PendingSync {
+    group: Arc<GroupCommit>,
+    ticket: u64,
+    sync: Box<dyn FnOnce() -> Result<(), DbError> + Send>,
+}
+
+impl GroupCommit {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Registers a commit of the writes made so far. `sync` makes them durable, it's only run if
+    // no other sync covers them.
+    pub fn commit(self: &Arc<Self>, sync: impl FnOnce() -> Result<(), DbError> + Send + 'static) -> PendingSync {
+        let mut state = self.lock();
+        state.committed += 1;
+        PendingSync { group: self.clone(), ticket: state.committed, sync: Box::new(sync) }
+    }
+
+    // Syncs run so far
+    pub fn syncs(&self) -> u64 {
+        self.lock().syncs
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SyncState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl PendingSync {
+
+    // Returns once a sync started after the commit finished
+    pub fn wait(self) -> Result<(), DbError> {
+        let group = &self.group;
+        let mut state = group.lock();
+        while state.syncing && state.synced < self.ticket {
+            state = group.synced.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+        if state.synced >= self.ticket {
+            return Ok(());
+        }
+        // Nobody is syncing, this commit syncs for every commit registered until now
+        let covered = state.committed;
+        state.syncing = true;
+        drop(state);
+        let result = (self.sync)();
+        let mut state = group.lock();
+        state.syncing = false;
+        state.syncs += 1;
+        if result.is_ok() {
+            state.synced = state.synced.max(covered);
+        }
+        // On failure the waiters find nobody syncing, and one of them tries again
+        group.synced.notify_all();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_commits_share_syncs() {
+        let group = Arc::new(GroupCommit::new());
+        let threads: Vec<_> = (0..8).map(|_| {
+            let group = group.clone();
+            thread::spawn(move || {
+                for _ in 0..10 {
+                    group.commit(|| {
+                        thread::sleep(Duration::from_millis(5));
+                        Ok(())
+                    }).wait().unwrap();
+                }
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(group.syncs() < 80);
+    }
+
+    #[test]
+    fn failed_sync_is_retried_by_the_next_commit() {
+        let group = Arc::new(GroupCommit::new());
+        let failed = group.commit(|| Err(DbError::StorageError("disk full".to_string()))).wait();
+        assert_eq!(failed, Err(DbError::StorageError("disk full".to_string())));
+        group.commit(|| Ok(())).wait().unwrap();
+        assert_eq!(group.syncs(), 2);
+    }
+}
@@ -0,0 +1,287 @@
+// Secondary indexes over a single column
+// An ordered index keeps the column's values in a B-tree, so range filters and ordering on the
+// column can walk a key range instead of scanning the table
+
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::collation::Collation;
+use crate::dtype::*;
+use crate::engine::{Column, DbError, Row};
+use crate::hash::StableHasher;
+use crate::plan::{CmpOp, Operand, Program};
+use crate::storage::RowId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    Ordered,
+}
+
+// Owned, totally ordered form of a column value
+// F64 keys use the total order (NaN last, -0.0 == 0.0), UTF8 keys the column's collation
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IndexKey {
+    U32(u32),
+    F64(u64),
+    UTF8(String),
+    Bytes(Vec<u8>),
+    Uuid(Uuid),
+}
+
+impl IndexKey {
+    pub fn from_value(value: &ColumnValue, collation: Collation) -> IndexKey {
+        match value {
+            ColumnValue::U32(val) => IndexKey::U32(*val),
+            ColumnValue::F64(val) => IndexKey::F64(f64_ordered_bits(*val)),
+            ColumnValue::UTF8(val) => IndexKey::UTF8(collation.sort_key(val).into_owned()),
+            ColumnValue::Bytes(val) => IndexKey::Bytes(val.to_vec()),
+            ColumnValue::Uuid(val) => IndexKey::Uuid(*val),
+        }
+    }
+
+    // Keys that are equal hash equally, in every process
+    pub fn stable_hash(&self) -> u64 {
+        let mut hasher = StableHasher::default();
+        match self {
+            IndexKey::U32(val) => { hasher.write(&[1]); hasher.write(&val.to_le_bytes()) },
+            IndexKey::F64(bits) => { hasher.write(&[2]); hasher.write_u64(*bits) },
+            IndexKey::UTF8(val) => { hasher.write(&[3]); hasher.write_field(val.as_bytes()) },
+            IndexKey::Bytes(val) => { hasher.write(&[4]); hasher.write_field(val) },
+            IndexKey::Uuid(val) => { hasher.write(&[5]); hasher.write(&val.0) },
+        }
+        hasher.finish()
+    }
+
+    // Key of a stored column value
+    pub fn of_column(column: &Column, data: &[u8]) -> Result<IndexKey, DbError> {
+        let value = canonical_column(&column.dtype, data)
+            .map_err(|_| DbError::DatabaseIntegrityError(format!("Column {} cannot be represented as data type {:?}", column.name, column.dtype)))?;
+        Ok(IndexKey::from_value(&value, column.collation))
+    }
+}
+
+#[derive(Debug)]
+pub struct Index {
+    pub kind: IndexKind,
+    // Rejects rows whose key is already present
+    pub unique: bool,
+    pub column: Column,
+    // Position of the column in the table schema
+    pub column_idx: usize,
+    entries: BTreeMap<IndexKey, Vec<RowId>>,
+}
+
+impl Index {
+
+    pub fn new(kind: IndexKind, column: Column, column_idx: usize) -> Index {
+        Index { kind, unique: false, column, column_idx, entries: BTreeMap::new() }
+    }
+
+    pub fn new_unique(kind: IndexKind, column: Column, column_idx: usize) -> Index {
+        Index { unique: true, ..Index::new(kind, column, column_idx) }
+    }
+
+    pub fn key_of(&self, data: &[u8]) -> Result<IndexKey, DbError> {
+        IndexKey::of_column(&self.column, data)
+    }
+
+    // `row` holds the input columns, `column_mapping` gives the input position of each schema column
+    pub fn input_key(&self, row: &Row, column_mapping: &[usize]) -> Result<IndexKey, DbError> {
+        self.key_of(row.get_column(column_mapping[self.column_idx]))
+    }
+
+    pub fn insert(&mut self, key: IndexKey, row_id: RowId) {
+        self.entries.entry(key).or_default().push(row_id);
+    }
+
+    // `row_ids` must be sorted
+    pub fn remove(&mut self, row_ids: &[RowId]) {
+        self.entries.retain(|_, ids| {
+            ids.retain(|id| row_ids.binary_search(id).is_err());
+            !ids.is_empty()
+        });
+    }
+
+    pub fn contains(&self, key: &IndexKey) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    // For unique indexes, fails on the first of `keys` already present or repeated within `keys`
+    pub fn check_unique<'k>(&self, table: &str, keys: impl IntoIterator<Item = &'k IndexKey>) -> Result<(), DbError> {
+        if !self.unique {
+            return Ok(());
+        }
+        let mut seen = std::collections::HashSet::new();
+        for key in keys {
+            if self.contains(key) || !seen.insert(key) {
+                return Err(DbError::DuplicateKey { index: format!("{table}.{}", self.column.name), key: key.clone() });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &IndexKey) -> &[RowId] {
+        self.entries.get(key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    // Row ids with keys in the range, in key order
+    pub fn range(&self, lower: Bound<&IndexKey>, upper: Bound<&IndexKey>) -> impl Iterator<Item = RowId> + '_ {
+        // BTreeMap::range panics on inverted bounds, those are just empty
+        let empty = match (lower, upper) {
+            (Bound::Included(l) | Bound::Excluded(l), Bound::Included(u) | Bound::Excluded(u)) => {
+                l > u || (l == u && (matches!(lower, Bound::Excluded(_)) || matches!(upper, Bound::Excluded(_))))
+            },
+            _ => false,
+        };
+        let range = if empty { None } else { Some(self.entries.range::<IndexKey, _>((lower, upper))) };
+        range.into_iter().flatten().flat_map(|(_, ids)| ids.iter().copied())
+    }
+
+    // Same definition, without entries
+    pub fn empty_copy(&self) -> Index {
+        Index { kind: self.kind, unique: self.unique, column: self.column.clone(), column_idx: self.column_idx, entries: BTreeMap::new() }
+    }
+
+    // Entries of `expected` missing here, and entries here that `expected` doesn't have
+    pub fn diff(&self, expected: &Index) -> (usize, usize) {
+        let entries = |index: &Index| -> std::collections::HashSet<(IndexKey, RowId)> {
+            index.entries.iter().flat_map(|(key, ids)| ids.iter().map(move |id| (key.clone(), *id))).collect()
+        };
+        let (actual, expected) = (entries(self), entries(expected));
+        (expected.difference(&actual).count(), actual.difference(&expected).count())
+    }
+
+    // Estimated bytes of the entries, see `MemoryUsage`
+    pub fn memory_usage(&self) -> usize {
+        self.entries.iter().map(|(key, ids)| {
+            let key_heap = match key {
+                IndexKey::UTF8(text) => text.capacity(),
+                IndexKey::Bytes(bytes) => bytes.capacity(),
+                _ => 0,
+            };
+            size_of::<(IndexKey, Vec<RowId>)>() + key_heap + ids.capacity() * size_of::<RowId>()
+        }).sum()
+    }
+
+    // Number of distinct keys
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Whether keys built from `value` are comparable with the keys of a `dtype` column
+fn key_compatible(dtype: &DataType, value: &ColumnValue) -> bool {
+    matches!((dtype, value),
+        (DataType::U32, ColumnValue::U32(_))
+        | (DataType::F64, ColumnValue::F64(_))
+        | (DataType::UTF8 { .. } | DataType::ENUM { .. }, ColumnValue::UTF8(_))
+        | (DataType::VARBINARY { .. } | DataType::BUFFER { .. }, ColumnValue::Bytes(_))
+        | (DataType::UUID, ColumnValue::Uuid(_)))
+}
+
+// Row ids that may match the filter, sorted, or None if no index applies and the table must be
+// scanned. The set can include rows that don't match, so the filter is still applied to every row.
+pub fn candidates(indexes: &[Index], filter: &Program, params: &[ColumnValue]) -> Option<Vec<RowId>> {
+    match filter {
+        Program::Cmp(op, Operand::Column { idx, .. }, Operand::Param(param)) => cmp_candidates(indexes, *op, *idx, &params[*param]),
+        Program::Cmp(op, Operand::Param(param), Operand::Column { idx, .. }) => cmp_candidates(indexes, op.flipped(), *idx, &params[*param]),
+        // Either side narrows the conjunction, prefer the smaller candidate set
+        Program::And(left, right) => match (candidates(indexes, left, params), candidates(indexes, right, params)) {
+            (Some(l), Some(r)) => Some(if l.len() <= r.len() { l } else { r }),
+            (l, r) => l.or(r),
+        },
+        _ => None,
+    }
+}
+
+// Column and key of an equality the filter requires, for skipping data that can't contain the key
+pub fn equality_key(filter: &Program, params: &[ColumnValue]) -> Option<(usize, IndexKey)> {
+    match filter {
+        Program::Cmp(CmpOp::Eq, Operand::Column { idx, col }, Operand::Param(param))
+        | Program::Cmp(CmpOp::Eq, Operand::Param(param), Operand::Column { idx, col }) => {
+            let value = &params[*param];
+            key_compatible(&col.dtype, value).then(|| (*idx, IndexKey::from_value(value, col.collation)))
+        },
+        Program::And(left, right) => equality_key(left, params).or_else(|| equality_key(right, params)),
+        _ => None,
+    }
+}
+
+fn cmp_candidates(indexes: &[Index], op: CmpOp, column_idx: usize, value: &ColumnValue) -> Option<Vec<RowId>> {
+    let index = indexes.iter().find(|index| index.column_idx == column_idx)?;
+    // Mismatched types are left to the scan, which reports them as type errors
+    if !key_compatible(&index.column.dtype, value) {
+        return None;
+    }
+    // Binary UTF8 columns have no ordering in filters
+    let ordered = !matches!(index.column.dtype, DataType::UTF8 { .. } | DataType::ENUM { .. })
+        || index.column.collation != Collation::Binary;
+    let key = IndexKey::from_value(value, index.column.collation);
+    let mut ids: Vec<RowId> = match op {
+        CmpOp::Eq => index.get(&key).to_vec(),
+        CmpOp::Gt if ordered => index.range(Bound::Excluded(&key), Bound::Unbounded).collect(),
+        CmpOp::Gte if ordered => index.range(Bound::Included(&key), Bound::Unbounded).collect(),
+        CmpOp::Lt if ordered => index.range(Bound::Unbounded, Bound::Excluded(&key)).collect(),
+        CmpOp::Lte if ordered => index.range(Bound::Unbounded, Bound::Included(&key)).collect(),
+        _ => return None,
+    };
+    ids.sort_unstable();
+    Some(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Bound::*;
+
+    fn index_of(values: &[u32]) -> Index {
+        let mut index = Index::new(IndexKind::Ordered, Column::new("id", DataType::U32), 0);
+        for (row_id, val) in values.iter().enumerate() {
+            let key = index.input_key(&Row::of_columns(&[&val.to_le_bytes()]), &[0]).unwrap();
+            index.insert(key, row_id as RowId);
+        }
+        index
+    }
+
+    #[test]
+    fn range_traversal_in_key_order() {
+        let index = index_of(&[30, 10, 20, 10, 40]);
+
+        assert_eq!(index.range(Excluded(&IndexKey::U32(10)), Included(&IndexKey::U32(30))).collect::<Vec<_>>(), vec![2, 0]);
+        assert_eq!(index.range(Unbounded, Excluded(&IndexKey::U32(20))).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(index.range(Excluded(&IndexKey::U32(30)), Excluded(&IndexKey::U32(30))).count(), 0);
+        assert_eq!(index.range(Included(&IndexKey::U32(40)), Included(&IndexKey::U32(10))).count(), 0);
+    }
+
+    #[test]
+    fn removed_rows_leave_the_index() {
+        let mut index = index_of(&[30, 10, 20, 10]);
+
+        index.remove(&[1, 2]);
+
+        assert_eq!(index.get(&IndexKey::U32(10)), &[3]);
+        assert_eq!(index.get(&IndexKey::U32(20)), &[] as &[RowId]);
+        assert_eq!(index.len(), 2);
+    }
+
+    #[test]
+    fn diff_counts_missing_and_stale_entries() {
+        let actual = index_of(&[30, 10, 20]);
+        let mut expected = index_of(&[30, 10, 20, 40]);
+        expected.remove(&[1]);
+
+        assert_eq!(actual.diff(&expected), (1, 1));
+        assert_eq!(expected.diff(&expected), (0, 0));
+    }
+
+    #[test]
+    fn float_keys_use_total_order() {
+        assert!(IndexKey::from_value(&ColumnValue::F64(-1.0), Collation::Binary) < IndexKey::from_value(&ColumnValue::F64(0.0), Collation::Binary));
+        assert_eq!(IndexKey::from_value(&ColumnValue::F64(-0.0), Collation::Binary), IndexKey::from_value(&ColumnValue::F64(0.0), Collation::Binary));
+        assert!(IndexKey::from_value(&ColumnValue::F64(f64::NAN), Collation::Binary) > IndexKey::from_value(&ColumnValue::F64(f64::INFINITY), Collation::Binary));
+    }
+}
@@ -0,0 +1,135 @@
+// Server side of flow-controlled insert streaming, see `frame::INSERT_ROWS`
+// The client may only send as many rows as the last window update allowed, so neither side
+// has to hold more than one window of rows in memory. Windows are shared out of an `InsertBudget`
+// of bytes, a window reserving what its rows are expected to take until they come. The more
+// the other streams of a server were granted, the smaller a window, down to a row.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::engine::{Database, DbError, Row};
+
+pub const DEFAULT_INSERT_WINDOW: usize = 1024;
+// Bytes of streamed rows a server's connections may have been granted at once
+pub const DEFAULT_INSERT_BUDGET_BYTES: usize = 16 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowUpdate {
+    // Rows stored from the acknowledged chunk
+    pub accepted: usize,
+    // Max rows the client may send in its next chunk
+    pub window: usize,
+}
+
+#[derive(Debug)]
+pub struct InsertBudget {
+    max_bytes: usize,
+    granted: AtomicUsize,
+}
+
+impl InsertBudget {
+    pub fn new(max_bytes: usize) -> InsertBudget {
+        InsertBudget { max_bytes, granted: AtomicUsize::new(0) }
+    }
+
+    // Bytes granted to the windows of the streams open
+    pub fn granted(&self) -> usize {
+        self.granted.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct InsertStream {
+    table: String,
+    columns: Vec<String>,
+    max_window: usize,
+    window: usize,
+    inserted: usize,
+    budget: Arc<InsertBudget>,
+    // Bytes of the budget the window holds
+    reserved: usize,
+    // Expected bytes of a row: the largest the table holds, then the mean of those received
+    row_bytes: usize,
+    received: (usize, usize),
+}
+
+impl InsertStream {
+
+    // A stream whose windows are always `window` rows
+    pub fn open(db: &Database, table: &str, columns: &[&str], window: usize) -> Result<InsertStream, DbError> {
+        InsertStream::open_within(db, table, columns, window, Arc::new(InsertBudget::new(usize::MAX)))
+    }
+
+    // A stream whose windows are at most `max_window` rows, and what's left of `budget`
+    pub fn open_within(db: &Database, table: &str, columns: &[&str], max_window: usize, budget: Arc<InsertBudget>) -> Result<InsertStream, DbError> {
+        // Fail before any data is sent if the rows could never be stored
+        let schema = db.schema_for(table)?;
+        schema.project_from_schema(columns)?;
+        if max_window == 0 {
+            return Err(DbError::InputError("Insert window must allow at least one row".to_string()));
+        }
+        let mut stream = InsertStream {
+            table: table.to_string(),
+            columns: columns.iter().map(|col| col.to_string()).collect(),
+            max_window,
+            window: 0,
+            inserted: 0,
+            budget,
+            reserved: 0,
+            row_bytes: schema.max_row_size.max(1),
+            received: (0, 0),
+        };
+        stream.grant();
+        Ok(stream)
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // Whether it's the stream of inserts into these columns of the table
+    pub fn is_into(&self, table: &str, columns: &[&str]) -> bool {
+        self.table == table && self.columns.iter().map(String::as_str).eq(columns.iter().copied())
+    }
+
+    pub fn push(&mut self, db: &Database, chunk: &[Row]) -> Result<WindowUpdate, DbError> {
+        if chunk.len() > self.window {
+            return Err(DbError::FlowControlViolation { window: self.window, got: chunk.len() });
+        }
+        let columns: Vec<&str> = self.columns.iter().map(String::as_str).collect();
+        let accepted = db.insert_uncommitted(&self.table, &columns, chunk)?.len();
+        self.inserted += accepted;
+        let (rows, bytes) = self.received;
+        self.received = (rows + chunk.len(), bytes + chunk.iter().map(|row| row.data.len()).sum::<usize>());
+        if let Some(mean) = self.received.1.checked_div(self.received.0) {
+            self.row_bytes = mean.max(1);
+        }
+        self.grant();
+        Ok(WindowUpdate { accepted, window: self.window })
+    }
+
+    // Commits the streamed rows, returning how many were stored over the lifetime of the stream
+    pub fn finish(&mut self, db: &Database) -> Result<usize, DbError> {
+        db.commit_storage(&self.table)?;
+        Ok(self.inserted)
+    }
+
+    // The next window, of the rows the budget left has room for, reserving them
+    fn grant(&mut self) {
+        let (row_bytes, max_window) = (self.row_bytes, self.max_window);
+        let mut window = 1;
+        let update = self.budget.granted.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |granted| {
+            let free = self.budget.max_bytes.saturating_sub(granted - self.reserved);
+            window = (free / row_bytes).clamp(1, max_window);
+            Some(granted - self.reserved + window * row_bytes)
+        });
+        debug_assert!(update.is_ok());
+        (self.window, self.reserved) = (window, window * row_bytes);
+    }
+}
+
+impl Drop for InsertStream {
+    fn drop(&mut self) {
+        self.budget.granted.fetch_sub(self.reserved, Ordering::Relaxed);
+    }
+}
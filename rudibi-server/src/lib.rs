@@ -1,8 +1,52 @@
 pub mod storage;
 pub mod serial;
-pub mod dtype;
-pub mod query;
+pub use rudibi_core::{dtype, query};
 pub mod engine;
+pub mod plan;
+pub use rudibi_core::display;
+pub mod cursor;
+pub mod ingest;
+pub use rudibi_core::hash;
+pub mod limits;
+pub mod blob;
+pub mod bloom;
+pub use rudibi_core::collation;
+pub mod index;
+pub use rudibi_core::compress;
+pub mod catalog;
+pub mod tiered;
+pub mod wal;
+pub mod maintenance;
+pub mod spill;
+pub mod transaction;
+pub mod locking;
+pub mod group_commit;
+pub mod batch;
+pub mod cancel;
+pub use rudibi_core::frame;
+pub mod errors;
+pub mod protocol;
+pub mod pgwire;
+pub mod resp;
+pub mod workers;
+pub mod config;
+pub mod auth;
+pub mod permissions;
+pub mod tcp;
+pub mod metrics;
+pub mod telemetry;
+pub mod throttle;
+pub mod replication;
+pub mod changes;
+pub mod mapping;
+#[cfg(feature = "object-store")]
+pub mod object;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "async-server")]
+pub mod async_server;
+#[cfg(feature = "tls")]
+pub mod tls;
 
 // FIXME: Make util work only in tests / benches
 // #[cfg(test)]
@@ -0,0 +1,157 @@
+// Soft thresholds below the enforced limits, and quotas on table size
+// Crossing a soft threshold doesn't fail the operation, it's counted and reported to observers,
+// so operators see a table approaching its limits before writes start failing
+
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+use crate::engine::{DbError, Row};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Limit {
+    // Bytes of a single inserted row, hard limit is the schema's max row size
+    RowSize,
+    // Rows returned by one select, no hard limit
+    ResultRows,
+    // Rows or bytes of a table, hard limit is its `Quota`
+    TableQuota,
+    // Bytes of all table files, hard limit is the database's disk budget
+    DiskBudget,
+    // Bytes of rows one query holds in memory, hard limit is its `QueryMemory`
+    QueryMemory,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LimitWarning {
+    pub limit: Limit,
+    pub table: String,
+    pub value: usize,
+    pub soft: usize,
+    pub hard: Option<usize>,
+}
+
+pub trait LimitObserver: Send + Sync {
+    fn on_soft_limit(&self, warning: &LimitWarning);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SoftLimits {
+    // Fraction of the schema's max row size, e.g. 0.8 warns about rows over 80% of the limit
+    pub row_size: Option<f64>,
+    pub result_rows: Option<usize>,
+    // Fractions of the hard limits, as `row_size`
+    pub table_quota: Option<f64>,
+    pub disk_budget: Option<f64>,
+    pub query_memory: Option<f64>,
+}
+
+impl SoftLimits {
+    pub fn row_size_threshold(&self, max_row_size: usize) -> Option<usize> {
+        self.threshold(Limit::RowSize, max_row_size)
+    }
+
+    // Past what value `limit` warns, given its hard limit
+    pub fn threshold(&self, limit: Limit, hard: usize) -> Option<usize> {
+        let fraction = match limit {
+            Limit::RowSize => self.row_size,
+            Limit::ResultRows => return self.result_rows,
+            Limit::TableQuota => self.table_quota,
+            Limit::DiskBudget => self.disk_budget,
+            Limit::QueryMemory => self.query_memory,
+        };
+        fraction.map(|fraction| (hard as f64 * fraction) as usize)
+    }
+}
+
+// What to do with an insert that would take a table over its quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuotaPolicy {
+    #[default]
+    Reject,
+    // Delete the rows with the lowest ids to make room, for cache-style tables.
+    // Only for row quotas, byte quotas reject since deletes don't shrink disk files.
+    EvictOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quota {
+    pub max_rows: Option<usize>,
+    // Bytes held by the table's rows in memory and on disk, indexes not included
+    pub max_bytes: Option<u64>,
+    pub policy: QuotaPolicy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaKind {
+    Rows,
+    Bytes,
+    // Database-wide, for all table files together
+    DiskBudget,
+}
+
+#[derive(Default)]
+pub struct LimitMonitor {
+    pub soft: SoftLimits,
+    observers: Vec<std::sync::Arc<dyn LimitObserver>>,
+    crossed: HashMap<Limit, usize>,
+}
+
+impl LimitMonitor {
+
+    pub fn add_observer(&mut self, observer: std::sync::Arc<dyn LimitObserver>) {
+        self.observers.push(observer);
+    }
+
+    // Reports `value` of the table if it's past the soft threshold of `limit` but within `hard`,
+    // true if it was
+    pub fn check(&mut self, limit: Limit, table: &str, value: usize, hard: usize) -> bool {
+        let Some(soft) = self.soft.threshold(limit, hard).filter(|soft| value > *soft && value <= hard) else {
+            return false;
+        };
+        self.report(LimitWarning { limit, table: table.to_string(), value, soft, hard: Some(hard) });
+        true
+    }
+
+    pub fn report(&mut self, warning: LimitWarning) {
+        *self.crossed.entry(warning.limit).or_default() += 1;
+        for observer in &self.observers {
+            observer.on_soft_limit(&warning);
+        }
+    }
+
+    // Times each soft limit was crossed
+    pub fn crossed(&self, limit: Limit) -> usize {
+        self.crossed.get(&limit).copied().unwrap_or(0)
+    }
+}
+
+// Bytes of rows a query holds in memory, such as the rows of a result set before it's returned.
+// The query fails once they go over the limit, rather than the process running out of memory.
+// Going over the soft threshold of the limit is reported once a query.
+pub(crate) struct QueryMemory<'q> {
+    max: Option<usize>,
+    used: usize,
+    table: &'q str,
+    limits: &'q Mutex<LimitMonitor>,
+    warned: bool,
+}
+
+impl<'q> QueryMemory<'q> {
+
+    pub(crate) fn new(max: Option<usize>, table: &'q str, limits: &'q Mutex<LimitMonitor>) -> Self {
+        QueryMemory { max, used: 0, table, limits, warned: false }
+    }
+
+    pub(crate) fn charge(&mut self, rows: &[Row]) -> Result<(), DbError> {
+        let Some(max) = self.max else { return Ok(()) };
+        self.used += rows.iter().map(|row| row.data.len() + row.offsets.len() * size_of::<usize>()).sum::<usize>();
+        if self.used > max {
+            return Err(DbError::QueryMemoryExceeded { max, got: self.used });
+        }
+        if !self.warned {
+            let mut limits = self.limits.lock().unwrap_or_else(PoisonError::into_inner);
+            self.warned = limits.check(Limit::QueryMemory, self.table, self.used, max);
+        }
+        Ok(())
+    }
+}
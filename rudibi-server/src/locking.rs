@@ -0,0 +1,165 @@
+// Table locks held across several operations, for embedders that need a table to stay put while
+// they work on it, e.g. exporting it before truncating it. Operations on the locked table go
+// through the lock: through the database they'd wait for the lock to be dropped.
+// A thread holding table locks that would wait for a lock held by a thread waiting for one of
+// its own gets `DbError::Deadlock` instead, and should drop its locks before trying again.
+
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLockReadGuard;
+use std::thread::{self, ThreadId};
+
+use crate::engine::{Database, DbError, QueryOptions, ResultSet, Row, TableData, TableRead, TableWrite};
+use crate::query::{Bool, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockMode {
+    // Others can still read the table, nobody can write it
+    Shared,
+    // Nobody else can read or write the table
+    Exclusive,
+}
+
+pub(crate) enum HeldLock<'db> {
+    Shared(RwLockReadGuard<'db, TableData>),
+    Exclusive(TableWrite<'db>),
+}
+
+thread_local! {
+    // Table locks held by this thread, in any database
+    static HELD: Cell<usize> = const { Cell::new(0) };
+}
+
+pub(crate) fn holds_table_locks() -> bool {
+    HELD.get() > 0
+}
+
+// Which threads hold and wait for table locks. Locks taken for a single operation aren't in it,
+// they're let go before their thread waits for anything else.
+#[derive(Default)]
+pub(crate) struct LockGraph {
+    holders: HashMap<String, Vec<ThreadId>>,
+    waiting: HashMap<ThreadId, String>,
+}
+
+impl LockGraph {
+
+    // Records that the current thread waits for the table, unless the wait would close a cycle
+    pub(crate) fn wait_for(&mut self, table: &str) -> Result<(), DbError> {
+        let current = thread::current().id();
+        let mut tables = vec![table];
+        let mut seen = HashSet::new();
+        while let Some(next) = tables.pop() {
+            if !seen.insert(next) {
+                continue;
+            }
+            for holder in self.holders.get(next).into_iter().flatten() {
+                if *holder == current {
+                    return Err(DbError::Deadlock(table.to_string()));
+                }
+                tables.extend(self.waiting.get(holder).map(String::as_str));
+            }
+        }
+        self.waiting.insert(current, table.to_string());
+        Ok(())
+    }
+
+    pub(crate) fn done_waiting(&mut self) {
+        self.waiting.remove(&thread::current().id());
+    }
+
+    fn hold(&mut self, table: &str) {
+        self.holders.entry(table.to_string()).or_default().push(thread::current().id());
+    }
+
+    fn release(&mut self, table: &str) {
+        let current = thread::current().id();
+        if let Some(holders) = self.holders.get_mut(table) {
+            if let Some(position) = holders.iter().position(|holder| *holder == current) {
+                holders.swap_remove(position);
+            }
+            if holders.is_empty() {
+                self.holders.remove(table);
+            }
+        }
+    }
+}
+
+pub struct TableLock<'db> {
+    db: &'db Database,
+    table: String,
+    held: HeldLock<'db>,
+}
+
+impl<'db> TableLock<'db> {
+
+    pub(crate) fn new(db: &'db Database, table: &str, held: HeldLock<'db>) -> Self {
+        db.lock_graph().hold(table);
+        HELD.set(HELD.get() + 1);
+        TableLock { db, table: table.to_string(), held }
+    }
+
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    pub fn mode(&self) -> LockMode {
+        match self.held {
+            HeldLock::Shared(_) => LockMode::Shared,
+            HeldLock::Exclusive(_) => LockMode::Exclusive,
+        }
+    }
+
+    pub fn select(&self, values: &[Value], filter: &Bool) -> Result<ResultSet, DbError> {
+        self.db.select_from(values, &self.table, TableRead::Held(self.data()), filter, &QueryOptions::default())
+    }
+
+    pub fn count(&self, filter: &Bool) -> Result<usize, DbError> {
+        self.db.count_in(&self.table, self.data(), filter)
+    }
+
+    pub fn insert(&mut self, columns: &[&str], what: &[Row]) -> Result<usize, DbError> {
+        let (db, table, data) = self.writable()?;
+        let stored = db.insert_into(table, data, columns, what)?;
+        data.storage.commit()?;
+        db.notify_commit(table);
+        Ok(stored.len())
+    }
+
+    pub fn delete(&mut self, filter: &Bool) -> Result<usize, DbError> {
+        let (db, table, data) = self.writable()?;
+        let deleted = db.delete_matching(table, data, filter)?;
+        data.storage.commit()?;
+        db.notify_commit(table);
+        Ok(deleted)
+    }
+
+    pub fn update(&mut self, columns: &[&str], values: &Row, filter: &Bool) -> Result<usize, DbError> {
+        let (db, table, data) = self.writable()?;
+        let updated = db.update_in(table, data, columns, values, filter)?;
+        data.storage.commit()?;
+        db.notify_commit(table);
+        Ok(updated.new_ids.len())
+    }
+
+    pub(crate) fn data(&self) -> &TableData {
+        match &self.held {
+            HeldLock::Shared(data) => data,
+            HeldLock::Exclusive(data) => data,
+        }
+    }
+
+    pub(crate) fn writable(&mut self) -> Result<(&'db Database, &str, &mut TableData), DbError> {
+        match &mut self.held {
+            HeldLock::Exclusive(data) => Ok((self.db, &self.table, data)),
+            HeldLock::Shared(_) => Err(DbError::UnsupportedOperation(format!("Writing {} needs an exclusive lock", self.table))),
+        }
+    }
+}
+
+impl Drop for TableLock<'_> {
+    fn drop(&mut self) {
+        self.db.lock_graph().release(&self.table);
+        HELD.set(HELD.get() - 1);
+    }
+}
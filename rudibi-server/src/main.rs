@@ -0,0 +1,247 @@
+// Serves a database over TCP, see `protocol` for the commands
+// Usage: rudibi-server [--config FILE] [--bind HOST] [--port PORT] [--data-dir DIR]
+//                      [--tables memory|disk] [--durability MODE] [--text | --pg | --resp]
+//                      [--workers N] [--commands N [--queue N]] [--async] [--log LEVEL]
+//                      [--tls-cert FILE --tls-key FILE] [--idle-timeout SECS] [--keepalive SECS]
+//                      [--metrics-port PORT] [--backlog N] [--primary HOST:PORT]
+//        rudibi-server --hash-password | --hash-token
+// Settings are read from FILE, or `rudibi.toml` if it's in the working directory, and the flags
+// override them, see `config` for what they mean and their defaults.
+// Listens on 127.0.0.1:1337 unless told otherwise. Tables are kept in DIR, or only in memory if
+// it isn't given. With --text, connections speak the text mode rather than frames, with --pg the
+// PostgreSQL protocol, see `pgwire`, and with --resp the Redis protocol, see `resp`. Up to
+// --workers connections are served at once, others wait to be accepted.
+// With --commands, commands run on a pool of that many threads rather than on the connections'
+// and fail as busy beyond --queue waiting, see `Server::with_pool`.
+// With --async, when built with the async-server feature, frames and text mode are served on
+// tokio instead, see `async_server`, running up to --commands commands at once.
+// With --tls-cert and --tls-key, when built with the tls feature, connections are only served over
+// TLS, see `tls`.
+// With --idle-timeout, connections sending nothing for that long are closed, and with --keepalive
+// TCP keepalive probes connections quiet for that long, see `tcp`.
+// With --metrics-port, GET /metrics on that port is answered with the server's metrics for
+// Prometheus, see `metrics`.
+// With users or tokens in the settings, connections must authenticate before running commands,
+// see `auth`. --hash-password and --hash-token print the hash to put there of a password or token
+// read from stdin. Roles given to them say what they may do to each table, see `permissions`.
+// Rate limits in the settings keep each connection, or user, from running queries faster than
+// that, see `throttle`.
+// With --backlog, the server keeps that many of its latest changes for replicas to follow. With
+// --primary, it's a read-only replica of the primary serving frames there, bootstrapped from a
+// snapshot of its tables then following its changes, connecting again whenever the connection
+// ends, see `replication`.
+
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::auth::{hash_password, hash_token};
+use rudibi_server::config::{LogLevel, Protocol, ServerConfig};
+use rudibi_server::engine::Database;
+use rudibi_server::{metrics, pgwire, replication, resp};
+use rudibi_server::protocol::Server;
+use rudibi_server::tcp;
+use rudibi_server::workers::WorkerPool;
+
+// How long a replica waits before connecting to its primary again
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+fn main() {
+    match std::env::args().nth(1).as_deref() {
+        Some("--hash-password") => return print_hash(hash_password),
+        Some("--hash-token") => return print_hash(hash_token),
+        _ => {},
+    }
+    let config = ServerConfig::from_args(std::env::args().skip(1)).unwrap_or_else(|err| panic!("Bad configuration: {err:?}"));
+    let mut db = match &config.data_dir {
+        Some(dir) => Database::open_dir(dir).unwrap_or_else(|err| panic!("Failed to open {dir}: {err:?}")),
+        None => Database::new(),
+    };
+    db.set_query_memory_limit(config.query_memory);
+    db.set_disk_budget(config.disk_budget);
+    db.set_soft_limits(config.soft_limits);
+    let storage = config.storage();
+    let address = (config.bind.as_str(), config.port);
+    let tls = load_tls(&config);
+    let authenticated = |mut server: Server| {
+        if !config.credentials.is_empty() {
+            server = server.with_credentials(config.credentials.clone());
+        }
+        if !config.permissions.is_empty() {
+            server = server.with_permissions(config.permissions.clone());
+        }
+        if let Some(backlog) = config.replication_backlog {
+            server = server.with_change_feed(backlog);
+        }
+        if config.primary.is_some() {
+            server = server.as_replica();
+        }
+        server.with_rate_limits(config.connection_rates, config.user_rates)
+            .with_max_cursors(config.max_cursors)
+            .with_insert_budget(config.insert_budget)
+    };
+    if config.asynchronous {
+        assert!(tls.is_none(), "--async doesn't serve TLS");
+        return serve_async(authenticated(Server::new(db, storage)), &config);
+    }
+    let server = Arc::new(authenticated(match config.commands {
+        Some(commands) => Server::with_pool(db, storage, commands, config.queue),
+        None => Server::new(db, storage),
+    }));
+    if let Some(listener) = metrics_listener(&config) {
+        let server = server.clone();
+        thread::spawn(move || metrics::serve_http(&listener, || server.metrics()));
+    }
+    if let Some(primary) = config.primary.clone() {
+        let (server, token, log) = (server.clone(), config.primary_token.clone(), config.log);
+        thread::spawn(move || follow(&server, &primary, token.as_deref(), log));
+    }
+    let listener = TcpListener::bind(address).unwrap_or_else(|err| panic!("Failed to listen on {}:{}: {err}", address.0, address.1));
+    if config.log >= LogLevel::Info {
+        eprintln!("Listening on {}:{}", address.0, address.1);
+    }
+
+    let (protocol, log, settings) = (config.protocol, config.log, config.tcp);
+    let pool = WorkerPool::new(config.workers, 0, move |conn: TcpStream| {
+        if log >= LogLevel::Info {
+            eprintln!("Serving {}", conn.peer_addr().map_or("a closed connection".to_string(), |peer| peer.to_string()));
+        }
+        let served = settings.apply(&conn).and_then(|()| match &tls {
+            Some(tls) => serve_tls(&server, protocol, tls, conn),
+            None => serve(&server, protocol, &conn, &conn),
+        });
+        match served {
+            Err(err) if tcp::timed_out(&err) && log >= LogLevel::Info => eprintln!("Closed an idle connection"),
+            Err(err) if !tcp::timed_out(&err) && log >= LogLevel::Error => eprintln!("Connection ended: {err}"),
+            _ => {},
+        }
+    });
+    for conn in listener.incoming() {
+        match conn {
+            Ok(conn) => pool.submit(conn),
+            Err(err) if log >= LogLevel::Error => eprintln!("Failed to accept a connection: {err}"),
+            Err(_) => {},
+        }
+    }
+}
+
+fn metrics_listener(config: &ServerConfig) -> Option<TcpListener> {
+    let address = (config.bind.as_str(), config.metrics_port?);
+    let listener = TcpListener::bind(address).unwrap_or_else(|err| panic!("Failed to serve metrics on {}:{}: {err}", address.0, address.1));
+    if config.log >= LogLevel::Info {
+        eprintln!("Serving metrics on {}:{}", address.0, address.1);
+    }
+    Some(listener)
+}
+
+// Keeps the server a replica of `primary` for ever
+fn follow(server: &Server, primary: &str, token: Option<&str>, log: LogLevel) {
+    loop {
+        let replicated = TcpStream::connect(primary).and_then(|conn| {
+            if log >= LogLevel::Info {
+                eprintln!("Replicating {primary}");
+            }
+            replication::replicate(server, &conn, &conn, token)
+        });
+        match replicated {
+            Err(err) if log >= LogLevel::Error => eprintln!("Replication from {primary} failed: {err}"),
+            Ok(()) if log >= LogLevel::Info => eprintln!("Primary {primary} closed the connection"),
+            _ => {},
+        }
+        thread::sleep(RECONNECT_DELAY);
+    }
+}
+
+// The line read from stdin, without its line break
+fn print_hash(hash: fn(&str) -> String) {
+    let mut secret = String::new();
+    io::stdin().read_line(&mut secret).expect("Failed to read from stdin");
+    println!("{}", hash(secret.trim_end_matches(['\r', '\n'])));
+}
+
+fn serve(server: &Server, protocol: Protocol, input: impl Read, output: impl Write) -> io::Result<()> {
+    match protocol {
+        Protocol::Frames => server.serve(input, output),
+        Protocol::Text => server.serve_text(BufReader::new(input), output),
+        Protocol::Postgres => pgwire::serve(server, BufReader::new(input), output),
+        Protocol::Resp => resp::serve(server, BufReader::new(input), output),
+    }
+}
+
+#[cfg(feature = "tls")]
+type Tls = rudibi_server::tls::TlsConfig;
+// Never made without the feature
+#[cfg(not(feature = "tls"))]
+type Tls = std::convert::Infallible;
+
+#[cfg(feature = "tls")]
+fn load_tls(config: &ServerConfig) -> Option<Tls> {
+    let (cert, key) = (config.tls_cert.as_ref()?, config.tls_key.as_ref()?);
+    Some(rudibi_server::tls::load_config(cert, key).unwrap_or_else(|err| panic!("Failed to set up TLS: {err:?}")))
+}
+
+#[cfg(not(feature = "tls"))]
+fn load_tls(config: &ServerConfig) -> Option<Tls> {
+    assert!(config.tls_cert.is_none(), "TLS needs the tls feature");
+    None
+}
+
+#[cfg(feature = "tls")]
+fn serve_tls(server: &Server, protocol: Protocol, tls: &Tls, conn: TcpStream) -> io::Result<()> {
+    use rudibi_server::tls::TlsStream;
+
+    let stream = match protocol {
+        Protocol::Postgres => match TlsStream::accept_postgres(tls, server, conn)? {
+            Some(stream) => stream,
+            None => return Ok(()),
+        },
+        _ => TlsStream::accept(tls, conn)?,
+    };
+    serve(server, protocol, &stream, &stream)
+}
+
+#[cfg(not(feature = "tls"))]
+fn serve_tls(_: &Server, _: Protocol, tls: &Tls, _: TcpStream) -> io::Result<()> {
+    match *tls {}
+}
+
+#[cfg(feature = "async-server")]
+fn serve_async(server: Server, config: &ServerConfig) {
+    use rudibi_server::async_server::{AsyncServer, AsyncServerConfig};
+
+    let text = match config.protocol {
+        Protocol::Frames => false,
+        Protocol::Text => true,
+        Protocol::Postgres | Protocol::Resp => panic!("--async only serves frames and text mode"),
+    };
+    let defaults = AsyncServerConfig::default();
+    let async_config = AsyncServerConfig { max_commands: config.commands.unwrap_or(defaults.max_commands), text, log: config.log, tcp: config.tcp, ..defaults };
+    let address = (config.bind.as_str(), config.port);
+    let server = AsyncServer::new(server, async_config);
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start tokio");
+    thread::scope(|scope| {
+        if let Some(listener) = metrics_listener(config) {
+            let server = &server;
+            scope.spawn(move || metrics::serve_http(&listener, || server.server().metrics()));
+        }
+        if let Some(primary) = &config.primary {
+            let server = &server;
+            scope.spawn(move || follow(server.server(), primary, config.primary_token.as_deref(), config.log));
+        }
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind(address).await
+                .unwrap_or_else(|err| panic!("Failed to listen on {}:{}: {err}", address.0, address.1));
+            if config.log >= LogLevel::Info {
+                eprintln!("Listening on {}:{}", address.0, address.1);
+            }
+            server.listen(listener).await;
+        });
+    });
+}
+
+#[cfg(not(feature = "async-server"))]
+fn serve_async(_: Server, _: &ServerConfig) {
+    panic!("--async needs the async-server feature");
+}
@@ -0,0 +1,125 @@
+// Periodic maintenance: compaction, expiry of rows past their time to live, refresh of the
+// tables' stats and WAL checkpoints
+// A worker thread owned by the database runs it every interval, concurrently with the queries.
+// The worker only holds the database weakly, so dropping the database's last `Arc` ends it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::engine::{Database, DbError};
+use crate::wal::WalWriter;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ttl {
+    pub table: String,
+    // U32 column with the row's time in seconds since the Unix epoch
+    pub column: String,
+    pub max_age: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct MaintenanceConfig {
+    pub interval: Duration,
+    // Compact every table
+    pub compact: bool,
+    pub ttls: Vec<Ttl>,
+    // Take the stats of every table, see `Database::last_table_stats`
+    pub refresh_stats: bool,
+    // Checkpoint the database's log, see `Database::checkpoint`
+    pub checkpoint: Option<Arc<WalWriter>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MaintenanceReport {
+    pub rows_expired: usize,
+    pub tables_compacted: usize,
+    pub stats_refreshed: usize,
+    // Sequence of the checkpoint taken, None if there was none or a table was locked
+    pub checkpoint: Option<u64>,
+}
+
+// Pauses or stops the worker, clones control the same one
+#[derive(Debug, Clone)]
+pub struct MaintenanceHandle {
+    paused: Arc<AtomicBool>,
+    stopped: Arc<AtomicBool>,
+    last_run: Arc<Mutex<Option<Result<MaintenanceReport, DbError>>>>,
+    thread: thread::Thread,
+}
+
+impl MaintenanceHandle {
+    // Runs already due still happen, new ones aren't scheduled until resumed
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    // For good, starting maintenance again gives a new worker
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+
+    // What the last run did, None before the first
+    pub fn last_run(&self) -> Option<Result<MaintenanceReport, DbError>> {
+        self.last_run.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+}
+
+pub(crate) struct Worker {
+    handle: MaintenanceHandle,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+
+    pub(crate) fn start(config: MaintenanceConfig, db: Weak<Database>) -> Worker {
+        let paused = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let last_run = Arc::new(Mutex::new(None));
+        let (thread_paused, thread_stopped, thread_last_run) = (paused.clone(), stopped.clone(), last_run.clone());
+        let thread = thread::spawn(move || {
+            while !thread_stopped.load(Ordering::SeqCst) {
+                thread::park_timeout(config.interval);
+                if thread_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if thread_paused.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let Some(db) = db.upgrade() else { break };
+                let report = db.run_maintenance(&config);
+                *thread_last_run.lock().unwrap_or_else(PoisonError::into_inner) = Some(report);
+            }
+        });
+        let handle = MaintenanceHandle { paused, stopped, last_run, thread: thread.thread().clone() };
+        Worker { handle, thread: Some(thread) }
+    }
+
+    pub(crate) fn handle(&self) -> MaintenanceHandle {
+        self.handle.clone()
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        self.handle.stop();
+        // The worker drops the database itself if it held the last reference during a run
+        if let Some(thread) = self.thread.take().filter(|thread| thread.thread().id() != thread::current().id()) {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// Rows of results and inserts mapped to an application's structs, so it doesn't handle the bytes
+// of columns. With the derive feature, `#[derive(FromRow, ToRow)]` implements both for a struct
+// with named fields, each the column of the same name, or of the name given to it with
+// `#[rudibi(rename = "...")]`:
+//   #[derive(FromRow, ToRow)]
+//   struct Fruit { id: u32, #[rudibi(rename = "name")] label: String }
+//   db.insert("Fruits", Fruit::COLUMNS, &[kiwi.to_row()])?;
+//   let fruits: Vec<Fruit> = db.select(&values, "Fruits", &filter)?.rows_as()?;
+// Fields are of the types a column's value converts to, see `ResultSet::get`: u32, f64, String,
+// Vec<u8> and Uuid, an enum's label being a String.
+
+pub use rudibi_core::mapping::{FromRow, ToRow};
+
+#[cfg(feature = "derive")]
+pub use rudibi_derive::{FromRow, ToRow};
@@ -0,0 +1,160 @@
+// Counters and histograms of what a server does, in the Prometheus text format, for dashboards
+// and alerts. `Server::metrics` writes them, `serve_http` answers scrapes of /metrics with them:
+//   rudibi_queries_total{command="select"}              commands run, by their first keyword
+//   rudibi_query_errors_total{command="select"}         those that failed
+//   rudibi_query_duration_seconds{command="select"}     histogram of how long they took
+//   rudibi_rows_inserted_total, rudibi_rows_deleted_total, rudibi_rows_scanned_total
+//   rudibi_plan_cache_hits_total, rudibi_plan_cache_misses_total
+//   rudibi_table_rows{table="Fruits"}, rudibi_table_disk_bytes{table="Fruits"}
+//   rudibi_connections, rudibi_queries_running, rudibi_uptime_seconds
+// Commands that don't parse are counted as `invalid`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+// Upper bounds of the duration histograms' buckets, in seconds
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0, 5.0, 30.0];
+// A scrape sending nothing for this long is dropped, so it can't hold up the next
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+// Longest request line and headers of a scrape
+const HTTP_MAX_BYTES: usize = 8 << 10;
+
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    commands: BTreeMap<&'static str, CommandMetrics>,
+    rows_inserted: u64,
+    rows_deleted: u64,
+    rows_scanned: u64,
+}
+
+#[derive(Default)]
+struct CommandMetrics {
+    count: u64,
+    errors: u64,
+    // Of each bucket alone, summed up when written
+    buckets: [u64; BUCKETS.len()],
+    seconds: f64,
+}
+
+// What a query changed or read, for the row counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RowCounts {
+    pub inserted: usize,
+    pub deleted: usize,
+    pub scanned: usize,
+}
+
+impl Metrics {
+
+    pub fn record(&self, command: &'static str, took: Duration, failed: bool, rows: RowCounts) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.rows_inserted += rows.inserted as u64;
+        state.rows_deleted += rows.deleted as u64;
+        state.rows_scanned += rows.scanned as u64;
+        let metrics = state.commands.entry(command).or_default();
+        metrics.count += 1;
+        metrics.errors += failed as u64;
+        let seconds = took.as_secs_f64();
+        metrics.seconds += seconds;
+        if let Some(bucket) = BUCKETS.iter().position(|&bound| seconds <= bound) {
+            metrics.buckets[bucket] += 1;
+        }
+    }
+
+    // The counters and histograms, gauges are written by the caller with `gauge`
+    pub fn write_to(&self, out: &mut String) {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        header(out, "rudibi_queries_total", "counter", "Commands run");
+        for (command, metrics) in &state.commands {
+            sample(out, "rudibi_queries_total", &[("command", command)], metrics.count as f64);
+        }
+        header(out, "rudibi_query_errors_total", "counter", "Commands that failed");
+        for (command, metrics) in &state.commands {
+            sample(out, "rudibi_query_errors_total", &[("command", command)], metrics.errors as f64);
+        }
+        header(out, "rudibi_query_duration_seconds", "histogram", "How long commands took");
+        for (command, metrics) in &state.commands {
+            let mut below = 0;
+            for (bound, count) in BUCKETS.iter().zip(metrics.buckets) {
+                below += count;
+                sample(out, "rudibi_query_duration_seconds_bucket", &[("command", command), ("le", &bound.to_string())], below as f64);
+            }
+            sample(out, "rudibi_query_duration_seconds_bucket", &[("command", command), ("le", "+Inf")], metrics.count as f64);
+            sample(out, "rudibi_query_duration_seconds_sum", &[("command", command)], metrics.seconds);
+            sample(out, "rudibi_query_duration_seconds_count", &[("command", command)], metrics.count as f64);
+        }
+        counter(out, "rudibi_rows_inserted_total", "Rows inserted", state.rows_inserted);
+        counter(out, "rudibi_rows_deleted_total", "Rows deleted", state.rows_deleted);
+        counter(out, "rudibi_rows_scanned_total", "Rows read by selects", state.rows_scanned);
+    }
+}
+
+pub fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    header(out, name, "counter", help);
+    sample(out, name, &[], value as f64);
+}
+
+// One sample for each of `values`, as (label value, value), labelled `label`
+pub fn gauge(out: &mut String, name: &str, help: &str, label: &str, values: &[(&str, f64)]) {
+    header(out, name, "gauge", help);
+    for (label_value, value) in values {
+        sample(out, name, &[(label, label_value)], *value);
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n");
+}
+
+// Labels with an empty name are left out
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: f64) {
+    out.push_str(name);
+    let labels: Vec<String> = labels.iter().filter(|(label, _)| !label.is_empty())
+        .map(|(label, value)| format!("{label}=\"{}\"", escape(value)))
+        .collect();
+    if !labels.is_empty() {
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {value}");
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+// Answers each GET of /metrics accepted on `listener` with what `metrics` writes, one scrape at a
+// time, for ever
+pub fn serve_http(listener: &TcpListener, metrics: impl Fn() -> String) {
+    for conn in listener.incoming().flatten() {
+        let _ = reply_http(conn, &metrics);
+    }
+}
+
+fn reply_http(conn: TcpStream, metrics: &impl Fn() -> String) -> io::Result<()> {
+    conn.set_read_timeout(Some(HTTP_TIMEOUT))?;
+    let mut input = BufReader::new(&conn).take(HTTP_MAX_BYTES as u64);
+    let mut request_line = String::new();
+    input.read_line(&mut request_line)?;
+    // The headers, up to the empty line ending them
+    let mut header = String::new();
+    while input.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    let (status, body) = match request_line.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", metrics()),
+        ["GET", _] => ("404 Not Found", "Only /metrics is served\n".to_string()),
+        _ => ("405 Method Not Allowed", "Only GET is served\n".to_string()),
+    };
+    let mut output = &conn;
+    write!(output, "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())?;
+    output.flush()
+}
@@ -0,0 +1,486 @@
+// Table storage in an object store, so the data outlives the instance
+// New rows are kept in memory until a commit seals them into an immutable segment object. A
+// manifest object lists the segments and the ids deleted from them, it's rewritten on every
+// commit that changed it. Segments are fetched when first read, and kept in a local cache
+// directory if there is one, which never goes stale since segments don't change.
+// The crate has no network client, `DirObjectStore` here covers a store mounted as a directory,
+// any other store, e.g. an S3 bucket, needs its own `ObjectStore` implementation.
+
+use std::sync::OnceLock;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use crate::dtype::{decode_field, encode_field, take, take_u64};
+use crate::engine::{DbError, Row, Table};
+use crate::storage::{InMemoryStorage, MemoryUsage, MonotonicIds, ReplayIds, RowId, ScanItem, Storage, TableIterator};
+
+const SEGMENT_MAGIC: &[u8; 4] = b"RDBS";
+const MANIFEST_MAGIC: &[u8; 4] = b"RDBM";
+const MANIFEST_VERSION: u32 = 1;
+
+pub trait ObjectStore: Send + Sync {
+    // Replaces the object if there is one
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), DbError>;
+    // None if there's no object under the key
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError>;
+    fn delete(&self, key: &str) -> Result<(), DbError>;
+}
+
+// Objects as files under a directory, e.g. a mounted bucket
+pub struct DirObjectStore {
+    root: PathBuf,
+}
+
+impl DirObjectStore {
+    pub fn new(root: &str) -> Self {
+        DirObjectStore { root: PathBuf::from(root) }
+    }
+}
+
+fn object_err(key: &str, err: std::io::Error) -> DbError {
+    DbError::StorageError(format!("Failed to access object {key}: {err}"))
+}
+
+impl ObjectStore for DirObjectStore {
+    // Through a temporary file, so readers never see a partial object
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), DbError> {
+        let path = self.root.join(key);
+        let tmp = path.with_extension("tmp");
+        std::fs::create_dir_all(path.parent().unwrap()).map_err(|err| object_err(key, err))?;
+        std::fs::write(&tmp, data).map_err(|err| object_err(key, err))?;
+        std::fs::rename(&tmp, &path).map_err(|err| object_err(key, err))
+    }
+
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+        match std::fs::read(self.root.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(object_err(key, err)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), DbError> {
+        match std::fs::remove_file(self.root.join(key)) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(object_err(key, err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+struct Segment {
+    key: String,
+    first_id: RowId,
+    last_id: RowId,
+    // Loaded on first access, without the rows deleted before
+    rows: OnceLock<InMemoryStorage>,
+}
+
+pub struct ObjectStorage {
+    schema: Table,
+    store: Box<dyn ObjectStore>,
+    // Keys of the table's objects start with it
+    prefix: String,
+    cache_dir: Option<PathBuf>,
+    hot: InMemoryStorage,
+    segments: Vec<Segment>,
+    next_segment: u64,
+    // Ids deleted from sealed segments
+    tombstones: BTreeSet<RowId>,
+    sealed_rows: usize,
+    // Manifest differs from the one stored
+    dirty: bool,
+}
+
+impl ObjectStorage {
+
+    // Opens the table stored under `prefix`, or starts an empty one
+    pub fn open(schema: Table, store: Box<dyn ObjectStore>, prefix: &str) -> Result<Self, DbError> {
+        let mut storage = ObjectStorage {
+            hot: InMemoryStorage::new(schema.clone()),
+            schema,
+            store,
+            prefix: prefix.to_string(),
+            cache_dir: None,
+            segments: Vec::new(),
+            next_segment: 0,
+            tombstones: BTreeSet::new(),
+            sealed_rows: 0,
+            dirty: false,
+        };
+        if let Some(bytes) = storage.store.get(&storage.manifest_key())? {
+            storage.decode_manifest(&bytes)
+                .ok_or_else(|| DbError::DatabaseIntegrityError(format!("Manifest of {prefix} is malformed")))?;
+        }
+        let next_id = storage.segments.last().map_or(0, |seg| seg.last_id + 1);
+        storage.hot = InMemoryStorage::with_ids(storage.schema.clone(), Box::new(MonotonicIds::starting_at(next_id)));
+        Ok(storage)
+    }
+
+    // Keeps a copy of fetched segments in `dir`
+    pub fn with_cache_dir(mut self, dir: &str) -> Self {
+        self.cache_dir = Some(PathBuf::from(dir));
+        self
+    }
+
+    // Segments stored, empty ones not included
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    fn manifest_key(&self) -> String {
+        format!("{}/manifest", self.prefix)
+    }
+
+    fn column_mapping(&self) -> Vec<usize> {
+        (0..self.schema.column_layout.len()).collect()
+    }
+
+    fn segment_rows(&self, seg_idx: usize) -> &InMemoryStorage {
+        let seg = &self.segments[seg_idx];
+        // Scans and deletes have no error path in `Storage`, the rest loads with `load_segment`
+        seg.rows.get_or_init(|| self.load_segment(seg).expect("Segment must be readable"))
+    }
+
+    fn load_segment(&self, seg: &Segment) -> Result<InMemoryStorage, DbError> {
+        let cached = self.cache_dir.as_ref().map(|dir| dir.join(&seg.key));
+        let bytes = match cached.as_ref().and_then(|path| std::fs::read(path).ok()) {
+            Some(bytes) => bytes,
+            None => {
+                let bytes = self.store.get(&seg.key)?
+                    .ok_or_else(|| DbError::DatabaseIntegrityError(format!("Segment {} is missing", seg.key)))?;
+                // A failed cache write only costs a later fetch
+                if let Some(path) = &cached && std::fs::create_dir_all(path.parent().unwrap()).is_ok() {
+                    let _ = std::fs::write(path, &bytes);
+                }
+                bytes
+            },
+        };
+        let (ids, rows) = decode_segment(&bytes, self.schema.column_layout.len())
+            .ok_or_else(|| DbError::DatabaseIntegrityError(format!("Segment {} is malformed", seg.key)))?;
+        let (ids, rows): (Vec<RowId>, Vec<Row>) = ids.into_iter().zip(rows)
+            .filter(|(id, _)| !self.tombstones.contains(id))
+            .unzip();
+        let mut storage = InMemoryStorage::with_ids(self.schema.clone(), Box::new(ReplayIds::of(ids)));
+        storage.store(&rows, &self.column_mapping());
+        Ok(storage)
+    }
+
+    // Segment that would hold the id, if any
+    fn segment_of(&self, row_id: RowId) -> Option<usize> {
+        let seg_idx = self.segments.partition_point(|seg| seg.last_id < row_id);
+        self.segments.get(seg_idx).filter(|seg| seg.first_id <= row_id).map(|_| seg_idx)
+    }
+
+    // Writes the live hot rows as a new segment, with their ids
+    fn seal(&mut self) -> Result<(), DbError> {
+        if self.hot.row_count() == 0 {
+            return Ok(());
+        }
+        let ids: Vec<RowId> = self.hot.scan().map(|item| item.row_id).collect();
+        let key = format!("{}/seg-{}", self.prefix, self.next_segment);
+        self.store.put(&key, &encode_segment(self.hot.scan()))?;
+        self.next_segment += 1;
+        self.sealed_rows += ids.len();
+        let allocator = self.hot.replace_ids(Box::new(ReplayIds::none()));
+        let sealed = std::mem::replace(&mut self.hot, InMemoryStorage::with_ids(self.schema.clone(), allocator));
+        self.segments.push(Segment { key, first_id: ids[0], last_id: *ids.last().unwrap(), rows: OnceLock::from(sealed) });
+        self.dirty = true;
+        Ok(())
+    }
+
+    // Merges the sealed segments into one, dropping deleted rows for good
+    fn merge_segments(&mut self) -> Result<(), DbError> {
+        if self.segments.len() < 2 && self.tombstones.is_empty() {
+            return Ok(());
+        }
+        let mut ids = Vec::new();
+        let mut rows = Vec::new();
+        for seg in &self.segments {
+            let loaded;
+            let seg_rows = match seg.rows.get() {
+                Some(seg_rows) => seg_rows,
+                None => {
+                    loaded = self.load_segment(seg)?;
+                    &loaded
+                },
+            };
+            for item in seg_rows.scan() {
+                ids.push(item.row_id);
+                rows.push(Row { data: item.row_content.data.to_vec(), offsets: item.row_content.offsets.to_vec() });
+            }
+        }
+        // Until the merged segment is stored a failure leaves the segments as they were
+        let mut merged_segments = Vec::new();
+        if !ids.is_empty() {
+            let mut merged = InMemoryStorage::with_ids(self.schema.clone(), Box::new(ReplayIds::of(ids.clone())));
+            merged.store(&rows, &self.column_mapping());
+            let key = format!("{}/seg-{}", self.prefix, self.next_segment);
+            self.store.put(&key, &encode_segment(merged.scan()))?;
+            self.next_segment += 1;
+            merged_segments.push(Segment { key, first_id: ids[0], last_id: *ids.last().unwrap(), rows: OnceLock::from(merged) });
+        }
+        let old_keys: Vec<String> = std::mem::replace(&mut self.segments, merged_segments)
+            .into_iter().map(|seg| seg.key).collect();
+        self.tombstones.clear();
+        self.dirty = true;
+        self.store.put(&self.manifest_key(), &self.encode_manifest())?;
+        self.dirty = false;
+        // Only unreferenced once the new manifest is stored
+        for key in old_keys {
+            self.store.delete(&key)?;
+            if let Some(dir) = &self.cache_dir {
+                let _ = std::fs::remove_file(dir.join(&key));
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_manifest(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MANIFEST_MAGIC);
+        out.extend_from_slice(&MANIFEST_VERSION.to_le_bytes());
+        out.extend_from_slice(&self.next_segment.to_le_bytes());
+        out.extend_from_slice(&(self.sealed_rows as u64).to_le_bytes());
+        out.extend_from_slice(&(self.segments.len() as u64).to_le_bytes());
+        for seg in &self.segments {
+            encode_field(&mut out, seg.key.as_bytes());
+            out.extend_from_slice(&seg.first_id.to_le_bytes());
+            out.extend_from_slice(&seg.last_id.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.tombstones.len() as u64).to_le_bytes());
+        for id in &self.tombstones {
+            out.extend_from_slice(&id.to_le_bytes());
+        }
+        out
+    }
+
+    fn decode_manifest(&mut self, mut bytes: &[u8]) -> Option<()> {
+        if take(&mut bytes, 4)? != MANIFEST_MAGIC || take(&mut bytes, 4)? != MANIFEST_VERSION.to_le_bytes() {
+            return None;
+        }
+        self.next_segment = take_u64(&mut bytes)?;
+        self.sealed_rows = usize::try_from(take_u64(&mut bytes)?).ok()?;
+        for _ in 0..take_u64(&mut bytes)? {
+            let key = String::from_utf8(decode_field(&mut bytes)?.to_vec()).ok()?;
+            let first_id = take_u64(&mut bytes)?;
+            let last_id = take_u64(&mut bytes)?;
+            self.segments.push(Segment { key, first_id, last_id, rows: OnceLock::new() });
+        }
+        for _ in 0..take_u64(&mut bytes)? {
+            self.tombstones.insert(take_u64(&mut bytes)?);
+        }
+        bytes.is_empty().then_some(())
+    }
+}
+
+// Row count, then each row's id and columns
+fn encode_segment(rows: TableIterator) -> Vec<u8> {
+    let mut body = Vec::new();
+    let mut count = 0u64;
+    for item in rows {
+        body.extend_from_slice(&item.row_id.to_le_bytes());
+        for col_idx in 0..item.row_content.offsets.len() - 1 {
+            encode_field(&mut body, item.row_content.get_column(col_idx));
+        }
+        count += 1;
+    }
+    let mut out = Vec::with_capacity(body.len() + 12);
+    out.extend_from_slice(SEGMENT_MAGIC);
+    out.extend_from_slice(&count.to_le_bytes());
+    out.extend_from_slice(&body);
+    out
+}
+
+fn decode_segment(mut bytes: &[u8], columns: usize) -> Option<(Vec<RowId>, Vec<Row>)> {
+    if take(&mut bytes, 4)? != SEGMENT_MAGIC {
+        return None;
+    }
+    let mut ids = Vec::new();
+    let mut rows = Vec::new();
+    for _ in 0..take_u64(&mut bytes)? {
+        ids.push(take_u64(&mut bytes)?);
+        let fields = (0..columns).map(|_| decode_field(&mut bytes)).collect::<Option<Vec<_>>>()?;
+        rows.push(Row::of_columns(&fields));
+    }
+    bytes.is_empty().then_some((ids, rows))
+}
+
+impl Storage for ObjectStorage {
+
+    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) -> Vec<RowId> {
+        self.hot.store(rows, column_mapping)
+    }
+
+    // Segments hold increasing ids, and the hot rows come after all of them
+    fn scan(&self) -> TableIterator<'_> {
+        TableIterator::new(Box::new(
+            (0..self.segments.len())
+                .flat_map(move |seg_idx| self.segment_rows(seg_idx).scan())
+                .chain(self.hot.scan())
+        ))
+    }
+
+    fn get_rows<'a>(&'a self, row_ids: &'a [RowId]) -> TableIterator<'a> {
+        TableIterator::new(Box::new(row_ids.iter().filter_map(move |id| self.get_row(*id))))
+    }
+
+    // Fetches only the segment that can hold the row
+    fn get_row(&self, row_id: RowId) -> Option<ScanItem<'_>> {
+        match self.segment_of(row_id) {
+            Some(seg_idx) => self.segment_rows(seg_idx).get_row(row_id),
+            None => self.hot.get_row(row_id),
+        }
+    }
+
+    fn delete_rows(&mut self, row_ids: Vec<RowId>) {
+        let mut hot_ids = Vec::new();
+        for row_id in row_ids {
+            let Some(seg_idx) = self.segment_of(row_id) else {
+                hot_ids.push(row_id);
+                continue;
+            };
+            self.segment_rows(seg_idx);
+            let rows = self.segments[seg_idx].rows.get_mut().unwrap();
+            if rows.get_row(row_id).is_some() {
+                rows.delete_rows(vec![row_id]);
+                self.tombstones.insert(row_id);
+                self.sealed_rows -= 1;
+                self.dirty = true;
+            }
+        }
+        self.hot.delete_rows(hot_ids);
+    }
+
+    fn row_count(&self) -> usize {
+        self.sealed_rows + self.hot.row_count()
+    }
+
+    // Segments count once they're loaded
+    fn memory_usage(&self) -> MemoryUsage {
+        self.segments.iter()
+            .filter_map(|seg| seg.rows.get())
+            .fold(self.hot.memory_usage(), |usage, rows| usage + rows.memory_usage())
+    }
+
+    // Rows are durable once the commit returns
+    fn commit(&mut self) -> Result<(), DbError> {
+        self.seal()?;
+        if self.dirty {
+            self.store.put(&self.manifest_key(), &self.encode_manifest())?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), DbError> {
+        self.commit()
+    }
+
+    // Every commit seals a segment, merging them keeps reads from fetching many small objects.
+    // Compaction can't fail in `Storage`, a failed one leaves the rows to the next commit and
+    // the segments to the next compaction.
+    fn compact(&mut self) {
+        let _ = self.commit().and_then(|_| self.merge_segments());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use crate::testlib::{fruits_schema, random_temp_dir};
+
+    fn fruit(id: u32, name: &str) -> Row {
+        Row::of_columns(&[&id.to_le_bytes(), name.as_bytes()])
+    }
+
+    fn open(dir: &str) -> ObjectStorage {
+        ObjectStorage::open(fruits_schema(), Box::new(DirObjectStore::new(dir)), "fruits").unwrap()
+    }
+
+    fn scanned(storage: &ObjectStorage) -> Vec<RowId> {
+        storage.scan().map(|item| item.row_id).collect()
+    }
+
+    // Puts fail while the flag is set
+    struct FailingPuts(DirObjectStore, Arc<AtomicBool>);
+
+    impl ObjectStore for FailingPuts {
+        fn put(&self, key: &str, data: &[u8]) -> Result<(), DbError> {
+            if self.1.load(Ordering::SeqCst) {
+                return Err(DbError::StorageError(format!("Can't put {key}")));
+            }
+            self.0.put(key, data)
+        }
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbError> {
+            self.0.get(key)
+        }
+        fn delete(&self, key: &str) -> Result<(), DbError> {
+            self.0.delete(key)
+        }
+    }
+
+    #[test]
+    fn committed_rows_outlive_the_instance() {
+        let dir = random_temp_dir();
+        let mut storage = open(&dir);
+        let ids = storage.store(&[fruit(1, "apple"), fruit(2, "banana")], &vec![0, 1]);
+        storage.commit().unwrap();
+        storage.store(&[fruit(3, "cherry")], &vec![0, 1]);
+        drop(storage);
+
+        let mut reopened = open(&dir);
+        assert_eq!(scanned(&reopened), ids);
+        assert_eq!(reopened.get_row(ids[1]).unwrap().row_content.get_column(1), b"banana");
+        let more = reopened.store(&[fruit(4, "date")], &vec![0, 1]);
+        assert!(more[0] > ids[1]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_and_merges_persist() {
+        let dir = random_temp_dir();
+        let cache = random_temp_dir();
+        let mut storage = open(&dir).with_cache_dir(&cache);
+        let first = storage.store(&[fruit(1, "apple"), fruit(2, "banana")], &vec![0, 1]);
+        storage.commit().unwrap();
+        let second = storage.store(&[fruit(3, "cherry")], &vec![0, 1]);
+        storage.commit().unwrap();
+        storage.delete_rows(vec![first[0]]);
+        storage.commit().unwrap();
+        assert_eq!(open(&dir).row_count(), 2);
+        assert_eq!(scanned(&open(&dir).with_cache_dir(&cache)), vec![first[1], second[0]]);
+
+        storage.compact();
+        assert_eq!(storage.segment_count(), 1);
+        let reopened = open(&dir);
+        assert_eq!(reopened.segment_count(), 1);
+        assert_eq!(scanned(&reopened), vec![first[1], second[0]]);
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(cache).unwrap();
+    }
+
+    #[test]
+    fn failed_compaction_keeps_the_segments() {
+        let dir = random_temp_dir();
+        let failing = Arc::new(AtomicBool::new(false));
+        let store = FailingPuts(DirObjectStore::new(&dir), failing.clone());
+        let mut storage = ObjectStorage::open(fruits_schema(), Box::new(store), "fruits").unwrap();
+        let mut ids = storage.store(&[fruit(1, "apple")], &vec![0, 1]);
+        storage.commit().unwrap();
+        ids.extend(storage.store(&[fruit(2, "banana")], &vec![0, 1]));
+        storage.commit().unwrap();
+
+        failing.store(true, Ordering::SeqCst);
+        storage.compact();
+        assert_eq!(storage.segment_count(), 2);
+        assert_eq!(scanned(&storage), ids);
+
+        failing.store(false, Ordering::SeqCst);
+        storage.compact();
+        assert_eq!(storage.segment_count(), 1);
+        assert_eq!(scanned(&open(&dir)), ids);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
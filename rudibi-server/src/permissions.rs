@@ -0,0 +1,84 @@
+// What authenticated users may do to each table, see `Server::with_permissions`
+// Roles are granted privileges on tables, or on every table with `ALL_TABLES`, and users, the
+// names of credentials, are given roles. A user may do what any of their roles may, nothing else:
+//   analytics: read *           alice: analytics
+//   loader: read, write Orders  etl: analytics, loader
+// Admin commands, which tell about the whole server, need admin on `ALL_TABLES`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::engine::DbError;
+
+pub const ALL_TABLES: &str = "*";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Privilege {
+    // Selects, and describing the table
+    Read,
+    // Inserts and deletes
+    Write,
+    // Creating the table
+    Ddl,
+    // STATUS, STATS and LIST TABLES, see `protocol`
+    Admin,
+}
+
+impl Privilege {
+    pub fn parse(name: &str) -> Option<Privilege> {
+        match name.to_ascii_lowercase().as_str() {
+            "read" => Some(Privilege::Read),
+            "write" => Some(Privilege::Write),
+            "ddl" => Some(Privilege::Ddl),
+            "admin" => Some(Privilege::Admin),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Permissions {
+    roles: HashMap<String, HashSet<(Privilege, String)>>,
+    user_roles: HashMap<String, Vec<String>>,
+}
+
+impl Permissions {
+
+    pub fn grant(&mut self, role: &str, privilege: Privilege, table: &str) {
+        self.roles.entry(role.to_string()).or_default().insert((privilege, table.to_string()));
+    }
+
+    pub fn assign(&mut self, user: &str, role: &str) {
+        let roles = self.user_roles.entry(user.to_string()).or_default();
+        if !roles.iter().any(|name| name == role) {
+            roles.push(role.to_string());
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roles.is_empty() && self.user_roles.is_empty()
+    }
+
+    pub fn allows(&self, user: &str, privilege: Privilege, table: &str) -> bool {
+        self.user_roles.get(user).into_iter().flatten()
+            .filter_map(|role| self.roles.get(role))
+            .any(|grants| grants.contains(&(privilege, table.to_string())) || grants.contains(&(privilege, ALL_TABLES.to_string())))
+    }
+
+    pub fn check(&self, user: &str, privilege: Privilege, table: &str) -> Result<(), DbError> {
+        if self.allows(user, privilege, table) {
+            Ok(())
+        } else {
+            Err(DbError::PermissionDenied { user: user.to_string(), privilege, table: table.to_string() })
+        }
+    }
+
+    // Fails on a role given to a user but never granted anything, likely misspelled
+    pub fn validate(&self) -> Result<(), DbError> {
+        for (user, roles) in &self.user_roles {
+            if let Some(role) = roles.iter().find(|role| !self.roles.contains_key(*role)) {
+                return Err(DbError::InputError(format!("Unknown role {role} of {user}")));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+// Enough of the PostgreSQL frontend/backend protocol, version 3, for psql and drivers to connect
+// and run the commands of `protocol` as simple queries. A server with credentials, see `auth`, asks
+// for a cleartext password, the user's or a token named as the user, so clients should use TLS.
+// Without TLS encryption is declined. Rows are sent in text format. Queries are cancelled with the
+// key sent at startup, in a CancelRequest on another connection, see `cancel`. Selected rows are
+// sent as they're read rather than once they all are, see `Server::select_stream_as`. The extended query protocol, used for prepared
+// statements, is answered with an error.
+// Messages are a tag byte, then their length as a big-endian i32 counting itself and the body. The
+// startup message has no tag.
+
+use std::io::{self, ErrorKind, Read, Write};
+
+use crate::auth::Session;
+use crate::cancel::CancelKey;
+use crate::display::hex;
+use crate::dtype::{canonical_column, ColumnValue, DataType};
+use crate::engine::{Column, DbError, Row};
+use crate::frame::MAX_FRAME_BYTES;
+use crate::protocol::{is_select, statements, Reply, Server, STREAM_CHUNK_BYTES};
+use crate::telemetry;
+
+const PROTOCOL_VERSION: i32 = 3 << 16;
+pub(crate) const SSL_REQUEST: i32 = 80877103;
+const GSSENC_REQUEST: i32 = 80877104;
+pub(crate) const CANCEL_REQUEST: i32 = 80877102;
+
+// Type OIDs of the values sent, see pg_type
+const INT8: u32 = 20;
+const FLOAT8: u32 = 701;
+const TEXT: u32 = 25;
+const BYTEA: u32 = 17;
+const UUID: u32 = 2950;
+
+// Replies to the queries read from `input` until the client terminates or the stream ends. A
+// stream that doesn't follow the protocol ends the connection.
+pub fn serve(server: &Server, mut input: impl Read, mut output: impl Write) -> io::Result<()> {
+    let Some(session) = startup(server, &mut input, &mut output)? else { return Ok(()) };
+    // After an extended query message, the others up to the next Sync are ignored
+    let mut skipping = false;
+    while let Some((tag, body)) = read_message(&mut input)? {
+        let mut reply = Vec::new();
+        match tag {
+            b'Q' => {
+                query(server, &session, &body, &mut reply, &mut output)?;
+                ready_for_query(&mut reply);
+            },
+            b'X' => return Ok(()),
+            b'S' => {
+                skipping = false;
+                ready_for_query(&mut reply);
+            },
+            b'P' | b'B' | b'D' | b'E' | b'C' | b'H' | b'F' => {
+                if !skipping {
+                    error_response(&mut reply, "0A000", "Only simple queries are supported");
+                    skipping = true;
+                }
+            },
+            tag => {
+                error_response(&mut reply, "08P01", &format!("Unknown message type {:?}", tag as char));
+                ready_for_query(&mut reply);
+            },
+        }
+        output.write_all(&reply)?;
+        output.flush()?;
+    }
+    Ok(())
+}
+
+// None if the client only wanted to cancel a query, left before starting or failed to authenticate
+fn startup(server: &Server, input: &mut impl Read, output: &mut impl Write) -> io::Result<Option<Session>> {
+    let params = loop {
+        let Some(body) = read_body(input)? else { return Ok(None) };
+        if body.len() < 4 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Startup message without a version"));
+        }
+        match i32::from_be_bytes(body[..4].try_into().unwrap()) {
+            SSL_REQUEST | GSSENC_REQUEST => {
+                output.write_all(b"N")?;
+                output.flush()?;
+            },
+            CANCEL_REQUEST => {
+                if let Some(key) = cancel_key(&body[4..]) {
+                    server.cancel(key);
+                }
+                return Ok(None);
+            },
+            PROTOCOL_VERSION => break body[4..].to_vec(),
+            version => {
+                let mut reply = Vec::new();
+                error_response(&mut reply, "0A000", &format!("Unsupported protocol version {}.{}", version >> 16, version & 0xffff));
+                output.write_all(&reply)?;
+                output.flush()?;
+                return Ok(None);
+            },
+        }
+    };
+    let mut session = server.open_session();
+    if server.requires_auth() {
+        // Parameters are pairs of null-terminated names and values
+        let mut params = params.split(|&byte| byte == 0).map(String::from_utf8_lossy);
+        let user = std::iter::from_fn(|| Some((params.next()?, params.next()?))).find(|(name, _)| name == "user").map(|(_, user)| user);
+        let user = user.unwrap_or_default().into_owned();
+        let mut reply = Vec::new();
+        // AuthenticationCleartextPassword
+        message(&mut reply, b'R', |body| body.extend_from_slice(&3i32.to_be_bytes()));
+        output.write_all(&reply)?;
+        output.flush()?;
+        let password = match read_message(input)? {
+            Some((b'p', body)) => String::from_utf8_lossy(body.strip_suffix(b"\0").unwrap_or(&body)).into_owned(),
+            _ => return Ok(None),
+        };
+        if server.authenticate(&mut session, Some(&user), &password).is_err() {
+            let mut reply = Vec::new();
+            error_response(&mut reply, "28P01", &format!("Password authentication failed for user {user}"));
+            output.write_all(&reply)?;
+            output.flush()?;
+            return Ok(None);
+        }
+    }
+    let mut reply = Vec::new();
+    message(&mut reply, b'R', |body| body.extend_from_slice(&0i32.to_be_bytes()));
+    for (name, value) in [
+        ("server_version", "14.0"),
+        ("server_encoding", "UTF8"),
+        ("client_encoding", "UTF8"),
+        ("DateStyle", "ISO, MDY"),
+        ("integer_datetimes", "on"),
+        ("standard_conforming_strings", "on"),
+    ] {
+        message(&mut reply, b'S', |body| {
+            put_str(body, name);
+            put_str(body, value);
+        });
+    }
+    let key = session.cancel_key().expect("Sessions opened by the server can be cancelled");
+    message(&mut reply, b'K', |body| {
+        body.extend_from_slice(&key.id.to_be_bytes());
+        body.extend_from_slice(&key.secret.to_be_bytes());
+    });
+    ready_for_query(&mut reply);
+    output.write_all(&reply)?;
+    output.flush()?;
+    Ok(Some(session))
+}
+
+// Key of a CancelRequest, after its code: the process id and secret key of BackendKeyData
+pub(crate) fn cancel_key(body: &[u8]) -> Option<CancelKey> {
+    let body: [u8; 8] = body.try_into().ok()?;
+    Some(CancelKey { id: u32::from_be_bytes(body[..4].try_into().unwrap()), secret: u32::from_be_bytes(body[4..].try_into().unwrap()) })
+}
+
+// Runs the statements of a simple query in order, stopping at the first that fails. Rows of
+// selects are written to `output` once `reply` holds a chunk of them.
+fn query(server: &Server, session: &Session, body: &[u8], reply: &mut Vec<u8>, output: &mut impl Write) -> io::Result<()> {
+    let text = match std::str::from_utf8(body.strip_suffix(b"\0").unwrap_or(body)) {
+        Ok(text) => text,
+        Err(_) => {
+            error_response(reply, "22021", "Query isn't UTF-8");
+            return Ok(());
+        },
+    };
+    let mut empty = true;
+    for statement in statements(text) {
+        empty = false;
+        let _request = telemetry::request(None);
+        let done = if is_select(statement) {
+            stream_rows(server, session, statement, reply, output)?
+        } else {
+            server.handle_as(session, statement).map(|done| match done {
+                Reply::Created => command_complete(reply, "CREATE TABLE"),
+                Reply::Inserted(rows) => command_complete(reply, &format!("INSERT 0 {rows}")),
+                Reply::Deleted(rows) => command_complete(reply, &format!("DELETE {rows}")),
+                Reply::Declared => command_complete(reply, "DECLARE CURSOR"),
+                Reply::Closed => command_complete(reply, "CLOSE CURSOR"),
+                // Admin commands and fetches, selects are streamed
+                Reply::Selected(results) => {
+                    row_description(reply, &results.schema);
+                    for row in &results.data {
+                        data_row(reply, &results.schema, row);
+                    }
+                    command_complete(reply, &format!("SELECT {}", results.len()));
+                },
+            })
+        };
+        if let Err(err) = done {
+            error_response(reply, sql_state(&err), &format!("{err:?}"));
+            return Ok(());
+        }
+    }
+    if empty {
+        message(reply, b'I', |_| {});
+    }
+    Ok(())
+}
+
+// Sends the rows of a select as they're read, in chunks of about `STREAM_CHUNK_BYTES`
+fn stream_rows(server: &Server, session: &Session, statement: &str, reply: &mut Vec<u8>, output: &mut impl Write) -> io::Result<Result<(), DbError>> {
+    let rows = match server.select_stream_as(session, statement) {
+        Ok(rows) => rows,
+        Err(err) => return Ok(Err(err)),
+    };
+    let _serialize = telemetry::span("serialize");
+    let (schema, count) = (rows.schema.clone(), rows.len());
+    row_description(reply, &schema);
+    for row in rows {
+        let row = match row {
+            Ok(row) => row,
+            Err(err) => return Ok(Err(err)),
+        };
+        data_row(reply, &schema, &row);
+        if reply.len() >= STREAM_CHUNK_BYTES {
+            output.write_all(reply)?;
+            reply.clear();
+        }
+    }
+    command_complete(reply, &format!("SELECT {count}"));
+    Ok(Ok(()))
+}
+
+fn data_row(reply: &mut Vec<u8>, schema: &[Column], row: &Row) {
+    message(reply, b'D', |body| {
+        body.extend_from_slice(&(schema.len() as i16).to_be_bytes());
+        for (idx, column) in schema.iter().enumerate() {
+            let value = pg_text(&column.dtype, row.get_column(idx));
+            body.extend_from_slice(&(value.len() as i32).to_be_bytes());
+            body.extend_from_slice(value.as_bytes());
+        }
+    });
+}
+
+fn row_description(reply: &mut Vec<u8>, schema: &[Column]) {
+    message(reply, b'T', |body| {
+        body.extend_from_slice(&(schema.len() as i16).to_be_bytes());
+        for column in schema {
+            let (oid, size) = pg_type(&column.dtype);
+            put_str(body, &column.name);
+            // No table or column of it
+            body.extend_from_slice(&0u32.to_be_bytes());
+            body.extend_from_slice(&0i16.to_be_bytes());
+            body.extend_from_slice(&oid.to_be_bytes());
+            body.extend_from_slice(&size.to_be_bytes());
+            // Type modifier, then text format
+            body.extend_from_slice(&(-1i32).to_be_bytes());
+            body.extend_from_slice(&0i16.to_be_bytes());
+        }
+    });
+}
+
+// OID and size of the type a column is sent as, -1 for variable sizes
+fn pg_type(dtype: &DataType) -> (u32, i16) {
+    match dtype {
+        DataType::U32 => (INT8, 8),
+        DataType::F64 => (FLOAT8, 8),
+        DataType::UTF8 { .. } | DataType::ENUM { .. } => (TEXT, -1),
+        DataType::VARBINARY { .. } | DataType::BUFFER { .. } => (BYTEA, -1),
+        DataType::UUID => (UUID, 16),
+    }
+}
+
+// Stored bytes that can't be decoded as their data type are sent as bytea text
+fn pg_text(dtype: &DataType, data: &[u8]) -> String {
+    match canonical_column(dtype, data) {
+        Ok(ColumnValue::U32(val)) => val.to_string(),
+        Ok(ColumnValue::F64(val)) if val.is_nan() => "NaN".to_string(),
+        Ok(ColumnValue::F64(val)) if val.is_infinite() => if val > 0.0 { "Infinity" } else { "-Infinity" }.to_string(),
+        Ok(ColumnValue::F64(val)) => val.to_string(),
+        Ok(ColumnValue::UTF8(val)) => val.to_string(),
+        Ok(ColumnValue::Uuid(val)) => val.to_string(),
+        Ok(ColumnValue::Bytes(val)) => bytea(val),
+        Err(_) => bytea(data),
+    }
+}
+
+fn bytea(bytes: &[u8]) -> String {
+    format!("\\x{}", &hex(bytes)[2..])
+}
+
+// SQLSTATE codes of the errors, see the PostgreSQL errcodes appendix
+fn sql_state(err: &DbError) -> &'static str {
+    match err {
+        DbError::TableNotFound(_) => "42P01",
+        DbError::TableAlreadyExists(_) => "42P07",
+        DbError::ColumnNotFound(_) => "42703",
+        DbError::InputError(_) | DbError::InvalidColumnCount { .. } => "42601",
+        DbError::QueryError(_) => "42804",
+        DbError::InvalidEnumLabel { .. } => "22P02",
+        DbError::RowSizeExceeded { .. } | DbError::ColumnSizeOutOfBounds { .. } => "22001",
+        DbError::DuplicateKey { .. } => "23505",
+        DbError::QuotaExceeded { .. } => "53400",
+        DbError::UnsupportedOperation(_) => "0A000",
+        DbError::ServerBusy => "53000",
+        DbError::RateLimited { .. } => "53400",
+        DbError::ReadOnly => "25006",
+        DbError::AuthenticationFailed | DbError::NotAuthenticated => "28000",
+        DbError::PermissionDenied { .. } => "42501",
+        _ => "XX000",
+    }
+}
+
+fn ready_for_query(reply: &mut Vec<u8>) {
+    // Idle, outside of a transaction block
+    message(reply, b'Z', |body| body.push(b'I'));
+}
+
+fn command_complete(reply: &mut Vec<u8>, command_tag: &str) {
+    message(reply, b'C', |body| put_str(body, command_tag));
+}
+
+fn error_response(reply: &mut Vec<u8>, code: &str, text: &str) {
+    message(reply, b'E', |body| {
+        for (field, value) in [(b'S', "ERROR"), (b'V', "ERROR"), (b'C', code), (b'M', text)] {
+            body.push(field);
+            put_str(body, value);
+        }
+        body.push(0);
+    });
+}
+
+// Appends a message, filling in its length once the body is written
+fn message(reply: &mut Vec<u8>, tag: u8, body: impl FnOnce(&mut Vec<u8>)) {
+    reply.push(tag);
+    let start = reply.len();
+    reply.extend_from_slice(&[0; 4]);
+    body(reply);
+    let len = (reply.len() - start) as i32;
+    reply[start..start + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+fn put_str(body: &mut Vec<u8>, text: &str) {
+    body.extend_from_slice(text.as_bytes());
+    body.push(0);
+}
+
+// None if the stream ends before the next message starts
+fn read_message(input: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut tag = [0u8];
+    loop {
+        match input.read(&mut tag) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    match read_body(input)? {
+        Some(body) => Ok(Some((tag[0], body))),
+        None => Err(io::Error::new(ErrorKind::UnexpectedEof, "Message without a length")),
+    }
+}
+
+// The body after a length, None if the stream ends before the length starts
+fn read_body(input: &mut impl Read) -> io::Result<Option<Vec<u8>>> {
+    let mut len = [0u8; 4];
+    loop {
+        match input.read(&mut len[..1]) {
+            Ok(0) => return Ok(None),
+            Ok(_) => break,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    input.read_exact(&mut len[1..])?;
+    let len = i32::from_be_bytes(len);
+    if !(4..=MAX_FRAME_BYTES as i32).contains(&len) {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("Message of {len} bytes")));
+    }
+    let mut body = vec![0u8; len as usize - 4];
+    input.read_exact(&mut body)?;
+    Ok(Some(body))
+}
@@ -0,0 +1,376 @@
+// Query planning: resolving column references against a schema once per query shape
+// Constants are lifted out of the filter as positional parameters, so queries differing only
+// in their constants share the same cached plan
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::collation::Collation;
+use crate::dtype::*;
+use crate::engine::{Column, DbError, Table};
+use crate::query::{Bool, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CmpOp { Eq, Neq, Gt, Gte, Lt, Lte, DistinctFrom, NotDistinctFrom }
+
+impl CmpOp {
+    #[inline(always)]
+    pub fn apply(self, left: &ColumnValue, right: &ColumnValue) -> Result<bool, TypeError> {
+        match self {
+            CmpOp::Eq => left.eq(right),
+            CmpOp::Neq => left.neq(right),
+            CmpOp::Gt => left.gt(right),
+            CmpOp::Gte => left.gte(right),
+            CmpOp::Lt => left.lt(right),
+            CmpOp::Lte => left.lte(right),
+            CmpOp::DistinctFrom => left.is_distinct_from(right),
+            CmpOp::NotDistinctFrom => left.is_not_distinct_from(right),
+        }
+    }
+
+    // The operator with its operands swapped, e.g. `1 < x` is `x > 1`
+    pub fn flipped(self) -> CmpOp {
+        match self {
+            CmpOp::Gt => CmpOp::Lt,
+            CmpOp::Gte => CmpOp::Lte,
+            CmpOp::Lt => CmpOp::Gt,
+            CmpOp::Lte => CmpOp::Gte,
+            CmpOp::Eq | CmpOp::Neq | CmpOp::DistinctFrom | CmpOp::NotDistinctFrom => self,
+        }
+    }
+
+    // Two UTF8 values compare by the column's collation, everything else as in `apply`.
+    // Binary collation keeps the plain semantics, where UTF8 values have no ordering.
+    #[inline(always)]
+    pub fn apply_collated(self, left: &ColumnValue, right: &ColumnValue, collation: Collation) -> Result<bool, TypeError> {
+        let (ColumnValue::UTF8(l0), ColumnValue::UTF8(r0)) = (left, right) else {
+            return self.apply(left, right);
+        };
+        if collation == Collation::Binary {
+            return self.apply(left, right);
+        }
+        let ord = collation.compare(l0, r0);
+        let res = match self {
+            CmpOp::Eq | CmpOp::NotDistinctFrom => ord.is_eq(),
+            CmpOp::Neq | CmpOp::DistinctFrom => ord.is_ne(),
+            CmpOp::Gt => ord.is_gt(),
+            CmpOp::Gte => ord.is_ge(),
+            CmpOp::Lt => ord.is_lt(),
+            CmpOp::Lte => ord.is_le(),
+        };
+        Ok(res)
+    }
+}
+
+// SQL truth value. A comparison with a missing (NULL) operand is Unknown, and only rows whose
+// filter is definitively True are selected, so `Not` of an unknown comparison doesn't match either.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Truth { True, False, Unknown }
+
+impl From<bool> for Truth {
+    fn from(value: bool) -> Self {
+        if value { Truth::True } else { Truth::False }
+    }
+}
+
+// Kleene logic: Unknown only decides the result when the known operand doesn't
+impl Truth {
+    pub fn and(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::False, _) | (_, Truth::False) => Truth::False,
+            (Truth::True, Truth::True) => Truth::True,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn or(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::True, _) | (_, Truth::True) => Truth::True,
+            (Truth::False, Truth::False) => Truth::False,
+            _ => Truth::Unknown,
+        }
+    }
+
+    pub fn xor(self, other: Truth) -> Truth {
+        match (self, other) {
+            (Truth::Unknown, _) | (_, Truth::Unknown) => Truth::Unknown,
+            (left, right) => Truth::from(left != right),
+        }
+    }
+
+    pub fn is_true(self) -> bool {
+        self == Truth::True
+    }
+}
+
+impl std::ops::Not for Truth {
+    type Output = Truth;
+    fn not(self) -> Truth {
+        match self {
+            Truth::True => Truth::False,
+            Truth::False => Truth::True,
+            Truth::Unknown => Truth::Unknown,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Operand {
+    Column { idx: usize, col: Column },
+    // Index into the constants collected from the filter in traversal order
+    Param(usize),
+}
+
+impl Operand {
+    pub fn collation(&self) -> Option<Collation> {
+        match self {
+            Operand::Column { col, .. } => Some(col.collation),
+            Operand::Param(_) => None,
+        }
+    }
+}
+
+// Filter with column references resolved to schema positions
+#[derive(Debug)]
+pub enum Program {
+    True,
+    False,
+    Cmp(CmpOp, Operand, Operand),
+    And(Box<Program>, Box<Program>),
+    Or(Box<Program>, Box<Program>),
+    Xor(Box<Program>, Box<Program>),
+    Not(Box<Program>),
+}
+
+#[derive(Debug)]
+pub struct Plan {
+    pub projection: Vec<(usize, Column)>,
+    pub filter: Program,
+}
+
+impl Plan {
+    pub fn for_select(schema: &Table, values: &[Value], filter: &Bool) -> Result<Plan, DbError> {
+        let mut result_columns = Vec::with_capacity(values.len());
+        for val in values {
+            if let Value::ColumnRef(col_name) = val {
+                result_columns.push(*col_name);
+            } else {
+                return Err(DbError::UnsupportedOperation(format!("Selecting values other than column references not supported {:?}", val)));
+            }
+        }
+        let projection = schema.project_to_schema(&result_columns)?
+            .into_iter()
+            .map(|(idx, col)| (idx, col.clone()))
+            .collect();
+        let filter = compile_filter(schema, filter, &mut 0)?;
+        Ok(Plan { projection, filter })
+    }
+
+    pub fn for_filter(schema: &Table, filter: &Bool) -> Result<Plan, DbError> {
+        Ok(Plan { projection: vec![], filter: compile_filter(schema, filter, &mut 0)? })
+    }
+}
+
+fn compile_operand(schema: &Table, value: &Value, next_param: &mut usize) -> Result<Operand, DbError> {
+    match value {
+        Value::ColumnRef(name) => {
+            let (idx, col) = schema.project_to_schema(&[name])?[0];
+            Ok(Operand::Column { idx, col: col.clone() })
+        },
+        Value::Const(_) => {
+            let param = *next_param;
+            *next_param += 1;
+            Ok(Operand::Param(param))
+        }
+    }
+}
+
+fn compile_filter(schema: &Table, filter: &Bool, next_param: &mut usize) -> Result<Program, DbError> {
+    let cmp = |op, left, right, next_param: &mut usize| -> Result<Program, DbError> {
+        let left = compile_operand(schema, left, next_param)?;
+        let right = compile_operand(schema, right, next_param)?;
+        Ok(Program::Cmp(op, left, right))
+    };
+    let program = match filter {
+        Bool::True => Program::True,
+        Bool::False => Program::False,
+        Bool::Eq(left, right) => cmp(CmpOp::Eq, left, right, next_param)?,
+        Bool::Neq(left, right) => cmp(CmpOp::Neq, left, right, next_param)?,
+        Bool::Gt(left, right) => cmp(CmpOp::Gt, left, right, next_param)?,
+        Bool::Gte(left, right) => cmp(CmpOp::Gte, left, right, next_param)?,
+        Bool::Lt(left, right) => cmp(CmpOp::Lt, left, right, next_param)?,
+        Bool::Lte(left, right) => cmp(CmpOp::Lte, left, right, next_param)?,
+        Bool::IsDistinctFrom(left, right) => cmp(CmpOp::DistinctFrom, left, right, next_param)?,
+        Bool::IsNotDistinctFrom(left, right) => cmp(CmpOp::NotDistinctFrom, left, right, next_param)?,
+        Bool::And(left, right) => Program::And(Box::new(compile_filter(schema, left, next_param)?), Box::new(compile_filter(schema, right, next_param)?)),
+        Bool::Or(left, right) => Program::Or(Box::new(compile_filter(schema, left, next_param)?), Box::new(compile_filter(schema, right, next_param)?)),
+        Bool::Xor(left, right) => Program::Xor(Box::new(compile_filter(schema, left, next_param)?), Box::new(compile_filter(schema, right, next_param)?)),
+        Bool::Not(inner) => Program::Not(Box::new(compile_filter(schema, inner, next_param)?)),
+    };
+    Ok(program)
+}
+
+// Constants of the filter, in the same order `compile_filter` numbers its params
+pub fn collect_params<'a>(filter: &Bool<'a>, params: &mut Vec<ColumnValue<'a>>) {
+    let mut push = |value: &Value<'a>| if let Value::Const(val) = value { params.push(*val) };
+    match filter {
+        Bool::True | Bool::False => (),
+        Bool::Eq(left, right) |
+        Bool::Neq(left, right) |
+        Bool::Gt(left, right) |
+        Bool::Gte(left, right) |
+        Bool::Lt(left, right) |
+        Bool::Lte(left, right) |
+        Bool::IsDistinctFrom(left, right) |
+        Bool::IsNotDistinctFrom(left, right) => {
+            push(left);
+            push(right);
+        },
+        Bool::And(left, right) |
+        Bool::Or(left, right) |
+        Bool::Xor(left, right) => {
+            collect_params(left, params);
+            collect_params(right, params);
+        },
+        Bool::Not(inner) => collect_params(inner, params),
+    }
+}
+
+// Key identifying the plan of a query, ignoring the values of constants
+pub fn query_shape(kind: &str, values: &[Value], filter: &Bool) -> String {
+    let mut shape = format!("{kind}|");
+    for val in values {
+        write_value_shape(&mut shape, val);
+    }
+    shape.push('|');
+    write_filter_shape(&mut shape, filter);
+    shape
+}
+
+fn write_value_shape(shape: &mut String, value: &Value) {
+    match value {
+        // Length prefix keeps names containing separators unambiguous
+        Value::ColumnRef(name) => { shape.push_str(&format!("c{}:{name}", name.len())); },
+        Value::Const(_) => shape.push('?'),
+    }
+}
+
+fn write_filter_shape(shape: &mut String, filter: &Bool) {
+    let (tag, operands) = match filter {
+        Bool::True => ("T", None),
+        Bool::False => ("F", None),
+        Bool::Eq(left, right) => ("=", Some((left, right))),
+        Bool::Neq(left, right) => ("!=", Some((left, right))),
+        Bool::Gt(left, right) => (">", Some((left, right))),
+        Bool::Gte(left, right) => (">=", Some((left, right))),
+        Bool::Lt(left, right) => ("<", Some((left, right))),
+        Bool::Lte(left, right) => ("<=", Some((left, right))),
+        Bool::IsDistinctFrom(left, right) => ("distinct", Some((left, right))),
+        Bool::IsNotDistinctFrom(left, right) => ("notdistinct", Some((left, right))),
+        Bool::And(left, right) | Bool::Or(left, right) | Bool::Xor(left, right) => {
+            shape.push_str(match filter { Bool::And(..) => "and(", Bool::Or(..) => "or(", _ => "xor(" });
+            write_filter_shape(shape, left);
+            shape.push(',');
+            write_filter_shape(shape, right);
+            shape.push(')');
+            return;
+        },
+        Bool::Not(inner) => {
+            shape.push_str("not(");
+            write_filter_shape(shape, inner);
+            shape.push(')');
+            return;
+        },
+    };
+    shape.push_str(tag);
+    if let Some((left, right)) = operands {
+        shape.push('(');
+        write_value_shape(shape, left);
+        shape.push(',');
+        write_value_shape(shape, right);
+        shape.push(')');
+    }
+}
+
+// Bounded so that ad-hoc queries with unique shapes can't grow it forever
+const PLAN_CACHE_CAPACITY: usize = 1024;
+
+// Plans per table, then per query shape
+#[derive(Default)]
+pub struct PlanCache {
+    plans: HashMap<String, HashMap<String, Arc<Plan>>>,
+    pub hits: usize,
+    pub misses: usize,
+}
+
+impl PlanCache {
+
+    pub fn get_or_plan(&mut self, table: &str, shape: String, plan: impl FnOnce() -> Result<Plan, DbError>) -> Result<Arc<Plan>, DbError> {
+        if let Some(cached) = self.plans.get(table).and_then(|plans| plans.get(&shape)) {
+            self.hits += 1;
+            return Ok(cached.clone());
+        }
+        self.misses += 1;
+        let planned = Arc::new(plan()?);
+        if self.len() >= PLAN_CACHE_CAPACITY {
+            self.plans.clear();
+        }
+        self.plans.entry(table.to_string()).or_default().insert(shape, planned.clone());
+        Ok(planned)
+    }
+
+    // Drop plans referring to a table whose schema or name changed
+    pub fn invalidate(&mut self, table: &str) {
+        self.plans.remove(table);
+    }
+
+    pub fn len(&self) -> usize {
+        self.plans.values().map(|plans| plans.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kleene_truth_tables() {
+        use Truth::*;
+        assert_eq!(False.and(Unknown), False);
+        assert_eq!(True.and(Unknown), Unknown);
+        assert_eq!(True.or(Unknown), True);
+        assert_eq!(False.or(Unknown), Unknown);
+        assert_eq!(True.xor(Unknown), Unknown);
+        assert_eq!(True.xor(False), True);
+        assert_eq!(!Unknown, Unknown);
+        assert!(!Unknown.is_true() && !(!Unknown).is_true());
+    }
+
+    #[test]
+    fn shape_ignores_constants() {
+        let first = Bool::Eq(Value::ColumnRef("id"), Value::Const(ColumnValue::U32(1)));
+        let second = Bool::Eq(Value::ColumnRef("id"), Value::Const(ColumnValue::U32(2)));
+        let other = Bool::Gt(Value::ColumnRef("id"), Value::Const(ColumnValue::U32(1)));
+        let values = [Value::ColumnRef("id")];
+
+        assert_eq!(query_shape("select", &values, &first), query_shape("select", &values, &second));
+        assert_ne!(query_shape("select", &values, &first), query_shape("select", &values, &other));
+    }
+
+    #[test]
+    fn params_follow_traversal_order() {
+        let filter = Bool::and(
+            Bool::Gt(Value::Const(ColumnValue::U32(1)), Value::ColumnRef("id")),
+            Bool::Eq(Value::ColumnRef("name"), Value::Const(ColumnValue::UTF8("apple"))),
+        );
+
+        let mut params = Vec::new();
+        collect_params(&filter, &mut params);
+
+        assert_eq!(params, vec![ColumnValue::U32(1), ColumnValue::UTF8("apple")]);
+    }
+}
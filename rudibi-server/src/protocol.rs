@@ -0,0 +1,1551 @@
+// Text commands of the server, each sent in a frame of its own, see `frame`:
+//   CREATE TABLE Fruits (id U32, name UTF8(32))
+//   INSERT INTO Fruits (id, name) VALUES (100, 'apple'), (200, 'banana')
+//   SELECT id, name FROM Fruits WHERE name = 'apple' OR id > 150
+//   DELETE FROM Fruits WHERE id = 100
+// Keywords are case-insensitive. Strings are in single quotes and can't hold one, bytes are given
+// in hex as x'00ff' and only in inserts. Column types are U32, F64, UTF8(max bytes),
+// VARBINARY(max length), BUFFER(length), UUID and ENUM('label', ...).
+// A command is replied to with the selected rows as a text table or the number of rows created,
+// inserted or deleted, or with an error frame saying what went wrong, with a code telling what
+// kind of error it is, see `errors`.
+// In text mode, for netcat and scripts, commands are sent as they are instead. Each ends at a
+// newline or a semicolon and its reply ends with an empty line, errors start with `ERROR`.
+// `SET FORMAT CSV` and `SET FORMAT TABLE` pick how the connection's selected rows are shown.
+// A server with credentials, see `auth`, runs commands only for connections that authenticated:
+// with an AUTH frame holding `user\0password` or a token, or in text mode `AUTH user password`
+// or `AUTH token`. With permissions too, each command needs a privilege on its table, see
+// `permissions`.
+// A query can be cancelled from a second connection with the first's cancel key, see `cancel`: in
+// frames a CANCEL_KEY request gets the key and a CANCEL request holding it cancels, in text mode
+// `CANCEL KEY` and `CANCEL id secret`.
+// `BEGIN` opens a transaction of the connection, see `transaction`, ended by `COMMIT` or `ROLLBACK`.
+// Inserts, deletes and selects, streamed or not, run in it, other commands fail until it ends. A
+// connection ending with its transaction open rolls it back. The transaction holds the database,
+// so a CREATE TABLE waits for it to end, and the commands of other connections for the create.
+// A select can be kept as a named cursor of the connection, its rows fetched in batches:
+//   DECLARE ripe CURSOR FOR SELECT id FROM Fruits WHERE id > 100
+//   FETCH 100 FROM ripe           the next 100 rows at most, none once all were fetched
+//   CLOSE ripe
+// A connection has at most `Server::with_max_cursors` open, those left open close when it ends.
+// Rows are streamed into a table with INSERT_ROWS frames, each holding as many as the server's last
+// WINDOW reply allows, until an INSERT_DONE, see `ingest`. The windows of a server's connections
+// share `Server::with_insert_budget` bytes.
+// Blobs are written in BLOB_WRITE frames of a chunk each until a BLOB_DONE gives the blob's id, and
+// read back in chunks with BLOB_READ, see `blob`.
+// Tables are listed with `SHOW TABLES`, those the connection has a privilege on if the server has
+// permissions, and a table's columns with `DESCRIBE Fruits`, their types written as in CREATE and
+// the table's schema fingerprint in hex on each row, see `Table::fingerprint`.
+// Admin commands tell operators about the server, as rows:
+//   STATUS        uptime, connections open and queries running
+//   STATS         each query running: its connection's cancel key id, user, seconds and text
+//   LIST TABLES   each table's live rows and bytes on disk
+// With permissions they need the admin privilege, see `permissions`.
+// A PING frame, or `PING` in text mode, is replied to with PONG by any connection, for load
+// balancers checking the server is up.
+// A COMPRESS frame picks a codec compressing the connection's long payloads, see `frame`.
+// A select sent in a STREAM frame rather than EXECUTE is replied to with its rows as CSV, in ROWS
+// frames of about `STREAM_CHUNK_BYTES`, then a DONE frame with the row count. Its rows are held in
+// a spill file rather than memory past `STREAM_MEMORY_BYTES`, see `spill`.
+// A select can be sent encoded, see `serial`, in a QUERY frame rather than as text, and is replied
+// to as in a STREAM. Its filter compares any values and strings holding quotes, which text can't.
+// A server with a change feed serves replicas SNAPSHOT and CHANGES requests, and a replica fails
+// writes, see `replication`. Clients SUBSCRIBE to the changes of a table, or WATCH a select, see
+// `changes`.
+
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::auth::{Credentials, Session};
+use crate::blob::{BlobId, BLOB_CHUNK_SIZE};
+use crate::cancel::{CancelKey, CancelRegistry, CancelSlot, CancellationToken};
+use crate::changes::{self, Watch};
+use crate::cursor::{Cursors, DEFAULT_MAX_OPEN_CURSORS};
+use crate::display::{csv_header, csv_row, format_csv, DisplayOptions};
+use crate::dtype::{ColumnValue, DataType, Uuid};
+use crate::engine::{Column, Database, DbError, QueryOptions, ResultSet, Row, ScanStats, StorageCfg, Table};
+use crate::errors::{self, ErrorReply};
+use crate::frame::{self, Codec, Frame};
+use crate::ingest::{InsertBudget, InsertStream, WindowUpdate, DEFAULT_INSERT_BUDGET_BYTES, DEFAULT_INSERT_WINDOW};
+use crate::metrics::{self, Metrics, RowCounts};
+use crate::permissions::{Permissions, Privilege, ALL_TABLES};
+use crate::query::{Bool, Value};
+use crate::replication::{self, ChangeFeed, CHANGES_WAIT};
+use crate::serial;
+use crate::spill::ResultStream;
+use crate::telemetry;
+use crate::throttle::{RateLimits, Throttle};
+use crate::transaction::Transaction;
+use crate::wal::WalRecord;
+use crate::workers::WorkerPool;
+
+// Streamed rows are sent in chunks of about this many bytes
+pub const STREAM_CHUNK_BYTES: usize = 64 << 10;
+// Rows of a streamed select past this many bytes wait for the client in a spill file
+pub const STREAM_MEMORY_BYTES: usize = 4 << 20;
+
+pub use rudibi_core::command::{Command, Literal};
+
+#[derive(Debug)]
+pub enum Reply {
+    Created,
+    Inserted(usize),
+    Selected(ResultSet),
+    Deleted(usize),
+    Declared,
+    Closed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextFormat {
+    #[default]
+    Table,
+    // Values aren't truncated
+    Csv,
+}
+
+impl Reply {
+    pub fn render(&self, format: TextFormat) -> String {
+        match (self, format) {
+            (Reply::Selected(results), TextFormat::Csv) => format_csv(results, &DisplayOptions::full()).trim_end().to_string(),
+            _ => self.to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for Reply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reply::Created => write!(f, "CREATED"),
+            Reply::Inserted(rows) => write!(f, "INSERTED {rows}"),
+            Reply::Selected(results) => f.write_str(results.format_table().trim_end()),
+            Reply::Deleted(rows) => write!(f, "DELETED {rows}"),
+            Reply::Declared => write!(f, "DECLARED"),
+            Reply::Closed => write!(f, "CLOSED"),
+        }
+    }
+}
+
+// A database shared by the connections of a server
+pub struct Server {
+    shared: Arc<Shared>,
+    // Runs the commands handled, when the server has a pool of its own
+    pool: Option<WorkerPool<Job>>,
+    // Who may run commands, anyone when None
+    credentials: Option<Credentials>,
+    throttles: Throttles,
+    // What replicas are sent, none are served when None
+    feed: Option<Arc<ChangeFeed>>,
+    // Cursors each connection may have open
+    max_cursors: usize,
+    // Shared out as the windows of streamed inserts
+    insert_budget: Arc<InsertBudget>,
+}
+
+// Rate limits of connections and users, see `Server::with_rate_limits`
+#[derive(Default)]
+struct Throttles {
+    per_connection: RateLimits,
+    per_user: RateLimits,
+    users: Mutex<HashMap<String, Arc<Throttle>>>,
+}
+
+struct Shared {
+    db: RwLock<Database>,
+    // Where tables created by clients are kept
+    storage: StorageCfg,
+    // What authenticated users may do, anything when None
+    permissions: RwLock<Option<Permissions>>,
+    cancels: CancelRegistry,
+    started: Instant,
+    metrics: Metrics,
+    // Writes fail, the tables change only as the primary's do, see `Server::as_replica`
+    read_only: AtomicBool,
+    // Cursors open in the connections' sessions, see `Cursors::counted`
+    cursors_open: Arc<AtomicUsize>,
+}
+
+// Who runs a command, to check permissions for if anyone, and what cancels it
+#[derive(Default)]
+struct Caller {
+    user: Option<String>,
+    cancel: Option<CancellationToken>,
+    // Lists the command as running until it's done
+    slot: Option<Arc<CancelSlot>>,
+    // The connection's, None for commands run on the server directly
+    cursors: Option<Arc<Mutex<Cursors>>>,
+}
+
+impl Caller {
+    fn cursors(&self) -> Result<MutexGuard<'_, Cursors>, DbError> {
+        let cursors = self.cursors.as_ref().ok_or_else(|| DbError::UnsupportedOperation("Cursors are kept for connections only".to_string()))?;
+        Ok(cursors.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+impl Drop for Caller {
+    fn drop(&mut self) {
+        if let (Some(slot), Some(token)) = (&self.slot, &self.cancel) {
+            slot.finish_query(token);
+        }
+    }
+}
+
+// A select as the client sent it, parsed where it runs as its command borrows from it
+enum Statement {
+    Text(String),
+    Encoded(Vec<u8>),
+}
+
+impl Statement {
+    fn command(&self) -> Result<Command<'_>, DbError> {
+        match self {
+            Statement::Text(text) => parse(text),
+            Statement::Encoded(bytes) => serial::decode(bytes),
+        }
+    }
+}
+
+// Runs a command and sends its reply back, see `Server::on_pool`
+type Job = Box<dyn FnOnce(&Shared) + Send>;
+
+// A transaction a connection began, on a thread of its own holding the database until it ends.
+// Dropped open, it's rolled back, and the drop waits until it is.
+#[derive(Debug)]
+pub struct OpenTransaction {
+    jobs: Option<SyncSender<TransactionJob>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Runs a command in the transaction, see `OpenTransaction::run`. The thread ends once a job takes
+// the transaction to end it.
+type TransactionJob = Box<dyn FnOnce(&Shared, &mut Option<Transaction<'_>>) + Send>;
+
+impl OpenTransaction {
+
+    fn begin(shared: Arc<Shared>) -> OpenTransaction {
+        let (jobs, received) = sync_channel::<TransactionJob>(0);
+        let thread = thread::spawn(move || {
+            let db = shared.database();
+            let mut transaction = Some(db.begin());
+            while transaction.is_some() && let Ok(job) = received.recv() {
+                job(&shared, &mut transaction);
+            }
+        });
+        OpenTransaction { jobs: Some(jobs), thread: Some(thread) }
+    }
+
+    // Calls `call` on the transaction's thread, as `Server::on_pool` does on the pool's
+    fn run<T: Send + 'static>(&self, call: impl FnOnce(&Shared, &mut Option<Transaction<'_>>) -> Result<T, DbError> + Send + 'static) -> Result<T, DbError> {
+        let (sender, receiver) = sync_channel(1);
+        let trace = telemetry::current();
+        let job: TransactionJob = Box::new(move |shared, transaction| {
+            let _trace = trace.attach();
+            let _ = sender.send(call(shared, transaction));
+        });
+        let ended = || DbError::DatabaseIntegrityError("Transaction ended".to_string());
+        self.jobs.as_ref().expect("Jobs are sent until the drop").send(job).map_err(|_| ended())?;
+        receiver.recv().unwrap_or_else(|_| Err(ended()))
+    }
+}
+
+impl Drop for OpenTransaction {
+    fn drop(&mut self) {
+        drop(self.jobs.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Server {
+
+    // Commands run on the threads of the connections sending them
+    pub fn new(db: Database, storage: StorageCfg) -> Server {
+        Server { shared: Arc::new(Shared::new(db, storage)), pool: None, credentials: None, throttles: Throttles::default(), feed: None, max_cursors: DEFAULT_MAX_OPEN_CURSORS, insert_budget: Arc::new(InsertBudget::new(DEFAULT_INSERT_BUDGET_BYTES)) }
+    }
+
+    // Commands run on `workers` threads of the server, in the order they come. A connection waits
+    // for its command's reply before sending the next, and commands that would be queued behind
+    // `queue_depth` others fail with `DbError::ServerBusy` rather than wait.
+    pub fn with_pool(db: Database, storage: StorageCfg, workers: usize, queue_depth: usize) -> Server {
+        let shared = Arc::new(Shared::new(db, storage));
+        let pool = WorkerPool::new(workers, queue_depth, {
+            let shared = shared.clone();
+            move |job: Job| job(&shared)
+        });
+        Server { shared, pool: Some(pool), credentials: None, throttles: Throttles::default(), feed: None, max_cursors: DEFAULT_MAX_OPEN_CURSORS, insert_budget: Arc::new(InsertBudget::new(DEFAULT_INSERT_BUDGET_BYTES)) }
+    }
+
+    // Connections must authenticate before running commands
+    pub fn with_credentials(mut self, credentials: Credentials) -> Server {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    // Commands of authenticated connections need a privilege on their table, see `permissions`
+    pub fn with_permissions(self, permissions: Permissions) -> Server {
+        *self.shared.permissions.write().unwrap_or_else(PoisonError::into_inner) = Some(permissions);
+        self
+    }
+
+    // Each connection, and all the connections of a user together, run queries no faster than
+    // their limits, see `throttle`
+    pub fn with_rate_limits(mut self, per_connection: RateLimits, per_user: RateLimits) -> Server {
+        self.throttles.per_connection = per_connection;
+        self.throttles.per_user = per_user;
+        self
+    }
+
+    // Declaring more cursors on a connection fails with `DbError::TooManyCursors` until it closes some
+    pub fn with_max_cursors(mut self, max_cursors: usize) -> Server {
+        self.max_cursors = max_cursors;
+        self
+    }
+
+    // Rows streamed into the tables, see `frame::INSERT_ROWS`, are sent in windows taking no more
+    // than `bytes` together
+    pub fn with_insert_budget(mut self, bytes: usize) -> Server {
+        self.insert_budget = Arc::new(InsertBudget::new(bytes));
+        self
+    }
+
+    // Keeps the latest `backlog` changes to the tables for replicas, see `replication`
+    pub fn with_change_feed(mut self, backlog: usize) -> Server {
+        let feed = Arc::new(ChangeFeed::new(backlog));
+        self.shared.db.write().unwrap_or_else(PoisonError::into_inner).add_observer(feed.clone());
+        self.feed = Some(feed);
+        self
+    }
+
+    // Commands and requests that write fail with `DbError::ReadOnly`, the tables are changed by
+    // `replication::replicate` only
+    pub fn as_replica(self) -> Server {
+        self.shared.read_only.store(true, Ordering::Relaxed);
+        self
+    }
+
+    pub fn database(&self) -> RwLockReadGuard<'_, Database> {
+        self.shared.database()
+    }
+
+    // Creates a table of a primary's snapshot, or empties the replica's table of the same name,
+    // as long as the schema has the `fingerprint` the primary sent
+    pub(crate) fn replica_table(&self, table: &Table, fingerprint: u64) -> Result<(), DbError> {
+        let mut db = self.shared.db.write().unwrap_or_else(PoisonError::into_inner);
+        match db.schema_for(&table.name) {
+            Ok(schema) if schema.fingerprint() == fingerprint => db.delete(&table.name, &Bool::True).map(|_| ()),
+            Ok(_) => Err(DbError::DatabaseIntegrityError(format!("{} has another schema on the primary", table.name))),
+            Err(_) if table.fingerprint() != fingerprint => Err(DbError::DatabaseIntegrityError(format!("{} arrived with another schema than the primary's", table.name))),
+            Err(_) => db.new_table(table, self.shared.storage.clone()),
+        }
+    }
+
+    // What the server did and holds, in the Prometheus text format, see `metrics`
+    pub fn metrics(&self) -> String {
+        let shared = &self.shared;
+        let mut out = String::new();
+        shared.metrics.write_to(&mut out);
+        let db = shared.database();
+        let plans = db.plan_cache_stats();
+        metrics::counter(&mut out, "rudibi_plan_cache_hits_total", "Queries whose plan was cached", plans.hits as u64);
+        metrics::counter(&mut out, "rudibi_plan_cache_misses_total", "Queries planned anew", plans.misses as u64);
+        let tables = db.table_stats().unwrap_or_default();
+        let rows: Vec<_> = tables.iter().map(|table| (table.name.as_str(), table.rows as f64)).collect();
+        metrics::gauge(&mut out, "rudibi_table_rows", "Live rows of each table", "table", &rows);
+        let disk_bytes: Vec<_> = tables.iter().map(|table| (table.name.as_str(), table.disk_bytes as f64)).collect();
+        metrics::gauge(&mut out, "rudibi_table_disk_bytes", "Bytes of each table's files", "table", &disk_bytes);
+        metrics::gauge(&mut out, "rudibi_connections", "Connections open", "", &[("", shared.cancels.connections() as f64)]);
+        metrics::gauge(&mut out, "rudibi_queries_running", "Queries running", "", &[("", shared.cancels.running().len() as f64)]);
+        metrics::gauge(&mut out, "rudibi_cursors_open", "Cursors the connections have open", "", &[("", shared.cursors_open.load(Ordering::Relaxed) as f64)]);
+        metrics::gauge(&mut out, "rudibi_uptime_seconds", "Seconds since the server started", "", &[("", shared.started.elapsed().as_secs_f64())]);
+        out
+    }
+
+    // A connection that can be cancelled with its key
+    pub fn open_session(&self) -> Session {
+        let per_connection = self.throttles.per_connection;
+        let throttle = (!per_connection.is_empty()).then(|| Arc::new(Throttle::new(per_connection)));
+        let cursors = Arc::new(Mutex::new(Cursors::counted(self.max_cursors, self.shared.cursors_open.clone())));
+        Session { user: None, cancel: Some(self.shared.cancels.register()), codec: None, throttle, transaction: None, cursors, insert: None, blob: None }
+    }
+
+    // Cancels the query of the connection with `key`, false if none has it. The connection's
+    // later queries run as usual.
+    pub fn cancel(&self, key: CancelKey) -> bool {
+        self.shared.cancels.cancel(key)
+    }
+
+    pub fn requires_auth(&self) -> bool {
+        self.credentials.is_some()
+    }
+
+    // Logs `session` in as the user or token `secret` is for, see `Credentials::check`
+    pub fn authenticate(&self, session: &mut Session, user: Option<&str>, secret: &str) -> Result<(), DbError> {
+        let Some(credentials) = &self.credentials else {
+            return Err(DbError::UnsupportedOperation("Server has no credentials to authenticate with".to_string()));
+        };
+        session.user = Some(credentials.check(user, secret)?);
+        Ok(())
+    }
+
+    // Fails if the server has credentials or permissions and `session` hasn't authenticated
+    pub fn check_auth(&self, session: &Session) -> Result<(), DbError> {
+        let has_permissions = self.shared.permissions.read().unwrap_or_else(PoisonError::into_inner).is_some();
+        if (self.requires_auth() || has_permissions) && session.user.is_none() { Err(DbError::NotAuthenticated) } else { Ok(()) }
+    }
+
+    // For commands run on the database directly rather than through `handle_as`
+    pub fn check_access(&self, session: &Session, privilege: Privilege, table: &str) -> Result<(), DbError> {
+        self.check_auth(session)?;
+        self.shared.check_writable(privilege)?;
+        session.user.as_deref().map_or(Ok(()), |user| self.shared.check_access(user, privilege, table))
+    }
+
+    // Runs `text` for `session`, listed as running unless it's None
+    fn caller(session: &Session, text: Option<&str>) -> Caller {
+        let (user, cursors) = (session.user.clone(), Some(session.cursors.clone()));
+        match (&session.cancel, text) {
+            (Some(slot), Some(text)) => {
+                let cancel = Some(slot.run_query(user.as_deref(), text));
+                Caller { user, cancel, slot: Some(slot.clone()), cursors }
+            },
+            (slot, _) => Caller { user, cancel: slot.as_ref().map(|slot| slot.start_query()), slot: None, cursors },
+        }
+    }
+
+    // Replies to each request read from `input`, in order, until it ends. Requests that can't be
+    // carried out get an error frame, a stream that isn't framed ends the connection.
+    // Replies are flushed once no request is left read, so those to pipelined requests go out
+    // together rather than in a write each.
+    pub fn serve(&self, input: impl Read, output: impl Write) -> io::Result<()> {
+        let mut input = BufReader::new(input);
+        let mut output = BufWriter::new(output);
+        let mut session = self.open_session();
+        while let Some(request) = Frame::read_from(&mut input)? {
+            self.reply_to(&request, &mut session, &mut |reply| {
+                output.write_all(&reply.to_bytes()?)?;
+                if reply.is_urgent() { output.flush() } else { Ok(()) }
+            })?;
+            if input.buffer().is_empty() {
+                output.flush()?;
+            }
+        }
+        output.flush()
+    }
+
+    // Text mode: replies to each statement read from `input`, in order, until it ends
+    pub fn serve_text(&self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let mut format = TextFormat::default();
+        let mut session = self.open_session();
+        for line in input.lines() {
+            for statement in statements(&line?) {
+                write!(output, "{}\n\n", self.text_reply(statement, &mut format, &mut session))?;
+                output.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Hands the replies to `request` to `out`, one unless it's a STREAM, compressed once the
+    // connection picked a codec
+    pub(crate) fn reply_to(&self, request: &Frame, session: &mut Session, out: &mut dyn FnMut(Frame) -> io::Result<()>) -> io::Result<()> {
+        if request.tag & frame::COMPRESSED != 0 {
+            return match session.codec.and_then(|codec| request.decompressed(codec)) {
+                Some(request) => self.reply_to(&request, session, out),
+                None => out(Frame::error(request.request_id, &ErrorReply::new(errors::BAD_COMPRESSION, "Payload can't be decompressed"))),
+            };
+        }
+        if request.tag & frame::TRACED != 0 {
+            let Some((traceparent, request)) = request.untraced() else {
+                return out(Frame::error(request.request_id, &ErrorReply::new(errors::BAD_ENCODING, "Traceparent isn't UTF-8 ended by a NUL")));
+            };
+            let _request = telemetry::request(Some(&traceparent));
+            return self.reply_untraced(&request, session, out);
+        }
+        let _request = telemetry::request(None);
+        self.reply_untraced(request, session, out)
+    }
+
+    fn reply_untraced(&self, request: &Frame, session: &mut Session, out: &mut dyn FnMut(Frame) -> io::Result<()>) -> io::Result<()> {
+        let codec = session.codec;
+        let out = &mut |reply: Frame| match codec {
+            Some(codec) => out(reply.compressed(codec)?),
+            None => out(reply),
+        };
+        let payload = std::str::from_utf8(&request.payload);
+        let result = match (request.tag, payload) {
+            (frame::STREAM, Ok(text)) => return self.stream_rows(request.request_id, self.select_stream_as(session, text), out),
+            (frame::QUERY, _) => return self.stream_rows(request.request_id, self.query_stream_as(session, &request.payload), out),
+            (frame::INSERT_ROWS, _) => return out(match self.insert_rows_as(session, &request.payload) {
+                Ok(update) => Frame::new(frame::WINDOW, request.request_id, format!("{} {}", update.accepted, update.window)),
+                Err(err) => Frame::error(request.request_id, &ErrorReply::from(&err)),
+            }),
+            (frame::INSERT_DONE, _) => self.insert_done_as(session).map(|rows| Reply::Inserted(rows).to_string()),
+            (frame::BLOB_WRITE, _) => self.write_blob_as(session, &request.payload).map(|()| "OK".to_string()),
+            (frame::BLOB_DONE, _) => self.finish_blob_as(session).map(|id| id.0.to_string()),
+            (frame::BLOB_READ, Ok(text)) => return self.read_blob_as(request.request_id, session, text, out),
+            (frame::EXECUTE, Ok(text)) => match self.transaction_statement(session, text) {
+                Some(reply) => reply,
+                None => self.handle_as(session, text).map(|reply| telemetry::in_span("serialize", || reply.to_string())),
+            },
+            (frame::AUTH, Ok(text)) => {
+                let (user, secret) = text.split_once('\0').map_or((None, text), |(user, secret)| (Some(user), secret));
+                self.authenticate(session, user, secret).map(|()| "OK".to_string())
+            },
+            (frame::CANCEL_KEY, _) => session.cancel_key().map(|key| key.to_string())
+                .ok_or_else(|| DbError::UnsupportedOperation("Connection can't be cancelled".to_string())),
+            (frame::CANCEL, Ok(text)) => cancel(self, text),
+            (frame::PING, _) => Ok("PONG".to_string()),
+            (frame::COMPRESS, Ok(text)) => match Codec::negotiate(text) {
+                Some(codec) => {
+                    session.codec = Some(codec);
+                    Ok(codec.name().to_string())
+                },
+                None => return out(Frame::error(request.request_id, &ErrorReply::new(errors::UNSUPPORTED_CODEC, format!("No codec of {text} is supported")))),
+            },
+            (frame::SNAPSHOT, _) => return self.send_snapshot(request.request_id, session, out),
+            (frame::CHANGES, Ok(text)) => return out(match self.changes(session, text) {
+                Ok(changes) => Frame::new(frame::REPLY, request.request_id, changes),
+                Err(err) => Frame::error(request.request_id, &ErrorReply::from(&err)),
+            }),
+            (frame::SUBSCRIBE, Ok(text)) => return self.subscribe(request.request_id, session, text, out),
+            (frame::WATCH, Ok(text)) => return self.watch(request.request_id, session, text, out),
+            (frame::EXECUTE | frame::AUTH | frame::CANCEL | frame::STREAM | frame::COMPRESS | frame::CHANGES | frame::SUBSCRIBE | frame::WATCH | frame::BLOB_READ, Err(_)) =>
+                return out(Frame::error(request.request_id, &ErrorReply::new(errors::BAD_ENCODING, "Command isn't UTF-8"))),
+            (tag, _) => return out(Frame::error(request.request_id, &ErrorReply::new(errors::UNKNOWN_REQUEST, format!("Unknown request tag {tag}")))),
+        };
+        out(match result {
+            Ok(reply) => Frame::new(frame::REPLY, request.request_id, reply),
+            Err(err) => Frame::error(request.request_id, &ErrorReply::from(&err)),
+        })
+    }
+
+    // ROWS frames of the select's rows as CSV, the first starting with the header, then a DONE
+    // frame. An ERROR frame ends the stream if the rows can't be read.
+    fn stream_rows(&self, request_id: u32, rows: Result<ResultStream, DbError>, out: &mut dyn FnMut(Frame) -> io::Result<()>) -> io::Result<()> {
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+        };
+        let _serialize = telemetry::span("serialize");
+        let (schema, count) = (rows.schema.clone(), rows.len());
+        let options = DisplayOptions::full();
+        let mut chunk = csv_header(&schema);
+        for row in rows {
+            match row {
+                Ok(row) => chunk.push_str(&csv_row(&schema, &row, &options)),
+                Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+            }
+            if chunk.len() >= STREAM_CHUNK_BYTES {
+                out(Frame::new(frame::ROWS, request_id, std::mem::take(&mut chunk)))?;
+            }
+        }
+        if !chunk.is_empty() {
+            out(Frame::new(frame::ROWS, request_id, chunk))?;
+        }
+        out(Frame::new(frame::DONE, request_id, format!("SELECTED {count}")))
+    }
+
+    // ROWS frames of each table's rows, then a DONE frame with the sequence of the change feed the
+    // snapshot starts at, see `replication`
+    fn send_snapshot(&self, request_id: u32, session: &Session, out: &mut dyn FnMut(Frame) -> io::Result<()>) -> io::Result<()> {
+        let feed = match self.replicated(session) {
+            Ok(feed) => feed,
+            Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+        };
+        let start = feed.last_sequence();
+        let tables: Vec<String> = self.database().table_names().into_iter().map(str::to_string).collect();
+        for table in tables {
+            let chunks = match replication::snapshot(&self.database(), &table, feed) {
+                Ok(chunks) => chunks,
+                // Dropped since it was listed
+                Err(DbError::TableNotFound(_)) => continue,
+                Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+            };
+            for chunk in chunks {
+                out(Frame::new(frame::ROWS, request_id, serial::encode(&chunk)))?;
+            }
+        }
+        out(Frame::new(frame::DONE, request_id, start.to_string()))
+    }
+
+    // The changes after the sequence `after`, encoded, see `replication`
+    fn changes(&self, session: &Session, after: &str) -> Result<Vec<u8>, DbError> {
+        let feed = self.replicated(session)?;
+        let after = after.trim().parse().map_err(|_| DbError::InputError(format!("Bad sequence {after}")))?;
+        let records = feed.changes_after(after, CHANGES_WAIT)?;
+        Ok(serial::encode(&replication::feed_records(&self.database(), records)))
+    }
+
+    // EVENTS frames of the table's changes, the first holding the header, until the connection is
+    // gone. An ERROR frame ends them if the changes can't be followed, see `changes`.
+    fn subscribe(&self, request_id: u32, session: &Session, text: &str, out: &mut dyn FnMut(Frame) -> io::Result<()>) -> io::Result<()> {
+        let subscribed = changes::parse_subscribe(text).and_then(|(table, after)| {
+            self.check_access(session, Privilege::Read, table)?;
+            let feed = self.feed.as_deref().ok_or_else(|| DbError::UnsupportedOperation("Server has no change feed to subscribe to".to_string()))?;
+            let schema = self.database().schema_for(table)?.column_layout.clone();
+            Ok((table, after.unwrap_or_else(|| feed.last_sequence()), feed, schema))
+        });
+        let (table, after, feed, schema) = match subscribed {
+            Ok(subscribed) => subscribed,
+            Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+        };
+        out(Frame::new(frame::EVENTS, request_id, changes::header(&schema)))?;
+        follow_feed(request_id, frame::EVENTS, feed, after, out, |records| Ok(changes::events(&schema, table, records)))
+    }
+
+    // NOTIFY frames of the rows the select selects among those inserted, the first holding the
+    // header, until the connection is gone, see `changes`
+    fn watch(&self, request_id: u32, session: &Session, text: &str, out: &mut dyn FnMut(Frame) -> io::Result<()>) -> io::Result<()> {
+        let watched = parse(text).and_then(|command| {
+            let Command::Select { table, columns, filter } = command else {
+                return Err(DbError::InputError("Only selects are watched".to_string()));
+            };
+            self.check_access(session, Privilege::Read, table)?;
+            let feed = self.feed.as_deref().ok_or_else(|| DbError::UnsupportedOperation("Server has no change feed to watch".to_string()))?;
+            let db = self.database();
+            let filter = coerce_numbers(db.schema_for(table)?, filter);
+            Ok((Watch::new(&db, table, &columns, filter)?, feed, feed.last_sequence()))
+        });
+        let (watch, feed, after) = match watched {
+            Ok(watched) => watched,
+            Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+        };
+        out(Frame::new(frame::NOTIFY, request_id, csv_header(&watch.columns)))?;
+        follow_feed(request_id, frame::NOTIFY, feed, after, out, |records| watch.notifications(&self.database(), records))
+    }
+
+    // The change feed, if the session may replicate the server
+    fn replicated(&self, session: &Session) -> Result<&ChangeFeed, DbError> {
+        self.check_access(session, Privilege::Admin, ALL_TABLES)?;
+        self.feed.as_deref().ok_or_else(|| DbError::UnsupportedOperation("Server has no change feed to replicate".to_string()))
+    }
+
+    // Reply to a statement of text mode, without the empty line ending it
+    pub(crate) fn text_reply(&self, statement: &str, format: &mut TextFormat, session: &mut Session) -> String {
+        let _request = telemetry::request(None);
+        if statement.trim().eq_ignore_ascii_case("PING") {
+            return "PONG".to_string();
+        }
+        if let Some((user, secret)) = auth_statement(statement) {
+            return match self.authenticate(session, user, secret) {
+                Ok(()) => "OK".to_string(),
+                Err(err) => format!("ERROR {err:?}"),
+            };
+        }
+        if let Some(cancelled) = cancel_statement(self, session, statement) {
+            return match cancelled {
+                Ok(reply) => reply,
+                Err(err) => format!("ERROR {err:?}"),
+            };
+        }
+        if let Some(reply) = self.transaction_statement(session, statement) {
+            return reply.unwrap_or_else(|err| format!("ERROR {err:?}"));
+        }
+        match set_format(statement) {
+            Some(Ok(new_format)) => {
+                *format = new_format;
+                "OK".to_string()
+            },
+            Some(Err(err)) => format!("ERROR {err:?}"),
+            None => match self.handle_as(session, statement) {
+                Ok(reply) => telemetry::in_span("serialize", || reply.render(*format)),
+                Err(err) => format!("ERROR {err:?}"),
+            },
+        }
+    }
+
+    // Reply to BEGIN, COMMIT or ROLLBACK, None if the statement is none of them
+    fn transaction_statement(&self, session: &mut Session, statement: &str) -> Option<Result<String, DbError>> {
+        let keyword = statement.trim().to_ascii_uppercase();
+        let done = match keyword.as_str() {
+            "BEGIN" => self.check_auth(session).and_then(|()| match session.transaction {
+                Some(_) => Err(DbError::InputError("A transaction is open already".to_string())),
+                None => {
+                    session.transaction = Some(Arc::new(OpenTransaction::begin(self.shared.clone())));
+                    Ok(())
+                },
+            }),
+            "COMMIT" | "ROLLBACK" => match session.transaction.take() {
+                Some(open) => {
+                    let commit = keyword == "COMMIT";
+                    open.run(move |_, transaction| {
+                        let transaction = transaction.take().expect("Jobs run until the transaction ends");
+                        if commit { transaction.commit() } else { transaction.rollback() }
+                    })
+                },
+                None => Err(DbError::InputError("No transaction is open".to_string())),
+            },
+            _ => return None,
+        };
+        Some(done.map(|()| keyword))
+    }
+
+    // As `handle`, for a connection that must have authenticated if the server has credentials.
+    // Runs in the connection's transaction if it began one.
+    pub fn handle_as(&self, session: &Session, text: &str) -> Result<Reply, DbError> {
+        self.check_auth(session)?;
+        let throttles = self.admit(session)?;
+        let caller = Server::caller(session, Some(text));
+        let reply = match &session.transaction {
+            Some(open) => {
+                let owned = text.to_string();
+                open.run(move |shared, transaction| shared.handle(&owned, &caller, transaction.as_mut()))
+            },
+            None => self.run(text, caller),
+        };
+        charged(&throttles, reply)
+    }
+
+    // Runs the command whoever sends it, for servers embedded in a program
+    pub fn handle(&self, text: &str) -> Result<Reply, DbError> {
+        self.run(text, Caller::default())
+    }
+
+    // Runs on the calling thread, even if the server has a pool
+    pub fn execute(&self, command: Command) -> Result<Reply, DbError> {
+        self.shared.execute(command, &Caller::default(), None)
+    }
+
+    // As `execute`, for a connection, see `handle_as`
+    pub fn execute_as(&self, session: &Session, command: Command) -> Result<Reply, DbError> {
+        self.check_auth(session)?;
+        let throttles = self.admit(session)?;
+        charged(&throttles, self.shared.execute(command, &Server::caller(session, None), None))
+    }
+
+    // Runs a select, holding its rows in memory only up to `STREAM_MEMORY_BYTES`
+    pub fn select_stream_as(&self, session: &Session, text: &str) -> Result<ResultStream, DbError> {
+        self.stream_statement_as(session, text, Statement::Text(text.to_string()))
+    }
+
+    // As `select_stream_as`, for a `Command::Select` encoded, see `serial`
+    pub fn query_stream_as(&self, session: &Session, query: &[u8]) -> Result<ResultStream, DbError> {
+        let text = match serial::decode(query)? {
+            Command::Select { table, columns, filter } if columns.is_empty() => format!("SELECT * FROM {table} WHERE {filter}"),
+            Command::Select { table, columns, filter } => format!("SELECT {} FROM {table} WHERE {filter}", columns.join(", ")),
+            _ => return Err(DbError::InputError("Only selects are streamed".to_string())),
+        };
+        self.stream_statement_as(session, &text, Statement::Encoded(query.to_vec()))
+    }
+
+    // `text` is how the statement shows in STATS
+    fn stream_statement_as(&self, session: &Session, text: &str, statement: Statement) -> Result<ResultStream, DbError> {
+        self.check_auth(session)?;
+        let throttles = self.admit(session)?;
+        let caller = Server::caller(session, Some(text));
+        let rows = match &session.transaction {
+            Some(open) => open.run(move |shared, transaction| shared.select_stream(&statement, &caller, transaction.as_mut()))?,
+            None => self.on_pool(text, move |shared| shared.select_stream(&statement, &caller, None))?,
+        };
+        for throttle in &throttles {
+            throttle.charge(rows.scan_stats.rows_scanned, rows.data_bytes());
+        }
+        Ok(rows)
+    }
+
+    // Stores the rows of an insert streamed by the connection, see `frame::INSERT_ROWS`
+    pub fn insert_rows_as(&self, session: &mut Session, insert: &[u8]) -> Result<WindowUpdate, DbError> {
+        let Command::Insert { table, columns, rows } = serial::decode(insert)? else {
+            return Err(DbError::InputError("Only inserts are streamed".to_string()));
+        };
+        self.check_access(session, Privilege::Write, table)?;
+        if session.transaction.is_some() {
+            return Err(DbError::UnsupportedOperation("Inserts aren't streamed in a transaction".to_string()));
+        }
+        let db = self.database();
+        let stream = match &session.insert {
+            Some(stream) => stream.clone(),
+            None => {
+                let stream = InsertStream::open_within(&db, table, &columns, DEFAULT_INSERT_WINDOW, self.insert_budget.clone())?;
+                session.insert.insert(Arc::new(Mutex::new(stream))).clone()
+            },
+        };
+        let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+        if !stream.is_into(table, &columns) {
+            return Err(DbError::InputError("Rows are streamed into other columns until INSERT_DONE".to_string()));
+        }
+        let started = Instant::now();
+        let update = encode_rows(db.schema_for(table)?, &columns, rows).and_then(|rows| stream.push(&db, &rows));
+        let inserted = update.as_ref().map_or(0, |update| update.accepted);
+        self.shared.metrics.record("insert", started.elapsed(), update.is_err(), RowCounts { inserted, ..Default::default() });
+        update
+    }
+
+    // Commits the rows the connection streamed, how many there were
+    pub fn insert_done_as(&self, session: &mut Session) -> Result<usize, DbError> {
+        let stream = session.insert.take().ok_or_else(|| DbError::InputError("No insert is streamed".to_string()))?;
+        let mut stream = stream.lock().unwrap_or_else(PoisonError::into_inner);
+        stream.finish(&self.database())
+    }
+
+    // Appends a chunk to the blob the connection is writing, see `frame::BLOB_WRITE`
+    pub fn write_blob_as(&self, session: &mut Session, chunk: &[u8]) -> Result<(), DbError> {
+        self.check_auth(session)?;
+        self.shared.check_writable(Privilege::Write)?;
+        if chunk.len() > BLOB_CHUNK_SIZE {
+            return Err(DbError::InputError(format!("Blob chunks hold at most {BLOB_CHUNK_SIZE} bytes, got {}", chunk.len())));
+        }
+        let writer = match &session.blob {
+            Some(writer) => writer.clone(),
+            None => session.blob.insert(Arc::new(Mutex::new(self.database().create_blob()?))).clone(),
+        };
+        let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+        writer.write_all(chunk).map_err(|err| DbError::StorageError(format!("Failed to write blob: {err}")))
+    }
+
+    // Stores the blob the connection wrote, giving its id
+    pub fn finish_blob_as(&self, session: &mut Session) -> Result<BlobId, DbError> {
+        let writer = session.blob.take().ok_or_else(|| DbError::InputError("No blob is written".to_string()))?;
+        let writer = Arc::into_inner(writer).ok_or_else(|| DbError::InputError("Blob is written by another request".to_string()))?;
+        self.database().finish_blob(writer.into_inner().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    // BLOB_CHUNK frames of the blob with the id in `text`, then a DONE frame with its bytes
+    fn read_blob_as(&self, request_id: u32, session: &Session, text: &str, out: &mut dyn FnMut(Frame) -> io::Result<()>) -> io::Result<()> {
+        let reader = self.check_auth(session)
+            .and_then(|()| text.trim().parse().map_err(|_| DbError::InputError(format!("Invalid blob id {text}"))))
+            .and_then(|id| self.database().get_blob_reader(BlobId(id)));
+        let mut reader = match reader {
+            Ok(reader) => reader,
+            Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+        };
+        let mut sent = 0;
+        loop {
+            let mut chunk = Vec::with_capacity(BLOB_CHUNK_SIZE);
+            match reader.by_ref().take(BLOB_CHUNK_SIZE as u64).read_to_end(&mut chunk) {
+                Ok(0) => return out(Frame::new(frame::DONE, request_id, format!("READ {sent}"))),
+                Ok(len) => sent += len,
+                Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&DbError::StorageError(format!("Failed to read blob: {err}"))))),
+            }
+            out(Frame::new(frame::BLOB_CHUNK, request_id, chunk))?;
+        }
+    }
+
+    // The throttles of the session's connection and user, once they all let a query start
+    fn admit(&self, session: &Session) -> Result<Vec<Arc<Throttle>>, DbError> {
+        let mut throttles: Vec<Arc<Throttle>> = session.throttle.iter().cloned().collect();
+        let per_user = self.throttles.per_user;
+        if let Some(user) = session.user.as_ref().filter(|_| !per_user.is_empty()) {
+            let mut users = self.throttles.users.lock().unwrap_or_else(PoisonError::into_inner);
+            throttles.push(users.entry(user.clone()).or_insert_with(|| Arc::new(Throttle::new(per_user))).clone());
+        }
+        for throttle in &throttles {
+            throttle.admit()?;
+        }
+        Ok(throttles)
+    }
+
+    fn run(&self, text: &str, caller: Caller) -> Result<Reply, DbError> {
+        let owned = text.to_string();
+        self.on_pool(text, move |shared| shared.handle(&owned, &caller, None))
+    }
+
+    // Calls `call` on the server's pool if it has one, on the calling thread if not
+    fn on_pool<T: Send + 'static>(&self, text: &str, call: impl FnOnce(&Shared) -> Result<T, DbError> + Send + 'static) -> Result<T, DbError> {
+        let Some(pool) = &self.pool else { return call(&self.shared) };
+        let (sender, receiver) = sync_channel(1);
+        let trace = telemetry::current();
+        let job: Job = Box::new(move |shared| {
+            let _trace = trace.attach();
+            let _ = sender.send(call(shared));
+        });
+        pool.try_submit(job).map_err(|_| DbError::ServerBusy)?;
+        receiver.recv().unwrap_or_else(|_| Err(DbError::DatabaseIntegrityError(format!("Command panicked: {text}"))))
+    }
+}
+
+impl Shared {
+
+    fn new(db: Database, storage: StorageCfg) -> Shared {
+        Shared {
+            db: RwLock::new(db),
+            storage,
+            permissions: RwLock::new(None),
+            cancels: CancelRegistry::default(),
+            started: Instant::now(),
+            metrics: Metrics::default(),
+            read_only: AtomicBool::new(false),
+            cursors_open: Arc::default(),
+        }
+    }
+
+    fn database(&self) -> RwLockReadGuard<'_, Database> {
+        self.db.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn handle(&self, text: &str, caller: &Caller, transaction: Option<&mut Transaction>) -> Result<Reply, DbError> {
+        let command = telemetry::in_span("parse", || parse(text)).inspect_err(|_| self.metrics.record("invalid", Duration::ZERO, true, RowCounts::default()))?;
+        self.execute(command, caller, transaction)
+    }
+
+    fn select_stream(&self, statement: &Statement, caller: &Caller, transaction: Option<&mut Transaction>) -> Result<ResultStream, DbError> {
+        let started = Instant::now();
+        let rows = self.run_select_stream(statement, caller, transaction);
+        let scanned = rows.as_ref().map_or(0, |rows| rows.scan_stats.rows_scanned);
+        self.metrics.record("select", started.elapsed(), rows.is_err(), RowCounts { scanned, ..Default::default() });
+        rows
+    }
+
+    // Rows selected in a transaction are held in memory, as the transaction selects them
+    fn run_select_stream(&self, statement: &Statement, caller: &Caller, transaction: Option<&mut Transaction>) -> Result<ResultStream, DbError> {
+        let Command::Select { table, columns, filter } = telemetry::in_span("parse", || statement.command())? else {
+            return Err(DbError::InputError("Only selects are streamed".to_string()));
+        };
+        if let Some(user) = &caller.user {
+            self.check_access(user, Privilege::Read, table)?;
+        }
+        if let Some(transaction) = transaction {
+            let filter = coerce_numbers(transaction.database().schema_for(table)?, filter);
+            let values = select_values(transaction.database(), table, columns)?;
+            return Ok(ResultStream::of_result_set(transaction.select(&values, table, &filter)?));
+        }
+        let db = self.database();
+        let filter = coerce_numbers(db.schema_for(table)?, filter);
+        let values = select_values(&db, table, columns)?;
+        let options = QueryOptions { cancel: caller.cancel.clone(), ..Default::default() };
+        db.select_stream(&values, table, &filter, &options, STREAM_MEMORY_BYTES)
+    }
+
+    fn check_writable(&self, privilege: Privilege) -> Result<(), DbError> {
+        match privilege {
+            Privilege::Write | Privilege::Ddl if self.read_only.load(Ordering::Relaxed) => Err(DbError::ReadOnly),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_access(&self, user: &str, privilege: Privilege, table: &str) -> Result<(), DbError> {
+        match &*self.permissions.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(permissions) => permissions.check(user, privilege, table),
+            None => Ok(()),
+        }
+    }
+
+    // Whether the user may do anything to the table
+    fn can_see(&self, user: &str, table: &str) -> bool {
+        match &*self.permissions.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(permissions) => [Privilege::Read, Privilege::Write, Privilege::Ddl].into_iter().any(|privilege| permissions.allows(user, privilege, table)),
+            None => true,
+        }
+    }
+
+    // Runs the command, counted in the metrics
+    fn execute(&self, command: Command, caller: &Caller, transaction: Option<&mut Transaction>) -> Result<Reply, DbError> {
+        let (name, started) = (command.name(), Instant::now());
+        let reply = self.run_command(command, caller, transaction);
+        let rows = match &reply {
+            Ok(Reply::Inserted(rows)) => RowCounts { inserted: *rows, ..Default::default() },
+            Ok(Reply::Deleted(rows)) => RowCounts { deleted: *rows, ..Default::default() },
+            Ok(Reply::Selected(results)) => RowCounts { scanned: results.scan_stats.rows_scanned, ..Default::default() },
+            _ => RowCounts::default(),
+        };
+        self.metrics.record(name, started.elapsed(), reply.is_err(), rows);
+        reply
+    }
+
+    // Creating a table waits for the commands running, the others run concurrently. Selects stop
+    // once the caller's token is cancelled.
+    fn run_command(&self, command: Command, caller: &Caller, transaction: Option<&mut Transaction>) -> Result<Reply, DbError> {
+        if let Some((privilege, table)) = access(&command) {
+            self.check_writable(privilege)?;
+            if let Some(user) = &caller.user {
+                self.check_access(user, privilege, table)?;
+            }
+        }
+        let command = match command {
+            Command::Declare { cursor, table, columns, filter } => {
+                let Reply::Selected(results) = self.run_command(Command::Select { table, columns, filter }, caller, transaction)? else {
+                    unreachable!("Selects reply with their rows")
+                };
+                return caller.cursors()?.declare(cursor, results).map(|()| Reply::Declared);
+            },
+            Command::Fetch { cursor, count } => return caller.cursors()?.fetch(cursor, count).map(Reply::Selected),
+            Command::Close { cursor } => return caller.cursors()?.close(cursor).map(|()| Reply::Closed),
+            command => command,
+        };
+        if let Some(transaction) = transaction {
+            return Shared::run_in(command, transaction);
+        }
+        match command {
+            Command::CreateTable { table, columns } => {
+                let mut db = self.db.write().unwrap_or_else(PoisonError::into_inner);
+                db.new_table(&Table::new(table, columns), self.storage.clone())?;
+                Ok(Reply::Created)
+            },
+            Command::Insert { table, columns, rows } => {
+                let db = self.database();
+                let encoded = encode_rows(db.schema_for(table)?, &columns, rows)?;
+                Ok(Reply::Inserted(db.insert(table, &columns, &encoded)?))
+            },
+            Command::Select { table, columns, filter } => {
+                let db = self.database();
+                let filter = coerce_numbers(db.schema_for(table)?, filter);
+                let values = select_values(&db, table, columns)?;
+                let options = QueryOptions { cancel: caller.cancel.clone(), ..Default::default() };
+                Ok(Reply::Selected(db.select_with_options(&values, table, &filter, &options)?))
+            },
+            Command::Delete { table, filter } => {
+                let db = self.database();
+                let filter = coerce_numbers(db.schema_for(table)?, filter);
+                Ok(Reply::Deleted(db.delete(table, &filter)?))
+            },
+            Command::ShowTables => {
+                let db = self.database();
+                let rows = db.table_names().into_iter()
+                    .filter(|table| caller.user.as_deref().is_none_or(|user| self.can_see(user, table)))
+                    .map(|table| vec![table.as_bytes().to_vec()])
+                    .collect();
+                Ok(Reply::Selected(reply_rows(vec![text_column("name")], rows)))
+            },
+            Command::Describe { table } => {
+                let db = self.database();
+                let fingerprint = format!("{:016x}", db.schema_fingerprint(table)?);
+                let rows = db.schema_for(table)?.column_layout.iter()
+                    .map(|column| vec![column.name.as_bytes().to_vec(), type_sql(&column.dtype).into_bytes(), fingerprint.clone().into_bytes()])
+                    .collect();
+                Ok(Reply::Selected(reply_rows(vec![text_column("column"), text_column("type"), text_column("fingerprint")], rows)))
+            },
+            Command::Status => {
+                let schema = vec![Column::new("uptime_seconds", DataType::F64), Column::new("connections", DataType::U32), Column::new("running", DataType::U32)];
+                let row = vec![f64_value(self.started.elapsed().as_secs_f64()), u32_value(self.cancels.connections()), u32_value(self.cancels.running().len())];
+                Ok(Reply::Selected(reply_rows(schema, vec![row])))
+            },
+            Command::Stats => {
+                let schema = vec![Column::new("connection", DataType::U32), text_column("user"), Column::new("seconds", DataType::F64), text_column("query")];
+                let rows = self.cancels.running().into_iter().map(|query| vec![
+                    u32_value(query.connection as usize),
+                    query.user.unwrap_or_default().into_bytes(),
+                    f64_value(query.started.elapsed().as_secs_f64()),
+                    query.text.into_bytes(),
+                ]).collect();
+                Ok(Reply::Selected(reply_rows(schema, rows)))
+            },
+            Command::Declare { .. } | Command::Fetch { .. } | Command::Close { .. } => unreachable!("Cursors are run above"),
+            Command::ListTables => {
+                let schema = vec![text_column("name"), Column::new("rows", DataType::U32), Column::new("disk_bytes", DataType::F64)];
+                let rows = self.database().table_stats()?.into_iter()
+                    .map(|table| vec![table.name.into_bytes(), u32_value(table.rows), f64_value(table.disk_bytes as f64)])
+                    .collect();
+                Ok(Reply::Selected(reply_rows(schema, rows)))
+            },
+        }
+    }
+
+    // The commands that run in a connection's transaction, inserts, deletes and selects
+    fn run_in(command: Command, transaction: &mut Transaction) -> Result<Reply, DbError> {
+        let db = transaction.database();
+        match command {
+            Command::Insert { table, columns, rows } => {
+                let encoded = encode_rows(db.schema_for(table)?, &columns, rows)?;
+                Ok(Reply::Inserted(transaction.insert(table, &columns, &encoded)?))
+            },
+            Command::Select { table, columns, filter } => {
+                let filter = coerce_numbers(db.schema_for(table)?, filter);
+                let values = select_values(db, table, columns)?;
+                Ok(Reply::Selected(transaction.select(&values, table, &filter)?))
+            },
+            Command::Delete { table, filter } => {
+                let filter = coerce_numbers(db.schema_for(table)?, filter);
+                Ok(Reply::Deleted(transaction.delete(table, &filter)?))
+            },
+            command => Err(DbError::UnsupportedOperation(format!("Only inserts, deletes and selects run in a transaction, not {}", command.name()))),
+        }
+    }
+}
+
+// As a column's type is given in CREATE TABLE
+fn type_sql(dtype: &DataType) -> String {
+    match dtype {
+        DataType::U32 => "U32".to_string(),
+        DataType::F64 => "F64".to_string(),
+        DataType::UTF8 { max_bytes } => format!("UTF8({max_bytes})"),
+        DataType::VARBINARY { max_length } => format!("VARBINARY({max_length})"),
+        DataType::BUFFER { length } => format!("BUFFER({length})"),
+        DataType::UUID => "UUID".to_string(),
+        DataType::ENUM { labels } => format!("ENUM({})", labels.iter().map(|label| format!("'{label}'")).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+// Rows replied to a catalog or admin command, their values stored as in tables
+fn reply_rows(schema: Vec<Column>, rows: Vec<Vec<Vec<u8>>>) -> ResultSet {
+    let data = rows.iter().map(|row| Row::of_columns(&row.iter().map(Vec::as_slice).collect::<Vec<_>>())).collect();
+    ResultSet { schema, data, scan_stats: ScanStats::default() }
+}
+
+// Holds any text a frame can, commands included
+fn text_column(name: &str) -> Column {
+    Column::new(name, DataType::UTF8 { max_bytes: frame::MAX_FRAME_BYTES })
+}
+
+fn u32_value(n: usize) -> Vec<u8> {
+    u32::try_from(n).unwrap_or(u32::MAX).to_le_bytes().to_vec()
+}
+
+fn f64_value(n: f64) -> Vec<u8> {
+    n.to_le_bytes().to_vec()
+}
+
+// The privilege the command needs, on the table it's for, None if anyone may run it
+fn access<'c>(command: &'c Command) -> Option<(Privilege, &'c str)> {
+    match command {
+        Command::CreateTable { table, .. } => Some((Privilege::Ddl, table)),
+        Command::Insert { table, .. } | Command::Delete { table, .. } => Some((Privilege::Write, table)),
+        Command::Select { table, .. } | Command::Declare { table, .. } | Command::Describe { table } => Some((Privilege::Read, table)),
+        Command::Fetch { .. } | Command::Close { .. } | Command::ShowTables => None,
+        Command::Status | Command::Stats | Command::ListTables => Some((Privilege::Admin, ALL_TABLES)),
+    }
+}
+
+// Charges what a select scanned and returned to the throttles that let it run
+fn charged(throttles: &[Arc<Throttle>], reply: Result<Reply, DbError>) -> Result<Reply, DbError> {
+    if let Ok(Reply::Selected(results)) = &reply {
+        let bytes = results.data.iter().map(|row| row.data.len()).sum();
+        for throttle in throttles {
+            throttle.charge(results.scan_stats.rows_scanned, bytes);
+        }
+    }
+    reply
+}
+
+// The columns selected, all of the table's if none are given
+fn select_values<'a>(db: &'a Database, table: &str, columns: Vec<&'a str>) -> Result<Vec<Value<'a>>, DbError> {
+    if columns.is_empty() {
+        Ok(db.schema_for(table)?.column_layout.iter().map(|col| Value::ColumnRef(&col.name)).collect())
+    } else {
+        Ok(columns.into_iter().map(Value::ColumnRef).collect())
+    }
+}
+
+// Frames tagged `tag` of what `replies` makes of the feed's changes after `after`, empty ones after
+// `changes::HEARTBEAT` without any, until the connection is gone. An ERROR frame ends them if the
+// changes can't be followed.
+fn follow_feed(request_id: u32, tag: u8, feed: &ChangeFeed, mut after: u64, out: &mut dyn FnMut(Frame) -> io::Result<()>,
+               mut replies: impl FnMut(&[WalRecord]) -> Result<String, DbError>) -> io::Result<()> {
+    loop {
+        let reply = feed.changes_after(after, changes::HEARTBEAT)
+            .and_then(|records| Ok((replies(&records)?, records)));
+        let (reply, records) = match reply {
+            Ok(reply) => reply,
+            Err(err) => return out(Frame::error(request_id, &ErrorReply::from(&err))),
+        };
+        after = records.last().map_or(after, |record| record.sequence);
+        if !reply.is_empty() || records.is_empty() {
+            out(Frame::new(tag, request_id, reply))?;
+        }
+    }
+}
+
+// Whether the statement parses as a select, which can be streamed
+pub(crate) fn is_select(text: &str) -> bool {
+    matches!(parse(text), Ok(Command::Select { .. }))
+}
+
+// Statements of a line of text mode, split at semicolons outside strings
+pub(crate) fn statements(line: &str) -> impl Iterator<Item = &str> {
+    let mut quoted = false;
+    line.split(move |c| {
+        quoted ^= c == '\'';
+        c == ';' && !quoted
+    }).map(str::trim).filter(|statement| !statement.is_empty())
+}
+
+// None if the statement isn't a SET FORMAT
+fn set_format(statement: &str) -> Option<Result<TextFormat, DbError>> {
+    let words: Vec<&str> = statement.split_whitespace().collect();
+    match words[..] {
+        [set, format, name] if set.eq_ignore_ascii_case("SET") && format.eq_ignore_ascii_case("FORMAT") => Some(
+            match name.to_ascii_uppercase().as_str() {
+                "TABLE" => Ok(TextFormat::Table),
+                "CSV" => Ok(TextFormat::Csv),
+                _ => Err(DbError::InputError(format!("Unknown format {name}, expected TABLE or CSV"))),
+            }
+        ),
+        _ => None,
+    }
+}
+
+// Reply to a CANCEL frame or statement, holding a cancel key
+fn cancel(server: &Server, key: &str) -> Result<String, DbError> {
+    let key = CancelKey::parse(key).ok_or_else(|| DbError::InputError(format!("Bad cancel key {key}, expected id secret")))?;
+    server.cancel(key);
+    // Whether a connection had the key isn't told, as in PostgreSQL
+    Ok("OK".to_string())
+}
+
+// None if the statement isn't CANCEL KEY or CANCEL id secret
+fn cancel_statement(server: &Server, session: &Session, statement: &str) -> Option<Result<String, DbError>> {
+    let (first, rest) = statement.split_once(char::is_whitespace)?;
+    if !first.eq_ignore_ascii_case("CANCEL") {
+        return None;
+    }
+    Some(match rest.trim() {
+        key if key.eq_ignore_ascii_case("KEY") => session.cancel_key().map(|key| key.to_string())
+            .ok_or_else(|| DbError::UnsupportedOperation("Connection can't be cancelled".to_string())),
+        key => cancel(server, key),
+    })
+}
+
+// User, if given, and secret of an AUTH statement, None if the statement isn't one
+fn auth_statement(statement: &str) -> Option<(Option<&str>, &str)> {
+    let words: Vec<&str> = statement.split_whitespace().collect();
+    match words[..] {
+        [auth, secret] if auth.eq_ignore_ascii_case("AUTH") => Some((None, secret)),
+        [auth, user, secret] if auth.eq_ignore_ascii_case("AUTH") => Some((Some(user), secret)),
+        _ => None,
+    }
+}
+
+// Rows of an insert's literals, in the order of `columns`
+fn encode_rows(schema: &Table, columns: &[&str], rows: Vec<Vec<Literal>>) -> Result<Vec<Row>, DbError> {
+    let mut encoded = Vec::with_capacity(rows.len());
+    for row in rows {
+        if row.len() != columns.len() {
+            return Err(DbError::InvalidColumnCount { expected: columns.len(), got: row.len() });
+        }
+        let mut values = Vec::with_capacity(row.len());
+        for (column, literal) in columns.iter().zip(row) {
+            values.push(encode(schema.require_column(column)?.1, literal)?);
+        }
+        encoded.push(Row::of_columns(&values.iter().map(Vec::as_slice).collect::<Vec<_>>()));
+    }
+    Ok(encoded)
+}
+
+// The filter with integer constants compared to an F64 column taken as F64s, as `encode` takes
+// them for an insert, so `price > 3` compares numbers rather than failing on a U32
+fn coerce_numbers<'a>(schema: &Table, filter: Bool<'a>) -> Bool<'a> {
+    let compare = |op: fn(Value<'a>, Value<'a>) -> Bool<'a>, left: Value<'a>, right: Value<'a>| {
+        let is_f64 = |value: &Value| matches!(value, Value::ColumnRef(name)
+            if schema.columns.get(*name).is_some_and(|(_, column)| column.dtype == DataType::F64));
+        let coerce = |value: Value<'a>, other_f64: bool| match value {
+            Value::Const(ColumnValue::U32(number)) if other_f64 => Value::Const(ColumnValue::F64(number.into())),
+            value => value,
+        };
+        let (left_f64, right_f64) = (is_f64(&left), is_f64(&right));
+        op(coerce(left, right_f64), coerce(right, left_f64))
+    };
+    let combine = |op: fn(Box<Bool<'a>>, Box<Bool<'a>>) -> Bool<'a>, left: Box<Bool<'a>>, right: Box<Bool<'a>>| {
+        op(Box::new(coerce_numbers(schema, *left)), Box::new(coerce_numbers(schema, *right)))
+    };
+    match filter {
+        Bool::True => Bool::True,
+        Bool::False => Bool::False,
+        Bool::Eq(left, right) => compare(Bool::Eq, left, right),
+        Bool::Neq(left, right) => compare(Bool::Neq, left, right),
+        Bool::Gt(left, right) => compare(Bool::Gt, left, right),
+        Bool::Gte(left, right) => compare(Bool::Gte, left, right),
+        Bool::Lt(left, right) => compare(Bool::Lt, left, right),
+        Bool::Lte(left, right) => compare(Bool::Lte, left, right),
+        Bool::IsDistinctFrom(left, right) => compare(Bool::IsDistinctFrom, left, right),
+        Bool::IsNotDistinctFrom(left, right) => compare(Bool::IsNotDistinctFrom, left, right),
+        Bool::And(left, right) => combine(Bool::And, left, right),
+        Bool::Or(left, right) => combine(Bool::Or, left, right),
+        Bool::Xor(left, right) => combine(Bool::Xor, left, right),
+        Bool::Not(inner) => Bool::Not(Box::new(coerce_numbers(schema, *inner))),
+    }
+}
+
+// Stored bytes of a value given for `column`
+fn encode(column: &Column, literal: Literal) -> Result<Vec<u8>, DbError> {
+    let mismatch = || DbError::InputError(format!("{literal:?} can't be stored in {}, a {:?} column", column.name, column.dtype));
+    match (&column.dtype, literal) {
+        (DataType::U32, Literal::Number(number)) => number.parse::<u32>().map(|val| val.to_le_bytes().to_vec()).map_err(|_| mismatch()),
+        (DataType::F64, Literal::Number(number)) => number.parse::<f64>().map(|val| val.to_le_bytes().to_vec()).map_err(|_| mismatch()),
+        (DataType::UTF8 { .. } | DataType::ENUM { .. }, Literal::Text(text)) => Ok(text.as_bytes().to_vec()),
+        (DataType::UUID, Literal::Text(text)) => Uuid::parse(text).map(|uuid| uuid.0.to_vec()).map_err(|_| mismatch()),
+        (DataType::VARBINARY { .. } | DataType::BUFFER { .. }, Literal::Hex(hex)) => decode_hex(hex).ok_or_else(mismatch),
+        _ => Err(mismatch()),
+    }
+}
+
+pub(crate) fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok()).collect()
+}
+
+pub fn parse(text: &str) -> Result<Command<'_>, DbError> {
+    let mut parser = Parser { tokens: tokenize(text)?, next: 0 };
+    let command = if parser.keyword("CREATE") {
+        parser.expect_keyword("TABLE")?;
+        let table = parser.name()?;
+        let columns = parser.list(Parser::column)?;
+        Command::CreateTable { table, columns }
+    } else if parser.keyword("INSERT") {
+        parser.expect_keyword("INTO")?;
+        let table = parser.name()?;
+        let columns = parser.list(Parser::name)?;
+        parser.expect_keyword("VALUES")?;
+        let mut rows = vec![parser.list(Parser::literal)?];
+        while parser.symbol(",") {
+            rows.push(parser.list(Parser::literal)?);
+        }
+        Command::Insert { table, columns, rows }
+    } else if parser.keyword("SELECT") {
+        let (table, columns, filter) = parser.select()?;
+        Command::Select { table, columns, filter }
+    } else if parser.keyword("DECLARE") {
+        let cursor = parser.name()?;
+        parser.expect_keyword("CURSOR")?;
+        parser.expect_keyword("FOR")?;
+        parser.expect_keyword("SELECT")?;
+        let (table, columns, filter) = parser.select()?;
+        Command::Declare { cursor, table, columns, filter }
+    } else if parser.keyword("FETCH") {
+        let count = match parser.tokens.get(parser.next) {
+            Some(Token::Literal(Literal::Number(count))) => {
+                parser.next += 1;
+                count.parse().map_err(|_| DbError::InputError(format!("Invalid count {count}")))?
+            },
+            _ => 1,
+        };
+        parser.keyword("FROM");
+        Command::Fetch { cursor: parser.name()?, count }
+    } else if parser.keyword("CLOSE") {
+        Command::Close { cursor: parser.name()? }
+    } else if parser.keyword("DELETE") {
+        parser.expect_keyword("FROM")?;
+        let table = parser.name()?;
+        Command::Delete { table, filter: parser.filter()? }
+    } else if parser.keyword("SHOW") {
+        parser.expect_keyword("TABLES")?;
+        Command::ShowTables
+    } else if parser.keyword("DESCRIBE") {
+        Command::Describe { table: parser.name()? }
+    } else if parser.keyword("STATUS") {
+        Command::Status
+    } else if parser.keyword("STATS") {
+        Command::Stats
+    } else if parser.keyword("LIST") {
+        parser.expect_keyword("TABLES")?;
+        Command::ListTables
+    } else {
+        return Err(parser.unexpected("CREATE, INSERT, SELECT, DELETE, DECLARE, FETCH, CLOSE, SHOW, DESCRIBE, STATUS, STATS or LIST"));
+    };
+    match parser.tokens.get(parser.next) {
+        None => Ok(command),
+        Some(_) => Err(parser.unexpected("end of command")),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Symbol(&'a str),
+    Literal(Literal<'a>),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token<'_>>, DbError> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while let Some(first) = rest.chars().next() {
+        let end_of = |pred: fn(char) -> bool| rest.find(|c: char| !pred(c)).unwrap_or(rest.len());
+        let quoted = |from: usize| rest[from..].find('\'').map(|len| from + len)
+            .ok_or_else(|| DbError::InputError(format!("Unterminated string {rest}")));
+        let (token, len) = if first == '\'' {
+            let end = quoted(1)?;
+            (Token::Literal(Literal::Text(&rest[1..end])), end + 1)
+        } else if (first == 'x' || first == 'X') && rest[1..].starts_with('\'') {
+            let end = quoted(2)?;
+            (Token::Literal(Literal::Hex(&rest[2..end])), end + 1)
+        } else if first.is_ascii_digit() || (first == '-' && rest[1..].starts_with(|c: char| c.is_ascii_digit())) {
+            let len = 1 + rest[1..].find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len() - 1);
+            (Token::Literal(Literal::Number(&rest[..len])), len)
+        } else if first.is_alphanumeric() || first == '_' {
+            let len = end_of(|c| c.is_alphanumeric() || c == '_');
+            (Token::Word(&rest[..len]), len)
+        } else {
+            let len = ["<=", ">=", "!=", "<>"].iter().find(|op| rest.starts_with(*op)).map_or(first.len_utf8(), |op| op.len());
+            if !["(", ")", ",", "*", "=", "<", ">", "<=", ">=", "!=", "<>"].contains(&&rest[..len]) {
+                return Err(DbError::InputError(format!("Unexpected {}", &rest[..len])));
+            }
+            (Token::Symbol(&rest[..len]), len)
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+
+    fn unexpected(&self, expected: &str) -> DbError {
+        match self.tokens.get(self.next) {
+            Some(Token::Word(found) | Token::Symbol(found)) => DbError::InputError(format!("Expected {expected}, got {found}")),
+            Some(Token::Literal(found)) => DbError::InputError(format!("Expected {expected}, got {found:?}")),
+            None => DbError::InputError(format!("Expected {expected}, got end of command")),
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.tokens.get(self.next), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        self.next += found as usize;
+        found
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), DbError> {
+        if self.keyword(keyword) { Ok(()) } else { Err(self.unexpected(keyword)) }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = self.tokens.get(self.next) == Some(&Token::Symbol(symbol));
+        self.next += found as usize;
+        found
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> Result<(), DbError> {
+        if self.symbol(symbol) { Ok(()) } else { Err(self.unexpected(symbol)) }
+    }
+
+    fn name(&mut self) -> Result<&'a str, DbError> {
+        match self.tokens.get(self.next) {
+            Some(Token::Word(name)) => {
+                self.next += 1;
+                Ok(name)
+            },
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal<'a>, DbError> {
+        match self.tokens.get(self.next) {
+            Some(Token::Literal(literal)) => {
+                self.next += 1;
+                Ok(*literal)
+            },
+            _ => Err(self.unexpected("a value")),
+        }
+    }
+
+    // Items in parentheses, separated by commas
+    fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, DbError>) -> Result<Vec<T>, DbError> {
+        self.expect_symbol("(")?;
+        let mut items = vec![item(self)?];
+        while self.symbol(",") {
+            items.push(item(self)?);
+        }
+        self.expect_symbol(")")?;
+        Ok(items)
+    }
+
+    fn column(&mut self) -> Result<Column, DbError> {
+        let name = self.name()?;
+        let size = |parser: &mut Self| -> Result<usize, DbError> {
+            let size = parser.list(Parser::literal)?;
+            match size[..] {
+                [Literal::Number(size)] => size.parse().map_err(|_| DbError::InputError(format!("Invalid size {size} of {name}"))),
+                _ => Err(DbError::InputError(format!("{name} needs a size"))),
+            }
+        };
+        let dtype = match self.name()?.to_ascii_uppercase().as_str() {
+            "U32" => DataType::U32,
+            "F64" => DataType::F64,
+            "UUID" => DataType::UUID,
+            "UTF8" => DataType::UTF8 { max_bytes: size(self)? },
+            "VARBINARY" => DataType::VARBINARY { max_length: size(self)? },
+            "BUFFER" => DataType::BUFFER { length: size(self)? },
+            "ENUM" => {
+                let labels = self.list(Parser::literal)?.into_iter()
+                    .map(|label| match label {
+                        Literal::Text(label) => Ok(label.to_string()),
+                        other => Err(DbError::InputError(format!("Labels of {name} must be strings, got {other:?}"))),
+                    })
+                    .collect::<Result<_, _>>()?;
+                DataType::ENUM { labels }
+            },
+            other => return Err(DbError::InputError(format!("Unknown type {other} of {name}"))),
+        };
+        Ok(Column::new(name, dtype))
+    }
+
+    // What follows SELECT: the columns, * for all, the table and the filter
+    fn select(&mut self) -> Result<(&'a str, Vec<&'a str>, Bool<'a>), DbError> {
+        let mut columns = Vec::new();
+        if !self.symbol("*") {
+            columns.push(self.name()?);
+            while self.symbol(",") {
+                columns.push(self.name()?);
+            }
+        }
+        self.expect_keyword("FROM")?;
+        let table = self.name()?;
+        Ok((table, columns, self.filter()?))
+    }
+
+    // Optional WHERE clause, where AND binds closer than OR
+    fn filter(&mut self) -> Result<Bool<'a>, DbError> {
+        if self.keyword("WHERE") { self.or() } else { Ok(Bool::True) }
+    }
+
+    fn or(&mut self) -> Result<Bool<'a>, DbError> {
+        let mut filter = self.and()?;
+        while self.keyword("OR") {
+            filter = filter.or(self.and()?);
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Bool<'a>, DbError> {
+        let mut filter = self.not()?;
+        while self.keyword("AND") {
+            filter = filter.and(self.not()?);
+        }
+        Ok(filter)
+    }
+
+    fn not(&mut self) -> Result<Bool<'a>, DbError> {
+        if self.keyword("NOT") {
+            return Ok(Bool::Not(Box::new(self.not()?)));
+        }
+        if self.symbol("(") {
+            let filter = self.or()?;
+            self.expect_symbol(")")?;
+            return Ok(filter);
+        }
+        let left = self.operand()?;
+        let comparison = match self.tokens.get(self.next) {
+            Some(Token::Symbol("=")) => Bool::Eq,
+            Some(Token::Symbol("!=" | "<>")) => Bool::Neq,
+            Some(Token::Symbol("<")) => Bool::Lt,
+            Some(Token::Symbol("<=")) => Bool::Lte,
+            Some(Token::Symbol(">")) => Bool::Gt,
+            Some(Token::Symbol(">=")) => Bool::Gte,
+            _ => return Err(self.unexpected("a comparison")),
+        };
+        self.next += 1;
+        Ok(comparison(left, self.operand()?))
+    }
+
+    fn operand(&mut self) -> Result<Value<'a>, DbError> {
+        match self.tokens.get(self.next) {
+            Some(Token::Word(_)) => self.name().map(Value::ColumnRef),
+            Some(Token::Literal(Literal::Number(number))) => {
+                let number = *number;
+                self.next += 1;
+                let value = match number.parse::<u32>() {
+                    Ok(val) => ColumnValue::U32(val),
+                    Err(_) => ColumnValue::F64(number.parse().map_err(|_| DbError::InputError(format!("Invalid number {number}")))?),
+                };
+                Ok(Value::Const(value))
+            },
+            Some(Token::Literal(Literal::Text(text))) => {
+                let text = *text;
+                self.next += 1;
+                Ok(Value::Const(ColumnValue::UTF8(text)))
+            },
+            _ => Err(self.unexpected("a column or a value")),
+        }
+    }
+}
@@ -0,0 +1,318 @@
+// Replication of a primary's tables to read-only replicas
+// The primary keeps its latest stores, updates and deletes in a `ChangeFeed`, numbered as the WAL
+// numbers them, see `Server::with_change_feed`. Clients can follow a table's changes too, see
+// `changes`. A replica connects to it with frames and sends:
+//   SNAPSHOT   replied to with ROWS frames, each a `SnapshotChunk` of some rows of a table, then
+//              a DONE frame with the feed's sequence as text, the snapshot holds every change
+//              up to it
+//   CHANGES    the sequence the replica is at, as text, replied to with the changes after it as
+//              `FeedRecord`s, waiting up to `CHANGES_WAIT` for one. Fails with
+//              `DbError::ChangesExpired` once the feed dropped them.
+// Both send the fingerprint of each table's schema on the primary, see `Table::fingerprint`.
+// With permissions, both need the admin privilege. A table is read while no write can change
+// it, along with the feed's sequence then, and later changes are applied to it from there on.
+// `replicate` bootstraps a replica's server from a snapshot, then applies the primary's changes
+// to its own storage as they come. The replica's server fails writes, see `Server::as_replica`,
+// and serves reads as any other. A replica that falls further behind than the feed keeps, or
+// gets a change to a table created after its snapshot, or to one whose fingerprint changed since,
+// takes a new snapshot. Tables of the snapshot that the replica has are emptied first if their
+// fingerprint is the primary's, others it has are kept.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufReader, Read, Write};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, SystemTime};
+
+use crate::engine::{Database, DbError, Row, Table};
+use crate::errors::{self, ErrorReply};
+use crate::frame::{self, Frame};
+use crate::protocol::{Server, STREAM_CHUNK_BYTES};
+use crate::serial;
+use crate::storage::{RowId, StorageObserver};
+use crate::wal::{WalChange, WalRecord};
+
+// How long a CHANGES request waits for a change when there's none yet
+pub const CHANGES_WAIT: Duration = Duration::from_millis(500);
+// Changes replied to a CHANGES request stop once their rows take about this many bytes
+const CHANGES_MAX_BYTES: usize = 4 << 20;
+
+pub struct ChangeFeed {
+    // Changes kept, at least one
+    backlog: usize,
+    state: Mutex<FeedState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct FeedState {
+    changes: VecDeque<WalRecord>,
+    last_sequence: u64,
+}
+
+// Some rows of a table, with their ids on the primary, in a ROWS frame replying to SNAPSHOT
+#[derive(Debug, Clone)]
+pub struct SnapshotChunk {
+    pub table: Table,
+    // Of the table's schema on the primary
+    pub fingerprint: u64,
+    // Changes to the table up to this sequence are in the snapshot
+    pub sequence: u64,
+    pub row_ids: Vec<RowId>,
+    // In schema column order
+    pub rows: Vec<Row>,
+}
+
+// A change in a reply to CHANGES, with the fingerprint of its table's schema on the primary, 0 if
+// the table is gone
+#[derive(Debug, Clone)]
+pub struct FeedRecord {
+    pub fingerprint: u64,
+    pub record: WalRecord,
+}
+
+impl ChangeFeed {
+
+    // Keeps the latest `backlog` changes for replicas to catch up with
+    pub fn new(backlog: usize) -> ChangeFeed {
+        ChangeFeed { backlog: backlog.max(1), state: Mutex::default(), changed: Condvar::new() }
+    }
+
+    // Sequence of the last change, 0 if none
+    pub fn last_sequence(&self) -> u64 {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner).last_sequence
+    }
+
+    fn push(&self, table: &str, change: WalChange) {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.last_sequence += 1;
+        let record = WalRecord { sequence: state.last_sequence, time: SystemTime::now(), table: table.to_string(), change };
+        if state.changes.len() == self.backlog {
+            state.changes.pop_front();
+        }
+        state.changes.push_back(record);
+        self.changed.notify_all();
+    }
+
+    // The changes after `after`, in order, waiting up to `wait` for one if there's none yet
+    pub fn changes_after(&self, after: u64, wait: Duration) -> Result<Vec<WalRecord>, DbError> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (state, _) = self.changed.wait_timeout_while(state, wait, |state| state.last_sequence <= after)
+            .unwrap_or_else(PoisonError::into_inner);
+        let oldest = state.changes.front().map_or(state.last_sequence + 1, |record| record.sequence);
+        if after + 1 < oldest {
+            return Err(DbError::ChangesExpired { after, oldest });
+        }
+        let mut bytes = 0;
+        let changes = state.changes.iter().skip_while(|record| record.sequence <= after)
+            .take_while(|record| {
+                let fits = bytes < CHANGES_MAX_BYTES;
+                bytes += change_bytes(&record.change);
+                fits
+            })
+            .cloned()
+            .collect();
+        Ok(changes)
+    }
+}
+
+fn change_bytes(change: &WalChange) -> usize {
+    match change {
+        WalChange::Store { row_ids, rows } | WalChange::Update { row_ids, rows } =>
+            row_ids.len() * 8 + rows.iter().map(|row| row.data.len()).sum::<usize>(),
+        WalChange::Delete { row_ids } => row_ids.len() * 8,
+        WalChange::Checkpoint => 0,
+    }
+}
+
+impl StorageObserver for ChangeFeed {
+    fn on_store(&self, table: &str, row_ids: &[RowId], rows: &[Row], column_mapping: &[usize]) {
+        let rows = rows.iter()
+            .map(|row| Row::of_columns(&column_mapping.iter().map(|col_idx| row.get_column(*col_idx)).collect::<Vec<_>>()))
+            .collect();
+        self.push(table, WalChange::Store { row_ids: row_ids.to_vec(), rows });
+    }
+
+    fn on_delete(&self, table: &str, row_ids: &[RowId]) {
+        if !row_ids.is_empty() {
+            self.push(table, WalChange::Delete { row_ids: row_ids.to_vec() });
+        }
+    }
+
+    fn on_update(&self, table: &str, row_ids: &[RowId], rows: &[Row]) {
+        self.push(table, WalChange::Update { row_ids: row_ids.to_vec(), rows: rows.to_vec() });
+    }
+}
+
+// The table's rows as of now, in chunks of about `STREAM_CHUNK_BYTES`, at least one
+pub fn snapshot(db: &Database, table: &str, feed: &ChangeFeed) -> Result<Vec<SnapshotChunk>, DbError> {
+    let schema = db.schema_for(table)?.clone();
+    let fingerprint = db.schema_fingerprint(table)?;
+    let (sequence, row_ids, rows) = db.rows_with_ids(table, || feed.last_sequence())?;
+    let mut chunks = vec![SnapshotChunk { table: schema.clone(), fingerprint, sequence, row_ids: Vec::new(), rows: Vec::new() }];
+    let mut bytes = 0;
+    for (row_id, row) in row_ids.into_iter().zip(rows) {
+        if bytes >= STREAM_CHUNK_BYTES {
+            chunks.push(SnapshotChunk { table: schema.clone(), fingerprint, sequence, row_ids: Vec::new(), rows: Vec::new() });
+            bytes = 0;
+        }
+        bytes += row.data.len();
+        let chunk = chunks.last_mut().unwrap();
+        chunk.row_ids.push(row_id);
+        chunk.rows.push(row);
+    }
+    Ok(chunks)
+}
+
+// The changes of a reply to CHANGES, each with its table's fingerprint as of now
+pub fn feed_records(db: &Database, records: Vec<WalRecord>) -> Vec<FeedRecord> {
+    records.into_iter()
+        .map(|record| FeedRecord { fingerprint: db.schema_fingerprint(&record.table).unwrap_or(0), record })
+        .collect()
+}
+
+// Keeps `server` a replica of the primary at the other end of `input` and `output`, authenticating
+// with `token` if it's given. Returns once the primary closes the connection, fails if it can't
+// be replicated.
+pub fn replicate(server: &Server, input: impl Read, output: impl Write, token: Option<&str>) -> io::Result<()> {
+    let mut primary = Primary { input: BufReader::new(input), output, next_id: 0 };
+    if let Some(token) = token {
+        primary.request(frame::AUTH, token)?;
+        primary.reply()?;
+    }
+    let mut replica = Replica::default();
+    loop {
+        if !replica.bootstrap(server, &mut primary)? {
+            return Ok(());
+        }
+        loop {
+            match replica.follow(server, &mut primary)? {
+                Followed::Changes => {},
+                Followed::NeedsSnapshot => break,
+                Followed::Closed => return Ok(()),
+            }
+        }
+    }
+}
+
+struct Primary<R, W> {
+    input: BufReader<R>,
+    output: W,
+    next_id: u32,
+}
+
+impl<R: Read, W: Write> Primary<R, W> {
+
+    fn request(&mut self, tag: u8, payload: &str) -> io::Result<()> {
+        self.next_id = self.next_id.wrapping_add(1);
+        Frame::new(tag, self.next_id, payload).write_to(&mut self.output)
+    }
+
+    // The next frame replying to the last request, None once the primary closed the connection
+    fn reply(&mut self) -> io::Result<Option<Frame>> {
+        let reply = Frame::read_from(&mut self.input)?;
+        match reply {
+            Some(reply) if reply.request_id != self.next_id => Err(malformed(format!("Reply to request {} rather than {}", reply.request_id, self.next_id))),
+            Some(reply) if reply.tag == frame::ERROR => Err(ErrorReply::parse(&reply.payload).map_or_else(|| malformed("Unreadable error"), refused)),
+            reply => Ok(reply),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Replica {
+    // Sequence of each table's snapshot, its changes up to it are already applied
+    tables: HashMap<String, u64>,
+    // Of each table's schema on the primary as of its snapshot
+    fingerprints: HashMap<String, u64>,
+    // Ids of the primary's rows, mapped to the replica's for the primary's later deletes
+    row_ids: HashMap<String, HashMap<RowId, RowId>>,
+    // Sequence of the last change asked for
+    last_sequence: u64,
+}
+
+enum Followed {
+    Changes,
+    NeedsSnapshot,
+    Closed,
+}
+
+impl Replica {
+
+    // False if the primary closed the connection
+    fn bootstrap<R: Read, W: Write>(&mut self, server: &Server, primary: &mut Primary<R, W>) -> io::Result<bool> {
+        self.tables.clear();
+        self.fingerprints.clear();
+        self.row_ids.clear();
+        primary.request(frame::SNAPSHOT, "")?;
+        loop {
+            let Some(reply) = primary.reply()? else { return Ok(false) };
+            match reply.tag {
+                frame::ROWS => {
+                    let chunk: SnapshotChunk = serial::decode(&reply.payload).map_err(failed)?;
+                    let name = chunk.table.name.clone();
+                    if !self.tables.contains_key(&name) {
+                        server.replica_table(&chunk.table, chunk.fingerprint).map_err(failed)?;
+                        self.tables.insert(name.clone(), chunk.sequence);
+                        self.fingerprints.insert(name.clone(), chunk.fingerprint);
+                    }
+                    if !chunk.rows.is_empty() {
+                        let store = WalChange::Store { row_ids: chunk.row_ids, rows: chunk.rows };
+                        server.database().apply_logged(&name, store, self.row_ids.entry(name.clone()).or_default()).map_err(failed)?;
+                    }
+                },
+                frame::DONE => {
+                    self.last_sequence = sequence(&reply.payload)?;
+                    return Ok(true);
+                },
+                tag => return Err(malformed(format!("Unexpected frame {tag} in a snapshot"))),
+            }
+        }
+    }
+
+    fn follow<R: Read, W: Write>(&mut self, server: &Server, primary: &mut Primary<R, W>) -> io::Result<Followed> {
+        primary.request(frame::CHANGES, &self.last_sequence.to_string())?;
+        let reply = match primary.reply() {
+            Ok(Some(reply)) => reply,
+            Ok(None) => return Ok(Followed::Closed),
+            Err(err) if expired(&err) => return Ok(Followed::NeedsSnapshot),
+            Err(err) => return Err(err),
+        };
+        let changes: Vec<FeedRecord> = serial::decode(&reply.payload).map_err(failed)?;
+        for FeedRecord { fingerprint, record } in changes {
+            let Some(&snapshot) = self.tables.get(&record.table) else { return Ok(Followed::NeedsSnapshot) };
+            if self.fingerprints.get(&record.table) != Some(&fingerprint) {
+                return Ok(Followed::NeedsSnapshot);
+            }
+            if record.sequence > snapshot {
+                let row_ids = self.row_ids.entry(record.table.clone()).or_default();
+                server.database().apply_logged(&record.table, record.change, row_ids).map_err(failed)?;
+            }
+            self.last_sequence = record.sequence;
+        }
+        Ok(Followed::Changes)
+    }
+}
+
+fn sequence(payload: &[u8]) -> io::Result<u64> {
+    std::str::from_utf8(payload).ok().and_then(|text| text.parse().ok())
+        .ok_or_else(|| malformed("Snapshot without a sequence"))
+}
+
+fn malformed(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+// The primary refused a request
+fn refused(reply: ErrorReply) -> io::Error {
+    io::Error::other(reply)
+}
+
+// A change of the primary couldn't be applied
+fn failed(err: DbError) -> io::Error {
+    io::Error::other(format!("{err:?}"))
+}
+
+fn expired(err: &io::Error) -> bool {
+    let code = err.get_ref().and_then(|inner| inner.downcast_ref::<ErrorReply>()).map(|reply| reply.code);
+    code == Some(errors::code(&DbError::ChangesExpired { after: 0, oldest: 0 }))
+}
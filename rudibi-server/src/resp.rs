@@ -0,0 +1,251 @@
+// The Redis protocol (RESP 2), so Redis clients can use the server as a key-value store:
+//   SET key value, GET key, DEL key [key ...], EXISTS key [key ...]
+//   RSELECT * FROM Fruits WHERE id > 100
+// Keys and values are kept in the `KeyValues` table, created by the first SET with the server's
+// storage. Keys are UTF-8, values any bytes. RSELECT runs a select of `protocol` on any table and
+// replies with an array of rows, each an array of its values: bytes as they are, others as text.
+// PING, ECHO, QUIT and COMMAND, which clients send when connecting, are answered too. A server
+// with credentials, see `auth`, takes only AUTH [user] secret and QUIT until the connection
+// authenticates. With permissions, see `permissions`, SET and DEL need to write `KeyValues`, GET
+// and EXISTS to read it.
+// Commands are arrays of bulk strings, or inline as words on a line for telnet.
+
+use std::io::{self, BufRead, ErrorKind, Write};
+
+use crate::auth::Session;
+use crate::display::{format_raw, DisplayOptions};
+use crate::dtype::{ColumnValue, DataType};
+use crate::engine::{Column, DbError, Row};
+use crate::frame::MAX_FRAME_BYTES;
+use crate::permissions::Privilege;
+use crate::protocol::{parse, Command, Reply, Server};
+use crate::query::{Bool, Value};
+
+pub const TABLE: &str = "KeyValues";
+pub const MAX_KEY_BYTES: usize = 512;
+pub const MAX_VALUE_BYTES: usize = 1 << 20;
+
+const MAX_ARGS: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resp {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    // None is nil
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Resp>),
+}
+
+impl Resp {
+    pub fn write_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Resp::Simple(text) => out.extend_from_slice(format!("+{text}\r\n").as_bytes()),
+            // Line breaks would end the error early
+            Resp::Error(text) => out.extend_from_slice(format!("-{}\r\n", text.replace(['\r', '\n'], " ")).as_bytes()),
+            Resp::Integer(n) => out.extend_from_slice(format!(":{n}\r\n").as_bytes()),
+            Resp::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Resp::Bulk(Some(bytes)) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            },
+            Resp::Array(items) => {
+                out.extend_from_slice(format!("*{}\r\n", items.len()).as_bytes());
+                for item in items {
+                    item.write_into(out);
+                }
+            },
+        }
+    }
+}
+
+// Replies to each command read from `input`, in order, until it ends or the client quits. A
+// stream that isn't RESP ends the connection.
+pub fn serve(server: &Server, mut input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    let mut session = Session::default();
+    while let Some(args) = read_command(&mut input)? {
+        let Some(name) = args.first() else { continue };
+        let quit = name.eq_ignore_ascii_case(b"QUIT");
+        let reply = if quit {
+            Resp::Simple("OK")
+        } else {
+            run(server, &mut session, &args).unwrap_or_else(|err| Resp::Error(format!("ERR {err:?}")))
+        };
+        let mut bytes = Vec::new();
+        reply.write_into(&mut bytes);
+        output.write_all(&bytes)?;
+        output.flush()?;
+        if quit {
+            break;
+        }
+    }
+    Ok(())
+}
+
+pub fn run(server: &Server, session: &mut Session, args: &[Vec<u8>]) -> Result<Resp, DbError> {
+    let name = String::from_utf8_lossy(&args[0]).to_ascii_uppercase();
+    let args = &args[1..];
+    let arity = |ok: bool| if ok { Ok(()) } else { Err(DbError::InputError(format!("Wrong number of arguments for {name}"))) };
+    if name != "AUTH" {
+        server.check_auth(session)?;
+    }
+    match name.as_str() {
+        "AUTH" => {
+            arity(matches!(args.len(), 1 | 2))?;
+            let args = args.iter().map(|arg| std::str::from_utf8(arg)).collect::<Result<Vec<_>, _>>()
+                .map_err(|_| DbError::AuthenticationFailed)?;
+            let (user, secret) = if let [user, secret] = args[..] { (Some(user), secret) } else { (None, args[0]) };
+            server.authenticate(session, user, secret)?;
+            Ok(Resp::Simple("OK"))
+        },
+        "PING" => {
+            arity(args.len() <= 1)?;
+            Ok(args.first().map_or(Resp::Simple("PONG"), |msg| Resp::Bulk(Some(msg.clone()))))
+        },
+        "ECHO" => {
+            arity(args.len() == 1)?;
+            Ok(Resp::Bulk(Some(args[0].clone())))
+        },
+        "COMMAND" => Ok(Resp::Array(Vec::new())),
+        "SET" => {
+            arity(args.len() == 2)?;
+            server.check_access(session, Privilege::Write, TABLE)?;
+            set(server, key(&args[0])?, &args[1])?;
+            Ok(Resp::Simple("OK"))
+        },
+        "GET" => {
+            arity(args.len() == 1)?;
+            server.check_access(session, Privilege::Read, TABLE)?;
+            let db = server.database();
+            match db.select(&[Value::ColumnRef("value")], TABLE, &key_is(key(&args[0])?)) {
+                Ok(results) => Ok(Resp::Bulk(results.data.first().map(|row| row.get_column(0).to_vec()))),
+                Err(DbError::TableNotFound(_)) => Ok(Resp::Bulk(None)),
+                Err(err) => Err(err),
+            }
+        },
+        "DEL" => {
+            arity(!args.is_empty())?;
+            server.check_access(session, Privilege::Write, TABLE)?;
+            let keys = args.iter().map(|arg| key(arg)).collect::<Result<Vec<_>, _>>()?;
+            let filter = keys.into_iter().map(key_is).reduce(|a, b| Bool::Or(Box::new(a), Box::new(b))).unwrap();
+            match server.database().delete(TABLE, &filter) {
+                Ok(deleted) => Ok(Resp::Integer(deleted as i64)),
+                Err(DbError::TableNotFound(_)) => Ok(Resp::Integer(0)),
+                Err(err) => Err(err),
+            }
+        },
+        // A key given twice is counted twice
+        "EXISTS" => {
+            arity(!args.is_empty())?;
+            server.check_access(session, Privilege::Read, TABLE)?;
+            let db = server.database();
+            let mut found = 0;
+            for arg in args {
+                found += match db.count(TABLE, &key_is(key(arg)?)) {
+                    Ok(count) => count,
+                    Err(DbError::TableNotFound(_)) => 0,
+                    Err(err) => return Err(err),
+                };
+            }
+            Ok(Resp::Integer(found as i64))
+        },
+        "RSELECT" => {
+            arity(!args.is_empty())?;
+            let words = args.iter().map(|arg| std::str::from_utf8(arg)).collect::<Result<Vec<_>, _>>()
+                .map_err(|_| DbError::InputError("Select isn't UTF-8".to_string()))?;
+            let text = format!("SELECT {}", words.join(" "));
+            let Command::Select { table, columns, filter } = parse(&text)? else {
+                return Err(DbError::InputError("Expected a select".to_string()));
+            };
+            let Reply::Selected(results) = server.execute_as(session, Command::Select { table, columns, filter })? else {
+                unreachable!("Selects reply with rows");
+            };
+            Ok(Resp::Array(results.data.iter().map(|row| Resp::Array(
+                results.schema.iter().enumerate().map(|(idx, column)| Resp::Bulk(Some(bulk(&column.dtype, row.get_column(idx))))).collect()
+            )).collect()))
+        },
+        _ => Err(DbError::InputError(format!("Unknown command {name}"))),
+    }
+}
+
+// Replaces the value of `key`, creating the table on first use
+fn set(server: &Server, key: &str, value: &[u8]) -> Result<(), DbError> {
+    let missing = matches!(server.database().schema_for(TABLE), Err(DbError::TableNotFound(_)));
+    if missing {
+        match server.execute(Command::CreateTable { table: TABLE, columns: key_values_columns() }) {
+            Ok(_) | Err(DbError::TableAlreadyExists(_)) => {},
+            Err(err) => return Err(err),
+        }
+    }
+    let db = server.database();
+    let mut tx = db.begin();
+    tx.delete(TABLE, &key_is(key))?;
+    tx.insert(TABLE, &["key", "value"], &[Row::of_columns(&[key.as_bytes(), value])])?;
+    tx.commit()
+}
+
+fn key_values_columns() -> Vec<Column> {
+    vec![
+        Column::new("key", DataType::UTF8 { max_bytes: MAX_KEY_BYTES }),
+        Column::new("value", DataType::VARBINARY { max_length: MAX_VALUE_BYTES }),
+    ]
+}
+
+fn key(arg: &[u8]) -> Result<&str, DbError> {
+    std::str::from_utf8(arg).map_err(|_| DbError::InputError("Keys must be UTF-8".to_string()))
+}
+
+fn key_is(key: &str) -> Bool<'_> {
+    Bool::Eq(Value::ColumnRef("key"), Value::Const(ColumnValue::UTF8(key)))
+}
+
+fn bulk(dtype: &DataType, data: &[u8]) -> Vec<u8> {
+    match dtype {
+        DataType::VARBINARY { .. } | DataType::BUFFER { .. } => data.to_vec(),
+        _ => format_raw(dtype, data, &DisplayOptions::full()).into_bytes(),
+    }
+}
+
+// Arguments of the next command, None if the stream ends before it starts
+fn read_command(input: &mut impl BufRead) -> io::Result<Option<Vec<Vec<u8>>>> {
+    let Some(line) = read_line(input)? else { return Ok(None) };
+    let Some(count) = line.strip_prefix(b"*") else {
+        return Ok(Some(line.split(u8::is_ascii_whitespace).filter(|word| !word.is_empty()).map(<[u8]>::to_vec).collect()));
+    };
+    let count = number(count, MAX_ARGS)?;
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        let header = read_line(input)?.ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+        let Some(len) = header.strip_prefix(b"$") else {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Expected a bulk string"));
+        };
+        let mut arg = vec![0u8; number(len, MAX_FRAME_BYTES)? + 2];
+        input.read_exact(&mut arg)?;
+        if arg.split_off(arg.len() - 2) != b"\r\n" {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Bulk string longer than its length"));
+        }
+        args.push(arg);
+    }
+    Ok(Some(args))
+}
+
+// A line without its line break, None at the end of the stream
+fn read_line(input: &mut impl BufRead) -> io::Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+    if input.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    if line.ends_with(b"\n") {
+        line.pop();
+    }
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    Ok(Some(line))
+}
+
+fn number(digits: &[u8], max: usize) -> io::Result<usize> {
+    std::str::from_utf8(digits).ok().and_then(|digits| digits.parse().ok()).filter(|&n| n <= max)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("Bad length {}", String::from_utf8_lossy(digits))))
+}
@@ -1,64 +1,162 @@
+// Serialization impl for Client<->Server communication, of the server's own types on top of
+// those of `rudibi_core::serial`
 
-// Serialization impl for Client<->Server communication
+use std::time::{Duration, UNIX_EPOCH};
 
-pub trait Serializable<'a> : Sized {
-    fn serialized(&'a self) -> &'a [u8];
+use crate::dtype::take_u64;
+use crate::engine::{DbError, Row, Table};
+use crate::replication::{FeedRecord, SnapshotChunk};
+use crate::wal::{WalChange, WalRecord};
+
+pub use rudibi_core::serial::{encode, put_u64, take_tag, take_usize, Malformed, Serializable, Wire, MAX_FILTER_DEPTH};
+
+// The whole of `bytes` must be the value
+pub fn decode<'a, T: Wire<'a>>(bytes: &'a [u8]) -> Result<T, DbError> {
+    rudibi_core::serial::decode(bytes).map_err(|malformed| DbError::InputError(malformed.to_string()))
 }
 
-impl<'a> Serializable<'a> for u32 {
-    fn serialized(&'a self) -> &'a [u8] {
-        unsafe {
-            // Rust dark "unsafe" magic just to be able to view u32 as a byte ptr 
-            // (u32::to_le_bytes makes a copy)
-            // FIXME: Will this fail on big endian systems?
-            std::slice::from_raw_parts(self as *const u32 as *const u8, std::mem::size_of::<u32>())
-        }
+impl<'a> Wire<'a> for Table {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.name.as_str().encode_into(out);
+        self.column_layout.encode_into(out);
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let name = <&str>::decode_from(bytes)?;
+        Some(Table::new(name, Vec::decode_from(bytes)?))
     }
 }
 
-impl<'a> Serializable<'a> for &'a str {
-    fn serialized(&'a self) -> &'a [u8] {
-        str::as_bytes(self)
+fn encode_ids(out: &mut Vec<u8>, row_ids: &[u64]) {
+    put_u64(out, row_ids.len());
+    for row_id in row_ids {
+        out.extend_from_slice(&row_id.to_le_bytes());
     }
 }
 
-impl<'a> Serializable<'a> for f64 {
-    fn serialized(&'a self) -> &'a [u8] {
-        unsafe {
-            // Rust dark "unsafe" magic just to be able to view u32 as a byte ptr 
-            // (f64::to_le_bytes makes a copy)
-            // FIXME: Will this fail on big endian systems?
-            std::slice::from_raw_parts(self as *const f64 as *const u8, std::mem::size_of::<f64>())
+fn decode_ids(bytes: &mut &[u8]) -> Option<Vec<u64>> {
+    (0..take_u64(bytes)?).map(|_| take_u64(bytes)).collect()
+}
+
+impl<'a> Wire<'a> for WalRecord {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.sequence.to_le_bytes());
+        let micros = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+        out.extend_from_slice(&micros.to_le_bytes());
+        self.table.as_str().encode_into(out);
+        match &self.change {
+            WalChange::Store { row_ids, rows } => {
+                out.push(1);
+                encode_ids(out, row_ids);
+                rows.encode_into(out);
+            },
+            WalChange::Delete { row_ids } => {
+                out.push(2);
+                encode_ids(out, row_ids);
+            },
+            WalChange::Checkpoint => out.push(3),
+            WalChange::Update { row_ids, rows } => {
+                out.push(4);
+                encode_ids(out, row_ids);
+                rows.encode_into(out);
+            },
         }
     }
+
+    // A store or update must have an id for each row
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let sequence = take_u64(bytes)?;
+        let time = UNIX_EPOCH + Duration::from_micros(take_u64(bytes)?);
+        let table = <&str>::decode_from(bytes)?.to_string();
+        let rows_with_ids = |bytes: &mut &'a [u8]| {
+            let row_ids = decode_ids(bytes)?;
+            let rows: Vec<Row> = Vec::decode_from(bytes)?;
+            (rows.len() == row_ids.len()).then_some((row_ids, rows))
+        };
+        let change = match take_tag(bytes)? {
+            1 => rows_with_ids(bytes).map(|(row_ids, rows)| WalChange::Store { row_ids, rows })?,
+            2 => WalChange::Delete { row_ids: decode_ids(bytes)? },
+            3 => WalChange::Checkpoint,
+            4 => rows_with_ids(bytes).map(|(row_ids, rows)| WalChange::Update { row_ids, rows })?,
+            _ => return None,
+        };
+        Some(WalRecord { sequence, time, table, change })
+    }
 }
 
-impl<'a> Serializable<'a> for Vec<u8> {
-    fn serialized(&'a self) -> &'a [u8] {
-        self.as_slice()
+impl<'a> Wire<'a> for FeedRecord {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.fingerprint.to_le_bytes());
+        self.record.encode_into(out);
+    }
+
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let fingerprint = take_u64(bytes)?;
+        Some(FeedRecord { fingerprint, record: WalRecord::decode_from(bytes)? })
     }
 }
 
-impl<'a, const N: usize> Serializable<'a> for [u8; N] {
-    fn serialized(&'a self) -> &'a [u8] {
-        self.as_ref()
+impl<'a> Wire<'a> for SnapshotChunk {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        self.table.encode_into(out);
+        out.extend_from_slice(&self.fingerprint.to_le_bytes());
+        out.extend_from_slice(&self.sequence.to_le_bytes());
+        encode_ids(out, &self.row_ids);
+        self.rows.encode_into(out);
+    }
+
+    // Every row must have an id and a value for each column
+    fn decode_from(bytes: &mut &'a [u8]) -> Option<Self> {
+        let table = Table::decode_from(bytes)?;
+        let fingerprint = take_u64(bytes)?;
+        let sequence = take_u64(bytes)?;
+        let row_ids = decode_ids(bytes)?;
+        let rows: Vec<Row> = Vec::decode_from(bytes)?;
+        let columns = table.column_layout.len();
+        if rows.len() != row_ids.len() || rows.iter().any(|row| row.offsets.len() != columns + 1) {
+            return None;
+        }
+        Some(SnapshotChunk { table, fingerprint, sequence, row_ids, rows })
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Serializable;
+    use super::*;
+    use crate::protocol::{parse, Command};
 
-    #[test]
-    fn storable_f64_is_le_bytes() {
-        let val: f64 = 3.14159;
-        assert_eq!(&val.to_le_bytes(), val.serialized());
+    // Decoding and encoding again gives the same bytes
+    fn assert_round_trip<'a, T: Wire<'a>>(encoded: &'a [u8]) {
+        let decoded: T = decode(encoded).unwrap();
+        assert_eq!(encode(&decoded), encoded);
     }
 
     #[test]
-    fn storable_u32_is_le_bytes() {
-        let val: u32 = 100;
-        assert_eq!(&val.to_le_bytes(), val.serialized());
+    fn commands_round_trip() {
+        for text in [
+            "CREATE TABLE Stock (id U32, price F64, name UTF8(16), tag BUFFER(4), blob VARBINARY(9), uuid UUID, kind ENUM('a', 'b'))",
+            "INSERT INTO Stock (id, name, tag) VALUES (1, 'apple', x'0a0b0c0d'), (2, 'pear', x'01020304')",
+            "SELECT id, name FROM Stock WHERE NOT (id > 3 OR price <= 2.5) AND name <> 'pear'",
+            "DELETE FROM Stock",
+            "STATUS",
+            "LIST TABLES",
+            "DESCRIBE Stock",
+            "DECLARE cheap CURSOR FOR SELECT * FROM Stock WHERE price < 2.5",
+            "FETCH 50 FROM cheap",
+            "CLOSE cheap",
+        ] {
+            assert_round_trip::<Command>(&encode(&parse(text).unwrap()));
+        }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let row = encode(&Row::of_columns(&[b"ab", b"c"]));
+        let backwards = encode(&Row { data: b"abc".to_vec(), offsets: vec![0, 2, 1, 3] });
+
+        assert!(decode::<Row>(&row[..row.len() - 1]).is_err());
+        assert!(decode::<Row>(&[row.as_slice(), &[0]].concat()).is_err());
+        assert!(decode::<Row>(&backwards).is_err());
+        assert!(decode::<Command>(&[9]).is_err());
+    }
+}
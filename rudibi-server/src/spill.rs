@@ -0,0 +1,192 @@
+// Results of a select held in memory up to a threshold, and in a temporary file past it, so a
+// select over a huge table streams its rows instead of exhausting memory
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dtype::{decode_field, encode_field};
+use crate::engine::{Column, DbError, ResultSet, Row, ScanStats};
+
+fn spill_err(path: &str, err: std::io::Error) -> DbError {
+    DbError::StorageError(format!("Failed to access spill file {path}: {err}"))
+}
+
+// New file in the temporary directory, removed again by the stream reading it
+fn new_spill_file() -> Result<(String, File), DbError> {
+    let tmp = std::env::temp_dir();
+    loop {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        let path = format!("{}/rudibi_spill_{}_{nanos}", tmp.display(), std::process::id());
+        match File::create_new(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(spill_err(&path, err)),
+        }
+    }
+}
+
+// Collects the rows of a select, moving them to a spill file once they take up `max_bytes`
+pub(crate) struct SpillingRows {
+    max_bytes: usize,
+    rows: Vec<Row>,
+    bytes: usize,
+    spilled: Option<(String, BufWriter<File>)>,
+    count: usize,
+    // Of all rows, spilled or not
+    data_bytes: usize,
+}
+
+impl SpillingRows {
+
+    pub(crate) fn new(max_bytes: usize) -> Self {
+        SpillingRows { max_bytes, rows: Vec::new(), bytes: 0, spilled: None, count: 0, data_bytes: 0 }
+    }
+
+    pub(crate) fn push(&mut self, row: Row) -> Result<(), DbError> {
+        self.count += 1;
+        self.data_bytes += row.data.len();
+        if let Some((path, writer)) = &mut self.spilled {
+            return write_row(path, writer, &row);
+        }
+        self.bytes += row.data.len() + row.offsets.len() * size_of::<usize>();
+        self.rows.push(row);
+        if self.bytes > self.max_bytes {
+            let (path, file) = new_spill_file()?;
+            let mut writer = BufWriter::new(file);
+            for row in self.rows.drain(..) {
+                write_row(&path, &mut writer, &row)?;
+            }
+            self.spilled = Some((path, writer));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn finish(mut self, schema: Vec<Column>, scan_stats: ScanStats) -> Result<ResultStream, DbError> {
+        let source = match self.spilled.take() {
+            None => Source::Memory(std::mem::take(&mut self.rows).into_iter()),
+            Some((path, writer)) => {
+                let file = writer.into_inner().map_err(|err| spill_err(&path, err.into_error()))?;
+                drop(file);
+                let reader = BufReader::new(File::open(&path).map_err(|err| spill_err(&path, err))?);
+                Source::Spilled { path, reader }
+            },
+        };
+        Ok(ResultStream { schema, scan_stats, rows: self.count, data_bytes: self.data_bytes, remaining: self.count, source })
+    }
+}
+
+// A select that fails midway leaves no spill file behind
+impl Drop for SpillingRows {
+    fn drop(&mut self) {
+        if let Some((path, _)) = &self.spilled {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn write_row(path: &str, writer: &mut BufWriter<File>, row: &Row) -> Result<(), DbError> {
+    let mut record = Vec::with_capacity(row.data.len() + row.offsets.len() * size_of::<u64>());
+    for col_idx in 0..row.offsets.len() - 1 {
+        encode_field(&mut record, row.get_column(col_idx));
+    }
+    let mut framed = Vec::with_capacity(record.len() + size_of::<u64>());
+    encode_field(&mut framed, &record);
+    writer.write_all(&framed).map_err(|err| spill_err(path, err))
+}
+
+enum Source {
+    Memory(std::vec::IntoIter<Row>),
+    Spilled { path: String, reader: BufReader<File> },
+}
+
+// Rows of a select in result order, see `Database::select_stream`
+pub struct ResultStream {
+    pub schema: Vec<Column>,
+    pub scan_stats: ScanStats,
+    rows: usize,
+    data_bytes: usize,
+    remaining: usize,
+    source: Source,
+}
+
+impl ResultStream {
+    // Rows of the whole result, read or not
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    // Bytes of the whole result's rows as stored
+    pub fn data_bytes(&self) -> usize {
+        self.data_bytes
+    }
+
+    // Whether the rows went to a spill file
+    pub fn is_spilled(&self) -> bool {
+        self.spill_path().is_some()
+    }
+
+    // The spill file, removed when the stream is dropped
+    pub fn spill_path(&self) -> Option<&str> {
+        match &self.source {
+            Source::Spilled { path, .. } => Some(path),
+            Source::Memory(_) => None,
+        }
+    }
+
+    // The rows of a result already in memory
+    pub(crate) fn of_result_set(results: ResultSet) -> ResultStream {
+        let (rows, data_bytes) = (results.data.len(), results.data.iter().map(|row| row.data.len()).sum());
+        ResultStream { schema: results.schema, scan_stats: results.scan_stats, rows, data_bytes, remaining: rows, source: Source::Memory(results.data.into_iter()) }
+    }
+
+    // Reads the remaining rows into memory
+    pub fn into_result_set(mut self) -> Result<ResultSet, DbError> {
+        let data = self.by_ref().collect::<Result<Vec<_>, _>>()?;
+        Ok(ResultSet { schema: std::mem::take(&mut self.schema), data, scan_stats: self.scan_stats })
+    }
+
+    fn read_spilled(&mut self) -> Result<Row, DbError> {
+        let columns = self.schema.len();
+        let Source::Spilled { path, reader } = &mut self.source else { unreachable!() };
+        let truncated = || DbError::StorageError(format!("Spill file {path} is truncated"));
+        let mut len = [0; size_of::<u64>()];
+        reader.read_exact(&mut len).map_err(|err| spill_err(path, err))?;
+        let mut record = vec![0; usize::try_from(u64::from_le_bytes(len)).map_err(|_| truncated())?];
+        reader.read_exact(&mut record).map_err(|err| spill_err(path, err))?;
+        let mut rest = record.as_slice();
+        let fields = (0..columns).map(|_| decode_field(&mut rest)).collect::<Option<Vec<_>>>().ok_or_else(truncated)?;
+        Ok(Row::of_columns(&fields))
+    }
+}
+
+impl Iterator for ResultStream {
+    type Item = Result<Row, DbError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        match &mut self.source {
+            Source::Memory(rows) => rows.next().map(Ok),
+            Source::Spilled { .. } => Some(self.read_spilled()),
+        }
+    }
+}
+
+impl Drop for ResultStream {
+    fn drop(&mut self) {
+        if let Source::Spilled { path, .. } = &self.source {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
@@ -1,13 +1,70 @@
-use crate::engine::{Row, Table};
+use std::borrow::Cow;
 
-// Not flexible and too small, but OK for now
-pub type RowId = usize;
+use crate::bloom::BloomFilter;
+use crate::collation::Collation;
+use crate::compress::{compress, decompress, Compression};
+use crate::dtype::{decode_field, encode_field, DataType};
+use crate::engine::{Column, DbError, Row, Table};
+use crate::group_commit::{GroupCommit, PendingSync};
+use crate::hash::stable_hash;
+use crate::index::IndexKey;
 
+// Stable identifier of a row within its table. Never reused and unaffected by deletes of other rows.
+pub type RowId = u64;
 
+// Strategy for handing out RowIds to newly stored rows
+// Ids must be increasing, so that storages can keep rows sorted by id in insertion order
+pub trait IdAllocator: Send + Sync {
+    fn allocate(&mut self) -> RowId;
+}
+
+// Per-table counter starting from 0
+#[derive(Debug, Clone, Default)]
+pub struct MonotonicIds {
+    next: RowId,
+}
+
+impl MonotonicIds {
+    pub fn starting_at(next: RowId) -> Self {
+        MonotonicIds { next }
+    }
+}
+
+impl IdAllocator for MonotonicIds {
+    fn allocate(&mut self) -> RowId {
+        let id = self.next;
+        self.next += 1;
+        id
+    }
+}
+
+// Hands out ids assigned earlier, for storages that move rows into another one with their ids
+pub(crate) struct ReplayIds(std::vec::IntoIter<RowId>);
+
+impl ReplayIds {
+    pub(crate) fn of(ids: Vec<RowId>) -> Self {
+        ReplayIds(ids.into_iter())
+    }
+
+    // For a storage that must not get new rows directly
+    pub(crate) fn none() -> Self {
+        ReplayIds::of(Vec::new())
+    }
+}
+
+impl IdAllocator for ReplayIds {
+    fn allocate(&mut self) -> RowId {
+        self.0.next().expect("Rows must be stored with exactly the replayed ids")
+    }
+}
+
+
+// Borrowed from storages that keep rows in memory, owned when decoded from disk, so a scan
+// frees each row once it's done with it
 #[derive(Debug)]
 pub struct RowContent<'a> {
-    pub data: &'a [u8],
-    pub offsets: &'a [usize],
+    pub data: Cow<'a, [u8]>,
+    pub offsets: Cow<'a, [usize]>,
 }
 
 impl RowContent<'_> {
@@ -23,7 +80,48 @@ pub struct ScanItem<'a> { pub row_id: RowId, pub row_content: RowContent<'a> }
 
 // Rust requires a concrete implementation in return types for traits or something.
 // This is a workaround.
-type RowIter<'a> = Box<dyn Iterator<Item = ScanItem<'a>> + 'a>;
+pub type RowIter<'a> = Box<dyn Iterator<Item = ScanItem<'a>> + 'a>;
+
+// Rows of a scan handed over together, so filters can run over one column at a time
+pub struct RowBatch<'a> {
+    items: Vec<ScanItem<'a>>,
+}
+
+pub type BatchIterator<'a> = Box<dyn Iterator<Item = RowBatch<'a>> + 'a>;
+
+impl<'a> RowBatch<'a> {
+    pub fn new(items: Vec<ScanItem<'a>>) -> Self {
+        RowBatch { items }
+    }
+
+    // Splits a scan into batches of up to `batch_rows` rows
+    pub fn batches(mut iter: TableIterator<'a>, batch_rows: usize) -> BatchIterator<'a> {
+        Box::new(std::iter::from_fn(move || {
+            let items: Vec<ScanItem> = iter.by_ref().take(batch_rows).collect();
+            (!items.is_empty()).then_some(RowBatch { items })
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn items(&self) -> &[ScanItem<'a>] {
+        &self.items
+    }
+
+    // Values of one column, a slice per row
+    pub fn column(&self, col_idx: usize) -> Vec<&[u8]> {
+        self.items.iter().map(|item| item.row_content.get_column(col_idx)).collect()
+    }
+}
+
+// Decides which rows a storage deletes while scanning
+pub type RowFilter<'a> = dyn FnMut(&ScanItem) -> Result<bool, DbError> + 'a;
 
 pub struct TableIterator<'a> {
     iter: RowIter<'a>,
@@ -43,94 +141,355 @@ impl<'a> Iterator for TableIterator<'a> {
     }
 }
 
-pub trait Storage {
-    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>);
-    fn scan(&self) -> TableIterator;
+// Backend of a table, implementations from outside the crate are added with
+// `Database::new_table_with_storage`. Rows are handed over with a mapping of schema columns to
+// row columns, and come back with their columns in schema order. Storages are shared by the
+// threads of a database, behind a lock per table.
+pub trait Storage: Send + Sync {
+    // Returns the ids assigned to the stored rows, in input order
+    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) -> Vec<RowId>;
+    fn scan(&self) -> TableIterator<'_>;
+    // The rows of `scan` in batches of up to `batch_rows`
+    fn scan_batches(&self, batch_rows: usize) -> BatchIterator<'_> {
+        RowBatch::batches(self.scan(), batch_rows)
+    }
+    // Live rows among `row_ids`, which must be sorted. Unknown ids are skipped.
+    // The default filters a full scan, storages that can seek to a row should override it.
+    fn get_rows<'a>(&'a self, row_ids: &'a [RowId]) -> TableIterator<'a> {
+        let mut wanted = row_ids.iter().peekable();
+        TableIterator::new(Box::new(self.scan().filter(move |item| {
+            while wanted.next_if(|id| **id < item.row_id).is_some() {}
+            wanted.next_if_eq(&&item.row_id).is_some()
+        })))
+    }
+    // Live rows that may have a column equal to the key, see `IndexKey::stable_hash`.
+    // Can include rows that don't match, the default is a full scan.
+    fn scan_maybe_equal(&self, _column_idx: usize, _key_hash: u64) -> TableIterator<'_> {
+        self.scan()
+    }
+
+    // Keep a Bloom filter of the column's keys, for storages that can skip data by them
+    fn add_bloom_filter(&mut self, _column_idx: usize) -> Result<(), DbError> {
+        Err(DbError::UnsupportedOperation("Storage doesn't support Bloom filters".to_string()))
+    }
+
+    // Copy of the storage as it is now, for full scans that shouldn't hold the table's lock while
+    // writers wait. Writes to either one aren't seen by the other. Only worth it if taking one is
+    // cheap, storages that can't return None and are scanned under the lock.
+    fn snapshot(&self) -> Option<Box<dyn Storage>> {
+        None
+    }
+
+    // Commit point, storages make the writes before it durable as configured
+    fn commit(&mut self) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    // Same as `commit`, but a storage can leave the sync to wait for, so the engine waits without
+    // holding the table's lock and concurrent commits share the sync
+    fn commit_grouped(&mut self) -> Result<Option<PendingSync>, DbError> {
+        self.commit().map(|_| None)
+    }
+
+    // Makes every write so far durable whatever the durability, for checkpoints. Storages
+    // without a file of their own fail, their rows can't be dropped from the WAL.
+    fn sync(&mut self) -> Result<(), DbError> {
+        Err(DbError::UnsupportedOperation("Storage keeps no durable copy of its rows".to_string()))
+    }
+
+    // A single live row, by id
+    fn get_row(&self, row_id: RowId) -> Option<ScanItem<'_>> {
+        self.scan().find(|item| item.row_id == row_id)
+    }
     fn delete_rows(&mut self, row_ids: Vec<RowId>);
+    // Deletes the rows passing `filter` in one scan, and gives their ids in scan order.
+    // Nothing is deleted if `filter` fails on any row.
+    fn delete_where(&mut self, filter: &mut RowFilter) -> Result<Vec<RowId>, DbError> {
+        let mut matching = Vec::new();
+        for item in self.scan() {
+            if filter(&item)? { matching.push(item.row_id); }
+        }
+        self.delete_rows(matching.clone());
+        Ok(matching)
+    }
+    // Number of live rows, without scanning
+    fn row_count(&self) -> usize;
+
+    // Bytes held in memory, for storages that keep anything there
+    fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::default()
+    }
+
+    // Size of the files behind the storage, 0 if it has none
+    fn disk_bytes(&self) -> u64 {
+        0
+    }
+
+    // Replaces live rows with rows of the same size in place, keeping their ids.
+    // Rows are in schema column order. Either all rows are replaced, or none.
+    fn overwrite_rows(&mut self, _rows: &[(RowId, Row)]) -> Result<(), DbError> {
+        Err(DbError::UnsupportedOperation("Storage can't overwrite rows in place".to_string()))
+    }
+
+    // Reclaim space held by deleted rows, for storages that defer it
+    fn compact(&mut self) {}
+
+    // Relocate the backing data, for storages that have any
+    fn move_to(&mut self, _path: &str) -> Result<(), DbError> {
+        Err(DbError::UnsupportedOperation("Storage has no backing file to move".to_string()))
+    }
+
+    // Async reads, for storages that do IO. Others are scanned as they are.
+    #[cfg(feature = "async")]
+    fn as_async(&self) -> Option<&dyn crate::asynchronous::AsyncStorage> {
+        None
+    }
+}
+
+
+// Estimated bytes held in memory, by what they're for. Allocator overhead isn't counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryUsage {
+    // Column values of rows
+    pub data: usize,
+    // Column offsets, row ids, positions and tombstones
+    pub offsets: usize,
+    pub indexes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.data + self.offsets + self.indexes
+    }
+}
+
+impl std::ops::Add for MemoryUsage {
+    type Output = MemoryUsage;
+
+    fn add(self, other: MemoryUsage) -> MemoryUsage {
+        MemoryUsage { data: self.data + other.data, offsets: self.offsets + other.offsets, indexes: self.indexes + other.indexes }
+    }
+}
+
+
+// Subscriber to changes of table storage, e.g. caches, indexes or change data capture
+// Events are emitted by the engine after the storage call succeeded, regardless of backend.
+// Writes to different tables run concurrently, so do their events.
+pub trait StorageObserver: Send + Sync {
+    fn on_store(&self, _table: &str, _row_ids: &[RowId], _rows: &[Row], _column_mapping: &[usize]) {}
+    fn on_delete(&self, _table: &str, _row_ids: &[RowId]) {}
+    // Rows replaced in place, keeping their ids, in schema column order. Seen as a delete and a
+    // store by default.
+    fn on_update(&self, table: &str, row_ids: &[RowId], rows: &[Row]) {
+        self.on_delete(table, row_ids);
+        let column_mapping: Vec<usize> = (0..rows.first().map_or(0, |row| row.offsets.len() - 1)).collect();
+        self.on_store(table, row_ids, rows, &column_mapping);
+    }
+    fn on_compact(&self, _table: &str) {}
+    // A write to the table is done, seen once the table is unlocked
+    fn on_commit(&self, _table: &str) {}
 }
 
 
+// The row arrays are shared with snapshots, a write copies an array only while a snapshot still
+// holds it
 pub struct InMemoryStorage {
     offsets_per_row: usize,
-    data: Vec<u8>,
-    relative_column_offsets: Vec<usize>,
-    row_data_starts: Vec<usize>,
+    data: Arc<Vec<u8>>,
+    relative_column_offsets: Arc<Vec<usize>>,
+    row_data_starts: Arc<Vec<usize>>,
+    // Id of the row at each position, sorted ascending
+    row_ids: Arc<Vec<RowId>>,
+    // Tombstone of the row at each position, until the next compaction
+    deleted: Arc<Vec<bool>>,
+    live_rows: usize,
+    ids: Box<dyn IdAllocator>,
 }
 
 impl Storage for InMemoryStorage {
 
-    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) {
-        self.row_data_starts.reserve(rows.len());
-        self.relative_column_offsets.reserve(rows.len() * self.offsets_per_row);
+    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) -> Vec<RowId> {
+        let data = Arc::make_mut(&mut self.data);
+        let relative_column_offsets = Arc::make_mut(&mut self.relative_column_offsets);
+        let row_data_starts = Arc::make_mut(&mut self.row_data_starts);
+        let row_ids = Arc::make_mut(&mut self.row_ids);
+        let deleted = Arc::make_mut(&mut self.deleted);
+        row_data_starts.reserve(rows.len());
+        row_ids.reserve(rows.len());
+        relative_column_offsets.reserve(rows.len() * self.offsets_per_row);
+        let first_new = row_ids.len();
         for row in rows {
+            row_ids.push(self.ids.allocate());
+            deleted.push(false);
+            self.live_rows += 1;
             let mut next_offset = 0;
-            self.relative_column_offsets.push(next_offset);
+            relative_column_offsets.push(next_offset);
                 
-            let row_start = self.data.len();
-            self.row_data_starts.push(row_start);
+            let row_start = data.len();
+            row_data_starts.push(row_start);
 
             for i in column_mapping {
                 let col = row.get_column(*i);
-                self.data.extend_from_slice(col);
+                data.extend_from_slice(col);
                 next_offset += col.len();
-                self.relative_column_offsets.push(next_offset);
+                relative_column_offsets.push(next_offset);
             }
         }
+        row_ids[first_new..].to_vec()
+    }
 
+    // Rows are only marked deleted, their space is reclaimed by `compact` once tombstones
+    // outnumber live rows, so deleting any share of the table stays linear
+    fn delete_rows(&mut self, row_ids: Vec<RowId>) {
+        let deleted = Arc::make_mut(&mut self.deleted);
+        for id in row_ids {
+            if let Ok(pos) = self.row_ids.binary_search(&id) && !deleted[pos] {
+                deleted[pos] = true;
+                self.live_rows -= 1;
+            }
+        }
+        if self.row_ids.len() - self.live_rows > self.live_rows {
+            self.compact();
+        }
     }
 
-    fn delete_rows(&mut self, mut row_ids: Vec<RowId>) {
-        // Sorting in reverse order to avoid index shifting issues
-        row_ids.sort_by(|a, b| b.cmp(a));
-        for row_id in row_ids {
-            if row_id < self.row_data_starts.len() {
-                let start = self.row_data_starts[row_id];
-                let end = if row_id + 1 < self.row_data_starts.len() {
-                    self.row_data_starts[row_id + 1]
-                } else {
-                    // Case for the last row
-                    self.data.len()
-                };
-                self.data.drain(start..end);
-                let deleted_length = end - start;
-                self.row_data_starts.remove(row_id);
-                // Shift row starts
-                // TODO: SLOW
-                for i in row_id..self.row_data_starts.len() {
-                    if self.row_data_starts[i] > start {
-                        self.row_data_starts[i] -= deleted_length;
-                    }
-                }
+    fn delete_where(&mut self, filter: &mut RowFilter) -> Result<Vec<RowId>, DbError> {
+        let mut positions = Vec::new();
+        for pos in (0..self.row_ids.len()).filter(|pos| !self.deleted[*pos]) {
+            let item = ScanItem { row_id: self.row_ids[pos], row_content: self.get_row_content(pos).unwrap() };
+            if filter(&item)? { positions.push(pos); }
+        }
+        let matching: Vec<RowId> = positions.iter().map(|pos| self.row_ids[*pos]).collect();
+        let deleted = Arc::make_mut(&mut self.deleted);
+        for pos in positions {
+            deleted[pos] = true;
+        }
+        self.live_rows -= matching.len();
+        if self.row_ids.len() - self.live_rows > self.live_rows {
+            self.compact();
+        }
+        Ok(matching)
+    }
+
+    fn compact(&mut self) {
+        let mut data = Vec::with_capacity(self.data.len());
+        let mut relative_column_offsets = Vec::with_capacity(self.live_rows * self.offsets_per_row);
+        let mut row_data_starts = Vec::with_capacity(self.live_rows);
+        let mut row_ids = Vec::with_capacity(self.live_rows);
+        for pos in (0..self.row_ids.len()).filter(|pos| !self.deleted[*pos]) {
+            let row = self.get_row_content(pos).unwrap();
+            row_data_starts.push(data.len());
+            data.extend_from_slice(&row.data);
+            relative_column_offsets.extend_from_slice(&row.offsets);
+            row_ids.push(self.row_ids[pos]);
+        }
+        self.data = Arc::new(data);
+        self.relative_column_offsets = Arc::new(relative_column_offsets);
+        self.row_data_starts = Arc::new(row_data_starts);
+        self.row_ids = Arc::new(row_ids);
+        self.deleted = Arc::new(vec![false; self.live_rows]);
+    }
+
+    fn row_count(&self) -> usize {
+        self.live_rows
+    }
 
-                let offset_start = row_id * self.offsets_per_row;
-                let offset_end = (row_id + 1) * self.offsets_per_row;
-                self.relative_column_offsets.drain(offset_start..offset_end);
+    // Batches are cut from the row arrays directly, without an iterator call per row
+    fn scan_batches(&self, batch_rows: usize) -> BatchIterator<'_> {
+        Box::new((0..self.row_ids.len()).step_by(batch_rows.max(1)).filter_map(move |start| {
+            let items: Vec<ScanItem> = (start..self.row_ids.len().min(start + batch_rows))
+                .filter(|pos| !self.deleted[*pos])
+                .map(|pos| ScanItem { row_id: self.row_ids[pos], row_content: self.get_row_content(pos).unwrap() })
+                .collect();
+            (!items.is_empty()).then_some(RowBatch { items })
+        }))
+    }
+
+    fn overwrite_rows(&mut self, rows: &[(RowId, Row)]) -> Result<(), DbError> {
+        let mut positions = Vec::with_capacity(rows.len());
+        for (row_id, row) in rows {
+            let pos = self.row_ids.binary_search(row_id).ok().filter(|pos| !self.deleted[*pos])
+                .ok_or_else(|| DbError::StorageError(format!("Row {row_id} isn't stored")))?;
+            let current = self.get_row_content(pos).unwrap();
+            if current.offsets != row.offsets.as_slice() {
+                return Err(DbError::UnsupportedOperation(format!("Row {row_id} changes size")));
             }
+            positions.push(self.row_data_starts[pos]);
+        }
+        let data = Arc::make_mut(&mut self.data);
+        for (start, (_, row)) in positions.into_iter().zip(rows) {
+            data[start..start + row.data.len()].copy_from_slice(&row.data);
         }
+        Ok(())
     }
 
-    fn scan(&self) -> TableIterator {
+    // Capacity rather than length, that's what stays allocated
+    fn memory_usage(&self) -> MemoryUsage {
+        let offsets = (self.relative_column_offsets.capacity() + self.row_data_starts.capacity()) * size_of::<usize>()
+            + self.row_ids.capacity() * size_of::<RowId>()
+            + self.deleted.capacity();
+        MemoryUsage { data: self.data.capacity(), offsets, indexes: 0 }
+    }
+
+    fn scan(&self) -> TableIterator<'_> {
         TableIterator::new(Box::new(
-            (0..self.row_data_starts.len()).map(move |row_id| {
-                let row_content = self.get_row_content(row_id).unwrap();
-                ScanItem { row_id, row_content }
+            (0..self.row_data_starts.len()).filter(|pos| !self.deleted[*pos]).map(move |pos| {
+                let row_content = self.get_row_content(pos).unwrap();
+                ScanItem { row_id: self.row_ids[pos], row_content }
             })
         ))
     }
+
+    fn get_rows<'a>(&'a self, row_ids: &'a [RowId]) -> TableIterator<'a> {
+        TableIterator::new(Box::new(row_ids.iter().filter_map(move |id| self.get_row(*id))))
+    }
+
+    fn get_row(&self, row_id: RowId) -> Option<ScanItem<'_>> {
+        let pos = self.row_ids.binary_search(&row_id).ok().filter(|pos| !self.deleted[*pos])?;
+        Some(ScanItem { row_id, row_content: self.get_row_content(pos).unwrap() })
+    }
+
+    fn snapshot(&self) -> Option<Box<dyn Storage>> {
+        Some(Box::new(InMemoryStorage {
+            offsets_per_row: self.offsets_per_row,
+            data: self.data.clone(),
+            relative_column_offsets: self.relative_column_offsets.clone(),
+            row_data_starts: self.row_data_starts.clone(),
+            row_ids: self.row_ids.clone(),
+            deleted: self.deleted.clone(),
+            live_rows: self.live_rows,
+            // Rows stored into the snapshot would only be its own
+            ids: Box::new(MonotonicIds::default()),
+        }))
+    }
 }
 
 impl InMemoryStorage {
 
     pub fn new(schema: Table) -> Self {
+        InMemoryStorage::with_ids(schema, Box::new(MonotonicIds::default()))
+    }
+
+    pub fn with_ids(schema: Table, ids: Box<dyn IdAllocator>) -> Self {
         InMemoryStorage {
             offsets_per_row: schema.column_layout.len() + 1,
-            data: Vec::new(),
-            relative_column_offsets: Vec::new(),
-            row_data_starts: Vec::new(),
+            data: Arc::default(),
+            relative_column_offsets: Arc::default(),
+            row_data_starts: Arc::default(),
+            row_ids: Arc::default(),
+            deleted: Arc::default(),
+            live_rows: 0,
+            ids,
         }
     }
 
-    fn get_row_content(&self, row_id: RowId) -> Option<RowContent> {
+    // Swaps the allocator of new row ids, returning the previous one
+    pub(crate) fn replace_ids(&mut self, ids: Box<dyn IdAllocator>) -> Box<dyn IdAllocator> {
+        std::mem::replace(&mut self.ids, ids)
+    }
+
+    // Rows are addressed by their current position here, not by RowId
+    fn get_row_content(&self, row_id: usize) -> Option<RowContent<'_>> {
         if row_id < self.row_data_starts.len() {
             let start = self.row_data_starts[row_id];
             let end = if row_id + 1 < self.row_data_starts.len() {
@@ -143,7 +502,7 @@ impl InMemoryStorage {
             let offsets_start = row_id * self.offsets_per_row;
             let offsets_end = (row_id + 1) * self.offsets_per_row;
             let offsets = &self.relative_column_offsets[offsets_start..offsets_end];
-            Some(RowContent { data, offsets })
+            Some(RowContent { data: Cow::Borrowed(data), offsets: Cow::Borrowed(offsets) })
         } else {
             None
         }
@@ -151,201 +510,1197 @@ impl InMemoryStorage {
 }
 
 
-use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{File, OpenOptions};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+fn sync_file(path: &str) -> Result<(), DbError> {
+    OpenOptions::new().write(true).open(path).and_then(|file| file.sync_data())
+        .map_err(|err| DbError::StorageError(format!("Failed to sync {path}: {err}")))
+}
+
+// When writes to a disk table reach stable storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    // Written to the OS, but never fsynced. Fastest, a power loss can lose recent writes.
+    #[default]
+    Flush,
+    // Every batch of rows stored or deleted is fsynced before the call returns
+    SyncPerBatch,
+    // Fsynced once per commit, e.g. a finished insert stream rather than each of its chunks
+    SyncPerCommit,
+    // Fsynced once per commit like `SyncPerCommit`, but commits made while a sync runs share the
+    // next one. Much faster under many small concurrent writes.
+    GroupCommit,
+}
 
 pub struct DiskStorage {
     path: String,
+    durability: Durability,
+    // Applies to rows stored from now on, every record or packed page group says how it's compressed
+    compression: Compression,
+    columns: Vec<Column>,
+    live_rows: usize,
+    ids: Box<dyn IdAllocator>,
+    // File position of the first page, right after the header
+    pages_start: u64,
+    // Live rows of each page group by file position. The last one gets new rows while it has room.
+    pages: BTreeMap<u64, usize>,
+    file_len: u64,
+    // Ids of deleted rows, so scans skip them without decoding and skip pages without live rows
+    tombstones: RowIdSet,
+    // Location of each live row, rebuilt by `open`, so lookups and deletes seek to the row's page
+    row_positions: HashMap<RowId, RowLocation>,
+    segments: Vec<Segment>,
+    // Columns with a Bloom filter in every segment
+    bloom_columns: Vec<usize>,
+    // Times the file was opened for writing, so reads done without the table's lock can tell
+    // whether they raced a write
+    writes: AtomicU64,
+    group: Arc<GroupCommit>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecoveryReport {
+    // Complete rows found in the file, deleted ones included
+    pub rows_recovered: usize,
+    // Bytes of torn pages cut off the end of the file
+    pub bytes_truncated: u64,
+    // Pages that failed their checksum and were rewritten with the rows that were still intact
+    pub pages_repaired: usize,
+}
+
+struct Header {
+    format_version: u32,
+    offsets_per_row: usize,
+    // Encoded column layout
+    layout: Vec<u8>,
+}
+
+// None if the header is cut off, Err if the file isn't a table file
+fn read_header(reader: &mut impl Read) -> Option<Result<Header, ()>> {
+    let mut magic_buf = MagicType::default();
+    let mut version_buf = u32::to_le_bytes(0);
+    let mut word = u64::to_le_bytes(0);
+    reader.read_exact(&mut magic_buf).ok()?;
+    if &magic_buf != HEADER_MAGIC {
+        return Some(Err(()));
+    }
+    reader.read_exact(&mut version_buf).ok()?;
+    reader.read_exact(&mut word).ok()?;
+    let Ok(offsets_per_row) = usize::try_from(u64::from_le_bytes(word)) else { return Some(Err(())) };
+    reader.read_exact(&mut word).ok()?;
+    let mut layout = Vec::new();
+    let layout_len = u64::from_le_bytes(word);
+    if reader.take(layout_len).read_to_end(&mut layout).ok()? as u64 != layout_len {
+        return None;
+    }
+    Some(Ok(Header { format_version: u32::from_le_bytes(version_buf), offsets_per_row, layout }))
+}
+
+// Magic, format version u32, offsets per row u64, then the length-prefixed column layout
+fn encode_header(format_version: u32, offsets_per_row: usize, layout: &[u8]) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(HEADER_MAGIC);
+    header.extend_from_slice(&format_version.to_le_bytes());
+    header.extend_from_slice(&(offsets_per_row as u64).to_le_bytes());
+    encode_field(&mut header, layout);
+    header
+}
+
+type UpgradeStep = fn(&str) -> Result<(), DbError>;
+
+// Brings a file written with an older format version up to `FORMAT_VERSION`, rewriting it in place
+// one version at a time. A layout change adds the step from the previous version here.
+fn upgrade_format(path: &str, version: u32) -> Result<(), DbError> {
+    let steps: &[(u32, UpgradeStep)] = &[(1, upgrade_append_stream), (2, upgrade_field_widths), (3, upgrade_packed_groups)];
+    let mut current = version;
+    while current != FORMAT_VERSION {
+        let (_, step) = steps.iter().find(|(from, _)| *from == current)
+            .ok_or(DbError::UnsupportedFormatVersion { found: version, supported: FORMAT_VERSION })?;
+        step(path)?;
+        current += 1;
+    }
+    Ok(())
+}
+
+// Contents of an older file for an upgrade step, with the header parsed
+fn read_for_upgrade(path: &str) -> Result<(Vec<u8>, Header, usize), DbError> {
+    let old = std::fs::read(path).map_err(|err| DbError::StorageError(format!("Failed to upgrade {path}: {err}")))?;
+    let mut reader = old.as_slice();
+    let header = read_header(&mut reader).and_then(Result::ok)
+        .ok_or_else(|| DbError::DatabaseIntegrityError(format!("{path} has no valid table header")))?;
+    let header_len = old.len() - reader.len();
+    Ok((old, header, header_len))
+}
+
+// Appends pages holding the records, in order
+fn pack_pages<'r>(out: &mut Vec<u8>, records: impl IntoIterator<Item = &'r [u8]>) {
+    let mut page: Option<Page> = None;
+    for record in records {
+        if page.as_mut().and_then(|page| page.push_record(record)).is_none() {
+            if let Some(full) = page.replace(Page::empty(Page::span_for(record.len()))) {
+                out.extend_from_slice(&full.sealed());
+            }
+            page.as_mut().unwrap().push_record(record);
+        }
+    }
+    if let Some(last) = page {
+        out.extend_from_slice(&last.sealed());
+    }
+}
+
+// Version 1 appended rows right after the header. Its rows are valid version 2 page records as
+// they are, so they're packed into pages in file order. Torn rows at the end are left out.
+fn upgrade_append_stream(path: &str) -> Result<(), DbError> {
+    let (old, header, header_len) = read_for_upgrade(path)?;
+    let mut reader = &old[header_len..];
+    let mut records = Vec::new();
+    let mut last_id: Option<RowId> = None;
+    loop {
+        let mut peek = reader;
+        let Some((row_id, _, row_bytes)) = read_valid_row(&mut peek, header.offsets_per_row, reader.len() as u64, LEGACY_FIELD_BYTES) else { break };
+        if last_id.is_some_and(|last| row_id <= last) {
+            break;
+        }
+        last_id = Some(row_id);
+        let (record, rest) = reader.split_at(row_bytes as usize);
+        records.push(record);
+        reader = rest;
+    }
+    let mut upgraded = encode_header(2, header.offsets_per_row, &header.layout);
+    pack_pages(&mut upgraded, records);
+    std::fs::write(path, upgraded).map_err(|err| DbError::StorageError(format!("Failed to upgrade {path}: {err}")))
+}
+
+// Version 2 records had 64-bit offsets and content length, as written by 64-bit builds
+fn upgrade_field_widths(path: &str) -> Result<(), DbError> {
+    let (old, header, header_len) = read_for_upgrade(path)?;
+    let mut reader = &old[header_len..];
+    let mut records = Vec::new();
+    let mut last_id: Option<RowId> = None;
+    while !reader.is_empty() {
+        let remaining = reader.len() as u64;
+        let mut page = read_page_group(&mut reader, remaining).expect("Reading a slice can't fail");
+        let complete = page.span() >= 1 && page.bytes.len() == page.span() * PAGE_SIZE;
+        if !page.is_intact() {
+            page = page.salvage(header.offsets_per_row, last_id, LEGACY_FIELD_BYTES);
+        }
+        for slot in 0..page.slot_count() {
+            let record = page.record(slot);
+            last_id = Some(record_id(record));
+            records.push(narrow_record(record, header.offsets_per_row));
+        }
+        if !complete {
+            break;
+        }
+    }
+    let mut upgraded = encode_header(3, header.offsets_per_row, &header.layout);
+    pack_pages(&mut upgraded, records.iter().map(Vec::as_slice));
+    std::fs::write(path, upgraded).map_err(|err| DbError::StorageError(format!("Failed to upgrade {path}: {err}")))
+}
+
+// Version 4 added packed page groups, version 3 files are valid version 4 files as they are
+fn upgrade_packed_groups(path: &str) -> Result<(), DbError> {
+    let io_err = |err: std::io::Error| DbError::StorageError(format!("Failed to upgrade {path}: {err}"));
+    let mut file = OpenOptions::new().write(true).open(path).map_err(io_err)?;
+    file.seek(SeekFrom::Start(HEADER_MAGIC.len() as u64)).map_err(io_err)?;
+    file.write_all(&4u32.to_le_bytes()).map_err(io_err)
+}
+
+// Same record with the 64-bit fields of version 2 narrowed to `FIELD_BYTES`
+fn narrow_record(record: &[u8], offsets_per_row: usize) -> Vec<u8> {
+    let fields_end = 9 + (offsets_per_row + 1) * LEGACY_FIELD_BYTES;
+    let mut narrow = record[..9].to_vec();
+    for field in record[9..fields_end].chunks(LEGACY_FIELD_BYTES) {
+        narrow.extend_from_slice(&field[..FIELD_BYTES]);
+    }
+    narrow.extend_from_slice(&record[fields_end..]);
+    narrow
+}
+
+// Name, data type and collation of every column
+fn encode_layout(columns: &[Column]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(columns.len() as u64).to_le_bytes());
+    for col in columns {
+        encode_field(&mut out, col.name.as_bytes());
+        col.dtype.encode_into(&mut out);
+        encode_field(&mut out, col.collation.name().as_bytes());
+    }
+    out
+}
+
+fn decode_layout(mut bytes: &[u8]) -> Option<Vec<Column>> {
+    let count = u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?);
+    bytes = &bytes[8..];
+    let columns = (0..count).map(|_| {
+        let name = String::from_utf8(decode_field(&mut bytes)?.to_vec()).ok()?;
+        let dtype = DataType::decode(&mut bytes)?;
+        let collation = Collation::from_name(str::from_utf8(decode_field(&mut bytes)?).ok()?)?;
+        Some(Column::new(&name, dtype).with_collation(collation))
+    }).collect::<Option<Vec<_>>>()?;
+    bytes.is_empty().then_some(columns)
+}
+
+// Flags of a row record
+const DELETED: u8 = 1;
+// Content is a compressed block, the offsets still describe the decompressed columns
+const COMPRESSED: u8 = 2;
+
+// Width of the little-endian column offsets and content length of a record
+const FIELD_BYTES: usize = size_of::<u32>();
+// Up to version 2 the fields were `usize`, always read as 64-bit
+const LEGACY_FIELD_BYTES: usize = size_of::<u64>();
+
+fn read_field(reader: &mut impl Read, field_bytes: usize) -> Option<usize> {
+    let mut word = u64::to_le_bytes(0);
+    reader.read_exact(&mut word[..field_bytes]).ok()?;
+    usize::try_from(u64::from_le_bytes(word)).ok()
+}
+
+// Reads one row record, returning its id, tombstone and size in bytes, or None at EOF or if the row
+// isn't complete and consistent. `remaining` is the number of bytes left to read.
+fn read_valid_row(reader: &mut impl Read, offsets_per_row: usize, remaining: u64, field_bytes: usize) -> Option<(RowId, bool, u64)> {
+    let mut tombstone_buf = [0u8];
+    let mut row_id_buf = RowId::to_le_bytes(0);
+    reader.read_exact(&mut tombstone_buf).ok()?;
+    let flags = tombstone_buf[0];
+    if flags & !(DELETED | COMPRESSED) != 0 {
+        return None;
+    }
+    reader.read_exact(&mut row_id_buf).ok()?;
+
+    let mut last_offset = 0;
+    for idx in 0..offsets_per_row {
+        let offset = read_field(reader, field_bytes)?;
+        if (idx == 0 && offset != 0) || offset < last_offset {
+            return None;
+        }
+        last_offset = offset;
+    }
+    let content_len = read_field(reader, field_bytes)?;
+    let row_bytes = (1 + size_of::<RowId>() + (offsets_per_row + 1) * field_bytes + content_len) as u64;
+    if row_bytes > remaining {
+        return None;
+    }
+    if flags & COMPRESSED != 0 {
+        let mut content = vec![0u8; content_len];
+        reader.read_exact(&mut content).ok()?;
+        decompress(&content, last_offset)?;
+    } else if content_len != last_offset {
+        return None;
+    } else {
+        std::io::copy(&mut reader.take(content_len as u64), &mut std::io::sink()).ok()?;
+    }
+    Some((RowId::from_le_bytes(row_id_buf), flags & DELETED != 0, row_bytes))
+}
+
+// Row record: flags u8, row id u64, column offsets u32, content length u32, content
+fn encode_row(row_id: RowId, row: &Row, column_mapping: &[usize], compression: Compression) -> Vec<u8> {
+    let offsets_bytes = (column_mapping.len() + 2) * FIELD_BYTES;
+    let mut record = Vec::with_capacity(1 + size_of::<RowId>() + offsets_bytes + row.data.len());
+    record.push(0);
+    record.extend_from_slice(&row_id.to_le_bytes());
+    let mut last_offset: usize = 0;
+    record.extend_from_slice(&0u32.to_le_bytes());
+    for next_col in column_mapping {
+        last_offset += row.offsets[*next_col + 1] - row.offsets[*next_col];
+        record.extend_from_slice(&field(last_offset));
+    }
+    let mut content = Vec::with_capacity(last_offset);
+    for next_col in column_mapping {
+        content.extend_from_slice(row.get_column(*next_col));
+    }
+    if compression == Compression::Lz {
+        let compressed = compress(&content);
+        if compressed.len() < content.len() {
+            record[0] |= COMPRESSED;
+            content = compressed;
+        }
+    }
+    record.extend_from_slice(&field(content.len()));
+    record.extend_from_slice(&content);
+    record
+}
+
+// Rows are far smaller than 4 GiB, page groups address them with u32 already
+fn field(val: usize) -> [u8; FIELD_BYTES] {
+    u32::try_from(val).expect("Row too large for the disk format").to_le_bytes()
+}
+
+fn record_id(record: &[u8]) -> RowId {
+    RowId::from_le_bytes(record[1..9].try_into().unwrap())
+}
+
+// Id, tombstone and content of a record from a page that passed its checksum
+fn decode_row(record: &[u8], offsets_per_row: usize) -> (RowId, bool, RowContent<'static>) {
+    let row_id = record_id(record);
+    let offsets: Vec<usize> = record[9..9 + offsets_per_row * FIELD_BYTES].chunks(FIELD_BYTES)
+        .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()) as usize)
+        .collect();
+    let stored = &record[9 + (offsets_per_row + 1) * FIELD_BYTES..];
+    let content = match record[0] & COMPRESSED {
+        0 => stored.to_vec(),
+        _ => decompress(stored, offsets[offsets_per_row - 1]).expect("Failed to decompress row"),
+    };
+    let row_content = RowContent { data: Cow::Owned(content), offsets: Cow::Owned(offsets) };
+    (row_id, record[0] & DELETED != 0, row_content)
+}
+
+// Unit of reads and writes of a table file. A row too big for one page gets a group of consecutive pages.
+pub const PAGE_SIZE: usize = 8192;
+// Checksum u64, pages in the group u32, slot count u32, start of the row data u32
+const PAGE_HEADER_BYTES: usize = 20;
+// Offset and length (u32 each) of a record within its page group
+const SLOT_BYTES: usize = 8;
+// A packed group has 0 where a plain one has the start of its row data, then the codec's tag u8,
+// the pages of its image u32 and the compressed length u32, followed by a tombstone bit per slot
+// and the compressed image
+const PACKED_HEADER_BYTES: usize = 29;
+// Pages of new groups of a table that packs them, so that a full one compresses into fewer
+const PACKED_GROUP_PAGES: usize = 8;
+
+// Page group: header, slot directory growing forward, row records packed backward from the end.
+// Existing records never move, a delete only flips the tombstone in place.
+// With a compression that packs pages, a group is compressed whole once it's full. Its tombstones
+// are then kept beside the compressed image, so deletes don't change it.
+#[derive(Clone)]
+struct Page {
+    // The group's image, or its stored bytes until `unpacked`
+    bytes: Vec<u8>,
+    packed: Option<Packed>,
+}
+
+// Compressed image of a full group, written back as it was read
+#[derive(Clone)]
+struct Packed {
+    compression: Compression,
+    block: Vec<u8>,
+    // Pages the group takes in the file
+    span: usize,
+}
+
+impl Page {
+
+    fn empty(span: usize) -> Page {
+        let mut page = Page { bytes: vec![0; span * PAGE_SIZE], packed: None };
+        page.set_u32(8, span as u32);
+        page.set_u32(16, (span * PAGE_SIZE) as u32);
+        page
+    }
+
+    // Pages needed for a group holding just the record
+    fn span_for(record_len: usize) -> usize {
+        (PAGE_HEADER_BYTES + SLOT_BYTES + record_len).div_ceil(PAGE_SIZE)
+    }
+
+    fn get_u32(&self, at: usize) -> usize {
+        self.bytes.get(at..at + 4).map_or(0, |word| u32::from_le_bytes(word.try_into().unwrap()) as usize)
+    }
+
+    fn set_u32(&mut self, at: usize, val: u32) {
+        self.bytes[at..at + 4].copy_from_slice(&val.to_le_bytes());
+    }
+
+    fn span(&self) -> usize {
+        self.get_u32(8)
+    }
+
+    fn slot_count(&self) -> usize {
+        self.get_u32(12)
+    }
+
+    fn slot(&self, slot: usize) -> (usize, usize) {
+        let at = PAGE_HEADER_BYTES + slot * SLOT_BYTES;
+        (self.get_u32(at), self.get_u32(at + 4))
+    }
+
+    fn record(&self, slot: usize) -> &[u8] {
+        let (offset, len) = self.slot(slot);
+        &self.bytes[offset..offset + len]
+    }
+
+    // Slot of the added record, None if the page is full
+    fn push_record(&mut self, record: &[u8]) -> Option<usize> {
+        let slot = self.slot_count();
+        let data_start = self.get_u32(16);
+        let slots_end = PAGE_HEADER_BYTES + (slot + 1) * SLOT_BYTES;
+        let offset = data_start.checked_sub(record.len()).filter(|offset| *offset >= slots_end)?;
+        self.bytes[offset..data_start].copy_from_slice(record);
+        let at = PAGE_HEADER_BYTES + slot * SLOT_BYTES;
+        self.set_u32(at, offset as u32);
+        self.set_u32(at + 4, record.len() as u32);
+        self.set_u32(12, slot as u32 + 1);
+        self.set_u32(16, offset as u32);
+        Some(slot)
+    }
+
+    // `record` must have the length of the one it replaces
+    fn replace_record(&mut self, slot: usize, record: &[u8]) {
+        let (offset, len) = self.slot(slot);
+        self.bytes[offset..offset + len].copy_from_slice(record);
+    }
+
+    fn set_tombstone(&mut self, slot: usize) {
+        let (offset, _) = self.slot(slot);
+        self.bytes[offset] |= DELETED;
+    }
+
+    fn checksum(&self) -> u64 {
+        stable_hash(&self.bytes[8..])
+    }
+
+    fn is_intact(&self) -> bool {
+        self.bytes.len() == self.span() * PAGE_SIZE
+            && self.bytes[..8] == self.checksum().to_le_bytes()
+    }
+
+    // Bytes of a packed group as stored, rather than its image
+    fn is_packed(&self) -> bool {
+        self.get_u32(16) == 0
+    }
+
+    // Codec of a packed group as stored, None if the build doesn't have it
+    fn packed_compression(&self) -> Option<Compression> {
+        self.bytes.get(20).and_then(|tag| Compression::from_tag(*tag))
+    }
+
+    // Image of a group as stored, with the tombstones set since it was packed. None if a packed
+    // group's image can't be read back.
+    fn unpacked(self) -> Option<Page> {
+        if !self.is_packed() {
+            return Some(self);
+        }
+        let compression = self.packed_compression()?;
+        let slots = self.slot_count();
+        let block_start = PACKED_HEADER_BYTES + slots.div_ceil(8);
+        let block = self.bytes.get(block_start..block_start.checked_add(self.get_u32(25))?)?;
+        let mut image = Page { bytes: compression.unpack(block, self.get_u32(21).checked_mul(PAGE_SIZE)?)?, packed: None };
+        if !image.is_intact() || image.slot_count() != slots {
+            return None;
+        }
+        for slot in (0..slots).filter(|slot| self.bytes[PACKED_HEADER_BYTES + slot / 8] & (1 << (slot % 8)) != 0) {
+            image.set_tombstone(slot);
+        }
+        image.packed = Some(Packed { compression, block: block.to_vec(), span: self.span() });
+        Some(image)
+    }
+
+    // Image compressed whole, None for compressions that don't pack pages
+    fn pack(&self, compression: Compression) -> Option<Packed> {
+        let block = compression.pack(&self.checksummed())?;
+        let span = (PACKED_HEADER_BYTES + self.slot_count().div_ceil(8) + block.len()).div_ceil(PAGE_SIZE);
+        Some(Packed { compression, block, span })
+    }
+
+    // A full group packed, if that takes fewer pages
+    fn packed(self, compression: Compression) -> Page {
+        match self.pack(compression) {
+            Some(packed) if self.packed.is_none() && packed.span < self.span() => Page { packed: Some(packed), ..self },
+            _ => self,
+        }
+    }
+
+    // Bytes the group takes in the file
+    fn stored_len(&self) -> usize {
+        self.packed.as_ref().map_or(self.bytes.len(), |packed| packed.span * PAGE_SIZE)
+    }
+
+    // Image bytes with the checksum filled in
+    fn checksummed(&self) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+        bytes[..8].copy_from_slice(&self.checksum().to_le_bytes());
+        bytes
+    }
+
+    // Bytes to store, with the checksum filled in
+    fn sealed(&self) -> Vec<u8> {
+        let Some(packed) = &self.packed else { return self.checksummed() };
+        let slots = self.slot_count();
+        let mut stored = Page { bytes: vec![0; packed.span * PAGE_SIZE], packed: None };
+        stored.set_u32(8, packed.span as u32);
+        stored.set_u32(12, slots as u32);
+        stored.bytes[20] = packed.compression.tag();
+        stored.set_u32(21, self.span() as u32);
+        stored.set_u32(25, packed.block.len() as u32);
+        for (slot, _, deleted) in self.rows() {
+            stored.bytes[PACKED_HEADER_BYTES + slot / 8] |= (deleted as u8) << (slot % 8);
+        }
+        let block_start = PACKED_HEADER_BYTES + slots.div_ceil(8);
+        stored.bytes[block_start..block_start + packed.block.len()].copy_from_slice(&packed.block);
+        stored.checksummed()
+    }
+
+    // New page with the records that are still complete and consistent, in slot order and with
+    // ids after `last_id`. Keeps the span if the group is complete, else the smallest that fits.
+    // A packed group's records are only read from its image, and packed again into its pages.
+    fn salvage(&self, offsets_per_row: usize, mut last_id: Option<RowId>, field_bytes: usize) -> Page {
+        let complete = self.span() >= 1 && self.bytes.len() == self.span() * PAGE_SIZE;
+        if self.is_packed() {
+            return match self.clone().unpacked() {
+                Some(image) => image.salvage(offsets_per_row, last_id, field_bytes),
+                None => Page::empty(if complete { self.span() } else { 1 }),
+            };
+        }
+        let mut records = Vec::new();
+        let max_slots = self.bytes.len().saturating_sub(PAGE_HEADER_BYTES) / SLOT_BYTES;
+        for slot in 0..self.slot_count().min(max_slots) {
+            let (offset, len) = self.slot(slot);
+            let slots_end = PAGE_HEADER_BYTES + (slot + 1) * SLOT_BYTES;
+            if offset < slots_end || offset + len > self.bytes.len() {
+                continue;
+            }
+            let record = &self.bytes[offset..offset + len];
+            match read_valid_row(&mut &record[..], offsets_per_row, len as u64, field_bytes) {
+                Some((row_id, _, row_bytes)) if row_bytes == len as u64 && last_id.is_none_or(|last| row_id > last) => {
+                    last_id = Some(row_id);
+                    records.push(record);
+                },
+                _ => {},
+            }
+        }
+        let needed = Page::span_for(records.iter().map(|record| record.len() + SLOT_BYTES).sum());
+        let mut page = Page::empty(if complete { self.span().max(needed) } else { needed });
+        for record in records {
+            page.push_record(record);
+        }
+        let Some(Packed { compression, span, .. }) = self.packed else { return page };
+        match page.pack(compression) {
+            Some(packed) if packed.span <= span => Page { packed: Some(Packed { span, ..packed }), ..page },
+            _ => Page::empty(span),
+        }
+    }
+
+    // Id and tombstone of every record
+    fn rows(&self) -> impl Iterator<Item = (usize, RowId, bool)> + '_ {
+        (0..self.slot_count()).map(|slot| {
+            let record = self.record(slot);
+            (slot, record_id(record), record[0] & DELETED != 0)
+        })
+    }
+}
+
+// Set of RowIds as a bitmap of 64-id words, sparse ids only cost the words they touch
+#[derive(Debug, Default)]
+struct RowIdSet {
+    words: BTreeMap<u64, u64>,
+}
+
+impl RowIdSet {
+
+    fn insert(&mut self, row_id: RowId) {
+        *self.words.entry(row_id / 64).or_default() |= 1 << (row_id % 64);
+    }
+
+    fn contains(&self, row_id: RowId) -> bool {
+        self.words.get(&(row_id / 64)).is_some_and(|word| word & (1 << (row_id % 64)) != 0)
+    }
+}
+
+// Where a row is stored: the file position of its page group, and its slot there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct RowLocation {
+    page: u64,
+    slot: usize,
+}
+
+// Rows per segment, the unit a scan can skip
+pub const SEGMENT_ROWS: usize = 1024;
+
+// Consecutive rows of the file, deleted ones included
+struct Segment {
+    start: RowLocation,
+    rows: usize,
+    blooms: HashMap<usize, BloomFilter>,
+}
+
+impl Segment {
+    // Adds a row's keys to the segment's Bloom filters of `bloom_columns`
+    fn add_keys<'r>(&mut self, columns: &[Column], bloom_columns: &[usize], column_data: impl Fn(usize) -> &'r [u8]) {
+        for column_idx in bloom_columns {
+            let bloom = self.blooms.entry(*column_idx).or_insert_with(|| BloomFilter::new(SEGMENT_ROWS));
+            match IndexKey::of_column(&columns[*column_idx], column_data(*column_idx)) {
+                Ok(key) => bloom.insert(key.stable_hash()),
+                // Scans of the segment must still see the undecodable row
+                Err(_) => bloom.saturate(),
+            }
+        }
+    }
 }
 
 type MagicType = [u8; 4];
 const HEADER_MAGIC: &MagicType = b"RDBI";
+// Bumped whenever the file layout changes, see `upgrade_format`
+pub const FORMAT_VERSION: u32 = 4;
 
 impl DiskStorage {
 
-    pub fn new(schema: Table, path: &str) -> Self {
-        let storage = DiskStorage {
-            path: path.to_string()
+    // Opens the table file at `path`, or creates it if it doesn't exist or is empty
+    pub fn new(schema: Table, path: &str) -> Result<Self, DbError> {
+        DiskStorage::open(schema, path).map(|(storage, _)| storage)
+    }
+
+    // Same as `new`, but ids of new rows come from `ids`
+    pub fn with_ids(schema: Table, path: &str, ids: Box<dyn IdAllocator>) -> Result<Self, DbError> {
+        let mut storage = DiskStorage::new(schema, path)?;
+        storage.ids = ids;
+        Ok(storage)
+    }
+
+    // Swaps the allocator of new row ids, returning the previous one
+    pub(crate) fn replace_ids(&mut self, ids: Box<dyn IdAllocator>) -> Box<dyn IdAllocator> {
+        std::mem::replace(&mut self.ids, ids)
+    }
+
+    fn unopened(schema: Table, path: &str, ids: Box<dyn IdAllocator>) -> Self {
+        DiskStorage {
+            path: path.to_string(),
+            durability: Durability::default(),
+            compression: Compression::default(),
+            columns: schema.column_layout,
+            live_rows: 0,
+            ids,
+            pages_start: 0,
+            pages: BTreeMap::new(),
+            file_len: 0,
+            tombstones: RowIdSet::default(),
+            row_positions: HashMap::new(),
+            segments: Vec::new(),
+            bloom_columns: Vec::new(),
+            writes: AtomicU64::new(0),
+            group: Arc::new(GroupCommit::new()),
+        }
+    }
+
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.durability = durability;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    // In-place versions of the builders, for storages that wrap this one
+    pub(crate) fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
+    }
+
+    pub(crate) fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    fn sync_if(&self, durability: Durability) {
+        if self.durability == durability {
+            self.file_writer().sync_data().expect("Failed to sync file");
+        }
+    }
+
+    fn offsets_per_row(&self) -> usize {
+        self.columns.len() + 1
+    }
+
+    fn write_header(&mut self) {
+        let header = encode_header(FORMAT_VERSION, self.offsets_per_row(), &encode_layout(&self.columns));
+        self.file_writer().write_all(&header).expect("Failed to write header");
+        self.pages_start = header.len() as u64;
+        self.file_len = self.pages_start;
+    }
+
+    // Column layout stored in the header of a table file, which every format version has kept the same
+    pub fn stored_layout(path: &str) -> Result<Vec<Column>, DbError> {
+        let file = File::open(path).map_err(|err| DbError::StorageError(format!("Failed to open {path}: {err}")))?;
+        let header = read_header(&mut BufReader::new(file))
+            .and_then(Result::ok)
+            .ok_or_else(|| DbError::DatabaseIntegrityError(format!("{path} has no valid table header")))?;
+        if header.format_version > FORMAT_VERSION {
+            return Err(DbError::UnsupportedFormatVersion { found: header.format_version, supported: FORMAT_VERSION });
+        }
+        decode_layout(&header.layout).ok_or_else(|| DbError::DatabaseIntegrityError(format!("{path} has a malformed column layout")))
+    }
+
+    // Attaches an existing table file, or creates it. Pages that fail their checksum, e.g. torn by a
+    // crash mid-write, are rewritten with the rows that are still intact. A torn page group at the
+    // end of the file is cut off if none of its rows are.
+    pub fn open(schema: Table, path: &str) -> Result<(DiskStorage, RecoveryReport), DbError> {
+        let io_err = |err: std::io::Error| DbError::StorageError(format!("Failed to open {path}: {err}"));
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path).map_err(io_err)?;
+        let file_len = file.metadata().map_err(io_err)?.len();
+        let offsets_per_row = schema.column_layout.len() + 1;
+
+        let mut reader = BufReader::new(file);
+        let header = read_header(&mut reader);
+        if let Some(Ok(Header { format_version, .. })) = header && format_version != FORMAT_VERSION {
+            drop(reader);
+            upgrade_format(path, format_version)?;
+            return DiskStorage::open(schema, path);
+        }
+
+        let mut storage = DiskStorage::unopened(schema, path, Box::new(MonotonicIds::default()));
+        let mut report = RecoveryReport { rows_recovered: 0, bytes_truncated: 0, pages_repaired: 0 };
+        let Some(header) = header else {
+            // New file, or one that crashed before its header was complete
+            reader.into_inner().set_len(0).map_err(io_err)?;
+            storage.write_header();
+            report.bytes_truncated = file_len;
+            return Ok((storage, report));
         };
+        let header = header.map_err(|_| DbError::DatabaseIntegrityError(format!("{path} is not a table file")))?;
+        if header.offsets_per_row != offsets_per_row || header.layout != encode_layout(&storage.columns) {
+            return Err(DbError::DatabaseIntegrityError(format!("{path} holds a table with a different column layout")));
+        }
+        storage.pages_start = reader.stream_position().map_err(io_err)?;
+
+        let mut position = storage.pages_start;
+        let mut last_id: Option<RowId> = None;
+        while position < file_len {
+            let mut page = read_page_group(&mut reader, file_len - position).map_err(io_err)?;
+            let complete = page.span() >= 1 && page.bytes.len() == page.span() * PAGE_SIZE;
+            let intact = page.is_intact();
+            if intact && page.is_packed() {
+                if page.packed_compression().is_none() {
+                    return Err(DbError::UnsupportedOperation(format!("{path} has pages compressed with a codec this build lacks")));
+                }
+                if let Some(image) = page.clone().unpacked() {
+                    page = image;
+                }
+            }
+            let ordered = || {
+                let mut ids = page.rows().map(|(_, row_id, _)| row_id);
+                let first = ids.next();
+                first.is_none_or(|first| last_id.is_none_or(|last| first > last)) && ids.is_sorted()
+            };
+            if !intact || page.is_packed() || !ordered() {
+                page = page.salvage(offsets_per_row, last_id, FIELD_BYTES);
+                if !complete && page.slot_count() == 0 {
+                    break;
+                }
+                report.pages_repaired += 1;
+                let file = reader.get_mut();
+                file.seek(SeekFrom::Start(position)).map_err(io_err)?;
+                file.write_all(&page.sealed()).map_err(io_err)?;
+            }
+            storage.record_page(&page, position, &mut last_id, &mut report);
+            position += page.stored_len() as u64;
+            // A torn group was the last thing written, anything after it is garbage
+            if !complete {
+                break;
+            }
+            reader.seek(SeekFrom::Start(position)).map_err(io_err)?;
+        }
 
-        // FIXME: Opening file again should not override header
-        // FIXME: Tests always pre-create the file. Will this work if file is not present?
-        let mut writer = storage.buf_writer();
-        writer.write_all(HEADER_MAGIC).expect("Failed to write magic number");
-        writer.write_all(&(schema.column_layout.len() + 1 as usize).to_le_bytes()).expect("Failed to write offsets per row");
-        return storage;
+        let file = reader.into_inner();
+        if position < file_len {
+            report.bytes_truncated = file_len - position;
+        }
+        file.set_len(position).map_err(io_err)?;
+        storage.file_len = position;
+        storage.ids = Box::new(MonotonicIds::starting_at(last_id.map_or(0, |last| last + 1)));
+        Ok((storage, report))
     }
 
-    pub fn new_reader(&self) -> (BufReader<File>, usize) {
+    // Adds the rows of a page found by `open` to the positions and segments
+    fn record_page(&mut self, page: &Page, position: u64, last_id: &mut Option<RowId>, report: &mut RecoveryReport) {
+        let mut live_rows = 0;
+        for (slot, row_id, deleted) in page.rows() {
+            let location = RowLocation { page: position, slot };
+            if self.segments.last().is_none_or(|seg| seg.rows == SEGMENT_ROWS) {
+                self.segments.push(Segment { start: location, rows: 0, blooms: HashMap::new() });
+            }
+            self.segments.last_mut().unwrap().rows += 1;
+            if deleted {
+                self.tombstones.insert(row_id);
+            } else {
+                self.row_positions.insert(row_id, location);
+                self.live_rows += 1;
+                live_rows += 1;
+            }
+            *last_id = Some(row_id);
+            report.rows_recovered += 1;
+        }
+        self.pages.insert(position, live_rows);
+    }
+
+    fn new_reader(&self) -> BufReader<File> {
         // TODO: Use mmap instead
-        let file = OpenOptions::new().read(true).open(&self.path).expect("Failed to open file for writing");
-        let mut reader = BufReader::new(file);
-        let mut magic_buf = MagicType::default();
-        reader.read_exact(&mut magic_buf).expect("Failed to read magic number");
-        assert_eq!(&magic_buf, HEADER_MAGIC);
-        let mut offsets_per_row_buf = usize::to_le_bytes(0);
-        reader.read_exact(&mut offsets_per_row_buf).expect("Failed to read offsets per row");
+        let file = OpenOptions::new().read(true).open(&self.path).expect("Failed to open file for reading");
+        BufReader::new(file)
+    }
+
+    // Page group at `position`, checked against its checksum
+    fn read_page(&self, reader: &mut BufReader<File>, position: u64) -> Page {
+        reader.seek(SeekFrom::Start(position)).expect("Failed to seek to page");
+        let page = read_page_group(reader, self.file_len - position).expect("Failed to read page");
+        assert!(page.is_intact(), "Page at {position} of {} fails its checksum", self.path);
+        page.unpacked().unwrap_or_else(|| panic!("Page at {position} of {} can't be unpacked", self.path))
+    }
 
-        let num_offsets = usize::from_le_bytes(offsets_per_row_buf);
-        let offsets_bytes = num_offsets * size_of::<usize>();
-        // println!("Number of offsets per row: {num_offsets}");
-        return (reader, offsets_bytes);
+    // Pages of a new group that starts with a record of `record_len` bytes
+    fn group_span(&self, record_len: usize) -> usize {
+        let span = Page::span_for(record_len);
+        if self.compression.packs_pages() { span.max(PACKED_GROUP_PAGES) } else { span }
     }
 
-    pub fn buf_writer(&self) -> BufWriter<File> {
-        let file = OpenOptions::new().write(true).open(&self.path).expect("Failed to open file for writing");
-        BufWriter::new(file)
+    fn write_page(&self, writer: &mut File, position: u64, page: &Page) {
+        writer.seek(SeekFrom::Start(position)).expect("Failed to seek to page");
+        writer.write_all(&page.sealed()).expect("Failed to write page");
+    }
+
+    fn segment_end(&self, seg_idx: usize) -> RowLocation {
+        self.segments.get(seg_idx + 1).map(|seg| seg.start).unwrap_or(RowLocation { page: u64::MAX, slot: 0 })
+    }
+
+    // Rows stored from `start` up to, but not including, `end`
+    // Pages without live rows aren't read at all
+    fn scan_range(&self, start: RowLocation, end: RowLocation) -> TableIterator<'_> {
+        let mut reader = self.new_reader();
+        let offsets_per_row = self.offsets_per_row();
+        let mut positions = self.pages.range(start.page..)
+            .filter(|(_, live_rows)| **live_rows > 0)
+            .map(|(position, _)| *position);
+        let mut slot = 0;
+        let mut current: Option<(u64, Page)> = None;
+
+        TableIterator::new(Box::new(std::iter::from_fn(move || {
+            loop {
+                if current.is_none() {
+                    let position = positions.next()?;
+                    slot = if position == start.page { start.slot } else { 0 };
+                    current = Some((position, self.read_page(&mut reader, position)));
+                }
+                let (position, page) = current.as_ref().unwrap();
+                if (RowLocation { page: *position, slot }) >= end {
+                    return None;
+                }
+                if slot >= page.slot_count() {
+                    current = None;
+                    continue;
+                }
+                let record = page.record(slot);
+                slot += 1;
+                if self.tombstones.contains(record_id(record)) {
+                    continue;
+                }
+                let (row_id, _, row_content) = decode_row(record, offsets_per_row);
+                return Some(ScanItem { row_id, row_content });
+            }
+        })))
     }
 
     pub fn file_writer(&self) -> File {
+        self.writes.fetch_add(1, Ordering::Relaxed);
         OpenOptions::new().write(true).open(&self.path).expect("Failed to open file for writing")
     }
 }
 
-// TODO: Implement disk storage
+// Bytes of a page group, from its first page
+#[cfg(feature = "async")]
+pub(crate) fn page_group_len(first_page: &[u8]) -> usize {
+    Page { bytes: first_page[..PAGE_HEADER_BYTES.min(first_page.len())].to_vec(), packed: None }.span() * PAGE_SIZE
+}
+
+#[cfg(feature = "async")]
+impl DiskStorage {
+    pub(crate) fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub(crate) fn writes(&self) -> u64 {
+        self.writes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn file_len(&self) -> u64 {
+        self.file_len
+    }
+
+    // Positions of the page groups with live rows, in file order
+    pub(crate) fn live_pages(&self) -> Vec<u64> {
+        self.pages.iter().filter(|(_, live_rows)| **live_rows > 0).map(|(position, _)| *position).collect()
+    }
+
+    // Live rows of the page group read from `position`
+    pub(crate) fn live_rows_of(&self, position: u64, bytes: Vec<u8>) -> Result<Vec<ScanItem<'static>>, DbError> {
+        let page = Page { bytes, packed: None };
+        if !page.is_intact() {
+            return Err(DbError::DatabaseIntegrityError(format!("Page at {position} of {} fails its checksum", self.path)));
+        }
+        let page = page.unpacked()
+            .ok_or_else(|| DbError::DatabaseIntegrityError(format!("Page at {position} of {} can't be unpacked", self.path)))?;
+        let offsets_per_row = self.offsets_per_row();
+        Ok((0..page.slot_count())
+            .map(|slot| page.record(slot))
+            .filter(|record| !self.tombstones.contains(record_id(record)))
+            .map(|record| {
+                let (row_id, _, row_content) = decode_row(record, offsets_per_row);
+                ScanItem { row_id, row_content }
+            })
+            .collect())
+    }
+}
+
+// Reads a page group, or as much of one as there is in the next `remaining` bytes
+fn read_page_group(reader: &mut impl Read, remaining: u64) -> std::io::Result<Page> {
+    let mut bytes = Vec::with_capacity(PAGE_SIZE);
+    reader.take(remaining.min(PAGE_SIZE as u64)).read_to_end(&mut bytes)?;
+    let mut page = Page { bytes, packed: None };
+    let group_len = (page.span() * PAGE_SIZE) as u64;
+    if group_len > PAGE_SIZE as u64 && group_len <= remaining {
+        reader.take(group_len - PAGE_SIZE as u64).read_to_end(&mut page.bytes)?;
+    }
+    Ok(page)
+}
+
 impl Storage for DiskStorage {
-    
-    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) {
-        // println!("DiskStorage::store - start - storing {} rows", rows.len());
+
+    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) -> Vec<RowId> {
         // TODO: Storage error handling
-        // TODO: This is probably not optimal
-        let mut writer = self.buf_writer();
-        writer.seek(SeekFrom::End(0)).expect("Failed to seek writer to end");
-        // println!("Position {}", writer.stream_position().unwrap());
+        let mut writer = self.file_writer();
+        let mut reader = self.new_reader();
+        let stored_len = self.file_len;
+        let last_page = self.pages.last_key_value().map(|(position, _)| *position);
+        let mut current = last_page.map(|position| (position, self.read_page(&mut reader, position)));
+        let mut row_ids = Vec::with_capacity(rows.len());
         for row in rows {
-            // println!("\nRow: {:?}", row);
-            // println!("Column mapping: {:?}", column_mapping);
-            
-            // Write deleted=0
-            writer.write(&[0]).expect("Failed to write deleted=0");
-            
-            // Column offsets
-            // FIXME: This is bad.
-            let mut last_offset: usize = 0;
-            writer.write(&last_offset.to_le_bytes()).expect("Failed to write initial column offset");
-            for next_col in column_mapping {
-                let sz = row.offsets[*next_col + 1] - row.offsets[*next_col];
-                // println!("Last offset: {last_offset}, size: {sz}");
-                last_offset += sz;
-                writer.write(&last_offset.to_le_bytes()).expect("Failed to write offset");
-            }
-            
-            // Row content length
-            writer.write_all(&row.data.len().to_le_bytes()).expect("Failed to write content length");
-
-            // Row content
-            for next_col in column_mapping {
-                let col = row.get_column(*next_col);
-                // println!("Column {next_col}: {:?}", col);
-                writer.write_all(col).expect("Failed to write column");
+            let row_id = self.ids.allocate();
+            let record = encode_row(row_id, row, column_mapping, self.compression);
+            let slot = match current.as_mut().filter(|(_, page)| page.packed.is_none()).and_then(|(_, page)| page.push_record(&record)) {
+                Some(slot) => slot,
+                None => {
+                    // The last group, so a packed one frees the pages after it
+                    if let Some((position, full)) = current.take() {
+                        let full = full.packed(self.compression);
+                        self.write_page(&mut writer, position, &full);
+                        self.file_len = position + full.stored_len() as u64;
+                    }
+                    let mut page = Page::empty(self.group_span(record.len()));
+                    let slot = page.push_record(&record).expect("Record must fit its own page group");
+                    let position = self.file_len;
+                    self.file_len += page.bytes.len() as u64;
+                    self.pages.insert(position, 0);
+                    current = Some((position, page));
+                    slot
+                },
+            };
+            let location = RowLocation { page: current.as_ref().unwrap().0, slot };
+            row_ids.push(row_id);
+            self.row_positions.insert(row_id, location);
+            *self.pages.get_mut(&location.page).unwrap() += 1;
+            if self.segments.last().is_none_or(|seg| seg.rows == SEGMENT_ROWS) {
+                self.segments.push(Segment { start: location, rows: 0, blooms: HashMap::new() });
             }
+            let segment = self.segments.last_mut().unwrap();
+            segment.rows += 1;
+            segment.add_keys(&self.columns, &self.bloom_columns, |column_idx| row.get_column(column_mapping[column_idx]));
         }
-        writer.flush().expect("Failed to flush file");
-        // println!("\nDiskStorage::store - finished\n");
+        if let Some((position, page)) = &current {
+            self.write_page(&mut writer, *position, page);
+        }
+        if self.file_len < stored_len {
+            writer.set_len(self.file_len).expect("Failed to truncate file");
+        }
+        self.sync_if(Durability::SyncPerBatch);
+        self.live_rows += rows.len();
+        row_ids
     }
 
-    fn scan(&self) -> TableIterator {
+    fn scan(&self) -> TableIterator<'_> {
+        self.scan_range(RowLocation { page: 0, slot: 0 }, RowLocation { page: u64::MAX, slot: 0 })
+    }
 
-        let (mut reader, offsets_bytes) = self.new_reader();        // TODO: Use mmap instead
-        let mut row_num: RowId = 0;
+    fn scan_maybe_equal(&self, column_idx: usize, key_hash: u64) -> TableIterator<'_> {
+        if !self.bloom_columns.contains(&column_idx) {
+            return self.scan();
+        }
+        TableIterator::new(Box::new(
+            (0..self.segments.len())
+                .filter(move |seg_idx| self.segments[*seg_idx].blooms.get(&column_idx).is_some_and(|bloom| bloom.may_contain(key_hash)))
+                .flat_map(move |seg_idx| self.scan_range(self.segments[seg_idx].start, self.segment_end(seg_idx)))
+        ))
+    }
 
-        TableIterator::new(Box::new(std::iter::from_fn(move || {
+    fn add_bloom_filter(&mut self, column_idx: usize) -> Result<(), DbError> {
+        if self.bloom_columns.contains(&column_idx) {
+            return Ok(());
+        }
+        for seg in &mut self.segments {
+            seg.blooms.insert(column_idx, BloomFilter::new(SEGMENT_ROWS));
+        }
+        let live: Vec<(RowId, RowLocation)> = self.row_positions.iter().map(|(id, location)| (*id, *location)).collect();
+        for (row_id, location) in live {
+            let data = self.get_row(row_id).expect("Live row must be readable").row_content.get_column(column_idx).to_vec();
+            let seg_idx = self.segments.partition_point(|seg| seg.start <= location) - 1;
+            self.segments[seg_idx].add_keys(&self.columns, &[column_idx], |_| &data);
+        }
+        self.bloom_columns.push(column_idx);
+        Ok(())
+    }
 
-            // println!("\nReading row {row_num}...");
-            loop {
-                // println!("Will attempt to read row {}", row_num);
-                // Read tombstone
-                let mut tombstone_buf = 0u8.to_ne_bytes();
-                if reader.read_exact(&mut tombstone_buf).is_err_and(|err| err.kind() == std::io::ErrorKind::UnexpectedEof) {
-                    // Reached end of file
-                    return None;
-                }
-                
-                // Check if row is marked as deleted
-                if u8::from_ne_bytes(tombstone_buf) != 0 {
-                    // Skip row column offsets
-                    reader.seek_relative(offsets_bytes as i64).expect(format!("Failed to skip offsets in {row_num}").as_str());
-
-                    // Skip row content
-                    let mut len_buf = usize::to_le_bytes(0);
-                    reader.read_exact(&mut len_buf).expect("Failed to read content length");
-                    let content_len = usize::from_le_bytes(len_buf);
-                    reader.seek_relative(content_len as i64).expect(format!("Failed to skip content in {row_num}").as_str());
-
-                    // Try to read next row
-                    row_num += 1;
-                    continue;
-                }
+    fn row_count(&self) -> usize {
+        self.live_rows
+    }
 
-                // Read row column offsets
-                let mut offsets_buf = vec![0u8; offsets_bytes];
-                reader.read_exact(&mut offsets_buf).expect(format!("Failed to read offsets at {row_num}").as_str());
-                let offsets: Vec<usize> = offsets_buf.chunks(size_of::<usize>())
-                    .map(|chunk| usize::from_le_bytes(chunk.try_into().unwrap()))
-                    .collect();
-                // println!("Offsets: {:?}", offsets);
-
-                // Read content length
-                let mut len_buf = usize::to_le_bytes(0);
-                reader.read_exact(&mut len_buf).expect("Failed to read content length");
-                let content_len = usize::from_le_bytes(len_buf);
-
-                // Read content
-                let mut content = vec![0u8; content_len];
-                reader.read_exact(&mut content).expect("Failed to read content");
-                // println!("Content: {:?}", content);
-
-                // Create scan item
-                // FIXME: Dark Rust magic
-                let content_box = content.into_boxed_slice();
-                let offsets_box = offsets.into_boxed_slice();
-                let row_content = RowContent {
-                    data: Box::leak(content_box),
-                    offsets: Box::leak(offsets_box),
-                };
-                // print!("Row content: {row_content:?}\n");
-                let row_id = row_num.clone();
-                row_num += 1;
-                return Some(ScanItem { row_id, row_content } );
-            }
-        })))
+    fn disk_bytes(&self) -> u64 {
+        self.file_len
     }
 
-    fn delete_rows(&mut self, mut row_ids: Vec<RowId>) {
-        row_ids.sort();
+    #[cfg(feature = "async")]
+    fn as_async(&self) -> Option<&dyn crate::asynchronous::AsyncStorage> {
+        Some(self)
+    }
 
-        let (mut reader, offsets_bytes) = self.new_reader();
+    // Uncompressed records of plain groups are replaced within their page, each affected page is
+    // rewritten once
+    fn overwrite_rows(&mut self, rows: &[(RowId, Row)]) -> Result<(), DbError> {
+        let mut reader = self.new_reader();
+        let mut pages: BTreeMap<u64, Page> = BTreeMap::new();
+        let mut replacements = Vec::with_capacity(rows.len());
+        let column_mapping: Vec<usize> = (0..self.columns.len()).collect();
+        for (row_id, row) in rows {
+            let location = *self.row_positions.get(row_id)
+                .ok_or_else(|| DbError::StorageError(format!("Row {row_id} isn't stored")))?;
+            let page = pages.entry(location.page).or_insert_with(|| self.read_page(&mut reader, location.page));
+            let packed = page.packed.is_some();
+            let current = page.record(location.slot);
+            let record = encode_row(*row_id, row, &column_mapping, Compression::None);
+            if packed || current[0] & COMPRESSED != 0 || current.len() != record.len() {
+                return Err(DbError::UnsupportedOperation(format!("Row {row_id} can't be replaced in place")));
+            }
+            replacements.push((location, record));
+        }
+        for (location, record) in &replacements {
+            pages.get_mut(&location.page).unwrap().replace_record(location.slot, record);
+        }
         let mut writer = self.file_writer();
+        for (position, page) in &pages {
+            self.write_page(&mut writer, *position, page);
+        }
+        // Bloom filters only gain keys, the replaced ones become false positives
+        for ((location, _), (_, row)) in replacements.iter().zip(rows) {
+            let seg_idx = self.segments.partition_point(|seg| seg.start <= *location) - 1;
+            self.segments[seg_idx].add_keys(&self.columns, &self.bloom_columns, |column_idx| row.get_column(column_idx));
+        }
+        self.sync_if(Durability::SyncPerBatch);
+        Ok(())
+    }
 
-        let mut row_num: RowId = 0;
-        let mut len_buf = usize::to_le_bytes(0);
-
-        for next_deleted in row_ids {
-            'scan_loop: loop {
-                // Write deleted=1
-                if row_num == next_deleted {
-                    let row_start = reader.stream_position().expect(format!("Failed to read stream position at row {}", row_num).as_str());
-                    // println!("Will mark tombstone for {} at {}", row_num, row_start);
-                    writer.seek(SeekFrom::Start(row_start)).expect(format!("Failed to seek writer to {} at row {}", row_start, row_num).as_str());
-                    writer.write(&[1]).expect(format!("Failed to write tombstone at {}", row_num).as_str());
-                    break 'scan_loop;
+    // Rows stay on disk, only the maps to find them are in memory
+    fn memory_usage(&self) -> MemoryUsage {
+        let positions = self.row_positions.capacity() * size_of::<(RowId, RowLocation)>();
+        let pages = self.pages.len() * size_of::<(u64, usize)>();
+        let tombstones = self.tombstones.words.len() * size_of::<(u64, u64)>();
+        MemoryUsage { data: 0, offsets: positions + pages + tombstones, indexes: 0 }
+    }
+
+    // Pages are read once, and the ones with matching rows written back once
+    fn delete_where(&mut self, filter: &mut RowFilter) -> Result<Vec<RowId>, DbError> {
+        let mut reader = self.new_reader();
+        let offsets_per_row = self.offsets_per_row();
+        let live_pages: Vec<u64> = self.pages.iter().filter(|(_, live_rows)| **live_rows > 0).map(|(position, _)| *position).collect();
+        let mut changed = Vec::new();
+        let mut matching = Vec::new();
+        for position in live_pages {
+            let mut page = self.read_page(&mut reader, position);
+            let mut slots = Vec::new();
+            for slot in 0..page.slot_count() {
+                let record = page.record(slot);
+                if self.tombstones.contains(record_id(record)) {
+                    continue;
                 }
-                
-                // Check if row is marked as deleted
-                // Skip tombstone and row column offsets
-                reader.seek_relative(1 + offsets_bytes as i64).expect(format!("Failed to skip offsets in {row_num}").as_str());
-
-                // Skip row content
-                reader.read_exact(&mut len_buf).expect("Failed to read content length");
-                let content_len = usize::from_le_bytes(len_buf);
-                reader.seek_relative(content_len as i64).expect(format!("Failed to skip content in {row_num}").as_str());
-
-                // Try to read next row
-                row_num += 1;
-                continue 'scan_loop;
+                let (row_id, _, row_content) = decode_row(record, offsets_per_row);
+                if filter(&ScanItem { row_id, row_content })? {
+                    slots.push(slot);
+                    matching.push(row_id);
+                }
+            }
+            if !slots.is_empty() {
+                for slot in &slots {
+                    page.set_tombstone(*slot);
+                }
+                *self.pages.get_mut(&position).unwrap() -= slots.len();
+                changed.push((position, page));
             }
         }
-        
+        for row_id in &matching {
+            self.row_positions.remove(row_id);
+            self.tombstones.insert(*row_id);
+        }
+        self.live_rows -= matching.len();
+        let mut writer = self.file_writer();
+        for (position, page) in &changed {
+            self.write_page(&mut writer, *position, page);
+        }
+        self.sync_if(Durability::SyncPerBatch);
+        Ok(matching)
+    }
+
+    // Tombstones are set in place, each affected page is rewritten once
+    fn delete_rows(&mut self, row_ids: Vec<RowId>) {
+        let mut by_page: HashMap<u64, Vec<usize>> = HashMap::new();
+        for row_id in row_ids {
+            // Ids not present in this table, or already deleted
+            let Some(location) = self.row_positions.remove(&row_id) else { continue };
+            by_page.entry(location.page).or_default().push(location.slot);
+            *self.pages.get_mut(&location.page).unwrap() -= 1;
+            self.tombstones.insert(row_id);
+            self.live_rows -= 1;
+        }
+        let mut reader = self.new_reader();
+        let mut writer = self.file_writer();
+        for (position, slots) in by_page {
+            let mut page = self.read_page(&mut reader, position);
+            for slot in slots {
+                page.set_tombstone(slot);
+            }
+            self.write_page(&mut writer, position, &page);
+        }
+        self.sync_if(Durability::SyncPerBatch);
+    }
+
+    fn commit(&mut self) -> Result<(), DbError> {
+        match self.commit_grouped()? {
+            Some(pending) => pending.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn commit_grouped(&mut self) -> Result<Option<PendingSync>, DbError> {
+        match self.durability {
+            Durability::SyncPerCommit => sync_file(&self.path).map(|_| None),
+            Durability::GroupCommit => {
+                let path = self.path.clone();
+                Ok(Some(self.group.commit(move || sync_file(&path))))
+            },
+            Durability::Flush | Durability::SyncPerBatch => Ok(None),
+        }
     }
-}
 
+    fn sync(&mut self) -> Result<(), DbError> {
+        sync_file(&self.path)
+    }
+
+    fn get_rows<'a>(&'a self, row_ids: &'a [RowId]) -> TableIterator<'a> {
+        TableIterator::new(Box::new(row_ids.iter().filter_map(move |id| self.get_row(*id))))
+    }
+
+    // Reads only the row's page group
+    fn get_row(&self, row_id: RowId) -> Option<ScanItem<'_>> {
+        let location = *self.row_positions.get(&row_id)?;
+        let page = self.read_page(&mut self.new_reader(), location.page);
+        let (_, _, row_content) = decode_row(page.record(location.slot), self.offsets_per_row());
+        Some(ScanItem { row_id, row_content })
+    }
+
+    fn move_to(&mut self, path: &str) -> Result<(), DbError> {
+        if std::path::Path::new(path).exists() {
+            return Err(DbError::StorageError(format!("Cannot move table storage, {path} already exists")));
+        }
+        std::fs::rename(&self.path, path)
+            .map_err(|err| DbError::StorageError(format!("Failed to move {} to {path}: {err}", self.path)))?;
+        self.path = path.to_string();
+        Ok(())
+    }
+}
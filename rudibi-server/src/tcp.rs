@@ -0,0 +1,47 @@
+// Settings of accepted TCP connections, see `config`
+// A connection sending no request for its idle timeout is closed, freeing what serves it. TCP
+// keepalive probes a connection once it's been quiet for a while, so peers that went away without
+// closing, a crashed client or a dropped NAT entry, are found even while the server waits on them
+// for good.
+
+use std::io::{self, ErrorKind};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use socket2::{SockRef, TcpKeepalive};
+
+// Longest wait between keepalive probes once they start
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TcpSettings {
+    // None waits for requests for ever
+    pub idle_timeout: Option<Duration>,
+    // How long a connection is quiet before probes are sent, None sends none
+    pub keepalive: Option<Duration>,
+}
+
+impl TcpSettings {
+
+    // For connections served on threads, whose reads fail as timed out once idle
+    pub fn apply(&self, conn: &TcpStream) -> io::Result<()> {
+        conn.set_read_timeout(self.idle_timeout)?;
+        self.set_keepalive(conn)
+    }
+
+    pub fn set_keepalive<'a, S>(&self, conn: &'a S) -> io::Result<()> where SockRef<'a>: From<&'a S> {
+        let Some(time) = self.keepalive else { return Ok(()) };
+        let keepalive = TcpKeepalive::new().with_time(time).with_interval(time.min(KEEPALIVE_INTERVAL));
+        SockRef::from(conn).set_tcp_keepalive(&keepalive)
+    }
+}
+
+// Whether a connection ended for being idle, rather than failing
+pub fn timed_out(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+// Closes a connection idle for too long
+pub fn idle_error() -> io::Error {
+    io::Error::new(ErrorKind::TimedOut, "Connection was idle")
+}
@@ -0,0 +1,126 @@
+// OpenTelemetry spans of the requests a server handles, with the otel feature. Each request in
+// frames, text mode or PostgreSQL gets a `request` span, with a child span for each phase:
+//   parse       the command's text
+//   plan        the select's plan, cached or not, see `plan`
+//   scan        reading and filtering the table's rows
+//   serialize   writing the reply, or the streamed rows
+// A frame whose tag has `frame::TRACED` set starts with a W3C traceparent, e.g.
+//   00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01
+// then a NUL, and its request span continues that trace. Spans go to the global tracer provider,
+// which the program embedding the server installs along with its exporter. Without the feature,
+// or a provider, nothing is recorded.
+
+#[cfg(feature = "otel")]
+use opentelemetry::trace::{Span, SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState, Tracer};
+#[cfg(feature = "otel")]
+use opentelemetry::{global, Context, ContextGuard, KeyValue};
+
+// Ends its span when dropped, and makes the span the current one of the thread until then
+#[must_use]
+pub struct SpanGuard {
+    #[cfg(feature = "otel")]
+    active: Option<(Context, ContextGuard)>,
+}
+
+// The trace of a request, carried to the thread that runs its command, see `Server::with_pool`
+#[derive(Clone, Default)]
+pub struct TraceContext {
+    #[cfg(feature = "otel")]
+    context: Option<Context>,
+}
+
+// Keeps a trace the current one of the thread it's attached to, until it's dropped
+#[must_use]
+pub struct AttachedTrace {
+    #[cfg(feature = "otel")]
+    _attached: Option<ContextGuard>,
+}
+
+// Starts the span of a request, continuing the trace of `traceparent` if it's given and valid
+#[cfg(feature = "otel")]
+pub fn request(traceparent: Option<&str>) -> SpanGuard {
+    let parent = traceparent.and_then(remote_parent).unwrap_or_default();
+    start("request", &parent)
+}
+
+// Starts a span of a phase of the request running on the thread, none if there's no request
+#[cfg(feature = "otel")]
+pub fn span(name: &'static str) -> SpanGuard {
+    let current = Context::current();
+    match current.has_active_span() {
+        true => start(name, &current),
+        false => SpanGuard { active: None },
+    }
+}
+
+#[cfg(feature = "otel")]
+pub fn current() -> TraceContext {
+    let current = Context::current();
+    TraceContext { context: current.has_active_span().then_some(current) }
+}
+
+#[cfg(feature = "otel")]
+impl TraceContext {
+    pub fn attach(self) -> AttachedTrace {
+        AttachedTrace { _attached: self.context.map(Context::attach) }
+    }
+}
+
+#[cfg(feature = "otel")]
+fn start(name: &'static str, parent: &Context) -> SpanGuard {
+    let tracer = global::tracer("rudibi");
+    let mut span = tracer.start_with_context(name, parent);
+    span.set_attribute(KeyValue::new("db.system.name", "rudibi"));
+    let context = parent.with_span(span);
+    SpanGuard { active: Some((context.clone(), context.attach())) }
+}
+
+// None if it isn't a traceparent of version 00
+#[cfg(feature = "otel")]
+fn remote_parent(traceparent: &str) -> Option<Context> {
+    let ["00", trace_id, span_id, flags] = traceparent.trim().split('-').collect::<Vec<_>>()[..] else { return None };
+    if trace_id.len() != 32 || span_id.len() != 16 || flags.len() != 2 {
+        return None;
+    }
+    let (trace_id, span_id) = (TraceId::from_hex(trace_id).ok()?, SpanId::from_hex(span_id).ok()?);
+    let flags = TraceFlags::new(u8::from_str_radix(flags, 16).ok()?);
+    let parent = SpanContext::new(trace_id, span_id, flags, true, TraceState::default());
+    parent.is_valid().then(|| Context::new().with_remote_span_context(parent))
+}
+
+#[cfg(feature = "otel")]
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        if let Some((context, _attached)) = self.active.take() {
+            context.span().end();
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn request(_: Option<&str>) -> SpanGuard {
+    SpanGuard {}
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn span(_: &'static str) -> SpanGuard {
+    SpanGuard {}
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn current() -> TraceContext {
+    TraceContext {}
+}
+
+#[cfg(not(feature = "otel"))]
+impl TraceContext {
+    pub fn attach(self) -> AttachedTrace {
+        AttachedTrace {}
+    }
+}
+
+// Runs `call` in a span of a phase of the request
+pub fn in_span<T>(name: &'static str, call: impl FnOnce() -> T) -> T {
+    let _span = span(name);
+    call()
+}
@@ -1,6 +1,10 @@
 
+use std::io::Cursor;
+
 use crate::dtype::*;
 use crate::engine::*;
+use crate::frame::{self, Frame};
+use crate::protocol::Server;
 
 pub fn fruits_schema() -> Table {
     Table::new("Fruits",
@@ -50,6 +54,34 @@ pub fn fruits_table(storage: StorageCfg) -> Database {
     return db;
 }
 
+pub fn fruits_server() -> Server {
+    Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory)
+}
+
+// What a connection sending `lines` as text is replied, see `Server::serve_text`
+pub fn serve_text(server: &Server, lines: &[&str]) -> String {
+    let mut output = Vec::new();
+    server.serve_text(Cursor::new(lines.join("\n")), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+// EXECUTE requests of the statements, their ids counting from 0
+pub fn executes(statements: &[&str]) -> Vec<Frame> {
+    statements.iter().enumerate().map(|(id, statement)| Frame::new(frame::EXECUTE, id as u32, *statement)).collect()
+}
+
+// The frames a connection sending `requests` is replied, see `Server::serve`
+pub fn serve(server: &Server, requests: &[Frame]) -> Vec<Frame> {
+    let mut input = Vec::new();
+    for request in requests {
+        request.write_to(&mut input).unwrap();
+    }
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = output.as_slice();
+    std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect()
+}
+
 pub fn empty_table(storage: StorageCfg) -> Database {
     let mut db = Database::new();
     db.new_table(&Table::new("EmptyTable", vec![Column::new("id", DataType::U32)]), storage).unwrap();
@@ -80,8 +112,20 @@ pub fn random_temp_file() -> String {
     new_file
 }
 
+// New empty directory, same approach as `random_temp_file`
+pub fn random_temp_dir() -> String {
+    let tmp = env::temp_dir();
+    loop {
+        let unix_timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let dir = format!("{}/test_dir_{}", tmp.display(), unix_timestamp.as_nanos());
+        if std::fs::create_dir(&dir).is_ok() {
+            break dir;
+        }
+    }
+}
+
 pub fn with_tmp(fun: fn(StorageCfg)) {
     let file_path =  random_temp_file();
-    fun(StorageCfg::Disk { path: file_path.clone() });
+    fun(StorageCfg::disk(&file_path));
     std::fs::remove_file(file_path).unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,109 @@
+// Rate limits of connections and users, so one client sending too much can't starve the others.
+// Each rate has a budget refilling continuously, up to a second's worth. A query takes one from
+// the budget of queries when it starts, and the rows it scanned and the bytes of rows it returned
+// are taken once it's done, which may overdraw theirs. While a budget is short, queries fail with
+// `DbError::RateLimited`, saying how long until it's refilled enough.
+
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::engine::DbError;
+
+// Per second, None for no limit
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct RateLimits {
+    pub queries: Option<f64>,
+    pub rows_scanned: Option<f64>,
+    // Bytes of the selected rows as stored, not as they're sent
+    pub bytes_returned: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rate {
+    Queries,
+    RowsScanned,
+    BytesReturned,
+}
+
+#[derive(Debug)]
+pub struct Throttle {
+    limits: RateLimits,
+    // What's left of each rate's budget, by `Rate`, when they were last refilled
+    budgets: Mutex<([f64; 3], Instant)>,
+}
+
+impl RateLimits {
+
+    pub fn is_empty(&self) -> bool {
+        self.rates().all(|(_, limit)| limit.is_none())
+    }
+
+    fn rates(&self) -> impl Iterator<Item = (Rate, Option<f64>)> {
+        [(Rate::Queries, self.queries), (Rate::RowsScanned, self.rows_scanned), (Rate::BytesReturned, self.bytes_returned)].into_iter()
+    }
+}
+
+impl Rate {
+    // Of the budget, for a query to start
+    fn needed(self) -> f64 {
+        match self {
+            Rate::Queries => 1.0,
+            Rate::RowsScanned | Rate::BytesReturned => 0.0,
+        }
+    }
+}
+
+impl Throttle {
+
+    pub fn new(limits: RateLimits) -> Throttle {
+        let mut budgets = [0.0; 3];
+        for (rate, limit) in limits.rates() {
+            budgets[rate as usize] = limit.map_or(0.0, capacity);
+        }
+        Throttle { limits, budgets: Mutex::new((budgets, Instant::now())) }
+    }
+
+    // Takes a query from the budget, or fails with the longest wait of the budgets short
+    pub fn admit(&self) -> Result<(), DbError> {
+        let mut budgets = self.budgets.lock().unwrap_or_else(PoisonError::into_inner);
+        let (budgets, refilled) = &mut *budgets;
+        let now = Instant::now();
+        let elapsed = now.duration_since(*refilled).as_secs_f64();
+        *refilled = now;
+        let mut short = None;
+        for (rate, limit) in self.limits.rates() {
+            let Some(limit) = limit else { continue };
+            let budget = &mut budgets[rate as usize];
+            *budget = (*budget + elapsed * limit).min(capacity(limit));
+            if *budget < rate.needed() {
+                let retry_after = Duration::from_secs_f64((rate.needed() - *budget) / limit);
+                if short.is_none_or(|(_, wait)| retry_after > wait) {
+                    short = Some((rate, retry_after));
+                }
+            }
+        }
+        if let Some((rate, retry_after)) = short {
+            return Err(DbError::RateLimited { rate, retry_after });
+        }
+        if self.limits.queries.is_some() {
+            budgets[Rate::Queries as usize] -= 1.0;
+        }
+        Ok(())
+    }
+
+    // What an admitted query did, taken from the budgets
+    pub fn charge(&self, rows_scanned: usize, bytes_returned: usize) {
+        let mut budgets = self.budgets.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.limits.rows_scanned.is_some() {
+            budgets.0[Rate::RowsScanned as usize] -= rows_scanned as f64;
+        }
+        if self.limits.bytes_returned.is_some() {
+            budgets.0[Rate::BytesReturned as usize] -= bytes_returned as f64;
+        }
+    }
+}
+
+// A second's worth, and at least a query's for rates below one
+fn capacity(limit: f64) -> f64 {
+    limit.max(1.0)
+}
@@ -0,0 +1,176 @@
+// Two-tier table storage: new rows go to memory, and are flushed to a disk table in one batch
+// Flushes happen when the hot tier grows past its limit, when the storage is dropped and, with
+// `Durability::SyncPerCommit`, at every commit. Otherwise up to the limit of rows is lost in a crash.
+// Flushing runs on the writing thread, storages aren't shared across threads.
+// Ids all come from one allocator, so every cold row has a lower id than every hot row and
+// scans are a cold scan followed by a hot one.
+
+use crate::compress::Compression;
+use crate::engine::{DbError, Row, Table};
+use crate::group_commit::PendingSync;
+use crate::storage::{DiskStorage, Durability, InMemoryStorage, MemoryUsage, ReplayIds, RowId, ScanItem, Storage, TableIterator};
+
+// Hot rows kept in memory before they're flushed without waiting for a commit
+pub const DEFAULT_HOT_ROWS: usize = 4096;
+
+pub struct TieredStorage {
+    schema: Table,
+    hot: InMemoryStorage,
+    cold: DiskStorage,
+    durability: Durability,
+    max_hot_rows: usize,
+}
+
+impl TieredStorage {
+
+    // Opens the cold tier at `path`, or creates it
+    pub fn new(schema: Table, path: &str) -> Result<Self, DbError> {
+        let mut cold = DiskStorage::new(schema.clone(), path)?;
+        let ids = cold.replace_ids(Box::new(ReplayIds::none()));
+        let hot = InMemoryStorage::with_ids(schema.clone(), ids);
+        Ok(TieredStorage { schema, hot, cold, durability: Durability::default(), max_hot_rows: DEFAULT_HOT_ROWS })
+    }
+
+    pub fn with_durability(mut self, durability: Durability) -> Self {
+        self.cold.set_durability(durability);
+        self.durability = durability;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.cold.set_compression(compression);
+        self
+    }
+
+    pub fn with_max_hot_rows(mut self, max_hot_rows: usize) -> Self {
+        self.max_hot_rows = max_hot_rows;
+        self
+    }
+
+    // Rows not flushed yet
+    pub fn hot_rows(&self) -> usize {
+        self.hot.row_count()
+    }
+
+    // Moves the live hot rows to the disk tier, with their ids
+    pub fn flush(&mut self) {
+        if self.hot.row_count() == 0 {
+            return;
+        }
+        let (ids, rows): (Vec<RowId>, Vec<Row>) = self.hot.scan()
+            .map(|item| (item.row_id, Row { data: item.row_content.data.to_vec(), offsets: item.row_content.offsets.to_vec() }))
+            .unzip();
+        let column_mapping: Vec<usize> = (0..self.schema.column_layout.len()).collect();
+        self.cold.replace_ids(Box::new(ReplayIds::of(ids)));
+        self.cold.store(&rows, &column_mapping);
+        let allocator = self.hot.replace_ids(Box::new(ReplayIds::none()));
+        self.hot = InMemoryStorage::with_ids(self.schema.clone(), allocator);
+    }
+}
+
+impl Storage for TieredStorage {
+
+    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) -> Vec<RowId> {
+        let row_ids = self.hot.store(rows, column_mapping);
+        if self.hot.row_count() >= self.max_hot_rows {
+            self.flush();
+        }
+        row_ids
+    }
+
+    fn scan(&self) -> TableIterator<'_> {
+        TableIterator::new(Box::new(self.cold.scan().chain(self.hot.scan())))
+    }
+
+    fn get_rows<'a>(&'a self, row_ids: &'a [RowId]) -> TableIterator<'a> {
+        TableIterator::new(Box::new(self.cold.get_rows(row_ids).chain(self.hot.get_rows(row_ids))))
+    }
+
+    // Hot rows aren't in any Bloom filter, so they're always included
+    fn scan_maybe_equal(&self, column_idx: usize, key_hash: u64) -> TableIterator<'_> {
+        TableIterator::new(Box::new(self.cold.scan_maybe_equal(column_idx, key_hash).chain(self.hot.scan())))
+    }
+
+    fn add_bloom_filter(&mut self, column_idx: usize) -> Result<(), DbError> {
+        self.cold.add_bloom_filter(column_idx)
+    }
+
+    fn commit(&mut self) -> Result<(), DbError> {
+        match self.commit_grouped()? {
+            Some(pending) => pending.wait(),
+            None => Ok(()),
+        }
+    }
+
+    fn commit_grouped(&mut self) -> Result<Option<PendingSync>, DbError> {
+        if matches!(self.durability, Durability::SyncPerCommit | Durability::GroupCommit) {
+            self.flush();
+        }
+        self.cold.commit_grouped()
+    }
+
+    fn sync(&mut self) -> Result<(), DbError> {
+        self.flush();
+        self.cold.sync()
+    }
+
+    fn get_row(&self, row_id: RowId) -> Option<ScanItem<'_>> {
+        self.hot.get_row(row_id).or_else(|| self.cold.get_row(row_id))
+    }
+
+    fn delete_rows(&mut self, row_ids: Vec<RowId>) {
+        self.hot.delete_rows(row_ids.clone());
+        self.cold.delete_rows(row_ids);
+    }
+
+    fn row_count(&self) -> usize {
+        self.hot.row_count() + self.cold.row_count()
+    }
+
+    fn memory_usage(&self) -> MemoryUsage {
+        self.hot.memory_usage() + self.cold.memory_usage()
+    }
+
+    fn disk_bytes(&self) -> u64 {
+        self.cold.disk_bytes()
+    }
+
+    fn compact(&mut self) {
+        self.hot.compact();
+        self.cold.compact();
+    }
+
+    fn move_to(&mut self, path: &str) -> Result<(), DbError> {
+        self.cold.move_to(path)
+    }
+}
+
+impl Drop for TieredStorage {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testlib::{fruits_schema, random_temp_file};
+
+    fn fruit(id: u32, name: &str) -> Row {
+        Row::of_columns(&[&id.to_le_bytes(), name.as_bytes()])
+    }
+
+    #[test]
+    fn flushed_rows_keep_their_ids() {
+        let path = random_temp_file();
+        let mut storage = TieredStorage::new(fruits_schema(), &path).unwrap().with_max_hot_rows(3);
+        let ids = storage.store(&[fruit(1, "apple"), fruit(2, "banana")], &vec![0, 1]);
+        assert_eq!(storage.hot_rows(), 2);
+        let more = storage.store(&[fruit(3, "cherry")], &vec![0, 1]);
+        assert_eq!(storage.hot_rows(), 0);
+        storage.delete_rows(vec![ids[1]]);
+        let scanned: Vec<RowId> = storage.scan().map(|item| item.row_id).collect();
+        assert_eq!(scanned, vec![ids[0], more[0]]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
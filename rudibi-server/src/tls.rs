@@ -0,0 +1,79 @@
+// TLS for client connections, with a certificate and private key from PEM files
+// A `TlsStream` is read and written through shared references, like a `TcpStream`, so the serve
+// functions take it as their input and output. The handshake happens on the first read or write.
+// PostgreSQL clients ask for TLS in cleartext first, see `accept_postgres`; clients of the other
+// protocols start the handshake right away. Cancel requests of PostgreSQL clients can come in
+// cleartext, they hold nothing but the key.
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+
+use crate::engine::DbError;
+use crate::pgwire::{cancel_key, CANCEL_REQUEST, SSL_REQUEST};
+use crate::protocol::Server;
+
+pub type TlsConfig = Arc<ServerConfig>;
+
+// `cert` holds the server's certificate, then those of the CAs between it and the root
+pub fn load_config(cert: &str, key: &str) -> Result<TlsConfig, DbError> {
+    let certs = CertificateDer::pem_file_iter(cert).and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .map_err(|err| DbError::InputError(format!("Failed to read certificates from {cert}: {err}")))?;
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|err| DbError::InputError(format!("Failed to read a private key from {key}: {err}")))?;
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|err| DbError::InputError(format!("Bad certificate or key: {err}")))?;
+    Ok(Arc::new(config))
+}
+
+pub struct TlsStream {
+    stream: Mutex<StreamOwned<ServerConnection, TcpStream>>,
+}
+
+impl TlsStream {
+
+    pub fn accept(config: &TlsConfig, conn: TcpStream) -> io::Result<TlsStream> {
+        let tls = ServerConnection::new(config.clone()).map_err(io::Error::other)?;
+        Ok(TlsStream { stream: Mutex::new(StreamOwned::new(tls, conn)) })
+    }
+
+    // Answers the client's SSLRequest, which must be the first thing it sends. Clients that don't
+    // ask for TLS are refused, as their startup message would be in cleartext. None if the client
+    // sent a CancelRequest instead, which is carried out.
+    pub fn accept_postgres(config: &TlsConfig, server: &Server, mut conn: TcpStream) -> io::Result<Option<TlsStream>> {
+        let mut request = [0u8; 8];
+        conn.read_exact(&mut request)?;
+        if request == [16i32.to_be_bytes(), CANCEL_REQUEST.to_be_bytes()].concat()[..] {
+            let mut key = [0u8; 8];
+            conn.read_exact(&mut key)?;
+            server.cancel(cancel_key(&key).unwrap());
+            return Ok(None);
+        }
+        if request != [8i32.to_be_bytes(), SSL_REQUEST.to_be_bytes()].concat()[..] {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Client didn't ask for TLS"));
+        }
+        conn.write_all(b"S")?;
+        TlsStream::accept(config, conn).map(Some)
+    }
+}
+
+impl Read for &TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.lock().unwrap_or_else(PoisonError::into_inner).read(buf)
+    }
+}
+
+impl Write for &TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.lock().unwrap_or_else(PoisonError::into_inner).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.lock().unwrap_or_else(PoisonError::into_inner).flush()
+    }
+}
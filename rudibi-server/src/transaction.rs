@@ -0,0 +1,240 @@
+// Writes grouped so they're kept or undone together, with named savepoints to undo only the
+// writes made since. Each write is applied to the storage right away and recorded with what's
+// needed to undo it; storages are committed once, when the transaction is.
+// Undone deletes and updates store the old rows again under new ids, and rows evicted by a
+// quota to make room for an insert aren't brought back.
+// What other threads see of the writes before they're committed, and what the transaction sees
+// of theirs, depends on its `IsolationLevel`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::engine::{Database, DbError, QueryOptions, ResultSet, Row, TableData, TableRead};
+use crate::group_commit::PendingSync;
+use crate::locking::{LockMode, TableLock};
+use crate::query::{Bool, Value};
+use crate::storage::RowId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IsolationLevel {
+    // Tables are locked one statement at a time. Others see the writes before they're committed,
+    // and may see them undone.
+    ReadUncommitted,
+    // Tables written are locked until the transaction ends, others see the writes once they're
+    // committed. Each select sees what's committed when it runs.
+    #[default]
+    ReadCommitted,
+    // As `ReadCommitted`, and each table is read as it was when the transaction first read it.
+    // Writing a table others changed since fails with `DbError::WriteConflict`. Tables whose
+    // storage can't take snapshots are locked from the first read instead.
+    Snapshot,
+}
+
+enum Undo {
+    Stored { table: String, ids: Vec<RowId> },
+    Deleted { table: String, ids: Vec<RowId>, rows: Vec<Row> },
+    Updated { table: String, old_ids: Vec<RowId>, new_ids: Vec<RowId>, old_rows: Vec<Row> },
+}
+
+pub struct Transaction<'db> {
+    db: &'db Database,
+    isolation: IsolationLevel,
+    // Tables locked until the transaction ends
+    locks: HashMap<String, TableLock<'db>>,
+    // Tables read by a snapshot transaction and not locked since
+    snapshots: HashMap<String, TableData>,
+    undo: Vec<Undo>,
+    // Name and undo log length of each savepoint, oldest first
+    savepoints: Vec<(String, usize)>,
+    // Ids of rows stored again by an undo, keyed by the ids they had before
+    moved: HashMap<(String, RowId), RowId>,
+    touched: HashSet<String>,
+    done: bool,
+}
+
+impl<'db> Transaction<'db> {
+
+    pub(crate) fn new(db: &'db Database, isolation: IsolationLevel) -> Self {
+        Transaction {
+            db, isolation, locks: HashMap::new(), snapshots: HashMap::new(),
+            undo: Vec::new(), savepoints: Vec::new(), moved: HashMap::new(), touched: HashSet::new(), done: false,
+        }
+    }
+
+    pub fn isolation(&self) -> IsolationLevel {
+        self.isolation
+    }
+
+    pub fn database(&self) -> &'db Database {
+        self.db
+    }
+
+    pub fn insert(&mut self, table_name: &str, columns: &[&str], what: &[Row]) -> Result<usize, DbError> {
+        let ids = self.write(table_name, |db, data| db.insert_into(table_name, data, columns, what))?;
+        let stored = ids.len();
+        self.record(table_name, Undo::Stored { table: table_name.to_string(), ids });
+        Ok(stored)
+    }
+
+    pub fn delete(&mut self, table_name: &str, filter: &Bool) -> Result<usize, DbError> {
+        let (ids, rows) = self.write(table_name, |db, data| db.take_matching(table_name, data, filter))?;
+        let deleted = ids.len();
+        self.record(table_name, Undo::Deleted { table: table_name.to_string(), ids, rows });
+        Ok(deleted)
+    }
+
+    pub fn update(&mut self, table_name: &str, columns: &[&str], values: &Row, filter: &Bool) -> Result<usize, DbError> {
+        let updated = self.write(table_name, |db, data| db.update_in(table_name, data, columns, values, filter))?;
+        let count = updated.new_ids.len();
+        self.record(table_name, Undo::Updated { table: table_name.to_string(), old_ids: updated.old_ids, new_ids: updated.new_ids, old_rows: updated.old_rows });
+        Ok(count)
+    }
+
+    // Sees the writes made so far in the transaction
+    pub fn select(&mut self, values: &[Value], table_name: &str, filter: &Bool) -> Result<ResultSet, DbError> {
+        if self.isolation == IsolationLevel::Snapshot && self.held(table_name).is_none() {
+            match self.db.snapshot_table(table_name)? {
+                Some(snapshot) => { self.snapshots.insert(table_name.to_string(), snapshot); },
+                None => { self.lock(table_name)?; },
+            }
+        }
+        match self.held(table_name) {
+            Some(data) => self.db.select_from(values, table_name, TableRead::Held(data), filter, &QueryOptions::default()),
+            None => self.db.select(values, table_name, filter),
+        }
+    }
+
+    // Marks the current point, a name used again refers to its latest savepoint
+    pub fn savepoint(&mut self, name: &str) {
+        self.savepoints.push((name.to_string(), self.undo.len()));
+    }
+
+    // Undoes the writes made since the savepoint, which stays so it can be rolled back to again.
+    // Savepoints set after it are released.
+    pub fn rollback_to(&mut self, name: &str) -> Result<(), DbError> {
+        let position = self.find_savepoint(name)?;
+        let mark = self.savepoints[position].1;
+        self.savepoints.truncate(position + 1);
+        self.undo_to(mark)
+    }
+
+    // Forgets the savepoint and the ones set after it, keeping their writes
+    pub fn release(&mut self, name: &str) -> Result<(), DbError> {
+        let position = self.find_savepoint(name)?;
+        self.savepoints.truncate(position);
+        Ok(())
+    }
+
+    pub fn commit(mut self) -> Result<(), DbError> {
+        self.done = true;
+        self.commit_touched()
+    }
+
+    pub fn rollback(mut self) -> Result<(), DbError> {
+        self.done = true;
+        self.undo_to(0)?;
+        self.commit_touched()
+    }
+
+    fn record(&mut self, table_name: &str, undo: Undo) {
+        self.touched.insert(table_name.to_string());
+        self.undo.push(undo);
+    }
+
+    fn find_savepoint(&self, name: &str) -> Result<usize, DbError> {
+        self.savepoints.iter().rposition(|(savepoint, _)| savepoint == name)
+            .ok_or_else(|| DbError::SavepointNotFound(name.to_string()))
+    }
+
+    // What the transaction reads the table from rather than the database
+    fn held(&self, table_name: &str) -> Option<&TableData> {
+        self.locks.get(table_name).map(TableLock::data).or_else(|| self.snapshots.get(table_name))
+    }
+
+    fn lock(&mut self, table_name: &str) -> Result<&mut TableLock<'db>, DbError> {
+        if !self.locks.contains_key(table_name) {
+            let lock = self.db.lock_table(table_name, LockMode::Exclusive)?;
+            // Writes made by others since would be lost to what the transaction read
+            if self.snapshots.get(table_name).is_some_and(|snapshot| snapshot.changes != lock.data().changes) {
+                return Err(DbError::WriteConflict(table_name.to_string()));
+            }
+            self.snapshots.remove(table_name);
+            self.locks.insert(table_name.to_string(), lock);
+        }
+        Ok(self.locks.get_mut(table_name).unwrap())
+    }
+
+    fn write<R>(&mut self, table_name: &str, write: impl FnOnce(&'db Database, &mut TableData) -> Result<R, DbError>) -> Result<R, DbError> {
+        if self.isolation == IsolationLevel::ReadUncommitted {
+            return write(self.db, &mut *self.db.write_table(table_name)?);
+        }
+        let (db, _, data) = self.lock(table_name)?.writable()?;
+        write(db, data)
+    }
+
+    fn commit_touched(&mut self) -> Result<(), DbError> {
+        let mut pending = Vec::new();
+        for table_name in &self.touched {
+            match self.locks.get_mut(table_name) {
+                Some(lock) => pending.push((table_name, lock.writable()?.2.storage.commit_grouped()?)),
+                None => self.db.commit_storage(table_name)?,
+            }
+        }
+        // Synced once the tables are unlocked, as by `Database::commit_storage`
+        self.locks.clear();
+        self.snapshots.clear();
+        for (table_name, sync) in pending {
+            self.db.notify_commit(table_name);
+            sync.map_or(Ok(()), PendingSync::wait)?;
+        }
+        Ok(())
+    }
+
+    // Current id of a row recorded under `row_id`, which an earlier undo may have moved
+    fn resolve(&self, table_name: &str, mut row_id: RowId) -> RowId {
+        while let Some(moved) = self.moved.get(&(table_name.to_string(), row_id)) {
+            row_id = *moved;
+        }
+        row_id
+    }
+
+    fn remove(&mut self, table_name: &str, ids: &[RowId]) -> Result<(), DbError> {
+        let mut current: Vec<RowId> = ids.iter().map(|id| self.resolve(table_name, *id)).collect();
+        current.sort_unstable();
+        self.write(table_name, |db, data| {
+            db.remove_ids(table_name, data, current);
+            Ok(())
+        })
+    }
+
+    fn restore(&mut self, table_name: &str, ids: &[RowId], rows: &[Row]) -> Result<(), DbError> {
+        let restored = self.write(table_name, |db, data| db.restore_rows(table_name, data, rows))?;
+        for (old, new) in ids.iter().zip(restored) {
+            self.moved.insert((table_name.to_string(), *old), new);
+        }
+        Ok(())
+    }
+
+    fn undo_to(&mut self, mark: usize) -> Result<(), DbError> {
+        while self.undo.len() > mark {
+            match self.undo.pop().unwrap() {
+                Undo::Stored { table, ids } => self.remove(&table, &ids)?,
+                Undo::Deleted { table, ids, rows } => self.restore(&table, &ids, &rows)?,
+                Undo::Updated { table, old_ids, new_ids, old_rows } => {
+                    self.remove(&table, &new_ids)?;
+                    self.restore(&table, &old_ids, &old_rows)?;
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+// A transaction neither committed nor rolled back is rolled back
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.undo_to(0);
+            let _ = self.commit_touched();
+        }
+    }
+}
@@ -0,0 +1,268 @@
+// Write-ahead log of row changes, for point-in-time recovery from a base backup
+// `WalWriter` observes a database and appends a record for every store and delete, with a
+// sequence number and the time of the change. A backup of the table files taken together with
+// `WalWriter::last_sequence` can then be rolled forward to any later point with
+// `Database::recover_from_wal`. Schema changes aren't logged, the backup must have every table.
+// `Database::checkpoint` drops the records the table files already have durably, so the log
+// doesn't grow forever. Backups taken before the latest checkpoint can't be rolled forward.
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::dtype::{decode_field, encode_field, take, take_u64};
+use crate::engine::{DbError, Row};
+use crate::group_commit::GroupCommit;
+use crate::hash::stable_hash;
+use crate::storage::{RowId, StorageObserver};
+
+const WAL_MAGIC: &[u8; 4] = b"RDBW";
+const WAL_VERSION: u32 = 1;
+const STORE: u8 = 1;
+const DELETE: u8 = 2;
+const CHECKPOINT: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalChange {
+    // Rows in schema column order
+    Store { row_ids: Vec<RowId>, rows: Vec<Row> },
+    Delete { row_ids: Vec<RowId> },
+    // Rows replaced in place, keeping their ids, in schema column order. Only change feeds keep
+    // them, the log has a delete and a store instead, see `replication`.
+    Update { row_ids: Vec<RowId>, rows: Vec<Row> },
+    // Records up to this one were dropped from the log, see `Database::checkpoint`
+    Checkpoint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalRecord {
+    pub sequence: u64,
+    pub time: SystemTime,
+    pub table: String,
+    pub change: WalChange,
+}
+
+// Last change to replay, inclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecoveryTarget {
+    Sequence(u64),
+    Time(SystemTime),
+    // Everything in the log
+    End,
+}
+
+impl RecoveryTarget {
+    pub(crate) fn includes(&self, record: &WalRecord) -> bool {
+        match self {
+            RecoveryTarget::Sequence(sequence) => record.sequence <= *sequence,
+            RecoveryTarget::Time(time) => record.time <= *time,
+            RecoveryTarget::End => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WalReplay {
+    pub records_applied: usize,
+    // Sequence of the last applied record, or the base backup's if none was
+    pub last_sequence: u64,
+}
+
+fn wal_err(path: &str, err: std::io::Error) -> DbError {
+    DbError::StorageError(format!("Failed to access WAL {path}: {err}"))
+}
+
+pub struct WalWriter {
+    path: String,
+    // Held while a record is appended, so records of concurrent writes don't interleave
+    file: Mutex<File>,
+    last_sequence: AtomicU64,
+    // Records are synced when a write commits, once for all writes that commit meanwhile
+    group: Arc<GroupCommit>,
+}
+
+impl std::fmt::Debug for WalWriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WalWriter").field("path", &self.path).field("last_sequence", &self.last_sequence()).finish()
+    }
+}
+
+impl WalWriter {
+
+    // Appends to the log at `path`, or starts one. Sequence numbers continue from the last record.
+    pub fn open(path: &str) -> Result<WalWriter, DbError> {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path).map_err(|err| wal_err(path, err))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes).map_err(|err| wal_err(path, err))?;
+        let (records, valid_len) = if bytes.is_empty() {
+            file.write_all(WAL_MAGIC).and_then(|_| file.write_all(&WAL_VERSION.to_le_bytes())).map_err(|err| wal_err(path, err))?;
+            (Vec::new(), WAL_MAGIC.len() + 4)
+        } else {
+            decode_wal(path, &bytes)?
+        };
+        // A record torn by a crash is cut off, so new records follow the last complete one
+        file.set_len(valid_len as u64).map_err(|err| wal_err(path, err))?;
+        let last_sequence = records.last().map_or(0, |record| record.sequence);
+        Ok(WalWriter {
+            path: path.to_string(),
+            file: Mutex::new(file),
+            last_sequence: AtomicU64::new(last_sequence),
+            group: Arc::new(GroupCommit::new()),
+        })
+    }
+
+    // Sequence of the last logged change, 0 if none. A base backup is taken at this point.
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence.load(Ordering::Acquire)
+    }
+
+    fn append(&self, table: &str, kind: u8, body: impl FnOnce(&mut Vec<u8>)) {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let sequence = self.last_sequence.load(Ordering::Acquire) + 1;
+        let framed = frame(sequence, table, kind, body);
+        // TODO: Storage error handling, observers can't fail the change they see
+        file.write_all(&framed).unwrap_or_else(|err| panic!("{:?}", wal_err(&self.path, err)));
+        self.last_sequence.store(sequence, Ordering::Release);
+    }
+
+    // Replaces the records up to `sequence` with a checkpoint record, so numbering continues
+    // from the same point after the log is opened again. Records after it are kept.
+    pub(crate) fn checkpoint(&self, sequence: u64) -> Result<(), DbError> {
+        let path = &self.path;
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        let bytes = std::fs::read(path).map_err(|err| wal_err(path, err))?;
+        let header_len = WAL_MAGIC.len() + 4;
+        let mut rest = &bytes[header_len..];
+        let mut kept_from = header_len;
+        while let Some(record) = decode_framed(&mut rest) && record.sequence <= sequence {
+            kept_from = bytes.len() - rest.len();
+        }
+
+        // Written aside and renamed over the log, a crash leaves either log whole
+        let mut log = bytes[..header_len].to_vec();
+        log.extend(frame(sequence, "", CHECKPOINT, |out| out.extend_from_slice(&0u64.to_le_bytes())));
+        log.extend_from_slice(&bytes[kept_from..]);
+        let rewritten = format!("{path}.checkpoint");
+        std::fs::write(&rewritten, &log)
+            .and_then(|_| File::open(&rewritten)?.sync_all())
+            .and_then(|_| std::fs::rename(&rewritten, path))
+            .map_err(|err| wal_err(path, err))?;
+        *file = OpenOptions::new().read(true).append(true).open(path).map_err(|err| wal_err(path, err))?;
+        Ok(())
+    }
+}
+
+fn frame(sequence: u64, table: &str, kind: u8, body: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+    let micros = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64;
+    let mut record = Vec::new();
+    record.extend_from_slice(&sequence.to_le_bytes());
+    record.extend_from_slice(&micros.to_le_bytes());
+    record.push(kind);
+    encode_field(&mut record, table.as_bytes());
+    body(&mut record);
+
+    let mut framed = Vec::with_capacity(record.len() + 16);
+    framed.extend_from_slice(&(record.len() as u64).to_le_bytes());
+    framed.extend_from_slice(&stable_hash(&record).to_le_bytes());
+    framed.extend_from_slice(&record);
+    framed
+}
+
+impl StorageObserver for WalWriter {
+    fn on_store(&self, table: &str, row_ids: &[RowId], rows: &[Row], column_mapping: &[usize]) {
+        self.append(table, STORE, |out| {
+            out.extend_from_slice(&(rows.len() as u64).to_le_bytes());
+            for (row_id, row) in row_ids.iter().zip(rows) {
+                out.extend_from_slice(&row_id.to_le_bytes());
+                out.extend_from_slice(&(column_mapping.len() as u64).to_le_bytes());
+                for col_idx in column_mapping {
+                    encode_field(out, row.get_column(*col_idx));
+                }
+            }
+        });
+    }
+
+    // Makes the records of the commit durable, ahead of the table's own sync
+    fn on_commit(&self, _table: &str) {
+        let path = self.path.clone();
+        // A handle of its own, appends go on while it syncs. Taken under the lock, a checkpoint
+        // can replace the file.
+        let file = self.file.lock().unwrap_or_else(PoisonError::into_inner).try_clone();
+        // TODO: Storage error handling, observers can't fail the change they see
+        self.group.commit(move || file.and_then(|file| file.sync_data()).map_err(|err| wal_err(&path, err)))
+            .wait().unwrap_or_else(|err| panic!("{err:?}"));
+    }
+
+    fn on_delete(&self, table: &str, row_ids: &[RowId]) {
+        if row_ids.is_empty() {
+            return;
+        }
+        self.append(table, DELETE, |out| {
+            out.extend_from_slice(&(row_ids.len() as u64).to_le_bytes());
+            for row_id in row_ids {
+                out.extend_from_slice(&row_id.to_le_bytes());
+            }
+        });
+    }
+}
+
+// Records of the log at `path`, up to the first incomplete or damaged one
+pub fn read_wal(path: &str) -> Result<Vec<WalRecord>, DbError> {
+    let bytes = std::fs::read(path).map_err(|err| wal_err(path, err))?;
+    decode_wal(path, &bytes).map(|(records, _)| records)
+}
+
+// Records and the length of the log they take up
+fn decode_wal(path: &str, bytes: &[u8]) -> Result<(Vec<WalRecord>, usize), DbError> {
+    let mut rest = bytes;
+    if take(&mut rest, 4) != Some(WAL_MAGIC) {
+        return Err(DbError::DatabaseIntegrityError(format!("{path} is not a WAL")));
+    }
+    let version = take(&mut rest, 4).map(|v| u32::from_le_bytes(v.try_into().unwrap()));
+    if version != Some(WAL_VERSION) {
+        return Err(DbError::UnsupportedFormatVersion { found: version.unwrap_or(0), supported: WAL_VERSION });
+    }
+    let mut records = Vec::new();
+    let mut valid_len = bytes.len() - rest.len();
+    while let Some(record) = decode_framed(&mut rest) {
+        records.push(record);
+        valid_len = bytes.len() - rest.len();
+    }
+    Ok((records, valid_len))
+}
+
+fn decode_framed(rest: &mut &[u8]) -> Option<WalRecord> {
+    let len = usize::try_from(take_u64(rest)?).ok()?;
+    let checksum = take_u64(rest)?;
+    let record = take(rest, len)?;
+    if stable_hash(record) != checksum {
+        return None;
+    }
+    decode_record(record)
+}
+
+fn decode_record(mut bytes: &[u8]) -> Option<WalRecord> {
+    let sequence = take_u64(&mut bytes)?;
+    let time = UNIX_EPOCH + Duration::from_micros(take_u64(&mut bytes)?);
+    let kind = take(&mut bytes, 1)?[0];
+    let table = String::from_utf8(decode_field(&mut bytes)?.to_vec()).ok()?;
+    let count = take_u64(&mut bytes)?;
+    let change = match kind {
+        STORE => {
+            let mut row_ids = Vec::new();
+            let mut rows = Vec::new();
+            for _ in 0..count {
+                row_ids.push(take_u64(&mut bytes)?);
+                let columns = (0..take_u64(&mut bytes)?).map(|_| decode_field(&mut bytes)).collect::<Option<Vec<_>>>()?;
+                rows.push(Row::of_columns(&columns));
+            }
+            WalChange::Store { row_ids, rows }
+        },
+        DELETE => WalChange::Delete { row_ids: (0..count).map(|_| take_u64(&mut bytes)).collect::<Option<Vec<_>>>()? },
+        CHECKPOINT => WalChange::Checkpoint,
+        _ => return None,
+    };
+    bytes.is_empty().then_some(WalRecord { sequence, time, table, change })
+}
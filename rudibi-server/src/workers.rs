@@ -0,0 +1,71 @@
+// A fixed number of threads handling the jobs submitted to a pool, such as a server's connections.
+// Submitting waits while every thread is busy and `backlog` jobs are already queued, so at most
+// `workers` jobs run at once, or fails with `try_submit`. A job that panics doesn't take its
+// thread down.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+
+pub struct WorkerPool<T> {
+    sender: Option<SyncSender<T>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> WorkerPool<T> {
+
+    pub fn new(workers: usize, backlog: usize, handle: impl Fn(T) + Send + Sync + 'static) -> WorkerPool<T> {
+        assert!(workers > 0, "A worker pool needs a worker");
+        let (sender, receiver) = sync_channel(backlog);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let handle = Arc::new(handle);
+        let threads = (0..workers).map(|idx| {
+            let receiver = receiver.clone();
+            let handle = handle.clone();
+            thread::Builder::new()
+                .name(format!("worker-{idx}"))
+                .spawn(move || work(&receiver, &*handle))
+                .expect("Failed to spawn a worker thread")
+        }).collect();
+        WorkerPool { sender: Some(sender), threads }
+    }
+
+    pub fn submit(&self, job: T) {
+        // Workers only stop once the sender is dropped
+        let _ = self.sender.as_ref().unwrap().send(job);
+    }
+
+    // The job back if every thread is busy and `backlog` jobs are already queued
+    pub fn try_submit(&self, job: T) -> Result<(), T> {
+        match self.sender.as_ref().unwrap().try_send(job) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(job) | TrySendError::Disconnected(job)) => Err(job),
+        }
+    }
+
+    // Waits for the jobs submitted to be handled
+    pub fn join(self) {}
+}
+
+impl<T> Drop for WorkerPool<T> {
+    fn drop(&mut self) {
+        self.sender = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn work<T>(receiver: &Mutex<Receiver<T>>, handle: &dyn Fn(T)) {
+    loop {
+        let job = receiver.lock().unwrap_or_else(PoisonError::into_inner).recv();
+        match job {
+            Ok(job) => {
+                // The panic is reported by the panic hook
+                let _ = catch_unwind(AssertUnwindSafe(|| handle(job)));
+            },
+            Err(_) => return,
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::io::Cursor;
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::auth::{hash_token, Credentials, Session};
+use rudibi_server::engine::{DbError, ResultSet, StorageCfg};
+use rudibi_server::locking::LockMode;
+use rudibi_server::permissions::{Permissions, Privilege, ALL_TABLES};
+use rudibi_server::protocol::{Reply, Server};
+use rudibi_server::testlib::{fruits_server, fruits_table, random_temp_file};
+
+fn rows(reply: Result<Reply, DbError>) -> ResultSet {
+    match reply.unwrap() {
+        Reply::Selected(results) => results,
+        other => panic!("Expected rows, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_status() {
+    // GIVEN two connections open and one closed
+    let server = fruits_server();
+    let first = server.open_session();
+    let _second = server.open_session();
+    drop(server.open_session());
+
+    // WHEN
+    let status = rows(server.handle_as(&first, "STATUS"));
+
+    // THEN the STATUS itself is running
+    assert_eq!(status.schema.iter().map(|col| col.name.as_str()).collect::<Vec<_>>(), ["uptime_seconds", "connections", "running"]);
+    assert_eq!(status.len(), 1);
+    assert!(status.get::<f64>(0, "uptime_seconds").unwrap() >= 0.0);
+    assert_eq!(status.get::<u32>(0, "connections").unwrap(), 2);
+    assert_eq!(status.get::<u32>(0, "running").unwrap(), 1);
+    assert_eq!(rows(server.handle_as(&first, "STATUS")).get::<u32>(0, "running").unwrap(), 1);
+}
+
+#[test]
+fn test_stats_lists_running_queries() {
+    // GIVEN a delete waiting on a lock
+    let mut credentials = Credentials::default();
+    credentials.add_token("loader", &hash_token("loader-token")).unwrap();
+    let server = fruits_server().with_credentials(credentials);
+    let mut loader = server.open_session();
+    server.authenticate(&mut loader, None, "loader-token").unwrap();
+    let mut admin = server.open_session();
+    server.authenticate(&mut admin, None, "loader-token").unwrap();
+
+    let (during, deleted) = thread::scope(|scope| {
+        let db = server.database();
+        let lock = db.lock_table("Fruits", LockMode::Exclusive).unwrap();
+        let deleting = scope.spawn(|| server.handle_as(&loader, "DELETE FROM Fruits WHERE id = 100"));
+        thread::sleep(Duration::from_millis(100));
+
+        // WHEN
+        let during = rows(server.handle_as(&admin, "stats"));
+        drop(lock);
+        (during, deleting.join().unwrap())
+    });
+    let after = rows(server.handle_as(&admin, "STATS"));
+
+    // THEN
+    assert_eq!(during.len(), 2);
+    assert_eq!(during.get::<u32>(0, "connection").unwrap(), loader.cancel_key().unwrap().id);
+    assert_eq!(during.get::<&str>(0, "user").unwrap(), "loader");
+    assert_eq!(during.get::<&str>(0, "query").unwrap(), "DELETE FROM Fruits WHERE id = 100");
+    assert!(during.get::<f64>(0, "seconds").unwrap() >= 0.05);
+    assert_eq!(during.get::<&str>(1, "query").unwrap(), "stats");
+    assert_eq!(deleted.unwrap().to_string(), "DELETED 1");
+    assert_eq!(after.len(), 1);
+    assert_eq!(after.get::<&str>(0, "query").unwrap(), "STATS");
+}
+
+#[test]
+fn test_list_tables() {
+    // GIVEN a table on disk and one in memory
+    let path = random_temp_file();
+    let server = Server::new(fruits_table(StorageCfg::disk(&path)), StorageCfg::InMemory);
+    server.handle("CREATE TABLE Stock (id U32)").unwrap();
+    server.handle("INSERT INTO Stock (id) VALUES (1), (2)").unwrap();
+
+    // WHEN
+    let tables = rows(server.handle("LIST TABLES"));
+
+    // THEN
+    assert_eq!(tables.len(), 2);
+    assert_eq!(tables.get::<&str>(0, "name").unwrap(), "Fruits");
+    assert_eq!(tables.get::<u32>(0, "rows").unwrap(), 4);
+    assert_eq!(tables.get::<f64>(0, "disk_bytes").unwrap(), std::fs::metadata(&path).unwrap().len() as f64);
+    assert_eq!(tables.get::<&str>(1, "name").unwrap(), "Stock");
+    assert_eq!(tables.get::<u32>(1, "rows").unwrap(), 2);
+    assert_eq!(tables.get::<f64>(1, "disk_bytes").unwrap(), 0.0);
+    drop(server);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_admin_needs_the_privilege() {
+    // GIVEN
+    let mut credentials = Credentials::default();
+    let mut permissions = Permissions::default();
+    for user in ["analyst", "operator"] {
+        credentials.add_token(user, &hash_token(&format!("{user}-token"))).unwrap();
+    }
+    permissions.grant("analytics", Privilege::Read, ALL_TABLES);
+    permissions.grant("operations", Privilege::Admin, ALL_TABLES);
+    permissions.assign("analyst", "analytics");
+    permissions.assign("operator", "operations");
+    let server = fruits_server().with_credentials(credentials).with_permissions(permissions);
+    let login = |user: &str| {
+        let mut session = Session::default();
+        server.authenticate(&mut session, None, &format!("{user}-token")).unwrap();
+        session
+    };
+
+    // WHEN
+    let analyst = server.handle_as(&login("analyst"), "LIST TABLES");
+    let operator = server.handle_as(&login("operator"), "LIST TABLES");
+
+    // THEN
+    assert_eq!(analyst.unwrap_err(), DbError::PermissionDenied { user: "analyst".to_string(), privilege: Privilege::Admin, table: ALL_TABLES.to_string() });
+    assert_eq!(rows(operator).len(), 1);
+}
+
+#[test]
+fn test_text_mode() {
+    // GIVEN
+    let server = fruits_server();
+    let input = "SET FORMAT CSV; LIST TABLES; STATS; LIST FRUITS\n";
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve_text(Cursor::new(input), &mut output).unwrap();
+
+    // THEN
+    let output = String::from_utf8(output).unwrap();
+    let replies: Vec<&str> = output.split("\n\n").collect();
+    assert_eq!(replies[1], "name,rows,disk_bytes\nFruits,4,0");
+    assert!(replies[2].starts_with("connection,user,seconds,query\n"), "{}", replies[2]);
+    assert!(replies[2].ends_with(",STATS"), "{}", replies[2]);
+    assert_eq!(replies[3], "ERROR InputError(\"Expected TABLES, got FRUITS\")");
+}
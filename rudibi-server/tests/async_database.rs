@@ -0,0 +1,85 @@
+#![cfg(feature = "async")]
+
+use rudibi_server::asynchronous::AsyncDatabase;
+use rudibi_server::cancel::CancellationToken;
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, QueryOptions, Row, StorageCfg};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{check_equality, fruits_table, with_tmp};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+}
+
+fn test_async_database(storage: StorageCfg) {
+    // GIVEN
+    let db = AsyncDatabase::new(fruits_table(storage));
+
+    // WHEN
+    let results = block_on(async {
+        db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]].to_vec()).await.unwrap();
+        db.update("Fruits", &["name"], Row::of_columns(&[b"grape"]), Eq(ColumnRef("id"), Const(U32(100)))).await.unwrap();
+        db.delete("Fruits", Eq(ColumnRef("name"), Const(UTF8("banana")))).await.unwrap();
+        db.select(vec![ColumnRef("id"), ColumnRef("name")], "Fruits", True).await.unwrap()
+    });
+
+    // THEN
+    check_equality(&results, &[
+        [U32(400), UTF8("cherry")],
+        [U32(500), UTF8("kiwi")],
+        [U32(100), UTF8("grape")],
+    ]);
+}
+
+#[test]
+fn test_async_database_in_mem() {
+    test_async_database(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_async_database_on_disk() {
+    with_tmp(test_async_database);
+}
+
+#[test]
+fn test_async_database_errors_and_run() {
+    // GIVEN
+    let db = AsyncDatabase::new(fruits_table(StorageCfg::InMemory));
+
+    // WHEN
+    let (missing, count) = block_on(async {
+        let missing = db.select(vec![ColumnRef("id")], "Vegetables", True).await;
+        let count = db.run(|db| db.count("Fruits", &True)).await;
+        (missing, count)
+    });
+
+    // THEN
+    assert_eq!(missing.err(), Some(DbError::TableNotFound("Vegetables".to_string())));
+    assert_eq!(count, Ok(4));
+    assert_eq!(db.database().count("Fruits", &True).unwrap(), 4);
+}
+
+fn test_cancelled_async_select(storage: StorageCfg) {
+    // GIVEN
+    let db = AsyncDatabase::new(fruits_table(storage));
+    let token = CancellationToken::new();
+    token.cancel();
+
+    // WHEN
+    let options = QueryOptions { cancel: Some(token), ..Default::default() };
+    let result = block_on(db.select_with_options(vec![ColumnRef("id")], "Fruits", True, options));
+
+    // THEN
+    assert_eq!(result.err(), Some(DbError::QueryCancelled));
+}
+
+#[test]
+fn test_cancelled_async_select_in_mem() {
+    test_cancelled_async_select(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_cancelled_async_select_on_disk() {
+    with_tmp(test_cancelled_async_select);
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "async")]
+
+use std::sync::Arc;
+use std::thread;
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{QueryOptions, Row, StorageCfg};
+use rudibi_server::index::IndexKind;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, fruits_table, random_temp_file};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(future)
+}
+
+fn test_select_async(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+    let many: Vec<Row> = (1000..3000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+    db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(300)))).unwrap();
+    let filter = Eq(ColumnRef("name"), Const(UTF8("banana")));
+
+    // WHEN
+    let awaited = block_on(db.select_async(&[ColumnRef("id")], "Fruits", &filter, &QueryOptions::default())).unwrap();
+    let kiwis = block_on(db.select_async(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("kiwi"))), &QueryOptions::default())).unwrap();
+
+    // THEN
+    check_equality(&awaited, &[[U32(200)]]);
+    assert_eq!(awaited.scan_stats.rows_scanned, 2003);
+    assert_eq!(kiwis.data, db.select(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("kiwi")))).unwrap().data);
+    assert_eq!(kiwis.len(), 2000);
+}
+
+#[test]
+fn test_select_async_in_mem() {
+    test_select_async(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_select_async_on_disk() {
+    let path = random_temp_file();
+    test_select_async(StorageCfg::disk(&path));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_select_async_through_index() {
+    // GIVEN
+    let path = random_temp_file();
+    let mut db = fruits_table(StorageCfg::disk(&path));
+    db.create_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    let results = block_on(db.select_async(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(400))), &QueryOptions::default())).unwrap();
+
+    // THEN only the indexed row is read
+    check_equality(&results, &[[UTF8("cherry")]]);
+    assert_eq!(results.scan_stats.rows_scanned, 1);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_select_async_alongside_writes() {
+    // GIVEN
+    let path = random_temp_file();
+    let db = fruits_table(StorageCfg::disk(&path));
+    let many: Vec<Row> = (1000..4000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+    let db = Arc::new(db);
+    let kiwis = Eq(ColumnRef("name"), Const(UTF8("kiwi")));
+
+    // WHEN another thread writes while the scan awaits its reads
+    let writer = {
+        let db = db.clone();
+        thread::spawn(move || {
+            for id in 4000..4300u32 {
+                db.insert("Fruits", &["id", "name"], &[Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])]).unwrap();
+            }
+        })
+    };
+    let options = QueryOptions::default();
+    let future = db.select_async(&[ColumnRef("id")], "Fruits", &kiwis, &options);
+    fn assert_send<T: Send>(_: &T) {}
+    assert_send(&future);
+    let results = block_on(future).unwrap();
+    writer.join().unwrap();
+
+    // THEN rows read again after a write aren't returned twice
+    let mut ids: Vec<u32> = results.data.iter().map(|row| u32::from_le_bytes(row.get_column(0).try_into().unwrap())).collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), results.len());
+    assert!((3000..=3300).contains(&ids.len()));
+    assert!((1000..4000).all(|id| ids.binary_search(&id).is_ok()));
+    std::fs::remove_file(path).unwrap();
+}
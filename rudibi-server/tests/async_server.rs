@@ -0,0 +1,144 @@
+#![cfg(feature = "async-server")]
+
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::async_server::{AsyncServer, AsyncServerConfig};
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::tcp::TcpSettings;
+use rudibi_server::testlib::fruits_table;
+
+// Listens on a runtime of two threads, for the rest of the test
+fn listen(config: AsyncServerConfig) -> SocketAddr {
+    let server = AsyncServer::new(Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory), config);
+    let runtime = tokio::runtime::Builder::new_multi_thread().worker_threads(2).enable_all().build().unwrap();
+    let listener = runtime.block_on(tokio::net::TcpListener::bind("127.0.0.1:0")).unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || runtime.block_on(server.listen(listener)));
+    address
+}
+
+fn text_config() -> AsyncServerConfig {
+    AsyncServerConfig { text: true, ..AsyncServerConfig::default() }
+}
+
+fn read_reply(conn: &TcpStream) -> std::io::Result<String> {
+    let mut reply = String::new();
+    BufReader::new(conn).read_line(&mut reply)?;
+    Ok(reply)
+}
+
+#[test]
+fn test_idle_connections_dont_hold_threads() {
+    // GIVEN many more connections than runtime threads, all connected before any sends
+    let address = listen(text_config());
+    let mut conns: Vec<TcpStream> = (0..200).map(|_| TcpStream::connect(address).unwrap()).collect();
+
+    // WHEN
+    for (idx, conn) in conns.iter_mut().enumerate() {
+        writeln!(conn, "INSERT INTO Fruits (id, name) VALUES ({}, 'kiwi')", 1000 + idx).unwrap();
+    }
+
+    // THEN each is answered
+    for conn in &conns {
+        assert_eq!(read_reply(conn).unwrap(), "INSERTED 1\n");
+    }
+    writeln!(conns[0], "SELECT id FROM Fruits WHERE name = 'kiwi'; SET FORMAT CSV; SELECT name FROM Fruits WHERE id = 100").unwrap();
+    let mut reader = BufReader::new(&conns[0]);
+    let replies: Vec<String> = std::iter::repeat_with(|| {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        line
+    }).skip_while(|line| !line.starts_with("(")).take(7).collect();
+    assert_eq!(replies.concat(), "(200 rows)\n\nOK\n\nname\napple\n\n");
+}
+
+#[test]
+fn test_frames() {
+    // GIVEN
+    let address = listen(AsyncServerConfig::default());
+    let mut conn = TcpStream::connect(address).unwrap();
+
+    // WHEN
+    Frame::new(frame::EXECUTE, 4, "DELETE FROM Fruits WHERE id > 200").write_to(&mut conn).unwrap();
+    Frame::new(frame::EXECUTE, 5, "DELETE FROM Vegetables").write_to(&mut conn).unwrap();
+
+    // THEN
+    assert_eq!(Frame::read_from(&mut conn).unwrap(), Some(Frame::new(frame::REPLY, 4, "DELETED 2")));
+    assert_eq!(Frame::read_from(&mut conn).unwrap(), Some(Frame::error(5, &ErrorReply::from(&DbError::TableNotFound("Vegetables".to_string())))));
+}
+
+#[test]
+fn test_pipelined_requests() {
+    // GIVEN
+    let address = listen(AsyncServerConfig::default());
+    let mut conn = TcpStream::connect(address).unwrap();
+    let mut requests = Vec::new();
+    for idx in 0..100 {
+        Frame::new(frame::EXECUTE, idx, format!("INSERT INTO Fruits (id, name) VALUES ({}, 'kiwi')", 1000 + idx)).write_to(&mut requests).unwrap();
+    }
+
+    // WHEN all are sent before any reply is read
+    conn.write_all(&requests).unwrap();
+
+    // THEN
+    for idx in 0..100 {
+        assert_eq!(Frame::read_from(&mut conn).unwrap(), Some(Frame::new(frame::REPLY, idx, "INSERTED 1")));
+    }
+}
+
+#[test]
+fn test_idle_connections_are_closed() {
+    // GIVEN
+    let tcp = TcpSettings { idle_timeout: Some(Duration::from_millis(200)), keepalive: Some(Duration::from_secs(30)) };
+    let address = listen(AsyncServerConfig { tcp, ..text_config() });
+    let mut conn = TcpStream::connect(address).unwrap();
+
+    // WHEN
+    writeln!(conn, "PING").unwrap();
+
+    // THEN the connection is answered, then closed once it sends nothing more
+    let mut replies = String::new();
+    BufReader::new(&conn).read_to_string(&mut replies).unwrap();
+    assert_eq!(replies, "PONG\n\n");
+}
+
+#[test]
+fn test_streamed_select() {
+    // GIVEN
+    let address = listen(AsyncServerConfig::default());
+    let mut conn = TcpStream::connect(address).unwrap();
+
+    // WHEN
+    Frame::new(frame::STREAM, 6, "SELECT id FROM Fruits WHERE id < 300").write_to(&mut conn).unwrap();
+    Frame::new(frame::EXECUTE, 7, "DELETE FROM Fruits WHERE id = 100").write_to(&mut conn).unwrap();
+
+    // THEN
+    assert_eq!(Frame::read_from(&mut conn).unwrap(), Some(Frame::new(frame::ROWS, 6, "id\n100\n200\n")));
+    assert_eq!(Frame::read_from(&mut conn).unwrap(), Some(Frame::new(frame::DONE, 6, "SELECTED 2")));
+    assert_eq!(Frame::read_from(&mut conn).unwrap(), Some(Frame::new(frame::REPLY, 7, "DELETED 1")));
+}
+
+#[test]
+fn test_connections_beyond_the_limit_wait() {
+    // GIVEN a server taking one connection at a time, with one connected
+    let address = listen(AsyncServerConfig { max_connections: 1, ..text_config() });
+    let first = TcpStream::connect(address).unwrap();
+    let mut second = TcpStream::connect(address).unwrap();
+    second.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+
+    // WHEN
+    writeln!(second, "DELETE FROM Fruits WHERE id = 100").unwrap();
+    let waiting = read_reply(&second);
+    drop(first);
+    second.set_read_timeout(None).unwrap();
+
+    // THEN the second is served once the first leaves
+    assert!(matches!(waiting.unwrap_err().kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut));
+    assert_eq!(read_reply(&second).unwrap(), "DELETED 1\n");
+}
@@ -0,0 +1,226 @@
+use std::io::Cursor;
+use std::sync::LazyLock;
+
+use rudibi_server::auth::{hash_password_with, hash_token, Credentials, Session};
+use rudibi_server::config::ServerConfig;
+use rudibi_server::engine::DbError;
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_server;
+use rudibi_server::{pgwire, resp};
+
+// Checking hashes of `auth::PASSWORD_ITERATIONS` takes seconds without optimizations
+fn hash_password(password: &str) -> String {
+    hash_password_with(password, 1000)
+}
+
+// alice logs in with a password, backup with a token
+static CREDENTIALS: LazyLock<Credentials> = LazyLock::new(|| {
+    let mut credentials = Credentials::default();
+    credentials.add_user("alice", &hash_password("wonderland")).unwrap();
+    credentials.add_token("backup", &hash_token("s3cr3t-t0k3n")).unwrap();
+    credentials
+});
+
+fn guarded_server() -> Server {
+    fruits_server().with_credentials(CREDENTIALS.clone())
+}
+
+#[test]
+fn test_check_credentials() {
+    // WHEN
+    let password = CREDENTIALS.check(Some("alice"), "wonderland");
+    let token = CREDENTIALS.check(None, "s3cr3t-t0k3n");
+    let named_token = CREDENTIALS.check(Some("backup"), "s3cr3t-t0k3n");
+    let wrong_password = CREDENTIALS.check(Some("alice"), "looking-glass");
+    let password_alone = CREDENTIALS.check(None, "wonderland");
+    let token_as_alice = CREDENTIALS.check(Some("alice"), "s3cr3t-t0k3n");
+    let unknown_user = CREDENTIALS.check(Some("bob"), "wonderland");
+
+    // THEN
+    assert_eq!(password, Ok("alice".to_string()));
+    assert_eq!(token, Ok("backup".to_string()));
+    assert_eq!(named_token, Ok("backup".to_string()));
+    for failed in [wrong_password, password_alone, token_as_alice, unknown_user] {
+        assert_eq!(failed, Err(DbError::AuthenticationFailed));
+    }
+}
+
+#[test]
+fn test_hashes_are_salted() {
+    // WHEN
+    let first = hash_password("wonderland");
+    let second = hash_password("wonderland");
+
+    // THEN
+    assert!(first.starts_with("pbkdf2-sha256$1000$"), "{first}");
+    assert_ne!(first, second);
+    assert_eq!(hash_token("abc"), "sha256$ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+}
+
+#[test]
+fn test_bad_hashes() {
+    // GIVEN
+    let mut credentials = Credentials::default();
+
+    // WHEN
+    let plain_password = credentials.add_user("alice", "wonderland");
+    let short_hash = credentials.add_user("alice", "pbkdf2-sha256$1000$00ff$abcd");
+    let no_iterations = credentials.add_user("alice", &hash_password("x").replace("$1000$", "$0$"));
+    let plain_token = credentials.add_token("backup", "s3cr3t-t0k3n");
+
+    // THEN
+    assert!(matches!(plain_password, Err(DbError::InputError(msg)) if msg.starts_with("Bad password hash for alice")));
+    assert!(short_hash.is_err());
+    assert!(no_iterations.is_err());
+    assert!(matches!(plain_token, Err(DbError::InputError(msg)) if msg.starts_with("Bad token hash for backup")));
+    assert!(credentials.is_empty());
+}
+
+#[test]
+fn test_commands_need_authentication() {
+    // GIVEN
+    let server = guarded_server();
+    let mut session = Session::default();
+
+    // WHEN
+    let before = server.handle_as(&session, "SELECT name FROM Fruits WHERE id = 100").map(|reply| reply.to_string());
+    let failed = server.authenticate(&mut session, Some("alice"), "looking-glass");
+    let after_failing = server.handle_as(&session, "SELECT name FROM Fruits WHERE id = 100").map(|reply| reply.to_string());
+    server.authenticate(&mut session, Some("alice"), "wonderland").unwrap();
+    let after = server.handle_as(&session, "SELECT name FROM Fruits WHERE id = 100").map(|reply| reply.to_string());
+
+    // THEN
+    assert_eq!(before.unwrap_err(), DbError::NotAuthenticated);
+    assert_eq!(failed, Err(DbError::AuthenticationFailed));
+    assert_eq!(after_failing.unwrap_err(), DbError::NotAuthenticated);
+    assert_eq!(session.user.as_deref(), Some("alice"));
+    assert!(after.unwrap().contains("apple"));
+}
+
+#[test]
+fn test_servers_without_credentials() {
+    // GIVEN
+    let server = fruits_server();
+    let mut session = Session::default();
+
+    // WHEN
+    let selected = server.handle_as(&session, "SELECT name FROM Fruits WHERE id = 100");
+    let authenticated = server.authenticate(&mut session, None, "s3cr3t-t0k3n");
+
+    // THEN anyone runs commands, and there's nothing to authenticate with
+    assert!(selected.is_ok());
+    assert!(matches!(authenticated, Err(DbError::UnsupportedOperation(_))));
+}
+
+#[test]
+fn test_frames() {
+    // GIVEN
+    let server = guarded_server();
+    let mut input = Vec::new();
+    for request in [
+        Frame::new(frame::EXECUTE, 1, "SELECT name FROM Fruits WHERE id = 100"),
+        Frame::new(frame::AUTH, 2, "alice\0looking-glass"),
+        Frame::new(frame::AUTH, 3, "s3cr3t-t0k3n"),
+        Frame::new(frame::EXECUTE, 4, "DELETE FROM Fruits WHERE id = 100"),
+    ] {
+        request.write_to(&mut input).unwrap();
+    }
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+
+    // THEN
+    let mut output = Cursor::new(output);
+    let replies: Vec<Frame> = std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect();
+    assert_eq!(replies, vec![
+        Frame::error(1, &ErrorReply::from(&DbError::NotAuthenticated)),
+        Frame::error(2, &ErrorReply::from(&DbError::AuthenticationFailed)),
+        Frame::new(frame::REPLY, 3, "OK"),
+        Frame::new(frame::REPLY, 4, "DELETED 1"),
+    ]);
+}
+
+#[test]
+fn test_text_mode() {
+    // GIVEN
+    let server = guarded_server();
+    let input = "DELETE FROM Fruits WHERE id = 100\nAUTH alice wonderland; DELETE FROM Fruits WHERE id = 100\n";
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve_text(Cursor::new(input), &mut output).unwrap();
+
+    // THEN
+    assert_eq!(String::from_utf8(output).unwrap(), "ERROR NotAuthenticated\n\nOK\n\nDELETED 1\n\n");
+}
+
+#[test]
+fn test_resp() {
+    // GIVEN
+    let server = guarded_server();
+    let input = "SET a 1\r\nAUTH backup wrong\r\nAUTH s3cr3t-t0k3n\r\nSET a 1\r\nGET a\r\n";
+
+    // WHEN
+    let mut output = Vec::new();
+    resp::serve(&server, Cursor::new(input), &mut output).unwrap();
+
+    // THEN
+    assert_eq!(String::from_utf8(output).unwrap(), "-ERR NotAuthenticated\r\n-ERR AuthenticationFailed\r\n+OK\r\n+OK\r\n$1\r\n1\r\n");
+}
+
+fn pg_message(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    bytes.extend_from_slice(&((body.len() + 4) as i32).to_be_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+// Tags of the messages the server sends to a client logging in as alice with `password`
+fn pg_login(password: &str) -> String {
+    let mut body = (3i32 << 16).to_be_bytes().to_vec();
+    body.extend_from_slice(b"user\0alice\0database\0rudibi\0\0");
+    let mut input = ((body.len() + 4) as i32).to_be_bytes().to_vec();
+    input.extend(body);
+    input.extend(pg_message(b'p', format!("{password}\0").as_bytes()));
+    input.extend(pg_message(b'Q', b"SELECT name FROM Fruits WHERE id = 100\0"));
+
+    let mut output = Vec::new();
+    pgwire::serve(&guarded_server(), Cursor::new(input), &mut output).unwrap();
+    let mut tags = String::new();
+    let mut rest = &output[..];
+    while let [tag, len @ ..] = rest {
+        tags.push(*tag as char);
+        rest = &rest[1 + i32::from_be_bytes(len[..4].try_into().unwrap()) as usize..];
+    }
+    tags
+}
+
+#[test]
+fn test_postgres_password() {
+    // WHEN
+    let logged_in = pg_login("wonderland");
+    let refused = pg_login("looking-glass");
+
+    // THEN the password is asked for before the usual startup
+    assert_eq!(logged_in, "RRSSSSSSKZTDCZ");
+    assert_eq!(refused, "RE");
+}
+
+#[test]
+fn test_config() {
+    // GIVEN
+    let text = format!("[users]\nalice = \"{}\"\n\n[tokens]\nbackup = '{}'\n", hash_password("wonderland"), hash_token("s3cr3t-t0k3n"));
+
+    // WHEN
+    let config = ServerConfig::from_toml(&text).unwrap();
+    let bad = ServerConfig::from_toml("[users]\nalice = \"wonderland\"\n");
+
+    // THEN
+    assert_eq!(config.credentials.check(Some("alice"), "wonderland"), Ok("alice".to_string()));
+    assert_eq!(config.credentials.check(None, "s3cr3t-t0k3n"), Ok("backup".to_string()));
+    assert!(ServerConfig::default().credentials.is_empty());
+    assert!(matches!(bad, Err(DbError::InputError(msg)) if msg.starts_with("Line 2: Bad password hash for alice")));
+}
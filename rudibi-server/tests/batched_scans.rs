@@ -0,0 +1,65 @@
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{Row, StorageCfg, SCAN_BATCH_ROWS};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::storage::{DiskStorage, InMemoryStorage, RowId, Storage};
+use rudibi_server::testlib::{check_equality, fruits_schema, fruits_table, random_temp_file, with_tmp};
+
+fn batches_cover_scan(storage: &mut dyn Storage) {
+    // GIVEN
+    let rows: Vec<Row> = (0..25u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"fruit"])).collect();
+    storage.store(&rows, &vec![0, 1]);
+    storage.delete_rows(vec![3, 10, 11]);
+
+    // WHEN
+    let batches: Vec<Vec<RowId>> = storage.scan_batches(10)
+        .map(|batch| batch.items().iter().map(|item| item.row_id).collect())
+        .collect();
+
+    // THEN batches may be short, but never empty, and keep scan order
+    assert!(batches.iter().all(|batch| !batch.is_empty() && batch.len() <= 10));
+    let scanned: Vec<RowId> = storage.scan().map(|item| item.row_id).collect();
+    assert_eq!(batches.concat(), scanned);
+    let first = storage.scan_batches(10).next().unwrap();
+    assert!(first.column(1).iter().all(|name| *name == b"fruit"));
+}
+
+#[test]
+fn batches_cover_scan_in_mem() {
+    batches_cover_scan(&mut InMemoryStorage::new(fruits_schema()));
+}
+
+#[test]
+fn batches_cover_scan_on_disk() {
+    let path = random_temp_file();
+    batches_cover_scan(&mut DiskStorage::new(fruits_schema(), &path).unwrap());
+    std::fs::remove_file(path).unwrap();
+}
+
+fn test_column_comparisons(storage: StorageCfg) {
+    // GIVEN more rows than fit in a batch
+    let db = fruits_table(storage);
+    let many: Vec<Row> = (1000..1000 + 2 * SCAN_BATCH_ROWS as u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+
+    // WHEN
+    let count = |filter| db.count("Fruits", &filter).unwrap();
+
+    // THEN
+    assert_eq!(count(Gt(ColumnRef("id"), Const(U32(200)))), 2 + 2 * SCAN_BATCH_ROWS);
+    assert_eq!(count(Lt(Const(U32(200)), ColumnRef("id"))), 2 + 2 * SCAN_BATCH_ROWS);
+    assert_eq!(count(Lte(ColumnRef("id"), Const(U32(200)))), 2);
+    assert_eq!(count(Neq(ColumnRef("id"), Const(U32(1000)))), 3 + 2 * SCAN_BATCH_ROWS);
+    assert_eq!(count(IsNotDistinctFrom(ColumnRef("id"), Const(U32(1500)))), 1);
+    let results = db.select(&[ColumnRef("name")], "Fruits", &Gte(Const(U32(200)), ColumnRef("id"))).unwrap();
+    check_equality(&results, &[[UTF8("apple")], [UTF8("banana")]]);
+}
+
+#[test]
+fn test_column_comparisons_in_mem() {
+    test_column_comparisons(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_column_comparisons_on_disk() {
+    with_tmp(test_column_comparisons);
+}
@@ -0,0 +1,119 @@
+use std::sync::{Arc, Mutex};
+
+use rudibi_server::batch::BatchReport;
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, Row, StorageCfg};
+use rudibi_server::index::{IndexKey, IndexKind};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::storage::{RowId, StorageObserver};
+use rudibi_server::testlib::{check_equality, fruits_table, with_tmp};
+
+#[derive(Default)]
+struct StoreCalls {
+    rows: Mutex<Vec<usize>>,
+}
+
+impl StorageObserver for StoreCalls {
+    fn on_store(&self, _table: &str, row_ids: &[RowId], _rows: &[Row], _column_mapping: &[usize]) {
+        self.rows.lock().unwrap().push(row_ids.len());
+    }
+}
+
+fn test_batched_inserts_store_once(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    let stores = Arc::new(StoreCalls::default());
+    db.add_observer(stores.clone());
+
+    // WHEN rows are added one at a time
+    let mut batch = db.batch();
+    for id in 500..600u32 {
+        batch.insert("Fruits", &["id", "name"], &[Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])]);
+    }
+    let report = batch.apply().unwrap();
+
+    // THEN
+    assert_eq!(report, BatchReport { rows_inserted: 100, rows_deleted: 0 });
+    assert_eq!(*stores.rows.lock().unwrap(), vec![100]);
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("name"), Const(UTF8("kiwi")))).unwrap(), 100);
+}
+
+#[test]
+fn test_batched_inserts_store_once_in_mem() {
+    test_batched_inserts_store_once(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_batched_inserts_store_once_on_disk() {
+    with_tmp(test_batched_inserts_store_once);
+}
+
+fn test_batch_applies_in_order(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+
+    // WHEN
+    let mut batch = db.batch();
+    batch.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]])
+        .delete("Fruits", Eq(ColumnRef("name"), Const(UTF8("kiwi"))))
+        .delete("Fruits", Eq(ColumnRef("name"), Const(UTF8("banana"))))
+        .insert("Fruits", &["id", "name"], rows![[600u32, "kiwi"]]);
+    let report = batch.apply().unwrap();
+
+    // THEN the first kiwi is deleted, the second one comes after the delete
+    assert_eq!(report, BatchReport { rows_inserted: 2, rows_deleted: 3 });
+    let results = db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &True).unwrap();
+    check_equality(&results, &[
+        [U32(100), UTF8("apple")],
+        [U32(400), UTF8("cherry")],
+        [U32(600), UTF8("kiwi")],
+    ]);
+}
+
+#[test]
+fn test_batch_applies_in_order_in_mem() {
+    test_batch_applies_in_order(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_batch_applies_in_order_on_disk() {
+    with_tmp(test_batch_applies_in_order);
+}
+
+#[test]
+fn test_failed_write_stops_batch() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.create_unique_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    // WHEN the second insert repeats an id
+    let mut batch = db.batch();
+    batch.delete("Fruits", Eq(ColumnRef("name"), Const(UTF8("banana"))))
+        .insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]])
+        .insert("Fruits", &["name", "id"], rows![["lime", 100u32]])
+        .delete("Fruits", True);
+    let result = batch.apply();
+
+    // THEN the writes before it stay
+    assert_eq!(result, Err(DbError::DuplicateKey { index: "Fruits.id".to_string(), key: IndexKey::U32(100) }));
+    let results = db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &True).unwrap();
+    check_equality(&results, &[
+        [U32(100), UTF8("apple")],
+        [U32(400), UTF8("cherry")],
+        [U32(500), UTF8("kiwi")],
+    ]);
+}
+
+#[test]
+fn test_batch_to_unknown_table() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+
+    // WHEN
+    let mut batch = db.batch();
+    batch.insert("Vegetables", &["id"], rows![[1u32]]);
+
+    // THEN
+    assert_eq!(batch.apply(), Err(DbError::TableNotFound("Vegetables".to_string())));
+}
@@ -0,0 +1,132 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::thread;
+
+use rudibi_server::blob::{BlobId, BLOB_CHUNK_SIZE};
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{random_temp_dir, serve};
+
+fn read_blob(db: &Database, id: BlobId) -> Result<Vec<u8>, DbError> {
+    let mut read_back = Vec::new();
+    db.get_blob_reader(id)?.read_to_end(&mut read_back).unwrap();
+    Ok(read_back)
+}
+
+#[test]
+fn store_blob_referenced_from_row() {
+    // GIVEN
+    let mut db = Database::new();
+    db.new_table(&Table::new("Files", vec![
+        Column::new("name", DataType::UTF8 { max_bytes: 32 }),
+        Column::new("content", DataType::BUFFER { length: 8 }),
+    ]), StorageCfg::InMemory).unwrap();
+    let payload: Vec<u8> = (0..3_000_000u32).map(|i| (i % 256) as u8).collect();
+
+    // WHEN
+    let id = db.put_blob(&mut payload.as_slice()).unwrap();
+    db.insert("Files", &["name", "content"], rows![["video.bin", id.to_bytes()]]).unwrap();
+
+    // THEN
+    let results = db.select(&[ColumnRef("content")], "Files", &Eq(ColumnRef("name"), Const(UTF8("video.bin")))).unwrap();
+    let stored_id = BlobId::from_bytes(results.data[0].get_column(0)).unwrap();
+    assert_eq!(db.blob_len(stored_id), Ok(payload.len()));
+    let mut read_back = Vec::new();
+    db.get_blob_reader(stored_id).unwrap().read_to_end(&mut read_back).unwrap();
+    assert!(read_back == payload);
+}
+
+#[test]
+fn delete_blob() {
+    let db = Database::new();
+    let id = db.put_blob(&mut &b"hello"[..]).unwrap();
+
+    db.delete_blob(id).unwrap();
+
+    assert!(matches!(db.get_blob_reader(id), Err(DbError::BlobNotFound(_))));
+    assert_eq!(db.delete_blob(id), Err(DbError::BlobNotFound(id.0)));
+}
+
+#[test]
+fn blobs_kept_in_directory() {
+    // GIVEN
+    let dir = random_temp_dir();
+    let payload: Vec<u8> = (0..BLOB_CHUNK_SIZE * 2 + 5).map(|i| (i % 253) as u8).collect();
+    let db = Database::open_dir(&dir).unwrap();
+    let kept = db.put_blob(&mut payload.as_slice()).unwrap();
+    let deleted = db.put_blob(&mut &b"gone"[..]).unwrap();
+    db.delete_blob(deleted).unwrap();
+    // Written but never finished, as if the database crashed meanwhile
+    let mut unfinished = db.create_blob().unwrap();
+    unfinished.write_all(b"half").unwrap();
+    std::mem::forget(unfinished);
+    drop(db);
+
+    // WHEN
+    let reopened = Database::open_dir(&dir).unwrap();
+    let next = reopened.put_blob(&mut &b"next"[..]).unwrap();
+
+    // THEN blobs outlive the database, and ids aren't reused
+    assert_eq!(reopened.blob_len(kept), Ok(payload.len()));
+    assert!(read_blob(&reopened, kept).unwrap() == payload);
+    assert_eq!(read_blob(&reopened, deleted), Err(DbError::BlobNotFound(deleted.0)));
+    assert_eq!(next, BlobId(3));
+    assert_eq!(std::fs::read_dir(format!("{dir}/blobs")).unwrap().count(), 3);
+    drop(reopened);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn blobs_written_from_threads() {
+    // GIVEN
+    let db = Arc::new(Database::new());
+
+    // WHEN
+    let writers: Vec<_> = (0..4u8).map(|n| {
+        let db = db.clone();
+        thread::spawn(move || (n, db.put_blob(&mut vec![n; BLOB_CHUNK_SIZE + 1].as_slice()).unwrap()))
+    }).collect();
+    let written: Vec<_> = writers.into_iter().map(|writer| writer.join().unwrap()).collect();
+
+    // THEN
+    for (n, id) in written {
+        assert_eq!(read_blob(&db, id).unwrap(), vec![n; BLOB_CHUNK_SIZE + 1]);
+    }
+}
+
+#[test]
+fn blobs_in_frames() {
+    // GIVEN
+    let server = Server::new(Database::new(), StorageCfg::InMemory);
+    let (first, second) = (vec![1u8; BLOB_CHUNK_SIZE], vec![2u8; 10]);
+
+    // WHEN
+    let written = serve(&server, &[
+        Frame::new(frame::BLOB_WRITE, 1, first.clone()),
+        Frame::new(frame::BLOB_WRITE, 2, second.clone()),
+        Frame::new(frame::BLOB_WRITE, 3, vec![0u8; BLOB_CHUNK_SIZE + 1]),
+        Frame::new(frame::BLOB_DONE, 4, ""),
+        Frame::new(frame::BLOB_DONE, 5, ""),
+    ]);
+    let read = serve(&server, &[Frame::new(frame::BLOB_READ, 6, "0"), Frame::new(frame::BLOB_READ, 7, "1")]);
+
+    // THEN the blob is read back a chunk at a time
+    assert_eq!(written, [
+        Frame::new(frame::REPLY, 1, "OK"),
+        Frame::new(frame::REPLY, 2, "OK"),
+        Frame::error(3, &ErrorReply::from(&DbError::InputError(format!("Blob chunks hold at most {BLOB_CHUNK_SIZE} bytes, got {}", BLOB_CHUNK_SIZE + 1)))),
+        Frame::new(frame::REPLY, 4, "0"),
+        Frame::error(5, &ErrorReply::from(&DbError::InputError("No blob is written".to_string()))),
+    ]);
+    assert_eq!(read, [
+        Frame::new(frame::BLOB_CHUNK, 6, first),
+        Frame::new(frame::BLOB_CHUNK, 6, second),
+        Frame::new(frame::DONE, 6, format!("READ {}", BLOB_CHUNK_SIZE + 10)),
+        Frame::error(7, &ErrorReply::from(&DbError::BlobNotFound(1))),
+    ]);
+}
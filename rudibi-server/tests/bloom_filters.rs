@@ -0,0 +1,64 @@
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::storage::SEGMENT_ROWS;
+use rudibi_server::testlib::{check_equality, random_temp_file};
+
+fn readings_table(path: &str, rows: u32) -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Readings", vec![
+        Column::new("id", DataType::U32),
+        Column::new("value", DataType::F64),
+    ]), StorageCfg::disk(path)).unwrap();
+    let data: Vec<Row> = (0..rows)
+        .map(|id| Row::of_columns(&[&id.to_le_bytes(), &(id as f64 - 1.0).to_le_bytes()]))
+        .collect();
+    db.insert("Readings", &["id", "value"], &data).unwrap();
+    db
+}
+
+#[test]
+fn test_equality_skips_segments() {
+    // GIVEN
+    let path = random_temp_file();
+    let db = readings_table(&path, 3 * SEGMENT_ROWS as u32);
+    db.create_bloom_filter("Readings", "id").unwrap();
+
+    // WHEN
+    let hit = db.select(&[ColumnRef("value")], "Readings", &Eq(ColumnRef("id"), Const(U32(2500)))).unwrap();
+    let miss = db.select(&[ColumnRef("value")], "Readings", &Eq(ColumnRef("id"), Const(U32(99999)))).unwrap();
+
+    // THEN
+    check_equality(&hit, &[[F64(2499.0)]]);
+    assert!(hit.scan_stats.rows_scanned <= SEGMENT_ROWS, "{:?}", hit.scan_stats);
+    assert!(miss.is_empty() && miss.scan_stats.rows_scanned < SEGMENT_ROWS, "{:?}", miss.scan_stats);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_bloom_filter_covers_later_inserts_and_equal_floats() {
+    // GIVEN
+    let path = random_temp_file();
+    let db = readings_table(&path, 10);
+    db.create_bloom_filter("Readings", "value").unwrap();
+
+    // WHEN
+    db.insert("Readings", &["id", "value"], &[Row::of_columns(&[&10u32.to_le_bytes(), &(-0.0f64).to_le_bytes()])]).unwrap();
+    let zeros = db.select(&[ColumnRef("id")], "Readings", &Eq(ColumnRef("value"), Const(F64(0.0)))).unwrap();
+    let late = db.select(&[ColumnRef("id")], "Readings", &Eq(ColumnRef("value"), Const(F64(8.0)))).unwrap();
+
+    // THEN
+    check_equality(&zeros, &[[U32(1)], [U32(10)]]);
+    check_equality(&late, &[[U32(9)]]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_bloom_filter_unsupported_in_memory() {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Readings", vec![Column::new("id", DataType::U32)]), StorageCfg::InMemory).unwrap();
+
+    let result = db.create_bloom_filter("Readings", "id");
+
+    assert!(matches!(result, Err(DbError::UnsupportedOperation(_))), "{result:?}");
+}
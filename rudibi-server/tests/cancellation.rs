@@ -0,0 +1,192 @@
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::cancel::{CancelKey, CancellationToken};
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, QueryOptions, Row, StorageCfg};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::pgwire;
+use rudibi_server::protocol::Server;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, fruits_table, with_tmp};
+
+fn test_cancelled_select_fails(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+    let token = CancellationToken::new();
+    let options = QueryOptions { cancel: Some(token.clone()), ..Default::default() };
+    let filter = Eq(ColumnRef("name"), Const(UTF8("cherry")));
+    let before = db.select_with_options(&[ColumnRef("id")], "Fruits", &filter, &options).unwrap();
+
+    // WHEN
+    token.cancel();
+    let after = db.select_with_options(&[ColumnRef("id")], "Fruits", &filter, &options);
+    let streamed = db.select_stream(&[ColumnRef("id")], "Fruits", &filter, &options, 1024);
+
+    // THEN
+    check_equality(&before, &[[U32(400)]]);
+    assert_eq!(after.err(), Some(DbError::QueryCancelled));
+    assert!(matches!(streamed, Err(DbError::QueryCancelled)));
+}
+
+#[test]
+fn test_cancelled_select_fails_in_mem() {
+    test_cancelled_select_fails(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_cancelled_select_fails_on_disk() {
+    with_tmp(test_cancelled_select_fails);
+}
+
+#[test]
+fn test_cancel_from_another_thread() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let many: Vec<Row> = (1000..101_000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+    let token = CancellationToken::new();
+    let options = QueryOptions { cancel: Some(token.clone()), parallelism: 2, ..Default::default() };
+
+    // WHEN
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        token.cancel();
+    });
+    let result = loop {
+        match db.select_with_options(&[ColumnRef("id")], "Fruits", &Gt(ColumnRef("id"), Const(U32(0))), &options) {
+            Ok(results) => assert_eq!(results.len(), 100_004),
+            Err(err) => break err,
+        }
+    };
+    canceller.join().unwrap();
+
+    // THEN
+    assert_eq!(result, DbError::QueryCancelled);
+}
+
+fn many_fruits_server() -> Server {
+    let db = fruits_table(StorageCfg::InMemory);
+    let many: Vec<Row> = (1000..101_000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+    Server::new(db, StorageCfg::InMemory)
+}
+
+#[test]
+fn test_cancel_a_connection_by_key() {
+    // GIVEN
+    let server = many_fruits_server();
+    let session = server.open_session();
+    let key = session.cancel_key().unwrap();
+    let done = AtomicBool::new(false);
+
+    // WHEN another connection cancels with the key until a query stops
+    let result = thread::scope(|scope| {
+        scope.spawn(|| {
+            while !done.load(Ordering::Relaxed) {
+                assert!(server.cancel(key));
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let result = loop {
+            if let Err(err) = server.handle_as(&session, "SELECT id FROM Fruits WHERE id > 0") {
+                break err;
+            }
+        };
+        done.store(true, Ordering::Relaxed);
+        result
+    });
+
+    // THEN the connection's next query runs
+    assert_eq!(result, DbError::QueryCancelled);
+    assert!(server.handle_as(&session, "SELECT id FROM Fruits WHERE id = 100").is_ok());
+}
+
+#[test]
+fn test_cancel_needs_the_secret() {
+    // GIVEN
+    let server = many_fruits_server();
+    let session = server.open_session();
+    let key = session.cancel_key().unwrap();
+    let token = session.cancel.as_ref().unwrap().start_query();
+
+    // WHEN
+    let wrong_secret = server.cancel(CancelKey { id: key.id, secret: key.secret.wrapping_add(1) });
+    let other = server.open_session().cancel_key().unwrap();
+    let closed = server.cancel(other);
+
+    // THEN
+    assert!(!wrong_secret);
+    assert!(!closed);
+    assert!(!token.is_cancelled());
+}
+
+#[test]
+fn test_cancel_frames() {
+    // GIVEN
+    let server = many_fruits_server();
+    let session = server.open_session();
+    let token = session.cancel.as_ref().unwrap().start_query();
+    let mut input = Vec::new();
+    Frame::new(frame::CANCEL_KEY, 1, "").write_to(&mut input).unwrap();
+    Frame::new(frame::CANCEL, 2, "12").write_to(&mut input).unwrap();
+    Frame::new(frame::CANCEL, 3, session.cancel_key().unwrap().to_string()).write_to(&mut input).unwrap();
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+
+    // THEN
+    let mut output = Cursor::new(output);
+    let replies: Vec<Frame> = std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect();
+    assert!(CancelKey::parse(std::str::from_utf8(&replies[0].payload).unwrap()).is_some());
+    assert_eq!(replies[1..], [
+        Frame::error(2, &ErrorReply::from(&DbError::InputError("Bad cancel key 12, expected id secret".to_string()))),
+        Frame::new(frame::REPLY, 3, "OK"),
+    ]);
+    assert!(token.is_cancelled());
+}
+
+#[test]
+fn test_cancel_text_mode() {
+    // GIVEN
+    let server = many_fruits_server();
+    let session = server.open_session();
+    let token = session.cancel.as_ref().unwrap().start_query();
+    let input = format!("cancel key; CANCEL {}\n", session.cancel_key().unwrap());
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve_text(Cursor::new(input), &mut output).unwrap();
+
+    // THEN
+    let output = String::from_utf8(output).unwrap();
+    let replies: Vec<&str> = output.split_terminator("\n\n").collect();
+    assert!(CancelKey::parse(replies[0]).is_some(), "{output}");
+    assert_eq!(replies[1], "OK");
+    assert!(token.is_cancelled());
+}
+
+#[test]
+fn test_cancel_request_of_postgres() {
+    // GIVEN
+    let server = many_fruits_server();
+    let session = server.open_session();
+    let key = session.cancel_key().unwrap();
+    let token = session.cancel.as_ref().unwrap().start_query();
+    let mut request = 16i32.to_be_bytes().to_vec();
+    for part in [80877102, key.id, key.secret] {
+        request.extend_from_slice(&part.to_be_bytes());
+    }
+
+    // WHEN
+    let mut output = Vec::new();
+    pgwire::serve(&server, Cursor::new(request), &mut output).unwrap();
+
+    // THEN nothing is sent back
+    assert!(output.is_empty());
+    assert!(token.is_cancelled());
+}
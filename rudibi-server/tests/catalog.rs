@@ -0,0 +1,98 @@
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{Database, DbError, Row, StorageCfg};
+use rudibi_server::index::IndexKind;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{check_equality, fruits_schema, random_temp_dir};
+
+fn fruits_in(dir: &str) -> Database {
+    let mut db = Database::open_dir(dir).unwrap();
+    db.new_table(&fruits_schema(), StorageCfg::managed()).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[100u32, "apple"], [200u32, "banana"]]).unwrap();
+    db
+}
+
+#[test]
+fn test_reopened_directory_restores_tables_and_indexes() {
+    // GIVEN
+    let dir = random_temp_dir();
+    let mut db = fruits_in(&dir);
+    db.create_unique_index("Fruits", "id", IndexKind::Ordered).unwrap();
+    db.create_index("Fruits", "name", IndexKind::Ordered).unwrap();
+    db.drop_index("Fruits", "name").unwrap();
+    drop(db);
+
+    // WHEN
+    let mut reopened = Database::open_dir(&dir).unwrap();
+
+    // THEN
+    let results = reopened.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(200)))).unwrap();
+    check_equality(&results, &[[UTF8("banana")]]);
+    assert!(reopened.index_for("Fruits", "id").unwrap().unique);
+    assert!(reopened.index_for("Fruits", "name").is_none());
+    let duplicate = reopened.insert("Fruits", &["id", "name"], rows![[100u32, "cherry"]]);
+    assert!(matches!(duplicate, Err(DbError::DuplicateKey { .. })));
+    reopened.insert("Fruits", &["id", "name"], rows![[300u32, "cherry"]]).unwrap();
+    let results = reopened.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(100)], [U32(200)], [U32(300)]]);
+    drop(reopened);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_directory_is_locked_while_open() {
+    // GIVEN
+    let dir = random_temp_dir();
+    let db = fruits_in(&dir);
+
+    // WHEN
+    let second = Database::open_dir(&dir);
+
+    // THEN
+    assert!(matches!(second, Err(DbError::DatabaseLocked(_))));
+    drop(db);
+    assert!(Database::open_dir(&dir).is_ok());
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_table_names_dont_become_file_names() {
+    // GIVEN
+    let dir = random_temp_dir();
+    let mut db = fruits_in(&dir);
+
+    // WHEN
+    db.rename_table("Fruits", "../evil").unwrap();
+    drop(db);
+
+    // THEN
+    let reopened = Database::open_dir(&dir).unwrap();
+    let results = reopened.select(&[ColumnRef("id")], "../evil", &True).unwrap();
+    check_equality(&results, &[[U32(100)], [U32(200)]]);
+    assert!(!std::path::Path::new(&dir).join("../evil").exists());
+    drop(reopened);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_managed_table_cant_move_out_of_directory() {
+    // GIVEN
+    let dir = random_temp_dir();
+    let mut db = fruits_in(&dir);
+
+    // WHEN
+    let moved = db.rename_table_with_path("Fruits", "Moved", "/tmp/moved.rdb");
+
+    // THEN
+    assert!(matches!(moved, Err(DbError::UnsupportedOperation(_))));
+    assert!(db.select(&[ColumnRef("id")], "Fruits", &True).is_ok());
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_managed_table_needs_directory() {
+    let mut db = Database::new();
+    let result = db.new_table(&fruits_schema(), StorageCfg::managed());
+    assert!(matches!(result, Err(DbError::InputError(_))));
+}
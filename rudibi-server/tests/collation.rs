@@ -0,0 +1,68 @@
+use rudibi_server::collation::Collation;
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{check_equality, with_tmp};
+
+fn users_table(storage: StorageCfg) -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Users", vec![
+        Column::new("id", DataType::U32),
+        Column::new("email", DataType::UTF8 { max_bytes: 64 }).with_collation(Collation::CaseInsensitive),
+        Column::new("nick", DataType::UTF8 { max_bytes: 64 }),
+    ]), storage).unwrap();
+    db.insert("Users", &["id", "email", "nick"], rows![
+        [1u32, "Ann@Example.com", "Ann"],
+        [2u32, "bob@example.com", "bob"],
+        [3u32, "carl@example.com", "Carl"]
+    ]).unwrap();
+    db
+}
+
+fn test_case_insensitive_equality(storage: StorageCfg) {
+    // GIVEN
+    let db = users_table(storage);
+
+    // WHEN
+    let by_email = db.select(&[ColumnRef("id")], "Users", &Eq(ColumnRef("email"), Const(UTF8("ann@example.COM")))).unwrap();
+    let by_nick = db.select(&[ColumnRef("id")], "Users", &Eq(ColumnRef("nick"), Const(UTF8("ann")))).unwrap();
+
+    // THEN
+    check_equality(&by_email, &[[U32(1)]]);
+    assert_eq!(by_nick.len(), 0);
+}
+
+#[test]
+fn test_case_insensitive_equality_in_mem() {
+    test_case_insensitive_equality(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_case_insensitive_equality_on_disk() {
+    with_tmp(test_case_insensitive_equality);
+}
+
+#[test]
+fn test_ordering_follows_collation() {
+    // GIVEN
+    let db = users_table(StorageCfg::InMemory);
+
+    // WHEN
+    let by_email = db.select(&[ColumnRef("id")], "Users", &Lt(ColumnRef("email"), Const(UTF8("BOB@example.com")))).unwrap();
+    let by_email_desc = db.select(&[ColumnRef("id")], "Users", &Gte(ColumnRef("email"), Const(UTF8("BOB@example.com")))).unwrap();
+
+    // THEN
+    check_equality(&by_email, &[[U32(1)]]);
+    check_equality(&by_email_desc, &[[U32(2)], [U32(3)]]);
+}
+
+#[test]
+fn test_collation_requires_utf8() {
+    let mut db = Database::new();
+    let result = db.new_table(&Table::new("Bad", vec![
+        Column::new("id", DataType::U32).with_collation(Collation::CaseInsensitive),
+    ]), StorageCfg::InMemory);
+
+    assert!(matches!(result, Err(DbError::InputError(_))), "{result:?}");
+}
@@ -0,0 +1,168 @@
+use std::io::Cursor;
+
+use rudibi_server::engine::{Row, StorageCfg};
+use rudibi_server::errors::{self, ErrorReply};
+use rudibi_server::frame::{self, Codec, Frame, COMPRESS_MIN_BYTES, MAX_FRAME_BYTES};
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+
+const KIWIS: u32 = 1000;
+
+fn many_fruits_server() -> Server {
+    let db = fruits_table(StorageCfg::InMemory);
+    let many: Vec<Row> = (1000..1000 + KIWIS).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+    Server::new(db, StorageCfg::InMemory)
+}
+
+fn replies(server: &Server, requests: &[Frame]) -> Vec<Frame> {
+    let mut input = Vec::new();
+    for request in requests {
+        request.write_to(&mut input).unwrap();
+    }
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect()
+}
+
+fn error_code(reply: &Frame) -> u16 {
+    assert_eq!(reply.tag, frame::ERROR);
+    ErrorReply::parse(&reply.payload).unwrap().code
+}
+
+#[test]
+fn test_codec_round_trip() {
+    // GIVEN
+    let text = "the quick brown fox jumps over the lazy dog. ".repeat(100);
+
+    // WHEN
+    let compressed = Codec::Lz4.compress(text.as_bytes()).unwrap();
+
+    // THEN
+    assert!(compressed.len() < text.len() / 10);
+    assert_eq!(Codec::Lz4.decompress(&compressed).unwrap(), text.as_bytes());
+    assert_eq!(Codec::Lz4.decompress(&compressed[..compressed.len() - 1]), None);
+    assert_eq!(Codec::Lz4.decompress(&[1, 2]), None);
+}
+
+#[test]
+fn test_decompressed_payloads_fit_a_frame() {
+    // GIVEN a payload claiming to decompress past the maximum
+    let mut bomb = ((MAX_FRAME_BYTES + 1) as u32).to_le_bytes().to_vec();
+    bomb.extend_from_slice(&Codec::Lz4.compress(&[0; 100]).unwrap()[4..]);
+
+    // WHEN
+    let decompressed = Codec::Lz4.decompress(&bomb);
+
+    // THEN
+    assert_eq!(decompressed, None);
+}
+
+#[test]
+fn test_negotiate() {
+    assert_eq!(Codec::negotiate("lz4"), Some(Codec::Lz4));
+    assert_eq!(Codec::negotiate("brotli, LZ4"), Some(Codec::Lz4));
+    assert_eq!(Codec::negotiate("brotli"), None);
+    assert_eq!(Codec::negotiate(""), None);
+}
+
+#[test]
+fn test_long_replies_are_compressed() {
+    // GIVEN
+    let server = many_fruits_server();
+    let select = "SELECT id, name FROM Fruits WHERE name = 'kiwi'";
+
+    // WHEN
+    let plain = replies(&server, &[Frame::new(frame::EXECUTE, 1, select)]);
+    let compressed = replies(&server, &[
+        Frame::new(frame::COMPRESS, 1, "brotli,lz4"),
+        Frame::new(frame::EXECUTE, 2, select),
+        Frame::new(frame::EXECUTE, 3, "SELECT id FROM Fruits WHERE id = 100"),
+    ]);
+
+    // THEN
+    assert_eq!(compressed[0], Frame::new(frame::REPLY, 1, "lz4"));
+    assert_eq!(compressed[1].tag, frame::REPLY | frame::COMPRESSED);
+    assert!(compressed[1].payload.len() < plain[0].payload.len() / 2);
+    assert_eq!(compressed[1].decompressed(Codec::Lz4).unwrap(), Frame { request_id: 2, ..plain[0].clone() });
+    assert_eq!(compressed[2].tag, frame::REPLY);
+}
+
+#[test]
+fn test_streamed_rows_are_compressed() {
+    // GIVEN
+    let server = many_fruits_server();
+
+    // WHEN
+    let replies = replies(&server, &[
+        Frame::new(frame::COMPRESS, 1, "lz4"),
+        Frame::new(frame::STREAM, 2, "SELECT id, name FROM Fruits WHERE name = 'kiwi'"),
+    ]);
+
+    // THEN
+    assert_eq!(replies[1].tag, frame::ROWS | frame::COMPRESSED);
+    let rows = replies[1].decompressed(Codec::Lz4).unwrap();
+    assert!(String::from_utf8(rows.payload).unwrap().starts_with("id,name\n1000,kiwi\n"));
+    assert_eq!(replies[2], Frame::new(frame::DONE, 2, format!("SELECTED {KIWIS}")));
+}
+
+#[test]
+fn test_compressed_inserts() {
+    // GIVEN a bulk insert
+    let server = many_fruits_server();
+    let values: Vec<String> = (5000..5200).map(|id| format!("({id}, 'melon')")).collect();
+    let insert = format!("INSERT INTO Fruits (id, name) VALUES {}", values.join(", "));
+    assert!(insert.len() >= COMPRESS_MIN_BYTES);
+    let compressed = Frame::new(frame::EXECUTE, 2, insert).compressed(Codec::Lz4).unwrap();
+
+    // WHEN
+    let replies = replies(&server, &[Frame::new(frame::COMPRESS, 1, "lz4"), compressed]);
+
+    // THEN
+    assert_eq!(replies[1], Frame::new(frame::REPLY, 2, "INSERTED 200"));
+}
+
+#[test]
+fn test_compression_errors() {
+    // GIVEN
+    let server = many_fruits_server();
+    let compressed = Frame::new(frame::EXECUTE, 2, "SELECT id FROM Fruits WHERE id = 100 ".repeat(100)).compressed(Codec::Lz4).unwrap();
+    let broken = Frame::new(frame::EXECUTE | frame::COMPRESSED, 4, [0xff; 16]);
+
+    // WHEN
+    let replies = replies(&server, &[
+        Frame::new(frame::COMPRESS, 1, "brotli,snappy"),
+        compressed,
+        Frame::new(frame::COMPRESS, 3, "lz4"),
+        broken,
+        Frame::new(frame::EXECUTE, 5, "SELECT id FROM Fruits WHERE id = 100"),
+    ]);
+
+    // THEN a compressed payload before a codec is picked, or a broken one, is refused
+    assert_eq!(error_code(&replies[0]), errors::UNSUPPORTED_CODEC);
+    assert_eq!(error_code(&replies[1]), errors::BAD_COMPRESSION);
+    assert_eq!(replies[2], Frame::new(frame::REPLY, 3, "lz4"));
+    assert_eq!(error_code(&replies[3]), errors::BAD_COMPRESSION);
+    assert_eq!(replies[4].tag, frame::REPLY);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd() {
+    // GIVEN
+    let server = many_fruits_server();
+
+    // WHEN
+    let replies = replies(&server, &[
+        Frame::new(frame::COMPRESS, 1, "zstd,lz4"),
+        Frame::new(frame::EXECUTE, 2, "SELECT id, name FROM Fruits WHERE name = 'kiwi'"),
+    ]);
+
+    // THEN
+    assert_eq!(replies[0], Frame::new(frame::REPLY, 1, "zstd"));
+    assert_eq!(replies[1].tag, frame::REPLY | frame::COMPRESSED);
+    let reply = replies[1].decompressed(Codec::Zstd).unwrap();
+    assert!(String::from_utf8(reply.payload).unwrap().contains("1999"));
+    assert_eq!(Codec::Zstd.decompress(b"not zstd"), None);
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+use std::thread;
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{Database, Row, StorageCfg};
+use rudibi_server::index::IndexKind;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::storage::{InMemoryStorage, Storage};
+use rudibi_server::testlib::{fruits_schema, fruits_table, with_tmp};
+
+const WRITERS: u32 = 4;
+const ROWS_PER_WRITER: u32 = 200;
+
+#[test]
+fn test_database_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Database>();
+}
+
+fn test_concurrent_writes_and_selects(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.create_unique_index("Fruits", "id", IndexKind::Ordered).unwrap();
+    let db = Arc::new(db);
+
+    // WHEN writers insert one row at a time while readers select
+    let writers: Vec<_> = (0..WRITERS).map(|writer| {
+        let db = db.clone();
+        thread::spawn(move || {
+            for n in 0..ROWS_PER_WRITER {
+                let id = 1000 + writer * ROWS_PER_WRITER + n;
+                db.insert("Fruits", &["id", "name"], &[Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])]).unwrap();
+            }
+        })
+    }).collect();
+    let readers: Vec<_> = (0..2).map(|_| {
+        let db = db.clone();
+        thread::spawn(move || {
+            let mut seen = 0;
+            while seen < (WRITERS * ROWS_PER_WRITER) as usize {
+                let kiwis = db.select(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("kiwi")))).unwrap();
+                // Rows are only ever added, a select sees at least what the one before it saw
+                assert!(kiwis.len() >= seen);
+                seen = kiwis.len();
+            }
+        })
+    }).collect();
+    for thread in writers.into_iter().chain(readers) {
+        thread.join().unwrap();
+    }
+
+    // THEN
+    assert_eq!(db.count("Fruits", &True).unwrap(), 4 + (WRITERS * ROWS_PER_WRITER) as usize);
+    let last = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(1000 + WRITERS * ROWS_PER_WRITER - 1)))).unwrap();
+    assert_eq!(last.scan_stats.rows_scanned, 1);
+    assert_eq!(last.len(), 1);
+}
+
+#[test]
+fn test_concurrent_writes_and_selects_in_mem() {
+    test_concurrent_writes_and_selects(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_concurrent_writes_and_selects_on_disk() {
+    with_tmp(test_concurrent_writes_and_selects);
+}
+
+#[test]
+fn test_concurrent_deletes_and_updates() {
+    // GIVEN
+    let db = Arc::new(fruits_table(StorageCfg::InMemory));
+
+    // WHEN
+    let deleter = {
+        let db = db.clone();
+        thread::spawn(move || db.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap())
+    };
+    let updater = {
+        let db = db.clone();
+        thread::spawn(move || db.update("Fruits", &["name"], &Row::of_columns(&[b"grape"]), &Eq(ColumnRef("id"), Const(U32(400)))).unwrap())
+    };
+
+    // THEN each write applies as a whole, whichever runs first
+    assert_eq!(deleter.join().unwrap(), 2);
+    assert_eq!(updater.join().unwrap(), 1);
+    let names = db.select(&[ColumnRef("name")], "Fruits", &True).unwrap();
+    let mut names: Vec<&[u8]> = names.data.iter().map(|row| row.get_column(0)).collect();
+    names.sort();
+    assert_eq!(names, vec![&b"apple"[..], &b"grape"[..]]);
+}
+
+#[test]
+fn test_snapshot_is_unaffected_by_writes() {
+    // GIVEN
+    let mut storage = InMemoryStorage::new(fruits_schema());
+    let mapping = vec![0, 1];
+    let ids = storage.store(&[Row::of_columns(&[&100u32.to_le_bytes(), b"apple"]), Row::of_columns(&[&200u32.to_le_bytes(), b"banana"])], &mapping);
+    let snapshot = storage.snapshot().unwrap();
+
+    // WHEN
+    storage.store(&[Row::of_columns(&[&300u32.to_le_bytes(), b"cherry"])], &mapping);
+    storage.delete_rows(vec![ids[0]]);
+    storage.compact();
+
+    // THEN
+    let names: Vec<Vec<u8>> = snapshot.scan().map(|item| item.row_content.get_column(1).to_vec()).collect();
+    assert_eq!(names, vec![b"apple".to_vec(), b"banana".to_vec()]);
+    assert_eq!(snapshot.row_count(), 2);
+    assert_eq!(storage.row_count(), 2);
+}
+
+#[test]
+fn test_full_scans_alongside_deletes_and_compaction() {
+    // GIVEN
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), StorageCfg::InMemory).unwrap();
+    let rows: Vec<Row> = (0..10_000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), if id % 2 == 0 { b"kiwi" } else { b"lime" }])).collect();
+    db.insert("Fruits", &["id", "name"], &rows).unwrap();
+    let db = Arc::new(db);
+
+    // WHEN limes are deleted and the table compacted while readers scan it
+    let readers: Vec<_> = (0..2).map(|_| {
+        let db = db.clone();
+        thread::spawn(move || {
+            for _ in 0..20 {
+                // Each scan sees the table before or after the delete, never in between
+                let limes = db.select(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("lime")))).unwrap().len();
+                assert!(limes == 5_000 || limes == 0, "saw {limes} limes");
+            }
+        })
+    }).collect();
+    db.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("lime")))).unwrap();
+    db.compact("Fruits").unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    // THEN
+    assert_eq!(db.count("Fruits", &True).unwrap(), 5_000);
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use rudibi_server::config::{LogLevel, Protocol, ServerConfig, TableStorage};
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::limits::SoftLimits;
+use rudibi_server::storage::Durability;
+use rudibi_server::tcp::TcpSettings;
+use rudibi_server::testlib::random_temp_file;
+use rudibi_server::throttle::RateLimits;
+
+const FILE: &str = r#"
+# Serve psql on every interface
+[server]
+bind = "0.0.0.0"
+port = 5432
+protocol = "postgres"   # rather than frames
+commands = 8
+log = 'info'
+idle_timeout = 300
+keepalive = 0            # the default
+
+[storage]
+data_dir = "/var/lib/rudibi #1"
+durability = "group-commit"
+
+[limits]
+query_memory = 64_000_000
+soft_row_size = 0.8
+soft_result_rows = 1000
+soft_disk_budget = 0.9
+cursors = 4
+insert_budget = 65536
+
+[limits.connection]
+queries = 100
+rows_scanned = 0.5
+
+[replication]
+primary = "10.0.0.1:1337"
+token = "replica-secret"
+"#;
+
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn test_from_toml() {
+    // WHEN
+    let config = ServerConfig::from_toml(FILE).unwrap();
+
+    // THEN settings not in the file keep their defaults
+    assert_eq!(config, ServerConfig {
+        bind: "0.0.0.0".to_string(),
+        port: 5432,
+        protocol: Protocol::Postgres,
+        commands: Some(8),
+        log: LogLevel::Info,
+        tcp: TcpSettings { idle_timeout: Some(Duration::from_secs(300)), keepalive: None },
+        data_dir: Some("/var/lib/rudibi #1".to_string()),
+        durability: Durability::GroupCommit,
+        query_memory: Some(64_000_000),
+        soft_limits: SoftLimits { row_size: Some(0.8), result_rows: Some(1000), disk_budget: Some(0.9), ..Default::default() },
+        max_cursors: 4,
+        insert_budget: 65536,
+        connection_rates: RateLimits { queries: Some(100.0), rows_scanned: Some(0.5), bytes_returned: None },
+        primary: Some("10.0.0.1:1337".to_string()),
+        primary_token: Some("replica-secret".to_string()),
+        ..ServerConfig::default()
+    });
+    assert!(matches!(config.storage(), StorageCfg::Managed { durability: Durability::GroupCommit, .. }));
+}
+
+#[test]
+fn test_flags_override_the_file() {
+    // GIVEN
+    let path = random_temp_file();
+    std::fs::write(&path, FILE).unwrap();
+
+    // WHEN
+    let config = ServerConfig::from_args(args(&["--port", "6000", "--config", &path, "--text", "--tables", "memory", "--idle-timeout", "0", "--keepalive", "60", "--metrics-port", "9100", "--backlog", "5000", "--primary", "10.0.0.2:1337"])).unwrap();
+
+    // THEN
+    assert_eq!(config.bind, "0.0.0.0");
+    assert_eq!(config.port, 6000);
+    assert_eq!(config.protocol, Protocol::Text);
+    assert_eq!(config.tables, Some(TableStorage::Memory));
+    assert_eq!(config.tcp, TcpSettings { idle_timeout: None, keepalive: Some(Duration::from_secs(60)) });
+    assert_eq!(config.metrics_port, Some(9100));
+    assert_eq!(config.replication_backlog, Some(5000));
+    assert_eq!(config.primary.as_deref(), Some("10.0.0.2:1337"));
+    assert!(matches!(config.storage(), StorageCfg::InMemory));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_errors() {
+    // WHEN
+    let errors = [
+        ServerConfig::from_toml("[server]\nprot = \"text\""),
+        ServerConfig::from_toml("[server]\nport = 70000"),
+        ServerConfig::from_toml("\n[server]\nbind = 127"),
+        ServerConfig::from_toml("[storage]\ndurability = \"always\""),
+        ServerConfig::from_toml("[server\n"),
+        ServerConfig::from_toml("[storage]\ntables = \"disk\""),
+        ServerConfig::from_toml("[limits.connection]\nqueries = -1"),
+        ServerConfig::from_toml("[limits.user]\nqueries = 10"),
+        ServerConfig::from_toml("[replication]\ntoken = \"replica-secret\""),
+        ServerConfig::from_args(args(&["--workers", "0"])),
+        ServerConfig::from_args(args(&["--port"])),
+        ServerConfig::from_args(args(&["/var/lib/rudibi"])),
+        ServerConfig::from_args(args(&["--tls-cert", "server.pem"])),
+    ].map(|config| config.unwrap_err());
+
+    // THEN
+    let input_error = |msg: &str| DbError::InputError(msg.to_string());
+    assert_eq!(errors, [
+        input_error("Line 2: Unknown setting server.prot"),
+        input_error("Line 2: 70000 isn't between 1 and 65535"),
+        input_error("Line 3: Expected a string, got Integer(127)"),
+        input_error("Line 2: Unknown durability always, expected flush, sync-per-batch, sync-per-commit or group-commit"),
+        input_error("Line 1: Bad table header [server"),
+        input_error("Disk tables need a data_dir"),
+        input_error("Line 2: -1 isn't a rate"),
+        input_error("Rate limits per user need users or tokens"),
+        input_error("A replication token needs a primary"),
+        input_error("--workers: 0 isn't between 1 and 9223372036854775807"),
+        input_error("--port needs a value"),
+        input_error("Unknown argument /var/lib/rudibi"),
+        input_error("TLS needs both a cert and a key"),
+    ]);
+}
@@ -0,0 +1,116 @@
+
+use rudibi_server::engine::DbError;
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::{Reply, Server};
+use rudibi_server::testlib::{executes, fruits_server, serve, serve_text};
+
+fn cursors_open(server: &Server) -> String {
+    server.metrics().lines().find(|line| line.starts_with("rudibi_cursors_open ")).unwrap().to_string()
+}
+
+#[test]
+fn test_fetch_in_batches() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN
+    let output = serve_text(&server, &[
+        "SET FORMAT CSV",
+        "DECLARE ripe CURSOR FOR SELECT id, name FROM Fruits WHERE id > 100",
+        "FETCH 2 FROM ripe",
+        "FETCH ripe",
+        "FETCH 5 FROM ripe",
+        "CLOSE ripe",
+        "FETCH ripe",
+    ]);
+
+    // THEN the rows come in batches, then none
+    assert_eq!(output, [
+        "OK\n\n",
+        "DECLARED\n\n",
+        "id,name\n200,banana\n300,banana\n\n",
+        "id,name\n400,cherry\n\n",
+        "id,name\n\n",
+        "CLOSED\n\n",
+        "ERROR CursorNotFound(\"ripe\")\n\n",
+    ].concat());
+}
+
+#[test]
+fn test_cursors_in_frames() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN a cursor is declared twice, and one is fetched that wasn't
+    let replies = serve(&server, &executes(&[
+        "DECLARE all CURSOR FOR SELECT * FROM Fruits",
+        "DECLARE all CURSOR FOR SELECT id FROM Fruits",
+        "FETCH 1 FROM other",
+        "DELETE FROM Fruits",
+        "FETCH 1 FROM all",
+    ]));
+
+    // THEN the cursor keeps the rows it was declared with
+    assert_eq!(replies[..3], [
+        Frame::new(frame::REPLY, 0, "DECLARED"),
+        Frame::error(1, &ErrorReply::from(&DbError::CursorAlreadyExists("all".to_string()))),
+        Frame::error(2, &ErrorReply::from(&DbError::CursorNotFound("other".to_string()))),
+    ]);
+    assert_eq!(replies[4].tag, frame::REPLY);
+    assert!(String::from_utf8(replies[4].payload.clone()).unwrap().contains("apple"));
+}
+
+#[test]
+fn test_cursors_per_connection() {
+    // GIVEN
+    let server = fruits_server().with_max_cursors(2);
+    let (first, second) = (server.open_session(), server.open_session());
+
+    // WHEN
+    let declared: Vec<_> = ["a", "b", "c"].iter()
+        .map(|name| server.handle_as(&first, &format!("DECLARE {name} CURSOR FOR SELECT id FROM Fruits")))
+        .collect();
+    let other = server.handle_as(&second, "DECLARE a CURSOR FOR SELECT id FROM Fruits");
+    let missing = server.handle_as(&second, "FETCH 1 FROM b");
+    let open = cursors_open(&server);
+    drop(first);
+
+    // THEN each connection has its own, up to the limit, closed once the connection ends
+    assert!(matches!(declared[..], [Ok(Reply::Declared), Ok(Reply::Declared), Err(DbError::TooManyCursors { max: 2 })]));
+    assert!(matches!(other, Ok(Reply::Declared)));
+    assert_eq!(missing.unwrap_err(), DbError::CursorNotFound("b".to_string()));
+    assert_eq!(open, "rudibi_cursors_open 3");
+    assert_eq!(cursors_open(&server), "rudibi_cursors_open 1");
+}
+
+#[test]
+fn test_connection_ending_closes_cursors() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN
+    serve(&server, &executes(&["DECLARE ripe CURSOR FOR SELECT id FROM Fruits", "FETCH 1 FROM ripe"]));
+    serve_text(&server, &["DECLARE ripe CURSOR FOR SELECT id FROM Fruits"]);
+
+    // THEN
+    assert_eq!(cursors_open(&server), "rudibi_cursors_open 0");
+}
+
+#[test]
+fn test_cursor_in_transaction() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN a cursor is declared over a write of a transaction rolled back after
+    let output = serve_text(&server, &[
+        "BEGIN",
+        "INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')",
+        "DECLARE late CURSOR FOR SELECT name FROM Fruits WHERE id > 300",
+        "ROLLBACK",
+        "SET FORMAT CSV; FETCH 5 FROM late",
+    ]);
+
+    // THEN
+    assert!(output.ends_with("name\ncherry\nkiwi\n\n"));
+}
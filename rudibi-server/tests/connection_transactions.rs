@@ -0,0 +1,89 @@
+
+use rudibi_server::engine::DbError;
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{executes, fruits_server, serve, serve_text};
+
+fn kiwis(server: &Server) -> usize {
+    server.database().count("Fruits", &Eq(ColumnRef("name"), Const("kiwi".into()))).unwrap()
+}
+
+#[test]
+fn test_commit_and_rollback() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN a transaction is rolled back, then another committed
+    let output = serve_text(&server, &[
+        "BEGIN",
+        "INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')",
+        "SET FORMAT CSV; SELECT name FROM Fruits WHERE id > 300",
+        "ROLLBACK",
+        "begin; DELETE FROM Fruits WHERE id = 100; INSERT INTO Fruits (id, name) VALUES (600, 'kiwi'); commit",
+    ]);
+
+    // THEN the transaction saw its writes, and only those of the committed one were kept
+    assert_eq!(output, [
+        "BEGIN\n\n",
+        "INSERTED 1\n\n",
+        "OK\n\n",
+        "name\ncherry\nkiwi\n\n",
+        "ROLLBACK\n\n",
+        "BEGIN\n\n",
+        "DELETED 1\n\n",
+        "INSERTED 1\n\n",
+        "COMMIT\n\n",
+    ].concat());
+    assert_eq!(kiwis(&server), 1);
+    assert_eq!(server.database().count("Fruits", &Eq(ColumnRef("id"), Const(100u32.into()))).unwrap(), 0);
+}
+
+#[test]
+fn test_connection_ending_rolls_back() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN the connection ends with its transaction open
+    let replies = serve(&server, &executes(&["BEGIN", "INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')"]));
+
+    // THEN
+    assert_eq!(replies, [Frame::new(frame::REPLY, 0, "BEGIN"), Frame::new(frame::REPLY, 1, "INSERTED 1")]);
+    assert_eq!(kiwis(&server), 0);
+    assert_eq!(server.database().count("Fruits", &True).unwrap(), 4);
+}
+
+#[test]
+fn test_streamed_select_in_transaction() {
+    // GIVEN
+    let server = fruits_server();
+    let mut requests = executes(&["BEGIN", "INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')"]);
+    requests.push(Frame::new(frame::STREAM, 2, "SELECT id, name FROM Fruits WHERE id > 300"));
+
+    // WHEN
+    let replies = serve(&server, &requests);
+
+    // THEN the stream has the row inserted in the transaction
+    assert_eq!(replies[2..], [Frame::new(frame::ROWS, 2, "id,name\n400,cherry\n500,kiwi\n"), Frame::new(frame::DONE, 2, "SELECTED 2")]);
+}
+
+#[test]
+fn test_misplaced_statements() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN
+    let replies = serve(&server, &executes(&["COMMIT", "BEGIN", "BEGIN", "CREATE TABLE Nuts (id U32)", "ROLLBACK", "ROLLBACK"]));
+
+    // THEN only inserts, deletes and selects run in a transaction, which stays open
+    assert_eq!(replies, [
+        Frame::error(0, &ErrorReply::from(&DbError::InputError("No transaction is open".to_string()))),
+        Frame::new(frame::REPLY, 1, "BEGIN"),
+        Frame::error(2, &ErrorReply::from(&DbError::InputError("A transaction is open already".to_string()))),
+        Frame::error(3, &ErrorReply::from(&DbError::UnsupportedOperation("Only inserts, deletes and selects run in a transaction, not create".to_string()))),
+        Frame::new(frame::REPLY, 4, "ROLLBACK"),
+        Frame::error(5, &ErrorReply::from(&DbError::InputError("No transaction is open".to_string()))),
+    ]);
+    assert!(server.database().schema_for("Nuts").is_err());
+}
@@ -0,0 +1,90 @@
+use rudibi_server::dtype::{ColumnValue::*};
+use rudibi_server::engine::{CorruptRowPolicy, Database, DbError, QueryOptions, Row, ScanStats, StorageCfg};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{fruits_schema, check_equality, with_tmp};
+use rudibi_server::rows;
+
+// Fruits table where the name of the second row isn't valid UTF-8
+// Inserts only validate column sizes, so this gets stored as-is
+fn corrupt_fruits(storage: StorageCfg) -> Database {
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), storage).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![
+        [100u32, "apple"],
+        [200u32, [0xFFu8, 0xFE]],
+        [300u32, "cherry"]
+    ]).unwrap();
+    db
+}
+
+fn name_filter() -> rudibi_server::query::Bool<'static> {
+    Neq(ColumnRef("name"), Const(UTF8("apple")))
+}
+
+fn test_corrupt_row_fails_query(storage: StorageCfg) {
+    // GIVEN
+    let db = corrupt_fruits(storage);
+
+    // WHEN
+    let result = db.select(&[ColumnRef("id")], "Fruits", &name_filter());
+
+    // THEN
+    assert!(matches!(result, Err(DbError::DatabaseIntegrityError(_))), "{result:#?}");
+}
+
+#[test]
+fn test_corrupt_row_fails_query_in_mem() {
+    test_corrupt_row_fails_query(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_corrupt_row_fails_query_on_disk() {
+    with_tmp(test_corrupt_row_fails_query);
+}
+
+fn test_corrupt_row_skipped(storage: StorageCfg) {
+    // GIVEN
+    let db = corrupt_fruits(storage);
+
+    // WHEN
+    let options = QueryOptions { corrupt_rows: CorruptRowPolicy::Skip, ..Default::default() };
+    let results = db.select_with_options(&[ColumnRef("id")], "Fruits", &name_filter(), &options).unwrap();
+
+    // THEN
+    check_equality(&results, &[[U32(300)]]);
+    assert_eq!(results.scan_stats, ScanStats { rows_scanned: 3, rows_skipped: 1, rows_raw: 0 });
+}
+
+#[test]
+fn test_corrupt_row_skipped_in_mem() {
+    test_corrupt_row_skipped(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_corrupt_row_skipped_on_disk() {
+    with_tmp(test_corrupt_row_skipped);
+}
+
+fn test_corrupt_row_returned_raw(storage: StorageCfg) {
+    // GIVEN
+    let db = corrupt_fruits(storage);
+
+    // WHEN
+    let options = QueryOptions { corrupt_rows: CorruptRowPolicy::Raw, ..Default::default() };
+    let results = db.select_with_options(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &name_filter(), &options).unwrap();
+
+    // THEN
+    assert_eq!(results.len(), 2);
+    assert_eq!(results.data[0].get_column(1), &[0xFF, 0xFE]);
+    assert_eq!(results.scan_stats, ScanStats { rows_scanned: 3, rows_skipped: 0, rows_raw: 1 });
+}
+
+#[test]
+fn test_corrupt_row_returned_raw_in_mem() {
+    test_corrupt_row_returned_raw(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_corrupt_row_returned_raw_on_disk() {
+    with_tmp(test_corrupt_row_returned_raw);
+}
@@ -0,0 +1,63 @@
+use rudibi_server::dtype::{ColumnValue::*};
+use rudibi_server::engine::{Database, StorageCfg, DbError};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{empty_table, fruits_table, with_tmp};
+
+#[test]
+fn count_unknown_table() {
+    let db = Database::new();
+    assert_eq!(db.count("Unknown", &True), Err(DbError::TableNotFound("Unknown".to_string())));
+}
+
+fn count_empty(storage: StorageCfg) {
+    let db = empty_table(storage);
+    assert_eq!(db.count("EmptyTable", &True), Ok(0));
+}
+
+#[test]
+fn count_empty_in_mem() {
+    count_empty(StorageCfg::InMemory);
+}
+
+#[test]
+fn count_empty_on_disk() {
+    with_tmp(count_empty);
+}
+
+fn count_all_after_delete(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+    assert_eq!(db.count("Fruits", &True), Ok(4));
+
+    // WHEN
+    db.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+
+    // THEN
+    assert_eq!(db.count("Fruits", &True), Ok(2));
+}
+
+#[test]
+fn count_all_after_delete_in_mem() {
+    count_all_after_delete(StorageCfg::InMemory);
+}
+
+#[test]
+fn count_all_after_delete_on_disk() {
+    with_tmp(count_all_after_delete);
+}
+
+fn count_with_filter(storage: StorageCfg) {
+    let db = fruits_table(storage);
+    assert_eq!(db.count("Fruits", &Gte(ColumnRef("id"), Const(U32(200)))), Ok(3));
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("invalid"), Const(U32(200)))), Err(DbError::ColumnNotFound("invalid".to_string())));
+}
+
+#[test]
+fn count_with_filter_in_mem() {
+    count_with_filter(StorageCfg::InMemory);
+}
+
+#[test]
+fn count_with_filter_on_disk() {
+    with_tmp(count_with_filter);
+}
@@ -0,0 +1,63 @@
+use rudibi_server::cursor::Cursors;
+use rudibi_server::dtype::{ColumnValue::*};
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{fruits_table, check_equality};
+
+#[test]
+fn fetch_in_batches() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut cursors = Cursors::default();
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    cursors.declare("all_fruits", results).unwrap();
+
+    // WHEN
+    let first = cursors.fetch("all_fruits", 3).unwrap();
+    let second = cursors.fetch("all_fruits", 3).unwrap();
+    let exhausted = cursors.fetch("all_fruits", 3).unwrap();
+
+    // THEN
+    check_equality(&first, &[[U32(100)], [U32(200)], [U32(300)]]);
+    check_equality(&second, &[[U32(400)]]);
+    assert_eq!(exhausted.len(), 0);
+    assert_eq!(exhausted.schema[0].name, "id");
+}
+
+#[test]
+fn close_cursor() {
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut cursors = Cursors::default();
+    cursors.declare("c", db.select(&[ColumnRef("id")], "Fruits", &True).unwrap()).unwrap();
+
+    cursors.close("c").unwrap();
+
+    assert!(cursors.is_empty());
+    assert_eq!(cursors.fetch("c", 1).unwrap_err(), DbError::CursorNotFound("c".to_string()));
+    assert_eq!(cursors.close("c").unwrap_err(), DbError::CursorNotFound("c".to_string()));
+}
+
+#[test]
+fn duplicate_cursor_name() {
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut cursors = Cursors::default();
+    cursors.declare("c", db.select(&[ColumnRef("id")], "Fruits", &True).unwrap()).unwrap();
+
+    let result = cursors.declare("c", db.select(&[ColumnRef("id")], "Fruits", &True).unwrap());
+
+    assert_eq!(result.unwrap_err(), DbError::CursorAlreadyExists("c".to_string()));
+}
+
+#[test]
+fn open_cursor_limit() {
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut cursors = Cursors::new(2);
+    cursors.declare("a", db.select(&[ColumnRef("id")], "Fruits", &True).unwrap()).unwrap();
+    cursors.declare("b", db.select(&[ColumnRef("id")], "Fruits", &True).unwrap()).unwrap();
+
+    let result = cursors.declare("c", db.select(&[ColumnRef("id")], "Fruits", &True).unwrap());
+    assert_eq!(result.unwrap_err(), DbError::TooManyCursors { max: 2 });
+
+    cursors.close("a").unwrap();
+    cursors.declare("c", db.select(&[ColumnRef("id")], "Fruits", &True).unwrap()).unwrap();
+}
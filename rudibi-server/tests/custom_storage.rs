@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{Database, DbError, Row, StorageCfg};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::storage::{InMemoryStorage, RowId, Storage, TableIterator};
+use rudibi_server::testlib::{check_equality, fruits_schema};
+
+// Backend from outside the crate, keeps rows in memory and counts the stores
+struct CountingStorage {
+    rows: InMemoryStorage,
+    stored: Arc<AtomicUsize>,
+}
+
+impl Storage for CountingStorage {
+    fn store(&mut self, rows: &[Row], column_mapping: &Vec<usize>) -> Vec<RowId> {
+        self.stored.fetch_add(rows.len(), Ordering::Relaxed);
+        self.rows.store(rows, column_mapping)
+    }
+
+    fn scan(&self) -> TableIterator<'_> {
+        TableIterator::new(Box::new(self.rows.scan()))
+    }
+
+    fn delete_rows(&mut self, row_ids: Vec<RowId>) {
+        self.rows.delete_rows(row_ids);
+    }
+
+    fn row_count(&self) -> usize {
+        self.rows.row_count()
+    }
+}
+
+#[test]
+fn test_custom_storage_backs_a_table() {
+    // GIVEN
+    let stored = Arc::new(AtomicUsize::new(0));
+    let storage = CountingStorage { rows: InMemoryStorage::new(fruits_schema()), stored: stored.clone() };
+    let mut db = Database::new();
+
+    // WHEN
+    db.new_table_with_storage(&fruits_schema(), Box::new(storage)).unwrap();
+    db.insert("Fruits", &["name", "id"], rows![["apple", 100u32], ["banana", 200u32]]).unwrap();
+    db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+
+    // THEN
+    assert_eq!(stored.load(Ordering::Relaxed), 2);
+    let results = db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(200), UTF8("banana")]]);
+}
+
+#[test]
+fn test_custom_storage_table_name_must_be_free() {
+    // GIVEN
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), StorageCfg::InMemory).unwrap();
+
+    // WHEN
+    let result = db.new_table_with_storage(&fruits_schema(), Box::new(InMemoryStorage::new(fruits_schema())));
+
+    // THEN
+    assert!(matches!(result, Err(DbError::TableAlreadyExists(_))));
+}
@@ -0,0 +1,40 @@
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::display::{format_table, BinaryFormat, DisplayOptions};
+use rudibi_server::engine::StorageCfg;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::fruits_table;
+
+#[test]
+fn format_fruits_table() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let results = db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &True).unwrap();
+
+    // WHEN
+    let options = DisplayOptions { max_width: Some(4), binary: BinaryFormat::Hex };
+    let table = format_table(&results, &options);
+
+    // THEN
+    assert_eq!(table, "\
+| id  | name |
+| --- | ---- |
+| 100 | app… |
+| 200 | ban… |
+| 300 | ban… |
+| 400 | che… |
+(4 rows)
+");
+}
+
+#[test]
+fn result_set_displays_as_table() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+
+    // WHEN
+    let results = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+
+    // THEN
+    assert_eq!(results.format_table(), "| name  |\n| ----- |\n| apple |\n(1 rows)\n");
+    assert_eq!(results.to_string(), results.format_table());
+}
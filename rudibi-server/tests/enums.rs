@@ -0,0 +1,83 @@
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, with_tmp};
+use rudibi_server::rows;
+
+fn colors() -> DataType {
+    DataType::ENUM { labels: vec!["red".to_string(), "green".to_string(), "blue".to_string()] }
+}
+
+fn paints(storage: StorageCfg) -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Paints", vec![
+        Column::new("id", DataType::U32),
+        Column::new("color", colors()),
+    ]), storage).unwrap();
+    db.insert("Paints", &["color", "id"], rows![
+        ["green", 1u32],
+        ["blue", 2u32],
+        ["green", 3u32],
+    ]).unwrap();
+    db
+}
+
+fn filter_by_label(storage: StorageCfg) {
+    // GIVEN
+    let db = paints(storage);
+
+    // WHEN
+    let results = db.select(&[ColumnRef("id"), ColumnRef("color")], "Paints", &Eq(ColumnRef("color"), Const(UTF8("green")))).unwrap();
+
+    // THEN
+    check_equality(&results, &[
+        [U32(1), UTF8("green")],
+        [U32(3), UTF8("green")],
+    ]);
+    // Stored as the one byte index, not the label
+    assert_eq!(results.data[0].get_column(1), &[1]);
+}
+
+#[test]
+fn filter_by_label_in_mem() {
+    filter_by_label(StorageCfg::InMemory);
+}
+
+#[test]
+fn filter_by_label_on_disk() {
+    with_tmp(filter_by_label);
+}
+
+#[test]
+fn insert_unknown_label() {
+    let db = paints(StorageCfg::InMemory);
+
+    let result = db.insert("Paints", &["id", "color"], rows![[4u32, "purple"]]);
+
+    assert_eq!(result, Err(DbError::InvalidEnumLabel { column: "color".to_string(), label: "purple".to_string() }));
+    assert_eq!(db.count("Paints", &True), Ok(3));
+}
+
+#[test]
+fn wide_enum_uses_two_bytes() {
+    let labels: Vec<String> = (0..300).map(|i| format!("label{i}")).collect();
+    let mut db = Database::new();
+    db.new_table(&Table::new("Wide", vec![Column::new("value", DataType::ENUM { labels })]), StorageCfg::InMemory).unwrap();
+
+    db.insert("Wide", &["value"], rows![["label299"], ["label0"]]).unwrap();
+
+    let results = db.select(&[ColumnRef("value")], "Wide", &True).unwrap();
+    check_equality(&results, &[[UTF8("label299")], [UTF8("label0")]]);
+    assert_eq!(results.data[0].get_column(0), &299u16.to_le_bytes());
+}
+
+#[test]
+fn too_many_labels() {
+    let labels: Vec<String> = (0..=u16::MAX as usize + 1).map(|i| format!("label{i}")).collect();
+    let mut db = Database::new();
+
+    let result = db.new_table(&Table::new("Huge", vec![Column::new("value", DataType::ENUM { labels })]), StorageCfg::InMemory);
+
+    assert_eq!(result, Err(DbError::InputError("ENUM value has 65537 labels, more than the 65536 allowed".to_string())));
+    assert!(db.schema_for("Huge").is_err());
+}
@@ -0,0 +1,85 @@
+use std::io::Cursor;
+
+use rudibi_server::dtype::TypeError;
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::errors::{self, ErrorReply};
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::permissions::Privilege;
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+
+#[test]
+fn test_payload() {
+    // GIVEN
+    let err = DbError::TableNotFound("Vegetables".to_string());
+
+    // WHEN
+    let payload = ErrorReply::from(&err).to_payload();
+
+    // THEN
+    assert_eq!(payload, b"1001\0TableNotFound(\"Vegetables\")\0table=Vegetables");
+    assert_eq!(ErrorReply::parse(&payload), Some(ErrorReply::from(&err)));
+    assert_eq!(ErrorReply::parse(b"1001"), None);
+    assert_eq!(ErrorReply::parse(b"NotFound\0Table"), None);
+}
+
+#[test]
+fn test_codes_are_stable() {
+    // WHEN
+    let codes = [
+        DbError::TableNotFound("Fruits".to_string()),
+        DbError::ColumnNotFound("price".to_string()),
+        DbError::InputError("Expected FROM".to_string()),
+        DbError::QueryError(TypeError::DivisionByZero),
+        DbError::QuotaExceeded { table: "Fruits".to_string(), quota: rudibi_server::limits::QuotaKind::Rows, max: 10, got: 11 },
+        DbError::ServerBusy,
+        DbError::NotAuthenticated,
+        DbError::PermissionDenied { user: "alice".to_string(), privilege: Privilege::Write, table: "Fruits".to_string() },
+        DbError::QueryCancelled,
+        DbError::StorageError("Disk full".to_string()),
+    ].map(|err| errors::code(&err));
+
+    // THEN
+    assert_eq!(codes, [1001, 1004, 2001, 2002, 3001, 3004, 4002, 4003, 5001, 9003]);
+}
+
+#[test]
+fn test_context() {
+    // WHEN
+    let denied = ErrorReply::from(&DbError::PermissionDenied { user: "alice".to_string(), privilege: Privilege::Write, table: "Fruits".to_string() });
+    let type_error = ErrorReply::from(&DbError::QueryError(TypeError::DivisionByZero));
+    let busy = ErrorReply::from(&DbError::ServerBusy);
+
+    // THEN
+    assert_eq!(denied.get("user"), Some("alice"));
+    assert_eq!(denied.get("privilege"), Some("write"));
+    assert_eq!(denied.get("table"), Some("Fruits"));
+    assert_eq!(type_error.get("type_error"), Some("division_by_zero"));
+    assert!(busy.context.is_empty());
+    assert_eq!(busy.message, "ServerBusy");
+}
+
+#[test]
+fn test_error_frames() {
+    // GIVEN
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+    let mut input = Vec::new();
+    for (id, command) in [(1, "SELECT price FROM Fruits"), (2, "INSERT INTO Fruits (id, name) VALUES ('ten', 'kiwi')"), (3, "SELEC * FROM Fruits")] {
+        Frame::new(frame::EXECUTE, id, command).write_to(&mut input).unwrap();
+    }
+    Frame::new(42, 4, "").write_to(&mut input).unwrap();
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+
+    // THEN clients tell the errors apart by their code
+    let mut output = &output[..];
+    let errors: Vec<ErrorReply> = std::iter::from_fn(|| Frame::read_from(&mut output).unwrap())
+        .inspect(|reply| assert_eq!(reply.tag, frame::ERROR))
+        .map(|reply| ErrorReply::parse(&reply.payload).unwrap())
+        .collect();
+    assert_eq!(errors.iter().map(|err| err.code).collect::<Vec<_>>(), [1004, 2001, 2001, errors::UNKNOWN_REQUEST]);
+    assert_eq!(errors[0].get("column"), Some("price"));
+    assert_eq!(errors[3].message, "Unknown request tag 42");
+}
@@ -0,0 +1,64 @@
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, Row, StorageCfg, Table};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, with_tmp};
+use rudibi_server::rows;
+
+fn measurements(storage: StorageCfg) -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Measurements", vec![
+        Column::new("id", DataType::U32),
+        Column::new("value", DataType::F64),
+    ]), storage).unwrap();
+    db.insert("Measurements", &["id", "value"], rows![
+        [1u32, 1.5f64],
+        [2u32, f64::NAN],
+        [3u32, -0.0f64],
+        [4u32, 0.0f64],
+    ]).unwrap();
+    db
+}
+
+fn nan_never_equals(storage: StorageCfg) {
+    let db = measurements(storage);
+
+    let equal = db.select(&[ColumnRef("id")], "Measurements", &Eq(ColumnRef("value"), Const(F64(f64::NAN)))).unwrap();
+    let greater = db.select(&[ColumnRef("id")], "Measurements", &Gt(ColumnRef("value"), Const(F64(1.0)))).unwrap();
+    let not_equal = db.select(&[ColumnRef("id")], "Measurements", &Neq(ColumnRef("value"), Const(F64(1.5)))).unwrap();
+
+    assert_eq!(equal.len(), 0);
+    check_equality(&greater, &[[U32(1)]]);
+    check_equality(&not_equal, &[[U32(2)], [U32(3)], [U32(4)]]);
+}
+
+#[test]
+fn nan_never_equals_in_mem() {
+    nan_never_equals(StorageCfg::InMemory);
+}
+
+#[test]
+fn nan_never_equals_on_disk() {
+    with_tmp(nan_never_equals);
+}
+
+fn nan_not_distinct_from_nan(storage: StorageCfg) {
+    let db = measurements(storage);
+
+    let nan = db.select(&[ColumnRef("id")], "Measurements", &IsNotDistinctFrom(ColumnRef("value"), Const(F64(f64::NAN)))).unwrap();
+    let zero = db.select(&[ColumnRef("id")], "Measurements", &IsNotDistinctFrom(ColumnRef("value"), Const(F64(0.0)))).unwrap();
+    let not_nan = db.select(&[ColumnRef("id")], "Measurements", &IsDistinctFrom(ColumnRef("value"), Const(F64(f64::NAN)))).unwrap();
+
+    check_equality(&nan, &[[U32(2)]]);
+    check_equality(&zero, &[[U32(3)], [U32(4)]]);
+    check_equality(&not_nan, &[[U32(1)], [U32(3)], [U32(4)]]);
+}
+
+#[test]
+fn nan_not_distinct_from_nan_in_mem() {
+    nan_not_distinct_from_nan(StorageCfg::InMemory);
+}
+
+#[test]
+fn nan_not_distinct_from_nan_on_disk() {
+    with_tmp(nan_not_distinct_from_nan);
+}
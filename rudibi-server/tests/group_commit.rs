@@ -0,0 +1,50 @@
+use std::sync::Arc;
+use std::thread;
+
+use rudibi_server::compress::Compression;
+use rudibi_server::engine::{Database, Row, StorageCfg};
+use rudibi_server::query::Bool::*;
+use rudibi_server::storage::Durability;
+use rudibi_server::testlib::{fruits_schema, random_temp_file};
+use rudibi_server::wal::{read_wal, WalWriter};
+
+const WRITERS: u32 = 4;
+const ROWS_PER_WRITER: u32 = 50;
+
+#[test]
+fn test_concurrent_commits_are_all_durable() {
+    // GIVEN
+    let table_path = random_temp_file();
+    let wal_path = random_temp_file();
+    std::fs::remove_file(&wal_path).unwrap();
+    let wal = Arc::new(WalWriter::open(&wal_path).unwrap());
+    let mut db = Database::new();
+    db.add_observer(wal.clone());
+    let storage = StorageCfg::Disk { path: table_path.clone(), durability: Durability::GroupCommit, compression: Compression::default() };
+    db.new_table(&fruits_schema(), storage).unwrap();
+    let db = Arc::new(db);
+
+    // WHEN writers insert one row at a time
+    let writers: Vec<_> = (0..WRITERS).map(|writer| {
+        let db = db.clone();
+        thread::spawn(move || {
+            for n in 0..ROWS_PER_WRITER {
+                let id = writer * ROWS_PER_WRITER + n;
+                db.insert("Fruits", &["id", "name"], &[Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])]).unwrap();
+            }
+        })
+    }).collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    drop(db);
+
+    // THEN every commit returned with its rows in the table and the log
+    let mut reopened = Database::new();
+    let report = reopened.open_table(&fruits_schema(), &table_path, Durability::GroupCommit).unwrap();
+    assert_eq!(report.rows_recovered, (WRITERS * ROWS_PER_WRITER) as usize);
+    assert_eq!(reopened.count("Fruits", &True).unwrap(), (WRITERS * ROWS_PER_WRITER) as usize);
+    assert_eq!(read_wal(&wal_path).unwrap().len(), (WRITERS * ROWS_PER_WRITER) as usize);
+    std::fs::remove_file(&table_path).unwrap();
+    std::fs::remove_file(&wal_path).unwrap();
+}
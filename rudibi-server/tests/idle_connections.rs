@@ -0,0 +1,107 @@
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rudibi_server::auth::{hash_token, Credentials};
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::tcp::{self, TcpSettings};
+use rudibi_server::testlib::fruits_table;
+use socket2::SockRef;
+
+// Serves the first connection accepted with `settings`, giving back how that ended
+fn serve_one(settings: TcpSettings, text: bool) -> (TcpStream, thread::JoinHandle<std::io::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let served = thread::spawn(move || {
+        let (conn, _) = listener.accept()?;
+        settings.apply(&conn)?;
+        let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+        if text { server.serve_text(BufReader::new(&conn), &conn) } else { server.serve(&conn, &conn) }
+    });
+    (TcpStream::connect(address).unwrap(), served)
+}
+
+#[test]
+fn test_ping_before_authenticating() {
+    // GIVEN a server only authenticated connections may query
+    let mut credentials = Credentials::default();
+    credentials.add_token("backup", &hash_token("s3cr3t-t0k3n")).unwrap();
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory).with_credentials(credentials);
+    let mut input = Vec::new();
+    Frame::new(frame::PING, 1, "").write_to(&mut input).unwrap();
+    Frame::new(frame::EXECUTE, 2, "SELECT * FROM Fruits").write_to(&mut input).unwrap();
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    let mut text = Vec::new();
+    server.serve_text(Cursor::new("ping; SELECT * FROM Fruits\n"), &mut text).unwrap();
+
+    // THEN
+    let mut output = &output[..];
+    assert_eq!(Frame::read_from(&mut output).unwrap(), Some(Frame::new(frame::REPLY, 1, "PONG")));
+    assert_eq!(Frame::read_from(&mut output).unwrap(), Some(Frame::error(2, &ErrorReply::from(&DbError::NotAuthenticated))));
+    assert_eq!(String::from_utf8(text).unwrap(), "PONG\n\nERROR NotAuthenticated\n\n");
+}
+
+#[test]
+fn test_idle_connections_are_closed() {
+    // GIVEN
+    let settings = TcpSettings { idle_timeout: Some(Duration::from_millis(200)), ..TcpSettings::default() };
+    let (mut conn, served) = serve_one(settings, false);
+    Frame::new(frame::PING, 1, "").write_to(&mut conn).unwrap();
+    assert_eq!(Frame::read_from(&mut conn).unwrap(), Some(Frame::new(frame::REPLY, 1, "PONG")));
+
+    // WHEN the client sends nothing more
+    let started = Instant::now();
+    let closed = Frame::read_from(&mut conn);
+
+    // THEN the server hangs up
+    assert_eq!(closed.unwrap(), None);
+    assert!(started.elapsed() < Duration::from_secs(5));
+    assert!(tcp::timed_out(&served.join().unwrap().unwrap_err()));
+}
+
+#[test]
+fn test_busy_connections_stay_open() {
+    // GIVEN
+    let settings = TcpSettings { idle_timeout: Some(Duration::from_millis(300)), ..TcpSettings::default() };
+    let (mut conn, served) = serve_one(settings, true);
+    let mut replies = BufReader::new(conn.try_clone().unwrap());
+
+    // WHEN each request comes before the timeout, for longer than it altogether
+    for _ in 0..5 {
+        thread::sleep(Duration::from_millis(100));
+        writeln!(conn, "PING").unwrap();
+        let mut reply = String::new();
+        replies.read_line(&mut reply).unwrap();
+        assert_eq!(reply, "PONG\n");
+        replies.read_line(&mut reply).unwrap();
+    }
+    conn.shutdown(std::net::Shutdown::Write).unwrap();
+
+    // THEN
+    served.join().unwrap().unwrap();
+}
+
+#[test]
+fn test_keepalive() {
+    // GIVEN
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (conn, _) = listener.accept().unwrap();
+
+    // WHEN
+    TcpSettings { keepalive: Some(Duration::from_secs(45)), ..TcpSettings::default() }.apply(&conn).unwrap();
+
+    // THEN
+    let socket = SockRef::from(&conn);
+    assert!(socket.keepalive().unwrap());
+    #[cfg(target_os = "linux")]
+    assert_eq!(socket.tcp_keepalive_time().unwrap(), Duration::from_secs(45));
+    assert_eq!(conn.read_timeout().unwrap(), None);
+}
@@ -0,0 +1,186 @@
+use std::ops::Bound::*;
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, Row, StorageCfg};
+use rudibi_server::index::{IndexKey, IndexKind};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{check_equality, fruits_table, with_tmp};
+
+fn test_ordered_index_follows_changes(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.create_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    db.insert("Fruits", &["id", "name"], rows![[250u32, "kiwi"], [50u32, "lime"]]).unwrap();
+    db.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+
+    // THEN
+    let index = db.index_for("Fruits", "id").unwrap();
+    let ids: Vec<u64> = index.range(Included(&IndexKey::U32(50)), Excluded(&IndexKey::U32(400))).collect();
+    assert_eq!(ids, vec![5, 0, 4]);
+    assert_eq!(index.len(), 4);
+}
+
+#[test]
+fn test_ordered_index_follows_changes_in_mem() {
+    test_ordered_index_follows_changes(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_ordered_index_follows_changes_on_disk() {
+    with_tmp(test_ordered_index_follows_changes);
+}
+
+#[test]
+fn test_index_survives_rename() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.create_index("Fruits", "name", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    db.rename_table("Fruits", "Produce").unwrap();
+
+    // THEN
+    assert!(db.index_for("Fruits", "name").is_none());
+    assert_eq!(db.index_for("Produce", "name").unwrap().get(&IndexKey::UTF8("banana".to_string())), &[1, 2]);
+}
+
+#[test]
+fn test_create_and_drop_index_errors() {
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.create_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    assert_eq!(db.create_index("Fruits", "id", IndexKind::Ordered), Err(DbError::IndexAlreadyExists("Fruits.id".to_string())));
+    assert_eq!(db.create_index("Fruits", "weight", IndexKind::Ordered), Err(DbError::ColumnNotFound("weight".to_string())));
+    db.drop_index("Fruits", "id").unwrap();
+    assert_eq!(db.drop_index("Fruits", "id"), Err(DbError::IndexNotFound("Fruits.id".to_string())));
+}
+
+fn test_select_uses_index(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.create_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    let point = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(300)))).unwrap();
+    let range = db.select(&[ColumnRef("id")], "Fruits", &And(
+        Box::new(Gte(Const(U32(300)), ColumnRef("id"))),
+        Box::new(Eq(ColumnRef("name"), Const(UTF8("banana")))),
+    )).unwrap();
+
+    // THEN
+    check_equality(&point, &[[UTF8("banana")]]);
+    assert_eq!(point.scan_stats.rows_scanned, 1);
+    check_equality(&range, &[[U32(200)], [U32(300)]]);
+    assert_eq!(range.scan_stats.rows_scanned, 3);
+}
+
+#[test]
+fn test_select_uses_index_in_mem() {
+    test_select_uses_index(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_select_uses_index_on_disk() {
+    with_tmp(test_select_uses_index);
+}
+
+#[test]
+fn test_indexed_select_still_type_checks() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.create_index("Fruits", "id", IndexKind::Ordered).unwrap();
+    db.create_index("Fruits", "name", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    let mismatched = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(UTF8("100"))));
+    let unordered = db.select(&[ColumnRef("name")], "Fruits", &Gt(ColumnRef("name"), Const(UTF8("zzz"))));
+
+    // THEN
+    assert!(matches!(mismatched, Err(DbError::QueryError(_))), "{mismatched:?}");
+    assert!(matches!(unordered, Err(DbError::QueryError(_))), "{unordered:?}");
+}
+
+fn test_unique_index_rejects_duplicates(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.create_unique_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    let existing = db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"], [100u32, "lime"]]);
+    let within_batch = db.insert("Fruits", &["id", "name"], rows![[600u32, "kiwi"], [600u32, "lime"]]);
+
+    // THEN
+    assert_eq!(existing, Err(DbError::DuplicateKey { index: "Fruits.id".to_string(), key: IndexKey::U32(100) }));
+    assert_eq!(within_batch, Err(DbError::DuplicateKey { index: "Fruits.id".to_string(), key: IndexKey::U32(600) }));
+    assert_eq!(db.count("Fruits", &True), Ok(4));
+    assert!(db.index_for("Fruits", "id").unwrap().contains(&IndexKey::U32(400)));
+    assert!(!db.index_for("Fruits", "id").unwrap().contains(&IndexKey::U32(500)));
+}
+
+#[test]
+fn test_unique_index_rejects_duplicates_in_mem() {
+    test_unique_index_rejects_duplicates(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_unique_index_rejects_duplicates_on_disk() {
+    with_tmp(test_unique_index_rejects_duplicates);
+}
+
+#[test]
+fn test_unique_index_over_duplicate_values() {
+    let mut db = fruits_table(StorageCfg::InMemory);
+
+    let result = db.create_unique_index("Fruits", "name", IndexKind::Ordered);
+
+    assert_eq!(result, Err(DbError::DuplicateKey { index: "Fruits.name".to_string(), key: IndexKey::UTF8("banana".to_string()) }));
+    assert!(db.index_for("Fruits", "name").is_none());
+}
+
+#[test]
+fn test_unique_index_allows_reinsert_after_delete() {
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.create_unique_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+
+    assert_eq!(db.insert("Fruits", &["id", "name"], rows![[100u32, "apricot"]]), Ok(1));
+}
+
+fn test_reindex_consistent_indexes(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.create_index("Fruits", "name", IndexKind::Ordered).unwrap();
+    db.create_unique_index("Fruits", "id", IndexKind::Ordered).unwrap();
+    db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(200)))).unwrap();
+
+    // WHEN
+    let checks = db.reindex("Fruits").unwrap();
+
+    // THEN
+    assert_eq!(checks.len(), 2);
+    assert!(checks.iter().all(|check| check.is_consistent()), "{checks:?}");
+    assert_eq!(db.index_for("Fruits", "name").unwrap().get(&IndexKey::UTF8("banana".to_string())), &[2]);
+    assert!(db.index_for("Fruits", "id").unwrap().unique);
+}
+
+#[test]
+fn test_reindex_consistent_indexes_in_mem() {
+    test_reindex_consistent_indexes(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_reindex_consistent_indexes_on_disk() {
+    with_tmp(test_reindex_consistent_indexes);
+}
+
+#[test]
+fn test_reindex_without_indexes() {
+    let db = fruits_table(StorageCfg::InMemory);
+
+    assert_eq!(db.reindex("Fruits"), Ok(vec![]));
+    assert!(matches!(db.reindex("Vegetables"), Err(DbError::TableNotFound(_))));
+}
@@ -0,0 +1,126 @@
+
+use rudibi_server::dtype::{ColumnValue::*};
+use rudibi_server::engine::{Database, DbError, Row, StorageCfg};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::ingest::{InsertStream, WindowUpdate};
+use rudibi_server::protocol::{Command, Literal};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::serial;
+use rudibi_server::testlib::{check_equality, fruits_schema, fruits_server, serve, with_tmp};
+use rudibi_server::rows;
+
+fn stream_in_chunks(storage: StorageCfg) {
+    // GIVEN
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), storage).unwrap();
+    let mut stream = InsertStream::open(&db, "Fruits", &["id", "name"], 2).unwrap();
+
+    // WHEN
+    let first = stream.push(&db, rows![[100u32, "apple"], [200u32, "banana"]]).unwrap();
+    let second = stream.push(&db, rows![[300u32, "cherry"]]).unwrap();
+
+    // THEN
+    assert_eq!(first, WindowUpdate { accepted: 2, window: 2 });
+    assert_eq!(second, WindowUpdate { accepted: 1, window: 2 });
+    assert_eq!(stream.finish(&db), Ok(3));
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(100)], [U32(200)], [U32(300)]]);
+}
+
+#[test]
+fn stream_in_chunks_in_mem() {
+    stream_in_chunks(StorageCfg::InMemory);
+}
+
+#[test]
+fn stream_in_chunks_on_disk() {
+    with_tmp(stream_in_chunks);
+}
+
+#[test]
+fn chunk_larger_than_window() {
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), StorageCfg::InMemory).unwrap();
+    let mut stream = InsertStream::open(&db, "Fruits", &["id", "name"], 1).unwrap();
+
+    let result = stream.push(&db, rows![[100u32, "apple"], [200u32, "banana"]]);
+
+    assert_eq!(result, Err(DbError::FlowControlViolation { window: 1, got: 2 }));
+    assert_eq!(db.count("Fruits", &True), Ok(0));
+}
+
+#[test]
+fn open_stream_with_missing_column() {
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), StorageCfg::InMemory).unwrap();
+
+    let result = InsertStream::open(&db, "Fruits", &["id"], 10);
+
+    assert!(matches!(result, Err(DbError::InvalidColumnCount { expected: 2, got: 1 })));
+}
+
+fn insert(rows: &[(u32, &str)]) -> Vec<u8> {
+    let (ids, names): (Vec<String>, Vec<&str>) = rows.iter().map(|(id, name)| (id.to_string(), *name)).unzip();
+    let rows = ids.iter().zip(names).map(|(id, name)| vec![Literal::Number(id), Literal::Text(name)]).collect();
+    serial::encode(&Command::Insert { table: "Fruits", columns: vec!["id", "name"], rows })
+}
+
+#[test]
+fn test_streamed_in_frames() {
+    // GIVEN a budget with room for two rows of the table, of 24 bytes at most
+    let server = fruits_server().with_insert_budget(48);
+
+    // WHEN
+    let replies = serve(&server, &[
+        Frame::new(frame::INSERT_ROWS, 1, insert(&[])),
+        Frame::new(frame::INSERT_ROWS, 2, insert(&[(500, "kiwi"), (600, "lime"), (700, "plum")])),
+        Frame::new(frame::INSERT_ROWS, 3, insert(&[(500, "kiwi"), (600, "lime")])),
+        Frame::new(frame::INSERT_DONE, 4, ""),
+        Frame::new(frame::INSERT_DONE, 5, ""),
+    ]);
+
+    // THEN rows are taken a window at a time, which grows once they turn out shorter
+    assert_eq!(replies, [
+        Frame::new(frame::WINDOW, 1, "0 2"),
+        Frame::error(2, &ErrorReply::from(&DbError::FlowControlViolation { window: 2, got: 3 })),
+        Frame::new(frame::WINDOW, 3, "2 6"),
+        Frame::new(frame::REPLY, 4, "INSERTED 2"),
+        Frame::error(5, &ErrorReply::from(&DbError::InputError("No insert is streamed".to_string()))),
+    ]);
+    assert_eq!(server.database().count("Fruits", &True), Ok(6));
+}
+
+#[test]
+fn test_windows_share_the_budget() {
+    // GIVEN
+    let server = fruits_server().with_insert_budget(24 * 10);
+    let (mut first, mut second) = (server.open_session(), server.open_session());
+
+    // WHEN a stream holds most of the budget, then lets it go
+    let held = server.insert_rows_as(&mut first, &insert(&[])).unwrap();
+    let squeezed = server.insert_rows_as(&mut second, &insert(&[(500, "kiwi")])).unwrap();
+    server.insert_done_as(&mut first).unwrap();
+    let freed = server.insert_rows_as(&mut second, &insert(&[(600, "lime")])).unwrap();
+
+    // THEN
+    assert_eq!(held, WindowUpdate { accepted: 0, window: 10 });
+    assert_eq!(squeezed, WindowUpdate { accepted: 1, window: 1 });
+    assert_eq!(freed.window, 240 / 8);
+    assert_eq!(server.insert_done_as(&mut second), Ok(2));
+}
+
+#[test]
+fn test_streamed_into_one_table() {
+    // GIVEN
+    let server = fruits_server();
+    let mut session = server.open_session();
+    server.insert_rows_as(&mut session, &insert(&[(500, "kiwi")])).unwrap();
+    let other = Command::Insert { table: "Fruits", columns: vec!["name", "id"], rows: vec![] };
+
+    // WHEN
+    let result = server.insert_rows_as(&mut session, &serial::encode(&other));
+
+    // THEN
+    assert_eq!(result, Err(DbError::InputError("Rows are streamed into other columns until INSERT_DONE".to_string())));
+}
@@ -0,0 +1,107 @@
+use std::io::Cursor;
+
+use rudibi_server::auth::{hash_token, Credentials, Session};
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::permissions::{Permissions, Privilege};
+use rudibi_server::protocol::{parse, Reply, Server};
+use rudibi_server::testlib::fruits_table;
+
+const STOCK: &str = "CREATE TABLE Stock (id U32, price F64, name UTF8(16), tag BUFFER(4), blob VARBINARY(9), uuid UUID, kind ENUM('fruit', 'nut'))";
+
+fn server() -> Server {
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+    server.handle(STOCK).unwrap();
+    server
+}
+
+// The values of each row, as text
+fn rows(reply: Result<Reply, DbError>) -> Vec<Vec<String>> {
+    let Reply::Selected(results) = reply.unwrap() else { panic!("Expected rows") };
+    (0..results.len()).map(|row| {
+        results.schema.iter().map(|column| results.get::<&str>(row, &column.name).unwrap().to_string()).collect()
+    }).collect()
+}
+
+#[test]
+fn test_show_tables() {
+    // WHEN
+    let tables = rows(server().handle("show tables"));
+
+    // THEN
+    assert_eq!(tables, [["Fruits"], ["Stock"]]);
+}
+
+#[test]
+fn test_describe() {
+    // GIVEN
+    let server = server();
+
+    // WHEN
+    let mut columns = rows(server.handle("DESCRIBE Stock"));
+    let missing = server.handle("DESCRIBE Vegetables");
+    columns.iter_mut().for_each(|column| column.truncate(2));
+
+    // THEN types are written as in CREATE TABLE
+    assert_eq!(columns, [
+        ["id", "U32"], ["price", "F64"], ["name", "UTF8(16)"], ["tag", "BUFFER(4)"],
+        ["blob", "VARBINARY(9)"], ["uuid", "UUID"], ["kind", "ENUM('fruit', 'nut')"],
+    ]);
+    let types: Vec<String> = columns.iter().map(|column| column.join(" ")).collect();
+    assert!(parse(&format!("CREATE TABLE Copy ({})", types.join(", "))).is_ok());
+    assert_eq!(missing.unwrap_err(), DbError::TableNotFound("Vegetables".to_string()));
+}
+
+#[test]
+fn test_describe_fingerprint() {
+    // GIVEN
+    let server = server();
+    let fingerprint = server.database().schema_fingerprint("Stock").unwrap();
+
+    // WHEN
+    let columns = rows(server.handle("DESCRIBE Stock"));
+
+    // THEN each row ends with the table's fingerprint in hex
+    assert_eq!(columns.len(), 7);
+    assert!(columns.iter().all(|column| u64::from_str_radix(&column[2], 16) == Ok(fingerprint)), "{columns:?}");
+}
+
+#[test]
+fn test_users_see_their_tables() {
+    // GIVEN a loader who may write Stock only
+    let mut credentials = Credentials::default();
+    credentials.add_token("loader", &hash_token("loader-token")).unwrap();
+    let mut permissions = Permissions::default();
+    permissions.grant("loader", Privilege::Write, "Stock");
+    permissions.assign("loader", "loader");
+    let server = server().with_credentials(credentials).with_permissions(permissions);
+    let mut session = Session::default();
+    let unauthenticated = server.handle_as(&session, "SHOW TABLES");
+    server.authenticate(&mut session, None, "loader-token").unwrap();
+
+    // WHEN
+    let tables = rows(server.handle_as(&session, "SHOW TABLES"));
+    let described = server.handle_as(&session, "DESCRIBE Fruits");
+
+    // THEN
+    assert_eq!(unauthenticated.unwrap_err(), DbError::NotAuthenticated);
+    assert_eq!(tables, [["Stock"]]);
+    assert_eq!(described.unwrap_err(), DbError::PermissionDenied { user: "loader".to_string(), privilege: Privilege::Read, table: "Fruits".to_string() });
+}
+
+#[test]
+fn test_text_mode() {
+    // GIVEN
+    let server = server();
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve_text(Cursor::new("SHOW TABLES; DESCRIBE Fruits; SHOW Fruits\n"), &mut output).unwrap();
+
+    // THEN
+    assert_eq!(String::from_utf8(output).unwrap(), [
+        "| name   |\n| ------ |\n| Fruits |\n| Stock  |\n(2 rows)",
+        "| column | type     | fingerprint      |\n| ------ | -------- | ---------------- |\n| id     | U32      | 1519fc395f66c97c |\n| name   | UTF8(20) | 1519fc395f66c97c |\n(2 rows)",
+        "ERROR InputError(\"Expected TABLES, got Fruits\")",
+        "",
+    ].join("\n\n"));
+}
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+use std::thread::{self, sleep};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table, TableStats};
+use rudibi_server::maintenance::{MaintenanceConfig, MaintenanceHandle, MaintenanceReport, Ttl};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::check_equality;
+
+fn now() -> u32 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as u32
+}
+
+// Sessions last seen an hour ago, a minute ago and now
+fn sessions() -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Sessions", vec![
+        Column::new("id", DataType::U32),
+        Column::new("seen", DataType::U32),
+    ]), StorageCfg::InMemory).unwrap();
+    db.insert("Sessions", &["id", "seen"], rows![[1u32, now() - 3600], [2u32, now() - 60], [3u32, now()]]).unwrap();
+    db
+}
+
+fn config(interval: Duration) -> MaintenanceConfig {
+    MaintenanceConfig {
+        interval,
+        compact: true,
+        ttls: vec![Ttl { table: "Sessions".to_string(), column: "seen".to_string(), max_age: Duration::from_secs(600) }],
+        refresh_stats: true,
+        checkpoint: None,
+    }
+}
+
+// The report of the worker's first run
+fn first_run(handle: &MaintenanceHandle) -> MaintenanceReport {
+    for _ in 0..200 {
+        if let Some(run) = handle.last_run() {
+            return run.unwrap();
+        }
+        sleep(Duration::from_millis(10));
+    }
+    panic!("Maintenance didn't run");
+}
+
+#[test]
+fn test_run_expires_compacts_and_refreshes_stats() {
+    // GIVEN
+    let db = sessions();
+
+    // WHEN
+    let before = db.last_table_stats();
+    let report = db.run_maintenance(&config(Duration::from_secs(60))).unwrap();
+
+    // THEN
+    assert_eq!(report, MaintenanceReport { rows_expired: 1, tables_compacted: 1, stats_refreshed: 1, checkpoint: None });
+    check_equality(&db.select(&[ColumnRef("id")], "Sessions", &True).unwrap(), &[[U32(2)], [U32(3)]]);
+    assert_eq!(before, []);
+    assert_eq!(db.last_table_stats(), [TableStats { name: "Sessions".to_string(), rows: 2, disk_bytes: 0 }]);
+}
+
+#[test]
+fn test_worker_runs_on_its_own() {
+    // GIVEN
+    let db = Arc::new(sessions());
+
+    // WHEN nothing is written or polled
+    let handle = db.start_maintenance(config(Duration::from_millis(10))).unwrap();
+    let report = first_run(&handle);
+
+    // THEN
+    assert_eq!(report.rows_expired, 1);
+    check_equality(&db.select(&[ColumnRef("id")], "Sessions", &True).unwrap(), &[[U32(2)], [U32(3)]]);
+}
+
+#[test]
+fn test_worker_runs_beside_writes() {
+    // GIVEN
+    let db = Arc::new(sessions());
+    let handle = db.start_maintenance(config(Duration::from_millis(1))).unwrap();
+
+    // WHEN
+    let writer = thread::spawn({
+        let db = db.clone();
+        move || (100..300u32).try_for_each(|id| db.insert("Sessions", &["id", "seen"], rows![[id, now()]]).map(drop))
+    });
+
+    // THEN the writes went through, and a run not skipping the locked table expired the old row
+    assert_eq!(writer.join().unwrap(), Ok(()));
+    first_run(&handle);
+    let expired = (0..200).any(|_| db.count("Sessions", &True) == Ok(202) || { sleep(Duration::from_millis(10)); false });
+    assert!(expired);
+}
+
+#[test]
+fn test_paused_and_stopped_workers_dont_run() {
+    // GIVEN
+    let db = Arc::new(sessions());
+    let handle = db.start_maintenance(config(Duration::from_millis(50))).unwrap();
+
+    // WHEN
+    handle.pause();
+    sleep(Duration::from_millis(150));
+    let paused = handle.last_run();
+    handle.resume();
+    handle.stop();
+    sleep(Duration::from_millis(100));
+    let stopped = handle.last_run();
+
+    // THEN
+    assert_eq!((paused, stopped), (None, None));
+    assert!(handle.is_stopped());
+    assert_eq!(db.count("Sessions", &True).unwrap(), 3);
+}
+
+#[test]
+fn test_worker_ends_with_the_database() {
+    // GIVEN
+    let db = Arc::new(sessions());
+    let handle = db.start_maintenance(config(Duration::from_secs(60))).unwrap();
+
+    // WHEN
+    drop(db);
+
+    // THEN
+    assert!(handle.is_stopped());
+}
+
+#[test]
+fn test_ttl_column_must_be_seconds() {
+    let db = Arc::new(sessions());
+    let mut bad = config(Duration::from_secs(1));
+    bad.ttls[0].column = "missing".to_string();
+    assert!(matches!(db.start_maintenance(bad), Err(DbError::ColumnNotFound(_))));
+}
@@ -0,0 +1,88 @@
+#![cfg(feature = "derive")]
+
+use rudibi_server::dtype::{DataType, TypeError, Uuid};
+use rudibi_server::engine::{Column, Database, StorageCfg, Table};
+use rudibi_server::mapping::{FromRow, ToRow};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{fruits_table, with_tmp};
+
+#[derive(Debug, PartialEq, FromRow, ToRow)]
+struct Fruit {
+    id: u32,
+    #[rudibi(rename = "name")]
+    label: String,
+}
+
+#[derive(Debug, PartialEq, FromRow, ToRow)]
+struct Parcel {
+    id: Uuid,
+    weight: f64,
+    r#type: String,
+    label: Vec<u8>,
+}
+
+#[derive(Debug, FromRow)]
+struct Weighed {
+    #[rudibi(rename = "id")]
+    weight: f64,
+}
+
+fn test_round_trip(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+    let fruits = [Fruit { id: 500, label: "kiwi".to_string() }, Fruit { id: 600, label: "lime".to_string() }];
+
+    // WHEN
+    db.insert("Fruits", Fruit::COLUMNS, &fruits.iter().map(ToRow::to_row).collect::<Vec<_>>()).unwrap();
+    let selected = db.select(&[ColumnRef("name"), ColumnRef("id")], "Fruits", &Gt(ColumnRef("id"), Const(400u32.into()))).unwrap();
+
+    // THEN
+    assert_eq!(Fruit::COLUMNS, ["id", "name"]);
+    assert_eq!(selected.rows_as::<Fruit>().unwrap(), fruits);
+}
+
+#[test]
+fn test_round_trip_in_mem() {
+    test_round_trip(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_round_trip_on_disk() {
+    with_tmp(test_round_trip);
+}
+
+#[test]
+fn test_types() {
+    // GIVEN
+    let mut db = Database::new();
+    let schema = Table::new("Parcels", vec![
+        Column::new("id", DataType::UUID),
+        Column::new("weight", DataType::F64),
+        Column::new("type", DataType::ENUM { labels: vec!["letter".to_string(), "box".to_string()] }),
+        Column::new("label", DataType::VARBINARY { max_length: 16 }),
+    ]);
+    db.new_table(&schema, StorageCfg::InMemory).unwrap();
+    let parcel = Parcel { id: Uuid([7; 16]), weight: 1.5, r#type: "box".to_string(), label: vec![0, 255] };
+
+    // WHEN
+    db.insert("Parcels", Parcel::COLUMNS, &[parcel.to_row()]).unwrap();
+    let selected = db.select(&[ColumnRef("label"), ColumnRef("type"), ColumnRef("weight"), ColumnRef("id")], "Parcels", &True).unwrap();
+
+    // THEN
+    assert_eq!(Parcel::from_row(&selected, 0), Ok(parcel));
+}
+
+#[test]
+fn test_columns_not_mapping() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let selected = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+
+    // WHEN
+    let missing = selected.rows_as::<Fruit>();
+    let mistyped = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap().rows_as::<Weighed>();
+
+    // THEN
+    assert_eq!(missing, Err(TypeError::UnknownColumn("name".to_string())));
+    assert_eq!(mistyped.map(|weighed| weighed[0].weight), Err(TypeError::ConversionError));
+}
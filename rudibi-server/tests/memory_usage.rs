@@ -0,0 +1,57 @@
+use rudibi_server::engine::StorageCfg;
+use rudibi_server::index::IndexKind;
+use rudibi_server::query::Bool::*;
+use rudibi_server::testlib::{fruits_table, random_temp_file};
+
+#[test]
+fn test_in_memory_table_is_accounted() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    let before = db.memory_usage().tables["Fruits"];
+
+    // WHEN
+    db.create_index("Fruits", "name", IndexKind::Ordered).unwrap();
+    let mut blob: &[u8] = &[7u8; 5000];
+    db.put_blob(&mut blob).unwrap();
+
+    // THEN
+    let usage = db.memory_usage();
+    let fruits = usage.tables["Fruits"];
+    assert!(before.data >= "applebananabananacherry".len() + 4 * 4);
+    assert!(before.offsets > 0);
+    assert_eq!(before.indexes, 0);
+    assert!(fruits.indexes > 0);
+    assert_eq!(usage.blobs, 5000);
+    assert_eq!(usage.total(), fruits.total() + 5000);
+}
+
+#[test]
+fn test_deleted_rows_are_released_by_compaction() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let before = db.memory_usage().tables["Fruits"];
+
+    // WHEN
+    db.delete("Fruits", &True).unwrap();
+    db.compact("Fruits").unwrap();
+
+    // THEN
+    let after = db.memory_usage().tables["Fruits"];
+    assert!(after.data < before.data, "{after:?} vs {before:?}");
+}
+
+#[test]
+fn test_disk_table_holds_no_row_data() {
+    // GIVEN
+    let path = random_temp_file();
+
+    // WHEN
+    let db = fruits_table(StorageCfg::disk(&path));
+
+    // THEN
+    let fruits = db.memory_usage().tables["Fruits"];
+    assert_eq!(fruits.data, 0);
+    assert!(fruits.offsets > 0);
+    drop(db);
+    std::fs::remove_file(path).unwrap();
+}
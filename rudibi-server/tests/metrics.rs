@@ -0,0 +1,90 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use rudibi_server::metrics;
+use rudibi_server::testlib::fruits_server;
+
+// The value of the sample with exactly this name and labels
+fn sample(metrics: &str, name: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| line.strip_prefix(name)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[test]
+fn test_counters() {
+    // GIVEN
+    let server = fruits_server();
+    server.handle("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi'), (600, 'kiwi')").unwrap();
+    server.handle("SELECT id FROM Fruits WHERE name = 'kiwi'").unwrap();
+    server.handle("SELECT id FROM Vegetables").unwrap_err();
+    server.handle("DELETE FROM Fruits WHERE id > 450").unwrap();
+    server.handle("SELEKT id FROM Fruits").unwrap_err();
+
+    // WHEN
+    let metrics = server.metrics();
+
+    // THEN
+    assert_eq!(sample(&metrics, r#"rudibi_queries_total{command="select"}"#), Some(2.0));
+    assert_eq!(sample(&metrics, r#"rudibi_query_errors_total{command="select"}"#), Some(1.0));
+    assert_eq!(sample(&metrics, r#"rudibi_queries_total{command="insert"}"#), Some(1.0));
+    assert_eq!(sample(&metrics, r#"rudibi_query_errors_total{command="invalid"}"#), Some(1.0));
+    assert_eq!(sample(&metrics, "rudibi_rows_inserted_total"), Some(2.0));
+    assert_eq!(sample(&metrics, "rudibi_rows_deleted_total"), Some(2.0));
+    assert_eq!(sample(&metrics, "rudibi_rows_scanned_total"), Some(6.0));
+    assert_eq!(sample(&metrics, r#"rudibi_table_rows{table="Fruits"}"#), Some(4.0));
+    assert_eq!(sample(&metrics, "rudibi_connections"), Some(0.0));
+    assert!(metrics.contains("# TYPE rudibi_query_duration_seconds histogram\n"));
+}
+
+#[test]
+fn test_histogram_buckets_add_up() {
+    // GIVEN
+    let server = fruits_server();
+    for _ in 0..3 {
+        server.handle("SELECT id FROM Fruits WHERE id > 0").unwrap();
+    }
+
+    // WHEN
+    let metrics = server.metrics();
+
+    // THEN the buckets count the selects at most as long as their bound
+    let buckets: Vec<f64> = metrics.lines()
+        .filter(|line| line.starts_with(r#"rudibi_query_duration_seconds_bucket{command="select""#))
+        .map(|line| line.rsplit(' ').next().unwrap().parse().unwrap())
+        .collect();
+    assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(buckets.last(), Some(&3.0));
+    assert_eq!(sample(&metrics, r#"rudibi_query_duration_seconds_count{command="select"}"#), Some(3.0));
+}
+
+#[test]
+fn test_http() {
+    // GIVEN
+    let server = Arc::new(fruits_server());
+    server.handle("SELECT id FROM Fruits WHERE id > 0").unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn({
+        let server = server.clone();
+        move || metrics::serve_http(&listener, || server.metrics())
+    });
+    let get = |path: &str| {
+        let mut conn = TcpStream::connect(address).unwrap();
+        write!(conn, "GET {path} HTTP/1.1\r\nHost: localhost\r\nAccept: */*\r\n\r\n").unwrap();
+        let mut response = String::new();
+        conn.read_to_string(&mut response).unwrap();
+        response
+    };
+
+    // WHEN
+    let scraped = get("/metrics");
+    let missing = get("/");
+
+    // THEN
+    let (head, body) = scraped.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+    assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())), "{head}");
+    assert_eq!(sample(body, r#"rudibi_queries_total{command="select"}"#), Some(1.0));
+    assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"), "{missing}");
+}
@@ -0,0 +1,56 @@
+use std::sync::{Arc, Mutex};
+
+use rudibi_server::dtype::{ColumnValue::*};
+use rudibi_server::engine::{Row, StorageCfg};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::storage::{RowId, StorageObserver};
+use rudibi_server::testlib::{fruits_table, with_tmp};
+use rudibi_server::rows;
+
+#[derive(Default)]
+struct EventLog {
+    events: Mutex<Vec<String>>,
+}
+
+impl StorageObserver for EventLog {
+    fn on_store(&self, table: &str, row_ids: &[RowId], _rows: &[Row], _column_mapping: &[usize]) {
+        self.events.lock().unwrap().push(format!("store {table} {row_ids:?}"));
+    }
+
+    fn on_delete(&self, table: &str, row_ids: &[RowId]) {
+        self.events.lock().unwrap().push(format!("delete {table} {row_ids:?}"));
+    }
+
+    fn on_compact(&self, table: &str) {
+        self.events.lock().unwrap().push(format!("compact {table}"));
+    }
+}
+
+fn observe_changes(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    let log = Arc::new(EventLog::default());
+    db.add_observer(log.clone());
+
+    // WHEN
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "date"], [600u32, "fig"]]).unwrap();
+    db.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+    db.compact("Fruits").unwrap();
+
+    // THEN
+    assert_eq!(*log.events.lock().unwrap(), vec![
+        "store Fruits [4, 5]".to_string(),
+        "delete Fruits [1, 2]".to_string(),
+        "compact Fruits".to_string(),
+    ]);
+}
+
+#[test]
+fn observe_changes_in_mem() {
+    observe_changes(StorageCfg::InMemory);
+}
+
+#[test]
+fn observe_changes_on_disk() {
+    with_tmp(observe_changes);
+}
@@ -0,0 +1,140 @@
+use rudibi_server::compress::Compression;
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, Row, StorageCfg, Table};
+use rudibi_server::query::{Bool::{self, *}, Value::*};
+use rudibi_server::storage::Durability;
+use rudibi_server::testlib::{check_equality, random_temp_file};
+
+fn logs_schema() -> Table {
+    Table::new("Logs", vec![
+        Column::new("id", DataType::U32),
+        Column::new("message", DataType::UTF8 { max_bytes: 1000 }),
+    ])
+}
+
+fn message(id: u32) -> String {
+    format!("request {id} served by worker {} with status 200 OK; ", id % 4).repeat(8)
+}
+
+// Opens the table at `path`, or creates it, and inserts the rows with ids `ids`
+fn logs_table(path: &str, compression: Compression, ids: std::ops::Range<u32>) -> Database {
+    let mut db = Database::new();
+    let storage = StorageCfg::Disk { path: path.to_string(), durability: Durability::default(), compression };
+    db.new_table(&logs_schema(), storage).unwrap();
+    let data: Vec<Row> = ids.map(|id| Row::of_columns(&[&id.to_le_bytes(), message(id).as_bytes()])).collect();
+    db.insert("Logs", &["id", "message"], &data).unwrap();
+    db
+}
+
+fn file_len(path: &str) -> u64 {
+    std::fs::metadata(path).unwrap().len()
+}
+
+// Codecs of the features the tests are built with
+fn page_codecs() -> Vec<Compression> {
+    vec![
+        #[cfg(feature = "lz4")]
+        Compression::Lz4,
+        #[cfg(feature = "zstd")]
+        Compression::Zstd,
+    ]
+}
+
+#[test]
+fn test_row_compressed_table_is_smaller() {
+    // GIVEN
+    let plain_path = random_temp_file();
+    let compressed_path = random_temp_file();
+
+    // WHEN
+    let plain = logs_table(&plain_path, Compression::None, 0..500);
+    let compressed = logs_table(&compressed_path, Compression::Lz, 0..500);
+
+    // THEN
+    assert!(file_len(&compressed_path) * 3 < file_len(&plain_path));
+    let filter = Eq(ColumnRef("id"), Const(U32(321)));
+    let expected = message(321);
+    check_equality(&plain.select(&[ColumnRef("message")], "Logs", &filter).unwrap(), &[[UTF8(&expected)]]);
+    check_equality(&compressed.select(&[ColumnRef("message")], "Logs", &filter).unwrap(), &[[UTF8(&expected)]]);
+    std::fs::remove_file(plain_path).unwrap();
+    std::fs::remove_file(compressed_path).unwrap();
+}
+
+#[test]
+fn test_packed_table_is_smaller() {
+    for compression in page_codecs() {
+        // GIVEN
+        let plain_path = random_temp_file();
+        let row_path = random_temp_file();
+        let packed_path = random_temp_file();
+
+        // WHEN
+        drop(logs_table(&plain_path, Compression::None, 0..4000));
+        drop(logs_table(&row_path, Compression::Lz, 0..4000));
+        let packed = logs_table(&packed_path, compression, 0..4000);
+
+        // THEN whole groups compress better than each row, even padded to whole pages
+        assert!(file_len(&packed_path) * 6 < file_len(&plain_path), "{compression:?}");
+        assert!(file_len(&packed_path) < file_len(&row_path), "{compression:?}");
+        let filter = Eq(ColumnRef("id"), Const(U32(3))).or(Eq(ColumnRef("id"), Const(U32(3999))));
+        let (first, last) = (message(3), message(3999));
+        check_equality(&packed.select(&[ColumnRef("message")], "Logs", &filter).unwrap(), &[[UTF8(&first)], [UTF8(&last)]]);
+        std::fs::remove_file(plain_path).unwrap();
+        std::fs::remove_file(row_path).unwrap();
+        std::fs::remove_file(packed_path).unwrap();
+    }
+}
+
+#[test]
+fn test_packed_deletes_survive_reopen() {
+    for compression in page_codecs() {
+        // GIVEN a packed table with rows deleted from its packed groups
+        let path = random_temp_file();
+        let db = logs_table(&path, compression, 0..2000);
+        let len = file_len(&path);
+        assert_eq!(db.delete("Logs", &Lt(ColumnRef("id"), Const(U32(1500)))), Ok(1500));
+        drop(db);
+
+        // WHEN
+        let mut reopened = Database::new();
+        let report = reopened.open_table(&logs_schema(), &path, Durability::Flush).unwrap();
+
+        // THEN deletes don't change the size of a packed group
+        assert_eq!((report.rows_recovered, report.pages_repaired), (2000, 0), "{compression:?}");
+        assert_eq!(file_len(&path), len);
+        assert_eq!(reopened.count("Logs", &True), Ok(500));
+        let results = reopened.select(&[ColumnRef("message")], "Logs", &Eq(ColumnRef("id"), Const(U32(1500)))).unwrap();
+        check_equality(&results, &[[UTF8(&message(1500))]]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_mixed_rows_after_reopen() {
+    for compression in page_codecs().into_iter().chain([Compression::None]) {
+        // GIVEN rows compressed each on their own, then more stored with another compression
+        let path = random_temp_file();
+        let db = logs_table(&path, Compression::Lz, 0..300);
+        db.delete("Logs", &Eq(ColumnRef("id"), Const(U32(1)))).unwrap();
+        drop(db);
+        drop(logs_table(&path, compression, 300..1000));
+
+        // WHEN
+        let mut reopened = Database::new();
+        reopened.open_table(&logs_schema(), &path, Durability::Flush).unwrap();
+        reopened.insert("Logs", &["id", "message"], &[Row::of_columns(&[&1000u32.to_le_bytes(), b"short"])]).unwrap();
+
+        // THEN
+        let filter = [0, 1, 299, 999, 1000].map(|id| Eq(ColumnRef("id"), Const(U32(id)))).into_iter().reduce(Bool::or).unwrap();
+        let results = reopened.select(&[ColumnRef("id"), ColumnRef("message")], "Logs", &filter).unwrap();
+        let (first, old, new) = (message(0), message(299), message(999));
+        check_equality(&results, &[
+            [U32(0), UTF8(&first)],
+            [U32(299), UTF8(&old)],
+            [U32(999), UTF8(&new)],
+            [U32(1000), UTF8("short")],
+        ]);
+        assert_eq!(reopened.count("Logs", &True), Ok(1000), "{compression:?}");
+        std::fs::remove_file(path).unwrap();
+    }
+}
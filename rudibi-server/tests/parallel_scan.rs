@@ -0,0 +1,56 @@
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, QueryOptions, Row, StorageCfg, Table, PARALLEL_SCAN_ROWS};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::with_tmp;
+
+fn readings_table(storage: StorageCfg) -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Readings", vec![
+        Column::new("id", DataType::U32),
+        Column::new("value", DataType::F64),
+    ]), storage).unwrap();
+    let data: Vec<Row> = (0..PARALLEL_SCAN_ROWS as u32 + 1000)
+        .map(|id| Row::of_columns(&[&id.to_le_bytes(), &f64::from(id % 100).to_le_bytes()]))
+        .collect();
+    db.insert("Readings", &["id", "value"], &data).unwrap();
+    db
+}
+
+fn test_parallel_scan_matches_sequential(storage: StorageCfg) {
+    // GIVEN
+    let db = readings_table(storage);
+    let filter = Eq(ColumnRef("value"), Const(F64(7.0)));
+
+    // WHEN
+    let sequential = db.select_with_options(&[ColumnRef("id")], "Readings", &filter, &QueryOptions { parallelism: 1, ..Default::default() }).unwrap();
+    let parallel = db.select_with_options(&[ColumnRef("id")], "Readings", &filter, &QueryOptions { parallelism: 4, ..Default::default() }).unwrap();
+
+    // THEN rows come back in scan order either way
+    assert_eq!(parallel.data, sequential.data);
+    assert_eq!(parallel.len(), (0..PARALLEL_SCAN_ROWS + 1000).filter(|id| id % 100 == 7).count());
+    assert_eq!(parallel.data[1].get_column(0), 107u32.to_le_bytes());
+    assert_eq!(parallel.scan_stats, sequential.scan_stats);
+    assert_eq!(parallel.scan_stats.rows_scanned, PARALLEL_SCAN_ROWS + 1000);
+}
+
+#[test]
+fn test_parallel_scan_matches_sequential_in_mem() {
+    test_parallel_scan_matches_sequential(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_parallel_scan_matches_sequential_on_disk() {
+    with_tmp(test_parallel_scan_matches_sequential);
+}
+
+#[test]
+fn test_parallel_scan_fails_on_bad_filter() {
+    // GIVEN
+    let db = readings_table(StorageCfg::InMemory);
+
+    // WHEN comparing a number with text fails on every row
+    let result = db.select_with_options(&[ColumnRef("id")], "Readings", &Eq(ColumnRef("value"), Const(UTF8("seven"))), &QueryOptions { parallelism: 4, ..Default::default() });
+
+    // THEN
+    assert!(result.is_err());
+}
@@ -0,0 +1,166 @@
+use std::io::Cursor;
+
+use rudibi_server::auth::{hash_token, Credentials, Session};
+use rudibi_server::config::ServerConfig;
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::permissions::{Permissions, Privilege, ALL_TABLES};
+use rudibi_server::protocol::Server;
+use rudibi_server::resp;
+use rudibi_server::testlib::{fruits_server, fruits_table};
+
+// analyst reads every table, loader also writes Fruits and creates Stock
+fn permissions() -> Permissions {
+    let mut permissions = Permissions::default();
+    permissions.grant("analytics", Privilege::Read, ALL_TABLES);
+    permissions.grant("loader", Privilege::Write, "Fruits");
+    permissions.grant("loader", Privilege::Ddl, "Stock");
+    permissions.assign("analyst", "analytics");
+    permissions.assign("loader", "analytics");
+    permissions.assign("loader", "loader");
+    permissions
+}
+
+// Logs in with a token named as the user
+fn login(server: &Server, user: &str) -> Session {
+    let mut session = Session::default();
+    server.authenticate(&mut session, None, &format!("{user}-token")).unwrap();
+    session
+}
+
+fn guarded_server() -> Server {
+    let mut credentials = Credentials::default();
+    for user in ["analyst", "loader", "nobody"] {
+        credentials.add_token(user, &hash_token(&format!("{user}-token"))).unwrap();
+    }
+    fruits_server()
+        .with_credentials(credentials)
+        .with_permissions(permissions())
+}
+
+fn denied(user: &str, privilege: Privilege, table: &str) -> DbError {
+    DbError::PermissionDenied { user: user.to_string(), privilege, table: table.to_string() }
+}
+
+#[test]
+fn test_roles_grant_privileges() {
+    // GIVEN
+    let permissions = permissions();
+
+    // THEN
+    assert!(permissions.allows("analyst", Privilege::Read, "Fruits"));
+    assert!(permissions.allows("analyst", Privilege::Read, "Anything"));
+    assert!(!permissions.allows("analyst", Privilege::Write, "Fruits"));
+    assert!(permissions.allows("loader", Privilege::Read, "Fruits"));
+    assert!(permissions.allows("loader", Privilege::Write, "Fruits"));
+    assert!(!permissions.allows("loader", Privilege::Write, "Stock"));
+    assert!(!permissions.allows("nobody", Privilege::Read, "Fruits"));
+    assert_eq!(permissions.check("analyst", Privilege::Ddl, "Fruits"), Err(denied("analyst", Privilege::Ddl, "Fruits")));
+}
+
+#[test]
+fn test_analysts_select_but_dont_delete() {
+    // GIVEN
+    let server = guarded_server();
+    let analyst = login(&server, "analyst");
+
+    // WHEN
+    let selected = server.handle_as(&analyst, "SELECT name FROM Fruits WHERE id = 100");
+    let deleted = server.handle_as(&analyst, "DELETE FROM Fruits WHERE id = 100");
+    let created = server.handle_as(&analyst, "CREATE TABLE Stock (id U32)");
+
+    // THEN
+    assert!(selected.unwrap().to_string().contains("apple"));
+    assert_eq!(deleted.unwrap_err(), denied("analyst", Privilege::Write, "Fruits"));
+    assert_eq!(created.unwrap_err(), denied("analyst", Privilege::Ddl, "Stock"));
+    assert_eq!(server.handle("SELECT * FROM Fruits WHERE id = 100").unwrap().to_string().lines().count(), 4);
+}
+
+#[test]
+fn test_loaders_write_what_they_are_granted() {
+    // GIVEN
+    let server = guarded_server();
+    let loader = login(&server, "loader");
+    let nobody = login(&server, "nobody");
+
+    // WHEN
+    let deleted = server.handle_as(&loader, "DELETE FROM Fruits WHERE id = 100");
+    let created = server.handle_as(&loader, "CREATE TABLE Stock (id U32)");
+    let inserted = server.handle_as(&loader, "INSERT INTO Stock (id) VALUES (1)");
+    let selected = server.handle_as(&nobody, "SELECT * FROM Fruits");
+
+    // THEN
+    assert_eq!(deleted.unwrap().to_string(), "DELETED 1");
+    assert_eq!(created.unwrap().to_string(), "CREATED");
+    assert_eq!(inserted.unwrap_err(), denied("loader", Privilege::Write, "Stock"));
+    assert_eq!(selected.unwrap_err(), denied("nobody", Privilege::Read, "Fruits"));
+}
+
+#[test]
+fn test_pooled_commands_are_checked() {
+    // GIVEN
+    let mut credentials = Credentials::default();
+    credentials.add_token("analyst", &hash_token("analyst-token")).unwrap();
+    let server = Server::with_pool(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory, 2, 4)
+        .with_credentials(credentials)
+        .with_permissions(permissions());
+    let analyst = login(&server, "analyst");
+
+    // WHEN
+    let deleted = server.handle_as(&analyst, "DELETE FROM Fruits WHERE id = 100");
+
+    // THEN
+    assert_eq!(deleted.unwrap_err(), denied("analyst", Privilege::Write, "Fruits"));
+}
+
+#[test]
+fn test_resp_key_values() {
+    // GIVEN
+    let server = guarded_server();
+    let input = "AUTH analyst-token\r\nSET a 1\r\nGET a\r\nRSELECT name FROM Fruits WHERE id = 100\r\n";
+
+    // WHEN
+    let mut output = Vec::new();
+    resp::serve(&server, Cursor::new(input), &mut output).unwrap();
+
+    // THEN
+    assert_eq!(String::from_utf8(output).unwrap(), [
+        "+OK",
+        "-ERR PermissionDenied { user: \"analyst\", privilege: Write, table: \"KeyValues\" }",
+        "$-1",
+        "*1", "*1", "$5", "apple", "",
+    ].join("\r\n"));
+}
+
+#[test]
+fn test_config() {
+    // GIVEN
+    let text = format!("\
+[tokens]
+analyst = '{}'
+
+[roles.analytics]
+read = \"*\"
+
+[roles.loader]
+write = \"Fruits, Stock\"
+ddl = \"Stock\"
+
+[user_roles]
+analyst = \"analytics, loader\"
+", hash_token("analyst-token"));
+
+    // WHEN
+    let config = ServerConfig::from_toml(&text).unwrap();
+    let unknown_role = ServerConfig::from_toml(&text.replace("analytics, loader", "analytics, laoder"));
+    let unknown_privilege = ServerConfig::from_toml(&text.replace("ddl =", "drop ="));
+    let nobody_to_give_to = ServerConfig::from_toml("[roles.analytics]\nread = \"*\"\n");
+
+    // THEN
+    for (privilege, table) in [(Privilege::Read, "Orders"), (Privilege::Write, "Stock"), (Privilege::Ddl, "Stock")] {
+        assert!(config.permissions.allows("analyst", privilege, table));
+    }
+    assert!(!config.permissions.allows("analyst", Privilege::Ddl, "Fruits"));
+    assert!(matches!(unknown_role, Err(DbError::InputError(msg)) if msg == "Unknown role laoder of analyst"));
+    assert!(matches!(unknown_privilege, Err(DbError::InputError(msg)) if msg.starts_with("Line 9: Unknown privilege drop")));
+    assert!(nobody_to_give_to.is_err());
+}
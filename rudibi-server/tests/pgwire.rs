@@ -0,0 +1,151 @@
+use std::io::Cursor;
+
+use rudibi_server::pgwire;
+use rudibi_server::testlib::fruits_server;
+
+fn startup() -> Vec<u8> {
+    let mut body = (3i32 << 16).to_be_bytes().to_vec();
+    body.extend_from_slice(b"user\0tester\0\0");
+    let mut bytes = ((body.len() + 4) as i32).to_be_bytes().to_vec();
+    bytes.extend(body);
+    bytes
+}
+
+fn message(tag: u8, body: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![tag];
+    bytes.extend_from_slice(&((body.len() + 4) as i32).to_be_bytes());
+    bytes.extend_from_slice(body);
+    bytes
+}
+
+fn query(text: &str) -> Vec<u8> {
+    message(b'Q', format!("{text}\0").as_bytes())
+}
+
+fn serve(input: Vec<u8>) -> Vec<(char, Vec<u8>)> {
+    let server = fruits_server();
+    let mut output = Vec::new();
+    pgwire::serve(&server, Cursor::new(input), &mut output).unwrap();
+    let mut replies = Vec::new();
+    let mut rest = &output[..];
+    while let [tag, len @ ..] = rest {
+        let len = i32::from_be_bytes(len[..4].try_into().unwrap()) as usize;
+        replies.push((*tag as char, rest[5..1 + len].to_vec()));
+        rest = &rest[1 + len..];
+    }
+    replies
+}
+
+fn tags(replies: &[(char, Vec<u8>)]) -> String {
+    replies.iter().map(|(tag, _)| tag).collect()
+}
+
+// Fields of a data row, or the names of a row description
+fn fields(reply: &(char, Vec<u8>)) -> Vec<String> {
+    let (tag, body) = reply;
+    let count = i16::from_be_bytes(body[..2].try_into().unwrap());
+    let mut rest = &body[2..];
+    let mut fields = Vec::new();
+    for _ in 0..count {
+        if *tag == 'T' {
+            let end = rest.iter().position(|&b| b == 0).unwrap();
+            fields.push(String::from_utf8(rest[..end].to_vec()).unwrap());
+            rest = &rest[end + 1 + 18..];
+        } else {
+            let len = i32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            fields.push(String::from_utf8(rest[4..4 + len].to_vec()).unwrap());
+            rest = &rest[4 + len..];
+        }
+    }
+    fields
+}
+
+fn text(body: &[u8]) -> String {
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+#[test]
+fn test_startup_declines_ssl() {
+    // GIVEN a client asking for SSL before starting
+    let mut input = 8i32.to_be_bytes().to_vec();
+    input.extend_from_slice(&80877103i32.to_be_bytes());
+    input.extend(startup());
+    input.extend(message(b'X', &[]));
+
+    // WHEN
+    let server = fruits_server();
+    let mut output = Vec::new();
+    pgwire::serve(&server, Cursor::new(input), &mut output).unwrap();
+
+    // THEN it's told no, then authenticated and ready
+    assert_eq!(output[0], b'N');
+    assert_eq!(&output[1..10], &[b'R', 0, 0, 0, 8, 0, 0, 0, 0]);
+    assert_eq!(&output[output.len() - 6..], &[b'Z', 0, 0, 0, 5, b'I']);
+}
+
+#[test]
+fn test_simple_query() {
+    // GIVEN
+    let mut input = startup();
+    input.extend(query("SELECT id, name FROM Fruits WHERE id >= 300"));
+    input.extend(query("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi'); DELETE FROM Fruits WHERE id > 400"));
+
+    // WHEN
+    let replies = serve(input);
+
+    // THEN rows come between their description and the command tag, each query ends ready
+    let replies = &replies[replies.iter().position(|(tag, _)| *tag == 'Z').unwrap() + 1..];
+    assert_eq!(tags(replies), "TDDCZCCZ");
+    assert_eq!(fields(&replies[0]), vec!["id", "name"]);
+    assert_eq!(fields(&replies[1]), vec!["300", "banana"]);
+    assert_eq!(fields(&replies[2]), vec!["400", "cherry"]);
+    assert_eq!(text(&replies[3].1), "SELECT 2\0");
+    assert_eq!(text(&replies[5].1), "INSERT 0 1\0");
+    assert_eq!(text(&replies[6].1), "DELETE 1\0");
+}
+
+#[test]
+fn test_row_description_types() {
+    // GIVEN
+    let mut input = startup();
+    input.extend(query("CREATE TABLE Stock (id U32, price F64, tag VARBINARY(4))"));
+    input.extend(query("INSERT INTO Stock (id, price, tag) VALUES (1, 0.5, x'00ff')"));
+    input.extend(query("SELECT * FROM Stock"));
+
+    // WHEN
+    let replies = serve(input);
+
+    // THEN u32 is sent as int8, f64 as float8 and bytes as bytea in hex
+    let (_, description) = replies.iter().find(|(tag, _)| *tag == 'T').unwrap();
+    let oids: Vec<u32> = ["id", "price", "tag"].iter().map(|name| {
+        let at = description.windows(name.len() + 1).position(|w| w == format!("{name}\0").as_bytes()).unwrap() + name.len() + 1;
+        u32::from_be_bytes(description[at + 6..at + 10].try_into().unwrap())
+    }).collect();
+    assert_eq!(oids, vec![20, 701, 17]);
+    let row = replies.iter().find(|(tag, _)| *tag == 'D').unwrap();
+    assert_eq!(fields(row), vec!["1", "0.5", "\\x00ff"]);
+}
+
+#[test]
+fn test_errors() {
+    // GIVEN a failing statement before another, an empty query and a prepared statement
+    let mut input = startup();
+    input.extend(query("SELECT * FROM Vegetables; DELETE FROM Fruits"));
+    input.extend(query(" ; "));
+    input.extend(message(b'P', b"\0SELECT * FROM Fruits\0\0\0"));
+    input.extend(message(b'B', b"\0\0\0\0\0\0\0\0"));
+    input.extend(message(b'S', &[]));
+    input.extend(query("SELECT id FROM Fruits WHERE id = 100"));
+
+    // WHEN
+    let replies = serve(input);
+
+    // THEN statements after the error don't run, the extended protocol gets one error up to Sync
+    let replies = &replies[replies.iter().position(|(tag, _)| *tag == 'Z').unwrap() + 1..];
+    assert_eq!(tags(replies), "EZIZEZTDCZ");
+    let error = text(&replies[0].1);
+    assert!(error.contains("C42P01\0"), "{error}");
+    assert!(error.contains("MTableNotFound(\"Vegetables\")\0"), "{error}");
+    assert!(text(&replies[4].1).contains("C0A000\0"));
+    assert_eq!(fields(&replies[7]), vec!["100"]);
+}
@@ -0,0 +1,249 @@
+use std::io::{Cursor, Write};
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::engine::{Database, DbError, StorageCfg};
+use rudibi_server::errors::{self, ErrorReply};
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::locking::LockMode;
+use rudibi_server::protocol::{Reply, Server};
+use rudibi_server::query::Bool::True;
+use rudibi_server::testlib::{fruits_server, fruits_table, serve_text};
+
+fn reply(server: &Server, command: &str) -> String {
+    server.handle(command).unwrap().to_string()
+}
+
+#[test]
+fn test_create_insert_select_delete() {
+    // GIVEN
+    let server = Server::new(Database::new(), StorageCfg::InMemory);
+
+    // WHEN
+    let created = reply(&server, "CREATE TABLE Stock (id U32, name UTF8(16), price F64, kind ENUM('fruit', 'nut'))");
+    let inserted = reply(&server, "insert into Stock (id, name, price, kind) values (1, 'apple', 0.5, 'fruit'), (2, 'pecan', 12.25, 'nut')");
+    let deleted = reply(&server, "DELETE FROM Stock WHERE kind = 'nut'");
+    let selected = reply(&server, "SELECT * FROM Stock");
+
+    // THEN
+    assert_eq!(created, "CREATED");
+    assert_eq!(inserted, "INSERTED 2");
+    assert_eq!(deleted, "DELETED 1");
+    assert_eq!(selected, [
+        "| id | name  | price | kind  |",
+        "| -- | ----- | ----- | ----- |",
+        "|  1 | apple |   0.5 | fruit |",
+        "(1 rows)",
+    ].join("\n"));
+}
+
+#[test]
+fn test_select_where() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN
+    let selected = reply(&server, "SELECT id, name FROM Fruits WHERE NOT (name = 'banana' OR id >= 150) OR id = 400 AND id <> 300");
+
+    // THEN AND binds closer than OR
+    assert_eq!(selected, [
+        "| id  | name   |",
+        "| --- | ------ |",
+        "| 100 | apple  |",
+        "| 400 | cherry |",
+        "(2 rows)",
+    ].join("\n"));
+}
+
+#[test]
+fn test_integers_compared_with_floats() {
+    // GIVEN
+    let server = Server::new(Database::new(), StorageCfg::InMemory);
+
+    // WHEN integers are compared with an F64 column, as they're inserted into one
+    let output = serve_text(&server, &[
+        "CREATE TABLE Stock (id U32, price F64)",
+        "INSERT INTO Stock (id, price) VALUES (1, 0.5), (2, 3), (3, 12.25)",
+        "SET FORMAT CSV",
+        "SELECT id FROM Stock WHERE price > 3",
+        "SELECT id FROM Stock WHERE 3 = price AND id < 3",
+        "DELETE FROM Stock WHERE price < 1",
+    ]);
+
+    // THEN
+    assert_eq!(output, [
+        "CREATED\n\n",
+        "INSERTED 3\n\n",
+        "OK\n\n",
+        "id\n3\n\n",
+        "id\n2\n\n",
+        "DELETED 1\n\n",
+    ].concat());
+}
+
+#[test]
+fn test_errors_are_replied() {
+    // GIVEN
+    let server = fruits_server();
+
+    // WHEN
+    let unknown_table = server.handle("SELECT * FROM Vegetables");
+    let bad_syntax = server.handle("SELECT id Fruits");
+    let bad_value = server.handle("INSERT INTO Fruits (id, name) VALUES ('ten', 'kiwi')");
+    let missing_value = server.handle("INSERT INTO Fruits (id, name) VALUES (10)");
+
+    // THEN nothing was inserted
+    assert_eq!(unknown_table.err(), Some(DbError::TableNotFound("Vegetables".to_string())));
+    assert_eq!(bad_syntax.err(), Some(DbError::InputError("Expected FROM, got Fruits".to_string())));
+    assert_eq!(bad_value.err(), Some(DbError::InputError("Text(\"ten\") can't be stored in id, a U32 column".to_string())));
+    assert!(matches!(missing_value, Err(DbError::InvalidColumnCount { expected: 2, got: 1 })));
+    assert!(matches!(server.handle("DELETE FROM Fruits WHERE id = 999"), Ok(Reply::Deleted(0))));
+    assert_eq!(server.database().count("Fruits", &True).unwrap(), 4);
+}
+
+fn requests(frames: &[Frame]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for frame in frames {
+        frame.write_to(&mut bytes).unwrap();
+    }
+    bytes
+}
+
+fn replies(mut bytes: &[u8]) -> Vec<Frame> {
+    std::iter::from_fn(|| Frame::read_from(&mut bytes).unwrap()).collect()
+}
+
+#[test]
+fn test_serve_replies_to_each_frame() {
+    // GIVEN several requests on one connection
+    let server = fruits_server();
+    let input = requests(&[
+        Frame::new(frame::EXECUTE, 7, "INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')"),
+        Frame::new(frame::EXECUTE, 3, "DELETE FROM Fruits WHERE name = 'kiwi'"),
+        Frame::new(frame::EXECUTE, 9, "SELECT"),
+        Frame::new(42, 10, "SELECT * FROM Fruits"),
+        Frame::new(frame::EXECUTE, 11, vec![0xff, 0xfe]),
+    ]);
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+
+    // THEN replies come in order, with the id of their request
+    assert_eq!(replies(&output), vec![
+        Frame::new(frame::REPLY, 7, "INSERTED 1"),
+        Frame::new(frame::REPLY, 3, "DELETED 1"),
+        Frame::error(9, &ErrorReply::from(&DbError::InputError("Expected a name, got end of command".to_string()))),
+        Frame::error(10, &ErrorReply::new(errors::UNKNOWN_REQUEST, "Unknown request tag 42")),
+        Frame::error(11, &ErrorReply::new(errors::BAD_ENCODING, "Command isn't UTF-8")),
+    ]);
+}
+
+// Keeps what each write to it got
+#[derive(Default)]
+struct Writes(Vec<Vec<u8>>);
+
+impl Write for Writes {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_pipelined_requests() {
+    // GIVEN inserts and selects sent all at once, before any reply is read
+    let server = fruits_server();
+    let frames: Vec<Frame> = (0..50).flat_map(|idx| [
+        Frame::new(frame::EXECUTE, 2 * idx, format!("INSERT INTO Fruits (id, name) VALUES ({}, 'kiwi')", 1000 + idx)),
+        Frame::new(frame::EXECUTE, 2 * idx + 1, format!("SELECT id FROM Fruits WHERE id = {}", 1000 + idx)),
+    ]).collect();
+
+    // WHEN
+    let mut output = Writes::default();
+    server.serve(Cursor::new(requests(&frames)), &mut output).unwrap();
+
+    // THEN each select sees the insert before it, and the replies go out in one write
+    assert_eq!(output.0.len(), 1);
+    let replies = replies(&output.0[0]);
+    assert_eq!(replies.len(), 100);
+    for (idx, reply) in replies.iter().enumerate() {
+        assert_eq!((reply.tag, reply.request_id), (frame::REPLY, idx as u32));
+    }
+    assert_eq!(replies[1], Frame::new(frame::REPLY, 1, reply(&server, "SELECT id FROM Fruits WHERE id = 1000")));
+}
+
+#[test]
+fn test_serve_stops_at_broken_frames() {
+    // GIVEN a request cut short, and one longer than frames may be
+    let server = fruits_server();
+    let mut torn = requests(&[Frame::new(frame::EXECUTE, 1, "SELECT * FROM Fruits")]);
+    torn.truncate(torn.len() - 3);
+    let oversized = (frame::MAX_FRAME_BYTES as u32 + 1).to_le_bytes().to_vec();
+
+    // WHEN
+    let mut output = Vec::new();
+    let torn = server.serve(Cursor::new(torn), &mut output);
+    let oversized = server.serve(Cursor::new(oversized), &mut output);
+
+    // THEN
+    assert_eq!(torn.unwrap_err().kind(), std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(oversized.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_text_mode() {
+    // GIVEN statements split by semicolons and lines, with a semicolon in a string
+    let server = fruits_server();
+    let input = [
+        "INSERT INTO Fruits (id, name) VALUES (500, 'kiwi;lime');",
+        "",
+        "SELECT name FROM Fruits WHERE id > 300; SET FORMAT csv; SELECT id, name FROM Fruits WHERE id >= 400",
+        "SELECT * FROM Vegetables",
+        "SET FORMAT json",
+    ].join("\n");
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve_text(Cursor::new(input), &mut output).unwrap();
+
+    // THEN every reply ends with an empty line
+    assert_eq!(String::from_utf8(output).unwrap(), [
+        "INSERTED 1\n\n",
+        "| name      |\n| --------- |\n| cherry    |\n| kiwi;lime |\n(2 rows)\n\n",
+        "OK\n\n",
+        "id,name\n400,cherry\n500,kiwi;lime\n\n",
+        "ERROR TableNotFound(\"Vegetables\")\n\n",
+        "ERROR InputError(\"Unknown format json, expected TABLE or CSV\")\n\n",
+    ].concat());
+}
+
+#[test]
+fn test_pool_sheds_commands_beyond_its_queue() {
+    // GIVEN a pool of one worker and one queued command, with the worker held up on a lock
+    let server = Server::with_pool(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory, 1, 1);
+    let (running, queued, shed) = thread::scope(|scope| {
+        let db = server.database();
+        let lock = db.lock_table("Fruits", LockMode::Exclusive).unwrap();
+        let running = scope.spawn(|| server.handle("DELETE FROM Fruits WHERE id = 100"));
+        thread::sleep(Duration::from_millis(100));
+        let queued = scope.spawn(|| server.handle("SELECT id FROM Fruits WHERE id < 300"));
+        thread::sleep(Duration::from_millis(100));
+
+        // WHEN one more comes
+        let shed = server.handle("SELECT * FROM Fruits");
+        drop(lock);
+        (running.join().unwrap(), queued.join().unwrap(), shed)
+    });
+
+    // THEN it's turned away, the others run in order once the lock is let go
+    assert_eq!(shed.err(), Some(DbError::ServerBusy));
+    assert!(matches!(running, Ok(Reply::Deleted(1))));
+    assert_eq!(queued.unwrap().to_string(), "| id  |\n| --- |\n| 200 |\n(1 rows)");
+    assert!(matches!(server.handle("DELETE FROM Fruits WHERE id = 200"), Ok(Reply::Deleted(1))));
+}
@@ -0,0 +1,50 @@
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, QueryOptions, StorageCfg};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, fruits_table, with_tmp};
+
+// A projected id takes 4 bytes of data and two offsets
+const ID_ROW_BYTES: usize = 4 + 2 * size_of::<usize>();
+
+fn test_query_memory_limit(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.set_query_memory_limit(Some(3 * ID_ROW_BYTES));
+
+    // WHEN
+    let all = db.select(&[ColumnRef("id")], "Fruits", &True);
+    let bananas = db.select(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+
+    // THEN
+    assert_eq!(all.err(), Some(DbError::QueryMemoryExceeded { max: 3 * ID_ROW_BYTES, got: 4 * ID_ROW_BYTES }));
+    check_equality(&bananas, &[[U32(200)], [U32(300)]]);
+}
+
+#[test]
+fn test_query_memory_limit_in_mem() {
+    test_query_memory_limit(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_query_memory_limit_on_disk() {
+    with_tmp(test_query_memory_limit);
+}
+
+#[test]
+fn test_query_options_override_memory_limit() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.set_query_memory_limit(Some(ID_ROW_BYTES));
+
+    // WHEN
+    let raised = QueryOptions { max_memory: Some(4 * ID_ROW_BYTES), ..Default::default() };
+    let all = db.select_with_options(&[ColumnRef("id")], "Fruits", &True, &raised).unwrap();
+    let lowered = QueryOptions { max_memory: Some(0), ..Default::default() };
+    let cherry = db.select_with_options(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("cherry"))), &lowered);
+    let streamed = db.select_stream(&[ColumnRef("id")], "Fruits", &True, &QueryOptions::default(), 1024).unwrap();
+
+    // THEN streams aren't held to the limit
+    assert_eq!(all.len(), 4);
+    assert_eq!(cherry.err(), Some(DbError::QueryMemoryExceeded { max: 0, got: ID_ROW_BYTES }));
+    assert_eq!(streamed.count(), 4);
+}
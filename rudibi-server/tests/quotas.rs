@@ -0,0 +1,105 @@
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, Row, StorageCfg, Table};
+use rudibi_server::limits::{Quota, QuotaKind, QuotaPolicy};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{check_equality, fruits_schema, fruits_table, random_temp_file};
+
+fn test_row_quota_rejects(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.set_quota("Fruits", Quota { max_rows: Some(5), ..Default::default() }).unwrap();
+
+    // WHEN
+    let fits = db.insert("Fruits", &["id", "name"], rows![[500u32, "date"]]);
+    let over = db.insert("Fruits", &["id", "name"], rows![[600u32, "elder"]]);
+
+    // THEN
+    assert!(fits.is_ok());
+    assert_eq!(over, Err(DbError::QuotaExceeded { table: "Fruits".to_string(), quota: QuotaKind::Rows, max: 5, got: 6 }));
+    assert_eq!(db.count("Fruits", &True).unwrap(), 5);
+}
+
+#[test]
+fn test_row_quota_rejects_in_mem() {
+    test_row_quota_rejects(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_row_quota_rejects_on_disk() {
+    let path = random_temp_file();
+    test_row_quota_rejects(StorageCfg::disk(&path));
+    std::fs::remove_file(path).unwrap();
+}
+
+fn test_row_quota_evicts_oldest(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.set_quota("Fruits", Quota { max_rows: Some(4), policy: QuotaPolicy::EvictOldest, ..Default::default() }).unwrap();
+
+    // WHEN
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "date"], [600u32, "elder"]]).unwrap();
+    let too_many = db.insert("Fruits", &["id", "name"], rows![[1u32, "a"], [2u32, "b"], [3u32, "c"], [4u32, "d"], [5u32, "e"]]);
+
+    // THEN
+    assert!(matches!(too_many, Err(DbError::QuotaExceeded { quota: QuotaKind::Rows, .. })));
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(300)], [U32(400)], [U32(500)], [U32(600)]]);
+}
+
+#[test]
+fn test_row_quota_evicts_oldest_in_mem() {
+    test_row_quota_evicts_oldest(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_row_quota_evicts_oldest_on_disk() {
+    let path = random_temp_file();
+    test_row_quota_evicts_oldest(StorageCfg::disk(&path));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_byte_quota_rejects() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    let usage = db.memory_usage().tables["Fruits"];
+    let max = (usage.data + usage.offsets + 10) as u64;
+    db.set_quota("Fruits", Quota { max_bytes: Some(max), policy: QuotaPolicy::EvictOldest, ..Default::default() }).unwrap();
+
+    // WHEN
+    let over = db.insert("Fruits", &["id", "name"], rows![[500u32, "elderberry"]]);
+
+    // THEN
+    assert!(matches!(over, Err(DbError::QuotaExceeded { quota: QuotaKind::Bytes, .. })));
+    assert_eq!(db.count("Fruits", &True).unwrap(), 4);
+}
+
+#[test]
+fn test_disk_budget_covers_all_files() {
+    // GIVEN two disk tables, and an in-memory one the budget doesn't apply to
+    let first = random_temp_file();
+    let second = random_temp_file();
+    let mut db = fruits_table(StorageCfg::disk(&first));
+    db.new_table(&Table::new("Other", fruits_schema().column_layout), StorageCfg::disk(&second)).unwrap();
+    db.new_table(&Table::new("Cache", fruits_schema().column_layout), StorageCfg::InMemory).unwrap();
+    let used = std::fs::metadata(&first).unwrap().len() + std::fs::metadata(&second).unwrap().len();
+
+    // WHEN
+    db.set_disk_budget(Some(used + 5));
+    let over = db.insert("Other", &["id", "name"], rows![[1u32, "apple"]]);
+    let in_memory = db.insert("Cache", &["id", "name"], rows![[1u32, "apple"]]);
+
+    // THEN
+    assert_eq!(over, Err(DbError::QuotaExceeded { table: "Other".to_string(), quota: QuotaKind::DiskBudget, max: used + 5, got: used + 9 }));
+    assert!(in_memory.is_ok());
+    drop(db);
+    std::fs::remove_file(first).unwrap();
+    std::fs::remove_file(second).unwrap();
+}
+
+#[test]
+fn test_quota_needs_table() {
+    let mut db = fruits_table(StorageCfg::InMemory);
+    assert!(matches!(db.set_quota("Nope", Quota::default()), Err(DbError::TableNotFound(_))));
+}
@@ -0,0 +1,132 @@
+use std::io::Cursor;
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::auth::{hash_token, Credentials, Session};
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+use rudibi_server::throttle::{Rate, RateLimits};
+
+const SELECT: &str = "SELECT id FROM Fruits WHERE id > 0";
+
+fn limited_server(per_connection: RateLimits, per_user: RateLimits) -> Server {
+    let mut credentials = Credentials::default();
+    for user in ["alice", "bob"] {
+        credentials.add_token(user, &hash_token(&format!("{user}-token"))).unwrap();
+    }
+    Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory)
+        .with_credentials(credentials)
+        .with_rate_limits(per_connection, per_user)
+}
+
+fn login(server: &Server, user: &str) -> Session {
+    let mut session = server.open_session();
+    server.authenticate(&mut session, None, &format!("{user}-token")).unwrap();
+    session
+}
+
+fn rate_limited(result: Result<impl std::fmt::Debug, DbError>) -> (Rate, Duration) {
+    match result {
+        Err(DbError::RateLimited { rate, retry_after }) => (rate, retry_after),
+        other => panic!("Expected to be rate limited, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_queries_per_connection() {
+    // GIVEN two queries a second for each connection
+    let server = limited_server(RateLimits { queries: Some(2.0), ..Default::default() }, RateLimits::default());
+    let (first, second) = (login(&server, "alice"), login(&server, "alice"));
+
+    // WHEN
+    let replies: Vec<_> = (0..3).map(|_| server.handle_as(&first, SELECT)).collect();
+    let other = server.handle_as(&second, SELECT);
+    thread::sleep(Duration::from_millis(600));
+    let later = server.handle_as(&first, SELECT);
+
+    // THEN
+    assert!(replies[0].is_ok() && replies[1].is_ok());
+    let (rate, retry_after) = rate_limited(replies.into_iter().nth(2).unwrap());
+    assert_eq!(rate, Rate::Queries);
+    assert!(retry_after > Duration::from_millis(400) && retry_after <= Duration::from_millis(500), "{retry_after:?}");
+    assert!(other.is_ok());
+    assert!(later.is_ok());
+}
+
+#[test]
+fn test_rows_scanned_overdraw() {
+    // GIVEN 6 rows scanned a second, each select scanning the 4 fruits
+    let server = limited_server(RateLimits { rows_scanned: Some(6.0), ..Default::default() }, RateLimits::default());
+    let session = login(&server, "alice");
+
+    // WHEN
+    let replies: Vec<_> = (0..3).map(|_| server.handle_as(&session, SELECT)).collect();
+
+    // THEN the second select starts with 2 rows left and overdraws the budget
+    assert!(replies[0].is_ok() && replies[1].is_ok());
+    let (rate, retry_after) = rate_limited(replies.into_iter().nth(2).unwrap());
+    assert_eq!(rate, Rate::RowsScanned);
+    assert!(retry_after > Duration::from_millis(200) && retry_after <= Duration::from_millis(334), "{retry_after:?}");
+}
+
+#[test]
+fn test_bytes_returned_of_streams() {
+    // GIVEN 8 bytes a second, as many as two fruit ids
+    let server = limited_server(RateLimits { bytes_returned: Some(8.0), ..Default::default() }, RateLimits::default());
+    let session = login(&server, "alice");
+
+    // WHEN
+    let streamed = server.select_stream_as(&session, SELECT).map(|rows| rows.data_bytes());
+    let after = server.select_stream_as(&session, SELECT).map(|rows| rows.len());
+
+    // THEN
+    assert_eq!(streamed.unwrap(), 16);
+    assert_eq!(rate_limited(after).0, Rate::BytesReturned);
+}
+
+#[test]
+fn test_queries_per_user() {
+    // GIVEN a query a second for each user
+    let server = limited_server(RateLimits::default(), RateLimits { queries: Some(1.0), ..Default::default() });
+    let (alice, alice_again, bob) = (login(&server, "alice"), login(&server, "alice"), login(&server, "bob"));
+
+    // WHEN
+    let first = server.handle_as(&alice, SELECT);
+    let second = server.handle_as(&alice_again, SELECT);
+    let other = server.handle_as(&bob, SELECT);
+
+    // THEN the user's connections share its limit
+    assert!(first.is_ok());
+    assert_eq!(rate_limited(second).0, Rate::Queries);
+    assert!(other.is_ok());
+}
+
+#[test]
+fn test_error_frames() {
+    // GIVEN
+    let server = limited_server(RateLimits { queries: Some(1.0), ..Default::default() }, RateLimits::default());
+    let mut input = Vec::new();
+    Frame::new(frame::AUTH, 1, "alice-token").write_to(&mut input).unwrap();
+    for id in [2, 3] {
+        Frame::new(frame::EXECUTE, id, SELECT).write_to(&mut input).unwrap();
+    }
+    Frame::new(frame::PING, 4, "").write_to(&mut input).unwrap();
+
+    // WHEN
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+
+    // THEN the limit doesn't hold back pings
+    let mut output = Cursor::new(output);
+    let replies: Vec<Frame> = std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect();
+    assert_eq!(replies[1].tag, frame::REPLY);
+    assert_eq!(replies[2].tag, frame::ERROR);
+    let error = ErrorReply::parse(&replies[2].payload).unwrap();
+    assert_eq!(error.code, 3005);
+    assert_eq!(error.get("rate"), Some("queries"));
+    assert!(error.get("retry_after_ms").unwrap().parse::<u64>().unwrap() <= 1000);
+    assert_eq!(replies[3], Frame::new(frame::REPLY, 4, "PONG"));
+}
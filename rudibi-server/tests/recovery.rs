@@ -0,0 +1,282 @@
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::collation::Collation;
+use rudibi_server::compress::Compression;
+use rudibi_server::storage::{DiskStorage, Durability, RecoveryReport, FORMAT_VERSION, PAGE_SIZE};
+use rudibi_server::testlib::{check_equality, fruits_schema, fruits_table, random_temp_file};
+
+fn written_fruits(path: &str) {
+    let db = fruits_table(StorageCfg::disk(path));
+    db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(200)))).unwrap();
+}
+
+#[test]
+fn test_reopen_intact_file() {
+    // GIVEN
+    let path = random_temp_file();
+    written_fruits(&path);
+
+    // WHEN
+    let mut db = Database::new();
+    let report = db.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+
+    // THEN
+    assert_eq!(report, RecoveryReport { rows_recovered: 4, bytes_truncated: 0, pages_repaired: 0 });
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(100)], [U32(300)], [U32(400)], [U32(500)]]);
+    assert_eq!(db.get_by_id("Fruits", 4).unwrap().unwrap().get_column(1), b"kiwi");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_torn_page_is_repaired() {
+    // GIVEN the end of the last page, where its first row is stored, was cut off
+    let path = random_temp_file();
+    written_fruits(&path);
+    let len = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+
+    // WHEN
+    let mut db = Database::new();
+    let report = db.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+
+    // THEN the page keeps its intact rows
+    assert_eq!(report, RecoveryReport { rows_recovered: 3, bytes_truncated: 0, pages_repaired: 1 });
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(300)], [U32(400)]]);
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    assert_eq!(db.count("Fruits", &True), Ok(3));
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), len);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_garbage_tail_is_truncated() {
+    // GIVEN
+    let path = random_temp_file();
+    written_fruits(&path);
+    OpenOptions::new().append(true).open(&path).unwrap().write_all(&[0u8; 40]).unwrap();
+
+    // WHEN
+    let mut db = Database::new();
+    let report = db.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+
+    // THEN
+    assert_eq!(report, RecoveryReport { rows_recovered: 4, bytes_truncated: 40, pages_repaired: 0 });
+    assert_eq!(db.count("Fruits", &True), Ok(3));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_open_new_file() {
+    let path = random_temp_file();
+    std::fs::remove_file(&path).unwrap();
+    let mut db = Database::new();
+
+    let report = db.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[100u32, "apple"]]).unwrap();
+
+    assert_eq!(report, RecoveryReport { rows_recovered: 0, bytes_truncated: 0, pages_repaired: 0 });
+    assert_eq!(db.count("Fruits", &True), Ok(1));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_open_with_other_schema() {
+    let path = random_temp_file();
+    written_fruits(&path);
+    let mut db = Database::new();
+
+    let result = db.open_table(&Table::new("Fruits", vec![Column::new("id", DataType::U32)]), &path, Durability::Flush);
+
+    assert!(matches!(result, Err(DbError::DatabaseIntegrityError(_))), "{result:?}");
+    assert!(matches!(db.schema_for("Fruits"), Err(DbError::TableNotFound(_))));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_synced_writes_survive_reopen() {
+    for durability in [Durability::SyncPerBatch, Durability::SyncPerCommit] {
+        // GIVEN
+        let path = random_temp_file();
+        let db = fruits_table(StorageCfg::Disk { path: path.clone(), durability, compression: Compression::None });
+        db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+        drop(db);
+
+        // WHEN
+        let mut reopened = Database::new();
+        reopened.open_table(&fruits_schema(), &path, durability).unwrap();
+
+        // THEN
+        let results = reopened.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+        check_equality(&results, &[[U32(200)], [U32(300)], [U32(400)]]);
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_new_table_reopens_existing_file() {
+    // GIVEN
+    let path = random_temp_file();
+    written_fruits(&path);
+
+    // WHEN
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), StorageCfg::disk(&path)).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+
+    // THEN
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(100)], [U32(300)], [U32(400)], [U32(500)]]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_new_table_rejects_other_layout() {
+    // GIVEN a file with the same number of columns but different types
+    let path = random_temp_file();
+    written_fruits(&path);
+    let other = Table::new("Fruits", vec![
+        Column::new("id", DataType::U32),
+        Column::new("name", DataType::UTF8 { max_bytes: 40 }),
+    ]);
+
+    // WHEN
+    let mut db = Database::new();
+    let result = db.new_table(&other, StorageCfg::disk(&path));
+
+    // THEN
+    assert!(matches!(result, Err(DbError::DatabaseIntegrityError(_))), "{result:?}");
+    assert!(matches!(db.schema_for("Fruits"), Err(DbError::TableNotFound(_))));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_header_describes_columns() {
+    let path = random_temp_file();
+    let schema = Table::new("Tags", vec![
+        Column::new("label", DataType::UTF8 { max_bytes: 8 }).with_collation(Collation::CaseInsensitive),
+        Column::new("kind", DataType::ENUM { labels: vec!["a".to_string(), "b".to_string()] }),
+        Column::new("blob", DataType::VARBINARY { max_length: 16 }),
+    ]);
+    Database::new().new_table(&schema, StorageCfg::disk(&path)).unwrap();
+
+    assert_eq!(DiskStorage::stored_layout(&path).unwrap(), schema.column_layout);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_unknown_format_version_is_rejected() {
+    // GIVEN a file written by a newer release
+    let path = random_temp_file();
+    written_fruits(&path);
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(4)).unwrap();
+    file.write_all(&(FORMAT_VERSION + 1).to_le_bytes()).unwrap();
+    drop(file);
+
+    // WHEN
+    let mut db = Database::new();
+    let opened = db.open_table(&fruits_schema(), &path, Durability::Flush);
+    let created = db.new_table(&fruits_schema(), StorageCfg::disk(&path));
+
+    // THEN
+    let unsupported = || Err(DbError::UnsupportedFormatVersion { found: FORMAT_VERSION + 1, supported: FORMAT_VERSION });
+    assert_eq!(opened.map(|_| ()), unsupported());
+    assert_eq!(created, unsupported());
+    assert_eq!(DiskStorage::stored_layout(&path).map(|_| ()), unsupported());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_rows_bigger_than_a_page() {
+    // GIVEN
+    let path = random_temp_file();
+    let schema = Table::new("Notes", vec![
+        Column::new("id", DataType::U32),
+        Column::new("text", DataType::UTF8 { max_bytes: 3 * PAGE_SIZE }),
+    ]);
+    let texts: Vec<String> = (0..3).map(|i| "x".repeat(PAGE_SIZE * (i + 1) / 2)).collect();
+    let mut db = Database::new();
+    db.new_table(&schema, StorageCfg::disk(&path)).unwrap();
+    for (id, text) in texts.iter().enumerate() {
+        db.insert("Notes", &["id", "text"], rows![[id as u32, text.as_str()]]).unwrap();
+    }
+    db.delete("Notes", &Eq(ColumnRef("id"), Const(U32(0)))).unwrap();
+
+    // WHEN
+    let mut reopened = Database::new();
+    let report = reopened.open_table(&schema, &path, Durability::Flush).unwrap();
+
+    // THEN
+    assert_eq!(report, RecoveryReport { rows_recovered: 3, bytes_truncated: 0, pages_repaired: 0 });
+    let results = reopened.select(&[ColumnRef("text")], "Notes", &True).unwrap();
+    check_equality(&results, &[[UTF8(&texts[1])], [UTF8(&texts[2])]]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_many_pages_survive_reopen() {
+    // GIVEN
+    let path = random_temp_file();
+    let mut db = Database::new();
+    db.new_table(&fruits_schema(), StorageCfg::disk(&path)).unwrap();
+    let data: Vec<Row> = (0..5000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"fruit"])).collect();
+    db.insert("Fruits", &["id", "name"], &data).unwrap();
+    db.delete("Fruits", &Lt(ColumnRef("id"), Const(U32(4000)))).unwrap();
+    drop(db);
+
+    // WHEN
+    let mut reopened = Database::new();
+    let report = reopened.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+
+    // THEN
+    assert_eq!(report.rows_recovered, 5000);
+    assert_eq!(reopened.count("Fruits", &True), Ok(1000));
+    assert_eq!(reopened.get_by_id("Fruits", 4999).unwrap().unwrap().get_column(0), 4999u32.to_le_bytes());
+    std::fs::remove_file(path).unwrap();
+}
+
+// Row as written by format version 1: tombstone, row id, then 64-bit column offsets and content
+// length, content
+fn v1_row(row_id: u64, id: u32, name: &str) -> Vec<u8> {
+    let mut record = vec![0];
+    record.extend_from_slice(&row_id.to_le_bytes());
+    let name_end = 4 + name.len() as u64;
+    for offset in [0, 4, name_end, name_end] {
+        record.extend_from_slice(&offset.to_le_bytes());
+    }
+    record.extend_from_slice(&id.to_le_bytes());
+    record.extend_from_slice(name.as_bytes());
+    record
+}
+
+#[test]
+fn test_version_1_file_is_upgraded() {
+    // GIVEN a version 1 file, which appended rows right after the header
+    let path = random_temp_file();
+    Database::new().new_table(&fruits_schema(), StorageCfg::disk(&path)).unwrap();
+    let mut old = std::fs::read(&path).unwrap();
+    old[4..8].copy_from_slice(&1u32.to_le_bytes());
+    old.extend(v1_row(0, 100, "apple"));
+    old.extend(v1_row(1, 200, "banana"));
+    std::fs::write(&path, old).unwrap();
+
+    // WHEN
+    let mut db = Database::new();
+    let report = db.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[300u32, "cherry"]]).unwrap();
+
+    // THEN
+    assert_eq!(report.rows_recovered, 2);
+    let results = db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(100), UTF8("apple")], [U32(200), UTF8("banana")], [U32(300), UTF8("cherry")]]);
+    assert!(DiskStorage::stored_layout(&path).is_ok());
+    std::fs::remove_file(path).unwrap();
+}
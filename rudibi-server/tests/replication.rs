@@ -0,0 +1,201 @@
+use std::io::Cursor;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rudibi_server::dtype::DataType;
+use rudibi_server::engine::{Column, Database, DbError, StorageCfg, Table};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::replication::{self, ChangeFeed, FeedRecord, SnapshotChunk};
+use rudibi_server::serial;
+use rudibi_server::testlib::fruits_table;
+use rudibi_server::wal::WalChange;
+
+const SELECT: &str = "SELECT id, name FROM Fruits WHERE id > 0";
+
+// A primary serving frames on a port of its own, one thread per connection
+fn primary(backlog: usize) -> (Arc<Server>, String) {
+    let server = Arc::new(Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory).with_change_feed(backlog));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    thread::spawn({
+        let server = server.clone();
+        move || for conn in listener.incoming().flatten() {
+            let server = server.clone();
+            thread::spawn(move || server.serve(&conn, &conn));
+        }
+    });
+    (server, address)
+}
+
+fn replica_of(address: &str) -> Arc<Server> {
+    let replica = Arc::new(Server::new(Database::new(), StorageCfg::InMemory).as_replica());
+    let conn = TcpStream::connect(address).unwrap();
+    thread::spawn({
+        let replica = replica.clone();
+        move || replication::replicate(&replica, &conn, &conn, None)
+    });
+    replica
+}
+
+// Waits for the replica to reply to `text` as the primary does
+fn assert_caught_up(primary: &Server, replica: &Server, text: &str) {
+    let expected = primary.handle(text).unwrap().to_string();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let got = replica.handle(text).map(|reply| reply.to_string());
+        if got.as_ref() == Ok(&expected) {
+            return;
+        }
+        assert!(Instant::now() < deadline, "Replica replied {got:?} rather than {expected}");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn replies(server: &Server, requests: &[Frame]) -> Vec<Frame> {
+    let mut input = Vec::new();
+    for request in requests {
+        request.write_to(&mut input).unwrap();
+    }
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect()
+}
+
+#[test]
+fn test_snapshot_then_changes() {
+    // GIVEN
+    let (primary, address) = primary(1000);
+    let replica = replica_of(&address);
+    assert_caught_up(&primary, &replica, SELECT);
+
+    // WHEN
+    primary.handle("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi'), (600, 'lime')").unwrap();
+    primary.handle("DELETE FROM Fruits WHERE name = 'banana' OR id = 500").unwrap();
+    primary.handle("INSERT INTO Fruits (id, name) VALUES (700, 'mango')").unwrap();
+
+    // THEN
+    assert_caught_up(&primary, &replica, SELECT);
+    let replicated = replica.handle(SELECT).unwrap().to_string();
+    assert!(replicated.contains("mango") && !replicated.contains("banana") && !replicated.contains("kiwi"), "{replicated}");
+}
+
+#[test]
+fn test_tables_created_later() {
+    // GIVEN
+    let (primary, address) = primary(1000);
+    let replica = replica_of(&address);
+    assert_caught_up(&primary, &replica, SELECT);
+
+    // WHEN
+    primary.handle("CREATE TABLE Vegetables (id U32, name UTF8(16))").unwrap();
+    primary.handle("INSERT INTO Vegetables (id, name) VALUES (1, 'leek')").unwrap();
+    primary.handle("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')").unwrap();
+
+    // THEN the replica takes a new snapshot with the table
+    assert_caught_up(&primary, &replica, "SELECT id, name FROM Vegetables");
+    assert_caught_up(&primary, &replica, SELECT);
+}
+
+#[test]
+fn test_replica_is_read_only() {
+    // GIVEN
+    let (primary, address) = primary(1000);
+    let replica = replica_of(&address);
+    assert_caught_up(&primary, &replica, SELECT);
+
+    // WHEN
+    let insert = replica.handle("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')");
+    let create = replica.handle("CREATE TABLE Vegetables (id U32)");
+
+    // THEN
+    assert!(matches!(insert, Err(DbError::ReadOnly)), "{insert:?}");
+    assert!(matches!(create, Err(DbError::ReadOnly)), "{create:?}");
+    assert!(replica.handle("SHOW TABLES").is_ok());
+}
+
+#[test]
+fn test_expired_changes() {
+    // GIVEN a feed keeping the last two changes
+    let (primary, _) = primary(2);
+    for id in [500, 600, 700] {
+        primary.handle(&format!("INSERT INTO Fruits (id, name) VALUES ({id}, 'kiwi')")).unwrap();
+    }
+
+    // WHEN
+    let replies = replies(&primary, &[Frame::new(frame::CHANGES, 1, "0"), Frame::new(frame::CHANGES, 2, "1")]);
+
+    // THEN
+    assert_eq!(replies[0].tag, frame::ERROR);
+    let error = ErrorReply::parse(&replies[0].payload).unwrap();
+    assert_eq!((error.code, error.get("oldest")), (3006, Some("2")));
+    let changes: Vec<FeedRecord> = serial::decode(&replies[1].payload).unwrap();
+    assert_eq!(changes.iter().map(|change| change.record.sequence).collect::<Vec<_>>(), [2, 3]);
+    assert!(matches!(&changes[0].record.change, WalChange::Store { rows, .. } if rows.len() == 1));
+}
+
+#[test]
+fn test_fingerprints_are_sent() {
+    // GIVEN
+    let (primary, _) = primary(1000);
+    let fingerprint = primary.database().schema_fingerprint("Fruits").unwrap();
+    primary.handle("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')").unwrap();
+
+    // WHEN
+    let replies = replies(&primary, &[Frame::new(frame::SNAPSHOT, 1, ""), Frame::new(frame::CHANGES, 2, "0")]);
+
+    // THEN the snapshot's chunks and the changes have the primary's fingerprint of Fruits
+    let chunk: SnapshotChunk = serial::decode(&replies[0].payload).unwrap();
+    assert_eq!((chunk.table.name.as_str(), chunk.fingerprint), ("Fruits", fingerprint));
+    let changes: Vec<FeedRecord> = serial::decode(&replies.last().unwrap().payload).unwrap();
+    assert!(!changes.is_empty() && changes.iter().all(|change| change.fingerprint == fingerprint));
+}
+
+#[test]
+fn test_replica_with_another_schema() {
+    // GIVEN a replica with its own Fruits table
+    let (_, address) = primary(1000);
+    let own = Table::new("Fruits", vec![Column::new("id", DataType::U32)]);
+    let mut db = Database::new();
+    db.new_table(&own, StorageCfg::InMemory).unwrap();
+    let replica = Server::new(db, StorageCfg::InMemory).as_replica();
+    let conn = TcpStream::connect(&address).unwrap();
+
+    // WHEN
+    let replicated = replication::replicate(&replica, &conn, &conn, None);
+
+    // THEN
+    let err = replicated.unwrap_err();
+    assert!(err.to_string().contains("Fruits has another schema on the primary"), "{err}");
+    assert_eq!(replica.database().schema_fingerprint("Fruits"), Ok(own.fingerprint()));
+}
+
+#[test]
+fn test_changes_wait_for_a_change() {
+    // GIVEN
+    let feed = ChangeFeed::new(10);
+
+    // WHEN
+    let started = Instant::now();
+    let changes = feed.changes_after(0, Duration::from_millis(50)).unwrap();
+
+    // THEN
+    assert!(changes.is_empty());
+    assert!(started.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn test_server_without_feed() {
+    // GIVEN
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+
+    // WHEN
+    let replies = replies(&server, &[Frame::new(frame::SNAPSHOT, 1, ""), Frame::new(frame::CHANGES, 2, "0")]);
+
+    // THEN
+    assert!(replies.iter().all(|reply| reply.tag == frame::ERROR && ErrorReply::parse(&reply.payload).unwrap().code == 9001));
+}
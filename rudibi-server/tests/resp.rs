@@ -0,0 +1,110 @@
+use std::io::Cursor;
+
+use rudibi_server::auth::Session;
+use rudibi_server::engine::{Database, StorageCfg};
+use rudibi_server::protocol::Server;
+use rudibi_server::query::Bool::True;
+use rudibi_server::resp::{self, Resp};
+use rudibi_server::testlib::fruits_table;
+
+fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut bytes = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        bytes.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        bytes.extend_from_slice(arg);
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes
+}
+
+fn serve(server: &Server, input: Vec<u8>) -> String {
+    let mut output = Vec::new();
+    resp::serve(server, Cursor::new(input), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn run(server: &Server, args: &[&str]) -> Resp {
+    resp::run(server, &mut Session::default(), &args.iter().map(|arg| arg.as_bytes().to_vec()).collect::<Vec<_>>()).unwrap()
+}
+
+fn bulk(text: &str) -> Resp {
+    Resp::Bulk(Some(text.as_bytes().to_vec()))
+}
+
+#[test]
+fn test_set_get_del() {
+    // GIVEN
+    let server = Server::new(Database::new(), StorageCfg::InMemory);
+
+    // WHEN
+    let missing = run(&server, &["GET", "a"]);
+    let deleted_nothing = run(&server, &["DEL", "a"]);
+    run(&server, &["SET", "a", "1"]);
+    run(&server, &["set", "b", "2"]);
+    let set = run(&server, &["SET", "a", "3"]);
+
+    // THEN setting a key again replaces its value
+    assert_eq!(missing, Resp::Bulk(None));
+    assert_eq!(deleted_nothing, Resp::Integer(0));
+    assert_eq!(set, Resp::Simple("OK"));
+    assert_eq!(run(&server, &["GET", "a"]), bulk("3"));
+    assert_eq!(run(&server, &["EXISTS", "a", "b", "b", "c"]), Resp::Integer(3));
+    assert_eq!(run(&server, &["DEL", "a", "c"]), Resp::Integer(1));
+    assert_eq!(run(&server, &["GET", "a"]), Resp::Bulk(None));
+    assert_eq!(server.database().count(resp::TABLE, &True).unwrap(), 1);
+}
+
+#[test]
+fn test_rselect() {
+    // GIVEN
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+
+    // WHEN
+    let selected = run(&server, &["RSELECT", "id, name FROM Fruits", "WHERE id > 250"]);
+
+    // THEN
+    assert_eq!(selected, Resp::Array(vec![
+        Resp::Array(vec![bulk("300"), bulk("banana")]),
+        Resp::Array(vec![bulk("400"), bulk("cherry")]),
+    ]));
+}
+
+#[test]
+fn test_serve() {
+    // GIVEN binary-safe values, inline commands and errors
+    let server = Server::new(Database::new(), StorageCfg::InMemory);
+    let mut input = command(&[b"SET", b"k", b"a\r\nb"]);
+    input.extend(command(&[b"GET", b"k"]));
+    input.extend_from_slice(b"PING\r\nGET\r\nFLUSHALL\r\n");
+    input.extend(command(&[b"RSELECT", b"key FROM KeyValues"]));
+    input.extend_from_slice(b"QUIT\r\nPING\r\n");
+
+    // WHEN
+    let output = serve(&server, input);
+
+    // THEN nothing after QUIT is replied to
+    assert_eq!(output, [
+        "+OK\r\n",
+        "$4\r\na\r\nb\r\n",
+        "+PONG\r\n",
+        "-ERR InputError(\"Wrong number of arguments for GET\")\r\n",
+        "-ERR InputError(\"Unknown command FLUSHALL\")\r\n",
+        "*1\r\n*1\r\n$1\r\nk\r\n",
+        "+OK\r\n",
+    ].concat());
+}
+
+#[test]
+fn test_serve_stops_at_broken_commands() {
+    // GIVEN a bulk string longer than its length
+    let server = Server::new(Database::new(), StorageCfg::InMemory);
+    let input = b"*2\r\n$3\r\nGET\r\n$1\r\nkey\r\n".to_vec();
+
+    // WHEN
+    let mut output = Vec::new();
+    let served = resp::serve(&server, Cursor::new(input), &mut output);
+
+    // THEN
+    assert_eq!(served.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    assert!(output.is_empty());
+}
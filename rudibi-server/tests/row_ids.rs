@@ -0,0 +1,241 @@
+use std::borrow::Cow;
+use std::fs::OpenOptions;
+use std::io::{Seek, SeekFrom, Write};
+
+use rudibi_server::engine::{DbError, Row, StorageCfg};
+use rudibi_server::storage::{DiskStorage, InMemoryStorage, RowId, Storage};
+use rudibi_server::testlib::{fruits_schema, fruits_table, random_temp_file};
+use rudibi_server::rows;
+
+fn scanned_ids(storage: &dyn Storage) -> Vec<RowId> {
+    storage.scan().map(|item| item.row_id).collect()
+}
+
+fn ids_survive_deletes(storage: &mut dyn Storage) {
+    // GIVEN
+    let mapping = vec![0, 1];
+    let first = storage.store(rows![[100u32, "apple"], [200u32, "banana"], [300u32, "cherry"]], &mapping);
+    assert_eq!(first, vec![0, 1, 2]);
+
+    // WHEN
+    storage.delete_rows(vec![0]);
+    let second = storage.store(rows![[400u32, "date"]], &mapping);
+    storage.delete_rows(vec![2, 42]);
+
+    // THEN
+    assert_eq!(second, vec![3]);
+    assert_eq!(scanned_ids(storage), vec![1, 3]);
+    assert_eq!(storage.row_count(), 2);
+    let remaining: Vec<Vec<u8>> = storage.scan().map(|item| item.row_content.get_column(1).to_vec()).collect();
+    assert_eq!(remaining, vec![b"banana".to_vec(), b"date".to_vec()]);
+}
+
+#[test]
+fn ids_survive_deletes_in_mem() {
+    ids_survive_deletes(&mut InMemoryStorage::new(fruits_schema()));
+}
+
+#[test]
+fn ids_survive_deletes_on_disk() {
+    let path = random_temp_file();
+    ids_survive_deletes(&mut DiskStorage::new(fruits_schema(), &path).unwrap());
+    std::fs::remove_file(path).unwrap();
+}
+
+fn delete_already_deleted(storage: &mut dyn Storage) {
+    let mapping = vec![0, 1];
+    storage.store(rows![[100u32, "apple"], [200u32, "banana"]], &mapping);
+
+    storage.delete_rows(vec![1]);
+    storage.delete_rows(vec![1, 1]);
+
+    assert_eq!(scanned_ids(storage), vec![0]);
+    assert_eq!(storage.row_count(), 1);
+}
+
+#[test]
+fn delete_already_deleted_in_mem() {
+    delete_already_deleted(&mut InMemoryStorage::new(fruits_schema()));
+}
+
+#[test]
+fn delete_already_deleted_on_disk() {
+    let path = random_temp_file();
+    delete_already_deleted(&mut DiskStorage::new(fruits_schema(), &path).unwrap());
+    std::fs::remove_file(path).unwrap();
+}
+
+fn point_lookups(storage: &mut dyn Storage) {
+    // GIVEN
+    let mapping = vec![0, 1];
+    storage.store(rows![[100u32, "apple"], [200u32, "banana"], [300u32, "cherry"]], &mapping);
+    storage.delete_rows(vec![1]);
+
+    // WHEN
+    let cherry = storage.get_row(2).unwrap();
+
+    // THEN
+    assert_eq!(cherry.row_content.get_column(1), b"cherry");
+    assert!(storage.get_row(1).is_none());
+    assert!(storage.get_row(42).is_none());
+    let fetched: Vec<RowId> = storage.get_rows(&[0, 1, 2]).map(|item| item.row_id).collect();
+    assert_eq!(fetched, vec![0, 2]);
+}
+
+#[test]
+fn point_lookups_in_mem() {
+    point_lookups(&mut InMemoryStorage::new(fruits_schema()));
+}
+
+#[test]
+fn point_lookups_on_disk() {
+    let path = random_temp_file();
+    point_lookups(&mut DiskStorage::new(fruits_schema(), &path).unwrap());
+    std::fs::remove_file(path).unwrap();
+}
+
+fn delete_where_in_one_scan(storage: &mut dyn Storage) {
+    // GIVEN
+    let mapping = vec![0, 1];
+    storage.store(rows![[100u32, "apple"], [200u32, "banana"], [300u32, "banana"], [400u32, "cherry"]], &mapping);
+    storage.delete_rows(vec![0]);
+
+    // WHEN
+    let failed = storage.delete_where(&mut |item| match item.row_id {
+        3 => Err(DbError::DatabaseIntegrityError("bad row".to_string())),
+        _ => Ok(true),
+    });
+    let removed = storage.delete_where(&mut |item| Ok(item.row_content.get_column(1) == b"banana")).unwrap();
+
+    // THEN
+    assert!(failed.is_err());
+    assert_eq!(removed, vec![1, 2]);
+    assert_eq!(scanned_ids(storage), vec![3]);
+    assert_eq!(storage.row_count(), 1);
+    assert!(storage.get_row(2).is_none());
+}
+
+#[test]
+fn delete_where_in_one_scan_in_mem() {
+    delete_where_in_one_scan(&mut InMemoryStorage::new(fruits_schema()));
+}
+
+#[test]
+fn delete_where_in_one_scan_on_disk() {
+    let path = random_temp_file();
+    delete_where_in_one_scan(&mut DiskStorage::new(fruits_schema(), &path).unwrap());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn row_locations_survive_reopen() {
+    // GIVEN
+    let path = random_temp_file();
+    let mut storage = DiskStorage::new(fruits_schema(), &path).unwrap();
+    let data: Vec<Row> = (0..3000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"fruit"])).collect();
+    storage.store(&data, &vec![0, 1]);
+    drop(storage);
+
+    // WHEN
+    let mut reopened = DiskStorage::new(fruits_schema(), &path).unwrap();
+    reopened.delete_rows(vec![5, 2999]);
+    let added = reopened.store(rows![[3000u32, "kiwi"]], &vec![0, 1]);
+
+    // THEN
+    assert!(reopened.get_row(5).is_none());
+    assert_eq!(reopened.get_row(1500).unwrap().row_content.get_column(0), 1500u32.to_le_bytes());
+    assert_eq!(reopened.get_row(3000).unwrap().row_content.get_column(1), b"kiwi");
+    assert_eq!(added, vec![3000]);
+    assert_eq!(reopened.row_count(), 2999);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn scans_skip_pages_without_live_rows() {
+    // GIVEN a first page with only deleted rows
+    let path = random_temp_file();
+    let mut storage = DiskStorage::new(fruits_schema(), &path).unwrap();
+    let header_len = std::fs::metadata(&path).unwrap().len();
+    let data: Vec<Row> = (0..3000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"fruit"])).collect();
+    storage.store(&data, &vec![0, 1]);
+    storage.delete_rows((0..500).collect());
+
+    // WHEN the page is damaged, a scan reading it would fail its checksum
+    let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(SeekFrom::Start(header_len + 100)).unwrap();
+    file.write_all(&[0xff; 8]).unwrap();
+
+    // THEN
+    let ids = scanned_ids(&storage);
+    assert_eq!(ids.len(), 2500);
+    assert_eq!(ids[0], 500);
+    assert_eq!(storage.row_count(), 2500);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn get_by_id() {
+    let db = fruits_table(StorageCfg::InMemory);
+
+    let row = db.get_by_id("Fruits", 1).unwrap().unwrap();
+
+    assert_eq!(row.get_column(0), 200u32.to_le_bytes());
+    assert_eq!(row.get_column(1), b"banana");
+    assert!(db.get_by_id("Fruits", 42).unwrap().is_none());
+    assert!(matches!(db.get_by_id("Vegetables", 1), Err(DbError::TableNotFound(_))));
+}
+
+#[test]
+fn tombstones_until_compaction_in_mem() {
+    // GIVEN
+    let mut storage = InMemoryStorage::new(fruits_schema());
+    let mapping = vec![0, 1];
+    let rows: Vec<Row> = (0..10u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"fruit"])).collect();
+    storage.store(&rows, &mapping);
+
+    // WHEN
+    storage.delete_rows(vec![1, 4, 7]);
+    let before_compaction = scanned_ids(&storage);
+    storage.compact();
+
+    // THEN
+    assert_eq!(before_compaction, vec![0, 2, 3, 5, 6, 8, 9]);
+    assert_eq!(scanned_ids(&storage), before_compaction);
+    assert_eq!(storage.row_count(), 7);
+    assert!(storage.get_row(4).is_none());
+    assert_eq!(storage.get_row(5).unwrap().row_content.get_column(0), 5u32.to_le_bytes());
+    assert_eq!(storage.store(rows![[10u32, "fig"]], &mapping), vec![10]);
+}
+
+#[test]
+fn deleting_most_rows_compacts_in_mem() {
+    let mut storage = InMemoryStorage::new(fruits_schema());
+    let rows: Vec<Row> = (0..100_000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"fruit"])).collect();
+    storage.store(&rows, &vec![0, 1]);
+
+    storage.delete_rows((0..100_000).filter(|id| id % 4 != 0).collect());
+
+    assert_eq!(storage.row_count(), 25_000);
+    assert_eq!(scanned_ids(&storage).len(), 25_000);
+    assert_eq!(storage.get_row(99_996).unwrap().row_content.get_column(0), 99_996u32.to_le_bytes());
+}
+
+#[test]
+fn disk_rows_are_owned_by_the_scan() {
+    // GIVEN
+    let path = random_temp_file();
+    let mut on_disk = DiskStorage::new(fruits_schema(), &path).unwrap();
+    let mut in_mem = InMemoryStorage::new(fruits_schema());
+    on_disk.store(rows![[100u32, "apple"]], &vec![0, 1]);
+    in_mem.store(rows![[100u32, "apple"]], &vec![0, 1]);
+
+    // WHEN
+    let decoded = on_disk.scan().next().unwrap();
+    let borrowed = in_mem.scan().next().unwrap();
+
+    // THEN rows read from disk are freed with the item, not kept for the life of the program
+    assert!(matches!(decoded.row_content.data, Cow::Owned(_)));
+    assert!(matches!(borrowed.row_content.data, Cow::Borrowed(_)));
+    assert_eq!(decoded.row_content.get_column(1), borrowed.row_content.get_column(1));
+    std::fs::remove_file(path).unwrap();
+}
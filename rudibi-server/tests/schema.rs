@@ -1,6 +1,8 @@
 
-use rudibi_server::dtype::DataType;
+use rudibi_server::dtype::{ColumnValue::*, DataType};
 use rudibi_server::engine::*;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, fruits_schema, fruits_table, random_temp_file};
 
 #[test]
 fn create_duplicate_table() {
@@ -15,4 +17,81 @@ fn create_empty_table() {
     let mut db = Database::new();
     let result = db.new_table(&Table::new("EmptyTable", vec![]), StorageCfg::InMemory);
     assert_eq!(result.unwrap_err(), DbError::EmptyTableSchema);
-}
\ No newline at end of file
+}
+
+#[test]
+fn rename_table() {
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.rename_table("Fruits", "Food").unwrap();
+
+    assert_eq!(db.schema_for("Fruits").unwrap_err(), DbError::TableNotFound("Fruits".to_string()));
+    assert_eq!(db.schema_for("Food").unwrap().name, "Food");
+    let results = db.select(&[ColumnRef("id"), ColumnRef("name")], "Food", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+    check_equality(&results, &[[U32(100), UTF8("apple")]]);
+}
+
+#[test]
+fn rename_table_to_existing_name() {
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.new_table(&Table::new("Food", vec![Column::new("id", DataType::U32)]), StorageCfg::InMemory).unwrap();
+
+    let result = db.rename_table("Fruits", "Food");
+
+    assert_eq!(result.unwrap_err(), DbError::TableAlreadyExists("Food".to_string()));
+    assert!(db.schema_for("Fruits").is_ok());
+}
+
+#[test]
+fn rename_unknown_table() {
+    let mut db = Database::new();
+    let result = db.rename_table("Unknown", "Other");
+    assert_eq!(result.unwrap_err(), DbError::TableNotFound("Unknown".to_string()));
+}
+
+#[test]
+fn rename_disk_table_with_path() {
+    let old_path = random_temp_file();
+    let new_path = format!("{old_path}_renamed");
+    let mut db = fruits_table(StorageCfg::disk(&old_path));
+
+    db.rename_table_with_path("Fruits", "Food", &new_path).unwrap();
+
+    assert!(!std::path::Path::new(&old_path).exists());
+    let results = db.select(&[ColumnRef("id")], "Food", &True).unwrap();
+    assert_eq!(results.len(), 4);
+    std::fs::remove_file(new_path).unwrap();
+}
+
+#[test]
+fn rename_in_memory_table_with_path() {
+    let mut db = fruits_table(StorageCfg::InMemory);
+    let result = db.rename_table_with_path("Fruits", "Food", "/nonexistent/path");
+    assert!(matches!(result, Err(DbError::UnsupportedOperation(_))));
+    assert!(db.schema_for("Fruits").is_ok());
+}
+
+#[test]
+fn fingerprint_is_stable() {
+    // Persisted and exchanged between peers, so the value must not change between builds
+    assert_eq!(fruits_schema().fingerprint(), fruits_schema().fingerprint());
+    assert_eq!(fruits_schema().fingerprint(), 0x1519fc395f66c97c);
+}
+
+#[test]
+fn fingerprint_detects_schema_drift() {
+    let base = fruits_schema().fingerprint();
+    let renamed = Table::new("Fruit", fruits_schema().column_layout).fingerprint();
+    let wider = Table::new("Fruits", vec![Column::new("id", DataType::U32), Column::new("name", DataType::UTF8 { max_bytes: 21 })]).fingerprint();
+    let reordered = Table::new("Fruits", vec![Column::new("name", DataType::UTF8 { max_bytes: 20 }), Column::new("id", DataType::U32)]).fingerprint();
+
+    assert_ne!(base, renamed);
+    assert_ne!(base, wider);
+    assert_ne!(base, reordered);
+}
+
+#[test]
+fn database_schema_fingerprint() {
+    let db = fruits_table(StorageCfg::InMemory);
+    assert_eq!(db.schema_fingerprint("Fruits"), Ok(fruits_schema().fingerprint()));
+    assert_eq!(db.schema_fingerprint("Unknown"), Err(DbError::TableNotFound("Unknown".to_string())));
+}
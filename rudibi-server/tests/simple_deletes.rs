@@ -7,7 +7,7 @@ use rudibi_server::testlib::{empty_table, fruits_table, check_equality, with_tmp
 #[test]
 fn test_delete_non_existent_table() {
     // GIVEN
-    let mut db = Database::new();
+    let db = Database::new();
     
     // WHEN
     let result = db.delete("NonExistent", &True);
@@ -19,7 +19,7 @@ fn test_delete_non_existent_table() {
 
 fn test_delete_empty(storage: StorageCfg) {
     // GIVEN
-    let mut db = empty_table(storage);
+    let db = empty_table(storage);
 
     // WHEN
     let deleted_count = db.delete("EmptyTable", &True).unwrap();
@@ -41,7 +41,7 @@ fn test_delete_empty_on_disk() {
 
 fn test_delete_with_equality_filter(storage: StorageCfg) {
     // GIVEN
-    let mut db = fruits_table(storage);
+    let db = fruits_table(storage);
 
     // WHEN
     let deleted_count = db.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
@@ -68,7 +68,7 @@ fn test_delete_with_equality_filter_on_disk() {
 
 fn test_delete_with_greater_than_filter(storage: StorageCfg) {
     // GIVEN
-    let mut db = fruits_table(storage);
+    let db = fruits_table(storage);
 
     // WHEN
     let deleted_count = db.delete("Fruits", &Gt(ColumnRef("id"), Const(U32(200)))).unwrap();
@@ -94,7 +94,7 @@ fn test_delete_with_greater_than_filter_on_disk() {
 
 fn test_delete_all_rows(storage: StorageCfg) {
     // GIVEN
-    let mut db = fruits_table(storage);
+    let db = fruits_table(storage);
 
     // WHEN
     let deleted_count = db.delete("Fruits", &True).unwrap();
@@ -117,7 +117,7 @@ fn test_delete_all_rows_on_disk() {
 
 fn test_delete_with_invalid_column(storage: StorageCfg) {
     // GIVEN
-    let mut db = fruits_table(storage);
+    let db = fruits_table(storage);
 
     // WHEN
     let result = db.delete("Fruits", &Eq(ColumnRef("invalid"), Const(U32(100))));
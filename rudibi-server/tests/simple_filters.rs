@@ -142,4 +142,33 @@ fn test_invalid_table() {
 
     // THEN
     assert_eq!(result.unwrap_err(), DbError::TableNotFound("NonExistent".into()));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_repeated_query_shape_reuses_plan() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+
+    // WHEN
+    let first = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+    let second = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(400)))).unwrap();
+
+    // THEN
+    check_equality(&first, &[[ UTF8("apple") ]]);
+    check_equality(&second, &[[ UTF8("cherry") ]]);
+    let stats = db.plan_cache_stats();
+    assert_eq!((stats.plans, stats.hits, stats.misses), (1, 1, 1));
+}
+
+#[test]
+fn test_cached_plan_still_type_checks_constants() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    db.select(&[ColumnRef("name")], "Fruits", &Gt(ColumnRef("id"), Const(U32(100)))).unwrap();
+
+    // WHEN
+    let result = db.select(&[ColumnRef("name")], "Fruits", &Gt(ColumnRef("id"), Const(UTF8("banana"))));
+
+    // THEN
+    assert!(matches!(result, Err(DbError::QueryError(TypeError::InvalidArgType(_, _, _)))), "{result:#?}");
+}
@@ -7,13 +7,13 @@ use rudibi_server::rows;
 
 #[test]
 fn store_unknown_table() {
-    let mut db = Database::new();
+    let db = Database::new();
     let result = db.insert("UnknownTable", &["id"], rows![]);
     assert_eq!(result, Err(DbError::TableNotFound("UnknownTable".to_string())));
 }
 
 fn store_nothing(storage: StorageCfg) {
-    let mut db = empty_table(storage);
+    let db = empty_table(storage);
     let result = db.insert("EmptyTable", &["id"], rows![]);
     assert!(matches!(result, Ok(0)));
 }
@@ -0,0 +1,121 @@
+use std::sync::{Arc, Mutex};
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, Row, StorageCfg};
+use rudibi_server::limits::{Limit, LimitObserver, LimitWarning, Quota, QuotaKind, SoftLimits};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{fruits_table, random_temp_file};
+use rudibi_server::rows;
+
+#[derive(Default)]
+struct Warnings(Mutex<Vec<LimitWarning>>);
+
+impl LimitObserver for Warnings {
+    fn on_soft_limit(&self, warning: &LimitWarning) {
+        self.0.lock().unwrap().push(warning.clone());
+    }
+}
+
+#[test]
+fn large_row_crosses_soft_limit() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    let warnings = Arc::new(Warnings::default());
+    db.add_limit_observer(warnings.clone());
+    db.set_soft_limits(SoftLimits { row_size: Some(0.5), ..Default::default() });
+
+    // WHEN
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[600u32, "dragonfruit"], [700u32, "fig"]]).unwrap();
+
+    // THEN
+    assert_eq!(db.soft_limit_crossings(Limit::RowSize), 1);
+    assert_eq!(*warnings.0.lock().unwrap(), vec![
+        LimitWarning { limit: Limit::RowSize, table: "Fruits".to_string(), value: 15, soft: 12, hard: Some(24) }
+    ]);
+}
+
+#[test]
+fn large_result_crosses_soft_limit() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    db.set_soft_limits(SoftLimits { result_rows: Some(2), ..Default::default() });
+
+    // WHEN
+    let small = db.select(&[ColumnRef("id")], "Fruits", &Gt(ColumnRef("id"), Const(U32(200)))).unwrap();
+    let large = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+
+    // THEN
+    assert_eq!((small.len(), large.len()), (2, 4));
+    assert_eq!(db.soft_limit_crossings(Limit::ResultRows), 1);
+    assert_eq!(db.soft_limit_crossings(Limit::RowSize), 0);
+}
+
+fn warned(limit: Limit, value: usize, soft: usize, hard: usize) -> LimitWarning {
+    LimitWarning { limit, table: "Fruits".to_string(), value, soft, hard: Some(hard) }
+}
+
+#[test]
+fn table_quota_warns_before_rejecting() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    let warnings = Arc::new(Warnings::default());
+    db.add_limit_observer(warnings.clone());
+    db.set_soft_limits(SoftLimits { table_quota: Some(0.5), ..Default::default() });
+    db.set_quota("Fruits", Quota { max_rows: Some(6), ..Default::default() }).unwrap();
+
+    // WHEN
+    let inserts: Vec<_> = [500u32, 600, 700].into_iter()
+        .map(|id| db.insert("Fruits", &["id", "name"], rows![[id, "kiwi"]]))
+        .collect();
+
+    // THEN each row past half the quota warned, until the one over it failed
+    assert!(inserts[0].is_ok() && inserts[1].is_ok());
+    assert_eq!(inserts[2], Err(DbError::QuotaExceeded { table: "Fruits".to_string(), quota: QuotaKind::Rows, max: 6, got: 7 }));
+    assert_eq!(*warnings.0.lock().unwrap(), vec![warned(Limit::TableQuota, 5, 3, 6), warned(Limit::TableQuota, 6, 3, 6)]);
+}
+
+#[test]
+fn disk_budget_warns_before_rejecting() {
+    // GIVEN
+    let path = random_temp_file();
+    let mut db = fruits_table(StorageCfg::disk(&path));
+    let warnings = Arc::new(Warnings::default());
+    db.add_limit_observer(warnings.clone());
+    let budget = std::fs::metadata(&path).unwrap().len() as usize + 8;
+    db.set_soft_limits(SoftLimits { disk_budget: Some(0.99), ..Default::default() });
+    db.set_disk_budget(Some(budget as u64));
+
+    // WHEN a row of 7 bytes fits the budget, one of 14 after it doesn't
+    let fits = db.insert("Fruits", &["id", "name"], rows![[500u32, "fig"]]);
+    let over = db.insert("Fruits", &["id", "name"], rows![[600u32, "elderberry"]]);
+
+    // THEN
+    assert!(fits.is_ok());
+    assert!(matches!(over, Err(DbError::QuotaExceeded { quota: QuotaKind::DiskBudget, .. })));
+    let soft = (budget as f64 * 0.99) as usize;
+    assert_eq!(*warnings.0.lock().unwrap(), vec![warned(Limit::DiskBudget, budget - 1, soft, budget)]);
+    drop(db);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn query_memory_warns_before_failing() {
+    // GIVEN a limit of three projected ids, of 4 bytes of data and two offsets each
+    let row_bytes = 4 + 2 * size_of::<usize>();
+    let mut db = fruits_table(StorageCfg::InMemory);
+    let warnings = Arc::new(Warnings::default());
+    db.add_limit_observer(warnings.clone());
+    db.set_soft_limits(SoftLimits { query_memory: Some(0.5), ..Default::default() });
+    db.set_query_memory_limit(Some(3 * row_bytes));
+
+    // WHEN
+    let one = db.select(&[ColumnRef("id")], "Fruits", &Gt(ColumnRef("id"), Const(U32(300))));
+    let two = db.select(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana"))));
+    let all = db.select(&[ColumnRef("id")], "Fruits", &True);
+
+    // THEN
+    assert_eq!((one.unwrap().len(), two.unwrap().len()), (1, 2));
+    assert_eq!(all.err(), Some(DbError::QueryMemoryExceeded { max: 3 * row_bytes, got: 4 * row_bytes }));
+    assert_eq!(*warnings.0.lock().unwrap(), vec![warned(Limit::QueryMemory, 2 * row_bytes, 3 * row_bytes / 2, 3 * row_bytes)]);
+}
@@ -0,0 +1,67 @@
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{QueryOptions, Row, StorageCfg};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, fruits_table, with_tmp};
+
+fn test_large_result_spills(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+    let many: Vec<Row> = (1000..6000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+    let filter = Eq(ColumnRef("name"), Const(UTF8("kiwi")));
+
+    // WHEN
+    let stream = db.select_stream(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &filter, &QueryOptions::default(), 4096).unwrap();
+
+    // THEN
+    assert!(stream.is_spilled());
+    assert_eq!(stream.len(), 5000);
+    let expected = db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &filter).unwrap();
+    let streamed: Vec<Row> = stream.map(Result::unwrap).collect();
+    assert_eq!(streamed, expected.data);
+}
+
+#[test]
+fn test_large_result_spills_in_mem() {
+    test_large_result_spills(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_large_result_spills_on_disk() {
+    with_tmp(test_large_result_spills);
+}
+
+#[test]
+fn test_small_result_stays_in_memory() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+
+    // WHEN
+    let stream = db.select_stream(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana"))), &QueryOptions::default(), 4096).unwrap();
+
+    // THEN
+    assert!(!stream.is_spilled());
+    let results = stream.into_result_set().unwrap();
+    check_equality(&results, &[[UTF8("banana")], [UTF8("banana")]]);
+    assert_eq!(results.scan_stats.rows_scanned, 4);
+}
+
+#[test]
+fn test_spill_file_removed_with_stream() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let many: Vec<Row> = (1000..2000u32).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+
+    // WHEN
+    let mut stream = db.select_stream(&[ColumnRef("id")], "Fruits", &True, &QueryOptions::default(), 100).unwrap();
+    let first = stream.next().unwrap().unwrap();
+    let path = stream.spill_path().unwrap().to_string();
+    let existed = std::fs::exists(&path).unwrap();
+    drop(stream);
+
+    // THEN
+    assert_eq!(first.get_column(0), 100u32.to_le_bytes());
+    assert!(existed);
+    assert!(!std::fs::exists(&path).unwrap());
+}
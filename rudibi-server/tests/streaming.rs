@@ -0,0 +1,142 @@
+use std::io::Cursor;
+
+use rudibi_server::auth::Session;
+use rudibi_server::engine::{DbError, Row, StorageCfg};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::pgwire;
+use rudibi_server::protocol::{Command, Server, STREAM_CHUNK_BYTES};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::serial;
+use rudibi_server::testlib::fruits_table;
+
+const KIWIS: u32 = 100_000;
+
+fn many_fruits_server() -> Server {
+    let db = fruits_table(StorageCfg::InMemory);
+    let many: Vec<Row> = (1000..1000 + KIWIS).map(|id| Row::of_columns(&[&id.to_le_bytes(), b"kiwi"])).collect();
+    db.insert("Fruits", &["id", "name"], &many).unwrap();
+    Server::new(db, StorageCfg::InMemory)
+}
+
+fn replies(server: &Server, requests: &[Frame]) -> Vec<Frame> {
+    let mut input = Vec::new();
+    for request in requests {
+        request.write_to(&mut input).unwrap();
+    }
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect()
+}
+
+#[test]
+fn test_rows_come_in_chunks() {
+    // GIVEN
+    let server = many_fruits_server();
+
+    // WHEN
+    let replies = replies(&server, &[Frame::new(frame::STREAM, 1, "SELECT id, name FROM Fruits WHERE name = 'kiwi'")]);
+
+    // THEN
+    let (done, chunks) = replies.split_last().unwrap();
+    assert_eq!(done, &Frame::new(frame::DONE, 1, format!("SELECTED {KIWIS}")));
+    assert!(chunks.len() > 1);
+    assert!(chunks.iter().all(|chunk| chunk.tag == frame::ROWS && chunk.request_id == 1 && chunk.payload.len() < STREAM_CHUNK_BYTES + 64));
+    let csv: String = chunks.iter().map(|chunk| String::from_utf8(chunk.payload.clone()).unwrap()).collect();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), KIWIS as usize + 1);
+    assert_eq!(lines[..2], ["id,name", "1000,kiwi"]);
+    assert_eq!(lines.last(), Some(&"100999,kiwi"));
+}
+
+#[test]
+fn test_stream_errors() {
+    // GIVEN
+    let server = many_fruits_server();
+
+    // WHEN
+    let replies = replies(&server, &[
+        Frame::new(frame::STREAM, 1, "DELETE FROM Fruits"),
+        Frame::new(frame::STREAM, 2, "SELECT id FROM Vegetables"),
+        Frame::new(frame::STREAM, 3, "SELECT id FROM Fruits WHERE id > 200 AND id < 1000"),
+    ]);
+
+    // THEN nothing was deleted, and a stream without rows still has its header
+    assert_eq!(replies, vec![
+        Frame::error(1, &ErrorReply::from(&DbError::InputError("Only selects are streamed".to_string()))),
+        Frame::error(2, &ErrorReply::from(&DbError::TableNotFound("Vegetables".to_string()))),
+        Frame::new(frame::ROWS, 3, "id\n300\n400\n"),
+        Frame::new(frame::DONE, 3, "SELECTED 2"),
+    ]);
+    assert_eq!(server.database().count("Fruits", &rudibi_server::query::Bool::True).unwrap(), KIWIS as usize + 4);
+}
+
+#[test]
+fn test_encoded_selects() {
+    // GIVEN a name with a quote, which text commands can't compare with
+    let db = fruits_table(StorageCfg::InMemory);
+    db.insert("Fruits", &["id", "name"], &[Row::of_columns(&[&500u32.to_le_bytes(), b"kiwi's"])]).unwrap();
+    let server = Server::new(db, StorageCfg::InMemory);
+    let select = Command::Select { table: "Fruits", columns: vec!["id"], filter: Eq(ColumnRef("name"), Const("kiwi's".into())).or(Lt(ColumnRef("id"), Const(200u32.into()))) };
+    let delete = Command::Delete { table: "Fruits", filter: True };
+
+    // WHEN
+    let replies = replies(&server, &[
+        Frame::new(frame::QUERY, 1, serial::encode(&select)),
+        Frame::new(frame::QUERY, 2, serial::encode(&delete)),
+        Frame::new(frame::QUERY, 3, "SELECT id FROM Fruits"),
+    ]);
+
+    // THEN only encoded selects run
+    assert_eq!(replies[..3], [
+        Frame::new(frame::ROWS, 1, "id\n100\n500\n"),
+        Frame::new(frame::DONE, 1, "SELECTED 2"),
+        Frame::error(2, &ErrorReply::from(&DbError::InputError("Only selects are streamed".to_string()))),
+    ]);
+    assert_eq!((replies[3].tag, replies.len()), (frame::ERROR, 4));
+    assert_eq!(server.database().count("Fruits", &True).unwrap(), 5);
+}
+
+#[test]
+fn test_pooled_streams() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let server = Server::with_pool(db, StorageCfg::InMemory, 2, 4);
+
+    // WHEN
+    let rows = server.select_stream_as(&Session::default(), "SELECT name FROM Fruits WHERE id >= 300").unwrap();
+
+    // THEN
+    let names: Vec<Vec<u8>> = rows.map(|row| row.unwrap().get_column(0).to_vec()).collect();
+    assert_eq!(names, vec![b"banana".to_vec(), b"cherry".to_vec()]);
+}
+
+#[test]
+fn test_postgres_rows() {
+    // GIVEN
+    let mut input = (8 + 13i32).to_be_bytes().to_vec();
+    input.extend_from_slice(&(3i32 << 16).to_be_bytes());
+    input.extend_from_slice(b"user\0tester\0\0");
+    let query = b"SELECT id FROM Fruits WHERE name = 'kiwi'\0";
+    input.push(b'Q');
+    input.extend_from_slice(&((query.len() + 4) as i32).to_be_bytes());
+    input.extend_from_slice(query);
+
+    // WHEN
+    let mut output = Vec::new();
+    pgwire::serve(&many_fruits_server(), Cursor::new(input), &mut output).unwrap();
+
+    // THEN every row is sent, after the description and before the completion
+    let mut tags = Vec::new();
+    let mut rest = &output[..];
+    while let [tag, len @ ..] = rest {
+        let len = i32::from_be_bytes(len[..4].try_into().unwrap()) as usize;
+        tags.push((*tag, rest[5..1 + len].to_vec()));
+        rest = &rest[1 + len..];
+    }
+    let query_tags: Vec<u8> = tags.iter().map(|(tag, _)| *tag).skip_while(|&tag| tag != b'T').collect();
+    assert_eq!(query_tags.len(), KIWIS as usize + 3);
+    assert!(query_tags[1..=KIWIS as usize].iter().all(|&tag| tag == b'D'));
+    assert_eq!(tags[tags.len() - 2], (b'C', format!("SELECT {KIWIS}\0").into_bytes()));
+}
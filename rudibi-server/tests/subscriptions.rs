@@ -0,0 +1,161 @@
+use std::io::Cursor;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::auth::{hash_token, Credentials};
+use rudibi_server::dtype::ColumnValue::U32;
+use rudibi_server::engine::{Row, StorageCfg};
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::permissions::Permissions;
+use rudibi_server::protocol::Server;
+use rudibi_server::query::Bool::Eq;
+use rudibi_server::query::Value::{ColumnRef, Const};
+use rudibi_server::testlib::fruits_table;
+
+const HEADER: &str = "sequence,change,row_id,id,name\n";
+
+// A server with a change feed serving frames on a port of its own, one thread per connection
+fn serving(backlog: usize) -> (Arc<Server>, String) {
+    let server = Arc::new(Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory).with_change_feed(backlog));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    thread::spawn({
+        let server = server.clone();
+        move || for conn in listener.incoming().flatten() {
+            let server = server.clone();
+            thread::spawn(move || server.serve(&conn, &conn));
+        }
+    });
+    (server, address)
+}
+
+fn subscribe(address: &str, subscription: &str) -> TcpStream {
+    let mut conn = TcpStream::connect(address).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    Frame::new(frame::SUBSCRIBE, 1, subscription).write_to(&mut conn).unwrap();
+    conn
+}
+
+fn next_frame(conn: &mut TcpStream) -> Frame {
+    Frame::read_from(conn).unwrap().unwrap()
+}
+
+// The next `count` events, skipping empty frames
+fn events(conn: &mut TcpStream, count: usize) -> Vec<String> {
+    let mut events = Vec::new();
+    while events.len() < count {
+        let reply = next_frame(conn);
+        assert_eq!(reply.tag, frame::EVENTS, "{reply:?}");
+        events.extend(String::from_utf8(reply.payload).unwrap().lines().map(str::to_string));
+    }
+    events
+}
+
+fn error_code(reply: &Frame) -> u16 {
+    assert_eq!(reply.tag, frame::ERROR);
+    ErrorReply::parse(&reply.payload).unwrap().code
+}
+
+#[test]
+fn test_inserts_updates_and_deletes() {
+    // GIVEN
+    let (server, address) = serving(1000);
+    server.handle("CREATE TABLE Stock (id U32, count U32)").unwrap();
+    let mut conn = subscribe(&address, "Stock");
+    assert_eq!(next_frame(&mut conn).payload, b"sequence,change,row_id,id,count\n");
+
+    // WHEN
+    server.handle("INSERT INTO Stock (count, id) VALUES (10, 1), (20, 2)").unwrap();
+    server.handle("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi')").unwrap();
+    server.database().update("Stock", &["count"], &Row::of_columns(&[&15u32.to_le_bytes()]), &Eq(ColumnRef("id"), Const(U32(1)))).unwrap();
+    server.handle("DELETE FROM Stock WHERE id = 2").unwrap();
+
+    // THEN the other table's changes are left out
+    assert_eq!(events(&mut conn, 4), ["1,insert,0,1,10", "1,insert,1,2,20", "3,update,0,1,15", "4,delete,1,,"]);
+}
+
+#[test]
+fn test_moved_rows() {
+    // GIVEN
+    let (server, address) = serving(1000);
+    let mut conn = subscribe(&address, "Fruits");
+    assert_eq!(next_frame(&mut conn).payload, HEADER.as_bytes());
+
+    // WHEN a longer name doesn't fit where the row is
+    server.database().update("Fruits", &["name"], &Row::of_columns(&[b"apple, green"]), &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+
+    // THEN
+    assert_eq!(events(&mut conn, 2), ["1,delete,0,,", "2,insert,4,100,\"apple, green\""]);
+}
+
+#[test]
+fn test_resume_after_a_sequence() {
+    // GIVEN
+    let (server, address) = serving(1000);
+    for id in [500, 600, 700] {
+        server.handle(&format!("INSERT INTO Fruits (id, name) VALUES ({id}, 'kiwi')")).unwrap();
+    }
+
+    // WHEN
+    let mut conn = subscribe(&address, "Fruits 1");
+    next_frame(&mut conn);
+
+    // THEN
+    assert_eq!(events(&mut conn, 2), ["2,insert,5,600,kiwi", "3,insert,6,700,kiwi"]);
+}
+
+#[test]
+fn test_expired_sequence() {
+    // GIVEN a feed keeping the last two changes
+    let (server, address) = serving(2);
+    for id in [500, 600, 700] {
+        server.handle(&format!("INSERT INTO Fruits (id, name) VALUES ({id}, 'kiwi')")).unwrap();
+    }
+
+    // WHEN
+    let mut conn = subscribe(&address, "Fruits 0");
+    next_frame(&mut conn);
+
+    // THEN
+    assert_eq!(error_code(&next_frame(&mut conn)), 3006);
+}
+
+#[test]
+fn test_bad_subscriptions() {
+    // GIVEN
+    let mut credentials = Credentials::default();
+    credentials.add_token("nobody", &hash_token("nobody-token")).unwrap();
+    let guarded = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory)
+        .with_change_feed(1000)
+        .with_credentials(credentials)
+        .with_permissions(Permissions::default());
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory).with_change_feed(1000);
+    let without_feed = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+
+    // WHEN
+    let replies = |server: &Server, requests: &[Frame]| {
+        let mut input = Vec::new();
+        for request in requests {
+            request.write_to(&mut input).unwrap();
+        }
+        let mut output = Vec::new();
+        server.serve(Cursor::new(input), &mut output).unwrap();
+        let mut output = Cursor::new(output);
+        std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect::<Vec<_>>()
+    };
+    let denied = replies(&guarded, &[Frame::new(frame::AUTH, 1, "nobody-token"), Frame::new(frame::SUBSCRIBE, 2, "Fruits")]);
+    let bad = replies(&server, &[
+        Frame::new(frame::SUBSCRIBE, 1, "Vegetables"),
+        Frame::new(frame::SUBSCRIBE, 2, "Fruits soon"),
+    ]);
+    let unsupported = replies(&without_feed, &[Frame::new(frame::SUBSCRIBE, 1, "Fruits")]);
+
+    // THEN
+    assert_eq!(denied[0].tag, frame::REPLY);
+    assert_eq!(error_code(&denied[1]), 4003);
+    assert_eq!(bad.iter().map(error_code).collect::<Vec<_>>(), [1001, 2001]);
+    assert_eq!(error_code(&unsupported[0]), 9001);
+}
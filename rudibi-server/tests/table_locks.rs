@@ -0,0 +1,154 @@
+use std::sync::{mpsc, Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{DbError, Row, StorageCfg};
+use rudibi_server::locking::LockMode;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{check_equality, fruits_schema, fruits_table, with_tmp};
+
+fn test_exclusive_lock_holds_off_writers(storage: StorageCfg) {
+    // GIVEN
+    let db = Arc::new(fruits_table(storage));
+    let mut lock = db.lock_table("Fruits", LockMode::Exclusive).unwrap();
+    let (started, writer_started) = mpsc::channel();
+    let writer = {
+        let db = db.clone();
+        thread::spawn(move || {
+            started.send(()).unwrap();
+            db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap()
+        })
+    };
+    writer_started.recv().unwrap();
+    thread::sleep(Duration::from_millis(50));
+
+    // WHEN the table is exported then truncated under the lock
+    let exported = lock.select(&[ColumnRef("id")], &True).unwrap();
+    let deleted = lock.delete(&True).unwrap();
+    assert_eq!(lock.count(&True).unwrap(), 0);
+    drop(lock);
+
+    // THEN the insert waited for the lock, and lands after the truncate
+    assert_eq!(exported.len(), 4);
+    assert_eq!(deleted, 4);
+    assert_eq!(writer.join().unwrap(), 1);
+    let results = db.select(&[ColumnRef("name")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[UTF8("kiwi")]]);
+}
+
+#[test]
+fn test_exclusive_lock_holds_off_writers_in_mem() {
+    test_exclusive_lock_holds_off_writers(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_exclusive_lock_holds_off_writers_on_disk() {
+    with_tmp(test_exclusive_lock_holds_off_writers);
+}
+
+fn test_writes_through_exclusive_lock(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+
+    // WHEN
+    {
+        let mut lock = db.lock_table("Fruits", LockMode::Exclusive).unwrap();
+        assert_eq!(lock.insert(&["id", "name"], rows![[500u32, "kiwi"]]).unwrap(), 1);
+        assert_eq!(lock.update(&["name"], &Row::of_columns(&[b"grape"]), &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap(), 2);
+    }
+
+    // THEN
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("name"), Const(UTF8("grape")))).unwrap(), 2);
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("name"), Const(UTF8("kiwi")))).unwrap(), 1);
+}
+
+#[test]
+fn test_writes_through_exclusive_lock_in_mem() {
+    test_writes_through_exclusive_lock(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_writes_through_exclusive_lock_on_disk() {
+    with_tmp(test_writes_through_exclusive_lock);
+}
+
+#[test]
+fn test_shared_lock_allows_reads_only() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut lock = db.lock_table("Fruits", LockMode::Shared).unwrap();
+
+    // WHEN
+    let insert = lock.insert(&["id", "name"], rows![[500u32, "kiwi"]]);
+    let delete = lock.delete(&True);
+
+    // THEN other readers aren't held off
+    let expected = Err(DbError::UnsupportedOperation("Writing Fruits needs an exclusive lock".to_string()));
+    assert_eq!(insert, expected);
+    assert_eq!(delete, expected);
+    assert_eq!(lock.mode(), LockMode::Shared);
+    assert_eq!(lock.count(&True).unwrap(), 4);
+    assert_eq!(db.count("Fruits", &True).unwrap(), 4);
+}
+
+#[test]
+fn test_lock_unknown_table() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+
+    // WHEN
+    let lock = db.lock_table("Vegetables", LockMode::Exclusive);
+
+    // THEN
+    assert!(matches!(lock, Err(DbError::TableNotFound(_))));
+}
+
+#[test]
+fn test_waiting_for_own_lock_is_a_deadlock() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let _lock = db.lock_table("Fruits", LockMode::Exclusive).unwrap();
+
+    // WHEN the table is used through the database rather than the lock
+    let insert = db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]);
+    let count = db.count("Fruits", &True);
+
+    // THEN
+    assert_eq!(insert, Err(DbError::Deadlock("Fruits".to_string())));
+    assert_eq!(count, Err(DbError::Deadlock("Fruits".to_string())));
+}
+
+#[test]
+fn test_lock_cycle_aborts_one_thread() {
+    // GIVEN
+    let mut db = fruits_table(StorageCfg::InMemory);
+    let mut vegetables = fruits_schema();
+    vegetables.name = "Vegetables".to_string();
+    db.new_table(&vegetables, StorageCfg::InMemory).unwrap();
+    let db = Arc::new(db);
+    let both_locked = Arc::new(Barrier::new(2));
+
+    // WHEN each thread locks a table, then waits for the other's
+    let threads: Vec<_> = [("Fruits", "Vegetables"), ("Vegetables", "Fruits")].into_iter().map(|(first, second)| {
+        let (db, both_locked) = (db.clone(), both_locked.clone());
+        thread::spawn(move || {
+            let mut lock = db.lock_table(first, LockMode::Exclusive).unwrap();
+            both_locked.wait();
+            let result = db.lock_table(second, LockMode::Exclusive).map(|_| ());
+            // The thread that got both locks writes through its first one
+            if result.is_ok() {
+                lock.insert(&["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+            }
+            result
+        })
+    }).collect();
+    let results: Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+
+    // THEN one of them backs off, the other one goes ahead
+    let deadlocks = results.iter().filter(|result| matches!(result, Err(DbError::Deadlock(_)))).count();
+    assert_eq!(deadlocks, 1);
+    assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+    assert_eq!(db.count("Fruits", &True).unwrap() + db.count("Vegetables", &True).unwrap(), 4 + 1);
+}
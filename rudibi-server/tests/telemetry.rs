@@ -0,0 +1,126 @@
+#![cfg(feature = "otel")]
+
+use std::io::Cursor;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use opentelemetry::trace::{SpanId, TraceId};
+use opentelemetry::{global, Context};
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Span, SpanData, SpanProcessor};
+use rudibi_server::engine::StorageCfg;
+use rudibi_server::errors::{self, ErrorReply};
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+
+const SELECT: &str = "SELECT id, name FROM Fruits WHERE id > 100";
+
+// Keeps the spans that ended, of every test
+#[derive(Debug, Clone, Default)]
+struct Collected(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanProcessor for Collected {
+    fn on_start(&self, _: &mut Span, _: &Context) {}
+
+    fn on_end(&self, span: SpanData) {
+        self.0.lock().unwrap().push(span);
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        Ok(())
+    }
+
+    fn shutdown_with_timeout(&self, _: Duration) -> OTelSdkResult {
+        Ok(())
+    }
+}
+
+fn collected() -> &'static Collected {
+    static COLLECTED: OnceLock<Collected> = OnceLock::new();
+    COLLECTED.get_or_init(|| {
+        let collected = Collected::default();
+        global::set_tracer_provider(SdkTracerProvider::builder().with_span_processor(collected.clone()).build());
+        collected
+    })
+}
+
+// The spans of the trace, in the order they ended
+fn spans_of(trace_id: TraceId) -> Vec<SpanData> {
+    collected().0.lock().unwrap().iter().filter(|span| span.span_context.trace_id() == trace_id).cloned().collect()
+}
+
+fn traced(tag: u8, request_id: u32, traceparent: &str, payload: &str) -> Frame {
+    Frame::new(tag | frame::TRACED, request_id, format!("{traceparent}\0{payload}"))
+}
+
+fn replies(server: &Server, requests: &[Frame]) -> Vec<Frame> {
+    let mut input = Vec::new();
+    for request in requests {
+        request.write_to(&mut input).unwrap();
+    }
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    std::iter::from_fn(|| Frame::read_from(&mut output).unwrap()).collect()
+}
+
+fn assert_phases(spans: &[SpanData], client_span: SpanId) {
+    let names: Vec<&str> = spans.iter().map(|span| span.name.as_ref()).collect();
+    assert_eq!(names, ["parse", "plan", "scan", "serialize", "request"]);
+    let request = spans.last().unwrap();
+    assert_eq!(request.parent_span_id, client_span);
+    assert!(request.parent_span_is_remote);
+    assert!(spans[..4].iter().all(|span| span.parent_span_id == request.span_context.span_id()));
+}
+
+#[test]
+fn test_request_continues_the_trace() {
+    // GIVEN
+    collected();
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+    let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    // WHEN
+    let replies = replies(&server, &[traced(frame::EXECUTE, 1, traceparent, SELECT)]);
+
+    // THEN
+    assert_eq!(replies[0].tag, frame::REPLY);
+    let spans = spans_of(TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap());
+    assert_phases(&spans, SpanId::from_hex("00f067aa0ba902b7").unwrap());
+}
+
+#[test]
+fn test_commands_on_a_pool() {
+    // GIVEN the commands run on other threads than the connection's
+    collected();
+    let server = Server::with_pool(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory, 2, 4);
+    let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+
+    // WHEN
+    let replies = replies(&server, &[traced(frame::STREAM, 1, traceparent, SELECT)]);
+
+    // THEN
+    assert_eq!(replies.last().unwrap(), &Frame::new(frame::DONE, 1, "SELECTED 3"));
+    let spans = spans_of(TraceId::from_hex("0af7651916cd43dd8448eb211c80319c").unwrap());
+    assert_phases(&spans, SpanId::from_hex("b7ad6b7169203331").unwrap());
+}
+
+#[test]
+fn test_bad_trace_context() {
+    // GIVEN
+    collected();
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+    let trace_id = "11111111111111111111111111111111";
+
+    // WHEN
+    let replies = replies(&server, &[
+        traced(frame::EXECUTE, 1, &format!("01-{trace_id}-2222222222222222-01"), SELECT),
+        Frame::new(frame::EXECUTE | frame::TRACED, 2, SELECT),
+    ]);
+
+    // THEN a traceparent that isn't understood starts a trace of its own
+    assert_eq!(replies[0].tag, frame::REPLY);
+    assert!(spans_of(TraceId::from_hex(trace_id).unwrap()).is_empty());
+    assert_eq!(ErrorReply::parse(&replies[1].payload).unwrap().code, errors::BAD_ENCODING);
+}
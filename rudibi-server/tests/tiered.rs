@@ -0,0 +1,67 @@
+use rudibi_server::compress::Compression;
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{Database, Row, StorageCfg};
+use rudibi_server::index::IndexKind;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::storage::Durability;
+use rudibi_server::testlib::{check_equality, fruits_schema, fruits_table, random_temp_file};
+
+fn tiered(path: &str, durability: Durability, max_hot_rows: usize) -> StorageCfg {
+    StorageCfg::Tiered { path: path.to_string(), durability, compression: Compression::default(), max_hot_rows }
+}
+
+#[test]
+fn test_scans_merge_both_tiers() {
+    // GIVEN two rows flushed to disk, two still in memory
+    let path = random_temp_file();
+    let mut db = fruits_table(tiered(&path, Durability::Flush, 3));
+    db.create_index("Fruits", "id", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(200))).or(Eq(ColumnRef("id"), Const(U32(400))))).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "date"]]).unwrap();
+
+    // THEN
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(100)], [U32(300)], [U32(500)]]);
+    let results = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(500)))).unwrap();
+    check_equality(&results, &[[UTF8("date")]]);
+    drop(db);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_hot_rows_are_flushed_when_dropped() {
+    // GIVEN
+    let path = random_temp_file();
+    let db = fruits_table(tiered(&path, Durability::Flush, 1000));
+    let before = std::fs::metadata(&path).unwrap().len();
+
+    // WHEN
+    drop(db);
+
+    // THEN
+    assert!(std::fs::metadata(&path).unwrap().len() > before);
+    let mut reopened = Database::new();
+    reopened.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+    let results = reopened.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(100)], [U32(200)], [U32(300)], [U32(400)]]);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_sync_per_commit_flushes_every_commit() {
+    // GIVEN
+    let path = random_temp_file();
+
+    // WHEN
+    let db = fruits_table(tiered(&path, Durability::SyncPerCommit, 1000));
+
+    // THEN rows are on disk while the database is still open
+    let mut reader = Database::new();
+    reader.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+    assert_eq!(reader.select(&[ColumnRef("id")], "Fruits", &True).unwrap().len(), 4);
+    drop(db);
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "tls")]
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use rudibi_server::engine::{DbError, StorageCfg};
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+use rudibi_server::tls::{load_config, TlsStream};
+
+fn data(file: &str) -> String {
+    format!("{}/tests/data/{file}", env!("CARGO_MANIFEST_DIR"))
+}
+
+// Serves text mode over TLS, one connection
+fn listen() -> std::net::SocketAddr {
+    let config = load_config(&data("server.pem"), &data("server.key")).unwrap();
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let (conn, _) = listener.accept().unwrap();
+        let stream = TlsStream::accept(&config, conn).unwrap();
+        let _ = server.serve_text(BufReader::new(&stream), &stream);
+    });
+    address
+}
+
+fn client(conn: TcpStream) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut roots = RootCertStore::empty();
+    roots.add(CertificateDer::from_pem_file(data("ca.pem")).unwrap()).unwrap();
+    let config = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions().unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    StreamOwned::new(ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap(), conn)
+}
+
+#[test]
+fn test_text_mode_over_tls() {
+    // GIVEN
+    let mut stream = client(TcpStream::connect(listen()).unwrap());
+
+    // WHEN
+    writeln!(stream, "SELECT name FROM Fruits WHERE id = 100").unwrap();
+
+    // THEN
+    let lines: Vec<String> = BufReader::new(&mut stream).lines().take(4).map(Result::unwrap).collect();
+    assert_eq!(lines, vec!["| name  |", "| ----- |", "| apple |", "(1 rows)"]);
+}
+
+#[test]
+fn test_cleartext_clients_get_nothing() {
+    // GIVEN
+    let mut conn = TcpStream::connect(listen()).unwrap();
+
+    // WHEN
+    writeln!(conn, "SELECT name FROM Fruits").unwrap();
+    let mut reply = String::new();
+    let read = BufReader::new(&conn).read_line(&mut reply);
+
+    // THEN the handshake fails instead
+    assert!(read.is_err() || !reply.contains("apple"), "{reply}");
+}
+
+#[test]
+fn test_bad_files() {
+    // WHEN
+    let missing = load_config(&data("missing.pem"), &data("server.key"));
+    let not_a_key = load_config(&data("server.pem"), &data("server.pem"));
+
+    // THEN
+    assert!(matches!(missing, Err(DbError::InputError(msg)) if msg.starts_with("Failed to read certificates")));
+    assert!(matches!(not_a_key, Err(DbError::InputError(msg)) if msg.starts_with("Failed to read a private key")));
+}
@@ -0,0 +1,274 @@
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{Database, DbError, ResultSet, Row, StorageCfg};
+use rudibi_server::index::IndexKind;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::testlib::{check_equality, fruits_table, with_tmp};
+use rudibi_server::transaction::IsolationLevel;
+
+fn all_fruits(db: &Database) -> ResultSet {
+    db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &True).unwrap()
+}
+
+fn test_rollback_to_savepoint(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+    let mut tx = db.begin();
+    tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    tx.savepoint("step");
+    tx.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+    tx.insert("Fruits", &["id", "name"], rows![[600u32, "lime"]]).unwrap();
+
+    // WHEN
+    tx.rollback_to("step").unwrap();
+    tx.insert("Fruits", &["id", "name"], rows![[700u32, "plum"]]).unwrap();
+    tx.commit().unwrap();
+
+    // THEN only the writes since the savepoint are undone
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    let mut ids: Vec<u32> = results.data.iter().map(|row| u32::from_le_bytes(row.get_column(0).try_into().unwrap())).collect();
+    ids.sort();
+    assert_eq!(ids, vec![100, 200, 300, 400, 500, 700]);
+}
+
+#[test]
+fn test_rollback_to_savepoint_in_mem() {
+    test_rollback_to_savepoint(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_rollback_to_savepoint_on_disk() {
+    with_tmp(test_rollback_to_savepoint);
+}
+
+fn test_rollback_undoes_everything(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    db.create_unique_index("Fruits", "id", IndexKind::Ordered).unwrap();
+    let before = all_fruits(&db).len();
+
+    // WHEN
+    let mut tx = db.begin();
+    tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    tx.savepoint("update");
+    tx.update("Fruits", &["name"], &Row::of_columns(&[b"fig"]), &Eq(ColumnRef("id"), Const(U32(500)))).unwrap();
+    tx.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+    tx.rollback_to("update").unwrap();
+    tx.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(500)))).unwrap();
+    tx.rollback().unwrap();
+
+    // THEN
+    assert_eq!(all_fruits(&db).len(), before);
+    let apple = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+    check_equality(&apple, &[[UTF8("apple")]]);
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("id"), Const(U32(500)))).unwrap(), 0);
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+}
+
+#[test]
+fn test_rollback_undoes_everything_in_mem() {
+    test_rollback_undoes_everything(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_rollback_undoes_everything_on_disk() {
+    with_tmp(test_rollback_undoes_everything);
+}
+
+fn test_dropped_transaction_rolls_back(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+
+    // WHEN
+    {
+        let mut tx = db.begin();
+        tx.update("Fruits", &["name"], &Row::of_columns(&[b"grape"]), &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+        let seen = tx.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("grape")))).unwrap();
+        assert_eq!(seen.len(), 2);
+    }
+
+    // THEN
+    let results = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+    check_equality(&results, &[[UTF8("banana")], [UTF8("banana")]]);
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("name"), Const(UTF8("grape")))).unwrap(), 0);
+}
+
+#[test]
+fn test_dropped_transaction_rolls_back_in_mem() {
+    test_dropped_transaction_rolls_back(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_dropped_transaction_rolls_back_on_disk() {
+    with_tmp(test_dropped_transaction_rolls_back);
+}
+
+#[test]
+fn test_unknown_and_released_savepoints() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut tx = db.begin();
+    tx.savepoint("a");
+    tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    tx.savepoint("b");
+
+    // WHEN
+    let unknown = tx.rollback_to("c");
+    tx.release("a").unwrap();
+    let released = tx.rollback_to("b");
+    tx.commit().unwrap();
+
+    // THEN releasing keeps the writes, and the later savepoints go with it
+    assert_eq!(unknown, Err(DbError::SavepointNotFound("c".to_string())));
+    assert_eq!(released, Err(DbError::SavepointNotFound("b".to_string())));
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("id"), Const(U32(500)))).unwrap(), 1);
+}
+
+#[test]
+fn test_repeated_savepoint_name() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut tx = db.begin();
+    tx.savepoint("retry");
+    tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    tx.savepoint("retry");
+    tx.insert("Fruits", &["id", "name"], rows![[600u32, "lime"]]).unwrap();
+
+    // WHEN
+    tx.rollback_to("retry").unwrap();
+    tx.commit().unwrap();
+
+    // THEN the latest savepoint of that name is used
+    assert_eq!(db.count("Fruits", &True).unwrap(), 5);
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("id"), Const(U32(600)))).unwrap(), 0);
+}
+
+fn test_read_committed_holds_off_readers(storage: StorageCfg) {
+    // GIVEN
+    let db = Arc::new(fruits_table(storage));
+    let mut tx = db.begin();
+    tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    let (counted, count) = mpsc::channel();
+    let reader = {
+        let db = db.clone();
+        thread::spawn(move || counted.send(db.count("Fruits", &True).unwrap()).unwrap())
+    };
+
+    // WHEN
+    thread::sleep(Duration::from_millis(50));
+    let before_commit = count.try_recv();
+    tx.commit().unwrap();
+
+    // THEN the reader waited for the commit
+    assert_eq!(before_commit, Err(mpsc::TryRecvError::Empty));
+    assert_eq!(count.recv().unwrap(), 5);
+    reader.join().unwrap();
+}
+
+#[test]
+fn test_read_committed_holds_off_readers_in_mem() {
+    test_read_committed_holds_off_readers(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_read_committed_holds_off_readers_on_disk() {
+    with_tmp(test_read_committed_holds_off_readers);
+}
+
+fn test_read_uncommitted_shows_writes_early(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+    let mut tx = db.begin_with(IsolationLevel::ReadUncommitted);
+
+    // WHEN
+    tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    let before_rollback = db.count("Fruits", &True).unwrap();
+    tx.rollback().unwrap();
+
+    // THEN
+    assert_eq!(before_rollback, 5);
+    assert_eq!(db.count("Fruits", &True).unwrap(), 4);
+}
+
+#[test]
+fn test_read_uncommitted_shows_writes_early_in_mem() {
+    test_read_uncommitted_shows_writes_early(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_read_uncommitted_shows_writes_early_on_disk() {
+    with_tmp(test_read_uncommitted_shows_writes_early);
+}
+
+#[test]
+fn test_read_committed_sees_later_commits() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut tx = db.begin_with(IsolationLevel::ReadCommitted);
+    let before = tx.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+
+    // WHEN
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    let after = tx.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+
+    // THEN
+    assert_eq!((before.len(), after.len()), (4, 5));
+}
+
+#[test]
+fn test_snapshot_reads_repeat() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut tx = db.begin_with(IsolationLevel::Snapshot);
+    let before = tx.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+
+    // WHEN another write commits, then the transaction writes the table
+    db.delete("Fruits", &Eq(ColumnRef("name"), Const(UTF8("banana")))).unwrap();
+    let after = tx.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    let insert = tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]);
+
+    // THEN the first write wins
+    assert_eq!((before.len(), after.len()), (4, 4));
+    assert_eq!(insert, Err(DbError::WriteConflict("Fruits".to_string())));
+    drop(tx);
+    assert_eq!(db.count("Fruits", &True).unwrap(), 2);
+}
+
+#[test]
+fn test_snapshot_writes_after_own_reads() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+    let mut tx = db.begin_with(IsolationLevel::Snapshot);
+    tx.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+
+    // WHEN
+    tx.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]).unwrap();
+    let seen = tx.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(500)))).unwrap();
+    tx.commit().unwrap();
+
+    // THEN
+    check_equality(&seen, &[[UTF8("kiwi")]]);
+    assert_eq!(db.count("Fruits", &True).unwrap(), 5);
+}
+
+#[test]
+fn test_snapshot_locks_tables_without_snapshots() {
+    with_tmp(|storage| {
+        // GIVEN
+        let db = fruits_table(storage);
+        let mut tx = db.begin_with(IsolationLevel::Snapshot);
+
+        // WHEN
+        tx.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+
+        // THEN the table is locked by the transaction, writing it here would wait forever
+        let insert = db.insert("Fruits", &["id", "name"], rows![[500u32, "kiwi"]]);
+        assert_eq!(insert, Err(DbError::Deadlock("Fruits".to_string())));
+        tx.commit().unwrap();
+        assert_eq!(db.count("Fruits", &True).unwrap(), 4);
+    });
+}
@@ -0,0 +1,29 @@
+use rudibi_server::dtype::TypeError;
+use rudibi_server::engine::StorageCfg;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{fruits_table, with_tmp};
+
+fn test_typed_getters(storage: StorageCfg) {
+    // GIVEN
+    let db = fruits_table(storage);
+
+    // WHEN
+    let results = db.select(&[ColumnRef("id"), ColumnRef("name")], "Fruits", &True).unwrap();
+
+    // THEN
+    assert_eq!(results.get::<u32>(0, "id"), Ok(100));
+    assert_eq!(results.get::<String>(1, "name"), Ok("banana".to_string()));
+    assert_eq!(results.get::<&str>(3, "name"), Ok("cherry"));
+    assert_eq!(results.get::<f64>(0, "id"), Err(TypeError::ConversionError));
+    assert_eq!(results.get::<u32>(0, "weight"), Err(TypeError::UnknownColumn("weight".to_string())));
+}
+
+#[test]
+fn test_typed_getters_in_mem() {
+    test_typed_getters(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_typed_getters_on_disk() {
+    with_tmp(test_typed_getters);
+}
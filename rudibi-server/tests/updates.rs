@@ -0,0 +1,160 @@
+use std::sync::{Arc, Mutex};
+
+use rudibi_server::compress::Compression;
+use rudibi_server::dtype::{ColumnValue::*, DataType};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table};
+use rudibi_server::index::{IndexKey, IndexKind};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::storage::{Durability, RowId, StorageObserver};
+use rudibi_server::testlib::{check_equality, fruits_table, random_temp_file, with_tmp};
+
+#[derive(Default)]
+struct EventLog {
+    events: Mutex<Vec<String>>,
+}
+
+impl StorageObserver for EventLog {
+    fn on_store(&self, _table: &str, row_ids: &[RowId], _rows: &[Row], _column_mapping: &[usize]) {
+        self.events.lock().unwrap().push(format!("store {row_ids:?}"));
+    }
+
+    fn on_delete(&self, _table: &str, row_ids: &[RowId]) {
+        self.events.lock().unwrap().push(format!("delete {row_ids:?}"));
+    }
+
+    fn on_update(&self, _table: &str, row_ids: &[RowId], _rows: &[Row]) {
+        self.events.lock().unwrap().push(format!("update {row_ids:?}"));
+    }
+}
+
+fn counters_table(storage: StorageCfg) -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Counters", vec![
+        Column::new("id", DataType::U32),
+        Column::new("hits", DataType::U32),
+    ]), storage).unwrap();
+    db.insert("Counters", &["id", "hits"], rows![[1u32, 0u32], [2u32, 0u32], [3u32, 0u32]]).unwrap();
+    db
+}
+
+fn test_counter_updated_in_place(storage: StorageCfg) {
+    // GIVEN
+    let mut db = counters_table(storage);
+    let log = Arc::new(EventLog::default());
+    db.add_observer(log.clone());
+
+    // WHEN
+    let first = db.update("Counters", &["hits"], &Row::of_columns(&[&5u32.to_le_bytes()]), &Eq(ColumnRef("id"), Const(U32(2)))).unwrap();
+    let second = db.update("Counters", &["hits"], &Row::of_columns(&[&6u32.to_le_bytes()]), &Neq(ColumnRef("id"), Const(U32(1)))).unwrap();
+
+    // THEN
+    assert_eq!((first, second), (1, 2));
+    assert_eq!(*log.events.lock().unwrap(), vec!["update [1]", "update [1, 2]"]);
+    let results = db.select(&[ColumnRef("id"), ColumnRef("hits")], "Counters", &True).unwrap();
+    check_equality(&results, &[[U32(1), U32(0)], [U32(2), U32(6)], [U32(3), U32(6)]]);
+}
+
+#[test]
+fn test_counter_updated_in_place_in_mem() {
+    test_counter_updated_in_place(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_counter_updated_in_place_on_disk() {
+    with_tmp(test_counter_updated_in_place);
+}
+
+#[test]
+fn test_compressed_disk_table_falls_back() {
+    // GIVEN
+    let path = random_temp_file();
+    let mut db = counters_table(StorageCfg::Disk { path: path.clone(), durability: Durability::default(), compression: Compression::Lz });
+    let log = Arc::new(EventLog::default());
+    db.add_observer(log.clone());
+
+    // WHEN
+    let updated = db.update("Counters", &["hits"], &Row::of_columns(&[&7u32.to_le_bytes()]), &Eq(ColumnRef("id"), Const(U32(3)))).unwrap();
+
+    // THEN
+    assert_eq!(updated, 1);
+    let results = db.select(&[ColumnRef("id"), ColumnRef("hits")], "Counters", &Eq(ColumnRef("id"), Const(U32(3)))).unwrap();
+    check_equality(&results, &[[U32(3), U32(7)]]);
+    drop(db);
+    std::fs::remove_file(path).unwrap();
+}
+
+fn test_variable_width_rows_stored_again(storage: StorageCfg) {
+    // GIVEN
+    let mut db = fruits_table(storage);
+    let log = Arc::new(EventLog::default());
+    db.add_observer(log.clone());
+
+    // WHEN
+    let updated = db.update("Fruits", &["name"], &Row::of_columns(&[b"blueberry"]), &Eq(ColumnRef("id"), Const(U32(200)))).unwrap();
+
+    // THEN
+    assert_eq!(updated, 1);
+    assert_eq!(*log.events.lock().unwrap(), vec!["delete [1]", "store [4]"]);
+    let results = db.select(&[ColumnRef("name")], "Fruits", &Eq(ColumnRef("id"), Const(U32(200)))).unwrap();
+    check_equality(&results, &[[UTF8("blueberry")]]);
+    assert_eq!(db.count("Fruits", &True).unwrap(), 4);
+}
+
+#[test]
+fn test_variable_width_rows_stored_again_in_mem() {
+    test_variable_width_rows_stored_again(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_variable_width_rows_stored_again_on_disk() {
+    with_tmp(test_variable_width_rows_stored_again);
+}
+
+fn test_update_keeps_indexes(storage: StorageCfg) {
+    // GIVEN
+    let mut db = counters_table(storage);
+    db.create_unique_index("Counters", "id", IndexKind::Ordered).unwrap();
+    db.create_index("Counters", "hits", IndexKind::Ordered).unwrap();
+
+    // WHEN
+    db.update("Counters", &["hits"], &Row::of_columns(&[&9u32.to_le_bytes()]), &Eq(ColumnRef("id"), Const(U32(1)))).unwrap();
+    let renumbered = db.update("Counters", &["id"], &Row::of_columns(&[&10u32.to_le_bytes()]), &Eq(ColumnRef("id"), Const(U32(3))));
+    let duplicate = db.update("Counters", &["id"], &Row::of_columns(&[&2u32.to_le_bytes()]), &Eq(ColumnRef("id"), Const(U32(1))));
+    let collapsed = db.update("Counters", &["id"], &Row::of_columns(&[&7u32.to_le_bytes()]), &True);
+
+    // THEN
+    assert_eq!(renumbered, Ok(1));
+    assert_eq!(duplicate, Err(DbError::DuplicateKey { index: "Counters.id".to_string(), key: IndexKey::U32(2) }));
+    assert_eq!(collapsed, Err(DbError::DuplicateKey { index: "Counters.id".to_string(), key: IndexKey::U32(7) }));
+    let by_hits = db.select(&[ColumnRef("id")], "Counters", &Eq(ColumnRef("hits"), Const(U32(9)))).unwrap();
+    check_equality(&by_hits, &[[U32(1)]]);
+    let by_id = db.select(&[ColumnRef("hits")], "Counters", &Eq(ColumnRef("id"), Const(U32(10)))).unwrap();
+    check_equality(&by_id, &[[U32(0)]]);
+    assert_eq!(db.count("Counters", &Eq(ColumnRef("id"), Const(U32(3)))).unwrap(), 0);
+}
+
+#[test]
+fn test_update_keeps_indexes_in_mem() {
+    test_update_keeps_indexes(StorageCfg::InMemory);
+}
+
+#[test]
+fn test_update_keeps_indexes_on_disk() {
+    with_tmp(test_update_keeps_indexes);
+}
+
+#[test]
+fn test_update_rejects_invalid_values() {
+    // GIVEN
+    let db = fruits_table(StorageCfg::InMemory);
+
+    // WHEN
+    let unknown = db.update("Fruits", &["weight"], &Row::of_columns(&[&1u32.to_le_bytes()]), &True);
+    let too_long = db.update("Fruits", &["name"], &Row::of_columns(&[&[b'x'; 21]]), &True);
+
+    // THEN
+    assert_eq!(unknown, Err(DbError::ColumnNotFound("weight".to_string())));
+    assert!(matches!(too_long, Err(DbError::RowSizeExceeded { .. })));
+    assert_eq!(db.count("Fruits", &Eq(ColumnRef("name"), Const(UTF8("apple")))).unwrap(), 1);
+}
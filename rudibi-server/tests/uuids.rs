@@ -0,0 +1,69 @@
+use rudibi_server::display::{format_table, DisplayOptions};
+use rudibi_server::dtype::{self, ColumnValue::*, DataType, TypeError};
+use rudibi_server::engine::{Column, Database, DbError, Row, StorageCfg, Table};
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::testlib::{check_equality, with_tmp};
+use rudibi_server::rows;
+
+const FIRST: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+const SECOND: &str = "f81d4fae-7dec-11d0-a765-00a0c91e6bf6";
+
+fn sessions(storage: StorageCfg) -> Database {
+    let mut db = Database::new();
+    db.new_table(&Table::new("Sessions", vec![
+        Column::new("id", DataType::UUID),
+        Column::new("user", DataType::U32),
+    ]), storage).unwrap();
+    db.insert("Sessions", &["id", "user"], rows![
+        [dtype::Uuid::parse(FIRST).unwrap(), 1u32],
+        [dtype::Uuid::parse(SECOND).unwrap(), 2u32],
+    ]).unwrap();
+    db
+}
+
+fn filter_by_uuid(storage: StorageCfg) {
+    let db = sessions(storage);
+
+    let by_value = db.select(&[ColumnRef("user")], "Sessions", &Eq(ColumnRef("id"), Const(Uuid(dtype::Uuid::parse(SECOND).unwrap())))).unwrap();
+    let by_text = db.select(&[ColumnRef("user")], "Sessions", &Eq(ColumnRef("id"), Const(UTF8("67E55044-10B1-426F-9247-BB680E5FE0C8")))).unwrap();
+    let others = db.select(&[ColumnRef("user")], "Sessions", &Neq(ColumnRef("id"), Const(UTF8(FIRST)))).unwrap();
+
+    check_equality(&by_value, &[[U32(2)]]);
+    check_equality(&by_text, &[[U32(1)]]);
+    check_equality(&others, &[[U32(2)]]);
+}
+
+#[test]
+fn filter_by_uuid_in_mem() {
+    filter_by_uuid(StorageCfg::InMemory);
+}
+
+#[test]
+fn filter_by_uuid_on_disk() {
+    with_tmp(filter_by_uuid);
+}
+
+#[test]
+fn malformed_uuid_constant() {
+    let db = sessions(StorageCfg::InMemory);
+    let result = db.select(&[ColumnRef("user")], "Sessions", &Eq(ColumnRef("id"), Const(UTF8("not-a-uuid"))));
+    assert_eq!(result.unwrap_err(), DbError::QueryError(TypeError::ConversionError));
+}
+
+#[test]
+fn uuid_wrong_size() {
+    let db = sessions(StorageCfg::InMemory);
+    let result = db.insert("Sessions", &["id", "user"], rows![[[0u8; 15], 3u32]]);
+    assert_eq!(result, Err(DbError::RowSizeTooSmall { got: 19, min: 20 }));
+}
+
+#[test]
+fn uuids_formatted_canonically() {
+    let db = sessions(StorageCfg::InMemory);
+    let results = db.select(&[ColumnRef("id")], "Sessions", &True).unwrap();
+
+    let table = format_table(&results, &DisplayOptions::default());
+
+    assert!(table.contains(&format!("| {FIRST} |")), "{table}");
+    assert!(table.contains(&format!("| {SECOND} |")), "{table}");
+}
@@ -0,0 +1,210 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rudibi_server::dtype::ColumnValue::*;
+use rudibi_server::engine::{Database, DbError, Row, StorageCfg};
+use rudibi_server::maintenance::MaintenanceConfig;
+use rudibi_server::query::{Bool::*, Value::*};
+use rudibi_server::rows;
+use rudibi_server::storage::Durability;
+use rudibi_server::testlib::{check_equality, fruits_schema, random_temp_file};
+use rudibi_server::wal::{read_wal, RecoveryTarget, WalChange, WalWriter};
+
+struct Setup {
+    table_path: String,
+    backup_path: String,
+    wal_path: String,
+    // WAL sequence the backup was taken at
+    backup_sequence: u64,
+    // Copies of the backup being restored
+    restored: RefCell<Vec<String>>,
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        for path in [&self.table_path, &self.backup_path, &self.wal_path].into_iter().chain(self.restored.borrow().iter()) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+// Backup after the first rows, then more rows, then a delete of everything but one row
+fn bad_bulk_delete() -> Setup {
+    let table_path = random_temp_file();
+    let backup_path = random_temp_file();
+    let wal_path = random_temp_file();
+    std::fs::remove_file(&wal_path).unwrap();
+    let wal = Arc::new(WalWriter::open(&wal_path).unwrap());
+    let mut db = Database::new();
+    db.add_observer(wal.clone());
+    db.new_table(&fruits_schema(), StorageCfg::disk(&table_path)).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[100u32, "apple"], [200u32, "banana"]]).unwrap();
+    std::fs::copy(&table_path, &backup_path).unwrap();
+    let backup_sequence = wal.last_sequence();
+    db.insert("Fruits", &["id", "name"], rows![[300u32, "cherry"]]).unwrap();
+    db.delete("Fruits", &Eq(ColumnRef("id"), Const(U32(100)))).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[400u32, "date"]]).unwrap();
+    db.delete("Fruits", &Neq(ColumnRef("id"), Const(U32(400)))).unwrap();
+    Setup { table_path, backup_path, wal_path, backup_sequence, restored: RefCell::new(Vec::new()) }
+}
+
+// Opens a copy, the backup itself stays as taken
+fn restore(setup: &Setup) -> Database {
+    let path = random_temp_file();
+    std::fs::copy(&setup.backup_path, &path).unwrap();
+    let mut db = Database::new();
+    db.open_table(&fruits_schema(), &path, Durability::Flush).unwrap();
+    setup.restored.borrow_mut().push(path);
+    db
+}
+
+#[test]
+fn test_replay_stops_before_bad_delete() {
+    // GIVEN
+    let setup = bad_bulk_delete();
+    let records = read_wal(&setup.wal_path).unwrap();
+    let bad_delete = records.last().unwrap().sequence;
+    let mut db = restore(&setup);
+
+    // WHEN
+    let replay = db.recover_from_wal(&setup.wal_path, setup.backup_sequence, RecoveryTarget::Sequence(bad_delete - 1)).unwrap();
+
+    // THEN
+    assert_eq!(replay.records_applied, 3);
+    assert_eq!(replay.last_sequence, bad_delete - 1);
+    let results = db.select(&[ColumnRef("id")], "Fruits", &True).unwrap();
+    check_equality(&results, &[[U32(200)], [U32(300)], [U32(400)]]);
+}
+
+#[test]
+fn test_replay_to_end_and_by_time() {
+    // GIVEN
+    let setup = bad_bulk_delete();
+    let records = read_wal(&setup.wal_path).unwrap();
+    let after_backup = records.iter().find(|record| record.sequence == setup.backup_sequence + 1).unwrap();
+
+    // WHEN
+    let mut to_end = restore(&setup);
+    to_end.recover_from_wal(&setup.wal_path, setup.backup_sequence, RecoveryTarget::End).unwrap();
+    let mut before_changes = restore(&setup);
+    let nothing = before_changes.recover_from_wal(&setup.wal_path, setup.backup_sequence, RecoveryTarget::Time(records[0].time)).unwrap();
+    let mut by_time = restore(&setup);
+    let replay = by_time.recover_from_wal(&setup.wal_path, setup.backup_sequence, RecoveryTarget::Time(after_backup.time)).unwrap();
+
+    // THEN
+    check_equality(&to_end.select(&[ColumnRef("id")], "Fruits", &True).unwrap(), &[[U32(400)]]);
+    assert_eq!(nothing.records_applied, 0);
+    assert_eq!(nothing.last_sequence, setup.backup_sequence);
+    assert!(replay.records_applied >= 1);
+    let results = by_time.select(&[ColumnRef("id")], "Fruits", &Eq(ColumnRef("id"), Const(U32(300)))).unwrap();
+    check_equality(&results, &[[U32(300)]]);
+    assert!(matches!(&records[0].change, WalChange::Store { rows, .. } if rows.len() == 2));
+}
+
+#[test]
+fn test_torn_record_is_cut_off() {
+    // GIVEN a log whose last record was cut short
+    let setup = bad_bulk_delete();
+    let records = read_wal(&setup.wal_path).unwrap();
+    let mut file = std::fs::OpenOptions::new().append(true).open(&setup.wal_path).unwrap();
+    file.write_all(&[40, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]).unwrap();
+    drop(file);
+
+    // WHEN
+    let wal = WalWriter::open(&setup.wal_path).unwrap();
+
+    // THEN
+    assert_eq!(wal.last_sequence(), records.last().unwrap().sequence);
+    let mut db = Database::new();
+    db.add_observer(Arc::new(wal));
+    db.new_table(&fruits_schema(), StorageCfg::InMemory).unwrap();
+    db.insert("Fruits", &["id", "name"], &[Row::of_columns(&[&500u32.to_le_bytes(), b"elder"])]).unwrap();
+    let reread = read_wal(&setup.wal_path).unwrap();
+    assert_eq!(reread.len(), records.len() + 1);
+}
+
+#[test]
+fn test_checkpoint_truncates_log() {
+    // GIVEN
+    let setup = bad_bulk_delete();
+    let wal = Arc::new(WalWriter::open(&setup.wal_path).unwrap());
+    let mut db = Database::new();
+    db.add_observer(wal.clone());
+    db.open_table(&fruits_schema(), &setup.table_path, Durability::Flush).unwrap();
+    let logged = std::fs::metadata(&setup.wal_path).unwrap().len();
+
+    // WHEN a backup is taken at the checkpoint, and a row comes after it
+    let checkpoint = db.checkpoint(&wal).unwrap();
+    std::fs::copy(&setup.table_path, &setup.backup_path).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[500u32, "elder"]]).unwrap();
+
+    // THEN only the checkpoint and the row are left, and numbering goes on from them
+    assert_eq!(checkpoint, setup.backup_sequence + 4);
+    assert!(std::fs::metadata(&setup.wal_path).unwrap().len() < logged);
+    let records = read_wal(&setup.wal_path).unwrap();
+    assert_eq!(records.iter().map(|record| record.sequence).collect::<Vec<_>>(), vec![checkpoint, checkpoint + 1]);
+    assert!(matches!(records[0].change, WalChange::Checkpoint));
+    assert_eq!(WalWriter::open(&setup.wal_path).unwrap().last_sequence(), checkpoint + 1);
+
+    let mut restored = restore(&setup);
+    let replay = restored.recover_from_wal(&setup.wal_path, checkpoint, RecoveryTarget::End).unwrap();
+    assert_eq!(replay.records_applied, 1);
+    check_equality(&restored.select(&[ColumnRef("id")], "Fruits", &True).unwrap(), &[[U32(400)], [U32(500)]]);
+}
+
+#[test]
+fn test_backup_before_checkpoint_cant_recover() {
+    // GIVEN
+    let setup = bad_bulk_delete();
+    let wal = Arc::new(WalWriter::open(&setup.wal_path).unwrap());
+    let mut db = Database::new();
+    db.open_table(&fruits_schema(), &setup.table_path, Durability::Flush).unwrap();
+    let checkpoint = db.checkpoint(&wal).unwrap();
+
+    // WHEN
+    let mut restored = restore(&setup);
+    let replay = restored.recover_from_wal(&setup.wal_path, setup.backup_sequence, RecoveryTarget::End);
+
+    // THEN
+    let message = format!("Backup at WAL sequence {} predates checkpoint {checkpoint}", setup.backup_sequence);
+    assert_eq!(replay, Err(DbError::InputError(message)));
+}
+
+#[test]
+fn test_checkpoint_needs_durable_tables() {
+    // GIVEN
+    let wal_path = random_temp_file();
+    std::fs::remove_file(&wal_path).unwrap();
+    let wal = Arc::new(WalWriter::open(&wal_path).unwrap());
+    let mut db = Database::new();
+    db.add_observer(wal.clone());
+    db.new_table(&fruits_schema(), StorageCfg::InMemory).unwrap();
+    db.insert("Fruits", &["id", "name"], rows![[100u32, "apple"]]).unwrap();
+
+    // WHEN
+    let checkpoint = db.checkpoint(&wal);
+
+    // THEN the log keeps the only copy of the rows
+    assert!(matches!(checkpoint, Err(DbError::UnsupportedOperation(_))));
+    assert_eq!(read_wal(&wal_path).unwrap().len(), 1);
+    std::fs::remove_file(&wal_path).unwrap();
+}
+
+#[test]
+fn test_maintenance_checkpoints() {
+    // GIVEN
+    let setup = bad_bulk_delete();
+    let wal = Arc::new(WalWriter::open(&setup.wal_path).unwrap());
+    let mut db = Database::new();
+    db.open_table(&fruits_schema(), &setup.table_path, Durability::Flush).unwrap();
+    let config = MaintenanceConfig { interval: Duration::from_secs(60), compact: false, ttls: vec![], refresh_stats: false, checkpoint: Some(wal.clone()) };
+
+    // WHEN
+    let report = db.run_maintenance(&config).unwrap();
+
+    // THEN
+    assert_eq!(report.checkpoint, Some(setup.backup_sequence + 4));
+    assert_eq!(read_wal(&setup.wal_path).unwrap().len(), 1);
+}
@@ -0,0 +1,110 @@
+use std::io::Cursor;
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::engine::StorageCfg;
+use rudibi_server::errors::ErrorReply;
+use rudibi_server::frame::{self, Frame};
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+
+// A server with a change feed serving frames on a port of its own, one thread per connection
+fn serving() -> (Arc<Server>, String) {
+    let server = Arc::new(Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory).with_change_feed(1000));
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    thread::spawn({
+        let server = server.clone();
+        move || for conn in listener.incoming().flatten() {
+            let server = server.clone();
+            thread::spawn(move || server.serve(&conn, &conn));
+        }
+    });
+    (server, address)
+}
+
+fn watch(address: &str, select: &str) -> TcpStream {
+    let mut conn = TcpStream::connect(address).unwrap();
+    conn.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    Frame::new(frame::WATCH, 1, select).write_to(&mut conn).unwrap();
+    conn
+}
+
+// The next `count` lines of NOTIFY frames, skipping empty frames
+fn notified(conn: &mut TcpStream, count: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    while lines.len() < count {
+        let reply = Frame::read_from(conn).unwrap().unwrap();
+        assert_eq!(reply.tag, frame::NOTIFY, "{reply:?}");
+        lines.extend(String::from_utf8(reply.payload).unwrap().lines().map(str::to_string));
+    }
+    lines
+}
+
+fn error_codes(server: &Server, requests: &[Frame]) -> Vec<u16> {
+    let mut input = Vec::new();
+    for request in requests {
+        request.write_to(&mut input).unwrap();
+    }
+    let mut output = Vec::new();
+    server.serve(Cursor::new(input), &mut output).unwrap();
+    let mut output = Cursor::new(output);
+    std::iter::from_fn(|| Frame::read_from(&mut output).unwrap())
+        .map(|reply| ErrorReply::parse(&reply.payload).map_or(0, |err| err.code))
+        .collect()
+}
+
+#[test]
+fn test_inserted_rows_matching() {
+    // GIVEN
+    let (server, address) = serving();
+    let mut conn = watch(&address, "SELECT name FROM Fruits WHERE id > 450");
+    assert_eq!(notified(&mut conn, 1), ["name"]);
+
+    // WHEN
+    server.handle("INSERT INTO Fruits (id, name) VALUES (500, 'kiwi'), (400, 'lime')").unwrap();
+    server.handle("DELETE FROM Fruits WHERE id = 500").unwrap();
+    server.handle("INSERT INTO Fruits (id, name) VALUES (600, 'mango, ripe')").unwrap();
+
+    // THEN
+    assert_eq!(notified(&mut conn, 2), ["kiwi", "\"mango, ripe\""]);
+}
+
+#[test]
+fn test_several_watches() {
+    // GIVEN
+    let (server, address) = serving();
+    let mut all = watch(&address, "SELECT * FROM Fruits");
+    let mut kiwis = watch(&address, "SELECT id FROM Fruits WHERE name = 'kiwi'");
+    assert_eq!(notified(&mut all, 1), ["id,name"]);
+    assert_eq!(notified(&mut kiwis, 1), ["id"]);
+
+    // WHEN
+    server.handle("INSERT INTO Fruits (id, name) VALUES (500, 'lime')").unwrap();
+    server.handle("INSERT INTO Fruits (id, name) VALUES (600, 'kiwi')").unwrap();
+
+    // THEN
+    assert_eq!(notified(&mut all, 2), ["500,lime", "600,kiwi"]);
+    assert_eq!(notified(&mut kiwis, 1), ["600"]);
+}
+
+#[test]
+fn test_bad_watches() {
+    // GIVEN
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory).with_change_feed(1000);
+    let without_feed = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+
+    // WHEN
+    let bad = error_codes(&server, &[
+        Frame::new(frame::WATCH, 1, "DELETE FROM Fruits WHERE id = 1"),
+        Frame::new(frame::WATCH, 2, "SELECT id FROM Vegetables"),
+        Frame::new(frame::WATCH, 3, "SELECT id FROM Fruits WHERE colour = 'red'"),
+    ]);
+    let unsupported = error_codes(&without_feed, &[Frame::new(frame::WATCH, 1, "SELECT id FROM Fruits")]);
+
+    // THEN
+    assert_eq!(bad, [2001, 1001, 1004]);
+    assert_eq!(unsupported, [9001]);
+}
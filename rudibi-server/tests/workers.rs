@@ -0,0 +1,95 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+use rudibi_server::engine::StorageCfg;
+use rudibi_server::protocol::Server;
+use rudibi_server::testlib::fruits_table;
+use rudibi_server::workers::WorkerPool;
+
+#[test]
+fn test_pool_caps_jobs_running() {
+    // GIVEN a pool of 3 and jobs that take a while
+    let running = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let done = Arc::new(AtomicUsize::new(0));
+    let pool = {
+        let (running, most, done) = (running.clone(), most.clone(), done.clone());
+        WorkerPool::new(3, 0, move |_: usize| {
+            most.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            running.fetch_sub(1, Ordering::SeqCst);
+            done.fetch_add(1, Ordering::SeqCst);
+        })
+    };
+
+    // WHEN
+    for job in 0..12 {
+        pool.submit(job);
+    }
+    pool.join();
+
+    // THEN
+    assert_eq!(done.load(Ordering::SeqCst), 12);
+    assert_eq!(most.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_pool_survives_panics() {
+    // GIVEN a pool of 1 whose first job panics
+    let done = Arc::new(AtomicUsize::new(0));
+    let pool = {
+        let done = done.clone();
+        WorkerPool::new(1, 4, move |job: usize| {
+            assert!(job > 0, "First job fails");
+            done.fetch_add(1, Ordering::SeqCst);
+        })
+    };
+
+    // WHEN
+    for job in 0..3 {
+        pool.submit(job);
+    }
+    pool.join();
+
+    // THEN the worker went on with the others
+    assert_eq!(done.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_connections_are_served_concurrently() {
+    // GIVEN a text-mode server with clients that stay connected
+    let server = Server::new(fruits_table(StorageCfg::InMemory), StorageCfg::InMemory);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let pool = WorkerPool::new(4, 0, move |conn: TcpStream| {
+        server.serve_text(BufReader::new(&conn), &conn).unwrap();
+    });
+    thread::spawn(move || {
+        for conn in listener.incoming() {
+            pool.submit(conn.unwrap());
+        }
+    });
+
+    // WHEN each client sends a command only once all of them are connected
+    let connected = Arc::new(Barrier::new(4));
+    let clients: Vec<_> = (0..4).map(|idx| {
+        let connected = connected.clone();
+        thread::spawn(move || {
+            let mut conn = TcpStream::connect(address).unwrap();
+            connected.wait();
+            writeln!(conn, "INSERT INTO Fruits (id, name) VALUES ({}, 'kiwi')", 500 + idx).unwrap();
+            let mut reply = String::new();
+            BufReader::new(&conn).read_line(&mut reply).unwrap();
+            reply
+        })
+    }).collect();
+
+    // THEN every client is answered while the others are still connected
+    for client in clients {
+        assert_eq!(client.join().unwrap(), "INSERTED 1\n");
+    }
+}